@@ -1,39 +1,71 @@
 // All of the todos are left by me, just that sometimes I forget or sometimes I address myself in
 // third person, don't sweat it.
 use anyhow::Result;
-use page::Database;
+use log::{LevelFilter, Log, Metadata, Record};
+use sand::page::Database;
 
-pub mod page;
+/*
+* Prints the logs of sand to stderr. Off unless SAND_LOG is set, to a level or to comma separated
+* `target=level` pairs, e.g. `SAND_LOG=debug` or `SAND_LOG=sand::pager=trace,sand::btree=debug`.
+*/
+struct StderrLogger {
+    filters: Vec<(String, LevelFilter)>,
+}
+
+impl StderrLogger {
+    fn install() {
+        let Ok(spec) = std::env::var("SAND_LOG") else {
+            return;
+        };
+        let filters: Vec<(String, LevelFilter)> = spec
+            .split(',')
+            .filter_map(|filter| match filter.split_once('=') {
+                Some((target, level)) => Some((target.to_string(), level.parse().ok()?)),
+                None => Some((String::new(), filter.parse().ok()?)),
+            })
+            .collect();
+        let max_level = filters.iter().map(|(_, level)| *level).max();
+        let logger = Box::leak(Box::new(StderrLogger { filters }));
+        if log::set_logger(logger).is_ok() {
+            log::set_max_level(max_level.unwrap_or(LevelFilter::Off));
+        }
+    }
+}
+
+impl Log for StderrLogger {
+    // The most specific filter for the target wins.
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filters
+            .iter()
+            .filter(|(target, _)| metadata.target().starts_with(target.as_str()))
+            .max_by_key(|(target, _)| target.len())
+            .is_some_and(|(_, level)| metadata.level() <= *level)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{} {}: {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
 
 // TODO: At some point remove the allow dead code thingy.
 fn main() -> Result<()> {
+    StderrLogger::install();
     let db_file_path = "../sand.db";
-    let mut database = Database::open(db_file_path.to_string())?;
-    let page = database.pager.read_page(1 as usize)?;
-    println!("{:#?}", page);
-
-    let page = database.pager.read_page(1 as usize)?;
+    let mut database = Database::open_read_only(db_file_path.to_string())?;
+    let page = database.pager.read_page(1)?;
     println!("{:#?}", page);
-    let page = page.clone();
 
-    for cell in page.cells.clone() {
-        match cell {
-            page::file_structures::BTreeCell::TableInteriorCell(_table_interior_cell) => todo!(),
-            page::file_structures::BTreeCell::TableLeafCell(table_leaf_cell) => {
-                // NOTE: This is just to see I'm reading the right things.
-                match &table_leaf_cell.payload[3] {
-                    page::file_structures::Value::Null => todo!(),
-                    page::file_structures::Value::Integer(index) => {
-                        let page = database.pager.read_page(*index as usize)?;
-                        println!("{:#?}", page);
-                    }
-                    page::file_structures::Value::Float(_) => todo!(),
-                    page::file_structures::Value::Text(_) => todo!(),
-                    page::file_structures::Value::Blob(_vec) => todo!(),
-                }
-            }
-            page::file_structures::BTreeCell::IndexInteriorCell(_index_interior_cell) => todo!(),
-            page::file_structures::BTreeCell::IndexLeafCell(_index_leaf_cell) => todo!(),
+    // NOTE: This is just to see I'm reading the right things.
+    let names: Vec<String> = database.tables().map(|table| table.name.clone()).collect();
+    for name in names {
+        let rows = database.query(&format!("SELECT rowid, * FROM \"{}\"", name))?;
+        println!("{}: {}", name, rows.columns().join(", "));
+        for row in rows {
+            println!("{:?}", row?);
         }
     }
     Ok(())
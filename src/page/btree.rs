@@ -0,0 +1,1568 @@
+use log::{debug, trace};
+use std::cmp::Ordering;
+
+use super::cursor::check_depth;
+use super::errors::{DBError, Location, Result};
+use super::file_structures::{
+    cell_size, encode_payload, encode_varint, read_u32, read_varint, BTreeCell, DBHeader, PageType,
+    Value, DB_HEADER_SIZE,
+};
+use super::freelist;
+use super::logging::BTREE;
+use super::pager::Pager;
+
+/*
+* The write side of the b-tree.
+*
+* Reads go through the parsed BTreePage, but edits work on the raw bytes of a page the same way
+* SQLite does it. A page looks like this:
+*
+* | page header | cell pointer array -> | unallocated space | <- cell content area | reserved |
+*
+* The cell pointer array grows down from the header and the cell content grows up from the end of
+* the usable space. Space freed up inside the cell content area is kept in a linked list of
+* freeblocks, each starting with the 2 byte offset of the next freeblock and its 2 byte size.
+* Leftover pieces smaller than 4 bytes can't hold a freeblock and are counted as fragmented bytes
+* in the page header.
+*/
+#[derive(Debug)]
+pub struct RawPage {
+    pub page_index: usize,
+    pub data: Vec<u8>,
+    db_header: DBHeader,
+    // Page 1 starts with the database header, the b-tree page header comes after it.
+    header_offset: usize,
+}
+
+// Cells always take up at least 4 bytes, so they can be turned into a freeblock once removed.
+const MIN_CELL_SIZE: usize = 4;
+
+const MAX_FRAGMENTED_BYTES: usize = 60;
+
+impl RawPage {
+    // Reads a b-tree page, making sure it is one that can be worked on safely.
+    pub fn load(pager: &mut Pager, page_index: usize) -> Result<RawPage> {
+        let page = RawPage::load_unchecked(pager, page_index)?;
+        page.check()
+            .map_err(|err| err.on_page(page_index).at_offset(page.header_offset))?;
+        Ok(page)
+    }
+
+    // Reads a page that is about to be rebuilt from scratch, whatever it holds now.
+    fn load_unchecked(pager: &mut Pager, page_index: usize) -> Result<RawPage> {
+        let data = pager.read_raw_page(page_index)?;
+        Ok(RawPage {
+            page_index,
+            data,
+            db_header: pager.db_header.clone(),
+            header_offset: header_offset(page_index),
+        })
+    }
+
+    /*
+     * Checks that the header, every cell and every freeblock stay inside the page and out of each
+     * other's way, so the methods below can index into the page without checking again. Errors
+     * carry the offset of whatever is wrong, the b-tree header when there is nothing better.
+     */
+    fn check(&self) -> Result<()> {
+        let page_type = self.page_type()?;
+        let usable_size = self.db_header.usable_size();
+        let corrupt = |offset: usize, description: String| {
+            DBError::Corrupt(Location::offset(offset), description)
+        };
+
+        let pointers_end = self.cell_pointer_offset(self.cell_count());
+        let content_area = self.cell_content_area();
+        if pointers_end > content_area || content_area > usable_size {
+            return Err(corrupt(
+                self.header_offset,
+                format!(
+                    "Cell pointers end at {}, after the cell content area starting at {}",
+                    pointers_end, content_area
+                ),
+            ));
+        }
+
+        for index in 0..self.cell_count() {
+            let offset = self.cell_offset(index);
+            let size = cell_size(&self.data, &page_type, offset, &self.db_header)
+                .map_err(|err| err.at_offset(offset))?;
+            if offset < content_area || offset + size > usable_size {
+                return Err(corrupt(
+                    offset,
+                    format!(
+                        "Cell {} of {} bytes is outside of the cell content area",
+                        index, size
+                    ),
+                ));
+            }
+        }
+
+        // Freeblocks come in order of their offsets, that also keeps the list from looping.
+        let mut freeblock = self.first_freeblock();
+        let mut previous_end = content_area;
+        while freeblock != 0 {
+            if freeblock < previous_end || freeblock + 4 > usable_size {
+                return Err(corrupt(
+                    freeblock,
+                    "Freeblock is out of order or outside of the cell content area".to_string(),
+                ));
+            }
+            let size = self.read_u16(freeblock + 2);
+            if size < 4 || freeblock + size > usable_size {
+                return Err(corrupt(
+                    freeblock,
+                    format!("Freeblock of {} bytes does not fit the page", size),
+                ));
+            }
+            previous_end = freeblock + size;
+            freeblock = self.read_u16(freeblock);
+        }
+        Ok(())
+    }
+
+    pub fn page_type(&self) -> Result<PageType> {
+        self.data[self.header_offset].try_into()
+    }
+
+    fn is_interior(&self) -> bool {
+        let page_type = self.data[self.header_offset];
+        page_type == PageType::TableInteriorPage as u8
+            || page_type == PageType::IndexInteriorPage as u8
+    }
+
+    fn header_size(&self) -> usize {
+        if self.is_interior() {
+            12
+        } else {
+            8
+        }
+    }
+
+    fn read_u16(&self, offset: usize) -> usize {
+        u16::from_be_bytes([self.data[offset], self.data[offset + 1]]) as usize
+    }
+
+    fn write_u16(&mut self, offset: usize, value: usize) {
+        self.data[offset..offset + 2].copy_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    pub fn first_freeblock(&self) -> usize {
+        self.read_u16(self.header_offset + 1)
+    }
+
+    fn set_first_freeblock(&mut self, offset: usize) {
+        self.write_u16(self.header_offset + 1, offset);
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.read_u16(self.header_offset + 3)
+    }
+
+    fn set_cell_count(&mut self, cell_count: usize) {
+        self.write_u16(self.header_offset + 3, cell_count);
+    }
+
+    // A stored 0 means 65536, which only happens on 64KiB pages without reserved space.
+    pub fn cell_content_area(&self) -> usize {
+        match self.read_u16(self.header_offset + 5) {
+            0 => 65536,
+            offset => offset,
+        }
+    }
+
+    fn set_cell_content_area(&mut self, offset: usize) {
+        self.write_u16(self.header_offset + 5, offset);
+    }
+
+    pub fn fragmented_bytes(&self) -> usize {
+        self.data[self.header_offset + 7] as usize
+    }
+
+    fn set_fragmented_bytes(&mut self, fragmented_bytes: usize) {
+        self.data[self.header_offset + 7] = fragmented_bytes as u8;
+    }
+
+    pub fn right_most_pointer(&self) -> Option<u32> {
+        if self.is_interior() {
+            Some(read_u32(&self.data, self.header_offset + 8))
+        } else {
+            None
+        }
+    }
+
+    pub fn set_right_most_pointer(&mut self, page_index: u32) {
+        let offset = self.header_offset + 8;
+        self.data[offset..offset + 4].copy_from_slice(&page_index.to_be_bytes());
+    }
+
+    fn cell_pointer_offset(&self, index: usize) -> usize {
+        self.header_offset + self.header_size() + 2 * index
+    }
+
+    pub fn cell_offset(&self, index: usize) -> usize {
+        self.read_u16(self.cell_pointer_offset(index))
+    }
+
+    // Raw bytes of the cell at `index`.
+    pub fn cell(&self, index: usize) -> Result<&[u8]> {
+        let offset = self.cell_offset(index);
+        let size = cell_size(&self.data, &self.page_type()?, offset, &self.db_header)?;
+        Ok(&self.data[offset..offset + size])
+    }
+
+    pub fn cells(&self) -> Result<Vec<Vec<u8>>> {
+        (0..self.cell_count())
+            .map(|index| self.cell(index).map(|cell| cell.to_vec()))
+            .collect()
+    }
+
+    // Space between the end of the cell pointer array and the start of the cell content area.
+    fn gap(&self) -> usize {
+        self.cell_content_area() - self.cell_pointer_offset(self.cell_count())
+    }
+
+    // Every byte that can still be used for cells and their pointers.
+    pub fn free_space(&self) -> usize {
+        let mut free_space = self.gap() + self.fragmented_bytes();
+        let mut freeblock = self.first_freeblock();
+        while freeblock != 0 {
+            free_space += self.read_u16(freeblock + 2);
+            freeblock = self.read_u16(freeblock);
+        }
+        free_space
+    }
+
+    // Whether all of the cells can be added to the page, with or without defragmenting it.
+    pub fn fits(&self, cells: &[Vec<u8>]) -> bool {
+        let needed: usize = cells
+            .iter()
+            .map(|cell| cell.len().max(MIN_CELL_SIZE) + 2)
+            .sum();
+        needed <= self.free_space()
+    }
+
+    // Adds the cell so it becomes the cell at `index`. Returns false if there is no room for it.
+    pub fn insert_cell(&mut self, index: usize, cell: &[u8]) -> Result<bool> {
+        let size = cell.len().max(MIN_CELL_SIZE);
+        if size + 2 > self.free_space() {
+            return Ok(false);
+        }
+
+        // The pointer array grows by 2 bytes whichever way the cell gets its space.
+        let freeblock = if self.gap() >= 2 {
+            self.take_freeblock(size)
+        } else {
+            None
+        };
+        let offset = match freeblock {
+            Some(offset) => offset,
+            None => {
+                if self.gap() < size + 2 {
+                    self.defragment()?;
+                }
+                let offset = self.cell_content_area() - size;
+                self.set_cell_content_area(offset);
+                offset
+            }
+        };
+        self.data[offset..offset + cell.len()].copy_from_slice(cell);
+
+        let cell_count = self.cell_count();
+        let start = self.cell_pointer_offset(index);
+        let end = self.cell_pointer_offset(cell_count);
+        self.data.copy_within(start..end, start + 2);
+        self.write_u16(start, offset);
+        self.set_cell_count(cell_count + 1);
+        Ok(true)
+    }
+
+    // Takes `size` bytes out of the first freeblock big enough, returning where they start.
+    fn take_freeblock(&mut self, size: usize) -> Option<usize> {
+        let mut previous = self.header_offset + 1;
+        let mut freeblock = self.first_freeblock();
+        while freeblock != 0 {
+            let next = self.read_u16(freeblock);
+            let block_size = self.read_u16(freeblock + 2);
+            if block_size >= size {
+                let remainder = block_size - size;
+                if remainder < MIN_CELL_SIZE {
+                    // Too small to stay a freeblock, unlink it and count what is left as fragments.
+                    // The fragment count has to stay below 60, defragment once it would not.
+                    if self.fragmented_bytes() + remainder > MAX_FRAGMENTED_BYTES {
+                        return None;
+                    }
+                    self.write_u16(previous, next);
+                    let fragmented_bytes = self.fragmented_bytes() + remainder;
+                    self.set_fragmented_bytes(fragmented_bytes);
+                    return Some(freeblock);
+                }
+
+                // Hand out the end of the block so the freeblock itself stays where it is.
+                self.write_u16(freeblock + 2, remainder);
+                return Some(freeblock + remainder);
+            }
+            previous = freeblock;
+            freeblock = next;
+        }
+        None
+    }
+
+    // Removes the cell at `index` and gives its space back to the page.
+    pub fn remove_cell(&mut self, index: usize) -> Result<()> {
+        let offset = self.cell_offset(index);
+        let size = self.cell(index)?.len().max(MIN_CELL_SIZE);
+        self.release_space(offset, size);
+
+        let cell_count = self.cell_count();
+        let start = self.cell_pointer_offset(index);
+        let end = self.cell_pointer_offset(cell_count);
+        self.data.copy_within(start + 2..end, start);
+        self.data[end - 2..end].fill(0);
+        self.set_cell_count(cell_count - 1);
+        Ok(())
+    }
+
+    /*
+     * Gives `size` bytes at `offset` back to the page. The space becomes a freeblock, merged with
+     * the freeblocks right before and after it. Fragmented bytes between them get merged in as well,
+     * anything less than 4 bytes between two freeblocks can only be fragments. Space right at the
+     * start of the cell content area goes back to the unallocated space instead.
+     */
+    pub fn release_space(&mut self, offset: usize, size: usize) {
+        let mut start = offset;
+        let mut end = offset + size;
+        let mut fragmented_bytes = self.fragmented_bytes();
+
+        // Freeblocks are kept in order of their offset, find the ones on either side.
+        let mut before_previous = 0;
+        let mut previous = 0;
+        let mut next = self.first_freeblock();
+        while next != 0 && next < start {
+            before_previous = previous;
+            previous = next;
+            next = self.read_u16(next);
+        }
+
+        if next != 0 && next < end + MIN_CELL_SIZE {
+            fragmented_bytes = fragmented_bytes.saturating_sub(next - end);
+            end = next + self.read_u16(next + 2);
+            next = self.read_u16(next);
+        }
+        if previous != 0 {
+            let previous_end = previous + self.read_u16(previous + 2);
+            if previous_end + MIN_CELL_SIZE > start {
+                fragmented_bytes = fragmented_bytes.saturating_sub(start - previous_end);
+                start = previous;
+                previous = before_previous;
+            }
+        }
+        self.set_fragmented_bytes(fragmented_bytes);
+
+        if start == self.cell_content_area() {
+            self.data[start..end].fill(0);
+            self.set_cell_content_area(end);
+        } else {
+            self.write_u16(start, next);
+            self.write_u16(start + 2, end - start);
+            next = start;
+        }
+
+        match previous {
+            0 => self.set_first_freeblock(next),
+            previous => self.write_u16(previous, next),
+        }
+    }
+
+    // Less than a third of the page is in use, time to merge it with a sibling.
+    fn is_underfull(&self) -> bool {
+        self.free_space() * 3 > self.db_header.usable_size() * 2
+    }
+
+    // Page number of the child at `index` of an interior page, the cell count standing for the
+    // right most pointer.
+    fn child(&self, index: usize) -> Result<usize> {
+        if index < self.cell_count() {
+            Ok(read_u32(self.cell(index)?, 0) as usize)
+        } else {
+            Ok(self.right_most_pointer().unwrap_or(0) as usize)
+        }
+    }
+
+    // Packs every cell at the end of the page, turning all freeblocks and fragmented bytes back
+    // into unallocated space.
+    pub fn defragment(&mut self) -> Result<()> {
+        let cells = self.cells()?;
+        let mut content_area = self.db_header.usable_size();
+        let mut content = vec![0u8; content_area];
+        for (index, cell) in cells.iter().enumerate() {
+            content_area -= cell.len().max(MIN_CELL_SIZE);
+            content[content_area..content_area + cell.len()].copy_from_slice(cell);
+            self.write_u16(self.cell_pointer_offset(index), content_area);
+        }
+
+        let pointer_array_end = self.cell_pointer_offset(cells.len());
+        self.data[pointer_array_end..content_area].fill(0);
+        self.data[content_area..self.db_header.usable_size()]
+            .copy_from_slice(&content[content_area..]);
+        self.set_cell_content_area(content_area);
+        self.set_first_freeblock(0);
+        self.set_fragmented_bytes(0);
+        Ok(())
+    }
+
+    // Turns the page into an empty page of the given type.
+    pub fn clear(&mut self, page_type: PageType) {
+        let usable_size = self.db_header.usable_size();
+        self.data[self.header_offset..usable_size].fill(0);
+        self.data[self.header_offset] = page_type as u8;
+        self.set_cell_content_area(usable_size);
+    }
+
+    // Replaces the whole content of the page.
+    pub fn rebuild(
+        &mut self,
+        page_type: PageType,
+        cells: &[Vec<u8>],
+        right_most_pointer: Option<u32>,
+    ) -> Result<()> {
+        self.clear(page_type);
+        if let Some(right_most_pointer) = right_most_pointer {
+            self.set_right_most_pointer(right_most_pointer);
+        }
+        for (index, cell) in cells.iter().enumerate() {
+            if !self.insert_cell(index, cell)? {
+                return Err(DBError::Corrupt(
+                    Location::page(self.page_index),
+                    "Cells do not fit on the page".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn header_offset(page_index: usize) -> usize {
+    if page_index == 1 {
+        DB_HEADER_SIZE
+    } else {
+        0
+    }
+}
+
+// Rowid of a raw table leaf or table interior cell.
+fn cell_rowid(cell: &[u8], page_type: &PageType) -> Result<u64> {
+    match page_type {
+        PageType::TableLeafPage => {
+            let (_, payload_size_length) = read_varint(cell)?;
+            Ok(read_varint(cell.get(payload_size_length..).unwrap_or_default())?.0)
+        }
+        PageType::TableInteriorPage => Ok(read_varint(cell.get(4..).unwrap_or_default())?.0),
+        _ => Err(DBError::corrupt(format!(
+            "Expected a table page, got: {:?}",
+            page_type
+        ))),
+    }
+}
+
+// An interior cell pointing at `left_child_page`, `divider` being the rest of the cell: the rowid
+// varint of a table interior cell, the payload of an index interior cell.
+fn interior_cell(left_child_page: usize, divider: &[u8]) -> Vec<u8> {
+    let mut cell = (left_child_page as u32).to_be_bytes().to_vec();
+    cell.extend_from_slice(divider);
+    cell
+}
+
+// Builds a table leaf cell, moving the part of the payload that does not fit on the page out to a
+// chain of overflow pages.
+fn table_leaf_cell(pager: &mut Pager, rowid: u64, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut cell = encode_varint(payload.len() as u64);
+    cell.extend(encode_varint(rowid));
+    add_payload(pager, &PageType::TableLeafPage, cell, payload)
+}
+
+// Builds an index leaf cell. Index interior cells keep the same part of a payload on the page, so
+// the cell moves up into an interior page as it is, behind a left child pointer.
+fn index_leaf_cell(pager: &mut Pager, payload: &[u8]) -> Result<Vec<u8>> {
+    let cell = encode_varint(payload.len() as u64);
+    add_payload(pager, &PageType::IndexLeafPage, cell, payload)
+}
+
+// Adds as much of the payload as stays on the page to the cell, followed by the first page of the
+// overflow chain the rest goes to.
+fn add_payload(
+    pager: &mut Pager,
+    page_type: &PageType,
+    mut cell: Vec<u8>,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    let local_size = pager.db_header.local_payload_size(page_type, payload.len());
+    cell.extend_from_slice(&payload[..local_size]);
+    if local_size < payload.len() {
+        let first_overflow_page = write_overflow_chain(pager, &payload[local_size..])?;
+        cell.extend(first_overflow_page.to_be_bytes());
+    }
+    Ok(cell)
+}
+
+fn write_overflow_chain(pager: &mut Pager, data: &[u8]) -> Result<u32> {
+    let page_size = pager.db_header.page_size as usize;
+    let chunks: Vec<&[u8]> = data.chunks(pager.db_header.usable_size() - 4).collect();
+    let pages = chunks
+        .iter()
+        .map(|_| pager.allocate_page())
+        .collect::<Result<Vec<usize>>>()?;
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let next_page = pages.get(index + 1).copied().unwrap_or(0) as u32;
+        let mut page = vec![0u8; page_size];
+        page[0..4].copy_from_slice(&next_page.to_be_bytes());
+        page[4..4 + chunk.len()].copy_from_slice(chunk);
+        pager.write_page(pages[index], page);
+    }
+    Ok(pages[0] as u32)
+}
+
+// Puts every page of an overflow chain on the freelist.
+fn free_overflow_chain(pager: &mut Pager, first_overflow_page: u32) -> Result<()> {
+    let mut next_page = first_overflow_page;
+    while next_page != 0 {
+        let page = pager.read_raw_page(next_page as usize)?;
+        freelist::release_page(pager, next_page as usize)?;
+        next_page = read_u32(&page, 0);
+    }
+    Ok(())
+}
+
+// First overflow page of a raw cell with a payload, if its payload spilled.
+fn cell_overflow_page(
+    cell: &[u8],
+    page_type: &PageType,
+    db_header: &DBHeader,
+) -> Result<Option<u32>> {
+    let payload_start = match page_type {
+        PageType::IndexInteriorPage => 4,
+        _ => 0,
+    };
+    let (payload_size, _) = read_varint(cell.get(payload_start..).unwrap_or_default())?;
+    let payload_size = payload_size as usize;
+    if db_header.local_payload_size(page_type, payload_size) == payload_size {
+        return Ok(None);
+    }
+    Ok(Some(read_u32(cell, cell.len() - 4)))
+}
+
+// A step on the way from the root down to a leaf.
+#[derive(Debug)]
+struct PathEntry {
+    page_index: usize,
+    // Index of the child we went into, the cell count for the right most pointer.
+    child_index: usize,
+    right_most: bool,
+}
+
+// Walks down from the root to the leaf that holds, or would hold, the rowid. Returns the path to
+// the leaf, the leaf and the index of the first cell with a rowid not less than the one given.
+fn find_leaf(
+    pager: &mut Pager,
+    root_page: usize,
+    rowid: u64,
+) -> Result<(Vec<PathEntry>, RawPage, usize)> {
+    let mut path = Vec::new();
+    let mut page_index = root_page;
+    loop {
+        let page = RawPage::load(pager, page_index)?;
+        let page_type = page.page_type()?;
+        let cells = page.cells()?;
+        let mut index = cells.len();
+        for (i, cell) in cells.iter().enumerate() {
+            // Rowids are signed.
+            if cell_rowid(cell, &page_type)? as i64 >= rowid as i64 {
+                index = i;
+                break;
+            }
+        }
+
+        match page_type {
+            PageType::TableInteriorPage => {
+                path.push(PathEntry {
+                    page_index,
+                    child_index: index,
+                    right_most: index == cells.len(),
+                });
+                page_index = match cells.get(index) {
+                    Some(cell) => read_u32(cell, 0) as usize,
+                    None => page.right_most_pointer().unwrap() as usize,
+                };
+                check_depth(path.len(), page_index)?;
+            }
+            PageType::TableLeafPage => return Ok((path, page, index)),
+            _ => {
+                return Err(DBError::Corrupt(
+                    Location::page(page_index),
+                    format!("Expected a table b-tree page, got: {:?}", page_type),
+                ))
+            }
+        }
+    }
+}
+
+/*
+* Inserts a row into the table b-tree rooted at `root_page`. Fails if the rowid is already taken.
+*
+* This only touches the table b-tree, the entries for the row go into the indexes of the table with
+* `insert_entry`.
+*/
+pub fn insert(pager: &mut Pager, root_page: usize, rowid: u64, values: &[Value]) -> Result<()> {
+    trace!(target: BTREE, "Inserting rowid {} into the tree at page {}", rowid, root_page);
+    let (mut path, leaf, index) = find_leaf(pager, root_page, rowid)?;
+    if index < leaf.cell_count()
+        && cell_rowid(leaf.cell(index)?, &PageType::TableLeafPage)? == rowid
+    {
+        return Err(DBError::ConstraintViolation(format!(
+            "Rowid {} already exists",
+            rowid
+        )));
+    }
+
+    let payload = encode_payload(values, &pager.db_header);
+    let cell = table_leaf_cell(pager, rowid, &payload)?;
+    let appending =
+        index == leaf.cell_count() && path.iter().all(|path_entry| path_entry.right_most);
+    insert_cells(pager, &mut path, leaf, index, vec![cell], appending)
+}
+
+/*
+* Walks down an index b-tree to the entry `compare` finds equal to the one looked for, or to the
+* leaf it would go into. `compare` orders an entry of the tree against the one looked for. Returns
+* the path to the page, the page, the index of the first entry not less than the one looked for
+* and whether that entry is equal to it. Without an equal entry the page is always a leaf.
+*
+* Entries are compared as the parsed page has them, with any overflow read back in.
+*/
+fn find_entry(
+    pager: &mut Pager,
+    root_page: usize,
+    compare: impl Fn(&[Value]) -> Ordering,
+) -> Result<(Vec<PathEntry>, RawPage, usize, bool)> {
+    let mut path = Vec::new();
+    let mut page_index = root_page;
+    loop {
+        let parsed = pager.read_page(page_index)?;
+        let page = RawPage::load(pager, page_index)?;
+        let entries = parsed
+            .cells
+            .iter()
+            .map(|cell| match cell {
+                BTreeCell::IndexLeafCell(cell) => Ok(cell.payload.as_slice()),
+                BTreeCell::IndexInteriorCell(cell) => Ok(cell.payload.as_slice()),
+                _ => Err(DBError::Corrupt(
+                    Location::page(page_index),
+                    format!(
+                        "Expected an index b-tree page, got: {:?}",
+                        parsed.header.page_type
+                    ),
+                )),
+            })
+            .collect::<Result<Vec<&[Value]>>>()?;
+        let index = entries.partition_point(|entry| compare(entry) == Ordering::Less);
+        let found = entries
+            .get(index)
+            .is_some_and(|entry| compare(entry) == Ordering::Equal);
+
+        if found || parsed.header.page_type == PageType::IndexLeafPage {
+            return Ok((path, page, index, found));
+        }
+        path.push(PathEntry {
+            page_index,
+            child_index: index,
+            right_most: index == entries.len(),
+        });
+        page_index = page.child(index)?;
+        check_depth(path.len(), page_index)?;
+    }
+}
+
+/*
+* Inserts an entry into the index b-tree rooted at `root_page`, `compare` ordering two entries the
+* way the index does. Entries end with the rowid of their row, so no two are equal, an entry that
+* is already there is a constraint violation all the same.
+*/
+pub fn insert_entry(
+    pager: &mut Pager,
+    root_page: usize,
+    entry: &[Value],
+    compare: impl Fn(&[Value], &[Value]) -> Ordering,
+) -> Result<()> {
+    trace!(target: BTREE, "Inserting an entry into the index at page {}", root_page);
+    let (mut path, leaf, index, found) =
+        find_entry(pager, root_page, |other| compare(other, entry))?;
+    if found {
+        return Err(DBError::ConstraintViolation(format!(
+            "Entry {:?} already exists in the index at page {}",
+            entry, root_page
+        )));
+    }
+
+    let payload = encode_payload(entry, &pager.db_header);
+    let cell = index_leaf_cell(pager, &payload)?;
+    insert_cells(pager, &mut path, leaf, index, vec![cell], false)
+}
+
+/*
+* Removes the row with the given rowid from the table b-tree rooted at `root_page`, returning
+* whether there was such a row. Pages that end up less than a third full are merged with a sibling
+* or get cells from it, and pages that are no longer needed go on the freelist.
+*
+* Like `insert` this leaves indexes on the table alone, `delete_entry` takes the entries for the
+* row out of them.
+*/
+pub fn delete(pager: &mut Pager, root_page: usize, rowid: u64) -> Result<bool> {
+    trace!(target: BTREE, "Deleting rowid {} from the tree at page {}", rowid, root_page);
+    let (mut path, mut leaf, index) = find_leaf(pager, root_page, rowid)?;
+    if index == leaf.cell_count()
+        || cell_rowid(leaf.cell(index)?, &PageType::TableLeafPage)? != rowid
+    {
+        return Ok(false);
+    }
+
+    if let Some(first_overflow_page) = cell_overflow_page(
+        leaf.cell(index)?,
+        &PageType::TableLeafPage,
+        &pager.db_header,
+    )? {
+        free_overflow_chain(pager, first_overflow_page)?;
+    }
+    leaf.remove_cell(index)?;
+    pager.write_page(leaf.page_index, leaf.data.clone());
+    balance_underfull(pager, &mut path, leaf)?;
+    Ok(true)
+}
+
+/*
+* Removes an entry from the index b-tree rooted at `root_page`, returning whether it was there.
+* `compare` orders two entries the way the index does.
+*
+* Entries of an index are in interior pages too. One taken out of an interior page is replaced by
+* the entry right before it, the last one of the right most leaf under its left child, the way
+* SQLite does it. Either way it is a leaf that loses a cell and gets rebalanced like in `delete`.
+*/
+pub fn delete_entry(
+    pager: &mut Pager,
+    root_page: usize,
+    entry: &[Value],
+    compare: impl Fn(&[Value], &[Value]) -> Ordering,
+) -> Result<bool> {
+    trace!(target: BTREE, "Deleting an entry from the index at page {}", root_page);
+    let (mut path, mut page, index, found) =
+        find_entry(pager, root_page, |other| compare(other, entry))?;
+    if !found {
+        return Ok(false);
+    }
+
+    let page_type = page.page_type()?;
+    if let Some(first_overflow_page) =
+        cell_overflow_page(page.cell(index)?, &page_type, &pager.db_header)?
+    {
+        free_overflow_chain(pager, first_overflow_page)?;
+    }
+    if page_type == PageType::IndexLeafPage {
+        page.remove_cell(index)?;
+        pager.write_page(page.page_index, page.data.clone());
+        balance_underfull(pager, &mut path, page)?;
+        return Ok(true);
+    }
+
+    // The entry before it keeps its overflow pages, the cell only moves.
+    let left_child = page.child(index)?;
+    let mut leaf = RawPage::load(pager, left_child)?;
+    let mut depth = path.len() + 1;
+    while let Some(right_most_pointer) = leaf.right_most_pointer() {
+        depth += 1;
+        check_depth(depth, right_most_pointer as usize)?;
+        leaf = RawPage::load(pager, right_most_pointer as usize)?;
+    }
+    let Some(last) = leaf.cell_count().checked_sub(1) else {
+        return Err(DBError::Corrupt(
+            Location::page(leaf.page_index),
+            "Index leaf without any entries".to_string(),
+        ));
+    };
+    let previous_cell = leaf.cell(last)?.to_vec();
+    let previous = match &pager.read_page(leaf.page_index)?.cells[last] {
+        BTreeCell::IndexLeafCell(cell) => cell.payload.clone(),
+        _ => {
+            return Err(DBError::Corrupt(
+                Location::page(leaf.page_index),
+                "Expected an index leaf page".to_string(),
+            ))
+        }
+    };
+    leaf.remove_cell(last)?;
+    pager.write_page(leaf.page_index, leaf.data);
+
+    // The new cell may be larger than the one it replaces and split the page.
+    page.remove_cell(index)?;
+    insert_cells(
+        pager,
+        &mut path,
+        page,
+        index,
+        vec![interior_cell(left_child, &previous_cell)],
+        false,
+    )?;
+
+    // Splits may have moved the leaf to other parents, look for it again: it is where an entry
+    // right before the one that moved up would go.
+    let (mut path, leaf, _, _) =
+        find_entry(pager, root_page, |other| match compare(other, &previous) {
+            Ordering::Less => Ordering::Less,
+            _ => Ordering::Greater,
+        })?;
+    balance_underfull(pager, &mut path, leaf)?;
+    Ok(true)
+}
+
+/*
+* Rebalances a page that lost cells. An underfull page is put together with its left sibling, or
+* its right one if it is the first child, and their cells are spread over as few pages as they fit
+* on. Two pages that fit on one become one, taking a divider out of the parent, which may leave the
+* parent underfull in turn. When the root is left as an interior page without any cells, its only
+* child is pulled up into it and the tree gets one level shallower.
+*/
+fn balance_underfull(pager: &mut Pager, path: &mut Vec<PathEntry>, page: RawPage) -> Result<()> {
+    let Some(parent_entry) = path.pop() else {
+        return collapse_root(pager, page);
+    };
+    if !page.is_underfull() {
+        return Ok(());
+    }
+
+    let mut parent = RawPage::load(pager, parent_entry.page_index)?;
+    if parent.cell_count() == 0 {
+        return Ok(());
+    }
+    let divider_index = parent_entry.child_index.saturating_sub(1);
+    let divider = parent.cell(divider_index)?.to_vec();
+    let left = RawPage::load(pager, parent.child(divider_index)?)?;
+    let mut right = RawPage::load(pager, parent.child(divider_index + 1)?)?;
+    let page_type = right.page_type()?;
+
+    let mut cells = left.cells()?;
+    match page_type {
+        // The rowid of a table leaf divider is only a copy, the pages get new dividers.
+        PageType::TableLeafPage => {}
+        // An index divider is an entry of its own, it comes down between the two pages.
+        PageType::IndexLeafPage => cells.push(divider[4..].to_vec()),
+        // The divider comes down between the two pages, pointing at the left page's right most
+        // child.
+        _ => {
+            let left_right_most = left.right_most_pointer().unwrap() as usize;
+            cells.push(interior_cell(left_right_most, &divider[4..]));
+        }
+    }
+    cells.extend(right.cells()?);
+
+    let right_most_pointer = right.right_most_pointer();
+    right.clear(page_type);
+    let (mut chunks, dividers) = if right.fits(&cells) {
+        (vec![(cells, right_most_pointer)], Vec::new())
+    } else {
+        split_cells(
+            &pager.db_header,
+            &page_type,
+            cells,
+            right_most_pointer,
+            false,
+        )?
+    };
+
+    // The right page keeps the last chunk so the pointer to it in the parent stays as it is.
+    let (last_cells, last_right_most_pointer) = chunks.pop().unwrap();
+    right.rebuild(page_type, &last_cells, last_right_most_pointer)?;
+    pager.write_page(right.page_index, right.data);
+
+    let mut divider_cells = Vec::new();
+    for (chunk_index, ((cells, right_most_pointer), divider)) in
+        chunks.into_iter().zip(dividers).enumerate()
+    {
+        let page_index = if chunk_index == 0 {
+            left.page_index
+        } else {
+            pager.allocate_page()?
+        };
+        write_new_page(pager, page_index, &page_type, &cells, right_most_pointer)?;
+        divider_cells.push(interior_cell(page_index, &divider));
+    }
+    debug!(
+        target: BTREE,
+        "Page {}: rebalanced with page {} into {} pages",
+        right.page_index,
+        left.page_index,
+        divider_cells.len() + 1
+    );
+    if divider_cells.is_empty() {
+        freelist::release_page(pager, left.page_index)?;
+    }
+
+    parent.remove_cell(divider_index)?;
+    if divider_cells.is_empty() {
+        pager.write_page(parent.page_index, parent.data.clone());
+        return balance_underfull(pager, path, parent);
+    }
+    insert_cells(pager, path, parent, divider_index, divider_cells, false)
+}
+
+// Pulls the only child of a root without cells up into the root.
+fn collapse_root(pager: &mut Pager, mut root: RawPage) -> Result<()> {
+    while root.cell_count() == 0 {
+        let Some(child_index) = root.right_most_pointer() else {
+            return Ok(());
+        };
+        let child = RawPage::load(pager, child_index as usize)?;
+        let cells = child.cells()?;
+
+        // The root may be page 1, which has less room because of the database header.
+        let page_type = child.page_type()?;
+        let mut new_root = RawPage {
+            page_index: root.page_index,
+            data: root.data.clone(),
+            db_header: root.db_header.clone(),
+            header_offset: root.header_offset,
+        };
+        if new_root
+            .rebuild(page_type, &cells, child.right_most_pointer())
+            .is_err()
+        {
+            return Ok(());
+        }
+
+        debug!(
+            target: BTREE,
+            "Page {}: pulled its only child, page {}, up into the root",
+            root.page_index,
+            child.page_index
+        );
+        freelist::release_page(pager, child.page_index)?;
+        pager.write_page(new_root.page_index, new_root.data.clone());
+        root = new_root;
+    }
+    Ok(())
+}
+
+/*
+* Adds cells to a page starting at `index`, splitting the page when they do not fit.
+*
+* A split spreads the cells over the page and as many new pages as needed. The page itself keeps
+* the right most part, so the pointer to it in the parent stays valid, and a divider cell for each
+* new page is inserted into the parent right before that pointer. That may split the parent in
+* turn. The root can't move, so when it overflows its cells go to new pages and the root becomes
+* an interior page pointing at them, making the tree one level deeper.
+*/
+fn insert_cells(
+    pager: &mut Pager,
+    path: &mut Vec<PathEntry>,
+    mut page: RawPage,
+    index: usize,
+    new_cells: Vec<Vec<u8>>,
+    appending: bool,
+) -> Result<()> {
+    if page.fits(&new_cells) {
+        for (i, cell) in new_cells.iter().enumerate() {
+            page.insert_cell(index + i, cell)?;
+        }
+        pager.write_page(page.page_index, page.data);
+        return Ok(());
+    }
+
+    let page_type = page.page_type()?;
+    let mut cells = page.cells()?;
+    cells.splice(index..index, new_cells);
+    let (mut chunks, dividers) = split_cells(
+        &pager.db_header,
+        &page_type,
+        cells,
+        page.right_most_pointer(),
+        appending,
+    )?;
+    debug!(
+        target: BTREE,
+        "Page {}: split into {} pages",
+        page.page_index,
+        chunks.len()
+    );
+
+    if path.is_empty() {
+        let mut children = Vec::new();
+        for (cells, right_most_pointer) in chunks {
+            let child = pager.allocate_page()?;
+            write_new_page(pager, child, &page_type, &cells, right_most_pointer)?;
+            children.push(child);
+        }
+
+        let divider_cells: Vec<Vec<u8>> = dividers
+            .iter()
+            .zip(children.iter())
+            .map(|(divider, &child)| interior_cell(child, divider))
+            .collect();
+        let right_most_pointer = *children.last().unwrap() as u32;
+        let interior_type = match page_type {
+            PageType::IndexLeafPage | PageType::IndexInteriorPage => PageType::IndexInteriorPage,
+            _ => PageType::TableInteriorPage,
+        };
+        page.rebuild(interior_type, &divider_cells, Some(right_most_pointer))?;
+        pager.write_page(page.page_index, page.data);
+        return Ok(());
+    }
+
+    let (last_cells, last_right_most_pointer) = chunks.pop().unwrap();
+    let mut divider_cells = Vec::new();
+    for ((cells, right_most_pointer), divider) in chunks.into_iter().zip(dividers) {
+        let child = pager.allocate_page()?;
+        write_new_page(pager, child, &page_type, &cells, right_most_pointer)?;
+        divider_cells.push(interior_cell(child, &divider));
+    }
+    page.rebuild(page_type, &last_cells, last_right_most_pointer)?;
+    pager.write_page(page.page_index, page.data);
+
+    let parent = path.pop().unwrap();
+    let parent_page = RawPage::load(pager, parent.page_index)?;
+    insert_cells(
+        pager,
+        path,
+        parent_page,
+        parent.child_index,
+        divider_cells,
+        false,
+    )
+}
+
+fn write_new_page(
+    pager: &mut Pager,
+    page_index: usize,
+    page_type: &PageType,
+    cells: &[Vec<u8>],
+    right_most_pointer: Option<u32>,
+) -> Result<()> {
+    let mut page = RawPage::load_unchecked(pager, page_index)?;
+    page.rebuild(*page_type, cells, right_most_pointer)?;
+    pager.write_page(page_index, page.data);
+    Ok(())
+}
+
+// Cells of a page and its right most pointer.
+type Chunk = (Vec<Vec<u8>>, Option<u32>);
+
+/*
+* Spreads the cells of an overfull page over as few pages as possible, evenly by size. Returns the
+* cells for each page and the divider that goes into the parent for every page but the last one,
+* the divider being the interior cell without its left child pointer.
+*
+* For table leaves the divider is the largest rowid on the page. Interior pages give up a cell for
+* every divider instead: its left child becomes the right most pointer of the page before it and
+* the rest of it moves up into the parent. Index leaves give up a cell too, every entry of an index
+* is in the tree once and the one between two pages is in their parent.
+*
+* When appending to the right most leaf, which is what inserting with increasing rowids does, the
+* new cell goes onto a page of its own. The full page stays full rather than being split in half
+* and only ever filled to half after that.
+*/
+fn split_cells(
+    db_header: &DBHeader,
+    page_type: &PageType,
+    mut cells: Vec<Vec<u8>>,
+    right_most_pointer: Option<u32>,
+    appending: bool,
+) -> Result<(Vec<Chunk>, Vec<Vec<u8>>)> {
+    let size = |cell: &Vec<u8>| cell.len().max(MIN_CELL_SIZE) + 2;
+    let header_size = match page_type {
+        PageType::TableInteriorPage | PageType::IndexInteriorPage => 12,
+        _ => 8,
+    };
+    let capacity = db_header.usable_size() - header_size;
+    let total: usize = cells.iter().map(size).sum();
+    let target = total / total.div_ceil(capacity).max(2);
+
+    let mut chunks = Vec::new();
+    let mut dividers = Vec::new();
+    match page_type {
+        PageType::TableLeafPage if appending => {
+            let new_cell = cells.pop().unwrap();
+            dividers.push(encode_varint(cell_rowid(cells.last().unwrap(), page_type)?));
+            chunks.push((cells, None));
+            chunks.push((vec![new_cell], None));
+        }
+        PageType::TableLeafPage => {
+            let mut current: Vec<Vec<u8>> = Vec::new();
+            let mut current_size = 0;
+            for cell in cells {
+                if !current.is_empty()
+                    && (current_size + size(&cell) > capacity || current_size >= target)
+                {
+                    dividers.push(encode_varint(cell_rowid(
+                        current.last().unwrap(),
+                        page_type,
+                    )?));
+                    chunks.push((std::mem::take(&mut current), None));
+                    current_size = 0;
+                }
+                current_size += size(&cell);
+                current.push(cell);
+            }
+            chunks.push((current, None));
+        }
+        _ => {
+            let interior = *page_type != PageType::IndexLeafPage;
+            let cell_count = cells.len();
+            let mut current: Vec<Vec<u8>> = Vec::new();
+            let mut current_size = 0;
+            for (index, cell) in cells.into_iter().enumerate() {
+                let overfull = current_size + size(&cell) > capacity;
+                if !current.is_empty()
+                    && (overfull || current_size >= target && index + 1 < cell_count)
+                {
+                    // The last page needs a cell too, when the last one does not fit the one
+                    // before it divides instead.
+                    let mut next = Vec::new();
+                    let divider = if index + 1 < cell_count {
+                        cell
+                    } else {
+                        next.push(cell);
+                        current.pop().unwrap()
+                    };
+                    let (right_most_pointer, divider) = if interior {
+                        (Some(read_u32(&divider, 0)), divider[4..].to_vec())
+                    } else {
+                        (None, divider)
+                    };
+                    dividers.push(divider);
+                    chunks.push((std::mem::replace(&mut current, next), right_most_pointer));
+                    current_size = current.iter().map(size).sum();
+                    continue;
+                }
+                current_size += size(&cell);
+                current.push(cell);
+            }
+            chunks.push((current, right_most_pointer));
+            // A page keeps at least one cell, SQLite's balancing never leaves one empty.
+            if chunks.iter().any(|(cells, _)| cells.is_empty()) {
+                return Err(DBError::corrupt(format!(
+                    "Splitting {} cells of a {:?} left a page without any",
+                    cell_count, page_type
+                )));
+            }
+        }
+    }
+
+    Ok((chunks, dividers))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::btree::{delete, insert, interior_cell, split_cells, RawPage, MIN_CELL_SIZE};
+    use crate::page::cursor::BTreeCursor;
+    use crate::page::errors::DBError;
+    use crate::page::file_structures::{encode_varint, PageType, Value};
+    use crate::page::test_utils::{temp_copy, Sqlite3};
+    use crate::page::Database;
+
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+    // notes(id INTEGER PRIMARY KEY, author TEXT COLLATE NOCASE, score INTEGER, title TEXT,
+    // code TEXT UNIQUE) with 600 rows and an index of every kind: on two columns with one of them
+    // DESC, with a collation of its own, partial on score > 50 and on lower(title).
+    const INDEXED_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/indexed.db");
+
+    fn row(rowid: u64) -> Vec<Value> {
+        // Every 37th row is big enough to need overflow pages.
+        let data_size = if rowid.is_multiple_of(37) {
+            1500
+        } else {
+            (rowid % 40) as usize
+        };
+        vec![
+            Value::Null,
+            Value::Text(format!("item-{}", rowid)),
+            Value::Blob(vec![rowid as u8; data_size]),
+            Value::Float(rowid as f64 / 4.0),
+        ]
+    }
+
+    // Reads the whole table back and checks it holds exactly the expected rows.
+    fn assert_rows(database: &mut Database, expected_rowids: &[u64]) {
+        let root_page = database.get_table("items").unwrap().root_page;
+        let mut cursor = BTreeCursor::new(root_page);
+        let mut rowids = Vec::new();
+        let mut more = cursor.first(&mut database.pager).unwrap();
+        while more {
+            let cell = cursor.cell().unwrap();
+            assert_eq!(
+                format!("{:?}", cell.payload),
+                format!("{:?}", row(cell.row_id))
+            );
+            rowids.push(cell.row_id);
+            more = cursor.next(&mut database.pager).unwrap();
+        }
+        assert_eq!(rowids, expected_rowids);
+    }
+
+    #[test]
+    fn insert_with_page_splits_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        let root_page = database.get_table("items").unwrap().root_page;
+
+        // Spread the inserts all over the table so splits happen everywhere, not just at the end.
+        let rowids: Vec<u64> = (0..1500u64).map(|i| (i * 7919) % 1500 + 1).collect();
+        for &rowid in rowids.iter() {
+            insert(&mut database.pager, root_page, rowid, &row(rowid)).unwrap();
+        }
+        database.pager.commit().unwrap();
+
+        let root = database.pager.read_page(root_page).unwrap();
+        assert_eq!(root.header.page_type, PageType::TableInteriorPage);
+
+        // Everything has to be there after opening the file again.
+        let mut database = Database::open(path).unwrap();
+        assert_rows(&mut database, &(1..=1500).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn insert_appending_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        for rowid in 1..=1000 {
+            let mut values = row(rowid);
+            values[0] = Value::Integer(rowid as i64);
+            assert_eq!(database.insert("items", values).unwrap(), rowid);
+        }
+
+        // Appending keeps the pages full. SQLite itself needs 176 pages for these rows, splitting
+        // every page in half would take well over 250.
+        assert!(database.pager.page_count() < 190);
+        assert!(database.insert("items", row(1001)).is_ok());
+
+        let mut database = Database::open(path).unwrap();
+        assert_rows(&mut database, &(1..=1001).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn split_interior_cells_test() {
+        let database = Database::open(EMPTY_TABLE_DB.to_string()).unwrap();
+        let db_header = &database.pager.db_header;
+        let capacity = db_header.usable_size() - 12;
+        let size = |cell: &Vec<u8>| cell.len().max(MIN_CELL_SIZE) + 2;
+
+        // Rowids from 2^56 up take 9 byte varints, the largest interior cells there are. Every
+        // cell count puts the split point somewhere else, with all cells large or only the last
+        // ones.
+        for count in 2..200u64 {
+            for first_large in [0, count / 2, count - 1] {
+                let cells: Vec<Vec<u8>> = (0..count)
+                    .map(|i| {
+                        let rowid = if i < first_large { i } else { (1 << 56) + i };
+                        interior_cell(i as usize + 2, &encode_varint(rowid))
+                    })
+                    .collect();
+                let (chunks, dividers) = split_cells(
+                    db_header,
+                    &PageType::TableInteriorPage,
+                    cells,
+                    Some(1000),
+                    false,
+                )
+                .unwrap();
+
+                assert_eq!(dividers.len() + 1, chunks.len());
+                assert_eq!(
+                    chunks.iter().map(|(cells, _)| cells.len()).sum::<usize>(),
+                    count as usize - dividers.len()
+                );
+                for (cells, _) in chunks.iter() {
+                    assert!(!cells.is_empty(), "{} cells", count);
+                    assert!(cells.iter().map(size).sum::<usize>() <= capacity);
+                }
+                assert_eq!(chunks.last().unwrap().1, Some(1000));
+            }
+        }
+    }
+
+    // A row for the notes table of INDEXED_DB. Every 9th title is long enough for its index entries
+    // to need overflow pages, every 10th code is NULL, which UNIQUE allows any number of.
+    fn note(id: u64) -> Vec<Value> {
+        let authors = [Some("Dave"), Some("dave"), Some("Erin"), None, Some("bob")];
+        let title = if id.is_multiple_of(9) {
+            format!("Long note {} ", id).repeat(100)
+        } else {
+            format!(
+                "{} note {}",
+                ["Extra", "extra", "EXTRA"][id as usize % 3],
+                id
+            )
+        };
+        let text = |text: Option<String>| text.map_or(Value::Null, Value::Text);
+        vec![
+            Value::Null,
+            text(authors[id as usize % 5].map(String::from)),
+            Value::Integer((id as i64 * 37) % 101),
+            Value::Text(title),
+            text((!id.is_multiple_of(10)).then(|| format!("x{}", id))),
+        ]
+    }
+
+    // Checks with SQLite that the indexes of INDEXED_DB have an entry for every row they cover and
+    // nothing else.
+    fn assert_indexes(path: &str) {
+        let mut sqlite3 = Sqlite3::open(path);
+        assert_eq!(sqlite3.run("PRAGMA integrity_check;"), ["ok"]);
+        // The integrity check does not count the entries of partial indexes, dbstat counts them
+        // page by page.
+        let entries = sqlite3.run("SELECT sum(ncell) FROM dbstat WHERE name = 'notes_high';");
+        assert_eq!(
+            entries,
+            sqlite3.run("SELECT count(*) FROM notes WHERE score > 50;")
+        );
+
+        // Looking rows up through an index finds the same ones as going through the table.
+        for (index, condition) in [
+            ("notes_high", "score > 50"),
+            ("notes_author_score", "author = 'DAVE' AND score < 20"),
+            ("notes_title", "title = 'EXTRA NOTE 1002' COLLATE NOCASE"),
+            (
+                "notes_lower",
+                "lower(title) = lower('Long note 999 ' || substr(title, 15))",
+            ),
+            ("sqlite_autoindex_notes_1", "code > 'x1300'"),
+        ] {
+            let query = |access: &str| {
+                format!(
+                    "SELECT group_concat(id) FROM (SELECT id FROM notes {} WHERE {} ORDER BY id);",
+                    access, condition
+                )
+            };
+            let rows = sqlite3.run(&query(&format!("INDEXED BY {}", index)));
+            assert_eq!(rows, sqlite3.run(&query("NOT INDEXED")), "{}", index);
+        }
+    }
+
+    #[test]
+    fn insert_into_indexes_test() {
+        let path = temp_copy(INDEXED_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        for id in 601..=1400 {
+            assert_eq!(database.insert("notes", note(id)).unwrap(), id);
+        }
+
+        // A code that is taken fails the whole insert, the row does not go into the table either.
+        let mut duplicate = note(1401);
+        duplicate[4] = Value::Text("c0919".to_string());
+        assert!(matches!(
+            database.insert("notes", duplicate),
+            Err(DBError::ConstraintViolation(_))
+        ));
+        drop(database);
+
+        assert_indexes(&path);
+        assert_eq!(
+            Sqlite3::open(&path).run("SELECT count(*), max(id) FROM notes;"),
+            ["1400|1400"]
+        );
+    }
+
+    #[test]
+    fn delete_from_indexes_test() {
+        let path = temp_copy(INDEXED_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        for id in 601..=1400 {
+            database.insert("notes", note(id)).unwrap();
+        }
+
+        // Deleting all over the table takes entries out of the leaves and the interior pages of
+        // every index, and merges their pages.
+        let ids: Vec<u64> = (0..1400u64).map(|i| (i * 7919) % 1400 + 1).collect();
+        for &id in ids.iter().filter(|id| !id.is_multiple_of(3)) {
+            assert!(database.delete("notes", id).unwrap());
+        }
+        assert!(!database.delete("notes", 1).unwrap());
+        drop(database);
+        assert_indexes(&path);
+        assert_eq!(
+            Sqlite3::open(&path).run("SELECT count(*) FROM notes;"),
+            ["466"]
+        );
+
+        // Without any rows every index is down to its root, an empty leaf.
+        let mut database = Database::open(path.clone()).unwrap();
+        for &id in ids.iter().filter(|id| id.is_multiple_of(3)) {
+            assert!(database.delete("notes", id).unwrap());
+        }
+        drop(database);
+        let mut sqlite3 = Sqlite3::open(&path);
+        assert_eq!(sqlite3.run("PRAGMA integrity_check;"), ["ok"]);
+        assert_eq!(
+            sqlite3.run(
+                "SELECT name, count(*), sum(ncell) FROM dbstat \
+                 WHERE name LIKE '%notes%' GROUP BY name ORDER BY name;"
+            ),
+            [
+                "notes|1|0",
+                "notes_author_score|1|0",
+                "notes_high|1|0",
+                "notes_lower|1|0",
+                "notes_title|1|0",
+                "sqlite_autoindex_notes_1|1|0",
+            ]
+        );
+    }
+
+    #[test]
+    fn insert_duplicate_rowid_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        let mut values = row(5);
+        values[0] = Value::Integer(5);
+        database.insert("items", values.clone()).unwrap();
+        assert!(database.insert("items", values).is_err());
+        // A NULL rowid alias picks the next rowid.
+        assert_eq!(database.insert("items", row(6)).unwrap(), 6);
+        assert!(database.insert("items", vec![Value::Null]).is_err());
+        assert!(database.insert("missing", row(1)).is_err());
+
+        let mut database = Database::open(path).unwrap();
+        assert_rows(&mut database, &[5, 6]);
+    }
+
+    #[test]
+    fn insert_cell_uses_freeblocks_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path).unwrap();
+        let root_page = database.get_table("items").unwrap().root_page;
+        let mut page = RawPage::load(&mut database.pager, root_page).unwrap();
+        let usable_size = database.pager.db_header.usable_size();
+
+        assert!(page.insert_cell(0, &[3, 1, 0, 0, 9]).unwrap());
+        assert_eq!(page.cell_content_area(), usable_size - 5);
+
+        // Turn 20 bytes in front of the cell into a freeblock by hand.
+        let freeblock = usable_size - 25;
+        page.data[freeblock..freeblock + 4].copy_from_slice(&[0, 0, 0, 20]);
+        page.data[1..3].copy_from_slice(&(freeblock as u16).to_be_bytes());
+        page.data[5..7].copy_from_slice(&(freeblock as u16).to_be_bytes());
+        let free_space = page.free_space();
+
+        // The cell comes out of the end of the freeblock, which shrinks.
+        assert!(page
+            .insert_cell(0, &[8, 2, 0, 0, 0, 0, 0, 0, 0, 0])
+            .unwrap());
+        assert_eq!(page.cell_offset(0), freeblock + 10);
+        assert_eq!(page.first_freeblock(), freeblock);
+        assert_eq!(page.free_space(), free_space - 12);
+
+        // 8 of the 10 bytes left would leave 2, those become fragmented bytes.
+        assert!(page.insert_cell(2, &[6, 3, 0, 0, 0, 0, 0, 0]).unwrap());
+        assert_eq!(page.cell_offset(2), freeblock);
+        assert_eq!(page.first_freeblock(), 0);
+        assert_eq!(page.fragmented_bytes(), 2);
+
+        // Defragmenting packs everything at the end again.
+        let cells = page.cells().unwrap();
+        page.defragment().unwrap();
+        assert_eq!(page.cells().unwrap(), cells);
+        assert_eq!(page.fragmented_bytes(), 0);
+        assert_eq!(page.cell_content_area(), usable_size - 5 - 10 - 8);
+    }
+
+    #[test]
+    fn delete_with_page_merges_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        let root_page = database.get_table("items").unwrap().root_page;
+        for rowid in 1..=1000u64 {
+            insert(&mut database.pager, root_page, rowid, &row(rowid)).unwrap();
+        }
+        database.pager.commit().unwrap();
+        let page_count = database.pager.page_count();
+
+        // Delete most rows from all over the table, keeping every 10th.
+        let deleted: Vec<u64> = (0..1000u64)
+            .map(|i| (i * 7919) % 1000 + 1)
+            .filter(|rowid| rowid % 10 != 0)
+            .collect();
+        for &rowid in deleted.iter() {
+            assert!(delete(&mut database.pager, root_page, rowid).unwrap());
+        }
+        assert!(!delete(&mut database.pager, root_page, 5).unwrap());
+        database.pager.commit().unwrap();
+
+        // The file does not shrink, the pages that are no longer used go on the freelist.
+        assert_eq!(database.pager.page_count(), page_count);
+        assert!(database.pager.db_header.freelist_pages as usize > page_count / 2);
+
+        let mut database = Database::open(path).unwrap();
+        assert_rows(
+            &mut database,
+            &(10..=1000).step_by(10).collect::<Vec<u64>>(),
+        );
+    }
+
+    #[test]
+    fn delete_all_rows_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        for rowid in 1..=300 {
+            database.insert("items", row(rowid)).unwrap();
+        }
+        for rowid in (1..=300).rev() {
+            assert!(database.delete("items", rowid).unwrap());
+        }
+        assert!(database.delete("missing", 1).is_err());
+
+        // The tree shrinks back to a single leaf, every other page is free.
+        let mut database = Database::open(path).unwrap();
+        let root_page = database.get_table("items").unwrap().root_page;
+        let root = database.pager.read_page(root_page).unwrap();
+        assert_eq!(root.header.page_type, PageType::TableLeafPage);
+        assert_eq!(
+            database.pager.db_header.freelist_pages as usize,
+            database.pager.page_count() - 2
+        );
+        assert_rows(&mut database, &[]);
+    }
+
+    #[test]
+    fn release_space_merges_freeblocks_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path).unwrap();
+        let root_page = database.get_table("items").unwrap().root_page;
+        let mut page = RawPage::load(&mut database.pager, root_page).unwrap();
+        let usable_size = database.pager.db_header.usable_size();
+
+        // Four cells of 10 bytes each, the first one at the very end of the page.
+        for rowid in 0..4 {
+            assert!(page
+                .insert_cell(rowid, &[8, rowid as u8, 0, 0, 0, 0, 0, 0, 0, 0])
+                .unwrap());
+        }
+        let content_area = page.cell_content_area();
+        assert_eq!(content_area, usable_size - 40);
+
+        // Two cells apart from each other become two freeblocks.
+        page.remove_cell(2).unwrap();
+        page.remove_cell(0).unwrap();
+        assert_eq!(page.first_freeblock(), usable_size - 30);
+        assert_eq!(page.read_u16(usable_size - 30), usable_size - 10);
+        assert_eq!(page.cell_count(), 2);
+
+        // The cell between them joins both into one.
+        page.remove_cell(0).unwrap();
+        assert_eq!(page.first_freeblock(), usable_size - 30);
+        assert_eq!(page.read_u16(usable_size - 28), 30);
+        assert_eq!(page.read_u16(usable_size - 30), 0);
+
+        // The last cell sits at the start of the content area, which takes everything back.
+        page.remove_cell(0).unwrap();
+        assert_eq!(page.cell_count(), 0);
+        assert_eq!(page.first_freeblock(), 0);
+        assert_eq!(page.cell_content_area(), usable_size);
+    }
+}
@@ -0,0 +1,609 @@
+use std::sync::Arc;
+
+use super::errors::{DBError, Location, Result};
+use super::file_structures::{BTreeCell, BTreePage, PageType, TableLeafCell, Value};
+use super::pager::Pager;
+
+/*
+* Cursor over a table B-tree that visits the rows in rowid order.
+*
+* Table interior pages hold (left child, rowid) cells where every rowid in the left child is less
+* than or equal to the cell's rowid, and the right most pointer holds everything larger than the
+* last cell. The rows themselves only live on the leaf pages, so walking the tree in order is a
+* matter of keeping the path from the root to the current leaf around.
+*
+* The cursor does not hold on to the Pager, it is passed into every call instead. That way more
+* than one cursor can be open on the same database at a time.
+*/
+#[derive(Debug)]
+pub struct BTreeCursor {
+    pub root_page: usize,
+    // Path from the root page to the current leaf page. For interior pages the index is the child
+    // we descended into, where `cell_count` stands for the right most pointer. For the leaf page it
+    // is the index of the current cell.
+    stack: Vec<(Arc<BTreePage>, usize)>,
+}
+
+impl BTreeCursor {
+    pub fn new(root_page: usize) -> BTreeCursor {
+        BTreeCursor {
+            root_page,
+            stack: Vec::new(),
+        }
+    }
+
+    // Whether the cursor points at a row.
+    pub fn is_valid(&self) -> bool {
+        match self.stack.last() {
+            Some((page, index)) => *index < page.cells.len(),
+            None => false,
+        }
+    }
+
+    // The row the cursor points at, None once it has run off either end of the table.
+    pub fn cell(&self) -> Option<&TableLeafCell> {
+        let (page, index) = self.stack.last()?;
+        match page.cells.get(*index) {
+            Some(BTreeCell::TableLeafCell(cell)) => Some(cell),
+            _ => None,
+        }
+    }
+
+    pub fn rowid(&self) -> Option<u64> {
+        self.cell().map(|cell| cell.row_id)
+    }
+
+    // Moves to the row with the smallest rowid. Returns false if the table is empty.
+    pub fn first(&mut self, pager: &mut Pager) -> Result<bool> {
+        self.stack.clear();
+        self.descend(pager, self.root_page, Edge::Left)?;
+        Ok(self.is_valid())
+    }
+
+    // Moves to the row with the largest rowid. Returns false if the table is empty.
+    pub fn last(&mut self, pager: &mut Pager) -> Result<bool> {
+        self.stack.clear();
+        self.descend(pager, self.root_page, Edge::Right)?;
+        Ok(self.is_valid())
+    }
+
+    // Moves to the next row in rowid order. Returns false once there are no more rows, the cursor
+    // then sits past the last row and `prev` brings it back.
+    pub fn next(&mut self, pager: &mut Pager) -> Result<bool> {
+        let Some((page, index)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        if *index + 1 < page.cells.len() {
+            *index += 1;
+            return Ok(true);
+        }
+
+        // Done with this leaf, find the closest ancestor with a child to the right we have not
+        // visited yet.
+        let leaf_level = self.stack.len() - 1;
+        let ancestor = self.stack[..leaf_level]
+            .iter()
+            .rposition(|(page, index)| *index < page.cells.len());
+        let Some(level) = ancestor else {
+            let (page, index) = &mut self.stack[leaf_level];
+            *index = page.cells.len();
+            return Ok(false);
+        };
+
+        self.stack.truncate(level + 1);
+        let (page, index) = &mut self.stack[level];
+        *index += 1;
+        let child = child_page(page, *index)?;
+        self.descend(pager, child, Edge::Left)?;
+        Ok(self.is_valid())
+    }
+
+    // Moves to the previous row in rowid order. Returns false once there are no more rows, the
+    // cursor is then no longer positioned and has to be moved with `first`, `last` or `seek`.
+    pub fn prev(&mut self, pager: &mut Pager) -> Result<bool> {
+        let Some((page, index)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        if *index > 0 && !page.cells.is_empty() {
+            *index = (*index).min(page.cells.len()) - 1;
+            return Ok(true);
+        }
+
+        // Done with this leaf, find the closest ancestor with a child to the left we have not
+        // visited yet.
+        let leaf_level = self.stack.len() - 1;
+        let ancestor = self.stack[..leaf_level]
+            .iter()
+            .rposition(|(_, index)| *index > 0);
+        let Some(level) = ancestor else {
+            self.stack.clear();
+            return Ok(false);
+        };
+
+        self.stack.truncate(level + 1);
+        let (page, index) = &mut self.stack[level];
+        *index -= 1;
+        let child = child_page(page, *index)?;
+        self.descend(pager, child, Edge::Right)?;
+        Ok(self.is_valid())
+    }
+
+    // Moves to the row with the given rowid, or the first row after it if there is no such row.
+    // Returns true only when a row with exactly that rowid exists. Rowids are signed, they are
+    // compared as i64.
+    pub fn seek(&mut self, pager: &mut Pager, rowid: u64) -> Result<bool> {
+        self.stack.clear();
+        let mut page_index = self.root_page;
+        loop {
+            let page = pager.read_page(page_index)?;
+            match page.header.page_type {
+                PageType::TableInteriorPage => {
+                    let index = page.cells.partition_point(|cell| match cell {
+                        BTreeCell::TableInteriorCell(cell) => (cell.rowid as i64) < rowid as i64,
+                        _ => false,
+                    });
+                    page_index = child_page(&page, index)?;
+                    self.stack.push((page, index));
+                    check_depth(self.stack.len(), page_index)?;
+                }
+                PageType::TableLeafPage => {
+                    let index = page.cells.partition_point(|cell| match cell {
+                        BTreeCell::TableLeafCell(cell) => (cell.row_id as i64) < rowid as i64,
+                        _ => false,
+                    });
+                    let cell_count = page.cells.len();
+                    self.stack.push((page, index));
+                    if index == cell_count && cell_count > 0 {
+                        // Everything on this leaf is smaller, the row we want starts the next one.
+                        self.stack.last_mut().unwrap().1 = cell_count - 1;
+                        self.next(pager)?;
+                    }
+                    return Ok(self.rowid() == Some(rowid));
+                }
+                _ => return Err(not_a_table_page(page_index, &page)),
+            }
+        }
+    }
+
+    // Pushes the path from `page_index` down to its left most or right most leaf.
+    fn descend(&mut self, pager: &mut Pager, page_index: usize, edge: Edge) -> Result<()> {
+        let mut page_index = page_index;
+        loop {
+            let page = pager.read_page(page_index)?;
+            match page.header.page_type {
+                PageType::TableInteriorPage => {
+                    let index = match edge {
+                        Edge::Left => 0,
+                        Edge::Right => page.cells.len(),
+                    };
+                    page_index = child_page(&page, index)?;
+                    self.stack.push((page, index));
+                    check_depth(self.stack.len(), page_index)?;
+                }
+                PageType::TableLeafPage => {
+                    let index = match edge {
+                        Edge::Left => 0,
+                        Edge::Right => page.cells.len().saturating_sub(1),
+                    };
+                    self.stack.push((page, index));
+                    return Ok(());
+                }
+                _ => return Err(not_a_table_page(page_index, &page)),
+            }
+        }
+    }
+}
+
+/*
+* Cursor over an index B-tree that visits the entries in index order.
+*
+* Index entries are records of the indexed columns followed by the rowid, and unlike in a table
+* they live on interior pages too: every interior cell is an entry that comes after everything in
+* its left child and before everything in the child to its right. Walking the tree in order visits
+* a child, then the cell after it, then the next child and so on.
+*
+* The cursor does not know how the entries are ordered, seeking takes a predicate telling entries
+* before the one wanted apart from the others instead.
+*/
+#[derive(Debug)]
+pub struct IndexCursor {
+    pub root_page: usize,
+    // Path from the root page to the current entry. For every page but the last the index is the
+    // child we descended into, `cell_count` standing for the right most pointer. For the last page,
+    // a leaf or an interior page, it is the index of the current cell.
+    stack: Vec<(Arc<BTreePage>, usize)>,
+}
+
+impl IndexCursor {
+    pub fn new(root_page: usize) -> IndexCursor {
+        IndexCursor {
+            root_page,
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self.stack.last() {
+            Some((page, index)) => *index < page.cells.len(),
+            None => false,
+        }
+    }
+
+    // The entry the cursor points at, None once it has run off either end of the index.
+    pub fn record(&self) -> Option<&[Value]> {
+        let (page, index) = self.stack.last()?;
+        match page.cells.get(*index) {
+            Some(BTreeCell::IndexLeafCell(cell)) => Some(&cell.payload),
+            Some(BTreeCell::IndexInteriorCell(cell)) => Some(&cell.payload),
+            _ => None,
+        }
+    }
+
+    // The rowid of the row the entry is for, the last value of the record.
+    pub fn rowid(&self) -> Option<i64> {
+        match self.record()?.last() {
+            Some(Value::Integer(rowid)) => Some(*rowid),
+            _ => None,
+        }
+    }
+
+    // Moves to the first entry. Returns false if the index is empty.
+    pub fn first(&mut self, pager: &mut Pager) -> Result<bool> {
+        self.stack.clear();
+        self.descend(pager, self.root_page, Edge::Left)?;
+        Ok(self.is_valid())
+    }
+
+    // Moves to the last entry. Returns false if the index is empty.
+    pub fn last(&mut self, pager: &mut Pager) -> Result<bool> {
+        self.stack.clear();
+        self.descend(pager, self.root_page, Edge::Right)?;
+        Ok(self.is_valid())
+    }
+
+    // Moves to the next entry. Returns false once there are no more, the cursor then sits past the
+    // last entry and `prev` brings it back.
+    pub fn next(&mut self, pager: &mut Pager) -> Result<bool> {
+        let Some((page, index)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        if is_interior(page) {
+            // The child to the right of the cell comes next, starting with its left most entry.
+            *index += 1;
+            let child = child_page(page, *index)?;
+            check_depth(self.stack.len(), child)?;
+            self.descend(pager, child, Edge::Left)?;
+            return Ok(self.is_valid());
+        }
+        if *index + 1 < page.cells.len() {
+            *index += 1;
+            return Ok(true);
+        }
+        Ok(self.leave_leaf())
+    }
+
+    // Moves to the previous entry. Returns false once there are no more, the cursor is then no
+    // longer positioned.
+    pub fn prev(&mut self, pager: &mut Pager) -> Result<bool> {
+        let Some((page, index)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        if is_interior(page) {
+            // The cell's left child comes before it, ending with its right most entry.
+            let child = child_page(page, *index)?;
+            check_depth(self.stack.len(), child)?;
+            self.descend(pager, child, Edge::Right)?;
+            return Ok(self.is_valid());
+        }
+        if *index > 0 && !page.cells.is_empty() {
+            *index = (*index).min(page.cells.len()) - 1;
+            return Ok(true);
+        }
+
+        // Done with this leaf, the entry before it is the cell to the left of the closest child
+        // that is not the left most one of its page.
+        let leaf_level = self.stack.len() - 1;
+        let ancestor = self.stack[..leaf_level]
+            .iter()
+            .rposition(|(_, index)| *index > 0);
+        let Some(level) = ancestor else {
+            self.stack.clear();
+            return Ok(false);
+        };
+        self.stack.truncate(level + 1);
+        self.stack[level].1 -= 1;
+        Ok(true)
+    }
+
+    /*
+     * Moves to the first entry `is_before` is false for, `is_before` being true for the entries up
+     * to some point in index order and false from there on. Returns false if there is no such
+     * entry, the cursor then sits past the last entry.
+     */
+    pub fn seek(
+        &mut self,
+        pager: &mut Pager,
+        is_before: impl Fn(&[Value]) -> bool,
+    ) -> Result<bool> {
+        self.stack.clear();
+        let mut page_index = self.root_page;
+        loop {
+            let page = pager.read_page(page_index)?;
+            let index = page.cells.partition_point(|cell| match cell {
+                BTreeCell::IndexLeafCell(cell) => is_before(&cell.payload),
+                BTreeCell::IndexInteriorCell(cell) => is_before(&cell.payload),
+                _ => false,
+            });
+            match page.header.page_type {
+                // The entry is in the child left of the first cell not before it, or that cell.
+                PageType::IndexInteriorPage => {
+                    page_index = child_page(&page, index)?;
+                    self.stack.push((page, index));
+                    check_depth(self.stack.len(), page_index)?;
+                }
+                PageType::IndexLeafPage => {
+                    let cell_count = page.cells.len();
+                    self.stack.push((page, index));
+                    if index == cell_count {
+                        return Ok(self.leave_leaf());
+                    }
+                    return Ok(true);
+                }
+                _ => return Err(not_an_index_page(page_index, &page)),
+            }
+        }
+    }
+
+    // Moves on from the last entry of a leaf to the cell after the closest child that is not the
+    // right most one of its page. Past the last entry of the index the cursor stays on the leaf,
+    // with its index at `cell_count`.
+    fn leave_leaf(&mut self) -> bool {
+        let leaf_level = self.stack.len() - 1;
+        let ancestor = self.stack[..leaf_level]
+            .iter()
+            .rposition(|(page, index)| *index < page.cells.len());
+        let Some(level) = ancestor else {
+            let (page, index) = &mut self.stack[leaf_level];
+            *index = page.cells.len();
+            return false;
+        };
+        self.stack.truncate(level + 1);
+        true
+    }
+
+    // Pushes the path from `page_index` down to its left most or right most leaf entry.
+    fn descend(&mut self, pager: &mut Pager, page_index: usize, edge: Edge) -> Result<()> {
+        let mut page_index = page_index;
+        loop {
+            let page = pager.read_page(page_index)?;
+            match page.header.page_type {
+                PageType::IndexInteriorPage => {
+                    let index = match edge {
+                        Edge::Left => 0,
+                        Edge::Right => page.cells.len(),
+                    };
+                    page_index = child_page(&page, index)?;
+                    self.stack.push((page, index));
+                    check_depth(self.stack.len(), page_index)?;
+                }
+                PageType::IndexLeafPage => {
+                    let index = match edge {
+                        Edge::Left => 0,
+                        Edge::Right => page.cells.len().saturating_sub(1),
+                    };
+                    self.stack.push((page, index));
+                    return Ok(());
+                }
+                _ => return Err(not_an_index_page(page_index, &page)),
+            }
+        }
+    }
+}
+
+fn is_interior(page: &BTreePage) -> bool {
+    page.header.page_type == PageType::IndexInteriorPage
+}
+
+enum Edge {
+    Left,
+    Right,
+}
+
+// SQLite's BTCURSOR_MAX_DEPTH. Even a tree of 65536 byte pages with rows of a few bytes is nowhere
+// near as deep, a path any longer means the pages point at each other in a loop.
+pub const MAX_DEPTH: usize = 20;
+
+// Fails when going down to `page_index` would take a path from the root longer than `MAX_DEPTH`.
+pub fn check_depth(depth: usize, page_index: usize) -> Result<()> {
+    if depth >= MAX_DEPTH {
+        return Err(DBError::Corrupt(
+            Location::page(page_index),
+            format!("B-tree is more than {} pages deep", MAX_DEPTH),
+        ));
+    }
+    Ok(())
+}
+
+// Page number of the child at `index` of an interior page, `cell_count` being the right most
+// pointer.
+fn child_page(page: &BTreePage, index: usize) -> Result<usize> {
+    match page.cells.get(index) {
+        Some(BTreeCell::TableInteriorCell(cell)) => Ok(cell.left_child_page as usize),
+        Some(BTreeCell::IndexInteriorCell(cell)) => Ok(cell.left_child_page as usize),
+        Some(cell) => Err(DBError::corrupt(format!(
+            "Expected an interior cell, got: {:?}",
+            cell
+        ))),
+        None => page
+            .header
+            .right_most_pointer
+            .map(|page| page as usize)
+            .ok_or_else(|| {
+                DBError::corrupt("Interior page without a right most pointer".to_string())
+            }),
+    }
+}
+
+fn not_a_table_page(page_index: usize, page: &BTreePage) -> DBError {
+    DBError::Corrupt(
+        Location::page(page_index),
+        format!(
+            "Expected a table b-tree page, got: {:?}",
+            page.header.page_type
+        ),
+    )
+}
+
+fn not_an_index_page(page_index: usize, page: &BTreePage) -> DBError {
+    DBError::Corrupt(
+        Location::page(page_index),
+        format!(
+            "Expected an index b-tree page, got: {:?}",
+            page.header.page_type
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::cursor::{BTreeCursor, IndexCursor};
+    use crate::page::file_structures::Value;
+    use crate::page::Database;
+
+    const MULTI_PAGE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/multi_page.db");
+    // Root page of the `worms` table in the fixture.
+    const WORMS_ROOT_PAGE: usize = 2;
+    // Root page of `worms_name`, the index on worms(name), whose root is an interior page.
+    const WORMS_NAME_ROOT_PAGE: usize = 3;
+
+    #[test]
+    fn cursor_forward_and_backward_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let pager = &mut database.pager;
+        let mut cursor = BTreeCursor::new(WORMS_ROOT_PAGE);
+
+        let mut rowids = Vec::new();
+        let mut more = cursor.first(pager).unwrap();
+        while more {
+            let cell = cursor.cell().unwrap();
+            assert!(
+                matches!(&cell.payload[1], Value::Text(name) if *name == format!("worm-{:04}", cell.row_id))
+            );
+            rowids.push(cell.row_id);
+            more = cursor.next(pager).unwrap();
+        }
+        assert_eq!(rowids, (1..=300).collect::<Vec<u64>>());
+        assert!(cursor.cell().is_none());
+
+        // Past the end `prev` walks back across leaf boundaries.
+        for rowid in (250..=300).rev() {
+            assert!(cursor.prev(pager).unwrap());
+            assert_eq!(cursor.rowid(), Some(rowid));
+        }
+
+        let mut rowids = Vec::new();
+        let mut more = cursor.last(pager).unwrap();
+        while more {
+            rowids.push(cursor.rowid().unwrap());
+            more = cursor.prev(pager).unwrap();
+        }
+        assert_eq!(rowids, (1..=300).rev().collect::<Vec<u64>>());
+        assert!(!cursor.is_valid());
+    }
+
+    #[test]
+    fn cursor_seek_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let pager = &mut database.pager;
+        let mut cursor = BTreeCursor::new(WORMS_ROOT_PAGE);
+
+        for rowid in [1, 17, 150, 299, 300] {
+            assert!(cursor.seek(pager, rowid).unwrap());
+            assert_eq!(cursor.rowid(), Some(rowid));
+        }
+
+        // Walking from a seek continues in order, across leaf boundaries.
+        assert!(cursor.seek(pager, 100).unwrap());
+        for rowid in 101..=130 {
+            assert!(cursor.next(pager).unwrap());
+            assert_eq!(cursor.rowid(), Some(rowid));
+        }
+        assert!(cursor.seek(pager, 100).unwrap());
+        for rowid in (70..100).rev() {
+            assert!(cursor.prev(pager).unwrap());
+            assert_eq!(cursor.rowid(), Some(rowid));
+        }
+
+        // Missing rowids land on the next row, or nowhere past the end.
+        assert!(!cursor.seek(pager, 0).unwrap());
+        assert_eq!(cursor.rowid(), Some(1));
+        assert!(!cursor.seek(pager, 301).unwrap());
+        assert!(!cursor.is_valid());
+    }
+
+    #[test]
+    fn index_cursor_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let pager = &mut database.pager;
+        let mut cursor = IndexCursor::new(WORMS_NAME_ROOT_PAGE);
+
+        // Entries on interior pages are visited in between their children.
+        let mut rowids = Vec::new();
+        let mut more = cursor.first(pager).unwrap();
+        while more {
+            let record = cursor.record().unwrap();
+            assert_eq!(
+                record[0],
+                Value::Text(format!("worm-{:04}", cursor.rowid().unwrap()))
+            );
+            rowids.push(cursor.rowid().unwrap());
+            more = cursor.next(pager).unwrap();
+        }
+        assert_eq!(rowids, (1..=300).collect::<Vec<i64>>());
+        assert!(cursor.record().is_none());
+        assert!(cursor.prev(pager).unwrap());
+        assert_eq!(cursor.rowid(), Some(300));
+
+        let mut rowids = Vec::new();
+        let mut more = cursor.last(pager).unwrap();
+        while more {
+            rowids.push(cursor.rowid().unwrap());
+            more = cursor.prev(pager).unwrap();
+        }
+        assert_eq!(rowids, (1..=300).rev().collect::<Vec<i64>>());
+        assert!(!cursor.is_valid());
+
+        let name = |record: &[Value]| match &record[0] {
+            Value::Text(name) => name.clone(),
+            value => panic!("Not a name: {:?}", value),
+        };
+        for rowid in 1..=300 {
+            let key = format!("worm-{:04}", rowid);
+            assert!(cursor.seek(pager, |record| name(record) < key).unwrap());
+            assert_eq!(cursor.rowid(), Some(rowid));
+        }
+        assert!(cursor
+            .seek(pager, |record| name(record).as_str() < "worm-0149x")
+            .unwrap());
+        for rowid in 150..=200 {
+            assert_eq!(cursor.rowid(), Some(rowid));
+            cursor.next(pager).unwrap();
+        }
+        assert!(cursor
+            .seek(pager, |record| name(record).as_str() < "worm-0149x")
+            .unwrap());
+        for rowid in (100..150).rev() {
+            assert!(cursor.prev(pager).unwrap());
+            assert_eq!(cursor.rowid(), Some(rowid));
+        }
+
+        // Seeking past every entry leaves the cursor past the end.
+        assert!(!cursor.seek(pager, |_| true).unwrap());
+        assert!(cursor.prev(pager).unwrap());
+        assert_eq!(cursor.rowid(), Some(300));
+        assert!(cursor.seek(pager, |_| false).unwrap());
+        assert_eq!(cursor.rowid(), Some(1));
+        assert!(!cursor.prev(pager).unwrap());
+    }
+}
@@ -1,31 +1,185 @@
 use std::error::Error;
-use std::fmt::{self, Formatter, Result};
+use std::fmt::{self, Formatter};
 
-#[derive(Debug, Clone)]
+use crate::sql::ast::Span;
+
+pub type Result<T> = std::result::Result<T, DBError>;
+
+#[derive(Debug)]
 pub enum DBError {
+    Io(std::io::Error),
     InvalidFileHeader(String),
-    InvalidPageHeader(String),
-    InvalidVarintSize,
-    InvalidPageType(u8),
-    InvalidSerialType(u64),
+    InvalidPageType(Location, u8),
+    InvalidVarint(Location),
+    InvalidSerialType(Location, u64),
+    // Text that is not valid in the text encoding of the database.
+    InvalidText(Location, String),
+    // Anything else in a page that does not add up: cells running past the end of the page, an
+    // overflow chain ending too soon, a pointer to a page that does not exist.
+    Corrupt(Location, String),
+    InvalidSchema(String),
+    ConstraintViolation(String),
+    // Another connection holds a lock we need.
+    Busy(String),
+    // A change that needs write access to a database opened read only.
+    ReadOnly(String),
+    // Something that can not be done right now, like checkpointing in the middle of a write.
+    Misuse(String),
+    // SQL that does not parse, with the part of the text the problem is at.
+    Syntax(Span, String),
+    // SQL that parses but cannot be run: a table or column that does not exist, a function called
+    // with the wrong number of arguments, something sand does not do.
+    Sql(String),
+}
+
+/*
+* Where in the database file bad bytes were found, as much of it as is known.
+*
+* The code decoding a value or a record only sees a slice of bytes and has no idea where they came
+* from, the callers fill in the rest on the way up with `on_page` and `at_offset`. The offset is
+* into the page, for anything inside a record it is the offset of the cell holding it.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Location {
+    pub page: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl Location {
+    pub fn page(page: usize) -> Location {
+        Location {
+            page: Some(page),
+            offset: None,
+        }
+    }
+
+    pub fn offset(offset: usize) -> Location {
+        Location {
+            page: None,
+            offset: Some(offset),
+        }
+    }
 }
 
-impl Error for DBError {}
+impl DBError {
+    pub fn corrupt(description: String) -> DBError {
+        DBError::Corrupt(Location::default(), description)
+    }
+
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Self::InvalidPageType(location, _)
+            | Self::InvalidVarint(location)
+            | Self::InvalidSerialType(location, _)
+            | Self::InvalidText(location, _)
+            | Self::Corrupt(location, _) => Some(*location),
+            _ => None,
+        }
+    }
+
+    // Fills in the page the error was found on, unless it is known already.
+    pub fn on_page(mut self, page: usize) -> DBError {
+        if let Some(location) = self.location_mut() {
+            location.page.get_or_insert(page);
+        }
+        self
+    }
+
+    // Fills in the offset into the page the error was found at, unless it is known already.
+    pub fn at_offset(mut self, offset: usize) -> DBError {
+        if let Some(location) = self.location_mut() {
+            location.offset.get_or_insert(offset);
+        }
+        self
+    }
+
+    fn location_mut(&mut self) -> Option<&mut Location> {
+        match self {
+            Self::InvalidPageType(location, _)
+            | Self::InvalidVarint(location)
+            | Self::InvalidSerialType(location, _)
+            | Self::InvalidText(location, _)
+            | Self::Corrupt(location, _) => Some(location),
+            _ => None,
+        }
+    }
+}
+
+impl Error for DBError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DBError {
+    fn from(err: std::io::Error) -> DBError {
+        DBError::Io(err)
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match (self.page, self.offset) {
+            (Some(page), Some(offset)) => write!(f, "page {}, offset {}: ", page, offset),
+            (Some(page), None) => write!(f, "page {}: ", page),
+            (None, Some(offset)) => write!(f, "offset {}: ", offset),
+            (None, None) => Ok(()),
+        }
+    }
+}
 
 impl fmt::Display for DBError {
-    fn fmt(&self, f: &mut Formatter) -> Result {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
             Self::InvalidFileHeader(msg) => write!(f, "{}", msg),
-            Self::InvalidPageHeader(msg) => write!(f, "{}", msg),
-            Self::InvalidVarintSize => write!(f, "Invalid Varint Size"),
-            Self::InvalidPageType(page_type) => write!(
+            Self::InvalidPageType(location, page_type) => write!(
                 f,
-                "Invalid Page Type: {}. Page Type must be either 2, 5, 10 or 15.",
-                page_type
+                "{}Invalid Page Type: {}. Page Type must be either 2, 5, 10 or 13.",
+                location, page_type
             ),
-            Self::InvalidSerialType(serial_type) => {
-                write!(f, "Invalid Serial Type: {}", serial_type)
+            Self::InvalidVarint(location) => write!(f, "{}Invalid Varint", location),
+            Self::InvalidSerialType(location, serial_type) => {
+                write!(f, "{}Invalid Serial Type: {}", location, serial_type)
             }
+            Self::InvalidText(location, msg) => write!(f, "{}{}", location, msg),
+            Self::Corrupt(location, msg) => write!(f, "{}{}", location, msg),
+            Self::InvalidSchema(msg) => write!(f, "{}", msg),
+            Self::ConstraintViolation(msg) => write!(f, "Constraint violation: {}", msg),
+            Self::Busy(msg) => write!(f, "Database is busy: {}", msg),
+            Self::ReadOnly(msg) => write!(f, "Database is read only: {}", msg),
+            Self::Misuse(msg) => write!(f, "{}", msg),
+            Self::Syntax(span, msg) => write!(f, "{} at offset {}", msg, span.start),
+            Self::Sql(msg) => write!(f, "{}", msg),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::page::errors::{DBError, Location};
+
+    #[test]
+    fn location_test() {
+        let err = DBError::InvalidSerialType(Location::default(), 10)
+            .at_offset(120)
+            .on_page(5)
+            // The innermost location wins.
+            .at_offset(8);
+        assert_eq!(
+            err.location(),
+            Some(Location {
+                page: Some(5),
+                offset: Some(120)
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "page 5, offset 120: Invalid Serial Type: 10"
+        );
+        assert_eq!(DBError::Busy("x".to_string()).on_page(5).location(), None);
+    }
+}
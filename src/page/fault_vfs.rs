@@ -0,0 +1,555 @@
+use super::errors::{DBError, Result};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::vfs::{LockLevel, OpenMode, Vfs, VfsFile};
+
+/*
+* A Vfs for tests that wraps another one and makes its I/O go wrong on purpose, to prove a commit
+* is all or nothing no matter where things stop.
+*
+* Writes, syncs and deletes are counted, and a fault can be set up for the nth one of them. Besides
+* that the contents every file had at its last sync are kept around, so `power_loss` can throw away
+* whatever was written after it, the way a machine losing power would. Files created or deleted
+* since their directory was last synced are gone or back again after losing power too.
+*/
+#[derive(Debug, Clone)]
+pub struct FaultVfs {
+    inner: Arc<dyn Vfs>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultOp {
+    // Writes and truncates.
+    Write,
+    Sync,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    // The operation fails with an I/O error without doing anything, later ones work again.
+    Fail,
+    // The operation says it worked but does nothing, like a disk that lies.
+    Drop,
+    // Only this many bytes of the write make it to the file before the process dies. Anything but
+    // a write just dies.
+    Tear(usize),
+    // The process dies right before the operation, it and everything after it fails.
+    Crash,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    // How many operations of each kind have been done.
+    counts: HashMap<FaultOp, usize>,
+    // Faults waiting for their operation, by the count that operation will have.
+    faults: HashMap<(FaultOp, usize), Fault>,
+    crashed: bool,
+    // Contents as of the last sync of every file written since.
+    synced: HashMap<String, Vec<u8>>,
+    // Files created or deleted since their directory was synced, with what they held before, None
+    // for files that did not exist.
+    unsynced_entries: HashMap<String, Option<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+pub struct FaultFile {
+    path: String,
+    inner: Box<dyn VfsFile>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultVfs {
+    pub fn new(inner: Arc<dyn Vfs>) -> FaultVfs {
+        FaultVfs {
+            inner,
+            state: Arc::new(Mutex::new(FaultState::default())),
+        }
+    }
+
+    // Makes the `nth` operation of kind `op` from now on, counting from 1, run into `fault`.
+    pub fn inject(&self, op: FaultOp, nth: usize, fault: Fault) {
+        let mut state = self.state.lock().unwrap();
+        let count = state.count(op);
+        state.faults.insert((op, count + nth), fault);
+    }
+
+    // How many operations of kind `op` have been done so far.
+    pub fn count(&self, op: FaultOp) -> usize {
+        self.state.lock().unwrap().count(op)
+    }
+
+    pub fn crashed(&self) -> bool {
+        self.state.lock().unwrap().crashed
+    }
+
+    // Starts over after a crash of the process. Everything it wrote is still there, the OS has it,
+    // and faults that did not happen yet are forgotten.
+    pub fn restart(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.crashed = false;
+        state.faults.clear();
+    }
+
+    // Starts over after losing power: every file goes back to what it was at its last sync, and
+    // the directories to what they were at theirs.
+    pub fn power_loss(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let synced: Vec<(String, Vec<u8>)> = state.synced.drain().collect();
+        let entries: Vec<(String, Option<Vec<u8>>)> = state.unsynced_entries.drain().collect();
+        drop(state);
+        let restore = |path: &str, contents: &[u8]| -> Result<()> {
+            let mut file = self.inner.open(path, OpenMode::Create)?;
+            file.truncate(0)?;
+            file.write_at(0, contents)?;
+            file.sync()
+        };
+        for (path, contents) in synced {
+            restore(&path, &contents)?;
+        }
+        for (path, contents) in entries {
+            match contents {
+                Some(contents) => restore(&path, &contents)?,
+                None => self.inner.delete(&path)?,
+            }
+        }
+        self.restart();
+        Ok(())
+    }
+
+    // Loses power after the OS wrote every file back on its own, but none of the directories.
+    pub fn power_loss_after_write_back(&self) -> Result<()> {
+        self.state.lock().unwrap().synced.clear();
+        self.power_loss()
+    }
+}
+
+impl FaultState {
+    fn count(&self, op: FaultOp) -> usize {
+        self.counts.get(&op).copied().unwrap_or(0)
+    }
+
+    /*
+     * Counts an operation and returns the fault it runs into, if it is one the operation has to
+     * carry out itself: `Drop`, or `Tear` for a write. The other faults end up as an error right
+     * here. A process that died does not get to do anything.
+     */
+    fn next(&mut self, op: FaultOp, is_write: bool) -> Result<Option<Fault>> {
+        self.check_crashed()?;
+        let count = self.counts.entry(op).or_insert(0);
+        *count += 1;
+        match self.faults.remove(&(op, *count)) {
+            Some(Fault::Fail) => Err(io_error(format!("Injected failure of {:?}", op))),
+            Some(Fault::Tear(_)) if !is_write => self.crash(),
+            Some(Fault::Crash) => self.crash(),
+            fault => Ok(fault),
+        }
+    }
+
+    fn crash<T>(&mut self) -> Result<T> {
+        self.crashed = true;
+        Err(io_error("The process crashed".to_string()))
+    }
+
+    fn check_crashed(&self) -> Result<()> {
+        if self.crashed {
+            return Err(io_error("The process crashed".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn io_error(message: String) -> DBError {
+    DBError::Io(std::io::Error::other(message))
+}
+
+impl Vfs for FaultVfs {
+    fn open(&self, path: &str, mode: OpenMode) -> Result<Box<dyn VfsFile>> {
+        let mut state = self.state.lock().unwrap();
+        state.check_crashed()?;
+        if mode == OpenMode::Create && !self.inner.exists(path)? {
+            state
+                .unsynced_entries
+                .entry(path.to_string())
+                .or_insert(None);
+        }
+        drop(state);
+        let inner = self.inner.open(path, mode)?;
+        Ok(Box::new(FaultFile {
+            path: path.to_string(),
+            inner,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.next(FaultOp::Delete, false)? == Some(Fault::Drop) {
+            return Ok(());
+        }
+        if self.inner.exists(path)? && !state.unsynced_entries.contains_key(path) {
+            let contents = match state.synced.get(path) {
+                Some(contents) => contents.clone(),
+                None => {
+                    let mut file = self.inner.open(path, OpenMode::ReadOnly)?;
+                    let mut contents = vec![0; file.size()? as usize];
+                    file.read_exact_at(0, &mut contents)?;
+                    contents
+                }
+            };
+            state
+                .unsynced_entries
+                .insert(path.to_string(), Some(contents));
+        }
+        state.synced.remove(path);
+        self.inner.delete(path)
+    }
+
+    fn exists(&self, path: &str) -> Result<bool> {
+        self.state.lock().unwrap().check_crashed()?;
+        self.inner.exists(path)
+    }
+
+    fn sync_directory(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.next(FaultOp::Sync, false)? == Some(Fault::Drop) {
+            return Ok(());
+        }
+        self.inner.sync_directory(path)?;
+        let directory = Path::new(path).parent();
+        state
+            .unsynced_entries
+            .retain(|entry, _| Path::new(entry).parent() != directory);
+        Ok(())
+    }
+}
+
+impl FaultFile {
+    // Keeps what the file holds right now, before the first write since its last sync changes it.
+    fn remember_synced(&mut self, state: &mut FaultState) -> Result<()> {
+        if state.synced.contains_key(&self.path) {
+            return Ok(());
+        }
+        let mut contents = vec![0; self.inner.size()? as usize];
+        self.inner.read_exact_at(0, &mut contents)?;
+        state.synced.insert(self.path.clone(), contents);
+        Ok(())
+    }
+}
+
+impl VfsFile for FaultFile {
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        self.state.lock().unwrap().check_crashed()?;
+        self.inner.read_at(offset, buffer)
+    }
+
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> Result<()> {
+        let state = self.state.clone();
+        let mut state = state.lock().unwrap();
+        let fault = state.next(FaultOp::Write, true)?;
+        if fault == Some(Fault::Drop) {
+            return Ok(());
+        }
+        self.remember_synced(&mut state)?;
+        if let Some(Fault::Tear(bytes)) = fault {
+            self.inner
+                .write_at(offset, &buffer[..bytes.min(buffer.len())])?;
+            return state.crash();
+        }
+        self.inner.write_at(offset, buffer)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let state = self.state.clone();
+        let mut state = state.lock().unwrap();
+        if state.next(FaultOp::Sync, false)? == Some(Fault::Drop) {
+            return Ok(());
+        }
+        self.inner.sync()?;
+        state.synced.remove(&self.path);
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<()> {
+        let state = self.state.clone();
+        let mut state = state.lock().unwrap();
+        if state.next(FaultOp::Write, false)? == Some(Fault::Drop) {
+            return Ok(());
+        }
+        self.remember_synced(&mut state)?;
+        self.inner.truncate(size)
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.state.lock().unwrap().check_crashed()?;
+        self.inner.size()
+    }
+
+    // Locks keep working after a crash, a dead process lets go of its locks.
+    fn lock(&mut self, level: LockLevel) -> Result<bool> {
+        self.inner.lock(level)
+    }
+
+    fn check_reserved_lock(&mut self) -> Result<bool> {
+        self.inner.check_reserved_lock()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn set_mmap_size(&mut self, size: u64) -> Result<()> {
+        self.inner.set_mmap_size(size)
+    }
+
+    fn fetch(&mut self, offset: u64, len: usize) -> Result<Option<&[u8]>> {
+        self.state.lock().unwrap().check_crashed()?;
+        self.inner.fetch(offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use crate::page::btree::{delete, insert};
+    use crate::page::cursor::BTreeCursor;
+    use crate::page::fault_vfs::{Fault, FaultOp, FaultVfs};
+    use crate::page::file_structures::{read_u32, BTreeCell, Value};
+    use crate::page::freelist::free_pages;
+    use crate::page::pager::JournalMode;
+    use crate::page::vfs::MemoryVfs;
+    use crate::page::wal::CheckpointMode;
+    use crate::page::Database;
+
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+    const PATH: &str = "crash.db";
+
+    fn item(id: u64) -> Vec<Value> {
+        // Long names so the rows spread over a good number of pages, some with overflow pages.
+        let name = format!("item-{}-{}", id, "x".repeat(id as usize % 7 * 150));
+        vec![Value::Null, Value::Text(name), Value::Null, Value::Null]
+    }
+
+    fn open(vfs: &FaultVfs) -> Database {
+        Database::open_with_vfs(PATH.to_string(), Arc::new(vfs.clone())).unwrap()
+    }
+
+    // A database with rows 1 to 20 in it, in the given journal mode.
+    fn setup(journal_mode: JournalMode) -> FaultVfs {
+        let memory = MemoryVfs::new();
+        memory.write_file(PATH, std::fs::read(EMPTY_TABLE_DB).unwrap());
+        let vfs = FaultVfs::new(Arc::new(memory));
+        let mut database = open(&vfs);
+        database.set_journal_mode(journal_mode).unwrap();
+        for id in 1..=20 {
+            database.insert("items", item(id)).unwrap();
+        }
+        vfs
+    }
+
+    // One transaction that splits, merges and frees pages, then a checkpoint in WAL mode. Returns
+    // whether the commit went through.
+    fn workload(database: &mut Database) -> bool {
+        let root_page = database.get_table("items").unwrap().root_page;
+        let result = (|| {
+            for id in 21..=120 {
+                insert(&mut database.pager, root_page, id, &item(id))?;
+            }
+            for id in (1..=60).filter(|id| id % 3 != 0) {
+                delete(&mut database.pager, root_page, id)?;
+            }
+            database.pager.commit()
+        })();
+        if result.is_err() {
+            database.pager.rollback().ok();
+            return false;
+        }
+        if database.journal_mode() == JournalMode::Wal {
+            database.checkpoint(CheckpointMode::Restart).ok();
+        }
+        true
+    }
+
+    fn rows_before() -> Vec<u64> {
+        (1..=20).collect()
+    }
+
+    fn rows_after() -> Vec<u64> {
+        (1..=120).filter(|id| *id > 60 || id % 3 == 0).collect()
+    }
+
+    /*
+     * Checks that every page of the database is used exactly once, by a b-tree, an overflow chain
+     * or the freelist, and that every row can be read. Returns the rowids of `items`.
+     */
+    fn check_consistency(database: &mut Database) -> Vec<u64> {
+        let mut used = HashSet::new();
+        let mut pages: Vec<usize> = database.tables().map(|table| table.root_page).collect();
+        pages.extend(database.indexes().map(|index| index.root_page));
+        let mut overflow_pages = Vec::new();
+        while let Some(page_index) = pages.pop() {
+            assert!(used.insert(page_index), "Page {} used twice", page_index);
+            let page = database.pager.read_page(page_index).unwrap();
+            pages.extend(page.header.right_most_pointer.map(|page| page as usize));
+            for cell in page.cells.iter() {
+                let (left_child_page, first_overflow_page) = match cell {
+                    BTreeCell::TableInteriorCell(cell) => (Some(cell.left_child_page), None),
+                    BTreeCell::TableLeafCell(cell) => (None, cell.first_overflow_page),
+                    BTreeCell::IndexInteriorCell(cell) => {
+                        (Some(cell.left_child_page), cell.first_overflow_page)
+                    }
+                    BTreeCell::IndexLeafCell(cell) => (None, cell.first_overflow_page),
+                };
+                pages.extend(left_child_page.map(|page| page as usize));
+                overflow_pages.extend(first_overflow_page);
+            }
+        }
+        for first_overflow_page in overflow_pages {
+            let mut next_page = first_overflow_page as usize;
+            while next_page != 0 {
+                assert!(used.insert(next_page), "Page {} used twice", next_page);
+                next_page = read_u32(&database.pager.read_raw_page(next_page).unwrap(), 0) as usize;
+            }
+        }
+        for page_index in free_pages(&mut database.pager).unwrap() {
+            assert!(used.insert(page_index), "Page {} used twice", page_index);
+        }
+        let expected: HashSet<usize> = (1..=database.pager.page_count()).collect();
+        assert_eq!(used, expected);
+
+        let root_page = database.get_table("items").unwrap().root_page;
+        let mut cursor = BTreeCursor::new(root_page);
+        let mut rowids = Vec::new();
+        let mut more = cursor.first(&mut database.pager).unwrap();
+        while more {
+            let cell = cursor.cell().unwrap();
+            match (&cell.payload[1], &item(cell.row_id)[1]) {
+                (Value::Text(name), Value::Text(expected)) => assert_eq!(name, expected),
+                (value, _) => panic!("Expected a name, got: {:?}", value),
+            }
+            rowids.push(cell.row_id);
+            more = cursor.next(&mut database.pager).unwrap();
+        }
+        rowids
+    }
+
+    // What happens after the fault.
+    #[derive(Debug, Clone, Copy)]
+    enum Ending {
+        Restart,
+        PowerLoss,
+        PowerLossAfterWriteBack,
+    }
+
+    // Crashes at every write, sync and delete of the workload in turn, with and without losing
+    // power, and checks the database comes back with either all of the commit or none of it.
+    fn crash_at_every_step(journal_mode: JournalMode, fault: Fault) {
+        let vfs = setup(journal_mode);
+        let mut database = open(&vfs);
+        let counts: Vec<(FaultOp, usize)> = [FaultOp::Write, FaultOp::Sync, FaultOp::Delete]
+            .into_iter()
+            .map(|op| (op, vfs.count(op)))
+            .collect();
+        assert!(workload(&mut database));
+        assert_eq!(check_consistency(&mut database), rows_after());
+
+        for (op, count) in counts {
+            for nth in 1..=vfs.count(op) - count {
+                for ending in [
+                    Ending::Restart,
+                    Ending::PowerLoss,
+                    Ending::PowerLossAfterWriteBack,
+                ] {
+                    let vfs = setup(journal_mode);
+                    let mut database = open(&vfs);
+                    vfs.inject(op, nth, fault);
+                    let committed = workload(&mut database);
+                    drop(database);
+                    match ending {
+                        Ending::Restart => vfs.restart(),
+                        Ending::PowerLoss => vfs.power_loss().unwrap(),
+                        Ending::PowerLossAfterWriteBack => {
+                            vfs.power_loss_after_write_back().unwrap()
+                        }
+                    }
+
+                    let mut database = open(&vfs);
+                    let rowids = check_consistency(&mut database);
+                    let context = format!("{:?} {:?} {} {:?}", fault, op, nth, ending);
+                    if committed {
+                        assert_eq!(rowids, rows_after(), "{}", context);
+                    } else {
+                        assert!(
+                            rowids == rows_before() || rowids == rows_after(),
+                            "{}",
+                            context
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rollback_journal_crash_test() {
+        crash_at_every_step(JournalMode::Delete, Fault::Crash);
+        crash_at_every_step(JournalMode::Delete, Fault::Tear(100));
+    }
+
+    #[test]
+    fn wal_crash_test() {
+        crash_at_every_step(JournalMode::Wal, Fault::Crash);
+        crash_at_every_step(JournalMode::Wal, Fault::Tear(100));
+    }
+
+    #[test]
+    fn failed_write_is_rolled_back_test() {
+        let faults = [
+            (JournalMode::Delete, FaultOp::Write, 1),
+            (JournalMode::Delete, FaultOp::Write, 4),
+            (JournalMode::Delete, FaultOp::Sync, 2),
+            (JournalMode::Delete, FaultOp::Sync, 3),
+            (JournalMode::Delete, FaultOp::Delete, 1),
+            (JournalMode::Wal, FaultOp::Write, 1),
+            (JournalMode::Wal, FaultOp::Sync, 1),
+        ];
+        for (journal_mode, op, nth) in faults {
+            {
+                let vfs = setup(journal_mode);
+                let mut database = open(&vfs);
+                vfs.inject(op, nth, Fault::Fail);
+                assert!(!workload(&mut database));
+
+                // The same connection goes on as if the commit never happened.
+                assert_eq!(check_consistency(&mut database), rows_before());
+                assert!(workload(&mut database));
+                assert_eq!(check_consistency(&mut database), rows_after());
+            }
+        }
+    }
+
+    #[test]
+    fn unsynced_commit_is_lost_on_power_loss_test() {
+        // The disk lies about syncing the WAL, so the commit never makes it to disk. It is gone
+        // after losing power, but the database is fine.
+        let vfs = setup(JournalMode::Wal);
+        let mut database = open(&vfs);
+        vfs.inject(FaultOp::Sync, 1, Fault::Drop);
+        vfs.inject(FaultOp::Write, 2, Fault::Crash);
+        assert!(workload(&mut database));
+        assert!(vfs.crashed());
+        drop(database);
+
+        vfs.power_loss().unwrap();
+        let mut database = open(&vfs);
+        assert_eq!(check_consistency(&mut database), rows_before());
+    }
+}
@@ -1,25 +1,97 @@
-use crate::page::errors::DBError;
-use anyhow::{anyhow, Result};
+use crate::page::errors::{DBError, Location, Result};
+use crate::page::logging::RECORD;
+use crate::page::vfs::VfsFile;
+use std::borrow::Cow;
 use std::convert::TryFrom;
-use std::{
-    fs::File,
-    io::{Read, Seek, SeekFrom},
-    usize,
-};
+
+use log::{debug, trace};
 
 // Database header size in bytes.
 pub const DB_HEADER_SIZE: usize = 100;
 
 // The constant file header.
-pub const HEADER_STRING: &'static [u8; 16] = b"SQLite format 3\0";
+pub const HEADER_STRING: &[u8; 16] = b"SQLite format 3\0";
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TextEncoding {
     UTF8,
     UTF16le,
     UTF16be,
 }
 
+impl TextEncoding {
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            Self::UTF8 => text.as_bytes().to_vec(),
+            Self::UTF16le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Self::UTF16be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        }
+    }
+
+    // Number of bytes `encode` turns the text into.
+    pub fn encoded_len(&self, text: &str) -> usize {
+        match self {
+            Self::UTF8 => text.len(),
+            Self::UTF16le | Self::UTF16be => text.encode_utf16().count() * 2,
+        }
+    }
+}
+
+/*
+* Turns the text of records back into Strings, following the text encoding of the database.
+*
+* SQLite stores whatever bytes it is handed without checking them, so databases with text that is
+* not valid in their encoding do exist. Reading such text fails, unless the decoder is lossy, then
+* every invalid sequence becomes U+FFFD instead.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextDecoder {
+    pub encoding: TextEncoding,
+    pub lossy: bool,
+}
+
+impl TextDecoder {
+    pub fn decode(&self, bytes: &[u8]) -> Result<String> {
+        let (text, valid) = match self.encoding {
+            TextEncoding::UTF8 => match String::from_utf8_lossy(bytes) {
+                Cow::Borrowed(text) => (text.to_string(), true),
+                Cow::Owned(text) => (text, false),
+            },
+            TextEncoding::UTF16le | TextEncoding::UTF16be => {
+                let units = bytes.chunks_exact(2).map(|unit| match self.encoding {
+                    TextEncoding::UTF16le => u16::from_le_bytes([unit[0], unit[1]]),
+                    _ => u16::from_be_bytes([unit[0], unit[1]]),
+                });
+                // A byte left over at the end is dropped, like SQLite does.
+                let mut valid = bytes.len().is_multiple_of(2);
+                let text = char::decode_utf16(units)
+                    .map(|char| {
+                        char.unwrap_or_else(|_| {
+                            valid = false;
+                            char::REPLACEMENT_CHARACTER
+                        })
+                    })
+                    .collect();
+                (text, valid)
+            }
+        };
+
+        if !valid && !self.lossy {
+            return Err(DBError::InvalidText(
+                Location::default(),
+                format!("Text is not valid {:?}: {:?}", self.encoding, text),
+            ));
+        }
+        if !valid {
+            debug!(
+                target: RECORD,
+                "Replaced invalid {:?} text with U+FFFD: {:?}", self.encoding, text
+            );
+        }
+        Ok(text)
+    }
+}
+
 /*
 * 100 byte DB header for the SQLite file.
 * The first page of the DB file is the file header. It gives the metadat for the database.
@@ -53,17 +125,17 @@ pub enum TextEncoding {
 #[derive(Debug, Clone)]
 pub struct DBHeader {
     header_string: [u8; 16],
-    pub page_size: u16,
-    write_version: u8,
-    read_version: u8,
+    pub page_size: u32,
+    pub write_version: u8,
+    pub read_version: u8,
     reserved_space: u8,
     max_payload_fraction: u8,
     min_payload_fraction: u8,
     min_leaf_fraction: u8,
-    change_counter: u32,
-    db_size_in_pages: u32,
-    freelist_trunk_page: u32,
-    freelist_pages: u32,
+    pub change_counter: u32,
+    pub db_size_in_pages: u32,
+    pub freelist_trunk_page: u32,
+    pub freelist_pages: u32,
     schema_cookie: u32,
     schema_format: u32,
     default_cache_page_size: u32,
@@ -73,43 +145,128 @@ pub struct DBHeader {
     incremental_vacuum: u32,
     application_id: u32,
     reserved: [u8; 20],
-    version_valid_for: u32,
+    pub version_valid_for: u32,
     version_number: u32,
 }
 
-pub fn read_db_header(file: &mut File) -> Result<DBHeader> {
-    let mut buffer: Vec<u8> = Vec::new();
-    buffer.resize(100, 0);
-    file.seek(SeekFrom::Start(0))?;
-    file.read_exact(&mut buffer)?;
+impl DBHeader {
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.text_encoding
+    }
+
+    /*
+     * Number of pages the page cache holds by default. Like `PRAGMA cache_size` a positive value is a
+     * number of pages and a negative one a number of KiB. 0 means SQLite's default of 2000 KiB.
+     */
+    pub fn cache_size_in_pages(&self) -> usize {
+        let page_size = self.page_size as i64;
+        match self.default_cache_page_size as i32 as i64 {
+            0 => (2000 * 1024 / page_size) as usize,
+            size if size > 0 => size as usize,
+            size => (-size * 1024 / page_size) as usize,
+        }
+    }
+
+    // Page size minus the reserved space at the end of every page.
+    pub fn usable_size(&self) -> usize {
+        self.page_size as usize - self.reserved_space as usize
+    }
+
+    // Largest payload that is stored entirely on a b-tree page of the given type.
+    pub fn max_local(&self, page_type: &PageType) -> usize {
+        let usable_size = self.usable_size();
+        match page_type {
+            PageType::TableLeafPage => usable_size - 35,
+            _ => (usable_size - 12) * self.max_payload_fraction as usize / 255 - 23,
+        }
+    }
+
+    // Smallest part of a spilled payload that is kept on the b-tree page of the given type.
+    pub fn min_local(&self, page_type: &PageType) -> usize {
+        let fraction = match page_type {
+            PageType::TableLeafPage => self.min_leaf_fraction,
+            _ => self.min_payload_fraction,
+        };
+        (self.usable_size() - 12) * fraction as usize / 255 - 23
+    }
+
+    // Number of payload bytes stored on the b-tree page itself, the rest goes to overflow pages.
+    pub fn local_payload_size(&self, page_type: &PageType, payload_size: usize) -> usize {
+        let max_local = self.max_local(page_type);
+        if payload_size <= max_local {
+            return payload_size;
+        }
+
+        let min_local = self.min_local(page_type);
+        let local_size = min_local + (payload_size - min_local) % (self.usable_size() - 4);
+        if local_size <= max_local {
+            local_size
+        } else {
+            min_local
+        }
+    }
+}
+
+pub fn read_db_header(file: &mut dyn VfsFile) -> Result<DBHeader> {
+    let mut buffer: Vec<u8> = vec![0; DB_HEADER_SIZE];
+    file.read_exact_at(0, &mut buffer)?;
+    parse_db_header(&buffer)
+}
 
+// Parses the header from the first 100 bytes of page 1.
+pub fn parse_db_header(buffer: &[u8]) -> Result<DBHeader> {
+    if buffer.len() < DB_HEADER_SIZE {
+        return Err(DBError::InvalidFileHeader(format!(
+            "The header takes {} bytes, the file only has {}",
+            DB_HEADER_SIZE,
+            buffer.len()
+        )));
+    }
     let mut header_string = [0u8; 16];
     let mut reserved = [0u8; 20];
-    let page_size = u16::from_be_bytes([buffer[16], buffer[17]]);
+    // 65536 does not fit the two bytes and is stored as 1.
+    let page_size = match u16::from_be_bytes([buffer[16], buffer[17]]) {
+        1 => 65536,
+        page_size => page_size as u32,
+    };
     let text_encoding_byte = u32::from_be_bytes([buffer[56], buffer[57], buffer[58], buffer[59]]);
 
     header_string.copy_from_slice(&buffer[0..16]);
     reserved.copy_from_slice(&buffer[72..92]);
 
     if !buffer.starts_with(HEADER_STRING) {
-        return Err(anyhow!(DBError::InvalidFileHeader(format!(
+        return Err(DBError::InvalidFileHeader(format!(
             "Invalid Header String: {}",
-            String::from_utf8_lossy(&buffer[..HEADER_STRING.len()]).to_string()
-        ))));
+            String::from_utf8_lossy(&buffer[..HEADER_STRING.len()])
+        )));
     }
 
-    if !page_size.is_power_of_two() {
-        return Err(anyhow!(DBError::InvalidFileHeader(format!(
-            "Page size must be a power of 2: {}",
+    if !page_size.is_power_of_two() || page_size < 512 {
+        return Err(DBError::InvalidFileHeader(format!(
+            "Page size must be a power of 2 between 512 and 65536: {}",
             page_size
-        ))));
+        )));
+    }
+
+    // SQLite refuses these as well, and the payload size math only works out with them.
+    if page_size as usize - (buffer[20] as usize) < 480 {
+        return Err(DBError::InvalidFileHeader(format!(
+            "{} bytes of reserved space leave less than 480 usable bytes in a page",
+            buffer[20]
+        )));
+    }
+    if buffer[21..24] != [64, 32, 32] {
+        return Err(DBError::InvalidFileHeader(format!(
+            "Payload fractions must be 64, 32 and 32: {:?}",
+            &buffer[21..24]
+        )));
     }
 
     if text_encoding_byte != 1 && text_encoding_byte != 2 && text_encoding_byte != 3 {
-        return Err(anyhow!(DBError::InvalidFileHeader(format!(
+        return Err(DBError::InvalidFileHeader(format!(
             "Invalid text encoding: {}",
             text_encoding_byte
-        ))));
+        )));
     }
 
     let header = DBHeader {
@@ -148,18 +305,55 @@ pub fn read_db_header(file: &mut File) -> Result<DBHeader> {
     Ok(header)
 }
 
+// Inverse of `read_db_header`, writes the header into the first 100 bytes of `buffer`.
+pub fn write_db_header(header: &DBHeader, buffer: &mut [u8]) {
+    let text_encoding: u32 = match header.text_encoding {
+        TextEncoding::UTF8 => 1,
+        TextEncoding::UTF16le => 2,
+        TextEncoding::UTF16be => 3,
+    };
+
+    buffer[0..16].copy_from_slice(&header.header_string);
+    let page_size = match header.page_size {
+        65536 => 1,
+        page_size => page_size as u16,
+    };
+    buffer[16..18].copy_from_slice(&page_size.to_be_bytes());
+    buffer[18] = header.write_version;
+    buffer[19] = header.read_version;
+    buffer[20] = header.reserved_space;
+    buffer[21] = header.max_payload_fraction;
+    buffer[22] = header.min_payload_fraction;
+    buffer[23] = header.min_leaf_fraction;
+    buffer[24..28].copy_from_slice(&header.change_counter.to_be_bytes());
+    buffer[28..32].copy_from_slice(&header.db_size_in_pages.to_be_bytes());
+    buffer[32..36].copy_from_slice(&header.freelist_trunk_page.to_be_bytes());
+    buffer[36..40].copy_from_slice(&header.freelist_pages.to_be_bytes());
+    buffer[40..44].copy_from_slice(&header.schema_cookie.to_be_bytes());
+    buffer[44..48].copy_from_slice(&header.schema_format.to_be_bytes());
+    buffer[48..52].copy_from_slice(&header.default_cache_page_size.to_be_bytes());
+    buffer[52..56].copy_from_slice(&header.vacuum.to_be_bytes());
+    buffer[56..60].copy_from_slice(&text_encoding.to_be_bytes());
+    buffer[60..64].copy_from_slice(&header.user_version.to_be_bytes());
+    buffer[64..68].copy_from_slice(&header.incremental_vacuum.to_be_bytes());
+    buffer[68..72].copy_from_slice(&header.application_id.to_be_bytes());
+    buffer[72..92].copy_from_slice(&header.reserved);
+    buffer[92..96].copy_from_slice(&header.version_valid_for.to_be_bytes());
+    buffer[96..100].copy_from_slice(&header.version_number.to_be_bytes());
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct BTreePageHeader {
-    page_type: PageType,
-    first_freeblock_offset: u16,
-    cell_count: u16,
-    cell_content_area: u16,
-    number_of_fragmented_free_bytes: u8,
-    right_most_pointer: Option<u32>,
+    pub page_type: PageType,
+    pub first_freeblock_offset: u16,
+    pub cell_count: u16,
+    pub cell_content_area: u16,
+    pub number_of_fragmented_free_bytes: u8,
+    pub right_most_pointer: Option<u32>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PageType {
     IndexInteriorPage = 2,
     TableInteriorPage = 5,
@@ -173,56 +367,112 @@ pub struct BTreePage {
     pub cells: Vec<BTreeCell>,
 }
 
-// TODO: Look into how databases handle I/O, passing the file descriptor around like a maniac is
-// not gonna cut it.
-pub fn read_page(file: &mut File, page_size: usize, page_index: usize) -> Result<BTreePage> {
-    // TODO: Implement some IO maybe?
-    let mut page: Vec<u8> = Vec::new();
-    page.resize(page_size, 0);
-    file.seek(SeekFrom::Start(((page_index - 1) * page_size) as u64))?;
-    file.read_exact(&mut page)?;
+pub fn read_page_bytes(
+    file: &mut dyn VfsFile,
+    page_size: usize,
+    page_index: usize,
+) -> Result<Vec<u8>> {
+    let mut page: Vec<u8> = vec![0; page_size];
+    file.read_exact_at(((page_index - 1) * page_size) as u64, &mut page)?;
+    Ok(page)
+}
+
+// Parses a raw page into a BTreePage. Payloads that spill onto overflow pages are put back together
+// with the pages returned by `read_overflow_page`.
+pub fn read_page(
+    page: &[u8],
+    page_index: usize,
+    db_header: &DBHeader,
+    text_decoder: &TextDecoder,
+    read_overflow_page: &mut dyn FnMut(u32) -> Result<Vec<u8>>,
+) -> Result<BTreePage> {
+    parse_page(
+        page,
+        page_index,
+        db_header,
+        text_decoder,
+        read_overflow_page,
+    )
+    .map_err(|err| err.on_page(page_index))
+}
 
-    let mut offset = if page_index == 1 { DB_HEADER_SIZE } else { 0 };
-    println!("Offset for reading Page Header: {}", offset);
+fn parse_page(
+    page: &[u8],
+    page_index: usize,
+    db_header: &DBHeader,
+    text_decoder: &TextDecoder,
+    read_overflow_page: &mut dyn FnMut(u32) -> Result<Vec<u8>>,
+) -> Result<BTreePage> {
+    let header_offset = if page_index == 1 { DB_HEADER_SIZE } else { 0 };
 
+    let bytes = bytes_at(page, header_offset, 8, "Page header")?;
     let mut header = BTreePageHeader {
-        page_type: page[offset].try_into()?,
-        first_freeblock_offset: u16::from_be_bytes([page[offset + 1], page[offset + 2]]),
-        cell_count: u16::from_be_bytes([page[offset + 3], page[offset + 4]]),
-        cell_content_area: u16::from_be_bytes([page[offset + 5], page[offset + 6]]),
-        number_of_fragmented_free_bytes: page[offset + 7],
+        page_type: PageType::try_from(bytes[0]).map_err(|err| err.at_offset(header_offset))?,
+        first_freeblock_offset: u16::from_be_bytes([bytes[1], bytes[2]]),
+        cell_count: u16::from_be_bytes([bytes[3], bytes[4]]),
+        cell_content_area: u16::from_be_bytes([bytes[5], bytes[6]]),
+        number_of_fragmented_free_bytes: bytes[7],
         right_most_pointer: None,
     };
-    offset += 8;
+    let mut offset = header_offset + 8;
 
     if header.page_type == PageType::TableInteriorPage
         || header.page_type == PageType::IndexInteriorPage
     {
-        header.right_most_pointer = Some(u32::from_be_bytes([
-            page[offset],
-            page[offset + 1],
-            page[offset + 2],
-            page[offset + 3],
-        ]));
+        let bytes = bytes_at(page, offset, 4, "Right most pointer")?;
+        header.right_most_pointer = Some(read_u32(bytes, 0));
         offset += 4;
     }
 
+    let cell_pointers = bytes_at(
+        page,
+        offset,
+        header.cell_count as usize * 2,
+        "Cell pointer array",
+    )?;
     let mut cells = Vec::new();
 
-    for _ in 0..header.cell_count {
-        let cell_pointer = u16::from_be_bytes([page[offset], page[offset + 1]]);
-        offset += 2;
-
-        let cell = read_cell(&page, &header.page_type, cell_pointer as usize)?;
+    for cell_pointer in cell_pointers.chunks_exact(2) {
+        let cell_pointer = u16::from_be_bytes([cell_pointer[0], cell_pointer[1]]) as usize;
+        let cell = read_cell(
+            page,
+            &header.page_type,
+            cell_pointer,
+            db_header,
+            text_decoder,
+            read_overflow_page,
+        )
+        .map_err(|err| err.at_offset(cell_pointer))?;
 
         cells.push(cell);
     }
+    trace!(
+        target: RECORD,
+        "Page {}: decoded {:?} with {} cells",
+        page_index,
+        header.page_type,
+        cells.len()
+    );
 
-    Ok(BTreePage {
-        // TODO: Get rid of dummy values
-        header,
-        cells,
-    })
+    Ok(BTreePage { header, cells })
+}
+
+// The `len` bytes of `buffer` at `offset`, an error saying what got cut short when they are not
+// all there.
+pub fn bytes_at<'a>(buffer: &'a [u8], offset: usize, len: usize, what: &str) -> Result<&'a [u8]> {
+    buffer
+        .get(offset..offset.saturating_add(len))
+        .ok_or_else(|| {
+            DBError::Corrupt(
+                Location::offset(offset),
+                format!(
+                    "{} of {} bytes runs past the end at {}",
+                    what,
+                    len,
+                    buffer.len()
+                ),
+            )
+        })
 }
 
 #[derive(Debug, Clone)]
@@ -261,53 +511,214 @@ pub struct IndexInteriorCell {
 
 #[derive(Debug, Clone)]
 pub struct IndexLeafCell {
-    pub payload: Vec<u8>,
+    pub payload: Vec<Value>,
     pub first_overflow_page: Option<u32>,
 }
 
 // ChatGPT says you can use try_into rather than writing `try_get_page_type`. Interesting to konw.
 impl TryFrom<u8> for PageType {
-    // NOTE: Turns out if you want to use anyhow Result here you need to define the error type as
-    // anyhow::Error.
-    type Error = anyhow::Error;
+    type Error = DBError;
 
     fn try_from(page_type_value: u8) -> Result<PageType> {
-        println!("Trying page type for: {}", page_type_value);
         match page_type_value {
             2 => Ok(Self::IndexInteriorPage),
             5 => Ok(Self::TableInteriorPage),
             10 => Ok(Self::IndexLeafPage),
             13 => Ok(Self::TableLeafPage),
-            _ => Err(anyhow!("Invalid Page Type: {}", page_type_value)),
+            _ => Err(DBError::InvalidPageType(
+                Location::default(),
+                page_type_value,
+            )),
         }
     }
 }
 
-fn read_cell(page: &[u8], page_type: &PageType, offset: usize) -> Result<BTreeCell> {
-    // TODO: Still have to do overflow cell data.
+/*
+* Cell layout per page type, all multi-byte integers are big-endian:
+* 1. Table Interior: 4 byte left child page number, varint rowid.
+* 2. Table Leaf: varint payload size, varint rowid, payload, 4 byte overflow page (if any).
+* 3. Index Interior: 4 byte left child page number, varint payload size, payload, 4 byte overflow
+*    page (if any).
+* 4. Index Leaf: varint payload size, payload, 4 byte overflow page (if any).
+*/
+fn read_cell(
+    page: &[u8],
+    page_type: &PageType,
+    offset: usize,
+    db_header: &DBHeader,
+    text_decoder: &TextDecoder,
+    read_overflow_page: &mut dyn FnMut(u32) -> Result<Vec<u8>>,
+) -> Result<BTreeCell> {
+    let mut offset = offset;
     match page_type {
-        PageType::IndexInteriorPage => todo!(),
-        PageType::TableInteriorPage => todo!(),
-        PageType::IndexLeafPage => todo!(),
+        PageType::IndexInteriorPage => {
+            let left_child_page = read_u32(bytes_at(page, offset, 4, "Left child pointer")?, 0);
+            offset += 4;
+
+            let (payload_size, varint_size) = read_varint(page.get(offset..).unwrap_or_default())?;
+            offset += varint_size;
+
+            let (payload, first_overflow_page) = read_cell_payload(
+                page,
+                page_type,
+                offset,
+                payload_size as usize,
+                db_header,
+                read_overflow_page,
+            )?;
+            Ok(BTreeCell::IndexInteriorCell(IndexInteriorCell {
+                left_child_page,
+                payload: read_payload(&payload, text_decoder)?,
+                first_overflow_page,
+            }))
+        }
+        PageType::TableInteriorPage => {
+            let left_child_page = read_u32(bytes_at(page, offset, 4, "Left child pointer")?, 0);
+            offset += 4;
+
+            let (rowid, _) = read_varint(page.get(offset..).unwrap_or_default())?;
+            Ok(BTreeCell::TableInteriorCell(TableInteriorCell {
+                left_child_page,
+                rowid,
+            }))
+        }
+        PageType::IndexLeafPage => {
+            let (payload_size, varint_size) = read_varint(page.get(offset..).unwrap_or_default())?;
+            offset += varint_size;
+
+            let (payload, first_overflow_page) = read_cell_payload(
+                page,
+                page_type,
+                offset,
+                payload_size as usize,
+                db_header,
+                read_overflow_page,
+            )?;
+            Ok(BTreeCell::IndexLeafCell(IndexLeafCell {
+                payload: read_payload(&payload, text_decoder)?,
+                first_overflow_page,
+            }))
+        }
         PageType::TableLeafPage => {
-            let mut offset = offset;
-            let (payload_size, varint_size) = read_varint(&page[offset..])?;
+            let (payload_size, varint_size) = read_varint(page.get(offset..).unwrap_or_default())?;
             offset += varint_size;
 
-            let (row_id, varint_size) = read_varint(&page[offset..])?;
+            let (row_id, varint_size) = read_varint(page.get(offset..).unwrap_or_default())?;
             offset += varint_size;
 
-            let payload = &page[offset..offset + payload_size as usize];
+            let (payload, first_overflow_page) = read_cell_payload(
+                page,
+                page_type,
+                offset,
+                payload_size as usize,
+                db_header,
+                read_overflow_page,
+            )?;
             Ok(BTreeCell::TableLeafCell(TableLeafCell {
                 row_id,
-                payload: read_payload(&payload)?,
-                first_overflow_page: None,
+                payload: read_payload(&payload, text_decoder)?,
+                first_overflow_page,
             }))
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/*
+* A payload that does not fit on the b-tree page keeps its first `local_payload_size` bytes on the
+* page, followed by the 4 byte page number of the first overflow page. Every overflow page starts
+* with the 4 byte page number of the next overflow page (0 for the last one) and the rest of the
+* usable space holds the next chunk of the payload.
+*/
+fn read_cell_payload(
+    page: &[u8],
+    page_type: &PageType,
+    offset: usize,
+    payload_size: usize,
+    db_header: &DBHeader,
+    read_overflow_page: &mut dyn FnMut(u32) -> Result<Vec<u8>>,
+) -> Result<(Vec<u8>, Option<u32>)> {
+    if payload_size > MAX_PAYLOAD_SIZE {
+        return Err(DBError::corrupt(format!(
+            "Payload of {} bytes is larger than any SQLite writes",
+            payload_size
+        )));
+    }
+    let local_size = db_header.local_payload_size(page_type, payload_size);
+    let mut payload = bytes_at(page, offset, local_size, "Payload")?.to_vec();
+    if local_size == payload_size {
+        return Ok((payload, None));
+    }
+
+    let first_overflow_page = read_u32(
+        bytes_at(page, offset + local_size, 4, "Overflow page pointer")?,
+        0,
+    );
+    let overflow_content_size = db_header.usable_size() - 4;
+    let mut next_page = first_overflow_page;
+
+    while payload.len() < payload_size {
+        if next_page == 0 {
+            return Err(DBError::corrupt(format!(
+                "Overflow chain ended after {} of {} payload bytes",
+                payload.len(),
+                payload_size
+            )));
+        }
+
+        trace!(target: RECORD, "Page {}: reading overflow payload", next_page);
+        let overflow_page = read_overflow_page(next_page)?;
+        let chunk_size = overflow_content_size.min(payload_size - payload.len());
+        let chunk = bytes_at(&overflow_page, 4, chunk_size, "Overflow page")
+            .map_err(|err| err.on_page(next_page as usize))?;
+        payload.extend_from_slice(chunk);
+        next_page = read_u32(&overflow_page, 0);
+    }
+
+    Ok((payload, Some(first_overflow_page)))
+}
+
+// Number of bytes the cell starting at `offset` takes up on the page, the 4 byte overflow page
+// number included.
+pub fn cell_size(
+    page: &[u8],
+    page_type: &PageType,
+    offset: usize,
+    db_header: &DBHeader,
+) -> Result<usize> {
+    let mut size = 0;
+    if *page_type == PageType::TableInteriorPage || *page_type == PageType::IndexInteriorPage {
+        size += 4;
+    }
+    if *page_type == PageType::TableInteriorPage {
+        let (_, varint_size) = read_varint(page.get(offset + size..).unwrap_or_default())?;
+        return Ok(size + varint_size);
+    }
+
+    let (payload_size, varint_size) = read_varint(page.get(offset + size..).unwrap_or_default())?;
+    size += varint_size;
+    if *page_type == PageType::TableLeafPage {
+        let (_, varint_size) = read_varint(page.get(offset + size..).unwrap_or_default())?;
+        size += varint_size;
+    }
+
+    let local_size = db_header.local_payload_size(page_type, payload_size as usize);
+    size += local_size;
+    if local_size < payload_size as usize {
+        size += 4;
+    }
+    Ok(size)
+}
+
+pub fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Null,
     Integer(i64),
@@ -332,9 +743,25 @@ pub enum SerialType {
     String(usize),
 }
 
+impl SerialType {
+    // Number of bytes a value of this type takes up in the record body.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Null | Self::Int0 | Self::Int1 => 0,
+            Self::I8 => 1,
+            Self::I16 => 2,
+            Self::I24 => 3,
+            Self::I32 => 4,
+            Self::I48 => 6,
+            Self::I64 | Self::F64 => 8,
+            Self::Blob(size) | Self::String(size) => *size,
+        }
+    }
+}
+
 // Same thing try from is better suited than `try_get_serial_type`.
 impl TryFrom<u64> for SerialType {
-    type Error = anyhow::Error;
+    type Error = DBError;
 
     fn try_from(value: u64) -> Result<Self> {
         match value {
@@ -348,126 +775,654 @@ impl TryFrom<u64> for SerialType {
             7 => Ok(Self::F64),
             8 => Ok(Self::Int0),
             9 => Ok(Self::Int1),
-            n if value > 12 && value % 2 == 0 => Ok(Self::Blob(((n - 12) / 2) as usize)),
-            n if value > 13 && value % 2 == 1 => Ok(Self::String(((n - 13) / 2) as usize)),
-            _ => Err(anyhow!(DBError::InvalidSerialType(value))),
+            n if value >= 12 && value.is_multiple_of(2) => Ok(Self::Blob(((n - 12) / 2) as usize)),
+            n if value >= 13 && value % 2 == 1 => Ok(Self::String(((n - 13) / 2) as usize)),
+            _ => Err(DBError::InvalidSerialType(Location::default(), value)),
         }
     }
 }
 
-fn read_payload(payload: &[u8]) -> Result<Vec<Value>> {
-    let mut offset = 0;
-    let (header_size, varint_size) = read_varint(&payload[offset..])?;
-    offset += varint_size;
+fn read_payload(payload: &[u8], text_decoder: &TextDecoder) -> Result<Vec<Value>> {
+    let (header_size, mut offset) = read_varint(payload)?;
+    let header_size = header_size as usize;
+    if header_size < offset || header_size > payload.len() {
+        return Err(DBError::corrupt(format!(
+            "Record header of {} bytes does not fit a payload of {}",
+            header_size,
+            payload.len()
+        )));
+    }
 
-    let mut header_size = header_size as usize - varint_size;
     let mut serial_types = Vec::new();
-
-    while header_size > 0 {
-        let (serial_type, varint_size) = read_varint(&payload[offset..])?;
-        let serial_type = SerialType::try_from(serial_type)?;
+    while offset < header_size {
+        let (serial_type, varint_size) = read_varint(&payload[offset..header_size])?;
+        serial_types.push(SerialType::try_from(serial_type)?);
         offset += varint_size;
-        serial_types.push(serial_type);
-
-        header_size -= varint_size;
     }
 
     let mut values = Vec::new();
 
     for serial_type in serial_types {
-        let (value, size) = read_value(&payload[offset..], serial_type)?;
+        let size = serial_type.size();
+        let bytes = bytes_at(payload, offset, size, "Record value")
+            // The offset is into the payload, the one of the cell is more use.
+            .map_err(|_| {
+                DBError::corrupt(format!(
+                    "Record values run past the end of the {} byte payload",
+                    payload.len()
+                ))
+            })?;
+        values.push(read_value(bytes, serial_type, text_decoder)?);
         offset += size;
-        values.push(value);
     }
 
     Ok(values)
 }
 
-fn read_value(buffer: &[u8], serial_type: SerialType) -> Result<(Value, usize)> {
-    match serial_type {
-        SerialType::Null => Ok((Value::Null, 0)),
-        SerialType::I8 => Ok((Value::Integer(buffer[0] as i64), 1)),
-        SerialType::I16 => Ok((
-            Value::Integer(i16::from_be_bytes([buffer[0], buffer[1]]) as i64),
-            2,
-        )),
-        SerialType::I24 => Ok((
-            Value::Integer(i32::from_be_bytes([0, buffer[0], buffer[1], buffer[2]]) as i64),
-            3,
-        )),
-        SerialType::I32 => Ok((
-            Value::Integer(i32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as i64),
-            4,
-        )),
-        SerialType::I48 => Ok((
-            Value::Integer(i64::from_be_bytes([
-                0, 0, buffer[0], buffer[1], buffer[2], buffer[3], buffer[4], buffer[5],
-            ])),
-            6,
-        )),
-        SerialType::I64 => Ok((
-            Value::Integer(i64::from_be_bytes([
-                buffer[0], buffer[1], buffer[2], buffer[3], buffer[4], buffer[5], buffer[6],
-                buffer[7],
-            ])),
-            8,
-        )),
-        SerialType::F64 => Ok((
-            Value::Float(f64::from_be_bytes([
-                buffer[0], buffer[1], buffer[2], buffer[3], buffer[4], buffer[5], buffer[6],
-                buffer[7],
-            ])),
-            8,
-        )),
-        SerialType::Int0 => Ok((Value::Integer(0), 0)),
-        SerialType::Int1 => Ok((Value::Integer(1), 0)),
-        SerialType::Blob(n) => Ok((Value::Blob(buffer[0..n].to_vec()), n)),
-        SerialType::String(n) => {
-            let value = String::from_utf8(buffer[0..n].to_vec())?;
-            Ok((Value::Text(value), n))
+// Decodes a value from exactly the `serial_type.size()` bytes it takes up.
+fn read_value(bytes: &[u8], serial_type: SerialType, text_decoder: &TextDecoder) -> Result<Value> {
+    // The odd sized integers are read into the high bytes and shifted back down, so the sign gets
+    // extended.
+    let integer = |bytes: &[u8]| {
+        let mut buffer = [0u8; 8];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        i64::from_be_bytes(buffer) >> (64 - 8 * bytes.len())
+    };
+    Ok(match serial_type {
+        SerialType::Null => Value::Null,
+        SerialType::I8
+        | SerialType::I16
+        | SerialType::I24
+        | SerialType::I32
+        | SerialType::I48
+        | SerialType::I64 => Value::Integer(integer(bytes)),
+        SerialType::F64 => Value::Float(f64::from_bits(integer(bytes) as u64)),
+        SerialType::Int0 => Value::Integer(0),
+        SerialType::Int1 => Value::Integer(1),
+        SerialType::Blob(_) => Value::Blob(bytes.to_vec()),
+        SerialType::String(_) => Value::Text(text_decoder.decode(bytes)?),
+    })
+}
+
+/*
+* Inverse of `read_payload`. A record is a header of varints, the header size (itself included)
+* followed by a serial type per value, and then the value bodies back to back.
+*/
+pub fn encode_payload(values: &[Value], db_header: &DBHeader) -> Vec<u8> {
+    let serial_types: Vec<u64> = values
+        .iter()
+        .map(|value| serial_type_of(value, db_header))
+        .collect();
+
+    let types_size: usize = serial_types.iter().map(|&t| varint_size(t)).sum();
+    // The header size counts its own varint, which can push it over to one more byte. Like SQLite,
+    // add a byte when the size with the varint needs a longer varint than the size without it.
+    let mut header_size = types_size + varint_size(types_size as u64);
+    if varint_size(header_size as u64) > varint_size(types_size as u64) {
+        header_size += 1;
+    }
+
+    let mut payload = encode_varint(header_size as u64);
+    for &serial_type in serial_types.iter() {
+        payload.extend(encode_varint(serial_type));
+    }
+    for (value, &serial_type) in values.iter().zip(serial_types.iter()) {
+        encode_value(&mut payload, value, serial_type, db_header.text_encoding);
+    }
+
+    payload
+}
+
+// Smallest serial type that holds the value.
+fn serial_type_of(value: &Value, db_header: &DBHeader) -> u64 {
+    match value {
+        Value::Null => 0,
+        // Serial types 8 and 9 only exist from schema format 4 on.
+        Value::Integer(0) if db_header.schema_format >= 4 => 8,
+        Value::Integer(1) if db_header.schema_format >= 4 => 9,
+        Value::Integer(value) => match value {
+            -0x80..=0x7F => 1,
+            -0x8000..=0x7FFF => 2,
+            -0x80_0000..=0x7F_FFFF => 3,
+            -0x8000_0000..=0x7FFF_FFFF => 4,
+            -0x8000_0000_0000..=0x7FFF_FFFF_FFFF => 5,
+            _ => 6,
+        },
+        Value::Float(_) => 7,
+        Value::Blob(blob) => blob.len() as u64 * 2 + 12,
+        Value::Text(text) => db_header.text_encoding.encoded_len(text) as u64 * 2 + 13,
+    }
+}
+
+fn encode_value(
+    buffer: &mut Vec<u8>,
+    value: &Value,
+    serial_type: u64,
+    text_encoding: TextEncoding,
+) {
+    match value {
+        Value::Null => {}
+        Value::Integer(value) => {
+            let size = match serial_type {
+                1..=4 => serial_type as usize,
+                5 => 6,
+                6 => 8,
+                _ => 0,
+            };
+            buffer.extend_from_slice(&value.to_be_bytes()[8 - size..]);
         }
+        Value::Float(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+        Value::Blob(blob) => buffer.extend_from_slice(blob),
+        Value::Text(text) => buffer.extend(text_encoding.encode(text)),
     }
 }
 
+// SQLite's SQLITE_MAX_LENGTH, no record it writes is larger. A bigger size can only come from
+// corrupt bytes, and trusting it would have us allocate that much.
+const MAX_PAYLOAD_SIZE: usize = 1_000_000_000;
+
+// Largest number of bytes a varint can take.
+pub const MAX_VARINT_SIZE: usize = 9;
+
+/*
+* SQLite varints are 1 to 9 bytes long, big-endian. The first 8 bytes carry 7 bits each with the
+* high bit set when another byte follows. A 9th byte, if there is one, carries all 8 of its bits so
+* the whole u64 range fits.
+*/
 pub fn read_varint(buffer: &[u8]) -> Result<(u64, usize)> {
-    let mut size = 0;
     let mut decoded_varint = 0;
-    let mut offset = 0;
 
-    while size < 8 && buffer[offset] >= 0x80 {
-        decoded_varint |= u64::from(buffer[offset]) & 0x7F;
-        decoded_varint <<= 7;
-        offset += 1;
-        size += 1;
+    for (offset, &byte) in buffer.iter().take(MAX_VARINT_SIZE).enumerate() {
+        if offset == MAX_VARINT_SIZE - 1 {
+            decoded_varint = (decoded_varint << 8) | u64::from(byte);
+            return Ok((decoded_varint, MAX_VARINT_SIZE));
+        }
+
+        decoded_varint = (decoded_varint << 7) | (u64::from(byte) & 0x7F);
+        if byte < 0x80 {
+            return Ok((decoded_varint, offset + 1));
+        }
     }
 
-    decoded_varint |= u64::from(buffer[offset]) & 0x7F;
-    size += 1;
-    Ok((decoded_varint, size))
+    // The buffer ended before the last byte of the varint.
+    Err(DBError::InvalidVarint(Location::default()))
 }
 
-// TODO: Read documentation of other dbs for encoding and make a more generic function.
-// Implement this as well
-pub fn encode_varint(_value: u64) -> Vec<u8> {
-    vec![0u8; 1]
+// Number of bytes `encode_varint` uses for the value.
+pub fn varint_size(value: u64) -> usize {
+    if value > 0x00FF_FFFF_FFFF_FFFF {
+        return MAX_VARINT_SIZE;
+    }
+
+    let significant_bits = 64 - value.leading_zeros() as usize;
+    significant_bits.div_ceil(7).max(1)
+}
+
+pub fn encode_varint(value: u64) -> Vec<u8> {
+    let size = varint_size(value);
+    let mut encoded = vec![0u8; size];
+    let mut value = value;
+
+    if size == MAX_VARINT_SIZE {
+        // The last byte takes the low 8 bits, the 56 bits left go into the 7 bit groups.
+        encoded[MAX_VARINT_SIZE - 1] = value as u8;
+        value >>= 8;
+        for byte in encoded[..MAX_VARINT_SIZE - 1].iter_mut().rev() {
+            *byte = (value as u8 & 0x7F) | 0x80;
+            value >>= 7;
+        }
+        return encoded;
+    }
+
+    for (i, byte) in encoded.iter_mut().rev().enumerate() {
+        *byte = value as u8 & 0x7F;
+        if i > 0 {
+            *byte |= 0x80;
+        }
+        value >>= 7;
+    }
+    encoded
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::page::file_structures::read_varint;
+    use crate::page::cursor::BTreeCursor;
+    use crate::page::errors::{DBError, Location};
+    use crate::page::file_structures::{
+        encode_payload, encode_varint, parse_db_header, read_page, read_payload, read_varint,
+        varint_size, write_db_header, BTreeCell, PageType, TextDecoder, TextEncoding, Value,
+        MAX_VARINT_SIZE,
+    };
+    use crate::page::pager::Pager;
+    use crate::page::test_utils::temp_copy;
+    use crate::page::Database;
+
+    const MULTI_PAGE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/multi_page.db");
+    const OVERFLOW_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/overflow.db");
+    // Made by SQLite with `PRAGMA encoding`: 30 rows in `items`, the last one on overflow pages.
+    const UTF16LE_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/utf16le.db");
+    const UTF16BE_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/utf16be.db");
+
+    // Walks the whole B-tree rooted at `page_index` and returns the cells of every leaf page in
+    // order.
+    fn collect_leaf_cells(pager: &mut Pager, page_index: usize) -> Vec<BTreeCell> {
+        let page = pager.read_page(page_index).unwrap();
+        let mut cells = Vec::new();
+        for cell in page.cells.iter() {
+            match cell {
+                BTreeCell::TableInteriorCell(cell) => {
+                    cells.extend(collect_leaf_cells(pager, cell.left_child_page as usize))
+                }
+                BTreeCell::IndexInteriorCell(cell) => {
+                    cells.extend(collect_leaf_cells(pager, cell.left_child_page as usize));
+                    cells.push(BTreeCell::IndexInteriorCell(cell.clone()));
+                }
+                leaf => cells.push(leaf.clone()),
+            }
+        }
+        if let Some(right_most_pointer) = page.header.right_most_pointer {
+            cells.extend(collect_leaf_cells(pager, right_most_pointer as usize));
+        }
+        cells
+    }
+
+    fn root_page_of(pager: &mut Pager, name: &str) -> usize {
+        let master = pager.read_page(1).unwrap();
+        for cell in master.cells.iter() {
+            if let BTreeCell::TableLeafCell(cell) = cell {
+                if matches!(&cell.payload[1], Value::Text(text) if text == name) {
+                    if let Value::Integer(root_page) = cell.payload[3] {
+                        return root_page as usize;
+                    }
+                }
+            }
+        }
+        panic!("{} not found in sqlite_master", name);
+    }
+
+    #[test]
+    fn read_table_interior_cells_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let root_page = root_page_of(&mut database.pager, "worms");
+        let root = database.pager.read_page(root_page).unwrap();
+        assert_eq!(root.header.page_type, PageType::TableInteriorPage);
+
+        let cells = collect_leaf_cells(&mut database.pager, root_page);
+        assert_eq!(cells.len(), 300);
+        for (i, cell) in cells.iter().enumerate() {
+            match cell {
+                BTreeCell::TableLeafCell(cell) => {
+                    assert_eq!(cell.row_id, i as u64 + 1);
+                    assert!(
+                        matches!(&cell.payload[1], Value::Text(name) if *name == format!("worm-{:04}", i + 1))
+                    );
+                }
+                _ => panic!("Expected a table leaf cell, got: {:?}", cell),
+            }
+        }
+    }
+
+    #[test]
+    fn read_index_cells_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let root_page = root_page_of(&mut database.pager, "worms_name");
+        let root = database.pager.read_page(root_page).unwrap();
+        assert_eq!(root.header.page_type, PageType::IndexInteriorPage);
+
+        let cells = collect_leaf_cells(&mut database.pager, root_page);
+        assert_eq!(cells.len(), 300);
+        for (i, cell) in cells.iter().enumerate() {
+            let payload = match cell {
+                BTreeCell::IndexLeafCell(cell) => &cell.payload,
+                BTreeCell::IndexInteriorCell(cell) => &cell.payload,
+                _ => panic!("Expected an index cell, got: {:?}", cell),
+            };
+            // Index records are the indexed columns followed by the rowid.
+            assert!(
+                matches!(&payload[0], Value::Text(name) if *name == format!("worm-{:04}", i + 1))
+            );
+            assert!(matches!(payload[1], Value::Integer(rowid) if rowid == i as i64 + 1));
+        }
+    }
+
+    #[test]
+    fn corrupt_page_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let root_page = root_page_of(&mut database.pager, "worms");
+        let leaf_page = match &database.pager.read_page(root_page).unwrap().cells[0] {
+            BTreeCell::TableInteriorCell(cell) => cell.left_child_page as usize,
+            cell => panic!("Expected a table interior cell, got: {:?}", cell),
+        };
+        let db_header = database.pager.db_header.clone();
+        let page_size = db_header.page_size as usize;
+        let file = std::fs::read(MULTI_PAGE_DB).unwrap();
+        let page = file[(leaf_page - 1) * page_size..leaf_page * page_size].to_vec();
+        let decoder = TextDecoder {
+            encoding: TextEncoding::UTF8,
+            lossy: false,
+        };
+        let parse = |page: &[u8]| {
+            read_page(page, leaf_page, &db_header, &decoder, &mut |_| {
+                panic!("No overflow pages expected")
+            })
+        };
+        assert!(parse(&page).is_ok());
+
+        let mut bad_type = page.clone();
+        bad_type[0] = 7;
+        let err = parse(&bad_type).unwrap_err();
+        assert!(matches!(err, DBError::InvalidPageType(_, 7)));
+        assert_eq!(err.location().unwrap().page, Some(leaf_page));
+
+        // Rows and rowids are small, so the record header starts two bytes into the cell and the
+        // first serial type follows its one byte size.
+        let cell_offset = u16::from_be_bytes([page[8], page[9]]) as usize;
+        let mut bad_serial_type = page.clone();
+        bad_serial_type[cell_offset + 3] = 10;
+        let err = parse(&bad_serial_type).unwrap_err();
+        assert!(matches!(err, DBError::InvalidSerialType(_, 10)));
+        assert_eq!(
+            err.location(),
+            Some(Location {
+                page: Some(leaf_page),
+                offset: Some(cell_offset)
+            })
+        );
+
+        let mut bad_pointer = page.clone();
+        bad_pointer[8..10].copy_from_slice(&(page_size as u16 - 1).to_be_bytes());
+        let err = parse(&bad_pointer).unwrap_err();
+        assert_eq!(err.location().unwrap().page, Some(leaf_page));
+
+        let mut bad_cell_count = page.clone();
+        bad_cell_count[3..5].copy_from_slice(&u16::MAX.to_be_bytes());
+        assert!(matches!(
+            parse(&bad_cell_count).unwrap_err(),
+            DBError::Corrupt(..)
+        ));
+        assert!(parse(&page[..20]).is_err());
+    }
+
+    #[test]
+    fn page_size_test() {
+        let file = std::fs::read(MULTI_PAGE_DB).unwrap();
+        let mut buffer = file[..100].to_vec();
+        // 65536 is stored as 1.
+        buffer[16..18].copy_from_slice(&1u16.to_be_bytes());
+        let header = parse_db_header(&buffer).unwrap();
+        assert_eq!(header.page_size, 65536);
+        assert_eq!(header.usable_size(), 65536);
+        let mut written = vec![0u8; 100];
+        write_db_header(&header, &mut written);
+        assert_eq!(written, buffer);
+
+        for page_size in [0u16, 256, 1000] {
+            buffer[16..18].copy_from_slice(&page_size.to_be_bytes());
+            assert!(matches!(
+                parse_db_header(&buffer),
+                Err(DBError::InvalidFileHeader(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn local_payload_size_test() {
+        let database = Database::open(OVERFLOW_DB.to_string()).unwrap();
+        let header = &database.pager.db_header;
+        // 512 byte pages with 8 reserved bytes.
+        assert_eq!(header.usable_size(), 504);
+        assert_eq!(header.max_local(&PageType::TableLeafPage), 469);
+        assert_eq!(header.max_local(&PageType::IndexLeafPage), 100);
+        assert_eq!(header.min_local(&PageType::TableLeafPage), 38);
+        assert_eq!(
+            header.local_payload_size(&PageType::TableLeafPage, 469),
+            469
+        );
+        // 38 + (1000 - 38) % 500 = 500 does not fit, so only the minimum stays local.
+        assert_eq!(
+            header.local_payload_size(&PageType::TableLeafPage, 1000),
+            38
+        );
+        // 38 + (600 - 38) % 500 = 100 fits.
+        assert_eq!(
+            header.local_payload_size(&PageType::TableLeafPage, 600),
+            100
+        );
+    }
+
+    #[test]
+    fn read_overflow_payload_test() {
+        let mut database = Database::open(OVERFLOW_DB.to_string()).unwrap();
+        let root_page = root_page_of(&mut database.pager, "docs");
+        let cells = collect_leaf_cells(&mut database.pager, root_page);
+        assert_eq!(cells.len(), 12);
+        for (i, cell) in cells.iter().enumerate() {
+            let id = i + 1;
+            let cell = match cell {
+                BTreeCell::TableLeafCell(cell) => cell,
+                _ => panic!("Expected a table leaf cell, got: {:?}", cell),
+            };
+            assert!(cell.first_overflow_page.is_some());
+            assert!(
+                matches!(&cell.payload[2], Value::Text(body) if *body == format!("{:0>1$}", id, id * 250))
+            );
+            assert!(
+                matches!(&cell.payload[3], Value::Blob(data) if *data == vec![b'A' + id as u8; id * 300])
+            );
+        }
+
+        let root_page = root_page_of(&mut database.pager, "docs_body");
+        let cells = collect_leaf_cells(&mut database.pager, root_page);
+        assert_eq!(cells.len(), 12);
+        for cell in cells.iter() {
+            let (payload, first_overflow_page) = match cell {
+                BTreeCell::IndexLeafCell(cell) => (&cell.payload, cell.first_overflow_page),
+                BTreeCell::IndexInteriorCell(cell) => (&cell.payload, cell.first_overflow_page),
+                _ => panic!("Expected an index cell, got: {:?}", cell),
+            };
+            let id = match payload[1] {
+                Value::Integer(rowid) => rowid as usize,
+                _ => panic!("Expected a rowid, got: {:?}", payload[1]),
+            };
+            assert!(first_overflow_page.is_some());
+            assert!(
+                matches!(&payload[0], Value::Text(body) if *body == format!("{:0>1$}", id, id * 250))
+            );
+        }
+    }
 
     #[test]
     fn decode_varint_test() {
         let result = read_varint(&[0x82, 0x2C]);
         match result {
-            Ok(value) => assert_eq!(value, (300 as u64, 2)),
+            Ok(value) => assert_eq!(value, (300_u64, 2)),
             Err(..) => unreachable!(),
         }
 
         let result = read_varint(&[0x81, 0x07]);
         match result {
-            Ok(value) => assert_eq!(value, (135 as u64, 2)),
+            Ok(value) => assert_eq!(value, (135_u64, 2)),
             Err(..) => unreachable!(),
         }
     }
+
+    #[test]
+    fn decode_varint_bounds_test() {
+        assert!(read_varint(&[]).is_err());
+        assert!(read_varint(&[0x81]).is_err());
+        assert!(read_varint(&[0xFF; 8]).is_err());
+        // Only 9 bytes are ever read, the 9th one in full.
+        assert_eq!(read_varint(&[0xFF; 12]).unwrap(), (u64::MAX, 9));
+        assert_eq!(
+            read_varint(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0xFF]).unwrap(),
+            (0xFF, 9)
+        );
+    }
+
+    #[test]
+    fn encode_varint_test() {
+        assert_eq!(encode_varint(0), vec![0x00]);
+        assert_eq!(encode_varint(0x7F), vec![0x7F]);
+        assert_eq!(encode_varint(300), vec![0x82, 0x2C]);
+        assert_eq!(encode_varint(135), vec![0x81, 0x07]);
+        assert_eq!(encode_varint(u64::MAX), vec![0xFF; 9]);
+    }
+
+    #[test]
+    fn varint_round_trip_test() {
+        // Values on either side of every size boundary: 7 bits per byte for the first 8 bytes,
+        // and the full 64 bits with the 9th.
+        let mut values = vec![0, 1, u64::MAX - 1, u64::MAX, 1 << 63, i64::MAX as u64];
+        for size in 1..MAX_VARINT_SIZE as u32 {
+            let boundary = 1u64 << (7 * size);
+            values.extend([boundary - 2, boundary - 1, boundary, boundary + 1]);
+        }
+
+        // And a deterministic spread of values of every bit length in between.
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        for _ in 0..10_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            values.push(state >> (state % 64));
+        }
+
+        for value in values {
+            let encoded = encode_varint(value);
+            let expected_size = match 64 - value.leading_zeros() {
+                0..=7 => 1,
+                bits @ 8..=56 => bits.div_ceil(7) as usize,
+                _ => 9,
+            };
+            assert_eq!(encoded.len(), expected_size, "size of {}", value);
+            assert_eq!(varint_size(value), expected_size, "size of {}", value);
+
+            // Trailing bytes must not be read.
+            let mut buffer = encoded.clone();
+            buffer.extend_from_slice(&[0xFF, 0xFF]);
+            assert_eq!(read_varint(&buffer).unwrap(), (value, encoded.len()));
+            assert!(read_varint(&encoded[..encoded.len() - 1]).is_err());
+        }
+    }
+
+    const UTF8: TextDecoder = TextDecoder {
+        encoding: TextEncoding::UTF8,
+        lossy: false,
+    };
+
+    #[test]
+    fn payload_round_trip_test() {
+        let database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let mut values = vec![
+            Value::Null,
+            Value::Float(-1.5),
+            Value::Text(String::new()),
+            Value::Blob(Vec::new()),
+            Value::Text("sand worm".to_string()),
+            Value::Blob(vec![0, 1, 2, 255]),
+        ];
+        // Integers right at the edges of every serial type, negative ones included.
+        for bits in [8, 16, 24, 32, 48, 64] {
+            let max = (1i128 << (bits - 1)) - 1;
+            for value in [max, max + 1, -max - 1, -max - 2] {
+                if let Ok(value) = i64::try_from(value) {
+                    values.push(Value::Integer(value));
+                }
+            }
+        }
+        values.extend([Value::Integer(0), Value::Integer(1)]);
+
+        let payload = encode_payload(&values, &database.pager.db_header);
+        let decoded = read_payload(&payload, &UTF8).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", values));
+
+        // Enough columns for the header size itself to need a 2 byte varint.
+        let values = vec![Value::Integer(-5); 200];
+        let payload = encode_payload(&values, &database.pager.db_header);
+        assert_eq!(read_varint(&payload).unwrap(), (201 + 1, 2));
+        let decoded = read_payload(&payload, &UTF8).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", values));
+
+        // Around where the header size goes from a 2 to a 3 byte varint: 16382 bytes of serial types
+        // and a 2 byte varint make 16384, which itself takes 3 bytes.
+        for (columns, header_size) in [(16381, 16383), (16382, 16385), (16383, 16386)] {
+            let values = vec![Value::Null; columns];
+            let payload = encode_payload(&values, &database.pager.db_header);
+            let varint_size = header_size - columns;
+            assert_eq!(
+                read_varint(&payload).unwrap(),
+                (header_size as u64, varint_size)
+            );
+            assert_eq!(payload.len(), header_size);
+            assert_eq!(read_payload(&payload, &UTF8).unwrap().len(), columns);
+        }
+    }
+
+    #[test]
+    fn utf16_database_test() {
+        for fixture in [UTF16LE_DB, UTF16BE_DB] {
+            let path = temp_copy(fixture);
+            let mut database = Database::open(path.clone()).unwrap();
+            // The schema is UTF-16 as well.
+            let root_page = database.get_table("items").unwrap().root_page;
+            let values = vec![
+                Value::Null,
+                Value::Text("new ïtem 😀".to_string()),
+                Value::Null,
+                Value::Null,
+            ];
+            database.insert("items", values).unwrap();
+
+            let mut database = Database::open(path).unwrap();
+            let mut names = Vec::new();
+            let mut cursor = BTreeCursor::new(root_page);
+            let mut more = cursor.first(&mut database.pager).unwrap();
+            while more {
+                match &cursor.cell().unwrap().payload[1] {
+                    Value::Text(name) => names.push(name.clone()),
+                    value => panic!("Expected a name, got: {:?}", value),
+                }
+                more = cursor.next(&mut database.pager).unwrap();
+            }
+            assert_eq!(names.len(), 31);
+            assert_eq!(names[0], "ïtem-1 😀");
+            assert_eq!(names[29], "ë".repeat(600));
+            assert_eq!(names[30], "new ïtem 😀");
+        }
+    }
+
+    #[test]
+    fn invalid_text_test() {
+        // Invalid UTF-8, a lone surrogate, and a byte left over.
+        let cases = [
+            (TextEncoding::UTF8, vec![b'a', 0xff, b'b'], "a\u{fffd}b"),
+            (
+                TextEncoding::UTF16le,
+                vec![b'a', 0, 0x00, 0xd8],
+                "a\u{fffd}",
+            ),
+            (TextEncoding::UTF16be, vec![0, b'a', 0, b'b', 0], "ab"),
+        ];
+        for (encoding, bytes, lossy_text) in cases {
+            let strict = TextDecoder {
+                encoding,
+                lossy: false,
+            };
+            assert!(strict.decode(&bytes).is_err());
+            let lossy = TextDecoder {
+                encoding,
+                lossy: true,
+            };
+            assert_eq!(lossy.decode(&bytes).unwrap(), lossy_text);
+        }
+
+        let valid = TextEncoding::UTF16be.encode("sand 😀");
+        assert_eq!(valid.len(), TextEncoding::UTF16be.encoded_len("sand 😀"));
+        let strict = TextDecoder {
+            encoding: TextEncoding::UTF16be,
+            lossy: false,
+        };
+        assert_eq!(strict.decode(&valid).unwrap(), "sand 😀");
+    }
 }
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+
+use super::errors::{DBError, Location, Result};
+use super::file_structures::read_u32;
+use super::pager::Pager;
+
+/*
+* Pages that are no longer in use go on the freelist, so later writes can use them again.
+*
+* The header points at the first freelist trunk page. A trunk page holds the page number of the
+* next trunk page (0 for the last one), the number of leaf page numbers on it, and then the leaf
+* page numbers themselves. Leaf pages hold nothing useful. The header also keeps the total number
+* of free pages, trunk pages included.
+*/
+
+// A trunk page could hold (usable size / 4 - 2) leaves, but SQLite versions before 3.6.0 got that
+// number wrong and read past the end of the page. SQLite never fills a trunk past this point to
+// keep those versions happy, so neither do we.
+fn max_trunk_leaves(pager: &Pager) -> usize {
+    pager.db_header.usable_size() / 4 - 8
+}
+
+// Every page on the freelist, trunk pages included, in the order they are stored.
+pub fn free_pages(pager: &mut Pager) -> Result<Vec<usize>> {
+    let mut pages = Vec::new();
+    let mut seen = HashSet::new();
+    let mut trunk_index = pager.db_header.freelist_trunk_page as usize;
+    while trunk_index != 0 {
+        // A page showing up twice means the list loops, walking it would never end.
+        if !seen.insert(trunk_index) {
+            return Err(DBError::Corrupt(
+                Location::page(trunk_index),
+                "Freelist trunk page is part of a loop".to_string(),
+            ));
+        }
+        let trunk = read_trunk(pager, trunk_index)?;
+        pages.push(trunk_index);
+        for leaf in 0..read_u32(&trunk, 4) as usize {
+            let leaf_index = read_u32(&trunk, 8 + 4 * leaf) as usize;
+            check_page_index(pager, leaf_index)?;
+            pages.push(leaf_index);
+        }
+        trunk_index = read_u32(&trunk, 0) as usize;
+    }
+
+    if pages.len() != pager.db_header.freelist_pages as usize {
+        return Err(DBError::InvalidFileHeader(format!(
+            "Header says there are {} free pages, the freelist has {}",
+            pager.db_header.freelist_pages,
+            pages.len()
+        )));
+    }
+    Ok(pages)
+}
+
+/*
+* Takes a page off the freelist, None when the list is empty. The page comes back zeroed.
+*
+* The last leaf of the first trunk page goes first. A trunk page without leaves is handed out
+* itself, and the next trunk page takes its place in the header.
+*/
+pub fn allocate_page(pager: &mut Pager) -> Result<Option<usize>> {
+    let trunk_index = pager.db_header.freelist_trunk_page as usize;
+    if trunk_index == 0 {
+        return Ok(None);
+    }
+
+    let mut trunk = read_trunk(pager, trunk_index)?;
+    let leaf_count = read_u32(&trunk, 4) as usize;
+    let page_index = if leaf_count == 0 {
+        pager.db_header.freelist_trunk_page = read_u32(&trunk, 0);
+        trunk_index
+    } else {
+        let offset = 8 + 4 * (leaf_count - 1);
+        let leaf_index = read_u32(&trunk, offset) as usize;
+        check_page_index(pager, leaf_index)?;
+        trunk[offset..offset + 4].fill(0);
+        trunk[4..8].copy_from_slice(&(leaf_count as u32 - 1).to_be_bytes());
+        pager.write_page(trunk_index, trunk);
+        leaf_index
+    };
+
+    pager.db_header.freelist_pages = pager.db_header.freelist_pages.saturating_sub(1);
+    let page_size = pager.db_header.page_size as usize;
+    pager.write_page(page_index, vec![0; page_size]);
+    Ok(Some(page_index))
+}
+
+// Puts a page on the freelist. It becomes a leaf of the first trunk page if that has room,
+// otherwise it becomes the new first trunk page.
+pub fn release_page(pager: &mut Pager, page_index: usize) -> Result<()> {
+    let trunk_index = pager.db_header.freelist_trunk_page as usize;
+    if trunk_index != 0 {
+        let mut trunk = pager.read_raw_page(trunk_index)?;
+        let leaf_count = read_u32(&trunk, 4) as usize;
+        if leaf_count < max_trunk_leaves(pager) {
+            let offset = 8 + 4 * leaf_count;
+            trunk[offset..offset + 4].copy_from_slice(&(page_index as u32).to_be_bytes());
+            trunk[4..8].copy_from_slice(&(leaf_count as u32 + 1).to_be_bytes());
+            pager.write_page(trunk_index, trunk);
+            pager.db_header.freelist_pages += 1;
+            return Ok(());
+        }
+    }
+
+    let mut trunk = vec![0u8; pager.db_header.page_size as usize];
+    trunk[0..4].copy_from_slice(&(trunk_index as u32).to_be_bytes());
+    pager.write_page(page_index, trunk);
+    pager.db_header.freelist_trunk_page = page_index as u32;
+    pager.db_header.freelist_pages += 1;
+    Ok(())
+}
+
+fn read_trunk(pager: &mut Pager, trunk_index: usize) -> Result<Vec<u8>> {
+    check_page_index(pager, trunk_index)?;
+    let trunk = pager.read_raw_page(trunk_index)?;
+    let leaf_count = read_u32(&trunk, 4) as usize;
+    if leaf_count > pager.db_header.usable_size() / 4 - 2 {
+        return Err(DBError::Corrupt(
+            Location::page(trunk_index),
+            format!("Freelist trunk page claims {} leaves", leaf_count),
+        ));
+    }
+    Ok(trunk)
+}
+
+fn check_page_index(pager: &Pager, page_index: usize) -> Result<()> {
+    if page_index < 2 || page_index > pager.page_count() {
+        return Err(DBError::corrupt(format!(
+            "Freelist points at page {}, the database has {} pages",
+            page_index,
+            pager.page_count()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::page::file_structures::{read_u32, Value};
+    use crate::page::freelist::{allocate_page, free_pages, release_page};
+    use crate::page::test_utils::temp_copy;
+    use crate::page::Database;
+
+    // Made by SQLite: 600 rows in `items`, then every row with an id not divisible by 3 deleted.
+    const FREELIST_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/freelist.db");
+
+    #[test]
+    fn free_pages_test() {
+        let mut database = Database::open(FREELIST_DB.to_string()).unwrap();
+        let pages = free_pages(&mut database.pager).unwrap();
+        assert_eq!(pages.len(), 66);
+        // The trunk page comes first.
+        assert_eq!(
+            pages[0],
+            database.pager.db_header.freelist_trunk_page as usize
+        );
+        assert_eq!(pages.iter().collect::<HashSet<_>>().len(), pages.len());
+    }
+
+    #[test]
+    fn allocate_and_release_test() {
+        let path = temp_copy(FREELIST_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        let page_count = database.pager.page_count();
+        let mut pages = free_pages(&mut database.pager).unwrap();
+
+        // Free pages are handed out before the file grows, the trunk page last.
+        let mut allocated = Vec::new();
+        while let Some(page_index) = allocate_page(&mut database.pager).unwrap() {
+            assert!(database
+                .pager
+                .read_raw_page(page_index)
+                .unwrap()
+                .iter()
+                .all(|&b| b == 0));
+            allocated.push(page_index);
+        }
+        assert_eq!(allocated.last(), pages.first());
+        allocated.sort();
+        pages.sort();
+        assert_eq!(allocated, pages);
+        assert_eq!(database.pager.db_header.freelist_pages, 0);
+        assert_eq!(database.pager.db_header.freelist_trunk_page, 0);
+        assert_eq!(database.pager.allocate_page().unwrap(), page_count + 1);
+        database.pager.rollback().unwrap();
+
+        // Releasing more pages than one trunk page holds starts another trunk page.
+        let released: Vec<usize> = (0..150)
+            .map(|_| database.pager.allocate_page().unwrap())
+            .collect();
+        for &page_index in released.iter() {
+            release_page(&mut database.pager, page_index).unwrap();
+        }
+        assert_eq!(free_pages(&mut database.pager).unwrap().len(), 150);
+        let trunk_index = database.pager.db_header.freelist_trunk_page as usize;
+        let trunk = database.pager.read_raw_page(trunk_index).unwrap();
+        assert_ne!(read_u32(&trunk, 0), 0);
+
+        // Inserting rows reuses the free pages instead of growing the file.
+        database.pager.rollback().unwrap();
+        assert_eq!(database.pager.page_count(), page_count);
+        for rowid in 1000..1100 {
+            let values = vec![
+                Value::Integer(rowid),
+                Value::Text(format!("item-{}", rowid)),
+                Value::Blob(vec![1; 100]),
+                Value::Float(0.0),
+            ];
+            database.insert("items", values).unwrap();
+        }
+        assert_eq!(database.pager.page_count(), page_count);
+        let free_page_count = database.pager.db_header.freelist_pages as usize;
+        assert!(free_page_count < pages.len());
+
+        let mut database = Database::open(path).unwrap();
+        assert_eq!(
+            free_pages(&mut database.pager).unwrap().len(),
+            free_page_count
+        );
+    }
+}
@@ -0,0 +1,446 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::info;
+
+use super::errors::{DBError, Result};
+use super::file_structures::read_u32;
+use super::logging::PAGER;
+use super::vfs::{LockLevel, OpenMode, Vfs, VfsFile};
+
+/*
+* The rollback journal, `<db>-journal`, makes a commit all or nothing.
+*
+* Before a page of the database file is overwritten its original content goes into the journal,
+* and the journal is synced to disk before the database file is touched. Once every page has been
+* written and synced the journal is deleted, that is the moment the commit happens. A journal that
+* is still around when the database is opened is a hot journal: the commit never finished, and
+* writing the original pages back undoes whatever part of it made it to the database file.
+*
+* The layout is the one SQLite uses, so SQLite can roll back our journals and we can roll back
+* theirs. The journal starts with a header padded to a sector:
+*   - 8 bytes of magic
+*   - number of page records that follow, 0xFFFFFFFF meaning as many as the file holds and 0 that
+*     the records were never synced
+*   - nonce for the record checksums
+*   - size of the database in pages before the commit
+*   - sector size
+*   - page size
+* followed by records of a 4 byte page number, the page, and a 4 byte checksum. SQLite may start
+* another header at the next sector boundary after the records, each with records of its own.
+*/
+
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+const JOURNAL_HEADER_SIZE: usize = 28;
+const SECTOR_SIZE: usize = 512;
+
+pub fn journal_path(db_path: &str) -> String {
+    format!("{}-journal", db_path)
+}
+
+/*
+* Writes the original content of the pages about to be overwritten to the journal and syncs it.
+* Only pages that exist in the file need to be in there, pages past `original_page_count` go away
+* when the file is truncated back to its original size.
+*
+* The header goes out saying there are no records. Only once the records are synced does it get
+* their number, and is synced again, the same as SQLite does on file systems that may append the
+* size of a file before its contents. A journal that lost power in between has records full of
+* garbage as far as we know, but the database file was not touched yet so none are needed.
+*/
+pub fn write_journal(
+    vfs: &dyn Vfs,
+    path: &str,
+    page_size: usize,
+    original_page_count: usize,
+    original_pages: &[(usize, Vec<u8>)],
+) -> Result<()> {
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or(0)
+        ^ std::process::id();
+
+    let mut journal = vec![0u8; SECTOR_SIZE];
+    journal[0..8].copy_from_slice(&JOURNAL_MAGIC);
+    journal[12..16].copy_from_slice(&nonce.to_be_bytes());
+    journal[16..20].copy_from_slice(&(original_page_count as u32).to_be_bytes());
+    journal[20..24].copy_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+    journal[24..28].copy_from_slice(&(page_size as u32).to_be_bytes());
+    for (page_index, page) in original_pages {
+        journal.extend((*page_index as u32).to_be_bytes());
+        journal.extend_from_slice(page);
+        journal.extend(checksum(nonce, page).to_be_bytes());
+    }
+
+    let mut file = vfs.open(path, OpenMode::Create)?;
+    file.truncate(0)?;
+    file.write_at(0, &journal)?;
+    file.sync()?;
+    file.write_at(8, &(original_pages.len() as u32).to_be_bytes())?;
+    file.sync()?;
+    // Without this the journal could be gone after losing power while the pages it has the
+    // originals of are not.
+    vfs.sync_directory(path)
+}
+
+// Deleting the journal is what commits the transaction, the commit is durable once the directory
+// is synced.
+pub fn delete_journal(vfs: &dyn Vfs, path: &str) -> Result<()> {
+    vfs.delete(path)?;
+    vfs.sync_directory(path)
+}
+
+/*
+* Rolls back an unfinished commit if the database has a hot journal. Every page in the journal is
+* written back, the file is cut back to its original size, and the journal is deleted. Returns
+* whether there was anything to roll back.
+*
+* A journal is only hot when nobody is writing. A connection writes with a RESERVED lock or more
+* from before it creates the journal until after it deletes it, so like SQLite we look for that
+* lock with our SHARED lock in hand, which the caller has to hold, and only then go up to EXCLUSIVE
+* to play the journal back. The lock is back at SHARED afterwards. A connection that can not write
+* to the database has no business rolling it back, it gets a read only error instead, the same as
+* SQLite's SQLITE_READONLY_ROLLBACK.
+*
+* A record with a bad checksum ends the play back. It was being written when the crash happened,
+* so the database file was not touched yet and neither it nor anything after it is needed.
+*/
+pub fn recover(vfs: &dyn Vfs, db_file: &mut dyn VfsFile, path: &str) -> Result<bool> {
+    if db_file.check_reserved_lock()? || !is_hot(vfs, path)? {
+        return Ok(false);
+    }
+    if db_file.is_read_only() {
+        return Err(DBError::ReadOnly(format!(
+            "Hot journal {} needs write access to the database to roll it back",
+            path
+        )));
+    }
+    if !db_file.lock(LockLevel::Exclusive)? {
+        return Err(DBError::Busy(format!(
+            "Another connection is using the database, its hot journal {} has to wait",
+            path
+        )));
+    }
+    // Another connection may have rolled the journal back before we got the lock.
+    let result = match is_hot(vfs, path) {
+        Ok(true) => play_back(vfs, db_file, path),
+        result => result,
+    };
+    db_file.lock(LockLevel::Shared)?;
+    result
+}
+
+// An empty journal, or one with its header zeroed out, belongs to a finished commit.
+fn is_hot(vfs: &dyn Vfs, path: &str) -> Result<bool> {
+    if !vfs.exists(path)? {
+        return Ok(false);
+    }
+    let mut magic = [0; 8];
+    let read = vfs.open(path, OpenMode::ReadOnly)?.read_at(0, &mut magic)?;
+    Ok(read == magic.len() && magic == JOURNAL_MAGIC)
+}
+
+fn play_back(vfs: &dyn Vfs, db_file: &mut dyn VfsFile, path: &str) -> Result<bool> {
+    let mut file = vfs.open(path, OpenMode::ReadOnly)?;
+    let mut journal = vec![0; file.size()? as usize];
+    file.read_exact_at(0, &mut journal)?;
+    drop(file);
+    // The crash came before the header was all there, so before any page was written.
+    if journal.len() < JOURNAL_HEADER_SIZE {
+        delete_journal(vfs, path)?;
+        return Ok(false);
+    }
+
+    let original_page_count = read_u32(&journal, 16) as usize;
+    let page_size = read_u32(&journal, 24) as usize;
+    if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+        return Err(DBError::InvalidFileHeader(format!(
+            "Invalid page size in journal {}: {}",
+            path, page_size
+        )));
+    }
+    let record_size = page_size + 8;
+    info!(target: PAGER, "Rolling back hot journal {}", path);
+
+    let mut header_offset = 0;
+    while journal.len() >= header_offset + JOURNAL_HEADER_SIZE
+        && journal[header_offset..].starts_with(&JOURNAL_MAGIC)
+    {
+        let record_count = read_u32(&journal, header_offset + 8);
+        let nonce = read_u32(&journal, header_offset + 12);
+        let sector_size = (read_u32(&journal, header_offset + 20) as usize).max(SECTOR_SIZE);
+        let records_start = header_offset + sector_size;
+        let records_in_file = journal.len().saturating_sub(records_start) / record_size;
+        let record_count = match record_count {
+            0xFFFF_FFFF => records_in_file,
+            record_count => (record_count as usize).min(records_in_file),
+        };
+
+        for record in 0..record_count {
+            let offset = records_start + record * record_size;
+            let page_index = read_u32(&journal, offset) as usize;
+            let page = &journal[offset + 4..offset + 4 + page_size];
+            if page_index == 0
+                || read_u32(&journal, offset + 4 + page_size) != checksum(nonce, page)
+            {
+                return finish_recovery(vfs, db_file, path, page_size, original_page_count);
+            }
+            db_file.write_at(((page_index - 1) * page_size) as u64, page)?;
+        }
+
+        let records_end = records_start + record_count * record_size;
+        header_offset = records_end.div_ceil(sector_size) * sector_size;
+    }
+    finish_recovery(vfs, db_file, path, page_size, original_page_count)
+}
+
+fn finish_recovery(
+    vfs: &dyn Vfs,
+    db_file: &mut dyn VfsFile,
+    path: &str,
+    page_size: usize,
+    original_page_count: usize,
+) -> Result<bool> {
+    db_file.truncate((original_page_count * page_size) as u64)?;
+    db_file.sync()?;
+    delete_journal(vfs, path)?;
+    Ok(true)
+}
+
+// SQLite only sums every 200th byte of the page, starting from the end.
+fn checksum(nonce: u32, page: &[u8]) -> u32 {
+    let mut checksum = nonce;
+    let mut index = page.len() as isize - 200;
+    while index > 0 {
+        checksum = checksum.wrapping_add(page[index as usize] as u32);
+        index -= 200;
+    }
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::page::btree::insert;
+    use crate::page::cursor::BTreeCursor;
+    use crate::page::errors::DBError;
+    use crate::page::file_structures::{read_page_bytes, Value};
+    use crate::page::journal::{journal_path, recover, write_journal};
+    use crate::page::test_utils::{temp_copy, Sqlite3};
+    use crate::page::vfs::{LockLevel, MemoryVfs, OpenMode, OsVfs, Vfs};
+    use crate::page::Database;
+
+    // Made by SQLite: 300 rows in `items`, then a process that updated and inserted rows in a
+    // transaction with a tiny page cache, so changes spilled into the file before it was killed.
+    const HOT_JOURNAL_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/hot_journal.db");
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+
+    fn item_names(database: &mut Database) -> Vec<(u64, String)> {
+        let root_page = database.get_table("items").unwrap().root_page;
+        let mut cursor = BTreeCursor::new(root_page);
+        let mut rows = Vec::new();
+        let mut more = cursor.first(&mut database.pager).unwrap();
+        while more {
+            let cell = cursor.cell().unwrap();
+            let name = match &cell.payload[1] {
+                Value::Text(name) => name.clone(),
+                value => panic!("Expected a name, got: {:?}", value),
+            };
+            rows.push((cell.row_id, name));
+            more = cursor.next(&mut database.pager).unwrap();
+        }
+        rows
+    }
+
+    #[test]
+    fn recover_sqlite_hot_journal_test() {
+        let path = temp_copy(HOT_JOURNAL_DB);
+        std::fs::copy(journal_path(HOT_JOURNAL_DB), journal_path(&path)).unwrap();
+
+        let mut database = Database::open(path.clone()).unwrap();
+        assert!(!Path::new(&journal_path(&path)).exists());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 29 * 512);
+        let expected: Vec<(u64, String)> =
+            (1..=300).map(|id| (id, format!("item-{}", id))).collect();
+        assert_eq!(item_names(&mut database), expected);
+    }
+
+    #[test]
+    fn journal_of_another_writer_is_left_alone_test() {
+        let path = temp_copy(HOT_JOURNAL_DB);
+        std::fs::copy(journal_path(HOT_JOURNAL_DB), journal_path(&path)).unwrap();
+        let original = std::fs::read(&path).unwrap();
+
+        // Another connection in the middle of a commit holds the exclusive lock.
+        let mut writer = OsVfs.open(&path, OpenMode::ReadWrite).unwrap();
+        assert!(writer.lock(LockLevel::Exclusive).unwrap());
+        assert!(matches!(
+            Database::open(path.clone()),
+            Err(DBError::Busy(_))
+        ));
+        assert!(Path::new(&journal_path(&path)).exists());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+
+        // Once it is gone without finishing, the journal is hot.
+        drop(writer);
+        let mut database = Database::open(path.clone()).unwrap();
+        assert!(!Path::new(&journal_path(&path)).exists());
+        assert_eq!(item_names(&mut database).len(), 300);
+    }
+
+    #[test]
+    fn journal_of_sqlite3_writer_is_left_alone_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut sqlite = Sqlite3::open(&path);
+        sqlite.run("BEGIN; INSERT INTO items (name) VALUES ('item-1');");
+        assert!(Path::new(&journal_path(&path)).exists());
+
+        // A writer with its changes still in memory only has RESERVED, we can read what was
+        // committed before.
+        let mut database = Database::open(path.clone()).unwrap();
+        assert!(Path::new(&journal_path(&path)).exists());
+        assert_eq!(item_names(&mut database), vec![]);
+        drop(database);
+
+        // Changes that did not fit in its cache go to the file under an EXCLUSIVE lock.
+        let original = std::fs::read(&path).unwrap();
+        sqlite.run(
+            "PRAGMA cache_size = 1;
+            WITH RECURSIVE ids(id) AS (SELECT 2 UNION ALL SELECT id + 1 FROM ids WHERE id < 300)
+            INSERT INTO items (name) SELECT 'item-' || id FROM ids;",
+        );
+        assert_ne!(std::fs::read(&path).unwrap(), original);
+        assert!(matches!(
+            Database::open(path.clone()),
+            Err(DBError::Busy(_))
+        ));
+        assert!(Path::new(&journal_path(&path)).exists());
+
+        sqlite.run("ROLLBACK;");
+        assert!(!Path::new(&journal_path(&path)).exists());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        let mut database = Database::open(path.clone()).unwrap();
+        assert_eq!(item_names(&mut database), vec![]);
+    }
+
+    #[test]
+    fn read_only_open_leaves_hot_journal_test() {
+        let path = temp_copy(HOT_JOURNAL_DB);
+        std::fs::copy(journal_path(HOT_JOURNAL_DB), journal_path(&path)).unwrap();
+        let original = std::fs::read(&path).unwrap();
+
+        assert!(matches!(
+            Database::open_read_only(path.clone()),
+            Err(DBError::ReadOnly(_))
+        ));
+        assert!(Path::new(&journal_path(&path)).exists());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn read_only_hot_journal_test() {
+        let vfs = MemoryVfs::new();
+        vfs.write_file("hot.db", std::fs::read(HOT_JOURNAL_DB).unwrap());
+        let journal = std::fs::read(journal_path(HOT_JOURNAL_DB)).unwrap();
+        vfs.write_file(&journal_path("hot.db"), journal);
+
+        let mut file = vfs.open("hot.db", OpenMode::ReadOnly).unwrap();
+        assert!(file.lock(LockLevel::Shared).unwrap());
+        assert!(matches!(
+            recover(&vfs, file.as_mut(), &journal_path("hot.db")),
+            Err(DBError::ReadOnly(_))
+        ));
+        assert!(vfs.exists(&journal_path("hot.db")).unwrap());
+    }
+
+    #[test]
+    fn unsynced_journal_records_are_ignored_test() {
+        let path = temp_copy(HOT_JOURNAL_DB);
+        std::fs::copy(journal_path(HOT_JOURNAL_DB), journal_path(&path)).unwrap();
+        let mut database = Database::open(path.clone()).unwrap();
+        let expected = item_names(&mut database);
+        let page_count = database.pager.page_count();
+        drop(database);
+
+        // Power went out after the records were written but before their number was.
+        write_journal(
+            &OsVfs,
+            &journal_path(&path),
+            512,
+            page_count,
+            &[(2, vec![0; 512]), (3, vec![0; 512])],
+        )
+        .unwrap();
+        let mut journal = std::fs::read(journal_path(&path)).unwrap();
+        assert_eq!(&journal[8..12], &[0, 0, 0, 2]);
+        journal[8..12].copy_from_slice(&[0; 4]);
+        std::fs::write(journal_path(&path), journal).unwrap();
+
+        let mut database = Database::open(path.clone()).unwrap();
+        assert!(!Path::new(&journal_path(&path)).exists());
+        assert_eq!(item_names(&mut database), expected);
+    }
+
+    #[test]
+    fn interrupted_commit_is_rolled_back_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        let values = vec![
+            Value::Null,
+            Value::Text("item-1".to_string()),
+            Value::Null,
+            Value::Null,
+        ];
+        database.insert("items", values).unwrap();
+        assert!(!Path::new(&journal_path(&path)).exists());
+
+        let root_page = database.get_table("items").unwrap().root_page;
+        for rowid in 2..=200 {
+            let values = [
+                Value::Null,
+                Value::Text(format!("item-{}", rowid)),
+                Value::Null,
+                Value::Null,
+            ];
+            insert(&mut database.pager, root_page, rowid, &values).unwrap();
+        }
+
+        // Do what a commit does, but crash after writing half of the pages.
+        let pager = &mut database.pager;
+        let page_size = pager.db_header.page_size as usize;
+        let original_page_count = pager.file.size().unwrap() as usize / page_size;
+        let mut dirty_pages: Vec<(usize, Vec<u8>)> = pager.dirty_pages.drain().collect();
+        dirty_pages.sort_by_key(|(page_index, _)| *page_index);
+        let original_pages: Vec<(usize, Vec<u8>)> = dirty_pages
+            .iter()
+            .filter(|(page_index, _)| *page_index <= original_page_count)
+            .map(|(page_index, _)| {
+                let page = read_page_bytes(pager.file.as_mut(), page_size, *page_index).unwrap();
+                (*page_index, page)
+            })
+            .collect();
+        write_journal(
+            pager.vfs.as_ref(),
+            &pager.journal_path,
+            page_size,
+            original_page_count,
+            &original_pages,
+        )
+        .unwrap();
+        for (page_index, page) in dirty_pages.iter().rev().take(dirty_pages.len() / 2 + 1) {
+            pager
+                .file
+                .write_at(((page_index - 1) * page_size) as u64, page)
+                .unwrap();
+        }
+        drop(database);
+
+        let mut database = Database::open(path.clone()).unwrap();
+        assert!(!Path::new(&journal_path(&path)).exists());
+        assert_eq!(database.pager.page_count(), original_page_count);
+        assert_eq!(item_names(&mut database), vec![(1, "item-1".to_string())]);
+    }
+}
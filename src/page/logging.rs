@@ -0,0 +1,14 @@
+/*
+* Log targets of the parts of sand.
+*
+* Everything goes through the `log` facade, so nothing is printed unless the program using sand
+* installs a logger. The targets let it pick the parts it wants to hear about, with env_logger for
+* example `RUST_LOG=sand::pager=trace` traces every page read and cache hit. `sand` covers them all.
+*/
+
+// Page reads, the page cache, commits, the rollback journal and the WAL.
+pub const PAGER: &str = "sand::pager";
+// Rows going in and out of b-trees, pages being split, merged and freed.
+pub const BTREE: &str = "sand::btree";
+// Decoding pages, records and text.
+pub const RECORD: &str = "sand::record";
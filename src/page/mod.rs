@@ -1,69 +1,588 @@
-use anyhow::Result;
-use file_structures::DBHeader;
-use pager::Pager;
+use cursor::{BTreeCursor, IndexCursor};
+use errors::{DBError, Result};
+use file_structures::{DBHeader, Value};
+use page_cache::PageCache;
+use pager::{JournalMode, Pager};
 use std::{
     collections::HashMap,
-    fs::File,
     sync::{Arc, RwLock},
 };
+use vfs::{LockLevel, OpenMode, OsVfs, Vfs};
 
+use crate::sql::{
+    ast::Expr,
+    compiler,
+    value::{self, Collation},
+    vm::{compare_sort_keys, SortOrder, Vm},
+    Rows,
+};
+use wal::{CheckpointMode, Wal};
+
+pub mod btree;
+pub mod cursor;
 pub mod errors;
+#[cfg(test)]
+pub mod fault_vfs;
 pub mod file_structures;
+pub mod freelist;
+pub mod journal;
+pub mod logging;
+pub mod page_cache;
 pub mod pager;
+pub mod schema;
+#[cfg(test)]
+pub mod test_utils;
+pub mod vfs;
+pub mod wal;
 
 // TODO: Check how to implement concurreny. Maybe the page cache and header and pager show be
 // sharable across instances? The cache should be at least.
 // Implementing Arc<RwLock<HashMap<uszie, BTreePage>>> for now. Seems like the right thing but
 // still need to look moer into this.
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Database {
     pub pager: Pager,
-    // Should this be shared like Rc or Arc?
-    header: DBHeader,
     // This I think should definitely be shared. Will look into this.
+    // Keyed by the lowercased table name, SQL names are case insensitive.
     tables: HashMap<String, Table>,
+    // Keyed by the lowercased index name.
+    indexes: HashMap<String, Index>,
 }
 
 impl Database {
     pub fn open(file_path: String) -> Result<Database> {
-        let mut file = File::open(file_path)?;
-        let page_cache = Arc::new(RwLock::new(HashMap::new()));
-        let header = file_structures::read_db_header(&mut file)?;
+        Database::open_with_vfs(file_path, Arc::new(OsVfs))
+    }
+
+    // Opens a database with all of its file access going through `vfs`.
+    pub fn open_with_vfs(file_path: String, vfs: Arc<dyn Vfs>) -> Result<Database> {
+        Database::open_with_mode(file_path, vfs, OpenMode::ReadWrite)
+    }
+
+    // Opens a database only to look at it, nothing is ever written to it. A hot journal is not
+    // rolled back either, opening fails with a read only error when there is one.
+    pub fn open_read_only(file_path: String) -> Result<Database> {
+        Database::open_read_only_with_vfs(file_path, Arc::new(OsVfs))
+    }
+
+    pub fn open_read_only_with_vfs(file_path: String, vfs: Arc<dyn Vfs>) -> Result<Database> {
+        Database::open_with_mode(file_path, vfs, OpenMode::ReadOnly)
+    }
+
+    fn open_with_mode(file_path: String, vfs: Arc<dyn Vfs>, mode: OpenMode) -> Result<Database> {
+        let mut file = vfs.open(&file_path, mode)?;
+        // A checkpoint going on in another connection changes both the WAL and the file, and a
+        // journal left behind by a commit that never finished has to be played back before
+        // anything is read, the header included.
+        if !file.lock(LockLevel::Shared)? {
+            return Err(DBError::Busy(
+                "Another connection is writing to the database".to_string(),
+            ));
+        }
+        let journal_path = journal::journal_path(&file_path);
+        let wal_path = wal::wal_path(&file_path);
+        let opened = journal::recover(vfs.as_ref(), file.as_mut(), &journal_path)
+            .and_then(|_| file_structures::read_db_header(file.as_mut()))
+            .and_then(|header| {
+                let wal = Wal::open(vfs.as_ref(), &wal_path, header.page_size as usize)?;
+                Ok((wal, header))
+            });
+        file.lock(LockLevel::None)?;
+        let (wal, header) = opened?;
+        let page_cache = Arc::new(RwLock::new(PageCache::new(header.cache_size_in_pages())));
 
         let pager = Pager {
-            db_header: header.clone(),
+            file_change_counter: header.change_counter,
+            db_header: header,
+            vfs,
             file,
             // NOTE: With Arc Clone is the way to go.
             page_cache: page_cache.clone(),
+            dirty_pages: HashMap::new(),
+            journal_path,
+            wal,
+            wal_path,
+            journal_mode: JournalMode::Delete,
+            lossy_text: false,
+        };
+
+        let mut database = Database {
+            pager,
+            tables: HashMap::new(),
+            indexes: HashMap::new(),
+        };
+        database.pager.begin_read()?;
+        // Page 1 may have a newer version in the WAL.
+        let loaded = database.pager.reload_header().and_then(|_| {
+            database.pager.detect_journal_mode();
+            database.load_schema()
+        });
+        database.pager.end_transaction()?;
+        loaded?;
+
+        Ok(database)
+    }
+
+    // The pager's copy of the header, which follows commits, rollbacks and journal mode changes.
+    pub fn header(&self) -> &DBHeader {
+        &self.pager.db_header
+    }
+
+    // Looks up a table by name, ignoring case like SQL does.
+    pub fn get_table(&self, name: &str) -> Option<&Table> {
+        self.tables.get(&name.to_lowercase())
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &Table> {
+        self.tables.values()
+    }
+
+    pub fn get_index(&self, name: &str) -> Option<&Index> {
+        self.indexes.get(&name.to_lowercase())
+    }
+
+    pub fn indexes(&self) -> impl Iterator<Item = &Index> {
+        self.indexes.values()
+    }
+
+    /*
+     * Runs a SELECT and returns an iterator over its result rows, each a Vec with a value for every
+     * result column. The SQL is parsed and compiled against the schema up front, the rows are read
+     * as the iterator is advanced. `EXPLAIN SELECT ...` lists the program the SELECT compiles to,
+     * an instruction per row, and `EXPLAIN QUERY PLAN SELECT ...` how it gets to the rows, like
+     * which index it searches.
+     */
+    pub fn query(&mut self, sql: &str) -> Result<Rows<'_>> {
+        // The read lasts until the rows are dropped.
+        if let Err(err) = self.begin_read() {
+            self.pager.end_transaction()?;
+            return Err(err);
+        }
+        match compiler::compile(&self.tables, &self.indexes, sql) {
+            Ok(program) => Ok(Rows::new(program, &mut self.pager)),
+            Err(err) => {
+                self.pager.end_transaction()?;
+                Err(err)
+            }
+        }
+    }
+
+    /*
+     * Inserts a row into the table and commits it, returning the rowid of the new row.
+     *
+     * `values` has a value for every column of the table. The value of an INTEGER PRIMARY KEY
+     * column is used as the rowid, a NULL there, or no such column at all, picks the rowid after the
+     * largest one in the table.
+     *
+     * The row gets an entry in every index of the table, partial indexes only when it matches their
+     * WHERE clause. A UNIQUE index that already has an entry with the same key fails the insert.
+     */
+    pub fn insert(&mut self, table_name: &str, values: Vec<Value>) -> Result<u64> {
+        self.write(|database| database.insert_row(table_name, values))
+    }
+
+    fn insert_row(&mut self, table_name: &str, values: Vec<Value>) -> Result<u64> {
+        let table = self
+            .get_table(table_name)
+            .ok_or_else(|| DBError::InvalidSchema(format!("No such table: {}", table_name)))?;
+        if values.len() != table.columns.len() {
+            return Err(DBError::InvalidSchema(format!(
+                "Table {} has {} columns but {} values were supplied",
+                table.name,
+                table.columns.len(),
+                values.len()
+            )));
+        }
+        if table.without_rowid {
+            return Err(DBError::InvalidSchema(format!(
+                "Inserting into WITHOUT ROWID table {} is not supported",
+                table.name
+            )));
+        }
+
+        let root_page = table.root_page;
+        let rowid_alias = table.rowid_alias;
+        let mut values = values;
+        let rowid = match rowid_alias.map(|index| &values[index]) {
+            Some(Value::Integer(rowid)) => *rowid as u64,
+            Some(Value::Null) | None => self.next_rowid(root_page)?,
+            Some(value) => {
+                return Err(DBError::ConstraintViolation(format!(
+                    "Rowid must be an integer, got: {:?}",
+                    value
+                )))
+            }
         };
+        // The rowid alias is stored as a NULL, its value lives in the rowid.
+        if let Some(index) = rowid_alias {
+            values[index] = Value::Null;
+        }
 
+        btree::insert(&mut self.pager, root_page, rowid, &values)?;
+
+        // The entries are worked out from the row as it is stored now.
+        let entries = index_entries(
+            &self.tables,
+            &self.indexes,
+            &mut self.pager,
+            table_name,
+            rowid,
+        )?;
+        for (index, entry) in entries {
+            let orders = index.entry_orders();
+            let key_size = index.columns.len();
+            if index.unique && !entry[..key_size].contains(&Value::Null) {
+                let key = &entry[..key_size];
+                let is_before =
+                    |other: &[Value]| compare_sort_keys(&orders[..key_size], other, key).is_lt();
+                let mut cursor = IndexCursor::new(index.root_page);
+                if cursor.seek(&mut self.pager, is_before)?
+                    && cursor.record().is_some_and(|other| {
+                        compare_sort_keys(&orders[..key_size], other, key).is_eq()
+                    })
+                {
+                    return Err(DBError::ConstraintViolation(format!(
+                        "UNIQUE constraint failed: index {}",
+                        index.name
+                    )));
+                }
+            }
+            btree::insert_entry(&mut self.pager, index.root_page, &entry, |a, b| {
+                compare_sort_keys(&orders, a, b)
+            })?;
+        }
+        Ok(rowid)
+    }
+
+    // Deletes the row with the given rowid and its index entries, returning whether there was such
+    // a row.
+    pub fn delete(&mut self, table_name: &str, rowid: u64) -> Result<bool> {
+        self.write(|database| database.delete_row(table_name, rowid))
+    }
+
+    fn delete_row(&mut self, table_name: &str, rowid: u64) -> Result<bool> {
+        let table = self
+            .get_table(table_name)
+            .ok_or_else(|| DBError::InvalidSchema(format!("No such table: {}", table_name)))?;
+        if table.without_rowid {
+            return Err(DBError::InvalidSchema(format!(
+                "Deleting from WITHOUT ROWID table {} is not supported",
+                table.name
+            )));
+        }
+
+        let root_page = table.root_page;
+        // The entries are worked out from the row before it is gone.
+        let entries = index_entries(
+            &self.tables,
+            &self.indexes,
+            &mut self.pager,
+            table_name,
+            rowid,
+        )?;
+        if !btree::delete(&mut self.pager, root_page, rowid)? {
+            return Ok(false);
+        }
+        for (index, entry) in entries {
+            let orders = index.entry_orders();
+            let deleted = btree::delete_entry(&mut self.pager, index.root_page, &entry, |a, b| {
+                compare_sort_keys(&orders, a, b)
+            })?;
+            if !deleted {
+                return Err(DBError::corrupt(format!(
+                    "Index {} has no entry for row {}",
+                    index.name, rowid
+                )));
+            }
+        }
+        Ok(true)
+    }
+
+    // Starts a read, with the schema read again if another connection changed the database.
+    fn begin_read(&mut self) -> Result<()> {
+        if self.pager.begin_read()? {
+            self.load_schema()?;
+        }
+        Ok(())
+    }
+
+    // Runs `write` in a write of its own and commits what it changed, or rolls it all back if it
+    // fails.
+    fn write<T>(&mut self, write: impl FnOnce(&mut Database) -> Result<T>) -> Result<T> {
+        let changed = self.pager.begin_write()?;
+        let result = (|| {
+            if changed {
+                self.load_schema()?;
+            }
+            let result = write(self)?;
+            self.pager.commit()?;
+            Ok(result)
+        })();
+        match result {
+            Ok(result) => {
+                self.pager.end_transaction()?;
+                Ok(result)
+            }
+            Err(err) => {
+                self.pager.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    pub fn journal_mode(&self) -> JournalMode {
+        self.pager.journal_mode
+    }
+
+    pub fn set_journal_mode(&mut self, journal_mode: JournalMode) -> Result<()> {
+        self.pager.set_journal_mode(journal_mode)
+    }
+
+    // See `Pager::lossy_text`.
+    pub fn set_lossy_text(&mut self, lossy_text: bool) {
+        self.pager.set_lossy_text(lossy_text);
+    }
+
+    // See `Pager::set_mmap_size`.
+    pub fn set_mmap_size(&mut self, size: u64) -> Result<()> {
+        self.pager.set_mmap_size(size)
+    }
+
+    // Copies the WAL into the database file, see `Pager::checkpoint`.
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> Result<(usize, usize)> {
+        self.pager.checkpoint(mode)
+    }
+
+    // One more than the largest rowid in the table.
+    fn next_rowid(&mut self, root_page: usize) -> Result<u64> {
+        let mut cursor = BTreeCursor::new(root_page);
+        if !cursor.last(&mut self.pager)? {
+            return Ok(1);
+        }
+
+        let largest_rowid = cursor.rowid().unwrap() as i64;
+        // SQLite tries random rowids at this point, we just give up.
+        largest_rowid
+            .checked_add(1)
+            .map(|rowid| rowid.max(1) as u64)
+            .ok_or_else(|| DBError::ConstraintViolation("Out of rowids".to_string()))
+    }
+
+    // Reads every `table` and `index` entry of sqlite_master into `tables` and `indexes`.
+    fn load_schema(&mut self) -> Result<()> {
+        let master_table = Table::get_master_table();
+        let mut cursor = BTreeCursor::new(master_table.root_page);
         let mut tables = HashMap::new();
-        tables.insert("sqlite_master".to_string(), Table::get_master_table());
+        // Name, table name, root page and sql of the indexes. They are parsed once all the tables
+        // are, their columns are looked up in the table.
+        let mut index_entries = Vec::new();
 
-        Ok(Database {
-            pager,
-            tables,
-            header,
-        })
+        let mut more = cursor.first(&mut self.pager)?;
+        while more {
+            let cell = cursor.cell().unwrap();
+            // Views, triggers and virtual tables have no b-tree of their own, their root page is 0.
+            match cell.payload.as_slice() {
+                [Value::Text(entry_type), _, _, Value::Integer(root_page), Value::Text(sql)]
+                    if entry_type == "table" && *root_page > 0 =>
+                {
+                    let table = schema::parse_create_table(sql, *root_page as usize)?;
+                    tables.insert(table.name.to_lowercase(), table);
+                }
+                // Indexes SQLite creates for UNIQUE and PRIMARY KEY constraints have no sql.
+                [Value::Text(entry_type), Value::Text(name), Value::Text(table_name), Value::Integer(root_page), sql]
+                    if entry_type == "index" =>
+                {
+                    let sql = match sql {
+                        Value::Text(sql) => Some(sql.clone()),
+                        _ => None,
+                    };
+                    index_entries.push((
+                        name.clone(),
+                        table_name.clone(),
+                        *root_page as usize,
+                        sql,
+                    ));
+                }
+                _ => {}
+            }
+            more = cursor.next(&mut self.pager)?;
+        }
+
+        let mut indexes = HashMap::new();
+        for (name, table_name, root_page, sql) in index_entries {
+            let table = tables.get(&table_name.to_lowercase()).ok_or_else(|| {
+                DBError::InvalidSchema(format!("No such table {} for index {}", table_name, name))
+            })?;
+            let index = schema::parse_index(name, table, root_page, sql)?;
+            indexes.insert(index.name.to_lowercase(), index);
+        }
+
+        tables.insert(master_table.name.clone(), master_table);
+        self.tables = tables;
+        self.indexes = indexes;
+        Ok(())
     }
 }
 
+#[derive(Debug)]
+pub struct Index {
+    pub name: String,
+    pub table_name: String,
+    pub root_page: usize,
+    pub sql: Option<String>,
+    // What the entries are ordered by. Every entry holds these columns followed by the rowid of the
+    // row it is for.
+    pub columns: Vec<IndexColumn>,
+    pub unique: bool,
+    // Partial indexes only have entries for the rows matching their WHERE clause.
+    pub where_clause: Option<Expr>,
+}
+
+impl Index {
+    // How the index orders its columns.
+    pub fn key_orders(&self) -> Vec<SortOrder> {
+        self.columns
+            .iter()
+            .map(|column| SortOrder {
+                descending: column.descending,
+                nulls_first: !column.descending,
+                collation: column
+                    .collation
+                    .as_deref()
+                    .and_then(Collation::from_name)
+                    .unwrap_or(Collation::Binary),
+            })
+            .collect()
+    }
+
+    // How the index orders its entries, by the columns and then by the rowid.
+    pub fn entry_orders(&self) -> Vec<SortOrder> {
+        let mut orders = self.key_orders();
+        orders.push(SortOrder {
+            descending: false,
+            nulls_first: true,
+            collation: Collation::Binary,
+        });
+        orders
+    }
+}
+
+/*
+ * The entries for the row with the given rowid in the indexes of its table, each index with the
+ * entry that belongs in it. Partial indexes the row does not match the WHERE clause of are left
+ * out.
+ *
+ * The values come from a query on the row, which works out the expressions of expression indexes
+ * and the WHERE clauses of partial ones the same way any other query would.
+ */
+fn index_entries<'a>(
+    tables: &HashMap<String, Table>,
+    indexes: &'a HashMap<String, Index>,
+    pager: &mut Pager,
+    table_name: &str,
+    rowid: u64,
+) -> Result<Vec<(&'a Index, Vec<Value>)>> {
+    let table = tables
+        .get(&table_name.to_lowercase())
+        .ok_or_else(|| DBError::InvalidSchema(format!("No such table: {}", table_name)))?;
+    let mut table_indexes: Vec<&Index> = indexes
+        .values()
+        .filter(|index| index.table_name.eq_ignore_ascii_case(&table.name))
+        .collect();
+    if table_indexes.is_empty() {
+        return Ok(Vec::new());
+    }
+    table_indexes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let mut result_columns = Vec::new();
+    for index in table_indexes.iter() {
+        for column in index.columns.iter() {
+            result_columns.push(match (&column.expression, column.column) {
+                (Some(expression), _) => expression.clone(),
+                (None, Some(column)) => quote(&table.columns[column].name),
+                (None, None) => {
+                    return Err(DBError::InvalidSchema(format!(
+                        "Index {} has a column that is neither a column nor an expression",
+                        index.name
+                    )))
+                }
+            });
+        }
+        let where_clause = index
+            .where_clause
+            .as_ref()
+            .zip(index.sql.as_deref())
+            .map(|(expr, sql)| &sql[expr.span.start..expr.span.end]);
+        result_columns.push(match where_clause {
+            Some(where_clause) => format!("({})", where_clause),
+            None => "1".to_string(),
+        });
+    }
+    let sql = format!(
+        "SELECT {} FROM {} WHERE rowid = {}",
+        result_columns.join(", "),
+        quote(&table.name),
+        rowid as i64
+    );
+
+    // A row that is not there has no entries.
+    let program = compiler::compile(tables, indexes, &sql)?;
+    let Some(row) = Vm::new(program, pager).step()? else {
+        return Ok(Vec::new());
+    };
+    let mut values = row.into_iter();
+    let mut entries = Vec::new();
+    for index in table_indexes {
+        let mut entry: Vec<Value> = values.by_ref().take(index.columns.len()).collect();
+        let matches = values
+            .next()
+            .is_some_and(|value| value::is_true(&value) == Some(true));
+        if matches {
+            entry.push(Value::Integer(rowid as i64));
+            entries.push((index, entry));
+        }
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexColumn {
+    // Position of the column in the table, None when the index is on an expression.
+    pub column: Option<usize>,
+    // SQL text of the expression for an index on one, None for a column.
+    pub expression: Option<String>,
+    pub descending: bool,
+    // The collation the index orders the column by, None for BINARY.
+    pub collation: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct Table {
     pub root_page: usize,
     pub name: String,
     pub columns: Vec<Column>,
+    // Index of the INTEGER PRIMARY KEY column. Its value is the rowid of the row and the record
+    // itself only stores a NULL in its place.
+    pub rowid_alias: Option<usize>,
+    pub without_rowid: bool,
+    // Keys of the PRIMARY KEY and UNIQUE constraints SQLite keeps a sqlite_autoindex_<table>_<n>
+    // index for, the n-th one being the key of index n.
+    pub unique_keys: Vec<Vec<IndexColumn>>,
 }
 
 #[derive(Debug)]
 pub struct Column {
     pub name: String,
     pub column_type: ColumnType,
-    // TODO: Find out how to handle primary keys.
+    // The type exactly as written in the CREATE TABLE statement, empty if there was none.
+    pub declared_type: String,
+    pub primary_key: bool,
+    // The COLLATE of the column definition, None for BINARY.
+    pub collation: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ColumnType {
     // NULL value.
     NULL,
@@ -75,37 +594,349 @@ pub enum ColumnType {
     Text,
     // Blob sotred exactly as input.
     Blob,
+    // Declared types that are none of the above, like DATE or DECIMAL(10, 5). Values are stored as
+    // integers or reals where that is lossless and as they are otherwise.
+    Numeric,
+}
+
+impl ColumnType {
+    // Column affinity from the declared type, the rules are checked in this order:
+    // https://www.sqlite.org/datatype3.html#determination_of_column_affinity
+    pub fn from_declared_type(declared_type: &str) -> ColumnType {
+        let declared_type = declared_type.to_uppercase();
+        if declared_type.contains("INT") {
+            ColumnType::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|name| declared_type.contains(name))
+        {
+            ColumnType::Text
+        } else if declared_type.contains("BLOB") || declared_type.is_empty() {
+            ColumnType::Blob
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|name| declared_type.contains(name))
+        {
+            ColumnType::Real
+        } else {
+            ColumnType::Numeric
+        }
+    }
 }
 
 impl Table {
     // NOTE: Turns out `const SQLITE_MASTER = Table {}` does not work because you cannot have non
     // constant method calls in the const declaration, so we go with a function instead.
     pub fn get_master_table() -> Table {
+        let column = |name: &str, column_type: ColumnType, declared_type: &str| Column {
+            name: name.to_string(),
+            column_type,
+            declared_type: declared_type.to_string(),
+            primary_key: false,
+            collation: None,
+        };
+
         Table {
             root_page: 1,
             name: "sqlite_master".to_string(),
             columns: vec![
-                Column {
-                    name: "type".to_string(),
-                    column_type: ColumnType::Text,
-                },
-                Column {
-                    name: "name".to_string(),
-                    column_type: ColumnType::Text,
-                },
-                Column {
-                    name: "table_name".to_string(),
-                    column_type: ColumnType::Text,
-                },
-                Column {
-                    name: "root_page".to_string(),
-                    column_type: ColumnType::Integer,
-                },
-                Column {
-                    name: "sql".to_string(),
-                    column_type: ColumnType::Text,
-                },
+                column("type", ColumnType::Text, "text"),
+                column("name", ColumnType::Text, "text"),
+                column("tbl_name", ColumnType::Text, "text"),
+                column("rootpage", ColumnType::Integer, "int"),
+                column("sql", ColumnType::Text, "text"),
             ],
+            rowid_alias: None,
+            without_rowid: false,
+            unique_keys: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::page::cursor::BTreeCursor;
+    use crate::page::errors::DBError;
+    use crate::page::file_structures::Value;
+    use crate::page::journal::journal_path;
+    use crate::page::logging::{BTREE, PAGER, RECORD};
+    use crate::page::pager::JournalMode;
+    use crate::page::test_utils::{capture_logs, temp_copy, Sqlite3};
+    use crate::page::vfs::{LockLevel, MemoryVfs, OpenMode, Vfs};
+    use crate::page::wal::wal_path;
+    use crate::page::Database;
+
+    const SAND_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/sand.db");
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+    const MULTI_PAGE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/multi_page.db");
+    const OVERFLOW_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/overflow.db");
+
+    #[test]
+    fn load_schema_test() {
+        let database = Database::open(SAND_DB.to_string()).unwrap();
+        let mut names: Vec<&str> = database.tables().map(|table| table.name.as_str()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["SandWorm", "sand", "sqlite_master", "sqlite_sequence"]
+        );
+
+        let sand_worm = database.get_table("sandworm").unwrap();
+        assert_eq!(sand_worm.root_page, 3);
+        assert_eq!(sand_worm.rowid_alias, Some(0));
+        assert_eq!(sand_worm.columns.len(), 5);
+        assert_eq!(sand_worm.columns[3].name, "description");
+        assert_eq!(sand_worm.columns[3].declared_type, "VARCHAR(1000)");
+
+        let sand = database.get_table("sand").unwrap();
+        assert_eq!(sand.root_page, 2);
+        assert_eq!(sand.columns[1].name, "name");
+    }
+
+    #[test]
+    fn memory_vfs_database_test() {
+        let vfs = MemoryVfs::new();
+        vfs.write_file("items.db", std::fs::read(EMPTY_TABLE_DB).unwrap());
+
+        let mut database =
+            Database::open_with_vfs("items.db".to_string(), Arc::new(vfs.clone())).unwrap();
+        let change_counter = database.header().change_counter;
+        for id in 1..=100 {
+            let values = vec![
+                Value::Null,
+                Value::Text(format!("item-{}", id)),
+                Value::Null,
+                Value::Null,
+            ];
+            database.insert("items", values).unwrap();
         }
+        assert!(database.delete("items", 50).unwrap());
+        // Every insert and delete is a commit of its own.
+        assert_eq!(database.header().change_counter, change_counter + 101);
+        drop(database);
+
+        // Nothing went to disk, and the changes are there when the file is opened again.
+        assert!(!vfs.exists(&journal_path("items.db")).unwrap());
+        let original_size = std::fs::metadata(EMPTY_TABLE_DB).unwrap().len() as usize;
+        assert!(vfs.read_file("items.db").unwrap().len() > original_size);
+        let mut database = Database::open_with_vfs("items.db".to_string(), Arc::new(vfs)).unwrap();
+        assert!(!database.delete("items", 50).unwrap());
+        assert!(database.delete("items", 100).unwrap());
+    }
+
+    #[test]
+    fn locks_test() {
+        let vfs = MemoryVfs::new();
+        vfs.write_file("items.db", std::fs::read(EMPTY_TABLE_DB).unwrap());
+        let open = || Database::open_with_vfs("items.db".to_string(), Arc::new(vfs.clone()));
+        let mut other = vfs.open("items.db", OpenMode::ReadWrite).unwrap();
+
+        // Opening reads the journal and the WAL, which a writer may be changing.
+        assert!(other.lock(LockLevel::Exclusive).unwrap());
+        assert!(matches!(open(), Err(DBError::Busy(_))));
+        assert!(other.lock(LockLevel::None).unwrap());
+        let mut database = open().unwrap();
+        database.set_journal_mode(JournalMode::Wal).unwrap();
+        let values = vec![Value::Null, Value::Null, Value::Null, Value::Null];
+        database.insert("items", values).unwrap();
+        assert!(vfs.exists(&wal_path("items.db")).unwrap());
+
+        // Nor does the WAL go away under a reader.
+        assert!(other.lock(LockLevel::Shared).unwrap());
+        assert!(matches!(
+            database.set_journal_mode(JournalMode::Delete),
+            Err(DBError::Busy(_))
+        ));
+        assert!(vfs.exists(&wal_path("items.db")).unwrap());
+        assert!(other.lock(LockLevel::None).unwrap());
+        database.set_journal_mode(JournalMode::Delete).unwrap();
+        assert!(!vfs.exists(&wal_path("items.db")).unwrap());
+    }
+
+    #[test]
+    fn reader_sees_other_connections_commits_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut reader = Database::open(path.clone()).unwrap();
+        let mut writer = Database::open(path.clone()).unwrap();
+        reader.pager.set_cache_capacity(3);
+        let count = |database: &mut Database| {
+            let mut rows = database.query("SELECT count(*) FROM items").unwrap();
+            rows.next().unwrap().unwrap()
+        };
+        let item = |id: i64| {
+            vec![
+                Value::Null,
+                Value::Text(format!("item-{}", id)),
+                Value::Null,
+                Value::Null,
+            ]
+        };
+
+        // The reader has pages of every size of the table in its cache.
+        for id in 1..=400 {
+            writer.insert("items", item(id)).unwrap();
+            if id % 50 == 0 {
+                assert_eq!(count(&mut reader), vec![Value::Integer(id)]);
+            }
+        }
+
+        // Nobody commits while the rows of a query are being read.
+        let rows = reader.query("SELECT name FROM items").unwrap();
+        assert!(matches!(
+            writer.insert("items", item(401)),
+            Err(DBError::Busy(_))
+        ));
+        drop(rows);
+        writer.insert("items", item(401)).unwrap();
+        assert_eq!(count(&mut reader), vec![Value::Integer(401)]);
+
+        // Tables made by another connection show up as well.
+        Sqlite3::open(&path).run("CREATE TABLE extra (x); INSERT INTO extra VALUES (42);");
+        let mut rows = reader.query("SELECT x FROM extra").unwrap();
+        assert_eq!(rows.next().unwrap().unwrap(), vec![Value::Integer(42)]);
+    }
+
+    fn item_names(database: &mut Database) -> Vec<String> {
+        let root_page = database.get_table("items").unwrap().root_page;
+        let mut cursor = BTreeCursor::new(root_page);
+        let mut names = Vec::new();
+        let mut more = cursor.first(&mut database.pager).unwrap();
+        while more {
+            names.push(format!("{:?}", cursor.cell().unwrap().payload[1]));
+            more = cursor.next(&mut database.pager).unwrap();
+        }
+        names
+    }
+
+    #[test]
+    fn mmap_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        database.set_mmap_size(1 << 20).unwrap();
+        // The file grows well past what was mapped when the first page was read.
+        for id in 1..=300 {
+            let values = vec![
+                Value::Null,
+                Value::Text(format!("item-{}", id)),
+                Value::Null,
+                Value::Null,
+            ];
+            database.insert("items", values).unwrap();
+        }
+        database.pager.set_cache_capacity(1);
+        let names = item_names(&mut database);
+        assert_eq!(names.len(), 300);
+        assert_eq!(names[299], "Text(\"item-300\")");
+
+        // Only part of the file mapped, and none of it.
+        for mmap_size in [4 * 512, 0] {
+            let mut database = Database::open(path.clone()).unwrap();
+            database.set_mmap_size(mmap_size).unwrap();
+            database.pager.set_cache_capacity(1);
+            assert_eq!(item_names(&mut database), names);
+        }
+    }
+
+    // Reads every row of the table and then writes to it. Errors are fine, the point is that
+    // nothing panics or loops forever.
+    fn exercise(database: &mut Database, table_name: &str, values: Vec<Value>) {
+        let Some(table) = database.get_table(table_name) else {
+            return;
+        };
+        let mut cursor = BTreeCursor::new(table.root_page);
+        let mut more = cursor.first(&mut database.pager);
+        while let Ok(true) = more {
+            more = cursor.next(&mut database.pager);
+        }
+        if let Ok(rowid) = database.insert(table_name, values) {
+            let _ = database.delete(table_name, rowid);
+        }
+        let _ = database.delete(table_name, 1);
+    }
+
+    #[test]
+    fn corrupt_database_fuzz_test() {
+        // xorshift64, so that a failure can be reproduced.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for (path, table_name) in [(MULTI_PAGE_DB, "worms"), (OVERFLOW_DB, "docs")] {
+            let original = std::fs::read(path).unwrap();
+            for _ in 0..500 {
+                let mut bytes = original.clone();
+                // Leave the database header alone most of the time, or very few runs get past
+                // opening the file.
+                let start = if random() % 8 == 0 { 0 } else { 100 };
+                for _ in 0..1 + random() % 8 {
+                    let offset = start + (random() as usize) % (bytes.len() - start);
+                    bytes[offset] = random() as u8;
+                }
+
+                let vfs = MemoryVfs::new();
+                vfs.write_file("fuzz.db", bytes);
+                let Ok(mut database) =
+                    Database::open_with_vfs("fuzz.db".to_string(), Arc::new(vfs))
+                else {
+                    continue;
+                };
+                let mut values = vec![Value::Null, Value::Text("fuzz".repeat(200)), Value::Null];
+                if table_name == "docs" {
+                    values.push(Value::Null);
+                }
+                exercise(&mut database, table_name, values);
+            }
+        }
+    }
+
+    #[test]
+    fn logging_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let (_, logs) = capture_logs(|| {
+            database.pager.read_page(2).unwrap();
+            database.pager.read_page(2).unwrap();
+        });
+        let messages: Vec<(&str, &str)> = logs
+            .iter()
+            .map(|(target, _, message)| (target.as_str(), message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (PAGER, "Page 2: cache miss"),
+                (PAGER, "Page 2: read from the file"),
+                (RECORD, "Page 2: decoded TableInteriorPage with 13 cells"),
+                (PAGER, "Page 2: cache hit"),
+            ]
+        );
+
+        let mut database = Database::open(temp_copy(EMPTY_TABLE_DB)).unwrap();
+        let (_, logs) = capture_logs(|| {
+            for id in 1..=50 {
+                let values = vec![
+                    Value::Null,
+                    Value::Text(format!("item-{}", id)),
+                    Value::Null,
+                    Value::Null,
+                ];
+                database.insert("items", values).unwrap();
+            }
+        });
+        assert!(
+            logs.iter()
+                .any(|(target, _, message)| target == BTREE
+                    && message.ends_with("split into 2 pages"))
+        );
+        assert!(logs
+            .iter()
+            .any(|(target, _, message)| target == PAGER && message.starts_with("Committing")));
     }
 }
@@ -0,0 +1,238 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use super::file_structures::BTreePage;
+
+/*
+* Parsed pages kept around so reading them again does not go to the file, with room for a fixed
+* number of pages.
+*
+* When it is full the least recently used page makes room. Pages are handed out as Arcs, and a page
+* somebody still holds on to, a cursor walking over it for example, is pinned: throwing it out would
+* not free anything and reading it again would only make a second copy. Pinned pages are skipped
+* when evicting, and when every page is pinned the cache grows past its capacity until some of them
+* are let go.
+*/
+#[derive(Debug)]
+pub struct PageCache {
+    capacity: usize,
+    // Page and the tick it was last used at.
+    pages: HashMap<usize, (Arc<BTreePage>, u64)>,
+    // Pages by the tick they were last used at, the least recently used first.
+    usage: BTreeMap<u64, usize>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> PageCache {
+        PageCache {
+            capacity: capacity.max(1),
+            pages: HashMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Changes the capacity, evicting pages right away if the cache holds more than that now.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // Looks up a page, counting the hit or miss and marking it as just used.
+    pub fn get(&mut self, page_index: usize) -> Option<Arc<BTreePage>> {
+        let tick = self.next_tick();
+        let Some((page, last_used)) = self.pages.get_mut(&page_index) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.usage.remove(last_used);
+        self.usage.insert(tick, page_index);
+        *last_used = tick;
+        self.stats.hits += 1;
+        Some(page.clone())
+    }
+
+    pub fn insert(&mut self, page_index: usize, page: Arc<BTreePage>) {
+        self.remove(page_index);
+        let tick = self.next_tick();
+        self.pages.insert(page_index, (page, tick));
+        self.usage.insert(tick, page_index);
+        self.evict();
+    }
+
+    pub fn remove(&mut self, page_index: usize) {
+        if let Some((_, last_used)) = self.pages.remove(&page_index) {
+            self.usage.remove(&last_used);
+        }
+    }
+
+    // Evicts the least recently used pages that are not pinned until the cache fits again.
+    fn evict(&mut self) {
+        if self.pages.len() <= self.capacity {
+            return;
+        }
+
+        let mut evicted = Vec::new();
+        let mut excess = self.pages.len() - self.capacity;
+        for (&last_used, &page_index) in self.usage.iter() {
+            if excess == 0 {
+                break;
+            }
+            let (page, _) = &self.pages[&page_index];
+            if Arc::strong_count(page) > 1 {
+                continue;
+            }
+            evicted.push((last_used, page_index));
+            excess -= 1;
+        }
+
+        for (last_used, page_index) in evicted {
+            self.usage.remove(&last_used);
+            self.pages.remove(&page_index);
+            self.stats.evictions += 1;
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::page::cursor::BTreeCursor;
+    use crate::page::file_structures::{BTreePage, BTreePageHeader, PageType};
+    use crate::page::page_cache::{CacheStats, PageCache};
+    use crate::page::Database;
+
+    const MULTI_PAGE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/multi_page.db");
+
+    fn page() -> Arc<BTreePage> {
+        Arc::new(BTreePage {
+            header: BTreePageHeader {
+                page_type: PageType::TableLeafPage,
+                first_freeblock_offset: 0,
+                cell_count: 0,
+                cell_content_area: 0,
+                number_of_fragmented_free_bytes: 0,
+                right_most_pointer: None,
+            },
+            cells: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn evicts_least_recently_used_test() {
+        let mut cache = PageCache::new(3);
+        for page_index in 1..=3 {
+            cache.insert(page_index, page());
+        }
+        // Using page 1 makes page 2 the least recently used one.
+        assert!(cache.get(1).is_some());
+        cache.insert(4, page());
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 1,
+                evictions: 1
+            }
+        );
+
+        cache.set_capacity(1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(3).is_some());
+        assert_eq!(cache.stats().evictions, 3);
+    }
+
+    #[test]
+    fn pinned_pages_are_not_evicted_test() {
+        let mut cache = PageCache::new(2);
+        cache.insert(1, page());
+        let pinned = cache.get(1).unwrap();
+        cache.insert(2, page());
+        cache.insert(3, page());
+        // Page 1 is the least recently used, but still held on to.
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(2).is_none());
+
+        // With every page pinned the cache goes over its capacity.
+        let others: Vec<_> = (4..=6)
+            .map(|page_index| {
+                let pinned = page();
+                cache.insert(page_index, pinned.clone());
+                pinned
+            })
+            .collect();
+        assert_eq!(cache.len(), 4);
+
+        drop(pinned);
+        drop(others);
+        cache.insert(7, page());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn pager_cache_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        // The header leaves the cache size at SQLite's default of 2000 KiB.
+        let capacity = database.pager.page_cache.read().unwrap().capacity();
+        assert_eq!(capacity, 2000 * 1024 / 512);
+
+        database.pager.set_cache_capacity(4);
+        let root_page = database.get_table("worms").unwrap().root_page;
+        let leaf_count = database.pager.read_page(root_page).unwrap().cells.len() as u64 + 1;
+
+        for _ in 0..2 {
+            let stats = database.pager.cache_stats();
+            let mut cursor = BTreeCursor::new(root_page);
+            let mut more = cursor.first(&mut database.pager).unwrap();
+            while more {
+                more = cursor.next(&mut database.pager).unwrap();
+            }
+            drop(cursor);
+
+            // The cursor keeps the root page pinned while the leaves come and go, so every leaf is
+            // a miss on both scans and the root page never is.
+            let new_stats = database.pager.cache_stats();
+            assert_eq!(new_stats.misses - stats.misses, leaf_count);
+            assert!(new_stats.evictions - stats.evictions >= leaf_count - 4);
+            assert!(database.pager.page_cache.read().unwrap().len() <= 4);
+        }
+    }
+}
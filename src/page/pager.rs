@@ -1,14 +1,20 @@
-use anyhow::{Ok, Result};
 use std::{
     collections::HashMap,
-    fs::File,
     sync::{Arc, RwLock},
-    usize,
 };
 
+use log::{debug, info, trace};
+
 use crate::page::file_structures;
 
-use super::file_structures::{BTreePage, DBHeader};
+use super::errors::{DBError, Result};
+use super::file_structures::{BTreePage, DBHeader, TextDecoder};
+use super::freelist;
+use super::journal;
+use super::logging::PAGER;
+use super::page_cache::{CacheStats, PageCache};
+use super::vfs::{LockLevel, Vfs, VfsFile};
+use super::wal::{self, CheckpointMode, Wal};
 
 // TODO: Not sure where to put the page cache, for now keeping it both at Pager and the top level DB
 // struct.
@@ -18,30 +24,499 @@ use super::file_structures::{BTreePage, DBHeader};
 #[derive(Debug)]
 pub struct Pager {
     pub db_header: DBHeader,
-    pub file: File,
-    pub page_cache: Arc<RwLock<HashMap<usize, Arc<BTreePage>>>>,
+    pub vfs: Arc<dyn Vfs>,
+    pub file: Box<dyn VfsFile>,
+    pub page_cache: Arc<RwLock<PageCache>>,
+    // Pages changed by the current write that have not made it to the file yet. Reads see these
+    // before the file.
+    pub dirty_pages: HashMap<usize, Vec<u8>>,
+    // Where the rollback journal goes while a commit is being written.
+    pub journal_path: String,
+    // The WAL of a database in WAL mode. Its frames take the place of the pages in the file.
+    pub wal: Option<Wal>,
+    // Where the WAL goes, it is only created with the first commit in WAL mode.
+    pub wal_path: String,
+    pub journal_mode: JournalMode,
+    // The change counter in the header of the file as of the last read. Another connection that
+    // commits to the file changes it, commits to the WAL do not.
+    pub file_change_counter: u32,
+    // Text that is not valid in the encoding of the database is read with U+FFFD in place of the
+    // bad bytes instead of failing the read.
+    pub lossy_text: bool,
+}
+
+// How commits are made atomic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JournalMode {
+    // Original pages go to a rollback journal that is deleted once the commit is done.
+    Delete,
+    // Changed pages are appended to the WAL and only copied into the file by a checkpoint.
+    Wal,
 }
 
+// SQLite locks the bytes starting at 1GiB, the page holding them is never used for data.
+const PENDING_BYTE: usize = 0x4000_0000;
+
 impl Pager {
     // Man, lifetimes are tough.
     // TODO: Look into why this is wrong. It's likely a lifetime issue. Maybe page cache stores a
     // reference and not the page? Since the page cache itself is Arc wrapped should the page
     // reference be wrapped in Arc as well?
     pub fn read_page(&mut self, page_index: usize) -> Result<Arc<BTreePage>> {
-        println!("Pager: Trying to read page at index: {}", page_index);
-        let mut page_cache = self.page_cache.write().unwrap();
-        if let Some(page) = page_cache.get(&page_index) {
-            println!("Got page {} from cache.", page_index);
+        if let Some(page) = self.page_cache.write().unwrap().get(page_index) {
+            trace!(target: PAGER, "Page {}: cache hit", page_index);
             return Ok(page.clone());
         }
+        trace!(target: PAGER, "Page {}: cache miss", page_index);
 
+        let page = self.read_raw_page(page_index)?;
+        let db_header = self.db_header.clone();
+        let text_decoder = TextDecoder {
+            encoding: db_header.text_encoding(),
+            lossy: self.lossy_text,
+        };
         let page = file_structures::read_page(
-            &mut self.file,
-            self.db_header.page_size as usize,
+            &page,
             page_index,
+            &db_header,
+            &text_decoder,
+            &mut |overflow_page| self.read_raw_page(overflow_page as usize),
         )?;
         let arc_page = Arc::new(page);
-        page_cache.insert(page_index, arc_page.clone());
+        self.page_cache
+            .write()
+            .unwrap()
+            .insert(page_index, arc_page.clone());
         Ok(arc_page)
     }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.page_cache.read().unwrap().stats()
+    }
+
+    // Changes how many pages the page cache holds, like `PRAGMA cache_size` with a positive value.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.page_cache.write().unwrap().set_capacity(capacity);
+    }
+
+    // Reads the raw bytes of a page, used for pages that are not b-tree pages like overflow pages.
+    pub fn read_raw_page(&mut self, page_index: usize) -> Result<Vec<u8>> {
+        // Page 1 is read before the page count is known.
+        if page_index == 0 || (page_index > 1 && page_index > self.page_count()) {
+            return Err(DBError::corrupt(format!(
+                "Page {} does not exist, the database has {} pages",
+                page_index,
+                self.page_count()
+            )));
+        }
+        if let Some(page) = self.dirty_pages.get(&page_index) {
+            trace!(target: PAGER, "Page {}: read from the dirty pages", page_index);
+            return Ok(page.clone());
+        }
+        if let Some(wal) = self.wal.as_mut() {
+            if let Some(page) = wal.read_page(page_index)? {
+                trace!(target: PAGER, "Page {}: read from the WAL", page_index);
+                return Ok(page);
+            }
+        }
+
+        let page_size = self.db_header.page_size as usize;
+        let offset = ((page_index - 1) * page_size) as u64;
+        if let Some(page) = self.file.fetch(offset, page_size)? {
+            trace!(target: PAGER, "Page {}: read from the memory map", page_index);
+            return Ok(page.to_vec());
+        }
+        trace!(target: PAGER, "Page {}: read from the file", page_index);
+        file_structures::read_page_bytes(self.file.as_mut(), page_size, page_index)
+    }
+
+    // Pages already read keep the text the way they were decoded, so they go.
+    pub fn set_lossy_text(&mut self, lossy_text: bool) {
+        self.lossy_text = lossy_text;
+        self.clear_page_cache();
+    }
+
+    // Reads the first `size` bytes of the file through a memory map, like SQLite's `PRAGMA
+    // mmap_size`. Pages past that, and every page with a VFS that can not map files, are read the
+    // usual way. 0, the default, turns it off.
+    pub fn set_mmap_size(&mut self, size: u64) -> Result<()> {
+        self.file.set_mmap_size(size)
+    }
+
+    // Number of pages in the database, pages allocated by the current write included.
+    pub fn page_count(&self) -> usize {
+        self.db_header.db_size_in_pages as usize
+    }
+
+    // Replaces the contents of a page. The change stays in memory until `commit`.
+    pub fn write_page(&mut self, page_index: usize, page: Vec<u8>) {
+        self.page_cache.write().unwrap().remove(page_index);
+        self.dirty_pages.insert(page_index, page);
+    }
+
+    // Hands out a zeroed page, reusing a page from the freelist when there is one and adding one to
+    // the end of the database otherwise.
+    pub fn allocate_page(&mut self) -> Result<usize> {
+        if let Some(page_index) = freelist::allocate_page(self)? {
+            debug!(target: PAGER, "Page {}: allocated from the freelist", page_index);
+            return Ok(page_index);
+        }
+
+        let page_size = self.db_header.page_size as usize;
+        let mut page_index = self.page_count() + 1;
+        if page_index == PENDING_BYTE / page_size + 1 {
+            page_index += 1;
+        }
+
+        self.db_header.db_size_in_pages = page_index as u32;
+        self.write_page(page_index, vec![0; page_size]);
+        debug!(target: PAGER, "Page {}: allocated at the end of the file", page_index);
+        Ok(page_index)
+    }
+
+    /*
+     * Starts a read, the database stays the way it is until `end_transaction`: other connections
+     * can read along but not commit. Whatever they committed since our last read is thrown out of
+     * the page cache first, the change counter in the header of the file tells whether there was
+     * anything. Returns whether there was, in which case the schema may have changed as well.
+     */
+    pub fn begin_read(&mut self) -> Result<bool> {
+        if !self.file.lock(LockLevel::Shared)? {
+            return Err(DBError::Busy(
+                "Another connection is writing to the database".to_string(),
+            ));
+        }
+        let result = self.refresh();
+        if result.is_err() {
+            self.file.lock(LockLevel::None)?;
+        }
+        result
+    }
+
+    // Starts a write, a read that also keeps other connections from writing until `commit` or
+    // `rollback`.
+    pub fn begin_write(&mut self) -> Result<bool> {
+        let changed = self.begin_read()?;
+        if !self.file.lock(LockLevel::Reserved)? {
+            self.file.lock(LockLevel::None)?;
+            return Err(DBError::Busy(
+                "Another connection is writing to the database".to_string(),
+            ));
+        }
+        Ok(changed)
+    }
+
+    // Ends a read, or a write that had nothing to commit.
+    pub fn end_transaction(&mut self) -> Result<()> {
+        self.file.lock(LockLevel::None)?;
+        Ok(())
+    }
+
+    // Needs the shared lock, a hot journal is rolled back before anything else.
+    fn refresh(&mut self) -> Result<bool> {
+        let recovered =
+            journal::recover(self.vfs.as_ref(), self.file.as_mut(), &self.journal_path)?;
+        let wal_changed = self.refresh_wal()?;
+        let file_change_counter = self.read_file_change_counter()?;
+        if !recovered && !wal_changed && file_change_counter == self.file_change_counter {
+            return Ok(false);
+        }
+        debug!(target: PAGER, "The database changed since the last read, emptying the page cache");
+        self.file_change_counter = file_change_counter;
+        self.clear_page_cache();
+        self.reload_header()?;
+        self.detect_journal_mode();
+        Ok(true)
+    }
+
+    // Another connection may have created the WAL, added to it, started it over or deleted it.
+    fn refresh_wal(&mut self) -> Result<bool> {
+        if !self.vfs.exists(&self.wal_path)? {
+            return Ok(self.wal.take().is_some());
+        }
+        match self.wal.as_mut() {
+            Some(wal) => wal.refresh(),
+            None => {
+                let page_size = self.db_header.page_size as usize;
+                self.wal = Wal::open(self.vfs.as_ref(), &self.wal_path, page_size)?;
+                Ok(self.wal.is_some())
+            }
+        }
+    }
+
+    fn read_file_change_counter(&mut self) -> Result<u32> {
+        let mut change_counter = [0; 4];
+        self.file.read_exact_at(24, &mut change_counter)?;
+        Ok(u32::from_be_bytes(change_counter))
+    }
+
+    // A WAL with frames in it is used even when the header does not ask for WAL mode, the same as
+    // SQLite does.
+    pub fn detect_journal_mode(&mut self) {
+        self.journal_mode = if self.db_header.write_version == 2
+            || self.wal.as_ref().is_some_and(|wal| !wal.is_empty())
+        {
+            JournalMode::Wal
+        } else {
+            JournalMode::Delete
+        };
+    }
+
+    fn clear_page_cache(&mut self) {
+        let capacity = self.page_cache.read().unwrap().capacity();
+        *self.page_cache.write().unwrap() = PageCache::new(capacity);
+    }
+
+    /*
+     * Writes every dirty page and the updated header to the file.
+     *
+     * The original content of the pages goes to the rollback journal first. Until the journal is
+     * deleted again at the very end, a crash leaves a hot journal behind that undoes the partly
+     * written commit the next time the database is opened.
+     *
+     * In WAL mode the pages are appended to the WAL instead and the file is left alone.
+     */
+    pub fn commit(&mut self) -> Result<()> {
+        if self.dirty_pages.is_empty() {
+            return Ok(());
+        }
+
+        debug!(
+            target: PAGER,
+            "Committing {} pages in {:?} mode",
+            self.dirty_pages.len(),
+            self.journal_mode
+        );
+        // Appending to the WAL can be done with readers around, see `wal`.
+        match self.journal_mode {
+            JournalMode::Delete => self.lock(LockLevel::Exclusive)?,
+            JournalMode::Wal => self.lock(LockLevel::Reserved)?,
+        }
+        let result = self.write_dirty_pages();
+        self.file.lock(LockLevel::None)?;
+        result
+    }
+
+    // Busy when another connection holds a lock that is in the way.
+    fn lock(&mut self, level: LockLevel) -> Result<()> {
+        if !self.file.lock(level)? {
+            return Err(DBError::Busy(
+                "Another connection is using the database".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_dirty_pages(&mut self) -> Result<()> {
+        self.db_header.change_counter = self.db_header.change_counter.wrapping_add(1);
+        self.db_header.version_valid_for = self.db_header.change_counter;
+        let mut first_page = self.read_raw_page(1)?;
+        file_structures::write_db_header(&self.db_header, &mut first_page);
+        self.write_page(1, first_page);
+
+        let page_size = self.db_header.page_size as usize;
+        let mut dirty_pages: Vec<(usize, Vec<u8>)> = self.dirty_pages.drain().collect();
+        dirty_pages.sort_by_key(|(page_index, _)| *page_index);
+
+        if self.journal_mode == JournalMode::Wal {
+            if self.wal.is_none() {
+                self.wal = Some(Wal::create(self.vfs.as_ref(), &self.wal_path, page_size)?);
+            }
+            let db_size_in_pages = self.db_header.db_size_in_pages;
+            let wal = self.wal.as_mut().unwrap();
+            return wal.append_transaction(&dirty_pages, db_size_in_pages);
+        }
+
+        let original_page_count = self.file.size()? as usize / page_size;
+        let mut original_pages = Vec::new();
+        for (page_index, _) in dirty_pages.iter() {
+            if *page_index <= original_page_count {
+                let page =
+                    file_structures::read_page_bytes(self.file.as_mut(), page_size, *page_index)?;
+                original_pages.push((*page_index, page));
+            }
+        }
+        journal::write_journal(
+            self.vfs.as_ref(),
+            &self.journal_path,
+            page_size,
+            original_page_count,
+            &original_pages,
+        )?;
+
+        for (page_index, page) in dirty_pages {
+            self.file
+                .write_at(((page_index - 1) * page_size) as u64, &page)?;
+        }
+        self.file.sync()?;
+        self.file_change_counter = self.db_header.change_counter;
+        journal::delete_journal(self.vfs.as_ref(), &self.journal_path)
+    }
+
+    /*
+     * Copies the WAL back into the database file. Returns the number of frames in the WAL and how
+     * many of them are in the database file now.
+     *
+     * That would pull pages out from under readers still reading the frames from before the last
+     * commit, see `wal`, so it takes the exclusive lock. PASSIVE does nothing when other
+     * connections are in the way, the other modes are busy then.
+     */
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> Result<(usize, usize)> {
+        if !self.dirty_pages.is_empty() {
+            return Err(DBError::Misuse(
+                "Can not checkpoint in the middle of a write".to_string(),
+            ));
+        }
+        let locked = match self.begin_read() {
+            Ok(_) => self.file.lock(LockLevel::Exclusive),
+            Err(DBError::Busy(_)) => Ok(false),
+            Err(err) => return Err(err),
+        };
+        let result = match locked {
+            Ok(true) => self.checkpoint_locked(mode),
+            Ok(false) if mode == CheckpointMode::Passive => Ok(self
+                .wal
+                .as_ref()
+                .map_or((0, 0), |wal| (wal.frame_count, wal.checkpointed_frames))),
+            Ok(false) => Err(DBError::Busy(
+                "Another connection is using the database".to_string(),
+            )),
+            Err(err) => Err(err),
+        };
+        self.file.lock(LockLevel::None)?;
+        result
+    }
+
+    // Needs the exclusive lock.
+    fn checkpoint_locked(&mut self, mode: CheckpointMode) -> Result<(usize, usize)> {
+        let Some(wal) = self.wal.as_mut() else {
+            return Ok((0, 0));
+        };
+        let frame_count = wal.frame_count;
+        let page_size = self.db_header.page_size as usize;
+        if wal.checkpointed_frames < frame_count {
+            debug!(
+                target: PAGER,
+                "Checkpointing {} WAL frames",
+                frame_count - wal.checkpointed_frames
+            );
+            for page_index in wal.pages() {
+                let page = wal.read_page(page_index)?.unwrap();
+                self.file
+                    .write_at(((page_index - 1) * page_size) as u64, &page)?;
+            }
+            self.file
+                .truncate(wal.db_size_in_pages as u64 * page_size as u64)?;
+            self.file.sync()?;
+            wal.checkpointed_frames = frame_count;
+        }
+        match mode {
+            CheckpointMode::Passive | CheckpointMode::Full => {}
+            CheckpointMode::Restart => wal.restart(false)?,
+            CheckpointMode::Truncate => wal.restart(true)?,
+        }
+        self.file_change_counter = self.read_file_change_counter()?;
+        Ok((frame_count, frame_count))
+    }
+
+    /*
+     * Switches between rollback journal and WAL mode. The file format versions in the header say
+     * which one a database uses, 1 for a rollback journal and 2 for WAL, and SQLite goes by them as
+     * well. Leaving WAL mode checkpoints everything and deletes the WAL.
+     */
+    pub fn set_journal_mode(&mut self, journal_mode: JournalMode) -> Result<()> {
+        if !self.dirty_pages.is_empty() {
+            return Err(DBError::Misuse(
+                "Can not change the journal mode in the middle of a write".to_string(),
+            ));
+        }
+        self.begin_write()?;
+        if let Err(err) = self.switch_journal_mode(journal_mode) {
+            self.rollback()?;
+            return Err(err);
+        }
+        self.end_transaction()
+    }
+
+    fn switch_journal_mode(&mut self, journal_mode: JournalMode) -> Result<()> {
+        if journal_mode == self.journal_mode {
+            return Ok(());
+        }
+        let version = match journal_mode {
+            JournalMode::Delete => {
+                self.lock(LockLevel::Exclusive)?;
+                self.checkpoint_locked(CheckpointMode::Truncate)?;
+                self.wal = None;
+                wal::delete_wal(self.vfs.as_ref(), &self.wal_path)?;
+                1
+            }
+            JournalMode::Wal => 2,
+        };
+
+        // The header change itself is a commit through the rollback journal.
+        self.journal_mode = JournalMode::Delete;
+        self.db_header.write_version = version;
+        self.db_header.read_version = version;
+        let first_page = self.read_raw_page(1)?;
+        self.write_page(1, first_page);
+        self.commit()?;
+        self.journal_mode = journal_mode;
+        info!(target: PAGER, "Journal mode is now {:?}", journal_mode);
+        Ok(())
+    }
+
+    // Throws away every change since the last commit. A commit that failed halfway may have
+    // written part of its pages to the file already, its journal puts the originals back.
+    pub fn rollback(&mut self) -> Result<()> {
+        let mut page_cache = self.page_cache.write().unwrap();
+        for page_index in self.dirty_pages.keys() {
+            page_cache.remove(*page_index);
+        }
+        drop(page_cache);
+        debug!(
+            target: PAGER,
+            "Rolling back {} dirty pages",
+            self.dirty_pages.len()
+        );
+        self.dirty_pages.clear();
+
+        if !self.file.lock(LockLevel::Shared)? {
+            return Err(DBError::Busy(
+                "Another connection is writing to the database".to_string(),
+            ));
+        }
+        let result = journal::recover(self.vfs.as_ref(), self.file.as_mut(), &self.journal_path)
+            .and_then(|recovered| {
+                if recovered {
+                    self.clear_page_cache();
+                }
+                self.reload_header()
+            });
+        self.file.lock(LockLevel::None)?;
+        result
+    }
+
+    // Reads the header again from page 1, which comes from the WAL if it has a newer version.
+    pub fn reload_header(&mut self) -> Result<()> {
+        let first_page = self.read_raw_page(1)?;
+        self.db_header = file_structures::parse_db_header(&first_page)?;
+        self.refresh_page_count()
+    }
+
+    // The page count in the header is only trusted when `version_valid_for` matches the change
+    // counter. Older writers did not keep it up to date, the file size is the truth then.
+    // With a WAL the last commit in it has the page count.
+    pub fn refresh_page_count(&mut self) -> Result<()> {
+        if let Some(wal) = self.wal.as_ref().filter(|wal| !wal.is_empty()) {
+            self.db_header.db_size_in_pages = wal.db_size_in_pages;
+            return Ok(());
+        }
+
+        let header = &mut self.db_header;
+        if header.db_size_in_pages == 0 || header.version_valid_for != header.change_counter {
+            let file_size = self.file.size()? as usize;
+            header.db_size_in_pages = (file_size / header.page_size as usize) as u32;
+        }
+        Ok(())
+    }
 }
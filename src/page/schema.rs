@@ -0,0 +1,406 @@
+use crate::sql::ast::{
+    ColumnConstraintKind, CreateIndex, CreateTable, IndexedColumn, Order, Statement,
+    TableConstraintKind, TableDefinition,
+};
+use crate::sql::parse_statement;
+
+use super::errors::{DBError, Result};
+use super::{Column, ColumnType, Index, IndexColumn, Table};
+
+// A column of a PRIMARY KEY or UNIQUE constraint: its name, COLLATE and whether it is DESC.
+type KeyColumn = (String, Option<String>, bool);
+
+/*
+* Builds a Table out of the `CREATE TABLE` statement stored in sqlite_master:
+*
+* CREATE [TEMP] TABLE [IF NOT EXISTS] [schema.]name (column-def, ..., [table-constraint, ...])
+*     [WITHOUT ROWID] [, STRICT]
+*
+* Only the column names, declared types, collations, primary key and UNIQUE constraints are picked
+* up, other constraints are ignored.
+*/
+pub fn parse_create_table(sql: &str, root_page: usize) -> Result<Table> {
+    let invalid = |reason: String| DBError::InvalidSchema(format!("{} in: {}", reason, sql));
+    let create_table = match parse_statement(sql) {
+        Ok(Statement::CreateTable(create_table)) => create_table,
+        Ok(_) => return Err(invalid("Not a CREATE TABLE".to_string())),
+        Err(err) => return Err(invalid(err.to_string())),
+    };
+    let CreateTable {
+        name,
+        definition:
+            TableDefinition::Columns {
+                columns: column_definitions,
+                constraints,
+                without_rowid,
+                ..
+            },
+        ..
+    } = create_table
+    else {
+        // SQLite writes the columns out for CREATE TABLE ... AS SELECT, this never makes it into
+        // sqlite_master.
+        return Err(invalid("CREATE TABLE without columns".to_string()));
+    };
+
+    let mut columns = Vec::new();
+    // Primary key columns and whether they were declared as `PRIMARY KEY DESC` on the column.
+    let mut primary_key: Vec<(String, bool)> = Vec::new();
+    // The PRIMARY KEY and UNIQUE constraints in the order they appear, and whether each is the
+    // primary key.
+    let mut keys: Vec<(Vec<KeyColumn>, bool)> = Vec::new();
+
+    for definition in column_definitions {
+        let collation =
+            definition
+                .constraints
+                .iter()
+                .find_map(|constraint| match &constraint.kind {
+                    ColumnConstraintKind::Collate(collation) => Some(collation.clone()),
+                    _ => None,
+                });
+        for constraint in definition.constraints.iter() {
+            let key_column = |descending| (definition.name.clone(), collation.clone(), descending);
+            match constraint.kind {
+                ColumnConstraintKind::PrimaryKey { order, .. } => {
+                    let descending = order == Some(Order::Desc);
+                    primary_key.push((definition.name.clone(), descending));
+                    keys.push((vec![key_column(descending)], true));
+                }
+                ColumnConstraintKind::Unique(_) => keys.push((vec![key_column(false)], false)),
+                _ => {}
+            }
+        }
+
+        let declared_type = definition
+            .type_name
+            .map(|type_name| type_name.to_string())
+            .unwrap_or_default();
+        columns.push(Column {
+            name: definition.name,
+            column_type: ColumnType::from_declared_type(&declared_type),
+            declared_type,
+            primary_key: false,
+            collation,
+        });
+    }
+
+    for constraint in constraints {
+        let (key, is_primary_key) = match constraint.kind {
+            TableConstraintKind::PrimaryKey { columns, .. } => (columns, true),
+            TableConstraintKind::Unique { columns, .. } => (columns, false),
+            _ => continue,
+        };
+        let mut key_columns = Vec::new();
+        for column in key.iter() {
+            let column_name = column
+                .column_name()
+                .ok_or_else(|| invalid("PRIMARY KEY or UNIQUE on an expression".to_string()))?;
+            if is_primary_key {
+                primary_key.push((column_name.to_string(), false));
+            }
+            key_columns.push((
+                column_name.to_string(),
+                column.collation().map(String::from),
+                column.order == Some(Order::Desc),
+            ));
+        }
+        keys.push((key_columns, is_primary_key));
+    }
+
+    for (key, _) in primary_key.iter() {
+        let column = columns
+            .iter_mut()
+            .find(|column| column.name.eq_ignore_ascii_case(key))
+            .ok_or_else(|| invalid(format!("No such column in PRIMARY KEY: {}", key)))?;
+        column.primary_key = true;
+    }
+
+    // A single INTEGER PRIMARY KEY column is an alias for the rowid, unless it is declared as
+    // `PRIMARY KEY DESC` in the column definition. Yes, that quirk is for real.
+    let rowid_alias = match primary_key.as_slice() {
+        [(key, false)] if !without_rowid => columns.iter().position(|column| {
+            column.name.eq_ignore_ascii_case(key)
+                && column.declared_type.eq_ignore_ascii_case("INTEGER")
+        }),
+        _ => None,
+    };
+
+    // The rowid is the primary key of a table with a rowid alias, and WITHOUT ROWID tables are
+    // stored in order of their primary key, neither needs an index for it. A constraint on the same
+    // columns as an earlier one shares its index.
+    let mut unique_keys: Vec<Vec<IndexColumn>> = Vec::new();
+    for (key, is_primary_key) in keys {
+        if is_primary_key && (rowid_alias.is_some() || without_rowid) {
+            continue;
+        }
+        let mut index_columns = Vec::new();
+        for (column_name, collation, descending) in key {
+            let position = columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(&column_name))
+                .ok_or_else(|| invalid(format!("No such column in key: {}", column_name)))?;
+            index_columns.push(IndexColumn {
+                column: Some(position),
+                expression: None,
+                descending,
+                collation: collation.or_else(|| columns[position].collation.clone()),
+            });
+        }
+        let duplicate = unique_keys.iter().any(|existing| {
+            existing.len() == index_columns.len()
+                && existing.iter().zip(index_columns.iter()).all(|(a, b)| {
+                    a.column == b.column && same_collation(&a.collation, &b.collation)
+                })
+        });
+        if !duplicate {
+            unique_keys.push(index_columns);
+        }
+    }
+
+    Ok(Table {
+        root_page,
+        name: name.name,
+        columns,
+        rowid_alias,
+        without_rowid,
+        unique_keys,
+    })
+}
+
+/*
+* Builds an Index out of its sqlite_master entry. Indexes made with
+*
+* CREATE [UNIQUE] INDEX [IF NOT EXISTS] [schema.]name ON table (indexed-column, ...) [WHERE expr]
+*
+* have that statement as their sql. The ones SQLite makes for PRIMARY KEY and UNIQUE constraints
+* have none, they are named sqlite_autoindex_<table>_<n> after the n-th constraint of the table.
+*/
+pub fn parse_index(
+    name: String,
+    table: &Table,
+    root_page: usize,
+    sql: Option<String>,
+) -> Result<Index> {
+    let Some(sql) = sql else {
+        let key = name
+            .rsplit('_')
+            .next()
+            .and_then(|number| number.parse::<usize>().ok())
+            .and_then(|number| table.unique_keys.get(number.checked_sub(1)?))
+            .ok_or_else(|| {
+                DBError::InvalidSchema(format!(
+                    "No PRIMARY KEY or UNIQUE constraint of table {} for index {}",
+                    table.name, name
+                ))
+            })?;
+        return Ok(Index {
+            name,
+            table_name: table.name.clone(),
+            root_page,
+            sql: None,
+            columns: key.clone(),
+            unique: true,
+            where_clause: None,
+        });
+    };
+
+    let invalid = |reason: String| DBError::InvalidSchema(format!("{} in: {}", reason, sql));
+    let create_index = match parse_statement(&sql) {
+        Ok(Statement::CreateIndex(create_index)) => create_index,
+        Ok(_) => return Err(invalid("Not a CREATE INDEX".to_string())),
+        Err(err) => return Err(invalid(err.to_string())),
+    };
+    let CreateIndex {
+        unique,
+        columns: indexed_columns,
+        where_clause,
+        ..
+    } = create_index;
+    let columns = indexed_columns
+        .iter()
+        .map(|indexed_column| index_column(indexed_column, table, &sql))
+        .collect();
+
+    Ok(Index {
+        name,
+        table_name: table.name.clone(),
+        root_page,
+        sql: Some(sql),
+        columns,
+        unique,
+        where_clause,
+    })
+}
+
+// A column of CREATE INDEX. Without a COLLATE of its own it is ordered by that of the column.
+fn index_column(indexed_column: &IndexedColumn, table: &Table, sql: &str) -> IndexColumn {
+    let column = indexed_column.column_name().and_then(|name| {
+        table
+            .columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+    });
+    let span = &indexed_column.expr.span;
+    IndexColumn {
+        column,
+        expression: match column {
+            Some(_) => None,
+            None => Some(sql[span.start..span.end].to_string()),
+        },
+        descending: indexed_column.order == Some(Order::Desc),
+        collation: indexed_column
+            .collation()
+            .map(String::from)
+            .or_else(|| column.and_then(|column| table.columns[column].collation.clone())),
+    }
+}
+
+fn same_collation(a: &Option<String>, b: &Option<String>) -> bool {
+    let name = |collation: &Option<String>| collation.as_deref().unwrap_or("BINARY").to_uppercase();
+    name(a) == name(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::schema::{parse_create_table, parse_index};
+    use crate::page::{ColumnType, IndexColumn};
+
+    #[test]
+    fn parse_create_table_test() {
+        let table = parse_create_table(
+            "CREATE TABLE SandWorm(\nid INTEGER PRIMARY KEY AUTOINCREMENT,\nsize_meters REAL NOT NULL,\nage_years INTEGER NOT NULL,\ndescription VARCHAR(1000),\nlast_sighted DATE\n)",
+            3,
+        )
+        .unwrap();
+        assert_eq!(table.name, "SandWorm");
+        assert_eq!(table.root_page, 3);
+        assert_eq!(table.rowid_alias, Some(0));
+        let columns: Vec<(&str, &str, ColumnType, bool)> = table
+            .columns
+            .iter()
+            .map(|column| {
+                (
+                    column.name.as_str(),
+                    column.declared_type.as_str(),
+                    column.column_type,
+                    column.primary_key,
+                )
+            })
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("id", "INTEGER", ColumnType::Integer, true),
+                ("size_meters", "REAL", ColumnType::Real, false),
+                ("age_years", "INTEGER", ColumnType::Integer, false),
+                ("description", "VARCHAR(1000)", ColumnType::Text, false),
+                ("last_sighted", "DATE", ColumnType::Numeric, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_create_table_primary_keys_test() {
+        // Untyped columns.
+        let table = parse_create_table("CREATE TABLE sqlite_sequence(name,seq)", 4).unwrap();
+        assert_eq!(table.columns.len(), 2);
+        assert_eq!(table.columns[0].column_type, ColumnType::Blob);
+        assert_eq!(table.rowid_alias, None);
+
+        // Table constraint primary key, quoted names and a multi word type.
+        let table = parse_create_table(
+            r#"CREATE TABLE IF NOT EXISTS "my table" ("the id" integer, [x] unsigned big int, CONSTRAINT pk PRIMARY KEY ("the id" ASC))"#,
+            5,
+        )
+        .unwrap();
+        assert_eq!(table.name, "my table");
+        assert_eq!(table.columns[0].name, "the id");
+        assert_eq!(table.columns[1].declared_type, "unsigned big int");
+        assert_eq!(table.rowid_alias, Some(0));
+
+        // Only INTEGER, and not INT, makes a rowid alias.
+        let table = parse_create_table("CREATE TABLE t(id INT PRIMARY KEY, v)", 6).unwrap();
+        assert!(table.columns[0].primary_key);
+        assert_eq!(table.rowid_alias, None);
+
+        let table =
+            parse_create_table("CREATE TABLE t(id INTEGER PRIMARY KEY DESC, v)", 6).unwrap();
+        assert_eq!(table.rowid_alias, None);
+
+        let table =
+            parse_create_table("CREATE TABLE t(a INTEGER, b TEXT, PRIMARY KEY(a, b))", 6).unwrap();
+        assert!(table.columns.iter().all(|column| column.primary_key));
+        assert_eq!(table.rowid_alias, None);
+
+        let table = parse_create_table(
+            "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT DEFAULT 'a, b' CHECK(length(v) > 0)) WITHOUT ROWID",
+            6,
+        )
+        .unwrap();
+        assert_eq!(table.columns.len(), 2);
+        assert!(table.without_rowid);
+        assert_eq!(table.rowid_alias, None);
+    }
+
+    #[test]
+    fn parse_index_test() {
+        let table = parse_create_table(
+            "CREATE TABLE notes(id INTEGER PRIMARY KEY, author TEXT COLLATE NOCASE, score INTEGER, \
+             code TEXT UNIQUE, UNIQUE(score, author COLLATE BINARY))",
+            2,
+        )
+        .unwrap();
+        let column = |column, descending, collation: Option<&str>| IndexColumn {
+            column,
+            expression: None,
+            descending,
+            collation: collation.map(String::from),
+        };
+
+        // The columns take the collation of the table's column unless they have one of their own.
+        let index = parse_index(
+            "notes_author_score".to_string(),
+            &table,
+            4,
+            Some("CREATE INDEX notes_author_score ON notes(author, score DESC, lower(code) COLLATE RTRIM)".to_string()),
+        )
+        .unwrap();
+        assert_eq!(index.table_name, "notes");
+        assert_eq!(index.root_page, 4);
+        assert!(!index.unique && index.where_clause.is_none());
+        assert_eq!(
+            index.columns,
+            vec![
+                column(Some(1), false, Some("NOCASE")),
+                column(Some(2), true, None),
+                IndexColumn {
+                    expression: Some("lower(code) COLLATE RTRIM".to_string()),
+                    ..column(None, false, Some("RTRIM"))
+                },
+            ]
+        );
+
+        let index = parse_index(
+            "notes_high".to_string(),
+            &table,
+            5,
+            Some("CREATE UNIQUE INDEX notes_high ON notes(score) WHERE score > 50".to_string()),
+        )
+        .unwrap();
+        assert!(index.unique && index.where_clause.is_some());
+
+        // Indexes of UNIQUE constraints have no SQL, and are numbered in the order of the
+        // constraints.
+        let index = parse_index("sqlite_autoindex_notes_2".to_string(), &table, 6, None).unwrap();
+        assert!(index.unique);
+        assert_eq!(
+            index.columns,
+            vec![
+                column(Some(2), false, None),
+                column(Some(1), false, Some("BINARY")),
+            ]
+        );
+        assert!(parse_index("sqlite_autoindex_notes_3".to_string(), &table, 7, None).is_err());
+    }
+}
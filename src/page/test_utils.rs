@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Copies a fixture to a new file in the temp directory, for tests that write to the database.
+pub fn temp_copy(fixture: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "sand-test-{}-{}.db",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::copy(fixture, &path).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+// A log record as `capture_logs` hands it out: target, level and message.
+pub type LogRecord = (String, Level, String);
+
+thread_local! {
+    // Records logged on this thread, None while nobody is capturing them.
+    static CAPTURED_LOGS: RefCell<Option<Vec<LogRecord>>> = const { RefCell::new(None) };
+}
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        CAPTURED_LOGS.with(|logs| {
+            if let Some(logs) = logs.borrow_mut().as_mut() {
+                logs.push((
+                    record.target().to_string(),
+                    record.level(),
+                    record.args().to_string(),
+                ));
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+
+// Runs `f` and returns what it logged. Every test runs on a thread of its own, so tests running at
+// the same time do not see each other's records.
+pub fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<LogRecord>) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&CAPTURE_LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+
+    CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let logs = CAPTURED_LOGS.with(|logs| logs.borrow_mut().take());
+    (result, logs.unwrap())
+}
+
+/*
+* A sqlite3 shell in a process of its own, for tests that check we get along with SQLite on the same
+* file. Its locks are held by that process, the way they would be by any other program using
+* SQLite. The shell has to be on the PATH, it is also what made the fixtures.
+*/
+pub struct Sqlite3 {
+    process: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+const SQLITE3_DONE: &str = "-- done --";
+
+impl Sqlite3 {
+    pub fn open(path: &str) -> Sqlite3 {
+        // Errors go to stdout as well so they come in order with the rows.
+        let mut process = Command::new("sh")
+            .arg("-c")
+            .arg("exec sqlite3 \"$0\" 2>&1")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("sqlite3 needs to be on the PATH");
+        let stdin = process.stdin.take().unwrap();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        Sqlite3 {
+            process,
+            stdin,
+            stdout,
+        }
+    }
+
+    // Runs the statements and waits for them to finish. Returns the lines they printed, rows and
+    // errors alike.
+    pub fn run(&mut self, sql: &str) -> Vec<String> {
+        writeln!(self.stdin, "{}\n.print {}", sql, SQLITE3_DONE).unwrap();
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            assert!(
+                self.stdout.read_line(&mut line).unwrap() > 0,
+                "sqlite3 quit, it printed: {:?}",
+                lines
+            );
+            let line = line.trim_end().to_string();
+            if line == SQLITE3_DONE {
+                return lines;
+            }
+            lines.push(line);
+        }
+    }
+}
+
+impl Drop for Sqlite3 {
+    fn drop(&mut self) {
+        let _ = writeln!(self.stdin, ".quit");
+        let _ = self.process.wait();
+    }
+}
@@ -0,0 +1,647 @@
+use super::errors::{DBError, Result};
+use memmap2::{Mmap, MmapOptions};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    os::unix::io::AsRawFd,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/*
+* Everything the database does with files goes through a Vfs, the same idea as SQLite's VFS. The
+* OS one reads and writes real files, the memory one keeps files in memory so databases can live
+* without a disk, and wrapping either one is how I/O gets traced or made to fail on purpose.
+*/
+pub trait Vfs: Debug + Send + Sync {
+    fn open(&self, path: &str, mode: OpenMode) -> Result<Box<dyn VfsFile>>;
+    // Deleting a file that does not exist is not an error.
+    fn delete(&self, path: &str) -> Result<()>;
+    fn exists(&self, path: &str) -> Result<bool>;
+    // Syncs the directory `path` is in, so creating or deleting the file survives losing power.
+    // Syncing the file only takes care of what is in it.
+    fn sync_directory(&self, path: &str) -> Result<()>;
+}
+
+pub trait VfsFile: Debug + Send {
+    // Reads into `buffer` starting at `offset`, returns how many bytes there were to read. That is
+    // less than the buffer only at the end of the file.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize>;
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> Result<()>;
+    // Returns once everything written so far is on disk.
+    fn sync(&mut self) -> Result<()>;
+    fn truncate(&mut self, size: u64) -> Result<()>;
+    fn size(&self) -> Result<u64>;
+    // Moves the lock on the file to `level`, returns false if another connection holds a lock that
+    // is in the way. The lock stays where it was then.
+    fn lock(&mut self, level: LockLevel) -> Result<bool>;
+    // Whether this or another connection holds a RESERVED lock or more, i.e. is writing.
+    fn check_reserved_lock(&mut self) -> Result<bool>;
+    fn is_read_only(&self) -> bool;
+
+    // Lets `fetch` read up to the first `size` bytes of the file through a memory map, 0 turns that
+    // off. Files that can not be mapped ignore it.
+    fn set_mmap_size(&mut self, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    // The `len` bytes at `offset` straight from the memory map, None when they are not mapped and
+    // have to be read with `read_at`.
+    fn fetch(&mut self, _offset: u64, _len: usize) -> Result<Option<&[u8]>> {
+        Ok(None)
+    }
+
+    // Same as `read_at` but fails unless the whole buffer gets filled.
+    fn read_exact_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let read = self.read_at(offset, buffer)?;
+        if read < buffer.len() {
+            return Err(DBError::Io(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Wanted {} bytes at offset {}, only {} are there",
+                    buffer.len(),
+                    offset,
+                    read
+                ),
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenMode {
+    ReadOnly,
+    // Read and write when allowed, read only otherwise, `VfsFile::is_read_only` tells which one it
+    // became.
+    ReadWrite,
+    // Read and write, creating the file if there is none.
+    Create,
+}
+
+// The locks SQLite takes on a database file. SQLite also has PENDING between RESERVED and
+// EXCLUSIVE, which is only ever held on the way to EXCLUSIVE so it is not a level of its own here.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum LockLevel {
+    None,
+    // Any number of connections can read.
+    Shared,
+    // One connection is getting ready to write, others can still read but not write.
+    Reserved,
+    // One connection writes, nobody else holds any lock.
+    Exclusive,
+}
+
+// Files on disk, through std::fs.
+#[derive(Debug, Default)]
+pub struct OsVfs;
+
+#[derive(Debug)]
+pub struct OsFile {
+    file: File,
+    read_only: bool,
+    mmap: Option<Mmap>,
+    mmap_size: u64,
+    lock: LockLevel,
+}
+
+/*
+* SQLite locks a database by locking bytes of the file from 1GiB on, whether or not the file is that
+* big. A shared lock is a read lock on the SHARED_SIZE bytes of the shared range, RESERVED is a
+* write lock on the reserved byte, and EXCLUSIVE a write lock on the pending byte and the shared
+* range. A connection on its way to EXCLUSIVE write locks the pending byte first, which keeps new
+* readers out, since they need to read lock it for a moment to get their shared lock.
+*
+* We take the very same locks, so SQLite and sand keep out of each other's way. On Linux they are
+* open file description locks, which unlike plain POSIX locks also keep two connections in the same
+* process apart and are not all dropped when any file descriptor for the file is closed.
+*/
+const PENDING_BYTE: i64 = 0x4000_0000;
+const RESERVED_BYTE: i64 = PENDING_BYTE + 1;
+const SHARED_FIRST: i64 = PENDING_BYTE + 2;
+const SHARED_SIZE: i64 = 510;
+
+#[cfg(target_os = "linux")]
+const F_SETLK: libc::c_int = libc::F_OFD_SETLK;
+#[cfg(target_os = "linux")]
+const F_GETLK: libc::c_int = libc::F_OFD_GETLK;
+#[cfg(not(target_os = "linux"))]
+const F_SETLK: libc::c_int = libc::F_SETLK;
+#[cfg(not(target_os = "linux"))]
+const F_GETLK: libc::c_int = libc::F_GETLK;
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &str, mode: OpenMode) -> Result<Box<dyn VfsFile>> {
+        let read_write = || OpenOptions::new().read(true).write(true).open(path);
+        let (file, read_only) = match mode {
+            OpenMode::ReadOnly => (File::open(path)?, true),
+            OpenMode::ReadWrite => match read_write() {
+                Err(err) if err.kind() == ErrorKind::PermissionDenied => (File::open(path)?, true),
+                file => (file?, false),
+            },
+            OpenMode::Create => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)?;
+                (file, false)
+            }
+        };
+        Ok(Box::new(OsFile {
+            file,
+            read_only,
+            mmap: None,
+            mmap_size: 0,
+            lock: LockLevel::None,
+        }))
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn exists(&self, path: &str) -> Result<bool> {
+        Ok(fs::exists(path)?)
+    }
+
+    fn sync_directory(&self, path: &str) -> Result<()> {
+        let directory = match Path::new(path).parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        File::open(directory)?.sync_all()?;
+        Ok(())
+    }
+}
+
+impl VfsFile for OsFile {
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.file.read(&mut buffer[read..]) {
+                Ok(0) => break,
+                Ok(bytes) => read += bytes,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(read)
+    }
+
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(buffer)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<()> {
+        // Touching a mapped page past the end of the file is a SIGBUS, so the map goes first.
+        self.mmap = None;
+        self.file.set_len(size)?;
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    // Goes up one level at a time like SQLite does, and back to where it started if a level can not
+    // be had.
+    fn lock(&mut self, level: LockLevel) -> Result<bool> {
+        if level <= self.lock {
+            if level < self.lock {
+                self.unlock_to(level)?;
+                self.lock = level;
+            }
+            return Ok(true);
+        }
+        if self.read_only && level >= LockLevel::Reserved {
+            return Err(DBError::ReadOnly(
+                "Can not write to a file opened read only".to_string(),
+            ));
+        }
+
+        let granted = (self.lock >= LockLevel::Shared || self.lock_shared()?)
+            && (level == LockLevel::Shared
+                || self.lock >= LockLevel::Reserved
+                || self.lock_bytes(libc::F_WRLCK, RESERVED_BYTE, 1)?)
+            && (level < LockLevel::Exclusive
+                || (self.lock_bytes(libc::F_WRLCK, PENDING_BYTE, 1)?
+                    && self.lock_bytes(libc::F_WRLCK, SHARED_FIRST, SHARED_SIZE)?));
+        if granted {
+            self.lock = level;
+        } else {
+            self.unlock_to(self.lock)?;
+        }
+        Ok(granted)
+    }
+
+    fn check_reserved_lock(&mut self) -> Result<bool> {
+        if self.lock >= LockLevel::Reserved {
+            return Ok(true);
+        }
+        let mut flock = byte_range(libc::F_WRLCK, RESERVED_BYTE, 1);
+        // SAFETY: `flock` is a valid flock struct that lives for the duration of the call.
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), F_GETLK, &mut flock) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(flock.l_type != libc::F_UNLCK as libc::c_short)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn set_mmap_size(&mut self, size: u64) -> Result<()> {
+        self.mmap = None;
+        self.mmap_size = size;
+        Ok(())
+    }
+
+    // The map is made the first time it is needed and made again when the file grew past it.
+    // Writes go through the same page cache of the OS, so what is mapped is never out of date.
+    fn fetch(&mut self, offset: u64, len: usize) -> Result<Option<&[u8]>> {
+        let end = offset + len as u64;
+        if end > self.mmap_size {
+            return Ok(None);
+        }
+        if self
+            .mmap
+            .as_ref()
+            .is_none_or(|mmap| (mmap.len() as u64) < end)
+        {
+            let size = self.size()?.min(self.mmap_size);
+            if size < end {
+                return Ok(None);
+            }
+            // SAFETY: The map is only read, and it is dropped before we truncate the file. Another
+            // process cutting the file short under us would still be a SIGBUS, SQLite has the same
+            // problem with its mmap.
+            self.mmap = Some(unsafe { MmapOptions::new().len(size as usize).map(&self.file)? });
+        }
+        let mmap = self.mmap.as_ref().unwrap();
+        Ok(Some(&mmap[offset as usize..end as usize]))
+    }
+}
+
+impl OsFile {
+    // Reading needs the pending byte for a moment, so nobody waiting for EXCLUSIVE is overtaken.
+    fn lock_shared(&mut self) -> Result<bool> {
+        if !self.lock_bytes(libc::F_RDLCK, PENDING_BYTE, 1)? {
+            return Ok(false);
+        }
+        let granted = self.lock_bytes(libc::F_RDLCK, SHARED_FIRST, SHARED_SIZE)?;
+        self.lock_bytes(libc::F_UNLCK, PENDING_BYTE, 1)?;
+        Ok(granted)
+    }
+
+    // Drops whatever is held beyond `level`. Going down never has to wait for anybody.
+    fn unlock_to(&mut self, level: LockLevel) -> Result<()> {
+        match level {
+            LockLevel::None => {
+                self.lock_bytes(libc::F_UNLCK, PENDING_BYTE, SHARED_SIZE + 2)?;
+            }
+            LockLevel::Shared | LockLevel::Reserved => {
+                self.lock_bytes(libc::F_RDLCK, SHARED_FIRST, SHARED_SIZE)?;
+                let len = if level == LockLevel::Shared { 2 } else { 1 };
+                self.lock_bytes(libc::F_UNLCK, PENDING_BYTE, len)?;
+            }
+            LockLevel::Exclusive => {}
+        }
+        Ok(())
+    }
+
+    // Locks or unlocks `len` bytes at `start`, returns false if another connection's lock is in the
+    // way.
+    fn lock_bytes(&mut self, lock_type: libc::c_int, start: i64, len: i64) -> Result<bool> {
+        let mut flock = byte_range(lock_type, start, len);
+        // SAFETY: `flock` is a valid flock struct that lives for the duration of the call.
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), F_SETLK, &mut flock) } == 0 {
+            return Ok(true);
+        }
+        let err = std::io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EAGAIN) | Some(libc::EACCES) => Ok(false),
+            _ => Err(err.into()),
+        }
+    }
+}
+
+fn byte_range(lock_type: libc::c_int, start: i64, len: i64) -> libc::flock {
+    // SAFETY: flock is plain old data, and open file description locks want a zero pid.
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+    flock.l_type = lock_type as libc::c_short;
+    flock.l_whence = libc::SEEK_SET as libc::c_short;
+    flock.l_start = start as libc::off_t;
+    flock.l_len = len as libc::off_t;
+    flock
+}
+
+/*
+* Files kept in memory, for databases that never touch the disk. Clones share the same files, and
+* a file stays around after it is closed until it is deleted, just like on disk.
+*/
+#[derive(Debug, Default, Clone)]
+pub struct MemoryVfs {
+    files: Arc<Mutex<HashMap<String, Arc<Mutex<MemoryFileData>>>>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFileData {
+    data: Vec<u8>,
+    // Connections holding SHARED or RESERVED, both of which read.
+    shared_locks: usize,
+    reserved_lock: bool,
+    exclusive_lock: bool,
+}
+
+#[derive(Debug)]
+pub struct MemoryFile {
+    file: Arc<Mutex<MemoryFileData>>,
+    read_only: bool,
+    lock: LockLevel,
+}
+
+impl MemoryVfs {
+    pub fn new() -> MemoryVfs {
+        MemoryVfs::default()
+    }
+
+    // Puts a file in place, replacing whatever was there, to start from an existing database.
+    pub fn write_file(&self, path: &str, data: Vec<u8>) {
+        let file = MemoryFileData {
+            data,
+            ..Default::default()
+        };
+        let mut files = self.files.lock().unwrap();
+        files.insert(path.to_string(), Arc::new(Mutex::new(file)));
+    }
+
+    // Contents of a file, None if there is no such file.
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        let files = self.files.lock().unwrap();
+        let file = files.get(path)?;
+        let data = file.lock().unwrap().data.clone();
+        Some(data)
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &str, mode: OpenMode) -> Result<Box<dyn VfsFile>> {
+        let mut files = self.files.lock().unwrap();
+        let file = match files.get(path) {
+            Some(file) => file.clone(),
+            None if mode == OpenMode::Create => files.entry(path.to_string()).or_default().clone(),
+            None => {
+                return Err(DBError::Io(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("No such file: {}", path),
+                )))
+            }
+        };
+        Ok(Box::new(MemoryFile {
+            file,
+            read_only: mode == OpenMode::ReadOnly,
+            lock: LockLevel::None,
+        }))
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        self.files.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    fn exists(&self, path: &str) -> Result<bool> {
+        Ok(self.files.lock().unwrap().contains_key(path))
+    }
+
+    fn sync_directory(&self, _path: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl VfsFile for MemoryFile {
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let file = self.file.lock().unwrap();
+        let start = (offset as usize).min(file.data.len());
+        let end = (start + buffer.len()).min(file.data.len());
+        buffer[..end - start].copy_from_slice(&file.data[start..end]);
+        Ok(end - start)
+    }
+
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Err(DBError::Io(std::io::Error::new(
+                ErrorKind::PermissionDenied,
+                "File is opened read only",
+            )));
+        }
+        let mut file = self.file.lock().unwrap();
+        let end = offset as usize + buffer.len();
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[offset as usize..end].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<()> {
+        self.file.lock().unwrap().data.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.lock().unwrap().data.len() as u64)
+    }
+
+    fn lock(&mut self, level: LockLevel) -> Result<bool> {
+        if self.read_only && level >= LockLevel::Reserved {
+            return Err(DBError::ReadOnly(
+                "Can not write to a file opened read only".to_string(),
+            ));
+        }
+        let mut file = self.file.lock().unwrap();
+        // Let go of our own lock first, then see whether the new one is possible.
+        match self.lock {
+            LockLevel::None => {}
+            LockLevel::Shared => file.shared_locks -= 1,
+            LockLevel::Reserved => {
+                file.shared_locks -= 1;
+                file.reserved_lock = false;
+            }
+            LockLevel::Exclusive => file.exclusive_lock = false,
+        }
+        let granted = match level {
+            LockLevel::None => true,
+            LockLevel::Shared => !file.exclusive_lock,
+            LockLevel::Reserved => !file.exclusive_lock && !file.reserved_lock,
+            LockLevel::Exclusive => {
+                !file.exclusive_lock && !file.reserved_lock && file.shared_locks == 0
+            }
+        };
+        let level = if granted { level } else { self.lock };
+        match level {
+            LockLevel::None => {}
+            LockLevel::Shared => file.shared_locks += 1,
+            LockLevel::Reserved => {
+                file.shared_locks += 1;
+                file.reserved_lock = true;
+            }
+            LockLevel::Exclusive => file.exclusive_lock = true,
+        }
+        self.lock = level;
+        Ok(granted)
+    }
+
+    fn check_reserved_lock(&mut self) -> Result<bool> {
+        let file = self.file.lock().unwrap();
+        Ok(file.reserved_lock || file.exclusive_lock)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+impl Drop for MemoryFile {
+    fn drop(&mut self) {
+        let _ = self.lock(LockLevel::None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::test_utils::{temp_copy, Sqlite3};
+    use crate::page::vfs::{LockLevel, MemoryVfs, OpenMode, OsVfs, Vfs};
+
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+
+    #[test]
+    fn memory_vfs_test() {
+        let vfs = MemoryVfs::new();
+        assert!(vfs.open("test.db", OpenMode::ReadWrite).is_err());
+
+        let mut file = vfs.open("test.db", OpenMode::Create).unwrap();
+        file.write_at(10, b"hello").unwrap();
+        assert_eq!(file.size().unwrap(), 15);
+        let mut buffer = [1u8; 20];
+        assert_eq!(file.read_at(8, &mut buffer).unwrap(), 7);
+        assert_eq!(&buffer[..7], b"\0\0hello");
+        assert!(file.read_exact_at(8, &mut buffer).is_err());
+        file.truncate(12).unwrap();
+        assert_eq!(vfs.read_file("test.db").unwrap(), b"\0\0\0\0\0\0\0\0\0\0he");
+
+        // Another handle sees the same file, but the locks keep them apart.
+        let mut other = vfs.open("test.db", OpenMode::ReadOnly).unwrap();
+        assert!(other.write_at(0, b"x").is_err());
+        assert!(file.lock(LockLevel::Shared).unwrap());
+        assert!(other.lock(LockLevel::Shared).unwrap());
+        assert!(!file.lock(LockLevel::Exclusive).unwrap());
+        // A writer getting ready still lets others read, but not write.
+        assert!(file.lock(LockLevel::Reserved).unwrap());
+        assert!(other.check_reserved_lock().unwrap());
+        assert!(other.lock(LockLevel::None).unwrap());
+        assert!(other.lock(LockLevel::Shared).unwrap());
+        assert!(!file.lock(LockLevel::Exclusive).unwrap());
+        assert!(other.lock(LockLevel::None).unwrap());
+        assert!(file.lock(LockLevel::Exclusive).unwrap());
+        assert!(!other.lock(LockLevel::Shared).unwrap());
+        drop(file);
+        assert!(!other.check_reserved_lock().unwrap());
+        assert!(other.lock(LockLevel::Shared).unwrap());
+
+        vfs.delete("test.db").unwrap();
+        assert!(!vfs.exists("test.db").unwrap());
+    }
+
+    #[test]
+    fn os_file_mmap_test() {
+        let path = std::env::temp_dir().join(format!("sand-test-{}-mmap.db", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let mut file = OsVfs.open(&path, OpenMode::Create).unwrap();
+        file.write_at(0, &[1; 4096]).unwrap();
+        assert_eq!(file.fetch(0, 1024).unwrap(), None);
+
+        file.set_mmap_size(8192).unwrap();
+        assert_eq!(file.fetch(1024, 1024).unwrap(), Some(&[1; 1024][..]));
+        // Writes show up in the map, and growing the file maps the new part as well.
+        file.write_at(1024, &[2; 4096]).unwrap();
+        assert_eq!(file.fetch(1024, 1024).unwrap(), Some(&[2; 1024][..]));
+        assert_eq!(file.fetch(4096, 1024).unwrap(), Some(&[2; 1024][..]));
+        // Past the file or the mmap size it is back to `read_at`.
+        assert_eq!(file.fetch(4096, 2048).unwrap(), None);
+        file.write_at(5120, &[3; 4096]).unwrap();
+        assert_eq!(file.fetch(7168, 1024).unwrap(), Some(&[3; 1024][..]));
+        assert_eq!(file.fetch(7168, 2048).unwrap(), None);
+
+        file.truncate(2048).unwrap();
+        assert_eq!(file.fetch(1024, 1024).unwrap(), Some(&[2; 1024][..]));
+        assert_eq!(file.fetch(2048, 1024).unwrap(), None);
+        drop(file);
+        OsVfs.delete(&path).unwrap();
+    }
+
+    fn is_locked(lines: Vec<String>) -> bool {
+        lines.iter().any(|line| line.contains("database is locked"))
+    }
+
+    #[test]
+    fn os_file_locks_sqlite3_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut file = OsVfs.open(&path, OpenMode::ReadWrite).unwrap();
+        let mut other = OsVfs.open(&path, OpenMode::ReadWrite).unwrap();
+        let mut sqlite = Sqlite3::open(&path);
+
+        // SQLite writing keeps us from writing, but not from reading while it is only reserved.
+        sqlite.run("BEGIN IMMEDIATE;");
+        assert!(file.check_reserved_lock().unwrap());
+        assert!(file.lock(LockLevel::Shared).unwrap());
+        assert!(!file.lock(LockLevel::Reserved).unwrap());
+        assert!(!file.lock(LockLevel::Exclusive).unwrap());
+        // Even a commit that changed nothing waits for the readers.
+        assert!(is_locked(sqlite.run("COMMIT;")));
+        assert!(file.lock(LockLevel::None).unwrap());
+        assert_eq!(sqlite.run("COMMIT;"), Vec::<String>::new());
+        assert!(!file.check_reserved_lock().unwrap());
+        sqlite.run("BEGIN EXCLUSIVE;");
+        assert!(!file.lock(LockLevel::Reserved).unwrap());
+        assert!(file.lock(LockLevel::None).unwrap());
+        assert!(!file.lock(LockLevel::Shared).unwrap());
+        sqlite.run("COMMIT;");
+
+        // And the other way around.
+        assert!(file.lock(LockLevel::Shared).unwrap());
+        assert!(is_locked(sqlite.run("BEGIN EXCLUSIVE;")));
+        assert!(file.lock(LockLevel::Reserved).unwrap());
+        assert!(!other.lock(LockLevel::Reserved).unwrap());
+        assert!(other.check_reserved_lock().unwrap());
+        assert!(is_locked(sqlite.run("BEGIN IMMEDIATE;")));
+        assert_eq!(sqlite.run("SELECT count(*) FROM items;"), vec!["0"]);
+        assert!(file.lock(LockLevel::Exclusive).unwrap());
+        assert!(is_locked(sqlite.run("SELECT count(*) FROM items;")));
+        assert!(!other.lock(LockLevel::Shared).unwrap());
+        assert!(file.lock(LockLevel::None).unwrap());
+        assert_eq!(sqlite.run("SELECT count(*) FROM items;"), vec!["0"]);
+        assert!(other.lock(LockLevel::Exclusive).unwrap());
+        drop(sqlite);
+        OsVfs.delete(&path).unwrap();
+    }
+}
@@ -0,0 +1,693 @@
+use super::errors::Result;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+};
+
+use log::debug;
+
+use super::file_structures::read_u32;
+use super::logging::PAGER;
+use super::vfs::{OpenMode, Vfs, VfsFile};
+
+/*
+* The write-ahead log, `<db>-wal`, of a database in WAL mode.
+*
+* In WAL mode changed pages are not written to the database file but appended to the WAL as frames.
+* The database file only gets them when the WAL is checkpointed, so until then the newest version of
+* a page is the last frame for it in the WAL, and pages without a frame are read from the database
+* file.
+*
+* The WAL starts with a 32 byte header:
+*   - magic, 0x377f0682 or 0x377f0683 where the last bit says the checksums use big endian words
+*   - format version, 3007000
+*   - page size
+*   - checkpoint sequence number
+*   - two salts, copied into every frame so frames left over from before the WAL was reset do not
+*     count
+*   - checksum of the first 24 bytes
+* Every frame is a 24 byte header followed by the page:
+*   - page number
+*   - for the last frame of a transaction the size of the database in pages after it, 0 otherwise
+*   - the two salts
+*   - checksum of the first 8 bytes of the frame header and the page, continuing from the checksum
+*     of the frame before it
+*
+* Frames are only valid up to the first one with the wrong salts or checksum, and only frames of
+* committed transactions are used, frames after the last commit frame belong to a transaction that
+* never finished.
+*
+* A checkpoint copies the newest version of every page back into the database file. After that the
+* WAL can start over from the beginning: it gets a new header with new salts, which turns every
+* frame still in the file into a left over.
+*
+* SQLite keeps an index of the WAL in shared memory, `<db>-shm`, along with a mark for every reader
+* of how far into the WAL it reads, so a checkpoint knows how far it can go without pulling pages
+* out from under a reader. We have no -shm, which means SQLite must not use the database while it
+* is in WAL mode. Every connection indexes the WAL itself, catching up at the start of every read,
+* and the locks on the database file stand in for the marks: a commit appends to the WAL with a
+* RESERVED lock, so readers carry on with the frames that were there when they started, and a
+* checkpoint needs EXCLUSIVE, so there are no such readers left.
+*/
+
+const WAL_MAGIC: u32 = 0x377f0682;
+const WAL_VERSION: u32 = 3007000;
+pub const WAL_HEADER_SIZE: usize = 32;
+pub const WAL_FRAME_HEADER_SIZE: usize = 24;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct WalHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub page_size: u32,
+    pub checkpoint_sequence: u32,
+    pub salt: (u32, u32),
+    pub checksum: (u32, u32),
+}
+
+impl WalHeader {
+    // Whether the checksums are computed over big endian words.
+    fn big_endian(&self) -> bool {
+        self.magic & 1 == 1
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(WAL_HEADER_SIZE);
+        for value in [
+            self.magic,
+            self.version,
+            self.page_size,
+            self.checkpoint_sequence,
+            self.salt.0,
+            self.salt.1,
+            self.checksum.0,
+            self.checksum.1,
+        ] {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+// How much work a checkpoint does, the same modes as `PRAGMA wal_checkpoint`. There is no busy
+// timeout, so where SQLite would wait for other connections to finish we are busy instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckpointMode {
+    // Copies the WAL into the database file if nobody else is reading or writing, and does nothing
+    // otherwise.
+    Passive,
+    // Same as PASSIVE, but busy instead of doing nothing.
+    Full,
+    // Same as FULL, then starts the WAL over from the beginning.
+    Restart,
+    // Same as RESTART, and truncates the WAL file to nothing.
+    Truncate,
+}
+
+#[derive(Debug)]
+pub struct Wal {
+    pub path: String,
+    pub header: WalHeader,
+    file: Box<dyn VfsFile>,
+    // Frame number, counting from 0, of the newest committed frame of each page.
+    frame_index: HashMap<usize, usize>,
+    // Number of frames up to and including the last commit frame.
+    pub frame_count: usize,
+    // Size of the database in pages as of the last commit in the WAL.
+    pub db_size_in_pages: u32,
+    // Checksum of the last committed frame, the next frame continues from it.
+    checksum: (u32, u32),
+    // Number of frames already copied into the database file by a checkpoint.
+    pub checkpointed_frames: usize,
+}
+
+pub fn wal_path(db_path: &str) -> String {
+    format!("{}-wal", db_path)
+}
+
+pub fn delete_wal(vfs: &dyn Vfs, path: &str) -> Result<()> {
+    vfs.delete(path)
+}
+
+impl Wal {
+    /*
+     * Opens the WAL of a database and indexes its committed frames. Returns None when there is no
+     * WAL or it does not have a valid header, SQLite treats that the same as an empty WAL. A WAL
+     * with another page size than the database is ignored the same way, its frames are not pages
+     * of this database.
+     */
+    pub fn open(vfs: &dyn Vfs, path: &str, db_page_size: usize) -> Result<Option<Wal>> {
+        if !vfs.exists(path)? {
+            return Ok(None);
+        }
+        let mut file = vfs.open(path, OpenMode::ReadWrite)?;
+        let mut header = [0; WAL_HEADER_SIZE];
+        let read = file.read_at(0, &mut header)?;
+        let Some(header) = read_wal_header(&header[..read]) else {
+            return Ok(None);
+        };
+        if header.page_size as usize != db_page_size {
+            debug!(
+                target: PAGER,
+                "Ignoring WAL {} with {} byte pages, the database has {} byte pages",
+                path,
+                header.page_size,
+                db_page_size
+            );
+            return Ok(None);
+        }
+
+        let mut wal = Wal {
+            path: path.to_string(),
+            checksum: header.checksum,
+            header,
+            file,
+            frame_index: HashMap::new(),
+            frame_count: 0,
+            db_size_in_pages: 0,
+            checkpointed_frames: 0,
+        };
+        wal.read_frames()?;
+        debug!(
+            target: PAGER,
+            "Opened WAL {} with {} committed frames",
+            path,
+            wal.frame_count
+        );
+        Ok(Some(wal))
+    }
+
+    /*
+     * Catches up with what other connections did to the WAL since we last looked, and returns
+     * whether they did anything. They may have committed more frames, or started it over after a
+     * checkpoint. Needs a SHARED lock on the database, see `Pager::checkpoint` for why that keeps
+     * the WAL from starting over while we read it.
+     */
+    pub fn refresh(&mut self) -> Result<bool> {
+        let mut header = [0; WAL_HEADER_SIZE];
+        let read = self.file.read_at(0, &mut header)?;
+        let header = read_wal_header(&header[..read]);
+        let restarted = match &header {
+            Some(header) if header.to_bytes() == self.header.to_bytes() => false,
+            // Truncated, the next commit writes a new header.
+            None => !self.is_empty(),
+            Some(header) => {
+                self.header = header.clone();
+                true
+            }
+        };
+        if restarted {
+            debug!(target: PAGER, "WAL {} was started over", self.path);
+            self.frame_index.clear();
+            self.frame_count = 0;
+            self.db_size_in_pages = 0;
+            self.checksum = self.header.checksum;
+            self.checkpointed_frames = 0;
+        }
+        let frame_count = self.frame_count;
+        if header.is_some() {
+            self.read_frames()?;
+        }
+        Ok(restarted || self.frame_count != frame_count)
+    }
+
+    // Indexes the frames committed after the first `frame_count` ones.
+    fn read_frames(&mut self) -> Result<()> {
+        let page_size = self.header.page_size as usize;
+        let frame_size = WAL_FRAME_HEADER_SIZE + page_size;
+        let start = WAL_HEADER_SIZE + self.frame_count * frame_size;
+        let size = self.file.size()? as usize;
+        if size <= start {
+            return Ok(());
+        }
+        let mut contents = vec![0; size - start];
+        self.file.read_exact_at(start as u64, &mut contents)?;
+
+        let big_endian = self.header.big_endian();
+        let first_frame = self.frame_count;
+        let mut uncommitted = HashMap::new();
+        let mut checksum = self.checksum;
+        let mut frame = 0;
+        while (frame + 1) * frame_size <= contents.len() {
+            let offset = frame * frame_size;
+            let frame_header = &contents[offset..offset + WAL_FRAME_HEADER_SIZE];
+            let page = &contents[offset + WAL_FRAME_HEADER_SIZE..offset + frame_size];
+            let page_index = read_u32(frame_header, 0) as usize;
+            let salt = (read_u32(frame_header, 8), read_u32(frame_header, 12));
+            if page_index == 0 || salt != self.header.salt {
+                break;
+            }
+            checksum = wal_checksum(big_endian, checksum, &frame_header[..8]);
+            checksum = wal_checksum(big_endian, checksum, page);
+            if checksum != (read_u32(frame_header, 16), read_u32(frame_header, 20)) {
+                break;
+            }
+
+            uncommitted.insert(page_index, first_frame + frame);
+            frame += 1;
+            let commit_size = read_u32(frame_header, 4);
+            if commit_size != 0 {
+                self.frame_index.extend(uncommitted.drain());
+                self.frame_count = first_frame + frame;
+                self.db_size_in_pages = commit_size;
+                self.checksum = checksum;
+            }
+        }
+        Ok(())
+    }
+
+    // Creates an empty WAL for a database that just switched to WAL mode.
+    pub fn create(vfs: &dyn Vfs, path: &str, page_size: usize) -> Result<Wal> {
+        let mut file = vfs.open(path, OpenMode::Create)?;
+        file.truncate(0)?;
+        // Commits in the WAL are only durable if the WAL itself is.
+        vfs.sync_directory(path)?;
+        let header = WalHeader {
+            magic: WAL_MAGIC,
+            version: WAL_VERSION,
+            page_size: page_size as u32,
+            checkpoint_sequence: 0,
+            salt: (random_u32(), random_u32()),
+            checksum: (0, 0),
+        };
+        Ok(Wal {
+            path: path.to_string(),
+            checksum: header.checksum,
+            header,
+            file,
+            frame_index: HashMap::new(),
+            frame_count: 0,
+            db_size_in_pages: 0,
+            checkpointed_frames: 0,
+        })
+    }
+
+    // Whether the WAL has any committed frames.
+    pub fn is_empty(&self) -> bool {
+        self.frame_count == 0
+    }
+
+    // Page numbers of every page with a committed frame.
+    pub fn pages(&self) -> Vec<usize> {
+        let mut pages: Vec<usize> = self.frame_index.keys().copied().collect();
+        pages.sort();
+        pages
+    }
+
+    /*
+     * Appends a transaction to the WAL and syncs it, the last frame marking the commit with the new
+     * size of the database. An empty WAL starts over from the beginning with a new header first.
+     */
+    pub fn append_transaction(
+        &mut self,
+        pages: &[(usize, Vec<u8>)],
+        db_size_in_pages: u32,
+    ) -> Result<()> {
+        if self.frame_count == 0 {
+            self.restart(false)?;
+        }
+
+        let big_endian = self.header.big_endian();
+        let mut checksum = self.checksum;
+        let mut frames = Vec::with_capacity(
+            pages.len() * (WAL_FRAME_HEADER_SIZE + pages.first().map_or(0, |(_, page)| page.len())),
+        );
+        for (i, (page_index, page)) in pages.iter().enumerate() {
+            let commit_size = if i + 1 == pages.len() {
+                db_size_in_pages
+            } else {
+                0
+            };
+            let mut frame_header = Vec::with_capacity(WAL_FRAME_HEADER_SIZE);
+            frame_header.extend((*page_index as u32).to_be_bytes());
+            frame_header.extend(commit_size.to_be_bytes());
+            frame_header.extend(self.header.salt.0.to_be_bytes());
+            frame_header.extend(self.header.salt.1.to_be_bytes());
+            checksum = wal_checksum(big_endian, checksum, &frame_header[..8]);
+            checksum = wal_checksum(big_endian, checksum, page);
+            frame_header.extend(checksum.0.to_be_bytes());
+            frame_header.extend(checksum.1.to_be_bytes());
+            frames.extend(frame_header);
+            frames.extend_from_slice(page);
+        }
+
+        let frame_size = WAL_FRAME_HEADER_SIZE + self.header.page_size as usize;
+        let offset = WAL_HEADER_SIZE + self.frame_count * frame_size;
+        self.file.write_at(offset as u64, &frames)?;
+        self.file.sync()?;
+
+        for (i, (page_index, _)) in pages.iter().enumerate() {
+            self.frame_index.insert(*page_index, self.frame_count + i);
+        }
+        self.frame_count += pages.len();
+        self.checksum = checksum;
+        self.db_size_in_pages = db_size_in_pages;
+        Ok(())
+    }
+
+    /*
+     * Starts the WAL over once everything in it is in the database file. The new header has the
+     * first salt incremented and a new random second salt, like SQLite does, so none of the frames
+     * in the file match it any more. With `truncate` the file is emptied instead, the header gets
+     * written with the next transaction.
+     */
+    pub fn restart(&mut self, truncate: bool) -> Result<()> {
+        self.header.magic = WAL_MAGIC;
+        self.header.checkpoint_sequence = self.header.checkpoint_sequence.wrapping_add(1);
+        self.header.salt = (self.header.salt.0.wrapping_add(1), random_u32());
+        let header = self.header.to_bytes();
+        self.header.checksum = wal_checksum(false, (0, 0), &header[..24]);
+
+        if truncate {
+            self.file.truncate(0)?;
+        } else {
+            self.file.write_at(0, &self.header.to_bytes())?;
+        }
+        self.file.sync()?;
+
+        self.frame_index.clear();
+        self.frame_count = 0;
+        self.db_size_in_pages = 0;
+        self.checksum = self.header.checksum;
+        self.checkpointed_frames = 0;
+        Ok(())
+    }
+
+    // The newest committed version of a page, None when the WAL does not have it.
+    pub fn read_page(&mut self, page_index: usize) -> Result<Option<Vec<u8>>> {
+        let Some(&frame) = self.frame_index.get(&page_index) else {
+            return Ok(None);
+        };
+        let page_size = self.header.page_size as usize;
+        let offset = WAL_HEADER_SIZE + frame * (WAL_FRAME_HEADER_SIZE + page_size);
+        let mut page = vec![0; page_size];
+        self.file
+            .read_exact_at((offset + WAL_FRAME_HEADER_SIZE) as u64, &mut page)?;
+        Ok(Some(page))
+    }
+}
+
+// Reads and checks the WAL header, None if it is not a valid one.
+fn read_wal_header(contents: &[u8]) -> Option<WalHeader> {
+    if contents.len() < WAL_HEADER_SIZE {
+        return None;
+    }
+    let header = WalHeader {
+        magic: read_u32(contents, 0),
+        version: read_u32(contents, 4),
+        page_size: read_u32(contents, 8),
+        checkpoint_sequence: read_u32(contents, 12),
+        salt: (read_u32(contents, 16), read_u32(contents, 20)),
+        checksum: (read_u32(contents, 24), read_u32(contents, 28)),
+    };
+
+    let page_size_valid =
+        (512..=65536).contains(&header.page_size) && header.page_size.is_power_of_two();
+    if header.magic & !1 != WAL_MAGIC || header.version != WAL_VERSION || !page_size_valid {
+        return None;
+    }
+    if wal_checksum(header.big_endian(), (0, 0), &contents[..24]) != header.checksum {
+        return None;
+    }
+    Some(header)
+}
+
+// Salts only have to differ from the ones before them, the random seed of a hasher is plenty.
+fn random_u32() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+// The checksum SQLite uses for the WAL, over 32 bit words in the byte order the magic asks for.
+pub fn wal_checksum(big_endian: bool, checksum: (u32, u32), data: &[u8]) -> (u32, u32) {
+    let (mut s0, mut s1) = checksum;
+    let word = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    for words in data.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&words[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&words[4..])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::page::cursor::BTreeCursor;
+    use crate::page::errors::DBError;
+    use crate::page::file_structures::Value;
+    use crate::page::pager::JournalMode;
+    use crate::page::test_utils::temp_copy;
+    use crate::page::vfs::OsVfs;
+    use crate::page::wal::{wal_path, CheckpointMode, Wal, WAL_FRAME_HEADER_SIZE, WAL_HEADER_SIZE};
+    use crate::page::Database;
+
+    /*
+     * Made by SQLite in WAL mode with 512 byte pages. The database file has 100 rows in `items`, the
+     * WAL has two more transactions: one adding rows 101 to 200 and renaming rows 1 to 50, and one
+     * deleting every row after 150. Its last frame is the commit frame of the second transaction.
+     */
+    const WAL_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/wal.db");
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+    const FRAME_SIZE: usize = WAL_FRAME_HEADER_SIZE + 512;
+
+    fn item_names(database: &mut Database) -> Vec<String> {
+        let root_page = database.get_table("items").unwrap().root_page;
+        let mut cursor = BTreeCursor::new(root_page);
+        let mut names = Vec::new();
+        let mut more = cursor.first(&mut database.pager).unwrap();
+        while more {
+            match &cursor.cell().unwrap().payload[1] {
+                Value::Text(name) => names.push(name.clone()),
+                value => panic!("Expected a name, got: {:?}", value),
+            }
+            more = cursor.next(&mut database.pager).unwrap();
+        }
+        names
+    }
+
+    fn expected_names(renamed: bool, row_count: u64) -> Vec<String> {
+        (1..=row_count)
+            .map(|id| match renamed && id <= 50 {
+                true => format!("changed-{}", id),
+                false => format!("item-{}", id),
+            })
+            .collect()
+    }
+
+    fn insert_items(database: &mut Database, rowids: impl Iterator<Item = u64>) {
+        for rowid in rowids {
+            let values = vec![
+                Value::Integer(rowid as i64),
+                Value::Text(format!("item-{}", rowid)),
+                Value::Blob(vec![rowid as u8; 40]),
+                Value::Null,
+            ];
+            database.insert("items", values).unwrap();
+        }
+    }
+
+    // Copies the fixture and its WAL, letting `change_wal` mess with the WAL first.
+    fn wal_copy(change_wal: impl Fn(&mut Vec<u8>)) -> String {
+        let path = temp_copy(WAL_DB);
+        let mut wal = std::fs::read(wal_path(WAL_DB)).unwrap();
+        change_wal(&mut wal);
+        std::fs::write(wal_path(&path), wal).unwrap();
+        path
+    }
+
+    #[test]
+    fn read_wal_test() {
+        let mut database = Database::open(WAL_DB.to_string()).unwrap();
+        let wal = database.pager.wal.as_ref().unwrap();
+        assert_eq!(wal.frame_count, 27);
+        // The database file only has 11 pages, the rest live in the WAL.
+        assert_eq!(database.pager.page_count(), 21);
+        assert_eq!(item_names(&mut database), expected_names(true, 150));
+    }
+
+    #[test]
+    fn wal_frames_after_last_valid_commit_test() {
+        // Without its commit frame the second transaction never happened.
+        let path = wal_copy(|wal| wal.truncate(wal.len() - FRAME_SIZE));
+        let mut database = Database::open(path).unwrap();
+        assert_eq!(item_names(&mut database), expected_names(true, 200));
+
+        // Neither did it when one of its frames has a bad checksum.
+        let path = wal_copy(|wal| wal[WAL_HEADER_SIZE + 19 * FRAME_SIZE + 100] ^= 1);
+        let mut database = Database::open(path).unwrap();
+        assert_eq!(item_names(&mut database), expected_names(true, 200));
+
+        // Frames with other salts are left over from before the WAL was reset.
+        let path = wal_copy(|wal| {
+            for frame in 0..(wal.len() - WAL_HEADER_SIZE) / FRAME_SIZE {
+                wal[WAL_HEADER_SIZE + frame * FRAME_SIZE + 8] ^= 1;
+            }
+        });
+        let mut database = Database::open(path).unwrap();
+        assert!(database.pager.wal.as_ref().unwrap().is_empty());
+        assert_eq!(item_names(&mut database), expected_names(false, 100));
+
+        // A WAL with a broken header is no WAL at all.
+        let path = wal_copy(|wal| wal[12] ^= 1);
+        let mut database = Database::open(path).unwrap();
+        assert!(database.pager.wal.is_none());
+        assert_eq!(item_names(&mut database), expected_names(false, 100));
+    }
+
+    #[test]
+    fn wal_with_other_page_size_test() {
+        // A WAL that is fine in itself, but with pages twice the size of the database's.
+        let path = temp_copy(WAL_DB);
+        let mut wal = Wal::create(&OsVfs, &wal_path(&path), 1024).unwrap();
+        wal.append_transaction(&[(2, vec![0xAB; 1024])], 11)
+            .unwrap();
+        drop(wal);
+
+        let mut database = Database::open(path).unwrap();
+        assert!(database.pager.wal.is_none());
+        assert_eq!(item_names(&mut database), expected_names(false, 100));
+    }
+
+    #[test]
+    fn write_wal_and_checkpoint_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut database = Database::open(path.clone()).unwrap();
+        database.set_journal_mode(JournalMode::Wal).unwrap();
+        let file_size = fs::metadata(&path).unwrap().len();
+        insert_items(&mut database, 1..=200);
+
+        // Everything went to the WAL, the database file is as it was.
+        assert_eq!(fs::metadata(&path).unwrap().len(), file_size);
+        assert!(database.pager.wal.as_ref().unwrap().frame_count > 200);
+
+        let mut database = Database::open(path.clone()).unwrap();
+        assert_eq!(database.journal_mode(), JournalMode::Wal);
+        assert_eq!(item_names(&mut database), expected_names(false, 200));
+
+        let (frame_count, checkpointed) = database.checkpoint(CheckpointMode::Passive).unwrap();
+        assert_eq!(checkpointed, frame_count);
+        assert!(fs::metadata(&path).unwrap().len() > file_size);
+
+        // After the checkpoint the database file has it all, the WAL is no longer needed.
+        fs::remove_file(wal_path(&path)).unwrap();
+        let mut database = Database::open(path).unwrap();
+        assert_eq!(item_names(&mut database), expected_names(false, 200));
+    }
+
+    #[test]
+    fn checkpoint_restart_and_truncate_test() {
+        // New frames continue the checksums of the frames SQLite wrote.
+        let path = wal_copy(|_| {});
+        let mut database = Database::open(path.clone()).unwrap();
+        insert_items(&mut database, 151..=160);
+        let mut database = Database::open(path.clone()).unwrap();
+        assert_eq!(item_names(&mut database), expected_names(true, 160));
+
+        let wal_size = fs::metadata(wal_path(&path)).unwrap().len();
+        database.checkpoint(CheckpointMode::Restart).unwrap();
+        assert_eq!(fs::metadata(wal_path(&path)).unwrap().len(), wal_size);
+        let mut database = Database::open(path.clone()).unwrap();
+        assert!(database.pager.wal.as_ref().unwrap().is_empty());
+        assert_eq!(item_names(&mut database), expected_names(true, 160));
+
+        // The next transaction starts at the beginning of the WAL again.
+        insert_items(&mut database, 161..=161);
+        assert!(database.pager.wal.as_ref().unwrap().frame_count < 5);
+        let mut database = Database::open(path.clone()).unwrap();
+        assert_eq!(item_names(&mut database), expected_names(true, 161));
+
+        database.checkpoint(CheckpointMode::Truncate).unwrap();
+        assert_eq!(fs::metadata(wal_path(&path)).unwrap().len(), 0);
+        let mut database = Database::open(path).unwrap();
+        assert_eq!(item_names(&mut database), expected_names(true, 161));
+    }
+
+    fn count(database: &mut Database) -> Value {
+        let mut rows = database.query("SELECT count(*) FROM items").unwrap();
+        rows.next().unwrap().unwrap().remove(0)
+    }
+
+    #[test]
+    fn wal_of_other_connections_test() {
+        let path = temp_copy(EMPTY_TABLE_DB);
+        let mut writer = Database::open(path.clone()).unwrap();
+        writer.set_journal_mode(JournalMode::Wal).unwrap();
+        let mut reader = Database::open(path.clone()).unwrap();
+        reader.pager.set_cache_capacity(3);
+        insert_items(&mut writer, 1..=100);
+        assert_eq!(count(&mut reader), Value::Integer(100));
+
+        // Commits go on while a query reads, which keeps to the rows there were when it started.
+        let mut rows = reader.query("SELECT name FROM items").unwrap();
+        assert!(rows.next().is_some());
+        insert_items(&mut writer, 101..=200);
+        assert_eq!(rows.count(), 99);
+        assert_eq!(count(&mut reader), Value::Integer(200));
+
+        // Checkpoints wait for it though, PASSIVE does nothing and the others are busy.
+        let mut rows = reader.query("SELECT name FROM items").unwrap();
+        assert!(rows.next().is_some());
+        let (frame_count, checkpointed) = writer.checkpoint(CheckpointMode::Passive).unwrap();
+        assert!(frame_count > 200 && checkpointed == 0);
+        for mode in [
+            CheckpointMode::Full,
+            CheckpointMode::Restart,
+            CheckpointMode::Truncate,
+        ] {
+            assert!(matches!(writer.checkpoint(mode), Err(DBError::Busy(_))));
+        }
+        drop(rows);
+
+        // The WAL starting over and going back to a rollback journal do not get past the reader.
+        writer.checkpoint(CheckpointMode::Truncate).unwrap();
+        assert_eq!(fs::metadata(wal_path(&path)).unwrap().len(), 0);
+        insert_items(&mut writer, 201..=210);
+        assert_eq!(count(&mut reader), Value::Integer(210));
+        writer.checkpoint(CheckpointMode::Restart).unwrap();
+        insert_items(&mut writer, 211..=211);
+        assert_eq!(count(&mut reader), Value::Integer(211));
+        writer.set_journal_mode(JournalMode::Delete).unwrap();
+        insert_items(&mut writer, 212..=212);
+        assert_eq!(count(&mut reader), Value::Integer(212));
+        assert_eq!(reader.journal_mode(), JournalMode::Delete);
+        assert!(reader.pager.wal.is_none());
+        assert_eq!(item_names(&mut reader), expected_names(false, 212));
+    }
+
+    #[test]
+    fn leave_wal_mode_test() {
+        let path = wal_copy(|_| {});
+        let mut database = Database::open(path.clone()).unwrap();
+        assert_eq!(database.journal_mode(), JournalMode::Wal);
+
+        // Not in the middle of a write.
+        let first_page = database.pager.read_raw_page(1).unwrap();
+        database.pager.write_page(1, first_page);
+        assert!(matches!(
+            database.set_journal_mode(JournalMode::Delete),
+            Err(DBError::Misuse(_))
+        ));
+        assert!(matches!(
+            database.checkpoint(CheckpointMode::Passive),
+            Err(DBError::Misuse(_))
+        ));
+        database.pager.rollback().unwrap();
+
+        database.set_journal_mode(JournalMode::Delete).unwrap();
+        assert!(!fs::exists(wal_path(&path)).unwrap());
+
+        let mut database = Database::open(path).unwrap();
+        assert_eq!(database.journal_mode(), JournalMode::Delete);
+        assert_eq!(database.pager.db_header.write_version, 1);
+        assert_eq!(item_names(&mut database), expected_names(true, 150));
+    }
+}
@@ -0,0 +1,838 @@
+use std::fmt::{self, Formatter};
+
+// Byte range of the SQL text something was parsed from, for pointing at it in error messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // The smallest span covering both.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Statement {
+    Select(Select),
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    CreateView(CreateView),
+    CreateTrigger(CreateTrigger),
+    Drop(DropObject),
+    Begin(Option<TransactionKind>),
+    Commit,
+    // ROLLBACK [TO [SAVEPOINT] name]
+    Rollback(Option<String>),
+    Savepoint(String),
+    Release(String),
+    // EXPLAIN [QUERY PLAN] statement
+    Explain {
+        query_plan: bool,
+        statement: Box<Statement>,
+    },
+}
+
+// A name that may be qualified with the schema it is in, like `main.worms`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualifiedName {
+    pub schema: Option<String>,
+    pub name: String,
+    pub span: Span,
+}
+
+/*
+* SELECT statements.
+*
+* A select is one or more cores, each `SELECT ... FROM ... WHERE ... GROUP BY ... HAVING ...` or
+* `VALUES (...)`, put together with UNION, INTERSECT and EXCEPT. ORDER BY and LIMIT apply to the
+* whole compound.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub with: Option<With>,
+    pub body: SelectCore,
+    pub compound: Vec<(CompoundOperator, SelectCore)>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct With {
+    pub recursive: bool,
+    pub tables: Vec<CommonTableExpression>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonTableExpression {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub select: Box<Select>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOperator {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum SelectCore {
+    Select {
+        distinct: bool,
+        columns: Vec<ResultColumn>,
+        from: Option<FromClause>,
+        where_clause: Option<Expr>,
+        group_by: Vec<Expr>,
+        having: Option<Expr>,
+        // `WINDOW name AS (...), ...`, windows OVER can refer to by name.
+        windows: Vec<(String, Window)>,
+    },
+    Values(Vec<Vec<Expr>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    // `*`
+    All,
+    // `table.*`
+    AllFrom(String),
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+// The tables of a FROM clause, joined left to right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromClause {
+    pub first: TableOrSubquery,
+    pub joins: Vec<Join>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableOrSubquery {
+    Table {
+        name: QualifiedName,
+        alias: Option<String>,
+        indexed: Option<Indexed>,
+    },
+    Subquery {
+        select: Box<Select>,
+        alias: Option<String>,
+    },
+}
+
+// `INDEXED BY name` or `NOT INDEXED` after a table name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indexed {
+    By(String),
+    Not,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub natural: bool,
+    pub kind: JoinKind,
+    pub table: TableOrSubquery,
+    pub constraint: Option<JoinConstraint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    // `a, b`
+    Comma,
+    // `a JOIN b` and `a INNER JOIN b`
+    Inner,
+    Cross,
+    Left,
+    Right,
+    Full,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    On(Expr),
+    Using(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub order: Option<Order>,
+    pub nulls: Option<NullsOrder>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
+/*
+* The window of a window function, `OVER (PARTITION BY ... ORDER BY ... frame)`. `OVER name` and
+* `OVER (name ...)` start from a window of the WINDOW clause.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub base: Option<String>,
+    // A bare `OVER name` takes the named window as it is, frame and all.
+    pub bare: bool,
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Option<Frame>,
+}
+
+impl Window {
+    pub fn exprs(&self) -> Vec<&Expr> {
+        let mut exprs: Vec<&Expr> = self.partition_by.iter().collect();
+        exprs.extend(self.order_by.iter().map(|term| &term.expr));
+        if let Some(frame) = &self.frame {
+            exprs.extend(frame.start.offset());
+            exprs.extend(frame.end.offset());
+        }
+        exprs
+    }
+
+    pub fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        let mut exprs: Vec<&mut Expr> = self.partition_by.iter_mut().collect();
+        exprs.extend(self.order_by.iter_mut().map(|term| &mut term.expr));
+        if let Some(frame) = &mut self.frame {
+            for bound in [&mut frame.start, &mut frame.end] {
+                if let FrameBound::Preceding(offset) | FrameBound::Following(offset) = bound {
+                    exprs.push(offset);
+                }
+            }
+        }
+        exprs
+    }
+}
+
+// The rows of its partition a window function looks at for a row, `ROWS BETWEEN ... AND ...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+    pub exclude: FrameExclude,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameUnits {
+    Rows,
+    // Rows by the value of their ORDER BY term.
+    Range,
+    // Groups of peers, rows that ORDER BY puts in the same place.
+    Groups,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Expr),
+    CurrentRow,
+    Following(Expr),
+    UnboundedFollowing,
+}
+
+impl FrameBound {
+    pub fn offset(&self) -> Option<&Expr> {
+        match self {
+            FrameBound::Preceding(offset) | FrameBound::Following(offset) => Some(offset),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameExclude {
+    NoOthers,
+    CurrentRow,
+    // The current row and its peers.
+    Group,
+    // The peers of the current row, but not the row itself.
+    Ties,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub limit: Expr,
+    pub offset: Option<Expr>,
+}
+
+/*
+* INSERT, UPDATE and DELETE.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub with: Option<With>,
+    // `INSERT OR ...`, REPLACE on its own is `INSERT OR REPLACE`.
+    pub or_conflict: Option<ConflictResolution>,
+    pub table: QualifiedName,
+    pub alias: Option<String>,
+    pub columns: Vec<String>,
+    pub source: InsertSource,
+    pub returning: Vec<ResultColumn>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Select(Box<Select>),
+    DefaultValues,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub with: Option<With>,
+    pub or_conflict: Option<ConflictResolution>,
+    pub table: QualifiedName,
+    pub alias: Option<String>,
+    pub assignments: Vec<(String, Expr)>,
+    pub where_clause: Option<Expr>,
+    pub returning: Vec<ResultColumn>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub with: Option<With>,
+    pub table: QualifiedName,
+    pub alias: Option<String>,
+    pub where_clause: Option<Expr>,
+    pub returning: Vec<ResultColumn>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictResolution {
+    Rollback,
+    Abort,
+    Fail,
+    Ignore,
+    Replace,
+}
+
+/*
+* CREATE and DROP.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub definition: TableDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableDefinition {
+    Columns {
+        columns: Vec<ColumnDefinition>,
+        constraints: Vec<TableConstraint>,
+        without_rowid: bool,
+        strict: bool,
+    },
+    AsSelect(Box<Select>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDefinition {
+    pub name: String,
+    pub type_name: Option<TypeName>,
+    pub constraints: Vec<ColumnConstraint>,
+}
+
+// A declared type like `VARCHAR(255)` or `UNSIGNED BIG INT`. SQLite only looks at the words to pick
+// the affinity, the numbers are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeName {
+    pub name: String,
+    pub arguments: Vec<String>,
+}
+
+impl fmt::Display for TypeName {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.arguments.is_empty() {
+            write!(f, "({})", self.arguments.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnConstraint {
+    // `CONSTRAINT name`
+    pub name: Option<String>,
+    pub kind: ColumnConstraintKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraintKind {
+    PrimaryKey {
+        order: Option<Order>,
+        on_conflict: Option<ConflictResolution>,
+        autoincrement: bool,
+    },
+    NotNull(Option<ConflictResolution>),
+    Null,
+    Unique(Option<ConflictResolution>),
+    Check(Expr),
+    Default(Expr),
+    Collate(String),
+    References(ForeignKey),
+    Generated {
+        expr: Expr,
+        stored: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableConstraint {
+    pub name: Option<String>,
+    pub kind: TableConstraintKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraintKind {
+    PrimaryKey {
+        columns: Vec<IndexedColumn>,
+        on_conflict: Option<ConflictResolution>,
+    },
+    Unique {
+        columns: Vec<IndexedColumn>,
+        on_conflict: Option<ConflictResolution>,
+    },
+    Check(Expr),
+    ForeignKey {
+        columns: Vec<String>,
+        foreign_key: ForeignKey,
+    },
+}
+
+// `REFERENCES table (columns) ...`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub on_delete: Option<ForeignKeyAction>,
+    pub on_update: Option<ForeignKeyAction>,
+    // DEFERRABLE INITIALLY DEFERRED, the constraint is only checked when the transaction commits.
+    pub deferred: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForeignKeyAction {
+    SetNull,
+    SetDefault,
+    Cascade,
+    Restrict,
+    NoAction,
+}
+
+// A column of an index or of a PRIMARY KEY or UNIQUE constraint. Indexes may be on expressions and
+// name a collation, both of which end up in `expr`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub expr: Expr,
+    pub order: Option<Order>,
+}
+
+impl IndexedColumn {
+    // Name of the column, None when indexing an expression.
+    pub fn column_name(&self) -> Option<&str> {
+        let mut expr = &self.expr;
+        while let ExprKind::Collate { expr: inner, .. } = &expr.kind {
+            expr = inner;
+        }
+        match &expr.kind {
+            ExprKind::Column { table: None, name } => Some(name),
+            _ => None,
+        }
+    }
+
+    // The collation named with COLLATE, None when there is none.
+    pub fn collation(&self) -> Option<&str> {
+        match &self.expr.kind {
+            ExprKind::Collate { collation, .. } => Some(collation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub unique: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub table: String,
+    pub columns: Vec<IndexedColumn>,
+    // Partial indexes only hold the rows matching this.
+    pub where_clause: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateView {
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub columns: Vec<String>,
+    pub select: Box<Select>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTrigger {
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub time: Option<TriggerTime>,
+    pub event: TriggerEvent,
+    pub table: String,
+    pub for_each_row: bool,
+    pub when: Option<Expr>,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerTime {
+    Before,
+    After,
+    InsteadOf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerEvent {
+    Delete,
+    Insert,
+    // `UPDATE OF columns`, no columns for any update.
+    Update(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropObject {
+    pub kind: ObjectKind,
+    pub if_exists: bool,
+    pub name: QualifiedName,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectKind {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionKind {
+    Deferred,
+    Immediate,
+    Exclusive,
+}
+
+/*
+* Expressions.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    // The expressions directly inside this one. Subqueries are not looked into, they have
+    // expressions of their own.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Literal(_)
+            | ExprKind::Variable(_)
+            | ExprKind::Column { .. }
+            | ExprKind::Exists(_)
+            | ExprKind::Subquery(_)
+            | ExprKind::Raise { .. } => Vec::new(),
+            ExprKind::Unary { expr, .. }
+            | ExprKind::Cast { expr, .. }
+            | ExprKind::Collate { expr, .. }
+            | ExprKind::IsNull { expr, .. }
+            | ExprKind::InSelect { expr, .. }
+            | ExprKind::InTable { expr, .. } => vec![expr],
+            ExprKind::Binary { left, right, .. } => vec![left, right],
+            ExprKind::Function {
+                arguments,
+                filter,
+                over,
+                ..
+            } => {
+                let mut children: Vec<&Expr> = arguments.iter().collect();
+                children.extend(filter.as_deref());
+                if let Some(over) = over {
+                    children.extend(over.exprs());
+                }
+                children
+            }
+            ExprKind::Row(arguments) => arguments.iter().collect(),
+            ExprKind::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                let mut children = vec![&**expr, &**pattern];
+                children.extend(escape.as_deref());
+                children
+            }
+            ExprKind::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            ExprKind::InList { expr, list, .. } => {
+                let mut children = vec![&**expr];
+                children.extend(list.iter());
+                children
+            }
+            ExprKind::Case {
+                operand,
+                when_then,
+                else_expr,
+            } => {
+                let mut children: Vec<&Expr> = operand.as_deref().into_iter().collect();
+                for (when, then) in when_then.iter() {
+                    children.push(when);
+                    children.push(then);
+                }
+                children.extend(else_expr.as_deref());
+                children
+            }
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match &mut self.kind {
+            ExprKind::Literal(_)
+            | ExprKind::Variable(_)
+            | ExprKind::Column { .. }
+            | ExprKind::Exists(_)
+            | ExprKind::Subquery(_)
+            | ExprKind::Raise { .. } => Vec::new(),
+            ExprKind::Unary { expr, .. }
+            | ExprKind::Cast { expr, .. }
+            | ExprKind::Collate { expr, .. }
+            | ExprKind::IsNull { expr, .. }
+            | ExprKind::InSelect { expr, .. }
+            | ExprKind::InTable { expr, .. } => vec![expr],
+            ExprKind::Binary { left, right, .. } => vec![left, right],
+            ExprKind::Function {
+                arguments,
+                filter,
+                over,
+                ..
+            } => {
+                let mut children: Vec<&mut Expr> = arguments.iter_mut().collect();
+                children.extend(filter.as_deref_mut());
+                if let Some(over) = over {
+                    children.extend(over.exprs_mut());
+                }
+                children
+            }
+            ExprKind::Row(arguments) => arguments.iter_mut().collect(),
+            ExprKind::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                let mut children = vec![&mut **expr, &mut **pattern];
+                children.extend(escape.as_deref_mut());
+                children
+            }
+            ExprKind::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            ExprKind::InList { expr, list, .. } => {
+                let mut children = vec![&mut **expr];
+                children.extend(list.iter_mut());
+                children
+            }
+            ExprKind::Case {
+                operand,
+                when_then,
+                else_expr,
+            } => {
+                let mut children: Vec<&mut Expr> = operand.as_deref_mut().into_iter().collect();
+                for (when, then) in when_then.iter_mut() {
+                    children.push(when);
+                    children.push(then);
+                }
+                children.extend(else_expr.as_deref_mut());
+                children
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Literal(Literal),
+    // A bound parameter: `?`, `?1`, `:name`, `@name` or `$name`, as written.
+    Variable(String),
+    // A column, maybe qualified with its table. Double quoted strings end up here too, SQLite only
+    // takes them for string literals when there is no column by that name.
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Unary {
+        operator: UnaryOperator,
+        expr: Box<Expr>,
+    },
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Function {
+        name: String,
+        arguments: Vec<Expr>,
+        distinct: bool,
+        // `count(*)`
+        star: bool,
+        // `FILTER (WHERE ...)`, the rows an aggregate takes.
+        filter: Option<Box<Expr>>,
+        // The window of a window function.
+        over: Option<Box<Window>>,
+    },
+    Cast {
+        expr: Box<Expr>,
+        type_name: TypeName,
+    },
+    Collate {
+        expr: Box<Expr>,
+        collation: String,
+    },
+    // LIKE, GLOB, REGEXP and MATCH.
+    Like {
+        operator: LikeOperator,
+        negated: bool,
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        escape: Option<Box<Expr>>,
+    },
+    Between {
+        negated: bool,
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+    },
+    InList {
+        negated: bool,
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+    },
+    InSelect {
+        negated: bool,
+        expr: Box<Expr>,
+        select: Box<Select>,
+    },
+    // `x IN table`
+    InTable {
+        negated: bool,
+        expr: Box<Expr>,
+        table: QualifiedName,
+    },
+    // `x ISNULL`, `x NOTNULL` and `x NOT NULL`. `x IS NULL` is a Binary IS.
+    IsNull {
+        negated: bool,
+        expr: Box<Expr>,
+    },
+    Exists(Box<Select>),
+    Subquery(Box<Select>),
+    Case {
+        operand: Option<Box<Expr>>,
+        when_then: Vec<(Expr, Expr)>,
+        else_expr: Option<Box<Expr>>,
+    },
+    // A row value, `(a, b)`.
+    Row(Vec<Expr>),
+    // RAISE(...) in the body of a trigger.
+    Raise {
+        kind: RaiseKind,
+        message: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Integer(i64),
+    Real(f64),
+    String(String),
+    Blob(Vec<u8>),
+    CurrentTime,
+    CurrentDate,
+    CurrentTimestamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Concat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LikeOperator {
+    Like,
+    Glob,
+    Regexp,
+    Match,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaiseKind {
+    Ignore,
+    Rollback,
+    Abort,
+    Fail,
+}
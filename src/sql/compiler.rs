@@ -0,0 +1,5118 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::page::errors::{DBError, Result};
+use crate::page::file_structures::Value;
+use crate::page::{ColumnType, Index, Table};
+
+use super::ast::{
+    BinaryOperator, Expr, ExprKind, Frame, FrameBound, FrameExclude, FrameUnits, FromClause,
+    Indexed, JoinConstraint, JoinKind, LikeOperator, Limit, Literal, NullsOrder, Order,
+    OrderingTerm, ResultColumn, Select, SelectCore, Statement, TableOrSubquery, UnaryOperator,
+    Window,
+};
+use super::functions::{self, AggregateFunction};
+use super::parse_statement;
+use super::planner::{self, Access, Bound, ColumnRef, Constraint, Key, OrderTerm, Plan, Term};
+use super::value::{self, Collation};
+use super::vm::{
+    Address, CursorId, Explain, Instruction, PlanStep, Program, Register, SortOrder,
+    EXPLAIN_COLUMNS, QUERY_PLAN_COLUMNS,
+};
+use super::window::{self, FrameEdge, Function, WindowCall};
+
+/*
+* Compiles SELECT statements into programs for the virtual machine in `vm`.
+*
+* A query becomes a loop over the rows of its table: Rewind and Next move a cursor along, the WHERE
+* clause jumps to Next for rows it is not true for and the result columns are worked out into
+* registers for ResultRow. The `planner` decides whether the loop goes over the whole table, a range
+* of its rowids or entries of one of its indexes, and which terms of WHERE that leaves to check. With
+* ORDER BY the loop fills a sorter, unless the rows already come out in that order, and a second
+* loop over the sorted rows hands them out. DISTINCT, OFFSET and LIMIT apply to rows on their way
+* out, in that order.
+*
+* A join is loops nested inside each other, one for each table, in the order the planner finds the
+* cheapest: the terms of ON and WHERE each go to the outermost loop that has the rows they depend
+* on. Tables on the right of a LEFT, RIGHT, FULL or CROSS JOIN keep their place. A LEFT JOIN that
+* found no row for the loops outside it goes through the rest once more with its table on a
+* NullRow. A RIGHT JOIN remembers the rowids of the rows it matched, and an extra loop after the
+* others hands out the rest of its table with NULLs for the tables on its left.
+*
+* An aggregate query steps the accumulators of its aggregates in the loops and hands out its groups
+* after them. When the planner can have the rows in the order of GROUP BY, a group is handed out by
+* a subroutine as soon as a row with other terms comes along. Otherwise the groups are found in a
+* hash table by their terms and handed out once every row is in. Without GROUP BY there is one
+* group, even for no rows at all.
+*
+* Window functions have values once every row is in. The rows, or the groups of an aggregate query,
+* go into a sorter with what the output reads of them, the arguments of the calls and the terms of
+* their windows. The calls of each window, the last one first, sort the rows by its PARTITION BY
+* and ORDER BY unless they are in that order already and add a column with their values, see
+* `window`. A loop over the rows then hands them out like the loops over the tables would.
+*
+* Subqueries are compiled inline, into the program of the query they are in, where they can read
+* the registers and cursors of the rows they are correlated with. The ones that are not correlated
+* are guarded by Once and only run the first time.
+*
+* Everything is checked while compiling, so a query against a column that does not exist fails
+* before the first row is read, even when the table is empty, like it does in SQLite.
+*/
+pub fn compile(
+    tables: &HashMap<String, Table>,
+    indexes: &HashMap<String, Index>,
+    sql: &str,
+) -> Result<Program> {
+    let only_select = || DBError::Sql("only SELECT statements can be queried".to_string());
+    let (select, explain) = match parse_statement(sql)? {
+        Statement::Select(select) => (select, None),
+        Statement::Explain {
+            statement,
+            query_plan,
+        } => match *statement {
+            Statement::Select(select) => (
+                select,
+                Some(match query_plan {
+                    true => Explain::QueryPlan,
+                    false => Explain::Program,
+                }),
+            ),
+            _ => return Err(only_select()),
+        },
+        _ => return Err(only_select()),
+    };
+
+    let mut compiler = Compiler {
+        tables,
+        indexes,
+        sql,
+        instructions: Vec::new(),
+        labels: Vec::new(),
+        register_count: 0,
+        cursor_count: 0,
+        outermost_reference: usize::MAX,
+        plan: Vec::new(),
+        plan_parent: 0,
+        subquery_count: 0,
+        select_count: select_count(&select),
+        window_count: 0,
+        accumulator_count: 0,
+        aggregate_arguments: false,
+    };
+    let columns = compiler.select(&select, None, &mut |compiler, first, count| {
+        compiler.emit(Instruction::ResultRow { first, count });
+        Ok(())
+    })?;
+    compiler.emit(Instruction::Halt);
+    let plan = std::mem::take(&mut compiler.plan);
+    Ok(Program {
+        columns: match explain {
+            Some(Explain::Program) => EXPLAIN_COLUMNS.map(String::from).to_vec(),
+            Some(Explain::QueryPlan) => QUERY_PLAN_COLUMNS.map(String::from).to_vec(),
+            None => columns,
+        },
+        register_count: compiler.register_count,
+        cursor_count: compiler.cursor_count,
+        accumulator_count: compiler.accumulator_count,
+        instructions: compiler.finish(),
+        explain,
+        plan,
+    })
+}
+
+// What a query does with a row on its way out, in SQL: result rows of the statement, the value
+// of a scalar subquery, the candidates of IN.
+type RowHandler<'h, 'a> = dyn FnMut(&mut Compiler<'a>, Register, usize) -> Result<()> + 'h;
+
+// A table of a FROM clause, under the name the query knows it by.
+#[derive(Clone)]
+struct Source<'a> {
+    table: &'a Table,
+    // The alias if there is one, the name of the table otherwise.
+    name: String,
+    cursor: CursorId,
+    // How deep the query is nested in subqueries, 0 for the statement itself.
+    depth: usize,
+    // The cursor on the index the query reads its columns from instead, when the index covers it.
+    covering: Option<(CursorId, &'a Index)>,
+    // Columns USING or NATURAL joined with a column of a table to the left. A name without a table
+    // is that column's.
+    merged: Vec<usize>,
+}
+
+impl Source<'_> {
+    fn resolve(&self, table: Option<&str>, name: &str) -> Option<ColumnRef> {
+        if table.is_some_and(|table| !table.eq_ignore_ascii_case(&self.name)) {
+            return None;
+        }
+        if let Some(index) = self
+            .table
+            .columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+        {
+            if table.is_none() && self.merged.contains(&index) {
+                return None;
+            }
+            return Some(ColumnRef::Column(index));
+        }
+        ["rowid", "oid", "_rowid_"]
+            .iter()
+            .any(|rowid| rowid.eq_ignore_ascii_case(name))
+            .then_some(ColumnRef::Rowid)
+    }
+
+    fn affinity(&self, column: ColumnRef) -> ColumnType {
+        match column {
+            ColumnRef::Column(index) => self.table.columns[index].column_type,
+            ColumnRef::Rowid => ColumnType::Integer,
+        }
+    }
+
+    // Whether a column is the rowid, under one of its names or as the INTEGER PRIMARY KEY.
+    fn is_rowid(&self, column: ColumnRef) -> bool {
+        match column {
+            ColumnRef::Column(index) => self.table.rowid_alias == Some(index),
+            ColumnRef::Rowid => true,
+        }
+    }
+
+    // The rowid for the INTEGER PRIMARY KEY.
+    fn normalized(&self, column: ColumnRef) -> ColumnRef {
+        match self.is_rowid(column) {
+            true => ColumnRef::Rowid,
+            false => column,
+        }
+    }
+}
+
+// The columns an expression can refer to: those of the query it is in and, for a subquery, those
+// of the queries it is nested in.
+struct Scope<'s> {
+    sources: &'s [Source<'s>],
+    depth: usize,
+    outer: Option<&'s Scope<'s>>,
+    // For an aggregate query once its groups are done, and a query with window functions once
+    // they are worked out, what its expressions read instead.
+    grouped: Option<&'s Grouped<'s>>,
+}
+
+impl<'s> Scope<'s> {
+    // What the query the source is a table of reads for its groups, once they are done.
+    fn grouped_for(&self, source: &Source) -> Option<&'s Grouped<'s>> {
+        match self.sources.iter().any(|own| std::ptr::eq(own, source)) {
+            true => self.grouped,
+            false => self.outer?.grouped_for(source),
+        }
+    }
+
+    // The source a column is in, looking further out while the name is not found.
+    fn lookup(&self, table: Option<&str>, name: &str) -> Option<(&'s Source<'s>, ColumnRef)> {
+        let found = self
+            .sources
+            .iter()
+            .find_map(|source| Some((source, source.resolve(table, name)?)));
+        found.or_else(|| self.outer?.lookup(table, name))
+    }
+
+    // Whether more than one table of the query the name is found in has a column by that name.
+    fn ambiguous(&self, table: Option<&str>, name: &str) -> bool {
+        let found = self
+            .sources
+            .iter()
+            .filter(|source| source.resolve(table, name).is_some())
+            .count();
+        match found {
+            0 => self.outer.is_some_and(|outer| outer.ambiguous(table, name)),
+            found => found > 1,
+        }
+    }
+}
+
+/*
+* What the result columns, HAVING and ORDER BY of an aggregate query read for a group, each in a
+* register: the terms of GROUP BY, the values of the aggregates, and those of the columns outside
+* them. What the output of a query with window functions reads for a row as well, with the values of
+* the window functions.
+*/
+#[derive(Clone)]
+struct Grouped<'e> {
+    terms: Vec<(&'e Expr, Register)>,
+    aggregates: Vec<(&'e Expr, Register)>,
+    // Columns by the cursor of their table.
+    columns: Vec<((CursorId, ColumnRef), Register)>,
+    windows: Vec<(&'e Expr, Register)>,
+}
+
+impl Grouped<'_> {
+    fn register(&self, expr: &Expr) -> Option<Register> {
+        self.terms
+            .iter()
+            .chain(self.aggregates.iter())
+            .chain(self.windows.iter())
+            .find(|(grouped, _)| same_expr(grouped, expr))
+            .map(|(_, register)| *register)
+    }
+}
+
+// How an aggregate query takes in its rows, see `Compiler::accumulate`.
+struct Aggregating<'e> {
+    grouped: Grouped<'e>,
+    // The accumulators of the aggregates, followed by those of the columns.
+    accumulators: usize,
+    grouping: Grouping,
+    // Whether the only min() or max() of the query took its value from the row, for the columns
+    // to take theirs from it as well.
+    hit: Option<Register>,
+}
+
+enum Grouping {
+    // Without GROUP BY, every row is in the one group.
+    Single,
+    // The groups in a hash table.
+    Hashed(CursorId),
+    /*
+     * The rows of a group one after the other: a group is over when the terms of a row differ from
+     * those of the group, by their collations. Whether there is a group yet, and the subroutine
+     * handing out a group with the register it returns to.
+     */
+    Streamed {
+        collations: Vec<Collation>,
+        started: Register,
+        subroutine: Address,
+        back: Register,
+    },
+}
+
+/*
+* The calls of window functions of a query that have the same window, worked out in one pass over
+* the rows sorted by the terms of its PARTITION BY and ORDER BY.
+*/
+struct WindowPass<'e> {
+    // With the window it starts from in the WINDOW clause filled in, and the frame.
+    window: Window,
+    keys: Vec<SortTerm>,
+    calls: Vec<(&'e Expr, Function)>,
+}
+
+// How a query with window functions keeps its rows until every row is in, see
+// `Compiler::window_row`.
+struct Windowing<'w, 'e> {
+    cursor: CursorId,
+    passes: &'w [WindowPass<'e>],
+    // Whether each pass sorts the rows, which are not in its order already.
+    sorts: Vec<bool>,
+    // The ends of the frame of each pass, with their offsets in registers.
+    edges: Vec<(FrameEdge, FrameEdge)>,
+    grouped: Grouped<'e>,
+    // Where the arguments and FILTER of each call, pass by pass, and the keys of each pass are in
+    // a row, after the values of `grouped` it keeps. The values of the calls come after them all.
+    arguments: Vec<(Range<usize>, Option<usize>)>,
+    keys: Vec<usize>,
+    width: usize,
+}
+
+impl Windowing<'_, '_> {
+    // The registers of the values a row keeps for the output, in the order they are in the row.
+    fn kept(&self) -> Vec<Register> {
+        let grouped = &self.grouped;
+        let exprs = grouped.terms.iter().chain(grouped.aggregates.iter());
+        exprs
+            .map(|(_, register)| *register)
+            .chain(grouped.columns.iter().map(|(_, register)| *register))
+            .collect()
+    }
+}
+
+// How a table of FROM is joined with the tables to its left. The first one is an inner join.
+struct Joined {
+    kind: JoinKind,
+    indexed: Option<Indexed>,
+    // The ON clause, or the equalities USING and NATURAL stand for.
+    on: Option<Expr>,
+}
+
+// Whether a join pairs rows that match nothing with a row of NULLs.
+fn is_outer(kind: JoinKind) -> bool {
+    matches!(kind, JoinKind::Left | JoinKind::Right | JoinKind::Full)
+}
+
+// One of the terms ANDed together in WHERE or in the ON clause of a join.
+#[derive(Clone)]
+struct Clause<'e> {
+    expr: &'e Expr,
+    // The table whose join the term is from, None for WHERE.
+    join: Option<usize>,
+    // The tables of FROM the term may depend on the rows of.
+    sources: Vec<usize>,
+}
+
+// What planning the loops of a query goes by.
+struct Planning<'q, 'e, 'a> {
+    sources: &'q [Source<'a>],
+    joins: &'q [Joined],
+    clauses: &'q [Clause<'e>],
+    // The result columns, for ORDER BY terms referring to them.
+    columns: &'q [Expr],
+    // The columns the query reads of each table.
+    used: &'q [Vec<ColumnRef>],
+    scope: &'q Scope<'q>,
+}
+
+impl Planning<'_, '_, '_> {
+    // Terms of WHERE, and of ON clauses of joins that are not outer joins, can be checked as soon
+    // as the rows they depend on are there. Those of an outer join are checked by its table.
+    fn is_general(&self, clause: &Clause) -> bool {
+        clause
+            .join
+            .is_none_or(|join| !is_outer(self.joins[join].kind))
+    }
+}
+
+/*
+* A loop of the nest the rows of a query go through, over a table of FROM. The loops are nested in
+* the order the planner puts the tables in.
+*/
+struct Level<'e, 'a> {
+    source: usize,
+    plan: Plan<'e, 'a>,
+    // The cursor on the index the plan goes through, if it does.
+    index_cursor: Option<CursorId>,
+    // For the table on the right of a LEFT or FULL JOIN, the terms of its ON clause the plan does
+    // not take care of. Rows they are not true for do not match.
+    on: Vec<&'e Expr>,
+    filter: Vec<&'e Expr>,
+    // Pairs the rows of the tables before with a row of NULLs when none of its rows match, for the
+    // table on the right of a LEFT or FULL JOIN.
+    left: bool,
+    // The set the rowids of rows that match go in, for the table on the right of a RIGHT or FULL
+    // JOIN.
+    matched: Option<CursorId>,
+    // Leaves out rows whose rowid is in the set, for the table of a RIGHT JOIN going through its
+    // rows that did not match.
+    unmatched: Option<CursorId>,
+}
+
+// What closing the loop of a level takes.
+struct Loop {
+    next: Address,
+    done: Address,
+    // The cursor the loop moves along, the top of the loop and whether it moves backwards.
+    scan: Option<(CursorId, Address, bool)>,
+    // For a LEFT or FULL JOIN: whether a row matched, whether the loop is going around with the
+    // row of NULLs and where that round starts.
+    left: Option<(Register, Register, Address)>,
+}
+
+struct SortTerm {
+    key: SortKey,
+    order: SortOrder,
+}
+
+enum SortKey {
+    // ORDER BY 2, or the alias of a result column.
+    Result(usize),
+    Expr(Expr),
+}
+
+// An end of a range of index entries: a key, or the NULLs the range leaves out.
+#[derive(Clone, Copy)]
+enum RangeEnd<'e> {
+    Key(Bound<'e>),
+    NotNull,
+}
+
+// Where result rows of a query go through DISTINCT, OFFSET and LIMIT.
+struct Output {
+    distinct: Option<CursorId>,
+    offset: Option<Register>,
+    limit: Option<Register>,
+    // Where the query is done, once LIMIT rows are out.
+    end: Address,
+}
+
+// Two operands of a comparison in registers, with what comparing them takes.
+#[derive(Clone, Copy)]
+struct Comparison {
+    operator: BinaryOperator,
+    left: Register,
+    right: Register,
+    null_eq: bool,
+    affinities: (Option<ColumnType>, Option<ColumnType>),
+    collation: Collation,
+}
+
+impl Comparison {
+    fn jump(&self, target: Address, jump_if_null: bool) -> Instruction {
+        Instruction::Compare {
+            operator: self.operator,
+            left: self.left,
+            right: self.right,
+            target,
+            jump_if_null,
+            null_eq: self.null_eq,
+            affinities: self.affinities,
+            collation: self.collation,
+        }
+    }
+
+    // The comparison that is true where this one is false, NULLs aside.
+    fn negated(mut self) -> Comparison {
+        self.operator = match self.operator {
+            BinaryOperator::Eq => BinaryOperator::NotEq,
+            BinaryOperator::NotEq => BinaryOperator::Eq,
+            BinaryOperator::Lt => BinaryOperator::GtEq,
+            BinaryOperator::LtEq => BinaryOperator::Gt,
+            BinaryOperator::Gt => BinaryOperator::LtEq,
+            _ => BinaryOperator::Lt,
+        };
+        self
+    }
+}
+
+struct Compiler<'a> {
+    tables: &'a HashMap<String, Table>,
+    indexes: &'a HashMap<String, Index>,
+    // The statement, for naming result columns after the SQL text of their expressions.
+    sql: &'a str,
+    // Jump targets are label numbers until `finish` swaps in the addresses of the labels.
+    instructions: Vec<Instruction>,
+    labels: Vec<Option<Address>>,
+    register_count: usize,
+    cursor_count: usize,
+    // The depth of the outermost query a column reference went to since the innermost subquery
+    // being compiled started. A subquery referring to nothing further out than itself is not
+    // correlated.
+    outermost_reference: usize,
+    // The steps of EXPLAIN QUERY PLAN, and the one the steps of the part of the query being
+    // compiled go under.
+    plan: Vec<PlanStep>,
+    plan_parent: usize,
+    subquery_count: usize,
+    // The SELECTs of the statement and the windows so far, which number the co-routines of the
+    // windows after the subqueries, like in SQLite.
+    select_count: usize,
+    window_count: usize,
+    accumulator_count: usize,
+    // Whether the arguments of an aggregate are being compiled, where another is out of place.
+    aggregate_arguments: bool,
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, instruction: Instruction) -> Address {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    fn label(&mut self) -> Address {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    // Makes the label stand for the next instruction emitted.
+    fn place(&mut self, label: Address) {
+        self.labels[label] = Some(self.instructions.len());
+    }
+
+    fn registers(&mut self, count: usize) -> Register {
+        self.register_count += count;
+        self.register_count - count
+    }
+
+    fn register(&mut self) -> Register {
+        self.registers(1)
+    }
+
+    fn accumulators(&mut self, count: usize) -> usize {
+        self.accumulator_count += count;
+        self.accumulator_count - count
+    }
+
+    fn cursor(&mut self) -> CursorId {
+        self.cursor_count += 1;
+        self.cursor_count - 1
+    }
+
+    fn finish(mut self) -> Vec<Instruction> {
+        for instruction in self.instructions.iter_mut() {
+            if let Some(target) = instruction.target_mut() {
+                *target = self.labels[*target].expect("every label is placed");
+            }
+        }
+        self.instructions
+    }
+
+    // Adds a step to the plan EXPLAIN QUERY PLAN shows, returning its id.
+    fn explain_step(&mut self, detail: String) -> usize {
+        let id = self.plan.len() + 1;
+        self.plan.push(PlanStep {
+            id,
+            parent: self.plan_parent,
+            detail,
+        });
+        id
+    }
+
+    // The steps of DISTINCT and ORDER BY on the way out.
+    fn explain_output(&mut self, distinct: bool, sorted: bool) {
+        if distinct {
+            self.explain_step("USE TEMP B-TREE FOR DISTINCT".to_string());
+        }
+        if sorted {
+            self.explain_step("USE TEMP B-TREE FOR ORDER BY".to_string());
+        }
+    }
+
+    fn text(&self, expr: &Expr) -> &str {
+        self.sql.get(expr.span.start..expr.span.end).unwrap_or("")
+    }
+
+    // Compiles a query, handing each of its result rows to `emit_row`. Returns the names of the
+    // result columns.
+    fn select(
+        &mut self,
+        select: &Select,
+        outer: Option<&Scope>,
+        emit_row: &mut RowHandler<'_, 'a>,
+    ) -> Result<Vec<String>> {
+        let unsupported = |what: &str| Err(DBError::Sql(format!("{} are not supported", what)));
+        if select.with.is_some() {
+            return unsupported("common table expressions");
+        }
+        if !select.compound.is_empty() {
+            return unsupported("compound SELECTs");
+        }
+        let SelectCore::Select {
+            distinct,
+            columns: result_columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            windows,
+        } = &select.body
+        else {
+            return unsupported("VALUES lists");
+        };
+
+        let depth = outer.map_or(0, |outer| outer.depth + 1);
+        let (mut sources, joins, merged) = match from {
+            None => (Vec::new(), Vec::new(), Vec::new()),
+            Some(from) => self.join_sources(from, depth)?,
+        };
+        let scope = Scope {
+            sources: &sources,
+            depth,
+            outer,
+            grouped: None,
+        };
+
+        let mut columns = Vec::new();
+        let mut names = Vec::new();
+        let mut aliases = Vec::new();
+        for result_column in result_columns {
+            match result_column {
+                ResultColumn::All | ResultColumn::AllFrom(_) => {
+                    if sources.is_empty() {
+                        return Err(DBError::Sql("no tables specified".to_string()));
+                    }
+                    // `*` leaves out the columns USING and NATURAL merged into a column to the left,
+                    // `table.*` does not.
+                    let (all, star) = match result_column {
+                        ResultColumn::AllFrom(table) => {
+                            let source = sources
+                                .iter()
+                                .find(|source| source.name.eq_ignore_ascii_case(table))
+                                .ok_or_else(|| DBError::Sql(format!("no such table: {}", table)))?;
+                            (std::slice::from_ref(source), false)
+                        }
+                        _ => (&sources[..], true),
+                    };
+                    for source in all {
+                        for (index, column) in source.table.columns.iter().enumerate() {
+                            if star && source.merged.contains(&index) {
+                                continue;
+                            }
+                            // The column a RIGHT or FULL JOIN merged a column into is whichever
+                            // of them is not NULL.
+                            let coalesced = merged
+                                .iter()
+                                .find(|(name, _)| name.eq_ignore_ascii_case(&column.name))
+                                .filter(|_| {
+                                    scope.lookup(None, &column.name).is_some_and(
+                                        |(found, found_column)| {
+                                            std::ptr::eq(found, source)
+                                                && found_column == ColumnRef::Column(index)
+                                        },
+                                    )
+                                });
+                            columns.push(match coalesced {
+                                Some((_, coalesced)) if star => coalesced.clone(),
+                                _ => column_expr(&source.name, &column.name),
+                            });
+                            names.push(column.name.clone());
+                            aliases.push(None);
+                        }
+                    }
+                }
+                ResultColumn::Expr { expr, alias } => {
+                    let name = match (alias, &expr.kind) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, ExprKind::Column { table, name }) if !sources.is_empty() => {
+                            let found = sources.iter().find_map(|source| {
+                                Some((source, source.resolve(table.as_deref(), name)?))
+                            });
+                            match found {
+                                Some((source, ColumnRef::Column(index))) => {
+                                    source.table.columns[index].name.clone()
+                                }
+                                _ => name.clone(),
+                            }
+                        }
+                        _ => self.text(expr).to_string(),
+                    };
+                    let mut expr = expr.clone();
+                    replace_merged(&mut expr, &merged);
+                    columns.push(expr);
+                    names.push(name);
+                    aliases.push(alias.clone());
+                }
+            }
+        }
+
+        let aliased: Vec<(&String, &Expr)> = aliases
+            .iter()
+            .zip(columns.iter())
+            .filter_map(|(alias, expr)| Some((alias.as_ref()?, expr)))
+            .collect();
+        let where_clause = where_clause.clone().map(|mut where_clause| {
+            replace_merged(&mut where_clause, &merged);
+            replace_aliases(&mut where_clause, &aliased, &scope);
+            where_clause
+        });
+        let mut order_by = Vec::new();
+        for (index, term) in select.order_by.iter().enumerate() {
+            let mut term = term.clone();
+            replace_merged(&mut term.expr, &merged);
+            replace_aliases(&mut term.expr, &aliased, &scope);
+            order_by.push(sort_term(&term, index, &columns, &aliases, &scope)?);
+        }
+
+        // GROUP BY terms can be result columns by number or alias, like ORDER BY terms.
+        let mut group_terms = Vec::new();
+        for (index, term) in group_by.iter().enumerate() {
+            let mut term = term.clone();
+            replace_merged(&mut term, &merged);
+            replace_aliases(&mut term, &aliased, &scope);
+            if let ExprKind::Literal(Literal::Integer(position)) = term.kind {
+                if position < 1 || position as usize > columns.len() {
+                    return Err(DBError::Sql(format!(
+                        "{} GROUP BY term out of range - should be between 1 and {}",
+                        ordinal(index + 1),
+                        columns.len()
+                    )));
+                }
+                term = columns[position as usize - 1].clone();
+            }
+            let mut calls = Vec::new();
+            aggregate_calls(&term, &mut calls);
+            if !calls.is_empty() {
+                return Err(DBError::Sql(
+                    "aggregate functions are not allowed in the GROUP BY clause".to_string(),
+                ));
+            }
+            group_terms.push(term);
+        }
+        let having = having.clone().map(|mut having| {
+            replace_merged(&mut having, &merged);
+            replace_aliases(&mut having, &aliased, &scope);
+            having
+        });
+        let sort_keys: Vec<&Expr> = order_by
+            .iter()
+            .filter_map(|term| match &term.key {
+                SortKey::Expr(expr) => Some(expr),
+                SortKey::Result(_) => None,
+            })
+            .collect();
+
+        /*
+         * The calls of window functions, grouped by their windows. The windows of the WINDOW clause
+         * are checked whether a call uses them or not. One can start from a window defined before
+         * it, SQLite leaves out the name of any other.
+         */
+        let mut definitions: Vec<(&str, Window)> = Vec::new();
+        for (name, window) in windows {
+            check_frame(window.frame.as_ref())?;
+            let window = match &window.base {
+                Some(base)
+                    if !(definitions.iter())
+                        .any(|(defined, _)| defined.eq_ignore_ascii_case(base)) =>
+                {
+                    Window {
+                        base: None,
+                        ..window.clone()
+                    }
+                }
+                _ => based_window(window, &definitions)?,
+            };
+            definitions.push((name, window));
+        }
+        let mut calls: Vec<&Expr> = Vec::new();
+        for expr in columns.iter().chain(sort_keys.iter().copied()) {
+            let mut found = Vec::new();
+            window_calls(expr, &mut found);
+            for call in found {
+                if !calls.iter().any(|other| same_expr(other, call)) {
+                    calls.push(call);
+                }
+            }
+        }
+        let passes = window_passes(&calls, &definitions, &merged, &scope)?;
+        let window_exprs: Vec<&Expr> = passes.iter().flat_map(|pass| pass.window.exprs()).collect();
+
+        let mut aggregates: Vec<&Expr> = Vec::new();
+        for expr in columns
+            .iter()
+            .chain(having.as_ref())
+            .chain(sort_keys.iter().copied())
+            .chain(window_exprs.iter().copied())
+        {
+            let mut calls = Vec::new();
+            aggregate_calls(expr, &mut calls);
+            for call in calls {
+                if !aggregates
+                    .iter()
+                    .any(|aggregate| same_expr(aggregate, call))
+                {
+                    aggregates.push(call);
+                }
+            }
+        }
+        let aggregate = !group_terms.is_empty() || !aggregates.is_empty();
+        if having.is_some() && !aggregate {
+            return Err(DBError::Sql(
+                "HAVING clause on a non-aggregate query".to_string(),
+            ));
+        }
+
+        let mut clauses = Vec::new();
+        for (position, joined) in joins.iter().enumerate() {
+            if let Some(on) = &joined.on {
+                if is_outer(joined.kind)
+                    && sources[position + 1..]
+                        .iter()
+                        .any(|source| names_column_of(on, source))
+                {
+                    return Err(DBError::Sql(
+                        "ON clause references tables to its right".to_string(),
+                    ));
+                }
+                split_clauses(on, Some(position), &sources, &mut clauses);
+            }
+        }
+        if let Some(where_clause) = &where_clause {
+            split_clauses(where_clause, None, &sources, &mut clauses);
+        }
+        let used: Vec<Vec<ColumnRef>> = sources
+            .iter()
+            .map(|source| {
+                let mut used = Vec::new();
+                let on = joins.iter().filter_map(|joined| joined.on.as_ref());
+                for expr in columns
+                    .iter()
+                    .chain(where_clause.as_ref())
+                    .chain(on)
+                    .chain(sort_keys.iter().copied())
+                    .chain(group_terms.iter())
+                    .chain(having.as_ref())
+                    .chain(window_exprs.iter().copied())
+                {
+                    columns_used(expr, source, &mut used);
+                }
+                used
+            })
+            .collect();
+
+        // The order the loops go through the tables in, how each gets to its rows and the terms of
+        // ON and WHERE each checks them against. The rows come out in the order of the outermost
+        // loop as long as the others find one row at most for each of its rows, and no RIGHT JOIN
+        // adds rows after them.
+        let planning = Planning {
+            sources: &sources,
+            joins: &joins,
+            clauses: &clauses,
+            columns: &columns,
+            used: &used,
+            scope: &scope,
+        };
+        let order = self.join_order(&planning)?;
+        let mut levels = self.plan_levels(&planning, &order, &[], &[])?;
+        let right_joins = joins
+            .iter()
+            .any(|joined| matches!(joined.kind, JoinKind::Right | JoinKind::Full));
+        let keeps_order =
+            !right_joins && levels.iter().skip(1).all(|level| level.plan.single_row());
+        /*
+         * An aggregate query wants its rows in the order of GROUP BY, for the rows of a group to
+         * come one after the other, each term in the direction ORDER BY sorts it in where ORDER BY
+         * starts with the same terms. Otherwise GROUP BY finds the groups in a hash table, which
+         * hands them out in that order too. The rows of a group also come together when the loops
+         * inside find more than one row, as long as GROUP BY is on the outermost table only. With
+         * window functions, the window whose calls are worked out first stands in for ORDER BY.
+         */
+        let first_order = passes.last().map_or(&order_by, |pass| &pass.keys);
+        let mut group_order = Vec::new();
+        for (index, term) in group_terms.iter().enumerate() {
+            let collation = expr_collation(term, &scope)?.unwrap_or(Collation::Binary);
+            let ascending = SortOrder {
+                descending: false,
+                nulls_first: true,
+                collation,
+            };
+            let order = first_order
+                .get(index)
+                .filter(|sort| sort.order.collation == collation)
+                .filter(|sort| sorts_by(sort, term, &columns))
+                .map_or(ascending, |sort| sort.order);
+            group_order.push(SortTerm {
+                key: SortKey::Expr(term.clone()),
+                order,
+            });
+        }
+        let outermost_only = levels.first().is_some_and(|outermost| {
+            group_terms.iter().all(|term| {
+                (sources.iter().enumerate()).all(|(position, source)| {
+                    position == outermost.source || !names_column_of(term, source)
+                })
+            })
+        });
+        // Other queries with window functions want their rows in the order of the window whose
+        // calls are worked out first.
+        let (wanted, keeps_order) = match (aggregate, passes.last()) {
+            (true, _) => (&group_order, keeps_order || !right_joins && outermost_only),
+            (false, Some(pass)) => (&pass.keys, keeps_order),
+            (false, None) => (&order_by, keeps_order),
+        };
+        if keeps_order && !wanted.is_empty() && !levels.is_empty() {
+            levels = self.plan_levels(&planning, &order, &[], wanted)?;
+        }
+        let ordered = keeps_order && levels.first().is_some_and(|level| level.plan.ordered);
+        let hashed = !group_terms.is_empty() && !ordered;
+        let mut sorted = match aggregate {
+            false => !order_by.is_empty() && !ordered,
+            true => {
+                !group_terms.is_empty()
+                    && !order_by
+                        .iter()
+                        .zip(group_order.iter())
+                        .all(|(sort, group)| {
+                            let SortKey::Expr(term) = &group.key else {
+                                unreachable!("GROUP BY terms are expressions")
+                            };
+                            sorts_by(sort, term, &columns) && sort.order == group.order
+                        })
+            }
+        };
+        /*
+         * Each window sorts the rows unless they are in its order already, the last one first, and
+         * ORDER BY sorts them once the windows are done unless they are still in its order. The one
+         * row of an aggregate query without GROUP BY is in every order.
+         */
+        let mut sorts = vec![false; passes.len()];
+        if !passes.is_empty() {
+            let single = aggregate && group_terms.is_empty();
+            let mut current: &[SortTerm] = match aggregate {
+                true => &group_order,
+                false if ordered => wanted,
+                false => &[],
+            };
+            for (index, pass) in passes.iter().enumerate().rev() {
+                if !single && !sorted_by(&pass.keys, current, &columns) {
+                    sorts[index] = true;
+                    current = &pass.keys;
+                }
+            }
+            sorted = !single && !order_by.is_empty() && !sorted_by(&order_by, current, &columns);
+        }
+        // SQLite shows a co-routine for each window, the innermost reading the tables and each of
+        // the others the one inside it.
+        let plan_parent = self.plan_parent;
+        let mut coroutines = Vec::new();
+        for _ in passes.iter() {
+            self.window_count += 1;
+            let number = self.select_count + self.window_count;
+            self.plan_parent = self.explain_step(format!("CO-ROUTINE (subquery-{})", number));
+            coroutines.push((self.plan_parent, number));
+        }
+        // The arguments a DISTINCT aggregate has seen, as SQLite shows them: before the loops for
+        // the one group there is, after GROUP BY otherwise.
+        let mut distinct_steps = Vec::new();
+        for aggregate in aggregates.iter() {
+            if let ExprKind::Function { distinct: true, .. } = aggregate.kind {
+                let name = aggregate_function(aggregate)?.name;
+                distinct_steps.push(format!("USE TEMP B-TREE FOR {}(DISTINCT)", name));
+            }
+        }
+        if group_terms.is_empty() {
+            for step in distinct_steps.drain(..) {
+                self.explain_step(step);
+            }
+        }
+        if levels.is_empty() {
+            self.explain_step("SCAN CONSTANT ROW".to_string());
+        }
+        self.explain_levels(&levels, &sources);
+        self.index_cursors(&mut levels, &mut sources);
+        let scope = Scope {
+            sources: &sources,
+            depth,
+            outer,
+            grouped: None,
+        };
+
+        let end = self.label();
+        let mut output = Output {
+            distinct: None,
+            offset: None,
+            limit: None,
+            end,
+        };
+        if let Some(limit) = &select.limit {
+            self.limit(limit, depth, &mut output)?;
+        }
+        if *distinct {
+            let cursor = self.cursor();
+            // Rows are the same when their values are equal by the collations they sort by.
+            let mut collations = Vec::new();
+            for expr in columns.iter() {
+                collations.push(expr_collation(expr, &scope)?.unwrap_or(Collation::Binary));
+            }
+            self.emit(Instruction::OpenEphemeral { cursor, collations });
+            output.distinct = Some(cursor);
+        }
+        let sorter = sorted.then(|| self.cursor());
+        if let Some(cursor) = sorter {
+            self.emit(Instruction::SorterOpen {
+                cursor,
+                orders: order_by.iter().map(|term| term.order).collect(),
+            });
+        }
+        // The rowids of the rows of the table on the right of a RIGHT or FULL JOIN that matched.
+        let mut matched_sets = vec![None; sources.len()];
+        for (position, joined) in joins.iter().enumerate() {
+            if matches!(joined.kind, JoinKind::Right | JoinKind::Full) {
+                let cursor = self.cursor();
+                self.emit(Instruction::OpenEphemeral {
+                    cursor,
+                    collations: vec![Collation::Binary],
+                });
+                matched_sets[position] = Some(cursor);
+            }
+        }
+        for level in levels.iter_mut().skip(1) {
+            level.matched = matched_sets[level.source];
+        }
+
+        // An aggregate query takes in its rows in the loops, and hands out its groups after them.
+        let aggregating = match aggregate {
+            false => None,
+            true => {
+                let mut bare = Vec::new();
+                for expr in columns
+                    .iter()
+                    .chain(having.as_ref())
+                    .chain(sort_keys.iter().copied())
+                    .chain(window_exprs.iter().copied())
+                {
+                    bare_columns(expr, &group_terms, &scope, &mut bare);
+                }
+                let grouped = Grouped {
+                    terms: group_terms
+                        .iter()
+                        .map(|term| (term, self.register()))
+                        .collect(),
+                    aggregates: aggregates
+                        .iter()
+                        .map(|aggregate| (*aggregate, self.register()))
+                        .collect(),
+                    columns: bare
+                        .into_iter()
+                        .map(|column| (column, self.register()))
+                        .collect(),
+                    windows: Vec::new(),
+                };
+                let count = grouped.aggregates.len() + grouped.columns.len();
+                let accumulators = self.accumulators(count);
+                let mut collations = Vec::new();
+                for term in group_terms.iter() {
+                    collations.push(expr_collation(term, &scope)?.unwrap_or(Collation::Binary));
+                }
+                let grouping = if group_terms.is_empty() {
+                    self.emit(Instruction::AggReset {
+                        first: accumulators,
+                        count,
+                    });
+                    Grouping::Single
+                } else if hashed {
+                    let cursor = self.cursor();
+                    self.emit(Instruction::GroupOpen {
+                        cursor,
+                        orders: group_order.iter().map(|term| term.order).collect(),
+                        first: accumulators,
+                        count,
+                    });
+                    Grouping::Hashed(cursor)
+                } else {
+                    let started = self.register();
+                    self.emit(Instruction::Integer {
+                        value: 0,
+                        target: started,
+                    });
+                    Grouping::Streamed {
+                        collations,
+                        started,
+                        subroutine: self.label(),
+                        back: self.register(),
+                    }
+                };
+                let extremes = grouped
+                    .aggregates
+                    .iter()
+                    .filter(|(aggregate, _)| {
+                        aggregate_function(aggregate).is_ok_and(|function| function.is_min_or_max())
+                    })
+                    .count();
+                let hit = (extremes == 1 && !grouped.columns.is_empty()).then(|| self.register());
+                Some(Aggregating {
+                    grouped,
+                    accumulators,
+                    grouping,
+                    hit,
+                })
+            }
+        };
+
+        // A query with window functions keeps its rows, or its groups, in a sorter until they are
+        // all in. The values of the calls go in registers of their own for the output.
+        let windowing = match passes.is_empty() {
+            true => None,
+            false => {
+                let cursor = self.cursor();
+                self.emit(Instruction::SorterOpen {
+                    cursor,
+                    orders: Vec::new(),
+                });
+                let mut grouped = match &aggregating {
+                    Some(aggregating) => aggregating.grouped.clone(),
+                    None => {
+                        let mut bare = Vec::new();
+                        for expr in columns.iter().chain(sort_keys.iter().copied()) {
+                            let mut parts = Vec::new();
+                            outside_windows(expr, &mut parts);
+                            for part in parts {
+                                bare_columns(part, &[], &scope, &mut bare);
+                            }
+                        }
+                        Grouped {
+                            terms: Vec::new(),
+                            aggregates: Vec::new(),
+                            columns: bare
+                                .into_iter()
+                                .map(|column| (column, self.register()))
+                                .collect(),
+                            windows: Vec::new(),
+                        }
+                    }
+                };
+                let calls = passes.iter().flat_map(|pass| pass.calls.iter());
+                grouped.windows = calls.map(|(call, _)| (*call, self.register())).collect();
+                let mut width =
+                    grouped.terms.len() + grouped.aggregates.len() + grouped.columns.len();
+                let mut arguments = Vec::new();
+                for (call, _) in passes.iter().flat_map(|pass| pass.calls.iter()) {
+                    let ExprKind::Function {
+                        arguments: exprs,
+                        filter,
+                        ..
+                    } = &call.kind
+                    else {
+                        unreachable!("window functions are function calls")
+                    };
+                    let filter = filter.as_ref().map(|_| width + exprs.len());
+                    arguments.push((width..width + exprs.len(), filter));
+                    width += exprs.len() + filter.is_some() as usize;
+                }
+                let mut keys = Vec::new();
+                for pass in passes.iter() {
+                    keys.push(width);
+                    width += pass.keys.len();
+                }
+                let mut edges = Vec::new();
+                for pass in passes.iter() {
+                    let frame = pass.window.frame.as_ref().expect("passes have frames");
+                    let start = self.frame_edge(&frame.start, &scope)?;
+                    edges.push((start, self.frame_edge(&frame.end, &scope)?));
+                }
+                Some(Windowing {
+                    cursor,
+                    passes: &passes,
+                    sorts: sorts.clone(),
+                    edges,
+                    grouped,
+                    arguments,
+                    keys,
+                    width,
+                })
+            }
+        };
+
+        // The loops over the rows of the tables. Without a table there is a single row, which WHERE
+        // may leave out.
+        let next = self.label();
+        if levels.is_empty() {
+            for clause in clauses.iter() {
+                self.jump_if(clause.expr, &scope, false, next, true)?;
+            }
+        }
+        self.nest(
+            &levels,
+            &scope,
+            &mut |compiler, scope| match (&aggregating, &windowing) {
+                (Some(aggregating), _) => compiler.accumulate(aggregating, scope),
+                (None, Some(windowing)) => compiler.window_row(windowing, scope),
+                (None, None) => {
+                    compiler.body(&columns, &order_by, sorter, &output, scope, emit_row)
+                }
+            },
+        )?;
+        self.place(next);
+
+        /*
+         * Then the rows of the table on the right of a RIGHT or FULL JOIN that did not match, with
+         * NULLs for the tables to its left, joined with the tables to its right as before. The terms
+         * of the ON clauses of the joins so far are left out, WHERE still applies.
+         */
+        for (right, set) in matched_sets.iter().enumerate() {
+            let Some(set) = *set else { continue };
+            let step = self.explain_step(format!("RIGHT-JOIN {}", sources[right].table.name));
+            let parent = std::mem::replace(&mut self.plan_parent, step);
+            let position = order.iter().position(|source| *source == right).unwrap();
+            let pass_order = order[position..].to_vec();
+            let nulls: Vec<usize> = (0..right).collect();
+            let pass_clauses: Vec<Clause> = clauses
+                .iter()
+                .filter(|clause| clause.join.is_none_or(|join| join > right))
+                .cloned()
+                .collect();
+            let mut pass_sources = sources.clone();
+            for source in pass_order.iter() {
+                pass_sources[*source].covering = None;
+            }
+            let pass_scope = Scope {
+                sources: &pass_sources,
+                depth,
+                outer,
+                grouped: None,
+            };
+            let planning = Planning {
+                sources: &pass_sources,
+                joins: &joins,
+                clauses: &pass_clauses,
+                columns: &columns,
+                used: &used,
+                scope: &pass_scope,
+            };
+            let mut pass = self.plan_levels(&planning, &pass_order, &nulls, &[])?;
+            self.explain_levels(&pass, &pass_sources);
+            self.index_cursors(&mut pass, &mut pass_sources);
+            pass[0].unmatched = Some(set);
+            for level in pass.iter_mut().skip(1) {
+                level.matched = matched_sets[level.source];
+            }
+            let pass_scope = Scope {
+                sources: &pass_sources,
+                depth,
+                outer,
+                grouped: None,
+            };
+            for source in nulls {
+                self.null_row(&pass_sources[source]);
+            }
+            self.nest(
+                &pass,
+                &pass_scope,
+                &mut |compiler, scope| match (&aggregating, &windowing) {
+                    (Some(aggregating), _) => compiler.accumulate(aggregating, scope),
+                    (None, Some(windowing)) => compiler.window_row(windowing, scope),
+                    (None, None) => {
+                        compiler.body(&columns, &order_by, sorter, &output, scope, emit_row)
+                    }
+                },
+            )?;
+            self.plan_parent = parent;
+        }
+        if hashed {
+            self.explain_step("USE TEMP B-TREE FOR GROUP BY".to_string());
+        }
+        for step in distinct_steps {
+            self.explain_step(step);
+        }
+        if sorts.last() == Some(&true) {
+            self.explain_step("USE TEMP B-TREE FOR ORDER BY".to_string());
+        }
+        if passes.is_empty() {
+            self.explain_output(*distinct, sorted);
+        }
+
+        // The groups, each with the values of its aggregates and the columns outside them, then
+        // HAVING.
+        if let Some(aggregating) = &aggregating {
+            let scope = Scope {
+                sources: &sources,
+                depth,
+                outer,
+                grouped: Some(&aggregating.grouped),
+            };
+            let mut output_group = |compiler: &mut Compiler<'a>| -> Result<()> {
+                compiler.group_values(aggregating)?;
+                let skip = compiler.label();
+                if let Some(having) = &having {
+                    compiler.jump_if(having, &scope, false, skip, true)?;
+                }
+                match &windowing {
+                    Some(windowing) => compiler.window_row(windowing, &scope)?,
+                    None => {
+                        compiler.body(&columns, &order_by, sorter, &output, &scope, emit_row)?
+                    }
+                }
+                compiler.place(skip);
+                Ok(())
+            };
+            match &aggregating.grouping {
+                Grouping::Single => output_group(self)?,
+                Grouping::Hashed(cursor) => {
+                    let done = self.label();
+                    self.emit(Instruction::GroupSort {
+                        cursor: *cursor,
+                        if_empty: done,
+                    });
+                    let top = self.label();
+                    self.place(top);
+                    for (index, (_, register)) in aggregating.grouped.terms.iter().enumerate() {
+                        self.emit(Instruction::Column {
+                            cursor: *cursor,
+                            column: index,
+                            target: *register,
+                        });
+                    }
+                    output_group(self)?;
+                    self.emit(Instruction::GroupNext {
+                        cursor: *cursor,
+                        target: top,
+                    });
+                    self.place(done);
+                }
+                // The last group is handed out once the loops are done, the others when the next
+                // one starts.
+                Grouping::Streamed {
+                    started,
+                    subroutine,
+                    back,
+                    ..
+                } => {
+                    let done = self.label();
+                    self.emit(Instruction::IfNot {
+                        register: *started,
+                        target: done,
+                        jump_if_null: true,
+                    });
+                    self.emit(Instruction::Gosub {
+                        register: *back,
+                        target: *subroutine,
+                    });
+                    self.emit(Instruction::Goto { target: done });
+                    self.place(*subroutine);
+                    output_group(self)?;
+                    self.emit(Instruction::Return { register: *back });
+                    self.place(done);
+                }
+            }
+        }
+
+        // Then the window functions, once every row is in, and the rows with their values.
+        if let Some(windowing) = &windowing {
+            for index in (1..coroutines.len()).rev() {
+                self.plan_parent = coroutines[index - 1].0;
+                self.explain_step(format!("SCAN (subquery-{})", coroutines[index].1));
+                if windowing.sorts[index - 1] {
+                    self.explain_step("USE TEMP B-TREE FOR ORDER BY".to_string());
+                }
+            }
+            self.plan_parent = plan_parent;
+            self.explain_step(format!("SCAN (subquery-{})", coroutines[0].1));
+
+            let values = self.compute_windows(windowing, &scope)?;
+            let scope = Scope {
+                sources: &sources,
+                depth,
+                outer,
+                grouped: Some(&windowing.grouped),
+            };
+            let cursor = windowing.cursor;
+            let done = self.label();
+            self.emit(Instruction::SorterSort {
+                cursor,
+                if_empty: done,
+            });
+            let top = self.label();
+            self.place(top);
+            let registers = windowing
+                .grouped
+                .windows
+                .iter()
+                .map(|(_, register)| *register);
+            for (column, target) in (0..)
+                .zip(windowing.kept())
+                .chain(values.into_iter().zip(registers))
+            {
+                self.emit(Instruction::Column {
+                    cursor,
+                    column,
+                    target,
+                });
+            }
+            self.body(&columns, &order_by, sorter, &output, &scope, emit_row)?;
+            self.emit(Instruction::SorterNext {
+                cursor,
+                target: top,
+            });
+            self.place(done);
+            self.explain_output(*distinct, sorted);
+        }
+
+        if let Some(cursor) = sorter {
+            let keys = order_by.len();
+            let first = self.registers(columns.len());
+            self.emit(Instruction::SorterSort {
+                cursor,
+                if_empty: end,
+            });
+            let top = self.label();
+            self.place(top);
+            for index in 0..columns.len() {
+                self.emit(Instruction::Column {
+                    cursor,
+                    column: keys + index,
+                    target: first + index,
+                });
+            }
+            self.output(&output, first, columns.len(), emit_row)?;
+            self.emit(Instruction::SorterNext {
+                cursor,
+                target: top,
+            });
+        }
+        self.place(end);
+        Ok(names)
+    }
+
+    /*
+     * Takes in a row of an aggregate query. Its group is found first: in the hash table by its
+     * terms, or for rows in the order of GROUP BY, the group the rows before were in unless the
+     * terms changed, handing that one out and starting another. Then each aggregate steps with its
+     * arguments, FILTER leaving out rows, and the columns outside the aggregates keep their values.
+     */
+    fn accumulate(&mut self, aggregating: &Aggregating, scope: &Scope) -> Result<()> {
+        let grouped = &aggregating.grouped;
+        match &aggregating.grouping {
+            Grouping::Single => {}
+            Grouping::Hashed(cursor) => {
+                let first = self.registers(grouped.terms.len());
+                for (index, (term, _)) in grouped.terms.iter().enumerate() {
+                    self.expr_into(term, scope, first + index)?;
+                }
+                self.emit(Instruction::GroupFind {
+                    cursor: *cursor,
+                    first,
+                    count: grouped.terms.len(),
+                });
+            }
+            Grouping::Streamed {
+                collations,
+                started,
+                subroutine,
+                back,
+            } => {
+                let first = self.registers(grouped.terms.len());
+                for (index, (term, _)) in grouped.terms.iter().enumerate() {
+                    self.expr_into(term, scope, first + index)?;
+                }
+                let (compare, changed, begin, step) =
+                    (self.label(), self.label(), self.label(), self.label());
+                self.emit(Instruction::If {
+                    register: *started,
+                    target: compare,
+                    jump_if_null: false,
+                });
+                self.emit(Instruction::Goto { target: begin });
+                self.place(compare);
+                for (index, ((_, previous), collation)) in
+                    grouped.terms.iter().zip(collations).enumerate()
+                {
+                    self.emit(Instruction::Compare {
+                        operator: BinaryOperator::NotEq,
+                        left: first + index,
+                        right: *previous,
+                        target: changed,
+                        jump_if_null: false,
+                        null_eq: true,
+                        affinities: (None, None),
+                        collation: *collation,
+                    });
+                }
+                self.emit(Instruction::Goto { target: step });
+                self.place(changed);
+                self.emit(Instruction::Gosub {
+                    register: *back,
+                    target: *subroutine,
+                });
+                self.place(begin);
+                for (index, (_, previous)) in grouped.terms.iter().enumerate() {
+                    self.emit(Instruction::Copy {
+                        source: first + index,
+                        target: *previous,
+                    });
+                }
+                self.emit(Instruction::AggReset {
+                    first: aggregating.accumulators,
+                    count: grouped.aggregates.len() + grouped.columns.len(),
+                });
+                self.emit(Instruction::Integer {
+                    value: 1,
+                    target: *started,
+                });
+                self.place(step);
+            }
+        }
+
+        if let Some(hit) = aggregating.hit {
+            self.emit(Instruction::Integer {
+                value: 0,
+                target: hit,
+            });
+        }
+        for (index, (expr, _)) in grouped.aggregates.iter().enumerate() {
+            let function = aggregate_function(expr)?;
+            let ExprKind::Function {
+                arguments,
+                distinct,
+                filter,
+                ..
+            } = &expr.kind
+            else {
+                unreachable!("aggregates are function calls")
+            };
+            if *distinct && arguments.len() != 1 {
+                return Err(DBError::Sql(
+                    "DISTINCT aggregates must have exactly one argument".to_string(),
+                ));
+            }
+            let skip = self.label();
+            if let Some(filter) = filter {
+                self.jump_if(filter, scope, false, skip, true)?;
+            }
+            let first_argument = self.registers(arguments.len());
+            self.aggregate_arguments = true;
+            let compiled = arguments
+                .iter()
+                .enumerate()
+                .try_for_each(|(index, argument)| {
+                    self.expr_into(argument, scope, first_argument + index)
+                });
+            self.aggregate_arguments = false;
+            compiled?;
+            let collation = match arguments.first() {
+                Some(argument) => expr_collation(argument, scope)?.unwrap_or(Collation::Binary),
+                None => Collation::Binary,
+            };
+            self.emit(Instruction::AggStep {
+                function,
+                first_argument,
+                argument_count: arguments.len(),
+                accumulator: aggregating.accumulators + index,
+                distinct: *distinct,
+                collation,
+                hit: aggregating.hit.filter(|_| function.is_min_or_max()),
+            });
+            self.place(skip);
+        }
+
+        // The columns keep the values of the first row, or of the row the min() or max() took.
+        let first_column = aggregating.accumulators + grouped.aggregates.len();
+        if let Some(hit) = aggregating.hit {
+            let keep = self.label();
+            self.emit(Instruction::IfNot {
+                register: hit,
+                target: keep,
+                jump_if_null: true,
+            });
+            self.emit(Instruction::AggReset {
+                first: first_column,
+                count: grouped.columns.len(),
+            });
+            self.place(keep);
+        }
+        for (index, ((cursor, column), _)) in grouped.columns.iter().enumerate() {
+            let source = scope
+                .sources
+                .iter()
+                .find(|source| source.cursor == *cursor)
+                .expect("the columns are of the query's own tables");
+            let register = self.register();
+            self.read_column(source, *column, register)?;
+            self.emit(Instruction::AggStep {
+                function: &functions::COLUMN,
+                first_argument: register,
+                argument_count: 1,
+                accumulator: first_column + index,
+                distinct: false,
+                collation: Collation::Binary,
+                hit: None,
+            });
+        }
+        Ok(())
+    }
+
+    // The values of the aggregates of a group and of the columns outside them, into registers.
+    fn group_values(&mut self, aggregating: &Aggregating) -> Result<()> {
+        let grouped = &aggregating.grouped;
+        for (index, (expr, target)) in grouped.aggregates.iter().enumerate() {
+            self.emit(Instruction::AggFinal {
+                function: aggregate_function(expr)?,
+                accumulator: aggregating.accumulators + index,
+                target: *target,
+            });
+        }
+        for (index, (_, target)) in grouped.columns.iter().enumerate() {
+            self.emit(Instruction::AggFinal {
+                function: &functions::COLUMN,
+                accumulator: aggregating.accumulators + grouped.aggregates.len() + index,
+                target: *target,
+            });
+        }
+        Ok(())
+    }
+
+    /*
+     * Keeps a row of a query with window functions in the sorter they are worked out over: what the
+     * output reads of it, the arguments and FILTER of each call and the terms of PARTITION BY and
+     * ORDER BY of each window. For an aggregate query the row is a group, whose values are in the
+     * registers of its group already, other queries read the columns of the tables.
+     */
+    fn window_row(&mut self, windowing: &Windowing, scope: &Scope) -> Result<()> {
+        let first = self.registers(windowing.width);
+        match scope.grouped {
+            Some(_) => {
+                for (index, source) in windowing.kept().into_iter().enumerate() {
+                    self.emit(Instruction::Copy {
+                        source,
+                        target: first + index,
+                    });
+                }
+            }
+            None => {
+                for (index, ((cursor, column), _)) in windowing.grouped.columns.iter().enumerate() {
+                    let source = scope
+                        .sources
+                        .iter()
+                        .find(|source| source.cursor == *cursor)
+                        .expect("the columns are of the query's own tables");
+                    self.read_column(source, *column, first + index)?;
+                }
+            }
+        }
+        let calls = windowing.passes.iter().flat_map(|pass| pass.calls.iter());
+        for ((call, _), (columns, filter_column)) in calls.zip(windowing.arguments.iter()) {
+            let ExprKind::Function {
+                arguments, filter, ..
+            } = &call.kind
+            else {
+                unreachable!("window functions are function calls")
+            };
+            for (column, argument) in columns.clone().zip(arguments) {
+                self.expr_into(argument, scope, first + column)?;
+            }
+            if let (Some(column), Some(filter)) = (filter_column, filter) {
+                self.expr_into(filter, scope, first + column)?;
+            }
+        }
+        for (pass, keys) in windowing.passes.iter().zip(windowing.keys.iter()) {
+            for (index, term) in pass.keys.iter().enumerate() {
+                let SortKey::Expr(expr) = &term.key else {
+                    unreachable!("the terms of windows are expressions")
+                };
+                self.expr_into(expr, scope, first + keys + index)?;
+            }
+        }
+        self.emit(Instruction::SorterInsert {
+            cursor: windowing.cursor,
+            first,
+            count: windowing.width,
+        });
+        Ok(())
+    }
+
+    /*
+     * Works out the window functions over the rows in the sorter, the calls of the last window
+     * first. The rows are sorted for each window that wants them in another order, and each call
+     * adds a column with its values. Returns the columns the values of the calls are in.
+     */
+    fn compute_windows(&mut self, windowing: &Windowing, scope: &Scope) -> Result<Vec<usize>> {
+        let mut values = vec![0; windowing.arguments.len()];
+        let mut column = windowing.width;
+        let mut first_call = windowing.arguments.len();
+        for (index, pass) in windowing.passes.iter().enumerate().rev() {
+            first_call -= pass.calls.len();
+            let keys: Vec<(usize, SortOrder)> = (windowing.keys[index]..)
+                .zip(pass.keys.iter().map(|term| term.order))
+                .collect();
+            if windowing.sorts[index] {
+                self.emit(Instruction::WindowSort {
+                    cursor: windowing.cursor,
+                    keys: keys.clone(),
+                });
+            }
+            let (partition, order) = keys.split_at(pass.window.partition_by.len());
+            let frame = pass.window.frame.as_ref().expect("passes have frames");
+            let (start, end) = windowing.edges[index];
+            for (offset, (call, function)) in pass.calls.iter().enumerate() {
+                let ExprKind::Function { arguments, .. } = &call.kind else {
+                    unreachable!("window functions are function calls")
+                };
+                let collation = match arguments.first() {
+                    Some(argument) => expr_collation(argument, scope)?.unwrap_or(Collation::Binary),
+                    None => Collation::Binary,
+                };
+                let (arguments, filter) = windowing.arguments[first_call + offset].clone();
+                self.emit(Instruction::WindowCall {
+                    cursor: windowing.cursor,
+                    call: Box::new(WindowCall {
+                        function: *function,
+                        arguments,
+                        filter,
+                        partition: partition
+                            .iter()
+                            .map(|(column, order)| (*column, order.collation))
+                            .collect(),
+                        order: order.to_vec(),
+                        units: frame.units,
+                        start,
+                        end,
+                        exclude: frame.exclude,
+                        collation,
+                    }),
+                });
+                values[first_call + offset] = column;
+                column += 1;
+            }
+        }
+        Ok(values)
+    }
+
+    // An end of a frame, with its offset in a register. An offset has to be a constant: SQLite
+    // takes any other expression for NULL, which is no offset a frame can have.
+    fn frame_edge(&mut self, bound: &FrameBound, scope: &Scope) -> Result<FrameEdge> {
+        let mut offset = |offset: &Expr| -> Result<Register> {
+            if is_constant(offset) {
+                return self.expr(offset, scope);
+            }
+            let target = self.register();
+            self.emit(Instruction::Null { target });
+            Ok(target)
+        };
+        Ok(match bound {
+            FrameBound::UnboundedPreceding => FrameEdge::UnboundedPreceding,
+            FrameBound::Preceding(expr) => FrameEdge::Preceding(offset(expr)?),
+            FrameBound::CurrentRow => FrameEdge::CurrentRow,
+            FrameBound::Following(expr) => FrameEdge::Following(offset(expr)?),
+            FrameBound::UnboundedFollowing => FrameEdge::UnboundedFollowing,
+        })
+    }
+
+    /*
+     * Looks up the tables of FROM. Returns them with how each is joined with the tables to its left,
+     * and what the names of the columns a RIGHT or FULL JOIN merged stand for.
+     */
+    #[allow(clippy::type_complexity)]
+    fn join_sources(
+        &mut self,
+        from: &FromClause,
+        depth: usize,
+    ) -> Result<(Vec<Source<'a>>, Vec<Joined>, Vec<(String, Expr)>)> {
+        let first = (false, JoinKind::Inner, &from.first, None);
+        let rest = from.joins.iter().map(|join| {
+            (
+                join.natural,
+                join.kind,
+                &join.table,
+                join.constraint.as_ref(),
+            )
+        });
+        let mut sources: Vec<Source<'a>> = Vec::new();
+        let mut joins = Vec::new();
+        let mut merged: Vec<(String, Expr)> = Vec::new();
+        for (natural, kind, table, constraint) in std::iter::once(first).chain(rest) {
+            let TableOrSubquery::Table {
+                name,
+                alias,
+                indexed,
+            } = table
+            else {
+                return Err(DBError::Sql(
+                    "subqueries in FROM are not supported".to_string(),
+                ));
+            };
+            let tables: &'a HashMap<String, Table> = self.tables;
+            let table = name
+                .schema
+                .as_ref()
+                .is_none_or(|schema| {
+                    ["main", "temp"]
+                        .iter()
+                        .any(|known| known.eq_ignore_ascii_case(schema))
+                })
+                .then(|| tables.get(&name.name.to_lowercase()))
+                .flatten()
+                .ok_or_else(|| DBError::Sql(format!("no such table: {}", name.name)))?;
+            if table.without_rowid {
+                return Err(DBError::Sql(format!(
+                    "querying WITHOUT ROWID table {} is not supported",
+                    table.name
+                )));
+            }
+            let mut source = Source {
+                table,
+                name: alias.clone().unwrap_or_else(|| table.name.clone()),
+                cursor: self.cursor(),
+                depth,
+                covering: None,
+                merged: Vec::new(),
+            };
+
+            // NATURAL joins on the columns the table has in common with the tables to its left.
+            let using: Vec<String> = match (natural, constraint) {
+                (true, Some(_)) => {
+                    return Err(DBError::Sql(
+                        "a NATURAL join may not have an ON or USING clause".to_string(),
+                    ))
+                }
+                (true, None) => table
+                    .columns
+                    .iter()
+                    .filter(|column| {
+                        sources.iter().any(|left| {
+                            matches!(left.resolve(None, &column.name), Some(ColumnRef::Column(_)))
+                        })
+                    })
+                    .map(|column| column.name.clone())
+                    .collect(),
+                (false, Some(JoinConstraint::Using(names))) => names.clone(),
+                _ => Vec::new(),
+            };
+            let mut on = match constraint {
+                Some(JoinConstraint::On(expr)) => {
+                    let mut expr = expr.clone();
+                    replace_merged(&mut expr, &merged);
+                    Some(expr)
+                }
+                _ => None,
+            };
+            for name in using {
+                let missing = || {
+                    DBError::Sql(format!(
+                        "cannot join using column {} - column not present in both tables",
+                        name
+                    ))
+                };
+                let column = table
+                    .columns
+                    .iter()
+                    .position(|column| column.name.eq_ignore_ascii_case(&name))
+                    .ok_or_else(missing)?;
+                let left: Vec<(&Source, usize)> = sources
+                    .iter()
+                    .filter_map(|left| match left.resolve(None, &name)? {
+                        ColumnRef::Column(index) => Some((left, index)),
+                        ColumnRef::Rowid => None,
+                    })
+                    .collect();
+                let (left_source, left_column) = match left[..] {
+                    [found] => found,
+                    [] => return Err(missing()),
+                    _ => {
+                        return Err(DBError::Sql(format!(
+                            "ambiguous reference to {} in USING()",
+                            name
+                        )))
+                    }
+                };
+                let left_expr = merged
+                    .iter()
+                    .find(|(merged_name, _)| merged_name.eq_ignore_ascii_case(&name))
+                    .map(|(_, expr)| expr.clone())
+                    .unwrap_or_else(|| {
+                        column_expr(
+                            &left_source.name,
+                            &left_source.table.columns[left_column].name,
+                        )
+                    });
+                let right_expr = column_expr(&source.name, &table.columns[column].name);
+                let equal = binary_expr(BinaryOperator::Eq, left_expr.clone(), right_expr.clone());
+                on = Some(match on {
+                    Some(on) => binary_expr(BinaryOperator::And, on, equal),
+                    None => equal,
+                });
+                source.merged.push(column);
+                if matches!(kind, JoinKind::Right | JoinKind::Full) {
+                    let coalesced = Expr {
+                        kind: ExprKind::Function {
+                            name: "coalesce".to_string(),
+                            arguments: vec![left_expr, right_expr],
+                            distinct: false,
+                            star: false,
+                            filter: None,
+                            over: None,
+                        },
+                        span: Default::default(),
+                    };
+                    merged.retain(|(merged_name, _)| !merged_name.eq_ignore_ascii_case(&name));
+                    merged.push((name.clone(), coalesced));
+                }
+            }
+            sources.push(source);
+            joins.push(Joined {
+                kind,
+                indexed: indexed.clone(),
+                on,
+            });
+        }
+        Ok((sources, joins, merged))
+    }
+
+    // LIMIT and OFFSET, which cannot refer to columns. A negative LIMIT is no limit at all and a
+    // negative OFFSET is none.
+    fn limit(&mut self, limit: &Limit, depth: usize, output: &mut Output) -> Result<()> {
+        let empty = Scope {
+            sources: &[],
+            depth,
+            outer: None,
+            grouped: None,
+        };
+        let register = self.constant_integer(&limit.limit, &empty)?;
+        self.emit(Instruction::IfNot {
+            register,
+            target: output.end,
+            jump_if_null: false,
+        });
+        output.limit = Some(register);
+        if let Some(offset) = &limit.offset {
+            output.offset = Some(self.constant_integer(offset, &empty)?);
+        }
+        Ok(())
+    }
+
+    // Picks the order the loops go through the tables of FROM in, see `planner::join_order`.
+    fn join_order(&self, planning: &Planning<'_, '_, 'a>) -> Result<Vec<usize>> {
+        // The table on the right of an outer join or a CROSS JOIN stays where it is, with the tables
+        // to its left before it and those to its right after, like SQLite keeps it.
+        let fixed = |source: usize| {
+            let kind = planning.joins[source].kind;
+            source > 0 && (is_outer(kind) || kind == JoinKind::Cross)
+        };
+        planner::join_order(planning.sources.len(), fixed, |source, outer| {
+            let outer_join = !outer.is_empty() && is_outer(planning.joins[source].kind);
+            let plan = self.plan_level(planning, source, outer, outer_join, &[])?;
+            Ok((plan.cost, plan.rows))
+        })
+    }
+
+    /*
+     * Plans the loops over the tables in the given order, `available` being tables whose rows are
+     * there before the first loop. Every term of WHERE and ON goes to the first loop that has the
+     * rows it depends on, where it narrows down how the loop gets to its rows or is checked
+     * against them. ORDER BY goes to the first loop.
+     */
+    fn plan_levels<'e>(
+        &self,
+        planning: &Planning<'_, 'e, 'a>,
+        order: &[usize],
+        available: &[usize],
+        order_by: &[SortTerm],
+    ) -> Result<Vec<Level<'e, 'a>>> {
+        let mut available = available.to_vec();
+        let mut placed = vec![false; planning.clauses.len()];
+        let mut levels = Vec::new();
+        for (position, source) in order.iter().copied().enumerate() {
+            let kind = planning.joins[source].kind;
+            let outer_join = position > 0 && is_outer(kind);
+            let order_by = match position {
+                0 => order_by,
+                _ => &[],
+            };
+            let plan = self.plan_level(planning, source, &available, outer_join, order_by)?;
+            available.push(source);
+            let kept = |expr: &Expr| plan.filter.iter().any(|kept| std::ptr::eq(*kept, expr));
+            let mut on = Vec::new();
+            let mut filter = Vec::new();
+            for (clause, placed) in planning.clauses.iter().zip(placed.iter_mut()) {
+                if *placed {
+                    continue;
+                }
+                if outer_join && clause.join == Some(source) {
+                    *placed = true;
+                    if kept(clause.expr) {
+                        on.push(clause.expr);
+                    }
+                } else if planning.is_general(clause)
+                    && clause
+                        .sources
+                        .iter()
+                        .all(|source| available.contains(source))
+                {
+                    *placed = true;
+                    // The plan of an outer join only goes by its ON clause.
+                    if outer_join || kept(clause.expr) {
+                        filter.push(clause.expr);
+                    }
+                }
+            }
+            levels.push(Level {
+                source,
+                plan,
+                index_cursor: None,
+                on,
+                filter,
+                left: outer_join && matches!(kind, JoinKind::Left | JoinKind::Full),
+                matched: None,
+                unmatched: None,
+            });
+        }
+        Ok(levels)
+    }
+
+    /*
+     * Has the planner pick how the loop over a table gets to its rows, given the constraints the
+     * terms that can be checked by then put on its columns, the order ORDER BY wants and the columns
+     * the query reads. Rows of the tables in `available` are there already. The table on the right
+     * of an outer join only goes by its ON clause.
+     */
+    fn plan_level<'e>(
+        &self,
+        planning: &Planning<'_, 'e, 'a>,
+        source: usize,
+        available: &[usize],
+        outer_join: bool,
+        order_by: &[SortTerm],
+    ) -> Result<Plan<'e, 'a>> {
+        let target = &planning.sources[source];
+        let unavailable: Vec<&Source> = planning
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| !available.contains(other))
+            .map(|(_, other)| other)
+            .collect();
+        let mut terms = Vec::new();
+        for clause in planning.clauses {
+            let offered = match outer_join {
+                true => clause.join == Some(source),
+                false => {
+                    planning.is_general(clause)
+                        && clause
+                            .sources
+                            .iter()
+                            .all(|other| *other == source || available.contains(other))
+                }
+            };
+            if offered {
+                self.terms(
+                    clause.expr,
+                    target,
+                    &unavailable,
+                    planning.scope,
+                    &mut terms,
+                )?;
+            }
+        }
+        let order: Vec<OrderTerm> = order_by
+            .iter()
+            .map(|term| OrderTerm {
+                column: match &term.key {
+                    SortKey::Result(position) => table_column(&planning.columns[*position], target),
+                    SortKey::Expr(expr) => table_column(expr, target),
+                },
+                order: term.order,
+            })
+            .collect();
+        let indexes: &'a HashMap<String, Index> = self.indexes;
+        let mut indexes: Vec<&'a Index> = indexes
+            .values()
+            .filter(|index| index.table_name.eq_ignore_ascii_case(&target.table.name))
+            .collect();
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
+        let usable_partial: Vec<&str> = indexes
+            .iter()
+            .filter(|index| {
+                index
+                    .where_clause
+                    .as_ref()
+                    .is_some_and(|where_clause| partial_index_usable(where_clause, &terms, target))
+            })
+            .map(|index| index.name.as_str())
+            .collect();
+        planner::plan(
+            target.table,
+            &indexes,
+            planning.joins[source].indexed.as_ref(),
+            &terms,
+            &order,
+            &planning.used[source],
+            &usable_partial,
+        )
+    }
+
+    // The steps of EXPLAIN QUERY PLAN for the loops.
+    fn explain_levels(&mut self, levels: &[Level], sources: &[Source]) {
+        for level in levels {
+            let source = &sources[level.source];
+            let mut detail = level.plan.describe(&source.name, source.table);
+            if level.left {
+                detail.push_str(" LEFT-JOIN");
+            }
+            self.explain_step(detail);
+        }
+    }
+
+    // Gives the loops going through an index a cursor on it. The query reads the columns of a table
+    // from the index when it covers them.
+    fn index_cursors(&mut self, levels: &mut [Level<'_, 'a>], sources: &mut [Source<'a>]) {
+        for level in levels.iter_mut() {
+            if let Access::Index {
+                index, covering, ..
+            } = &level.plan.access
+            {
+                let cursor = self.cursor();
+                level.index_cursor = Some(cursor);
+                if *covering {
+                    sources[level.source].covering = Some((cursor, *index));
+                }
+            }
+        }
+    }
+
+    // Breaks WHERE down into the terms ANDed together, with the constraints they put on columns of
+    // the source. A constraint's value cannot depend on the rows of the tables in `unavailable`,
+    // which has the source in it.
+    fn terms<'e>(
+        &self,
+        expr: &'e Expr,
+        source: &Source,
+        unavailable: &[&Source],
+        scope: &Scope,
+        terms: &mut Vec<Term<'e>>,
+    ) -> Result<()> {
+        let depends = |value: &Expr| unavailable.iter().any(|other| refers_to(value, other));
+        let mut constraints = Vec::new();
+        match &expr.kind {
+            ExprKind::Binary {
+                operator: BinaryOperator::And,
+                left,
+                right,
+            } => {
+                self.terms(left, source, unavailable, scope, terms)?;
+                return self.terms(right, source, unavailable, scope, terms);
+            }
+            ExprKind::Binary {
+                operator:
+                    operator @ (BinaryOperator::Eq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::LtEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::GtEq),
+                left,
+                right,
+            } => {
+                let flipped = match operator {
+                    BinaryOperator::Lt => BinaryOperator::Gt,
+                    BinaryOperator::LtEq => BinaryOperator::GtEq,
+                    BinaryOperator::Gt => BinaryOperator::Lt,
+                    BinaryOperator::GtEq => BinaryOperator::LtEq,
+                    operator => *operator,
+                };
+                for (column, value, operator) in
+                    [(&**left, &**right, *operator), (&**right, &**left, flipped)]
+                {
+                    match table_column(column, source) {
+                        Some(column) if !depends(value) => {
+                            constraints.push(Constraint {
+                                column,
+                                operator,
+                                value,
+                                value_affinity: self.affinity(value, scope),
+                                collation: comparison_collation(left, right, scope)?,
+                            });
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // Both ends or neither, the term is left to the filter unless both are taken care of.
+            ExprKind::Between {
+                negated: false,
+                expr: column_expr,
+                low,
+                high,
+            } if !depends(low) && !depends(high) => {
+                if let Some(column) = table_column(column_expr, source) {
+                    for (operator, value) in [
+                        (BinaryOperator::GtEq, &**low),
+                        (BinaryOperator::LtEq, &**high),
+                    ] {
+                        constraints.push(Constraint {
+                            column,
+                            operator,
+                            value,
+                            value_affinity: self.affinity(value, scope),
+                            collation: comparison_collation(column_expr, value, scope)?,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+        terms.push(Term { expr, constraints });
+        Ok(())
+    }
+
+    // Opens the cursors on the tables and indexes the loops go through.
+    fn open(&mut self, levels: &[Level], scope: &Scope) {
+        for level in levels {
+            let source = &scope.sources[level.source];
+            if source.covering.is_none() {
+                self.emit(Instruction::OpenRead {
+                    cursor: source.cursor,
+                    root_page: source.table.root_page,
+                    table: source.table.name.clone(),
+                });
+            }
+            if let (Access::Index { index, .. }, Some(cursor)) =
+                (&level.plan.access, level.index_cursor)
+            {
+                self.emit(Instruction::OpenIndex {
+                    cursor,
+                    root_page: index.root_page,
+                    index: index.name.clone(),
+                    key: index.key_orders(),
+                });
+            }
+        }
+    }
+
+    // Emits the loops of the levels, each inside the one before, with `body` running for every
+    // combination of rows they come to.
+    fn nest(
+        &mut self,
+        levels: &[Level],
+        scope: &Scope,
+        body: &mut dyn FnMut(&mut Compiler<'a>, &Scope) -> Result<()>,
+    ) -> Result<()> {
+        self.open(levels, scope);
+        let mut loops = Vec::new();
+        for level in levels {
+            loops.push(self.begin_loop(level, scope)?);
+        }
+        body(self, scope)?;
+        for (level, open) in levels.iter().zip(loops).rev() {
+            self.end_loop(level, open, scope);
+        }
+        Ok(())
+    }
+
+    /*
+     * The top of the loop of a level: gets to a row and checks it against the terms of the level.
+     * For an outer join the row matches once it passes the ON clause, and the round of the loop
+     * with a row of NULLs joins in after that.
+     */
+    fn begin_loop(&mut self, level: &Level, scope: &Scope) -> Result<Loop> {
+        let source = &scope.sources[level.source];
+        let next = self.label();
+        let done = self.label();
+        let left = match level.left {
+            true => {
+                let (matched, null_round) = (self.register(), self.register());
+                self.emit(Instruction::Integer {
+                    value: 0,
+                    target: matched,
+                });
+                self.emit(Instruction::Integer {
+                    value: 0,
+                    target: null_round,
+                });
+                Some((matched, null_round))
+            }
+            false => None,
+        };
+        let scan = self.access(source, &level.plan, level.index_cursor, scope, next, done)?;
+        if let Some(cursor) = level.unmatched {
+            let rowid = self.register();
+            self.rowid(source, rowid);
+            self.emit(Instruction::Found {
+                cursor,
+                first: rowid,
+                count: 1,
+                target: next,
+            });
+        }
+        for term in level.on.iter() {
+            self.jump_if(term, scope, false, next, true)?;
+        }
+        if let Some((matched, _)) = left {
+            self.emit(Instruction::Integer {
+                value: 1,
+                target: matched,
+            });
+        }
+        if let Some(cursor) = level.matched {
+            let rowid = self.register();
+            self.rowid(source, rowid);
+            self.emit(Instruction::IdxInsert {
+                cursor,
+                first: rowid,
+                count: 1,
+            });
+        }
+        let resume = self.label();
+        self.place(resume);
+        for term in level.filter.iter() {
+            self.jump_if(term, scope, false, next, true)?;
+        }
+        Ok(Loop {
+            next,
+            done,
+            scan,
+            left: left.map(|(matched, null_round)| (matched, null_round, resume)),
+        })
+    }
+
+    // The bottom of the loop of a level. An outer join that found no match goes around once more
+    // with a row of NULLs.
+    fn end_loop(&mut self, level: &Level, open: Loop, scope: &Scope) {
+        let end = self.label();
+        self.place(open.next);
+        if let Some((_, null_round, _)) = open.left {
+            self.emit(Instruction::If {
+                register: null_round,
+                target: end,
+                jump_if_null: false,
+            });
+        }
+        if let Some((cursor, top, reverse)) = open.scan {
+            self.emit(match reverse {
+                false => Instruction::Next {
+                    cursor,
+                    target: top,
+                },
+                true => Instruction::Prev {
+                    cursor,
+                    target: top,
+                },
+            });
+        }
+        self.place(open.done);
+        if let Some((matched, null_round, resume)) = open.left {
+            self.emit(Instruction::If {
+                register: matched,
+                target: end,
+                jump_if_null: false,
+            });
+            self.null_row(&scope.sources[level.source]);
+            self.emit(Instruction::Integer {
+                value: 1,
+                target: null_round,
+            });
+            self.emit(Instruction::Goto { target: resume });
+        }
+        self.place(end);
+    }
+
+    // Hands a row of the loops on: into the sorter for ORDER BY, or out through DISTINCT, OFFSET
+    // and LIMIT.
+    fn body(
+        &mut self,
+        columns: &[Expr],
+        order_by: &[SortTerm],
+        sorter: Option<CursorId>,
+        output: &Output,
+        scope: &Scope,
+        emit_row: &mut RowHandler<'_, 'a>,
+    ) -> Result<()> {
+        match sorter {
+            // The sorter gets the sort keys followed by the result columns.
+            Some(cursor) => {
+                let keys = order_by.len();
+                let first = self.registers(keys + columns.len());
+                for (index, expr) in columns.iter().enumerate() {
+                    self.expr_into(expr, scope, first + keys + index)?;
+                }
+                for (index, term) in order_by.iter().enumerate() {
+                    match &term.key {
+                        SortKey::Result(column) => {
+                            self.emit(Instruction::Copy {
+                                source: first + keys + column,
+                                target: first + index,
+                            });
+                        }
+                        SortKey::Expr(expr) => self.expr_into(expr, scope, first + index)?,
+                    }
+                }
+                self.emit(Instruction::SorterInsert {
+                    cursor,
+                    first,
+                    count: keys + columns.len(),
+                });
+            }
+            None => {
+                let first = self.registers(columns.len());
+                for (index, expr) in columns.iter().enumerate() {
+                    self.expr_into(expr, scope, first + index)?;
+                }
+                self.output(output, first, columns.len(), emit_row)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Makes the source read NULL for every column, through the index too when it covers the query.
+    fn null_row(&mut self, source: &Source) {
+        self.emit(Instruction::NullRow {
+            cursor: source.cursor,
+        });
+        if let Some((cursor, _)) = source.covering {
+            self.emit(Instruction::NullRow { cursor });
+        }
+    }
+
+    fn rowid(&mut self, source: &Source, target: Register) {
+        self.emit(match source.covering {
+            Some((cursor, _)) => Instruction::IdxRowid { cursor, target },
+            None => Instruction::Rowid {
+                cursor: source.cursor,
+                target,
+            },
+        });
+    }
+
+    /*
+     * Moves the cursors of the plan to the first row. For a loop it returns the cursor the loop
+     * moves along, the top of the loop and whether it moves backwards, for a lookup of a single row
+     * None. Jumps to `done` when there are no rows, and to `next` for an index entry whose row is
+     * not there.
+     */
+    fn access(
+        &mut self,
+        source: &Source,
+        plan: &Plan,
+        index_cursor: Option<CursorId>,
+        scope: &Scope,
+        next: Address,
+        done: Address,
+    ) -> Result<Option<(CursorId, Address, bool)>> {
+        let reverse = plan.reverse;
+        let first_entry = |cursor| match reverse {
+            false => Instruction::Rewind {
+                cursor,
+                if_empty: done,
+            },
+            true => Instruction::Last {
+                cursor,
+                if_empty: done,
+            },
+        };
+        match &plan.access {
+            Access::Scan => {
+                self.emit(first_entry(source.cursor));
+                let top = self.label();
+                self.place(top);
+                Ok(Some((source.cursor, top, reverse)))
+            }
+            Access::RowidEq(key) => {
+                let rowid = self.expr(key.value, scope)?;
+                self.emit(Instruction::SeekRowid {
+                    cursor: source.cursor,
+                    rowid,
+                    if_not_found: done,
+                });
+                Ok(None)
+            }
+            Access::RowidRange { lower, upper } => {
+                let (start, end) = match reverse {
+                    false => (lower, upper),
+                    true => (upper, lower),
+                };
+                match start {
+                    Some(bound) => {
+                        let first = self.register();
+                        self.key(&bound.key, scope, first, done)?;
+                        self.emit(Instruction::Seek {
+                            operator: seek_operator(bound.inclusive, reverse),
+                            cursor: source.cursor,
+                            first,
+                            count: 1,
+                            if_not_found: done,
+                        });
+                    }
+                    None => {
+                        self.emit(first_entry(source.cursor));
+                    }
+                }
+                let end = match end {
+                    Some(bound) => {
+                        let register = self.register();
+                        self.key(&bound.key, scope, register, done)?;
+                        Some((register, bound))
+                    }
+                    None => None,
+                };
+                let top = self.label();
+                self.place(top);
+                if let Some((key, bound)) = end {
+                    let rowid = self.register();
+                    self.emit(Instruction::Rowid {
+                        cursor: source.cursor,
+                        target: rowid,
+                    });
+                    self.emit(Instruction::Compare {
+                        operator: past_operator(bound.inclusive, reverse),
+                        left: rowid,
+                        right: key,
+                        target: done,
+                        jump_if_null: true,
+                        null_eq: false,
+                        affinities: (Some(ColumnType::Integer), bound.key.value_affinity),
+                        collation: Collation::Binary,
+                    });
+                }
+                Ok(Some((source.cursor, top, reverse)))
+            }
+            Access::Index {
+                index,
+                equal,
+                lower,
+                upper,
+                ..
+            } => {
+                let cursor = index_cursor.expect("an index plan has an index cursor");
+                let first = self.registers(equal.len() + 1);
+                for (position, key) in equal.iter().enumerate() {
+                    self.key(key, scope, first + position, done)?;
+                }
+
+                // The ends of the range in the order of the index. A range leaves out the NULLs,
+                // which come first in an ascending column and last in a descending one.
+                let descending = index
+                    .columns
+                    .get(equal.len())
+                    .is_some_and(|column| column.descending);
+                let (mut first_end, mut last_end) = match descending {
+                    false => (lower.map(RangeEnd::Key), upper.map(RangeEnd::Key)),
+                    true => (upper.map(RangeEnd::Key), lower.map(RangeEnd::Key)),
+                };
+                if lower.is_some() || upper.is_some() {
+                    match descending {
+                        false => first_end = first_end.or(Some(RangeEnd::NotNull)),
+                        true => last_end = last_end.or(Some(RangeEnd::NotNull)),
+                    }
+                }
+                let (start, end) = match reverse {
+                    false => (first_end, last_end),
+                    true => (last_end, first_end),
+                };
+
+                let range = first + equal.len();
+                match start {
+                    Some(start) => {
+                        let inclusive = self.range_end(start, scope, range, done)?;
+                        self.emit(Instruction::Seek {
+                            operator: seek_operator(inclusive, reverse),
+                            cursor,
+                            first,
+                            count: equal.len() + 1,
+                            if_not_found: done,
+                        });
+                    }
+                    None if !equal.is_empty() => {
+                        self.emit(Instruction::Seek {
+                            operator: seek_operator(true, reverse),
+                            cursor,
+                            first,
+                            count: equal.len(),
+                            if_not_found: done,
+                        });
+                    }
+                    None => {
+                        self.emit(first_entry(cursor));
+                    }
+                }
+                let stop = match end {
+                    Some(end) => {
+                        let inclusive = self.range_end(end, scope, range, done)?;
+                        Some((past_operator(inclusive, reverse), equal.len() + 1))
+                    }
+                    None if !equal.is_empty() => Some((past_operator(true, reverse), equal.len())),
+                    None => None,
+                };
+                let top = self.label();
+                self.place(top);
+                if let Some((operator, count)) = stop {
+                    self.emit(Instruction::IdxCompare {
+                        operator,
+                        cursor,
+                        first,
+                        count,
+                        target: done,
+                    });
+                }
+                if source.covering.is_none() {
+                    let rowid = self.register();
+                    self.emit(Instruction::IdxRowid {
+                        cursor,
+                        target: rowid,
+                    });
+                    self.emit(Instruction::SeekRowid {
+                        cursor: source.cursor,
+                        rowid,
+                        if_not_found: next,
+                    });
+                }
+                Ok(Some((cursor, top, reverse)))
+            }
+        }
+    }
+
+    // Works out a key the rows are looked up by, with the affinity it takes on. No row matches a
+    // NULL key.
+    fn key(&mut self, key: &Key, scope: &Scope, target: Register, done: Address) -> Result<()> {
+        self.expr_into(key.value, scope, target)?;
+        if key.affinity != ColumnType::Blob {
+            self.emit(Instruction::Affinity {
+                first: target,
+                affinities: vec![key.affinity],
+            });
+        }
+        self.emit(Instruction::IsNull {
+            register: target,
+            target: done,
+        });
+        Ok(())
+    }
+
+    // Works out an end of an index range into `target`, returning whether it is inclusive.
+    fn range_end(
+        &mut self,
+        end: RangeEnd,
+        scope: &Scope,
+        target: Register,
+        done: Address,
+    ) -> Result<bool> {
+        match end {
+            RangeEnd::Key(bound) => {
+                self.key(&bound.key, scope, target, done)?;
+                Ok(bound.inclusive)
+            }
+            RangeEnd::NotNull => {
+                self.emit(Instruction::Null { target });
+                Ok(false)
+            }
+        }
+    }
+
+    fn output(
+        &mut self,
+        output: &Output,
+        first: Register,
+        count: usize,
+        emit_row: &mut RowHandler<'_, 'a>,
+    ) -> Result<()> {
+        let skip = self.label();
+        if let Some(cursor) = output.distinct {
+            self.emit(Instruction::Found {
+                cursor,
+                first,
+                count,
+                target: skip,
+            });
+            self.emit(Instruction::IdxInsert {
+                cursor,
+                first,
+                count,
+            });
+        }
+        if let Some(register) = output.offset {
+            self.emit(Instruction::IfPos {
+                register,
+                target: skip,
+                decrement: 1,
+            });
+        }
+        emit_row(self, first, count)?;
+        if let Some(register) = output.limit {
+            self.emit(Instruction::DecrJumpZero {
+                register,
+                target: output.end,
+            });
+        }
+        self.place(skip);
+        Ok(())
+    }
+
+    // LIMIT and OFFSET, which have to be integers. Literals are checked right away.
+    fn constant_integer(&mut self, expr: &Expr, scope: &Scope) -> Result<Register> {
+        if let ExprKind::Literal(literal) = &expr.kind {
+            let value = match literal {
+                Literal::Integer(value) => Value::Integer(*value),
+                Literal::Real(value) => Value::Float(*value),
+                Literal::String(value) => Value::Text(value.clone()),
+                _ => Value::Null,
+            };
+            if !matches!(
+                value::apply_affinity(value, ColumnType::Numeric),
+                Value::Integer(_)
+            ) {
+                return Err(DBError::Sql("datatype mismatch".to_string()));
+            }
+        }
+        let register = self.expr(expr, scope)?;
+        self.emit(Instruction::MustBeInt { register });
+        Ok(register)
+    }
+
+    /*
+     * Compiles a subquery inline, handing each of its rows to `emit_row`. Returns whether it is
+     * correlated, referring to the rows of the queries it is in. `kind` is what EXPLAIN QUERY PLAN
+     * calls it, SCALAR or LIST.
+     */
+    fn subquery(
+        &mut self,
+        select: &Select,
+        scope: &Scope,
+        kind: &str,
+        emit_row: &mut RowHandler<'_, 'a>,
+    ) -> Result<bool> {
+        self.subquery_count += 1;
+        let number = self.subquery_count;
+        let step = self.explain_step(String::new());
+        let parent = std::mem::replace(&mut self.plan_parent, step);
+        let outermost = std::mem::replace(&mut self.outermost_reference, usize::MAX);
+        let compiled = self.select(select, Some(scope), emit_row);
+        let referenced = self.outermost_reference;
+        self.outermost_reference = outermost.min(referenced);
+        self.plan_parent = parent;
+        compiled?;
+        let correlated = referenced <= scope.depth;
+        self.plan[step - 1].detail = format!(
+            "{}{} SUBQUERY {}",
+            if correlated { "CORRELATED " } else { "" },
+            kind,
+            number
+        );
+        Ok(correlated)
+    }
+
+    // EXISTS and scalar subqueries, which only look at the first row.
+    fn first_row(
+        &mut self,
+        select: &Select,
+        scope: &Scope,
+        exists: bool,
+        target: Register,
+    ) -> Result<()> {
+        let once = self.emit(Instruction::Noop);
+        let done = self.label();
+        self.emit(match exists {
+            true => Instruction::Integer { value: 0, target },
+            false => Instruction::Null { target },
+        });
+        let correlated =
+            self.subquery(select, scope, "SCALAR", &mut |compiler, first, count| {
+                if exists {
+                    compiler.emit(Instruction::Integer { value: 1, target });
+                } else {
+                    single_column(count)?;
+                    compiler.emit(Instruction::Copy {
+                        source: first,
+                        target,
+                    });
+                }
+                compiler.emit(Instruction::Goto { target: done });
+                Ok(())
+            })?;
+        self.place(done);
+        if !correlated {
+            self.instructions[once] = Instruction::Once { target: done };
+        }
+        Ok(())
+    }
+
+    /*
+     * `x IN (...)`: true when x equals one of the candidates, otherwise NULL if x or one of them is
+     * NULL, false if not. The candidates of a list compare with their own affinity, those of a
+     * subquery without one.
+     */
+    fn membership(
+        &mut self,
+        expr: &Expr,
+        list: Option<&[Expr]>,
+        select: Option<&Select>,
+        negated: bool,
+        scope: &Scope,
+        target: Register,
+    ) -> Result<()> {
+        let value = self.expr(expr, scope)?;
+        let unknown = self.register();
+        let found = self.label();
+        let null = self.label();
+        let done = self.label();
+        self.emit(Instruction::Integer {
+            value: negated as i64,
+            target,
+        });
+        self.emit(Instruction::Integer {
+            value: 0,
+            target: unknown,
+        });
+        for item in list.unwrap_or_default() {
+            let comparison = Comparison {
+                operator: BinaryOperator::Eq,
+                left: value,
+                right: self.expr(item, scope)?,
+                null_eq: false,
+                affinities: (self.affinity(expr, scope), self.affinity(item, scope)),
+                collation: comparison_collation(expr, item, scope)?,
+            };
+            self.candidate(&comparison, found, unknown);
+        }
+        if let Some(select) = select {
+            let affinities = (self.affinity(expr, scope), None);
+            let collation = expr_collation(expr, scope)?.unwrap_or(Collation::Binary);
+            self.subquery(select, scope, "LIST", &mut |compiler, first, count| {
+                single_column(count)?;
+                let comparison = Comparison {
+                    operator: BinaryOperator::Eq,
+                    left: value,
+                    right: first,
+                    null_eq: false,
+                    affinities,
+                    collation,
+                };
+                compiler.candidate(&comparison, found, unknown);
+                Ok(())
+            })?;
+        }
+        self.emit(Instruction::If {
+            register: unknown,
+            target: null,
+            jump_if_null: false,
+        });
+        self.emit(Instruction::Goto { target: done });
+        self.place(found);
+        self.emit(Instruction::Integer {
+            value: !negated as i64,
+            target,
+        });
+        self.emit(Instruction::Goto { target: done });
+        self.place(null);
+        self.emit(Instruction::Null { target });
+        self.place(done);
+        Ok(())
+    }
+
+    // Jumps to `found` if the candidate equals the value, and notes when either is NULL.
+    fn candidate(&mut self, comparison: &Comparison, found: Address, unknown: Register) {
+        let next = self.label();
+        self.emit(comparison.jump(found, false));
+        self.emit(comparison.negated().jump(next, false));
+        self.emit(Instruction::Integer {
+            value: 1,
+            target: unknown,
+        });
+        self.place(next);
+    }
+
+    // Evaluates the operands of a comparison, or of IS and IS NOT.
+    fn comparison(
+        &mut self,
+        operator: BinaryOperator,
+        left: &Expr,
+        right: &Expr,
+        scope: &Scope,
+    ) -> Result<Comparison> {
+        let (operator, null_eq) = match operator {
+            BinaryOperator::Is => (BinaryOperator::Eq, true),
+            BinaryOperator::IsNot => (BinaryOperator::NotEq, true),
+            operator => (operator, false),
+        };
+        Ok(Comparison {
+            operator,
+            left: self.expr(left, scope)?,
+            right: self.expr(right, scope)?,
+            null_eq,
+            affinities: (self.affinity(left, scope), self.affinity(right, scope)),
+            collation: comparison_collation(left, right, scope)?,
+        })
+    }
+
+    // The value of a comparison: 1 or 0, NULL when comparing with NULL.
+    fn comparison_value(&mut self, comparison: &Comparison, target: Register) {
+        let done = self.label();
+        self.emit(Instruction::Integer { value: 1, target });
+        self.emit(comparison.jump(done, false));
+        self.emit(Instruction::Integer { value: 0, target });
+        self.place(done);
+        if !comparison.null_eq {
+            self.emit(Instruction::ZeroOrNull {
+                left: comparison.left,
+                right: comparison.right,
+                target,
+            });
+        }
+    }
+
+    // The two comparisons of BETWEEN, true where x is outside the range.
+    fn between(
+        &mut self,
+        expr: &Expr,
+        low: &Expr,
+        high: &Expr,
+        scope: &Scope,
+    ) -> Result<(Comparison, Comparison)> {
+        let value = self.expr(expr, scope)?;
+        let bound = |compiler: &mut Compiler<'a>, operator, limit: &Expr| {
+            Ok::<_, DBError>(Comparison {
+                operator,
+                left: value,
+                right: compiler.expr(limit, scope)?,
+                null_eq: false,
+                affinities: (
+                    compiler.affinity(expr, scope),
+                    compiler.affinity(limit, scope),
+                ),
+                collation: comparison_collation(expr, limit, scope)?,
+            })
+        };
+        let below = bound(self, BinaryOperator::Lt, low)?;
+        let above = bound(self, BinaryOperator::Gt, high)?;
+        Ok((below, above))
+    }
+
+    /*
+     * Jumps to `label` when the expression is `when`, or NULL if `jump_if_null` is set. AND, OR,
+     * NOT, comparisons, BETWEEN and IS NULL turn into jumps directly, other expressions are worked
+     * out into a register first.
+     */
+    fn jump_if(
+        &mut self,
+        expr: &Expr,
+        scope: &Scope,
+        when: bool,
+        label: Address,
+        jump_if_null: bool,
+    ) -> Result<()> {
+        match &expr.kind {
+            ExprKind::Binary {
+                operator: operator @ (BinaryOperator::And | BinaryOperator::Or),
+                left,
+                right,
+            } => {
+                // `when` is true for every side of AND when it is true, and of OR when it is false.
+                if (*operator == BinaryOperator::And) != when {
+                    self.jump_if(left, scope, when, label, jump_if_null)?;
+                    self.jump_if(right, scope, when, label, jump_if_null)?;
+                } else {
+                    let skip = self.label();
+                    self.jump_if(left, scope, !when, skip, !jump_if_null)?;
+                    self.jump_if(right, scope, when, label, jump_if_null)?;
+                    self.place(skip);
+                }
+            }
+            ExprKind::Binary {
+                operator:
+                    operator @ (BinaryOperator::Eq
+                    | BinaryOperator::NotEq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::LtEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::GtEq
+                    | BinaryOperator::Is
+                    | BinaryOperator::IsNot),
+                left,
+                right,
+            } => {
+                let comparison = self.comparison(*operator, left, right, scope)?;
+                let comparison = if when {
+                    comparison
+                } else {
+                    comparison.negated()
+                };
+                self.emit(comparison.jump(label, jump_if_null));
+            }
+            ExprKind::Unary {
+                operator: UnaryOperator::Not,
+                expr,
+            } => self.jump_if(expr, scope, !when, label, jump_if_null)?,
+            ExprKind::IsNull { negated, expr } => {
+                let register = self.expr(expr, scope)?;
+                self.emit(match when != *negated {
+                    true => Instruction::IsNull {
+                        register,
+                        target: label,
+                    },
+                    false => Instruction::NotNull {
+                        register,
+                        target: label,
+                    },
+                });
+            }
+            ExprKind::Between {
+                negated,
+                expr,
+                low,
+                high,
+            } => {
+                let (below, above) = self.between(expr, low, high, scope)?;
+                if when == *negated {
+                    self.emit(below.jump(label, jump_if_null));
+                    self.emit(above.jump(label, jump_if_null));
+                } else {
+                    let skip = self.label();
+                    self.emit(below.jump(skip, !jump_if_null));
+                    self.emit(above.jump(skip, !jump_if_null));
+                    self.emit(Instruction::Goto { target: label });
+                    self.place(skip);
+                }
+            }
+            _ => {
+                let register = self.expr(expr, scope)?;
+                self.emit(match when {
+                    true => Instruction::If {
+                        register,
+                        target: label,
+                        jump_if_null,
+                    },
+                    false => Instruction::IfNot {
+                        register,
+                        target: label,
+                        jump_if_null,
+                    },
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Works an expression out into a register of its own.
+    fn expr(&mut self, expr: &Expr, scope: &Scope) -> Result<Register> {
+        let register = self.register();
+        self.expr_into(expr, scope, register)?;
+        Ok(register)
+    }
+
+    fn expr_into(&mut self, expr: &Expr, scope: &Scope, target: Register) -> Result<()> {
+        if let Some(source) = scope.grouped.and_then(|grouped| grouped.register(expr)) {
+            self.emit(Instruction::Copy { source, target });
+            return Ok(());
+        }
+        match &expr.kind {
+            ExprKind::Literal(literal) => {
+                let instruction = match literal {
+                    Literal::Null => Instruction::Null { target },
+                    Literal::Integer(value) => Instruction::Integer {
+                        value: *value,
+                        target,
+                    },
+                    Literal::Real(value) => Instruction::Real {
+                        value: *value,
+                        target,
+                    },
+                    Literal::String(value) => Instruction::String {
+                        value: value.clone(),
+                        target,
+                    },
+                    Literal::Blob(value) => Instruction::Blob {
+                        value: value.clone(),
+                        target,
+                    },
+                    Literal::CurrentDate => return self.call("current_date", &[], scope, target),
+                    Literal::CurrentTime => return self.call("current_time", &[], scope, target),
+                    Literal::CurrentTimestamp => {
+                        return self.call("current_timestamp", &[], scope, target)
+                    }
+                };
+                self.emit(instruction);
+            }
+            // Parameters cannot be bound yet, and unbound parameters are NULL.
+            ExprKind::Variable(_) => {
+                self.emit(Instruction::Null { target });
+            }
+            ExprKind::Column { table, name } => {
+                self.column(expr, table.as_deref(), name, scope, target)?
+            }
+            ExprKind::Unary {
+                operator: UnaryOperator::Plus,
+                expr,
+            } => self.expr_into(expr, scope, target)?,
+            ExprKind::Unary { operator, expr } => {
+                let source = self.expr(expr, scope)?;
+                self.emit(match operator {
+                    UnaryOperator::Negate => Instruction::Negate { source, target },
+                    UnaryOperator::Not => Instruction::Not { source, target },
+                    _ => Instruction::BitNot { source, target },
+                });
+            }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+            } => match operator {
+                BinaryOperator::And | BinaryOperator::Or => {
+                    let left = self.expr(left, scope)?;
+                    let right = self.expr(right, scope)?;
+                    self.emit(match operator {
+                        BinaryOperator::And => Instruction::And {
+                            left,
+                            right,
+                            target,
+                        },
+                        _ => Instruction::Or {
+                            left,
+                            right,
+                            target,
+                        },
+                    });
+                }
+                BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq
+                | BinaryOperator::Is
+                | BinaryOperator::IsNot => {
+                    let comparison = self.comparison(*operator, left, right, scope)?;
+                    self.comparison_value(&comparison, target);
+                }
+                _ => {
+                    let left = self.expr(left, scope)?;
+                    let right = self.expr(right, scope)?;
+                    self.emit(Instruction::Arithmetic {
+                        operator: *operator,
+                        left,
+                        right,
+                        target,
+                    });
+                }
+            },
+            ExprKind::Function {
+                name,
+                arguments,
+                star,
+                filter,
+                over,
+                ..
+            } => {
+                // Aggregates only have values in the output of an aggregate query, where they are
+                // in registers already, and window functions in that of a query with window
+                // functions.
+                if over.is_some() {
+                    return Err(DBError::Sql(format!(
+                        "misuse of window function {}()",
+                        name
+                    )));
+                }
+                if let Some(function) = window::find_window(name, arguments.len()) {
+                    function?;
+                    return Err(DBError::Sql(format!(
+                        "misuse of window function {}()",
+                        name
+                    )));
+                }
+                if let Some(function) = functions::find_aggregate(name, arguments.len()) {
+                    function?;
+                    return Err(DBError::Sql(match self.aggregate_arguments {
+                        true => format!("misuse of aggregate function {}()", name),
+                        false => format!("misuse of aggregate: {}()", name),
+                    }));
+                }
+                if *star {
+                    return Err(DBError::Sql(format!(
+                        "wrong number of arguments to function {}()",
+                        name
+                    )));
+                }
+                if filter.is_some() {
+                    return Err(DBError::Sql(format!(
+                        "FILTER may not be used with non-aggregate {}()",
+                        name
+                    )));
+                }
+                let arguments: Vec<&Expr> = arguments.iter().collect();
+                self.call(name, &arguments, scope, target)?;
+            }
+            ExprKind::Cast { expr, type_name } => {
+                self.expr_into(expr, scope, target)?;
+                self.emit(Instruction::Cast {
+                    register: target,
+                    affinity: ColumnType::from_declared_type(&type_name.name),
+                });
+            }
+            ExprKind::Collate { expr, collation } => {
+                collation_named(collation)?;
+                self.expr_into(expr, scope, target)?;
+            }
+            // LIKE and GLOB are calls to the like() and glob() functions, pattern first.
+            ExprKind::Like {
+                operator,
+                negated,
+                expr,
+                pattern,
+                escape,
+            } => {
+                let mut arguments = vec![&**pattern, &**expr];
+                let name = match operator {
+                    LikeOperator::Like => {
+                        arguments.extend(escape.as_deref());
+                        "like"
+                    }
+                    LikeOperator::Glob => "glob",
+                    LikeOperator::Regexp | LikeOperator::Match => {
+                        return Err(DBError::Sql(format!(
+                            "no such function: {}",
+                            format!("{:?}", operator).to_uppercase()
+                        )))
+                    }
+                };
+                self.call(name, &arguments, scope, target)?;
+                if *negated {
+                    self.emit(Instruction::Not {
+                        source: target,
+                        target,
+                    });
+                }
+            }
+            ExprKind::Between {
+                negated,
+                expr,
+                low,
+                high,
+            } => {
+                let (below, above) = self.between(expr, low, high, scope)?;
+                let (outside_below, outside_above) = (self.register(), self.register());
+                self.comparison_value(&below, outside_below);
+                self.comparison_value(&above, outside_above);
+                self.emit(Instruction::Or {
+                    left: outside_below,
+                    right: outside_above,
+                    target,
+                });
+                if !*negated {
+                    self.emit(Instruction::Not {
+                        source: target,
+                        target,
+                    });
+                }
+            }
+            ExprKind::InList {
+                negated,
+                expr,
+                list,
+            } => self.membership(expr, Some(list), None, *negated, scope, target)?,
+            ExprKind::InSelect {
+                negated,
+                expr,
+                select,
+            } => self.membership(expr, None, Some(select), *negated, scope, target)?,
+            ExprKind::IsNull { negated, expr } => {
+                let register = self.expr(expr, scope)?;
+                let done = self.label();
+                self.emit(Instruction::Integer { value: 1, target });
+                self.emit(match negated {
+                    false => Instruction::IsNull {
+                        register,
+                        target: done,
+                    },
+                    true => Instruction::NotNull {
+                        register,
+                        target: done,
+                    },
+                });
+                self.emit(Instruction::Integer { value: 0, target });
+                self.place(done);
+            }
+            ExprKind::Exists(select) => self.first_row(select, scope, true, target)?,
+            ExprKind::Subquery(select) => self.first_row(select, scope, false, target)?,
+            ExprKind::Case {
+                operand,
+                when_then,
+                else_expr,
+            } => {
+                let done = self.label();
+                let operand = match operand {
+                    Some(operand) => Some((&**operand, self.expr(operand, scope)?)),
+                    None => None,
+                };
+                for (when, then) in when_then {
+                    let next = self.label();
+                    match operand {
+                        Some((operand, register)) => {
+                            let comparison = Comparison {
+                                operator: BinaryOperator::NotEq,
+                                left: register,
+                                right: self.expr(when, scope)?,
+                                null_eq: false,
+                                affinities: (
+                                    self.affinity(operand, scope),
+                                    self.affinity(when, scope),
+                                ),
+                                collation: comparison_collation(operand, when, scope)?,
+                            };
+                            self.emit(comparison.jump(next, true));
+                        }
+                        None => self.jump_if(when, scope, false, next, true)?,
+                    }
+                    self.expr_into(then, scope, target)?;
+                    self.emit(Instruction::Goto { target: done });
+                    self.place(next);
+                }
+                match else_expr {
+                    Some(else_expr) => self.expr_into(else_expr, scope, target)?,
+                    None => {
+                        self.emit(Instruction::Null { target });
+                    }
+                }
+                self.place(done);
+            }
+            ExprKind::Row(_) => return Err(DBError::Sql("row value misused".to_string())),
+            ExprKind::InTable { .. } => {
+                return Err(DBError::Sql(
+                    "IN with a table name is not supported".to_string(),
+                ))
+            }
+            ExprKind::Raise { .. } => {
+                return Err(DBError::Sql(
+                    "RAISE() may only be used within a trigger-program".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn column(
+        &mut self,
+        expr: &Expr,
+        table: Option<&str>,
+        name: &str,
+        scope: &Scope,
+        target: Register,
+    ) -> Result<()> {
+        let Some((source, column)) = scope.lookup(table, name) else {
+            // SQLite takes a double quoted name for a string when there is no column by that name.
+            if table.is_none() && self.text(expr).starts_with('"') {
+                self.emit(Instruction::String {
+                    value: name.to_string(),
+                    target,
+                });
+                return Ok(());
+            }
+            return Err(DBError::Sql(match table {
+                Some(table) => format!("no such column: {}.{}", table, name),
+                None => format!("no such column: {}", name),
+            }));
+        };
+        if scope.ambiguous(table, name) {
+            return Err(DBError::Sql(match table {
+                Some(table) => format!("ambiguous column name: {}.{}", table, name),
+                None => format!("ambiguous column name: {}", name),
+            }));
+        }
+        self.outermost_reference = self.outermost_reference.min(source.depth);
+        // Once the groups of an aggregate query are done, its columns are what the group kept.
+        if let Some(grouped) = scope.grouped_for(source) {
+            let column = (source.cursor, source.normalized(column));
+            let (_, register) = grouped
+                .columns
+                .iter()
+                .find(|(grouped, _)| *grouped == column)
+                .ok_or_else(|| DBError::Sql(format!("{} is not kept for the groups", name)))?;
+            self.emit(Instruction::Copy {
+                source: *register,
+                target,
+            });
+            return Ok(());
+        }
+        self.read_column(source, column, target)
+    }
+
+    // Reads a column of the row a table's cursor, or the cursor of its covering index, is on.
+    fn read_column(&mut self, source: &Source, column: ColumnRef, target: Register) -> Result<()> {
+        if let Some((cursor, index)) = source.covering {
+            let position = match column {
+                _ if source.is_rowid(column) => None,
+                ColumnRef::Column(column) => Some(
+                    index
+                        .columns
+                        .iter()
+                        .position(|index_column| index_column.column == Some(column))
+                        .ok_or_else(|| {
+                            DBError::Sql(format!(
+                                "index {} does not cover {}",
+                                index.name, source.table.columns[column].name
+                            ))
+                        })?,
+                ),
+                ColumnRef::Rowid => None,
+            };
+            match position {
+                Some(position) => {
+                    self.emit(Instruction::Column {
+                        cursor,
+                        column: position,
+                        target,
+                    });
+                    if source.affinity(column) == ColumnType::Real {
+                        self.emit(Instruction::RealAffinity { register: target });
+                    }
+                }
+                None => {
+                    self.emit(Instruction::IdxRowid { cursor, target });
+                }
+            }
+            return Ok(());
+        }
+        match column {
+            ColumnRef::Column(index) if !source.is_rowid(column) => {
+                self.emit(Instruction::Column {
+                    cursor: source.cursor,
+                    column: index,
+                    target,
+                });
+                if source.affinity(column) == ColumnType::Real {
+                    self.emit(Instruction::RealAffinity { register: target });
+                }
+            }
+            _ => {
+                self.emit(Instruction::Rowid {
+                    cursor: source.cursor,
+                    target,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        arguments: &[&Expr],
+        scope: &Scope,
+        target: Register,
+    ) -> Result<()> {
+        let function = functions::find(name, arguments.len())?;
+        let first_argument = self.registers(arguments.len());
+        for (index, argument) in arguments.iter().enumerate() {
+            self.expr_into(argument, scope, first_argument + index)?;
+        }
+        self.emit(Instruction::Function {
+            function,
+            first_argument,
+            argument_count: arguments.len(),
+            target,
+        });
+        Ok(())
+    }
+
+    // Columns have the affinity of their type and CAST the affinity of its type, other expressions
+    // have none.
+    fn affinity(&self, expr: &Expr, scope: &Scope) -> Option<ColumnType> {
+        match &expr.kind {
+            ExprKind::Column { table, name } => scope
+                .lookup(table.as_deref(), name)
+                .map(|(source, column)| source.affinity(column)),
+            ExprKind::Cast { type_name, .. } => {
+                Some(ColumnType::from_declared_type(&type_name.name))
+            }
+            ExprKind::Collate { expr, .. } => self.affinity(expr, scope),
+            _ => None,
+        }
+    }
+}
+
+fn single_column(count: usize) -> Result<()> {
+    if count != 1 {
+        return Err(DBError::Sql(format!(
+            "sub-select returns {} columns - expected 1",
+            count
+        )));
+    }
+    Ok(())
+}
+
+// The column of the source an expression is, the rowid for the INTEGER PRIMARY KEY. COLLATE aside.
+fn table_column(expr: &Expr, source: &Source) -> Option<ColumnRef> {
+    match &expr.kind {
+        ExprKind::Collate { expr, .. } => table_column(expr, source),
+        ExprKind::Column { table, name } => {
+            let column = source.resolve(table.as_deref(), name)?;
+            Some(source.normalized(column))
+        }
+        _ => None,
+    }
+}
+
+// The columns of the source an expression may read. Columns in subqueries are counted when they
+// have the name of one, whether or not they are the subquery's own.
+fn columns_used(expr: &Expr, source: &Source, used: &mut Vec<ColumnRef>) {
+    match &expr.kind {
+        ExprKind::Column { .. } => used.extend(table_column(expr, source)),
+        ExprKind::Exists(select)
+        | ExprKind::Subquery(select)
+        | ExprKind::InSelect { select, .. } => {
+            for expr in select_exprs(select) {
+                columns_used(expr, source, used);
+            }
+        }
+        _ => {}
+    }
+    for child in expr.children() {
+        columns_used(child, source, used);
+    }
+}
+
+// Every expression in a query, those of subqueries in FROM included.
+fn select_exprs(select: &Select) -> Vec<&Expr> {
+    let mut exprs = Vec::new();
+    let cores = std::iter::once(&select.body).chain(select.compound.iter().map(|(_, core)| core));
+    for core in cores {
+        match core {
+            SelectCore::Select {
+                columns,
+                from,
+                where_clause,
+                group_by,
+                having,
+                windows,
+                ..
+            } => {
+                for column in columns {
+                    if let ResultColumn::Expr { expr, .. } = column {
+                        exprs.push(expr);
+                    }
+                }
+                if let Some(from) = from {
+                    let tables = std::iter::once(&from.first)
+                        .chain(from.joins.iter().map(|join| &join.table));
+                    for table in tables {
+                        if let TableOrSubquery::Subquery { select, .. } = table {
+                            exprs.extend(select_exprs(select));
+                        }
+                    }
+                    for join in from.joins.iter() {
+                        if let Some(JoinConstraint::On(expr)) = &join.constraint {
+                            exprs.push(expr);
+                        }
+                    }
+                }
+                exprs.extend(where_clause);
+                exprs.extend(group_by);
+                exprs.extend(having);
+                for (_, window) in windows {
+                    exprs.extend(window.exprs());
+                }
+            }
+            SelectCore::Values(rows) => exprs.extend(rows.iter().flatten()),
+        }
+    }
+    exprs.extend(select.order_by.iter().map(|term| &term.expr));
+    if let Some(limit) = &select.limit {
+        exprs.push(&limit.limit);
+        exprs.extend(&limit.offset);
+    }
+    exprs
+}
+
+// Seek to the first row of a range: past the start when it is exclusive.
+fn seek_operator(inclusive: bool, reverse: bool) -> BinaryOperator {
+    match (reverse, inclusive) {
+        (false, true) => BinaryOperator::GtEq,
+        (false, false) => BinaryOperator::Gt,
+        (true, true) => BinaryOperator::LtEq,
+        (true, false) => BinaryOperator::Lt,
+    }
+}
+
+// How a row compares with the end of a range once it is past it.
+fn past_operator(inclusive: bool, reverse: bool) -> BinaryOperator {
+    match (reverse, inclusive) {
+        (false, true) => BinaryOperator::Gt,
+        (false, false) => BinaryOperator::GtEq,
+        (true, true) => BinaryOperator::Lt,
+        (true, false) => BinaryOperator::LtEq,
+    }
+}
+
+fn column_expr(table: &str, name: &str) -> Expr {
+    Expr {
+        kind: ExprKind::Column {
+            table: Some(table.to_string()),
+            name: name.to_string(),
+        },
+        span: Default::default(),
+    }
+}
+
+fn binary_expr(operator: BinaryOperator, left: Expr, right: Expr) -> Expr {
+    Expr {
+        kind: ExprKind::Binary {
+            operator,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+        span: Default::default(),
+    }
+}
+
+// Breaks an ON or WHERE clause down into the terms ANDed together.
+fn split_clauses<'e>(
+    expr: &'e Expr,
+    join: Option<usize>,
+    sources: &[Source],
+    clauses: &mut Vec<Clause<'e>>,
+) {
+    if let ExprKind::Binary {
+        operator: BinaryOperator::And,
+        left,
+        right,
+    } = &expr.kind
+    {
+        split_clauses(left, join, sources, clauses);
+        split_clauses(right, join, sources, clauses);
+        return;
+    }
+    clauses.push(Clause {
+        expr,
+        join,
+        sources: (0..sources.len())
+            .filter(|source| refers_to(expr, &sources[*source]))
+            .collect(),
+    });
+}
+
+// Whether an expression names a column of the source, subqueries aside.
+fn names_column_of(expr: &Expr, source: &Source) -> bool {
+    match &expr.kind {
+        ExprKind::Column { table, name } => source.resolve(table.as_deref(), name).is_some(),
+        _ => expr
+            .children()
+            .into_iter()
+            .any(|child| names_column_of(child, source)),
+    }
+}
+
+// Whether an expression may depend on the row of the source. Subqueries are taken to.
+fn refers_to(expr: &Expr, source: &Source) -> bool {
+    match &expr.kind {
+        ExprKind::Column { table, name } => source.resolve(table.as_deref(), name).is_some(),
+        ExprKind::Exists(_) | ExprKind::Subquery(_) | ExprKind::InSelect { .. } => true,
+        _ => expr
+            .children()
+            .into_iter()
+            .any(|child| refers_to(child, source)),
+    }
+}
+
+/*
+* Works out what an ORDER BY term sorts by. A number is the position of a result column and a bare
+* name that is the alias of a result column is that column, anything else is an expression over the
+* rows of the table.
+*/
+// Whether an ORDER BY term sorts by the expression.
+fn sorts_by(sort: &SortTerm, expr: &Expr, columns: &[Expr]) -> bool {
+    match &sort.key {
+        SortKey::Expr(key) => same_expr(key, expr),
+        SortKey::Result(column) => same_expr(&columns[*column], expr),
+    }
+}
+
+fn sort_term(
+    term: &OrderingTerm,
+    index: usize,
+    columns: &[Expr],
+    aliases: &[Option<String>],
+    scope: &Scope,
+) -> Result<SortTerm> {
+    let mut expr = &term.expr;
+    let mut collation = None;
+    if let ExprKind::Collate {
+        expr: inner,
+        collation: name,
+    } = &expr.kind
+    {
+        collation = Some(collation_named(name)?);
+        expr = inner;
+    }
+
+    let key = match &expr.kind {
+        ExprKind::Literal(Literal::Integer(position)) => {
+            if *position < 1 || *position as usize > columns.len() {
+                return Err(DBError::Sql(format!(
+                    "{} ORDER BY term out of range - should be between 1 and {}",
+                    ordinal(index + 1),
+                    columns.len()
+                )));
+            }
+            SortKey::Result(*position as usize - 1)
+        }
+        ExprKind::Column { table: None, name } => match aliases.iter().position(|alias| {
+            alias
+                .as_ref()
+                .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+        }) {
+            Some(position) => SortKey::Result(position),
+            None => SortKey::Expr(expr.clone()),
+        },
+        _ => SortKey::Expr(expr.clone()),
+    };
+    let collation = match (collation, &key) {
+        (Some(collation), _) => Some(collation),
+        (None, SortKey::Result(position)) => expr_collation(&columns[*position], scope)?,
+        (None, SortKey::Expr(expr)) => expr_collation(expr, scope)?,
+    };
+    Ok(SortTerm {
+        key,
+        order: sort_order(term, collation.unwrap_or(Collation::Binary)),
+    })
+}
+
+fn sort_order(term: &OrderingTerm, collation: Collation) -> SortOrder {
+    let descending = term.order == Some(Order::Desc);
+    SortOrder {
+        descending,
+        // NULLs are the smallest values.
+        nulls_first: term
+            .nulls
+            .map_or(!descending, |nulls| nulls == NullsOrder::First),
+        collation,
+    }
+}
+
+// Whether rows in the order of `current` are in the order of `terms`, which ORDER BY terms or the
+// terms of a window are: when `current` starts with them.
+fn sorted_by(terms: &[SortTerm], current: &[SortTerm], columns: &[Expr]) -> bool {
+    terms.len() <= current.len()
+        && terms.iter().zip(current).all(|(term, current)| {
+            let current_expr = match &current.key {
+                SortKey::Expr(expr) => expr,
+                SortKey::Result(column) => &columns[*column],
+            };
+            term.order == current.order && sorts_by(term, current_expr, columns)
+        })
+}
+
+// The name of a column a RIGHT or FULL JOIN merged with USING or NATURAL stands for whichever of the
+// columns merged is not NULL, like it does in SQLite.
+fn replace_merged(expr: &mut Expr, merged: &[(String, Expr)]) {
+    if let ExprKind::Column { table: None, name } = &expr.kind {
+        if let Some((_, coalesced)) = merged
+            .iter()
+            .find(|(merged_name, _)| merged_name.eq_ignore_ascii_case(name))
+        {
+            *expr = coalesced.clone();
+        }
+        return;
+    }
+    for child in expr.children_mut() {
+        replace_merged(child, merged);
+    }
+}
+
+// SQLite lets WHERE and ORDER BY refer to result columns by their alias, as long as there is no
+// column by that name.
+fn replace_aliases(expr: &mut Expr, aliases: &[(&String, &Expr)], scope: &Scope) {
+    if let ExprKind::Column { table: None, name } = &expr.kind {
+        if scope.lookup(None, name).is_none() {
+            if let Some((_, aliased)) = aliases
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            {
+                *expr = (*aliased).clone();
+            }
+        }
+        return;
+    }
+    for child in expr.children_mut() {
+        replace_aliases(child, aliases, scope);
+    }
+}
+
+// Forgets where an expression is in the SQL text.
+fn unplaced(expr: &mut Expr) {
+    expr.span = Default::default();
+    for child in expr.children_mut() {
+        unplaced(child);
+    }
+}
+
+// Whether two expressions are the same, wherever they are in the SQL text.
+fn same_expr(a: &Expr, b: &Expr) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    unplaced(&mut a);
+    unplaced(&mut b);
+    a == b
+}
+
+/*
+* Whether every row the terms of WHERE let through has an entry in a partial index with the given
+* WHERE. As in SQLite each term ANDed together in the index's WHERE has to be one of the terms, or
+* follow from one the way `x IS NOT NULL` follows from `x > 1`. Nothing is worked out from values,
+* so, as in SQLite, `score > 90` does not make an index WHERE score > 50 usable.
+*/
+fn partial_index_usable(where_clause: &Expr, terms: &[Term], source: &Source) -> bool {
+    if let ExprKind::Binary {
+        operator: BinaryOperator::And,
+        left,
+        right,
+    } = &where_clause.kind
+    {
+        return partial_index_usable(left, terms, source)
+            && partial_index_usable(right, terms, source);
+    }
+    let Some(implied) = of_table(where_clause, source) else {
+        return false;
+    };
+    terms
+        .iter()
+        .any(|term| of_table(term.expr, source).is_some_and(|term| implies(&term, &implied)))
+}
+
+// Whether an expression being true makes another one true.
+fn implies(expr: &Expr, implied: &Expr) -> bool {
+    // A comparison is never true with a NULL.
+    let compares = |column: &Expr| match &expr.kind {
+        ExprKind::Binary {
+            operator:
+                BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq,
+            left,
+            right,
+        } => same_expr(left, column) || same_expr(right, column),
+        _ => false,
+    };
+    if same_expr(expr, implied) {
+        return true;
+    }
+    match &implied.kind {
+        ExprKind::Binary {
+            operator: BinaryOperator::Or,
+            left,
+            right,
+        } => implies(expr, left) || implies(expr, right),
+        ExprKind::IsNull {
+            negated: true,
+            expr: column,
+        } => compares(column),
+        ExprKind::Binary {
+            operator: BinaryOperator::IsNot,
+            left,
+            right,
+        } if matches!(right.kind, ExprKind::Literal(Literal::Null)) => compares(left),
+        _ => false,
+    }
+}
+
+// An expression with its columns named the way the table of the source has them, so that it can be
+// compared with one of the table's schema. None when it reads columns of other sources.
+fn of_table(expr: &Expr, source: &Source) -> Option<Expr> {
+    let mut expr = expr.clone();
+    name_columns(&mut expr, source).then_some(expr)
+}
+
+// Renames the columns in place, false for a column not of the source.
+fn name_columns(expr: &mut Expr, source: &Source) -> bool {
+    if let ExprKind::Column { .. } = &expr.kind {
+        let Some(column) = table_column(expr, source) else {
+            return false;
+        };
+        let name = match column {
+            ColumnRef::Rowid => "rowid".to_string(),
+            ColumnRef::Column(column) => source.table.columns[column].name.to_lowercase(),
+        };
+        expr.kind = ExprKind::Column { table: None, name };
+        return true;
+    }
+    expr.children_mut()
+        .into_iter()
+        .all(|child| name_columns(child, source))
+}
+
+// The calls to aggregate functions in an expression, those in subqueries aside. An aggregate with
+// a window is a window function.
+fn aggregate_calls<'e>(expr: &'e Expr, calls: &mut Vec<&'e Expr>) {
+    if let ExprKind::Function {
+        name,
+        arguments,
+        over: None,
+        ..
+    } = &expr.kind
+    {
+        if functions::find_aggregate(name, arguments.len()).is_some() {
+            calls.push(expr);
+            return;
+        }
+    }
+    for child in expr.children() {
+        aggregate_calls(child, calls);
+    }
+}
+
+fn aggregate_function(expr: &Expr) -> Result<&'static AggregateFunction> {
+    match &expr.kind {
+        ExprKind::Function {
+            name, arguments, ..
+        } => functions::find_aggregate(name, arguments.len())
+            .unwrap_or_else(|| Err(DBError::Sql(format!("{}() is not an aggregate", name)))),
+        _ => Err(DBError::Sql("not an aggregate".to_string())),
+    }
+}
+
+/*
+* The columns of its own tables an expression of an aggregate query reads outside its aggregates and
+* GROUP BY terms, by the cursor of their table. Subqueries are taken to read every column they name.
+*/
+fn bare_columns(
+    expr: &Expr,
+    terms: &[Expr],
+    scope: &Scope,
+    columns: &mut Vec<(CursorId, ColumnRef)>,
+) {
+    if terms.iter().any(|term| same_expr(term, expr)) {
+        return;
+    }
+    let mut add = |column| {
+        if !columns.contains(&column) {
+            columns.push(column);
+        }
+    };
+    match &expr.kind {
+        ExprKind::Function {
+            name,
+            arguments,
+            over: None,
+            ..
+        } if functions::find_aggregate(name, arguments.len()).is_some() => return,
+        ExprKind::Column { table, name } => {
+            if let Some((source, column)) = scope.lookup(table.as_deref(), name) {
+                if scope.sources.iter().any(|own| std::ptr::eq(own, source)) {
+                    add((source.cursor, source.normalized(column)));
+                }
+            }
+        }
+        ExprKind::Exists(select)
+        | ExprKind::Subquery(select)
+        | ExprKind::InSelect { select, .. } => {
+            for source in scope.sources {
+                let mut used = Vec::new();
+                for expr in select_exprs(select) {
+                    columns_used(expr, source, &mut used);
+                }
+                for column in used {
+                    add((source.cursor, column));
+                }
+            }
+        }
+        _ => {}
+    }
+    for child in expr.children() {
+        bare_columns(child, terms, scope, columns);
+    }
+}
+
+// The calls to window functions in an expression, those in subqueries aside.
+fn window_calls<'e>(expr: &'e Expr, calls: &mut Vec<&'e Expr>) {
+    if let ExprKind::Function { over: Some(_), .. } = &expr.kind {
+        calls.push(expr);
+        return;
+    }
+    for child in expr.children() {
+        window_calls(child, calls);
+    }
+}
+
+// The largest parts of an expression without a call to a window function in them.
+fn outside_windows<'e>(expr: &'e Expr, parts: &mut Vec<&'e Expr>) {
+    let mut calls = Vec::new();
+    window_calls(expr, &mut calls);
+    if calls.is_empty() {
+        parts.push(expr);
+        return;
+    }
+    if let ExprKind::Function { over: Some(_), .. } = &expr.kind {
+        return;
+    }
+    for child in expr.children() {
+        outside_windows(child, parts);
+    }
+}
+
+// What a call with a window calls: a built-in window function or an aggregate.
+fn window_function(expr: &Expr) -> Result<Function> {
+    let ExprKind::Function {
+        name,
+        arguments,
+        distinct,
+        filter,
+        ..
+    } = &expr.kind
+    else {
+        unreachable!("window functions are function calls")
+    };
+    let function = match window::find_window(name, arguments.len()) {
+        Some(function) => Function::Window(function?),
+        None => match functions::find_aggregate(name, arguments.len()) {
+            Some(function) => Function::Aggregate(function?),
+            None => {
+                functions::find(name, arguments.len())?;
+                return Err(DBError::Sql(format!(
+                    "{}() may not be used as a window function",
+                    name
+                )));
+            }
+        },
+    };
+    if *distinct {
+        return Err(DBError::Sql(
+            "DISTINCT is not supported for window functions".to_string(),
+        ));
+    }
+    if filter.is_some() && matches!(function, Function::Window(_)) {
+        return Err(DBError::Sql(
+            "FILTER clause may only be used with aggregate window functions".to_string(),
+        ));
+    }
+    Ok(function)
+}
+
+// SQLite takes no frame that ends before it starts, whatever the offsets.
+fn check_frame(frame: Option<&Frame>) -> Result<()> {
+    if frame.is_some_and(|frame| {
+        matches!(
+            (&frame.start, &frame.end),
+            (
+                FrameBound::Following(_),
+                FrameBound::Preceding(_) | FrameBound::CurrentRow
+            ) | (FrameBound::CurrentRow, FrameBound::Preceding(_))
+        )
+    }) {
+        return Err(DBError::Sql("unsupported frame specification".to_string()));
+    }
+    Ok(())
+}
+
+/*
+* A window with the window of the WINDOW clause it starts from filled in, the last one by that name.
+* It takes PARTITION BY, ORDER BY and the frame from there, and may add ORDER BY and the frame where
+* that one has none.
+*/
+fn based_window(window: &Window, definitions: &[(&str, Window)]) -> Result<Window> {
+    let Some(name) = &window.base else {
+        return Ok(window.clone());
+    };
+    let (_, base) = definitions
+        .iter()
+        .rev()
+        .find(|(defined, _)| defined.eq_ignore_ascii_case(name))
+        .ok_or_else(|| DBError::Sql(format!("no such window: {}", name)))?;
+    let overridden = if !window.partition_by.is_empty() {
+        Some("PARTITION clause")
+    } else if !base.order_by.is_empty() && !window.order_by.is_empty() {
+        Some("ORDER BY clause")
+    } else if base.frame.is_some() && !window.bare {
+        Some("frame specification")
+    } else {
+        None
+    };
+    if let Some(overridden) = overridden {
+        return Err(DBError::Sql(format!(
+            "cannot override {} of window: {}",
+            overridden, name
+        )));
+    }
+    Ok(Window {
+        base: None,
+        bare: false,
+        partition_by: base.partition_by.clone(),
+        order_by: match base.order_by.is_empty() {
+            true => window.order_by.clone(),
+            false => base.order_by.clone(),
+        },
+        frame: window.frame.clone().or_else(|| base.frame.clone()),
+    })
+}
+
+/*
+* Works out the window of each call of a window function and groups the calls by their windows, in
+* the order the calls come in. Without a frame a window has the rows up to the peers of the current
+* one, and the built-in functions that look at the whole partition have frames of their own, which
+* windows only share when they are the same, like in SQLite.
+*/
+fn window_passes<'e>(
+    calls: &[&'e Expr],
+    definitions: &[(&str, Window)],
+    merged: &[(String, Expr)],
+    scope: &Scope,
+) -> Result<Vec<WindowPass<'e>>> {
+    let mut passes: Vec<WindowPass> = Vec::new();
+    for call in calls {
+        let function = window_function(call)?;
+        let ExprKind::Function {
+            over: Some(over), ..
+        } = &call.kind
+        else {
+            unreachable!("window function calls have windows")
+        };
+        check_frame(over.frame.as_ref())?;
+        let mut window = based_window(over, definitions)?;
+        let frame = window.frame.take().unwrap_or(Frame {
+            units: FrameUnits::Range,
+            start: FrameBound::UnboundedPreceding,
+            end: FrameBound::CurrentRow,
+            exclude: FrameExclude::NoOthers,
+        });
+        if frame.units == FrameUnits::Range
+            && (frame.start.offset().is_some() || frame.end.offset().is_some())
+            && window.order_by.len() != 1
+        {
+            return Err(DBError::Sql(
+                "RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression"
+                    .to_string(),
+            ));
+        }
+        window.frame = Some(match function {
+            Function::Window(function) => function.frame().unwrap_or(frame),
+            Function::Aggregate(_) => frame,
+        });
+        for expr in window.exprs_mut() {
+            replace_merged(expr, merged);
+        }
+        match passes
+            .iter_mut()
+            .find(|pass| same_window(&pass.window, &window))
+        {
+            Some(pass) => pass.calls.push((call, function)),
+            None => passes.push(WindowPass {
+                keys: window_keys(&window, scope)?,
+                window,
+                calls: vec![(call, function)],
+            }),
+        }
+    }
+    Ok(passes)
+}
+
+// The terms the rows are sorted by for a window: those of PARTITION BY, for the rows of a
+// partition to come together, then those of ORDER BY.
+fn window_keys(window: &Window, scope: &Scope) -> Result<Vec<SortTerm>> {
+    let partition = window.partition_by.iter().map(|expr| OrderingTerm {
+        expr: expr.clone(),
+        order: None,
+        nulls: None,
+    });
+    let mut keys = Vec::new();
+    for term in partition.chain(window.order_by.iter().cloned()) {
+        let collation = expr_collation(&term.expr, scope)?.unwrap_or(Collation::Binary);
+        let expr = match &term.expr.kind {
+            ExprKind::Collate { expr, .. } => (**expr).clone(),
+            _ => term.expr.clone(),
+        };
+        keys.push(SortTerm {
+            key: SortKey::Expr(expr),
+            order: sort_order(&term, collation),
+        });
+    }
+    Ok(keys)
+}
+
+// Whether two windows are the same, wherever they are in the SQL text.
+fn same_window(a: &Window, b: &Window) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    for expr in a.exprs_mut().into_iter().chain(b.exprs_mut()) {
+        unplaced(expr);
+    }
+    a == b
+}
+
+// Whether an expression has the same value wherever it is worked out: no columns, calls, parameters
+// or subqueries in it.
+fn is_constant(expr: &Expr) -> bool {
+    !matches!(
+        expr.kind,
+        ExprKind::Column { .. }
+            | ExprKind::Function { .. }
+            | ExprKind::Variable(_)
+            | ExprKind::Exists(_)
+            | ExprKind::Subquery(_)
+            | ExprKind::InSelect { .. }
+    ) && expr.children().into_iter().all(is_constant)
+}
+
+// How many SELECTs a statement has, those of its subqueries included.
+fn select_count(select: &Select) -> usize {
+    fn in_expr(expr: &Expr) -> usize {
+        let nested = match &expr.kind {
+            ExprKind::Exists(select)
+            | ExprKind::Subquery(select)
+            | ExprKind::InSelect { select, .. } => select_count(select),
+            _ => 0,
+        };
+        nested + expr.children().into_iter().map(in_expr).sum::<usize>()
+    }
+    1 + select_exprs(select).into_iter().map(in_expr).sum::<usize>()
+}
+
+fn ordinal(number: usize) -> String {
+    let suffix = match (number % 10, number % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", number, suffix)
+}
+
+fn collation_named(name: &str) -> Result<Collation> {
+    Collation::from_name(name)
+        .ok_or_else(|| DBError::Sql(format!("no such collation sequence: {}", name)))
+}
+
+// The collation an expression asks for with COLLATE, None when it does not.
+fn explicit_collation(expr: &Expr) -> Result<Option<Collation>> {
+    match &expr.kind {
+        ExprKind::Collate { collation, .. } => collation_named(collation).map(Some),
+        _ => Ok(None),
+    }
+}
+
+// The collation a column is declared with.
+fn column_collation(expr: &Expr, scope: &Scope) -> Option<Collation> {
+    let ExprKind::Column { table, name } = &expr.kind else {
+        return None;
+    };
+    match scope.lookup(table.as_deref(), name)? {
+        (source, ColumnRef::Column(index)) => {
+            Collation::from_name(source.table.columns[index].collation.as_deref()?)
+        }
+        (_, ColumnRef::Rowid) => None,
+    }
+}
+
+// The collation an expression compares and sorts by: its COLLATE, the collation of its column.
+fn expr_collation(expr: &Expr, scope: &Scope) -> Result<Option<Collation>> {
+    Ok(explicit_collation(expr)?.or_else(|| column_collation(expr, scope)))
+}
+
+/*
+* The collation a comparison uses: the left operand's COLLATE, then the right one's, then the
+* collation of the left operand's column, the right one's, and BINARY when neither has one.
+*/
+fn comparison_collation(left: &Expr, right: &Expr, scope: &Scope) -> Result<Collation> {
+    Ok(explicit_collation(left)?
+        .or(explicit_collation(right)?)
+        .or_else(|| column_collation(left, scope))
+        .or_else(|| column_collation(right, scope))
+        .unwrap_or(Collation::Binary))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::page::errors::DBError;
+    use crate::page::file_structures::Value;
+    use crate::page::schema::{parse_create_table, parse_index};
+    use crate::page::{Database, Index, Table};
+    use crate::sql::compiler::compile;
+    use crate::sql::value::{self, Collation};
+    use crate::sql::vm::{Instruction, Program, Vm};
+
+    // Made by SQLite: `authors(id INTEGER PRIMARY KEY, name, country)`, `books(id INTEGER PRIMARY
+    // KEY, author_id, title, year)` indexed by books_author(author_id), `countries(code TEXT
+    // PRIMARY KEY, name)` and the unindexed `reviews(book_id, stars)`. Some books have no author
+    // or one that is not there, some authors have no books and no country.
+    const JOINS_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/joins.db");
+    // Made by SQLite: `scores(id INTEGER PRIMARY KEY, team TEXT, points INTEGER, rating REAL)`
+    // with seven rows over three teams, ties in points and a NULL in each of the last two.
+    const WINDOWS_DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/windows.db");
+
+    type Schema = (HashMap<String, Table>, HashMap<String, Index>);
+
+    // The tables with their root pages, and the indexes by name, table, root page and sql.
+    fn schema_of(
+        tables: &[(&str, usize)],
+        indexes: &[(&str, &str, usize, Option<&str>)],
+    ) -> Schema {
+        let tables: HashMap<String, Table> = tables
+            .iter()
+            .map(|(sql, root_page)| {
+                let table = parse_create_table(sql, *root_page).unwrap();
+                (table.name.to_lowercase(), table)
+            })
+            .collect();
+        let indexes = indexes
+            .iter()
+            .map(|(name, table, root_page, sql)| {
+                let index = parse_index(
+                    name.to_string(),
+                    &tables[*table],
+                    *root_page,
+                    sql.map(str::to_string),
+                )
+                .unwrap();
+                (name.to_string(), index)
+            })
+            .collect();
+        (tables, indexes)
+    }
+
+    // `t(id INTEGER PRIMARY KEY, a INTEGER, b TEXT, c, d TEXT COLLATE NOCASE)` with the index
+    // t_a(a).
+    fn schema() -> Schema {
+        schema_of(
+            &[(
+                "CREATE TABLE t(id INTEGER PRIMARY KEY, a INTEGER, b TEXT, c, d TEXT COLLATE NOCASE)",
+                2,
+            )],
+            &[("t_a", "t", 3, Some("CREATE INDEX t_a ON t(a)"))],
+        )
+    }
+
+    // The schema of JOINS_DB.
+    fn joins_schema() -> Schema {
+        schema_of(
+            &[
+                (
+                    "CREATE TABLE authors(id INTEGER PRIMARY KEY, name TEXT, country TEXT)",
+                    2,
+                ),
+                (
+                    "CREATE TABLE books(id INTEGER PRIMARY KEY, author_id INTEGER, title TEXT, \
+                     year INTEGER)",
+                    3,
+                ),
+                (
+                    "CREATE TABLE countries(code TEXT PRIMARY KEY, name TEXT)",
+                    5,
+                ),
+                ("CREATE TABLE reviews(book_id INTEGER, stars INTEGER)", 7),
+            ],
+            &[
+                (
+                    "books_author",
+                    "books",
+                    4,
+                    Some("CREATE INDEX books_author ON books(author_id)"),
+                ),
+                ("sqlite_autoindex_countries_1", "countries", 6, None),
+            ],
+        )
+    }
+
+    // The schema of WINDOWS_DB.
+    fn windows_schema() -> Schema {
+        schema_of(
+            &[(
+                "CREATE TABLE scores(id INTEGER PRIMARY KEY, team TEXT, points INTEGER, rating REAL)",
+                2,
+            )],
+            &[],
+        )
+    }
+
+    // The rows the program for `sql` finds in the database, each value written out like the
+    // sqlite3 shell does.
+    fn rows(file_path: &str, (tables, indexes): &Schema, sql: &str) -> Vec<String> {
+        let program = compile(tables, indexes, sql).unwrap();
+        let mut database = Database::open_read_only(file_path.to_string()).unwrap();
+        database.pager.begin_read().unwrap();
+        let mut vm = Vm::new(program, &mut database.pager);
+        let mut rows = Vec::new();
+        while let Some(row) = vm.step().unwrap() {
+            let row: Vec<String> = row
+                .iter()
+                .map(|value| match value {
+                    Value::Null => String::new(),
+                    value => value::to_text(value).unwrap(),
+                })
+                .collect();
+            rows.push(row.join("|"));
+        }
+        vm.pager().end_transaction().unwrap();
+        rows
+    }
+
+    // EXPLAIN QUERY PLAN of the program for `sql`, one step a line.
+    fn query_plan((tables, indexes): &Schema, sql: &str) -> Vec<String> {
+        let program = compile(tables, indexes, sql).unwrap();
+        program.plan.into_iter().map(|step| step.detail).collect()
+    }
+
+    fn program(sql: &str) -> Program {
+        let (tables, indexes) = &schema();
+        compile(tables, indexes, sql).unwrap()
+    }
+
+    // The instructions of the program for `sql`, one line each.
+    fn listing(sql: &str) -> Vec<String> {
+        program(sql)
+            .instructions
+            .iter()
+            .map(|instruction| format!("{:?}", instruction))
+            .collect()
+    }
+
+    #[test]
+    fn scan_program_test() {
+        // WHERE jumps to Next unless it is true, NULL included, with the affinity of the column.
+        assert_eq!(
+            listing("SELECT b FROM t WHERE c > 3"),
+            [
+                r#"OpenRead { cursor: 0, root_page: 2, table: "t" }"#,
+                "Rewind { cursor: 0, if_empty: 8 }",
+                "Column { cursor: 0, column: 3, target: 0 }",
+                "Integer { value: 3, target: 1 }",
+                "Compare { operator: LtEq, left: 0, right: 1, target: 7, jump_if_null: true, \
+                 null_eq: false, affinities: (Some(Blob), None), collation: Binary }",
+                "Column { cursor: 0, column: 2, target: 2 }",
+                "ResultRow { first: 2, count: 1 }",
+                "Next { cursor: 0, target: 2 }",
+                "Halt",
+            ]
+        );
+
+        // IS NULL compares with NULL being equal to NULL, LIMIT counts down to the end.
+        assert_eq!(
+            listing("SELECT b FROM t WHERE c IS NULL LIMIT 2"),
+            [
+                "Integer { value: 2, target: 0 }",
+                "MustBeInt { register: 0 }",
+                "IfNot { register: 0, target: 12, jump_if_null: false }",
+                r#"OpenRead { cursor: 0, root_page: 2, table: "t" }"#,
+                "Rewind { cursor: 0, if_empty: 12 }",
+                "Column { cursor: 0, column: 3, target: 1 }",
+                "Null { target: 2 }",
+                "Compare { operator: NotEq, left: 1, right: 2, target: 11, jump_if_null: true, \
+                 null_eq: true, affinities: (Some(Blob), None), collation: Binary }",
+                "Column { cursor: 0, column: 2, target: 3 }",
+                "ResultRow { first: 3, count: 1 }",
+                "DecrJumpZero { register: 0, target: 12 }",
+                "Next { cursor: 0, target: 5 }",
+                "Halt",
+            ]
+        );
+    }
+
+    #[test]
+    fn search_program_test() {
+        // A rowid is looked up without a loop.
+        assert_eq!(
+            listing("SELECT b FROM t WHERE id = 5"),
+            [
+                r#"OpenRead { cursor: 0, root_page: 2, table: "t" }"#,
+                "Integer { value: 5, target: 0 }",
+                "SeekRowid { cursor: 0, rowid: 0, if_not_found: 5 }",
+                "Column { cursor: 0, column: 2, target: 1 }",
+                "ResultRow { first: 1, count: 1 }",
+                "Halt",
+            ]
+        );
+
+        // An index is searched from the first entry with the key to the last one, each looking up
+        // its row. The key gets the affinity of the column and a NULL key finds nothing.
+        assert_eq!(
+            listing("SELECT b FROM t WHERE a = 5"),
+            [
+                r#"OpenRead { cursor: 0, root_page: 2, table: "t" }"#,
+                r#"OpenIndex { cursor: 1, root_page: 3, index: "t_a", key: [SortOrder { descending: false, nulls_first: true, collation: Binary }] }"#,
+                "Integer { value: 5, target: 0 }",
+                "Affinity { first: 0, affinities: [Numeric] }",
+                "IsNull { register: 0, target: 12 }",
+                "Seek { operator: GtEq, cursor: 1, first: 0, count: 1, if_not_found: 12 }",
+                "IdxCompare { operator: Gt, cursor: 1, first: 0, count: 1, target: 12 }",
+                "IdxRowid { cursor: 1, target: 2 }",
+                "SeekRowid { cursor: 0, rowid: 2, if_not_found: 11 }",
+                "Column { cursor: 0, column: 2, target: 3 }",
+                "ResultRow { first: 3, count: 1 }",
+                "Next { cursor: 1, target: 6 }",
+                "Halt",
+            ]
+        );
+    }
+
+    #[test]
+    fn sorted_distinct_program_test() {
+        // The rows are sorted first, DISTINCT drops the ones already handed out on their way out.
+        assert_eq!(
+            listing("SELECT DISTINCT b FROM t ORDER BY c"),
+            [
+                "OpenEphemeral { cursor: 1, collations: [Binary] }",
+                "SorterOpen { cursor: 2, orders: [SortOrder { descending: false, nulls_first: true, collation: Binary }] }",
+                r#"OpenRead { cursor: 0, root_page: 2, table: "t" }"#,
+                "Rewind { cursor: 0, if_empty: 8 }",
+                "Column { cursor: 0, column: 2, target: 1 }",
+                "Column { cursor: 0, column: 3, target: 0 }",
+                "SorterInsert { cursor: 2, first: 0, count: 2 }",
+                "Next { cursor: 0, target: 4 }",
+                "SorterSort { cursor: 2, if_empty: 14 }",
+                "Column { cursor: 2, column: 1, target: 2 }",
+                "Found { cursor: 1, first: 2, count: 1, target: 13 }",
+                "IdxInsert { cursor: 1, first: 2, count: 1 }",
+                "ResultRow { first: 2, count: 1 }",
+                "SorterNext { cursor: 2, target: 9 }",
+                "Halt",
+            ]
+        );
+
+        // DISTINCT tells rows apart by the collation of the column.
+        let distinct = program("SELECT DISTINCT d, b FROM t");
+        assert!(matches!(
+            &distinct.instructions[0],
+            Instruction::OpenEphemeral { collations, .. }
+                if *collations == [Collation::NoCase, Collation::Binary]
+        ));
+
+        // Rows read in the order of an index need no sorter.
+        let ordered = program("SELECT a FROM t WHERE a > 1 ORDER BY a");
+        assert!(!ordered
+            .instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::SorterOpen { .. })));
+    }
+
+    #[test]
+    fn group_by_program_test() {
+        let program = program("SELECT b, count(*) FROM t GROUP BY b");
+        assert_eq!(program.columns, ["b", "count(*)"]);
+        assert_eq!(program.accumulator_count, 1);
+        let instructions = &program.instructions;
+
+        // Each row finds its group and steps its count, the groups are handed out once all are in.
+        assert!(matches!(
+            instructions[0],
+            Instruction::GroupOpen {
+                cursor: 1,
+                first: 0,
+                count: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            instructions[3..7],
+            [
+                Instruction::Column {
+                    cursor: 0,
+                    column: 2,
+                    target: 2
+                },
+                Instruction::GroupFind {
+                    cursor: 1,
+                    first: 2,
+                    count: 1
+                },
+                Instruction::AggStep { accumulator: 0, .. },
+                Instruction::Next {
+                    cursor: 0,
+                    target: 3
+                },
+            ]
+        ));
+        assert!(matches!(
+            instructions[7..10],
+            [
+                Instruction::GroupSort { cursor: 1, .. },
+                Instruction::Column {
+                    cursor: 1,
+                    column: 0,
+                    target: 0
+                },
+                Instruction::AggFinal {
+                    accumulator: 0,
+                    target: 1,
+                    ..
+                },
+            ]
+        ));
+        assert!(matches!(
+            instructions[13..],
+            [
+                Instruction::GroupNext {
+                    cursor: 1,
+                    target: 8
+                },
+                Instruction::Halt
+            ]
+        ));
+    }
+
+    #[test]
+    fn compile_errors_test() {
+        let (tables, indexes) = &schema();
+        let expect_error = |sql: &str, expected: &str| match compile(tables, indexes, sql) {
+            Err(DBError::Sql(message)) => assert_eq!(message, expected),
+            result => panic!("Expected {:?}, got: {:?}", expected, result.map(|_| ())),
+        };
+        expect_error("DELETE FROM t", "only SELECT statements can be queried");
+        expect_error("SELECT * FROM u", "no such table: u");
+        expect_error("SELECT e FROM t", "no such column: e");
+        expect_error(
+            "SELECT count(*) FROM t WHERE count(*) > 1",
+            "misuse of aggregate: count()",
+        );
+    }
+
+    #[test]
+    fn join_plan_test() {
+        let schema = joins_schema();
+
+        // The inner table is looked up by its rowid for every row of the outer one. The unindexed
+        // table goes outside, where it is scanned once, whatever the order of FROM.
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT a.name, b.title FROM books b JOIN authors a ON a.id = b.author_id"
+            ),
+            ["SCAN b", "SEARCH a USING INTEGER PRIMARY KEY (rowid=?)"]
+        );
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT title FROM books, reviews WHERE book_id = books.id"
+            ),
+            [
+                "SCAN reviews",
+                "SEARCH books USING INTEGER PRIMARY KEY (rowid=?)"
+            ]
+        );
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT * FROM authors JOIN countries ON country = code"
+            ),
+            [
+                "SCAN authors",
+                "SEARCH countries USING INDEX sqlite_autoindex_countries_1 (code=?)"
+            ]
+        );
+
+        // The table on the right of an outer or CROSS JOIN stays there, even where the other
+        // order would cost less.
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT title FROM reviews CROSS JOIN books WHERE book_id = books.id"
+            ),
+            [
+                "SCAN reviews",
+                "SEARCH books USING INTEGER PRIMARY KEY (rowid=?)"
+            ]
+        );
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT title FROM books CROSS JOIN reviews WHERE book_id = books.id"
+            ),
+            ["SCAN books", "SCAN reviews"]
+        );
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT a.name FROM books b LEFT JOIN authors a ON a.id = b.author_id"
+            ),
+            [
+                "SCAN b USING COVERING INDEX books_author",
+                "SEARCH a USING INTEGER PRIMARY KEY (rowid=?) LEFT-JOIN"
+            ]
+        );
+
+        // A FULL JOIN goes through the right table once more for the rows nothing matched.
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT * FROM authors a FULL JOIN books b ON b.author_id = a.id"
+            ),
+            [
+                "SCAN a",
+                "SEARCH b USING INDEX books_author (author_id=?) LEFT-JOIN",
+                "RIGHT-JOIN books",
+                "SCAN b",
+            ]
+        );
+    }
+
+    #[test]
+    fn left_join_program_test() {
+        let (tables, indexes) = &joins_schema();
+        let program = compile(
+            tables,
+            indexes,
+            "SELECT a.name, b.title FROM authors a LEFT JOIN books b ON b.author_id = a.id",
+        )
+        .unwrap();
+        let listing: Vec<String> = program
+            .instructions
+            .iter()
+            .map(|instruction| format!("{:?}", instruction))
+            .collect();
+
+        // Register 0 is set once a book matched. After the loop over the books, an author that had
+        // none goes through the output again with the books cursor on a row of NULLs, and register
+        // 1 sends it on to the next author from there.
+        assert_eq!(
+            listing[4..6],
+            [
+                "Integer { value: 0, target: 0 }",
+                "Integer { value: 0, target: 1 }",
+            ]
+        );
+        assert_eq!(listing[12], "Integer { value: 1, target: 0 }");
+        assert_eq!(
+            listing[16..23],
+            [
+                "If { register: 1, target: 22, jump_if_null: false }",
+                "Next { cursor: 2, target: 9 }",
+                "If { register: 0, target: 22, jump_if_null: false }",
+                "NullRow { cursor: 1 }",
+                "Integer { value: 1, target: 1 }",
+                "Goto { target: 13 }",
+                "Next { cursor: 0, target: 4 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn join_rows_test() {
+        let schema = joins_schema();
+        let rows = |sql| rows(JOINS_DB, &schema, sql);
+
+        assert_eq!(
+            rows("SELECT a.name, b.title FROM authors a LEFT JOIN books b ON b.author_id = a.id"),
+            [
+                "Austen|Emma",
+                "Austen|Persuasion",
+                "Tolstoy|War and Peace",
+                "Tolstoy|Anna Karenina",
+                "Borges|Ficciones",
+                "Murasaki|",
+                "Anonymous|",
+            ]
+        );
+
+        // The books of no author come after the authors, with NULLs for them.
+        assert_eq!(
+            rows("SELECT a.name, b.title FROM authors a FULL JOIN books b ON b.author_id = a.id"),
+            [
+                "Austen|Emma",
+                "Austen|Persuasion",
+                "Tolstoy|War and Peace",
+                "Tolstoy|Anna Karenina",
+                "Borges|Ficciones",
+                "Murasaki|",
+                "Anonymous|",
+                "|Beowulf",
+                "|Lost Book",
+            ]
+        );
+
+        // The name of both sides of a FULL JOIN USING is the one that is not NULL.
+        assert_eq!(
+            rows(
+                "SELECT * FROM authors a FULL JOIN countries c USING (name) \
+                 WHERE a.id = 1 OR c.code = 'FR'"
+            ),
+            ["1|Austen|GB|", "|France||FR"]
+        );
+
+        // NATURAL JOIN joins on every column of the same name, id and name here.
+        assert_eq!(
+            rows("SELECT id, name, title FROM authors NATURAL JOIN books ORDER BY id"),
+            [
+                "1|Austen|Emma",
+                "2|Tolstoy|Persuasion",
+                "3|Borges|War and Peace",
+                "4|Murasaki|Anna Karenina",
+                "5|Anonymous|Ficciones",
+            ]
+        );
+
+        // A correlated subquery can join too.
+        assert_eq!(
+            rows(
+                "SELECT a.name FROM authors a WHERE EXISTS (SELECT 1 FROM books b \
+                 JOIN reviews r ON r.book_id = b.id WHERE b.author_id = a.id AND r.stars = 5)"
+            ),
+            ["Austen", "Tolstoy"]
+        );
+    }
+
+    #[test]
+    fn sorted_group_by_program_test() {
+        let (tables, indexes) = &joins_schema();
+        let program = compile(
+            tables,
+            indexes,
+            "SELECT author_id, group_concat(title, '; ') FROM books GROUP BY 1 \
+             HAVING count(*) > 1 ORDER BY 1 DESC",
+        )
+        .unwrap();
+        assert_eq!(
+            program.plan[0].detail,
+            "SCAN books USING INDEX books_author"
+        );
+        let listing: Vec<String> = program
+            .instructions
+            .iter()
+            .map(|instruction| format!("{:?}", instruction))
+            .collect();
+        assert!(!listing.iter().any(|line| line.starts_with("GroupOpen")));
+
+        // The index is walked backwards. Register 3 says whether a group has started, register 0
+        // holds its key: a row with another key hands out the group before it with the subroutine
+        // at 23 and starts its own.
+        assert_eq!(listing[3], "Last { cursor: 1, if_empty: 20 }");
+        assert_eq!(
+            listing[7..15],
+            [
+                "If { register: 3, target: 9, jump_if_null: false }",
+                "Goto { target: 12 }",
+                "Compare { operator: NotEq, left: 7, right: 0, target: 11, jump_if_null: false, \
+                 null_eq: true, affinities: (None, None), collation: Binary }",
+                "Goto { target: 15 }",
+                "Gosub { register: 4, target: 23 }",
+                "Copy { source: 7, target: 0 }",
+                "AggReset { first: 0, count: 2 }",
+                "Integer { value: 1, target: 3 }",
+            ]
+        );
+
+        // The last group is handed out after the loop, if there was one. HAVING skips the
+        // ResultRow of the subroutine.
+        assert_eq!(
+            listing[19..23],
+            [
+                "Prev { cursor: 1, target: 4 }",
+                "IfNot { register: 3, target: 32, jump_if_null: true }",
+                "Gosub { register: 4, target: 23 }",
+                "Goto { target: 32 }",
+            ]
+        );
+        assert_eq!(
+            listing[27],
+            "Compare { operator: LtEq, left: 10, right: 11, target: 31, jump_if_null: true, \
+             null_eq: false, affinities: (None, None), collation: Binary }"
+        );
+        assert_eq!(listing[31], "Return { register: 4 }");
+    }
+
+    #[test]
+    fn aggregate_plan_test() {
+        let schema = joins_schema();
+
+        // Groups in no index's order are found in a temp B-tree, and so are the values of a
+        // DISTINCT aggregate.
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT year, count(*), sum(DISTINCT year) FROM books GROUP BY year"
+            ),
+            [
+                "SCAN books",
+                "USE TEMP B-TREE FOR GROUP BY",
+                "USE TEMP B-TREE FOR sum(DISTINCT)",
+            ]
+        );
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT author_id % 2 AS odd, count(*) FROM books GROUP BY odd"
+            ),
+            [
+                "SCAN books USING COVERING INDEX books_author",
+                "USE TEMP B-TREE FOR GROUP BY",
+            ]
+        );
+
+        // GROUP BY on the outer table of a join does not mind the rows of the inner one.
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT a.name, count(b.id), max(b.title) FROM authors a \
+                 LEFT JOIN books b ON b.author_id = a.id GROUP BY a.id"
+            ),
+            [
+                "SCAN a",
+                "SEARCH b USING INDEX books_author (author_id=?) LEFT-JOIN",
+            ]
+        );
+        assert_eq!(
+            query_plan(
+                &schema,
+                "SELECT count(DISTINCT author_id) FROM books, reviews WHERE book_id = books.id"
+            ),
+            [
+                "USE TEMP B-TREE FOR count(DISTINCT)",
+                "SCAN reviews",
+                "SEARCH books USING INTEGER PRIMARY KEY (rowid=?)",
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregate_rows_test() {
+        let schema = joins_schema();
+        let rows = |sql| rows(JOINS_DB, &schema, sql);
+
+        // Without GROUP BY there is a group even for no rows.
+        assert_eq!(
+            rows("SELECT count(*), sum(year) FROM books WHERE 0"),
+            ["0|"]
+        );
+        assert_eq!(
+            rows("SELECT count(*) FROM books HAVING count(*) > 1"),
+            ["7"]
+        );
+
+        // NULL is a group of its own, first in the order of the groups.
+        assert_eq!(
+            rows("SELECT author_id % 2 AS odd, count(*) FROM books GROUP BY odd ORDER BY odd DESC"),
+            ["1|4", "0|2", "|1"]
+        );
+        assert_eq!(
+            rows("SELECT year, count(*), sum(DISTINCT year) FROM books GROUP BY year LIMIT 3"),
+            ["|1|", "1815|1|1815", "1817|1|1817"]
+        );
+        assert_eq!(
+            rows(
+                "SELECT a.name, count(b.id), max(b.title) FROM authors a \
+                 LEFT JOIN books b ON b.author_id = a.id GROUP BY a.id"
+            ),
+            [
+                "Austen|2|Persuasion",
+                "Tolstoy|2|War and Peace",
+                "Borges|1|Ficciones",
+                "Murasaki|0|",
+                "Anonymous|0|",
+            ]
+        );
+
+        // DISTINCT drops repeated values before they are added up, total() is a real and sum() of
+        // integers an integer.
+        assert_eq!(
+            rows(
+                "SELECT count(DISTINCT author_id), count(author_id), total(stars), sum(stars) \
+                 FROM books, reviews WHERE book_id = books.id"
+            ),
+            ["3|4|17.0|17"]
+        );
+
+        // Columns outside the aggregates have the values of the first row of the group, or of the
+        // row the only min() or max() took its value from.
+        assert_eq!(rows("SELECT title, count(*) FROM books"), ["Emma|7"]);
+        assert_eq!(
+            rows("SELECT title, max(year), count(*) FROM books"),
+            ["Ficciones|1944|7"]
+        );
+        assert_eq!(
+            rows("SELECT title, max(year), min(year) FROM books"),
+            ["Emma|1944|1815"]
+        );
+        assert_eq!(
+            rows("SELECT title, max(year) FILTER (WHERE year < 1900) FROM books"),
+            ["Anna Karenina|1878"]
+        );
+        assert_eq!(
+            rows("SELECT author_id, title, min(year) FROM books GROUP BY author_id"),
+            [
+                "|Beowulf|",
+                "1|Emma|1815",
+                "2|War and Peace|1869",
+                "3|Ficciones|1944",
+                "9|Lost Book|1900",
+            ]
+        );
+
+        // DISTINCT and ORDER BY apply to the groups, and subqueries in the output see the group.
+        assert_eq!(
+            rows("SELECT DISTINCT count(*) FROM books GROUP BY author_id"),
+            ["1", "2"]
+        );
+        assert_eq!(
+            rows(
+                "SELECT author_id, (SELECT name FROM authors WHERE id = author_id), sum(year) \
+                 FROM books GROUP BY author_id ORDER BY 3"
+            ),
+            [
+                "||",
+                "9||1900",
+                "3|Borges|1944",
+                "1|Austen|3632",
+                "2|Tolstoy|3747"
+            ]
+        );
+    }
+
+    #[test]
+    fn window_program_test() {
+        let (tables, indexes) = &windows_schema();
+        // The plan of the program for `sql`, and how many sorts and calls of window functions it
+        // has.
+        let passes = |sql: &str| {
+            let program = compile(tables, indexes, sql).unwrap();
+            let count = |opcode: fn(&Instruction) -> bool| {
+                program
+                    .instructions
+                    .iter()
+                    .filter(|instruction| opcode(instruction))
+                    .count()
+            };
+            let sorts = count(|instruction| matches!(instruction, Instruction::WindowSort { .. }));
+            let calls = count(|instruction| matches!(instruction, Instruction::WindowCall { .. }));
+            let plan: Vec<String> = program.plan.into_iter().map(|step| step.detail).collect();
+            (plan, sorts, calls)
+        };
+
+        // Rows already in window order are not sorted again.
+        assert_eq!(
+            passes("SELECT id, sum(points) OVER (ORDER BY id) FROM scores"),
+            (
+                vec![
+                    "CO-ROUTINE (subquery-2)".to_string(),
+                    "SCAN scores".to_string(),
+                    "SCAN (subquery-2)".to_string(),
+                ],
+                0,
+                1
+            )
+        );
+
+        // Calls over the same window share a pass, and its sort.
+        assert_eq!(
+            passes(
+                "SELECT rank() OVER (PARTITION BY team ORDER BY points DESC), \
+                 row_number() OVER (PARTITION BY team ORDER BY points DESC) FROM scores"
+            ),
+            (
+                vec![
+                    "CO-ROUTINE (subquery-2)".to_string(),
+                    "CO-ROUTINE (subquery-3)".to_string(),
+                    "SCAN scores".to_string(),
+                    "USE TEMP B-TREE FOR ORDER BY".to_string(),
+                    "SCAN (subquery-3)".to_string(),
+                    "SCAN (subquery-2)".to_string(),
+                ],
+                1,
+                2
+            )
+        );
+
+        // Each window of another order gets a sort of its own. The rows are sorted for ORDER BY
+        // once the windows are done with them, and the groups of an aggregate query before.
+        let (plan, sorts, calls) = passes(
+            "SELECT id, sum(points) OVER (ORDER BY id), count(*) OVER (ORDER BY points) \
+             FROM scores",
+        );
+        assert_eq!((plan.len(), sorts, calls), (7, 2, 2));
+        assert_eq!(
+            passes("SELECT id, count(*) OVER (PARTITION BY team) FROM scores ORDER BY id DESC").0,
+            [
+                "CO-ROUTINE (subquery-2)",
+                "SCAN scores",
+                "USE TEMP B-TREE FOR ORDER BY",
+                "SCAN (subquery-2)",
+                "USE TEMP B-TREE FOR ORDER BY",
+            ]
+        );
+        assert_eq!(
+            passes(
+                "SELECT team, rank() OVER (ORDER BY sum(points) DESC) FROM scores GROUP BY team"
+            )
+            .0,
+            [
+                "CO-ROUTINE (subquery-2)",
+                "SCAN scores",
+                "USE TEMP B-TREE FOR GROUP BY",
+                "USE TEMP B-TREE FOR ORDER BY",
+                "SCAN (subquery-2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn window_rows_test() {
+        let schema = windows_schema();
+        let rows = |sql| rows(WINDOWS_DB, &schema, sql);
+
+        assert_eq!(
+            rows(
+                "SELECT team, sum(points), rank() OVER (ORDER BY sum(points) DESC) FROM scores \
+                 GROUP BY team"
+            ),
+            ["red|8|1", "green|5|2", "blue|2|3"]
+        );
+
+        // A window can add an ORDER BY to a named one, and a bare name takes the window with its
+        // frame. FILTER leaves rows out of the aggregate, not the window.
+        assert_eq!(
+            rows(
+                "SELECT id, sum(points) OVER (byteam ORDER BY id), \
+                 count(*) FILTER (WHERE points > 1) OVER byteam FROM scores \
+                 WINDOW byteam AS (PARTITION BY team)"
+            ),
+            ["2|1|0", "4|2|0", "6|5|1", "1|3|3", "3|5|3", "5|5|3", "7|8|3"]
+        );
+        assert_eq!(
+            rows(
+                "SELECT id, sum(points) OVER w FROM scores \
+                 WINDOW w AS (ORDER BY id ROWS 1 PRECEDING)"
+            ),
+            ["1|3", "2|4", "3|3", "4|3", "5|1", "6|5", "7|8"]
+        );
+
+        // The values are worked out over every row, before OFFSET and LIMIT.
+        assert_eq!(
+            rows(
+                "SELECT id, avg(rating) OVER (ORDER BY id ROWS 1 PRECEDING) FROM scores \
+                 LIMIT 3 OFFSET 2"
+            ),
+            ["3|2.0", "4|0.5", "5|1.75"]
+        );
+        assert_eq!(
+            rows(
+                "SELECT id, rating, count(*) OVER (ORDER BY rating DESC \
+                 RANGE BETWEEN 0.5 PRECEDING AND CURRENT ROW) FROM scores"
+            ),
+            ["5|3.0|1", "7|2.5|2", "2|2.0|2", "1|1.5|2", "6|1.0|2", "4|0.5|2", "3||1"]
+        );
+    }
+}
@@ -0,0 +1,976 @@
+use std::cmp::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::page::errors::{DBError, Result};
+use crate::page::file_structures::Value;
+use crate::page::ColumnType;
+
+use super::value::{self, Collation};
+
+/*
+* SQLite's built-in scalar functions, https://www.sqlite.org/lang_corefunc.html, and its aggregate
+* functions further down.
+*
+* Every function gets its arguments evaluated up front, including coalesce, ifnull and iif which
+* SQLite evaluates lazily. Nothing here has side effects, so that only costs some work.
+*/
+#[derive(Debug)]
+pub struct ScalarFunction {
+    pub name: &'static str,
+    min_arguments: usize,
+    // None for functions taking any number of arguments from `min_arguments` on.
+    max_arguments: Option<usize>,
+    function: fn(&[Value]) -> Result<Value>,
+}
+
+impl ScalarFunction {
+    pub fn call(&self, arguments: &[Value]) -> Result<Value> {
+        (self.function)(arguments)
+    }
+}
+
+const fn function(
+    name: &'static str,
+    min_arguments: usize,
+    max_arguments: Option<usize>,
+    function: fn(&[Value]) -> Result<Value>,
+) -> ScalarFunction {
+    ScalarFunction {
+        name,
+        min_arguments,
+        max_arguments,
+        function,
+    }
+}
+
+const FUNCTIONS: &[ScalarFunction] = &[
+    function("abs", 1, Some(1), abs),
+    function("char", 0, None, char),
+    function("coalesce", 2, None, coalesce),
+    function("concat", 1, None, concat),
+    function("concat_ws", 2, None, concat_ws),
+    function("current_date", 0, Some(0), current_date),
+    function("current_time", 0, Some(0), current_time),
+    function("current_timestamp", 0, Some(0), current_timestamp),
+    function("glob", 2, Some(2), glob),
+    function("hex", 1, Some(1), hex),
+    function("ifnull", 2, Some(2), coalesce),
+    function("iif", 3, Some(3), iif),
+    function("instr", 2, Some(2), instr),
+    function("length", 1, Some(1), length),
+    function("like", 2, Some(3), like),
+    function("likelihood", 2, Some(2), first),
+    function("likely", 1, Some(1), first),
+    function("lower", 1, Some(1), lower),
+    function("ltrim", 1, Some(2), ltrim),
+    function("max", 2, None, max),
+    function("min", 2, None, min),
+    function("nullif", 2, Some(2), nullif),
+    function("octet_length", 1, Some(1), octet_length),
+    function("quote", 1, Some(1), quote),
+    function("replace", 3, Some(3), replace),
+    function("round", 1, Some(2), round),
+    function("rtrim", 1, Some(2), rtrim),
+    function("sign", 1, Some(1), sign),
+    function("substr", 2, Some(3), substr),
+    function("substring", 2, Some(3), substr),
+    function("trim", 1, Some(2), trim),
+    function("typeof", 1, Some(1), type_of),
+    function("unicode", 1, Some(1), unicode),
+    function("unlikely", 1, Some(1), first),
+    function("upper", 1, Some(1), upper),
+    function("zeroblob", 1, Some(1), zeroblob),
+];
+
+// Looks up a scalar function by name, failing like SQLite when there is no such function or it
+// does not take that many arguments.
+pub fn find(name: &str, argument_count: usize) -> Result<&'static ScalarFunction> {
+    let candidates: Vec<&ScalarFunction> = FUNCTIONS
+        .iter()
+        .filter(|function| function.name.eq_ignore_ascii_case(name))
+        .collect();
+    if candidates.is_empty() {
+        return Err(DBError::Sql(format!("no such function: {}", name)));
+    }
+    candidates
+        .into_iter()
+        .find(|function| {
+            argument_count >= function.min_arguments
+                && function
+                    .max_arguments
+                    .is_none_or(|max_arguments| argument_count <= max_arguments)
+        })
+        .ok_or_else(|| DBError::Sql(format!("wrong number of arguments to function {}()", name)))
+}
+
+/*
+* SQLite's built-in aggregate functions, https://www.sqlite.org/lang_aggfunc.html. An accumulator
+* takes in the arguments of the rows of a group one at a time, and gives the value of the aggregate
+* for the group at the end.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Avg,
+    Count,
+    GroupConcat,
+    Max,
+    Min,
+    Sum,
+    Total,
+    // A column outside the aggregates of an aggregate query.
+    Column,
+}
+
+#[derive(Debug)]
+pub struct AggregateFunction {
+    pub name: &'static str,
+    min_arguments: usize,
+    max_arguments: usize,
+    aggregate: Aggregate,
+}
+
+const fn aggregate(
+    name: &'static str,
+    min_arguments: usize,
+    max_arguments: usize,
+    aggregate: Aggregate,
+) -> AggregateFunction {
+    AggregateFunction {
+        name,
+        min_arguments,
+        max_arguments,
+        aggregate,
+    }
+}
+
+// max and min with more than one argument are the scalar functions.
+const AGGREGATES: &[AggregateFunction] = &[
+    aggregate("avg", 1, 1, Aggregate::Avg),
+    aggregate("count", 0, 1, Aggregate::Count),
+    aggregate("group_concat", 1, 2, Aggregate::GroupConcat),
+    aggregate("max", 1, 1, Aggregate::Max),
+    aggregate("min", 1, 1, Aggregate::Min),
+    aggregate("string_agg", 2, 2, Aggregate::GroupConcat),
+    aggregate("sum", 1, 1, Aggregate::Sum),
+    aggregate("total", 1, 1, Aggregate::Total),
+];
+
+/*
+* The value a column outside the aggregates of an aggregate query has for a group: the one it has in
+* the first row of the group, or in the row the only min() or max() of the query took its value from,
+* for which the accumulator is reset before the step.
+*/
+pub static COLUMN: AggregateFunction = aggregate("column", 1, 1, Aggregate::Column);
+
+/*
+* Looks up an aggregate function by name. None when there is none by that name, or when it is
+* the scalar function of the same name that takes that many arguments.
+*/
+pub fn find_aggregate(
+    name: &str,
+    argument_count: usize,
+) -> Option<Result<&'static AggregateFunction>> {
+    let mut candidates = AGGREGATES
+        .iter()
+        .filter(|function| function.name.eq_ignore_ascii_case(name))
+        .peekable();
+    candidates.peek()?;
+    if let Some(function) = candidates.find(|function| {
+        (function.min_arguments..=function.max_arguments).contains(&argument_count)
+    }) {
+        return Some(Ok(function));
+    }
+    if FUNCTIONS
+        .iter()
+        .any(|function| function.name.eq_ignore_ascii_case(name))
+    {
+        return None;
+    }
+    Some(Err(DBError::Sql(format!(
+        "wrong number of arguments to function {}()",
+        name
+    ))))
+}
+
+// The state of an aggregate part way through a group.
+#[derive(Debug, Clone, Default)]
+pub struct Accumulator {
+    // The rows counted, or the values that were not NULL.
+    count: i64,
+    // The sum of the integers, while there are only integers and it fits.
+    integer_sum: i64,
+    // The sum as a real, and the rounding error Kahan-Babuska-Neumaier summation keeps track of.
+    real_sum: f64,
+    error: f64,
+    // Whether a value that is not an integer went into the sum, and whether the integers
+    // overflowed.
+    approximate: bool,
+    overflow: bool,
+    // min() or max() so far, or the value of the column.
+    value: Option<Value>,
+    // group_concat() so far.
+    text: Option<String>,
+}
+
+impl Accumulator {
+    fn add_real(&mut self, value: f64) {
+        let sum = self.real_sum + value;
+        self.error += match self.real_sum.abs() >= value.abs() {
+            true => (self.real_sum - sum) + value,
+            false => (value - sum) + self.real_sum,
+        };
+        self.real_sum = sum;
+    }
+
+    fn real_sum(&self) -> f64 {
+        match self.approximate {
+            true => self.real_sum + self.error,
+            false => self.integer_sum as f64,
+        }
+    }
+}
+
+impl AggregateFunction {
+    pub fn is_min_or_max(&self) -> bool {
+        matches!(self.aggregate, Aggregate::Min | Aggregate::Max)
+    }
+
+    /*
+     * Takes in the arguments of a row. Returns whether the aggregate has its value from the row now,
+     * for min() and max() and the value of a column. Text is compared by `collation`.
+     */
+    pub fn step(
+        &self,
+        accumulator: &mut Accumulator,
+        arguments: &[Value],
+        collation: Collation,
+    ) -> bool {
+        let argument = arguments.first().unwrap_or(&Value::Null);
+        match self.aggregate {
+            Aggregate::Count => {
+                if arguments.is_empty() || *argument != Value::Null {
+                    accumulator.count += 1;
+                }
+            }
+            Aggregate::Sum | Aggregate::Total | Aggregate::Avg => {
+                if *argument == Value::Null {
+                    return false;
+                }
+                accumulator.count += 1;
+                // Text that looks like an integer is one, other text is a real.
+                match value::apply_affinity(argument.clone(), ColumnType::Numeric) {
+                    Value::Integer(integer) => {
+                        accumulator.add_real(integer as f64);
+                        if !accumulator.approximate {
+                            match accumulator.integer_sum.checked_add(integer) {
+                                Some(sum) => accumulator.integer_sum = sum,
+                                None => {
+                                    accumulator.approximate = true;
+                                    accumulator.overflow = true;
+                                }
+                            }
+                        }
+                    }
+                    value => {
+                        accumulator.add_real(value::to_real(&value).unwrap_or(0.0));
+                        accumulator.approximate = true;
+                    }
+                }
+            }
+            Aggregate::Min | Aggregate::Max => {
+                if *argument == Value::Null {
+                    return false;
+                }
+                let wanted = match self.aggregate {
+                    Aggregate::Min => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                if accumulator
+                    .value
+                    .as_ref()
+                    .is_some_and(|value| value::compare(argument, value, collation) != wanted)
+                {
+                    return false;
+                }
+                accumulator.value = Some(argument.clone());
+                return true;
+            }
+            Aggregate::GroupConcat => {
+                let Some(text) = value::to_text(argument) else {
+                    return false;
+                };
+                match &mut accumulator.text {
+                    Some(concatenated) => {
+                        let separator = match arguments.get(1) {
+                            Some(separator) => value::to_text(separator).unwrap_or_default(),
+                            None => ",".to_string(),
+                        };
+                        concatenated.push_str(&separator);
+                        concatenated.push_str(&text);
+                    }
+                    None => accumulator.text = Some(text),
+                }
+            }
+            Aggregate::Column => {
+                if accumulator.value.is_none() {
+                    accumulator.value = Some(argument.clone());
+                }
+            }
+        }
+        false
+    }
+
+    // The value of the aggregate for the rows it took in. sum() fails once the integers overflow.
+    pub fn value(&self, accumulator: &Accumulator) -> Result<Value> {
+        let empty = accumulator.count == 0;
+        Ok(match self.aggregate {
+            Aggregate::Count => Value::Integer(accumulator.count),
+            Aggregate::Sum if empty => Value::Null,
+            Aggregate::Sum if accumulator.overflow => {
+                return Err(DBError::Sql("integer overflow".to_string()))
+            }
+            Aggregate::Sum if accumulator.approximate => Value::Float(accumulator.real_sum()),
+            Aggregate::Sum => Value::Integer(accumulator.integer_sum),
+            Aggregate::Total => Value::Float(accumulator.real_sum()),
+            Aggregate::Avg if empty => Value::Null,
+            Aggregate::Avg => Value::Float(accumulator.real_sum() / accumulator.count as f64),
+            Aggregate::Min | Aggregate::Max | Aggregate::Column => {
+                accumulator.value.clone().unwrap_or(Value::Null)
+            }
+            Aggregate::GroupConcat => accumulator.text.clone().map_or(Value::Null, Value::Text),
+        })
+    }
+}
+
+fn text_arguments<const N: usize>(arguments: &[Value]) -> Option<[String; N]> {
+    let mut texts: [String; N] = std::array::from_fn(|_| String::new());
+    for (text, argument) in texts.iter_mut().zip(arguments) {
+        *text = value::to_text(argument)?;
+    }
+    Some(texts)
+}
+
+fn abs(arguments: &[Value]) -> Result<Value> {
+    Ok(match value::to_numeric(&arguments[0]) {
+        Value::Integer(value) => Value::Integer(
+            value
+                .checked_abs()
+                .ok_or_else(|| DBError::Sql("integer overflow".to_string()))?,
+        ),
+        Value::Float(value) => Value::Float(value.abs()),
+        value => value,
+    })
+}
+
+fn char(arguments: &[Value]) -> Result<Value> {
+    Ok(Value::Text(
+        arguments
+            .iter()
+            .map(|argument| {
+                value::to_integer(argument)
+                    .and_then(|code| char::from_u32(code as u32))
+                    .unwrap_or('\u{fffd}')
+            })
+            .collect(),
+    ))
+}
+
+fn coalesce(arguments: &[Value]) -> Result<Value> {
+    Ok(arguments
+        .iter()
+        .find(|argument| !matches!(argument, Value::Null))
+        .cloned()
+        .unwrap_or(Value::Null))
+}
+
+fn concat(arguments: &[Value]) -> Result<Value> {
+    Ok(Value::Text(
+        arguments.iter().filter_map(value::to_text).collect(),
+    ))
+}
+
+fn concat_ws(arguments: &[Value]) -> Result<Value> {
+    let Some(separator) = value::to_text(&arguments[0]) else {
+        return Ok(Value::Null);
+    };
+    let texts: Vec<String> = arguments[1..].iter().filter_map(value::to_text).collect();
+    Ok(Value::Text(texts.join(&separator)))
+}
+
+fn first(arguments: &[Value]) -> Result<Value> {
+    Ok(arguments[0].clone())
+}
+
+fn glob(arguments: &[Value]) -> Result<Value> {
+    Ok(match text_arguments(arguments) {
+        Some([pattern, text]) => value::from_bool(value::glob(&pattern, &text)),
+        None => Value::Null,
+    })
+}
+
+fn hex(arguments: &[Value]) -> Result<Value> {
+    let bytes = match &arguments[0] {
+        Value::Null => Vec::new(),
+        Value::Blob(blob) => blob.clone(),
+        value => value::to_text(value).unwrap().into_bytes(),
+    };
+    Ok(Value::Text(
+        bytes.iter().map(|byte| format!("{:02X}", byte)).collect(),
+    ))
+}
+
+fn iif(arguments: &[Value]) -> Result<Value> {
+    Ok(match value::is_true(&arguments[0]) {
+        Some(true) => arguments[1].clone(),
+        _ => arguments[2].clone(),
+    })
+}
+
+fn instr(arguments: &[Value]) -> Result<Value> {
+    Ok(match (&arguments[0], &arguments[1]) {
+        (Value::Blob(haystack), Value::Blob(needle)) => Value::Integer(
+            haystack
+                .windows(needle.len().max(1))
+                .position(|window| needle.is_empty() || window == needle.as_slice())
+                .map_or(0, |position| position as i64 + 1),
+        ),
+        _ => match text_arguments(arguments) {
+            Some([haystack, needle]) => Value::Integer(
+                haystack
+                    .find(&needle)
+                    .map_or(0, |offset| haystack[..offset].chars().count() as i64 + 1),
+            ),
+            None => Value::Null,
+        },
+    })
+}
+
+fn length(arguments: &[Value]) -> Result<Value> {
+    Ok(match &arguments[0] {
+        Value::Null => Value::Null,
+        Value::Blob(blob) => Value::Integer(blob.len() as i64),
+        value => Value::Integer(value::to_text(value).unwrap().chars().count() as i64),
+    })
+}
+
+fn like(arguments: &[Value]) -> Result<Value> {
+    let escape = match arguments.get(2) {
+        Some(escape) => match value::to_text(escape) {
+            Some(escape) => Some(escape_character(&escape)?),
+            None => return Ok(Value::Null),
+        },
+        None => None,
+    };
+    Ok(match text_arguments(arguments) {
+        Some([pattern, text]) => value::from_bool(value::like(&pattern, &text, escape)),
+        None => Value::Null,
+    })
+}
+
+// The character of a LIKE ... ESCAPE clause.
+pub fn escape_character(escape: &str) -> Result<char> {
+    let mut chars = escape.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(DBError::Sql(
+            "ESCAPE expression must be a single character".to_string(),
+        )),
+    }
+}
+
+fn lower(arguments: &[Value]) -> Result<Value> {
+    Ok(value::to_text(&arguments[0])
+        .map_or(Value::Null, |text| Value::Text(text.to_ascii_lowercase())))
+}
+
+fn upper(arguments: &[Value]) -> Result<Value> {
+    Ok(value::to_text(&arguments[0])
+        .map_or(Value::Null, |text| Value::Text(text.to_ascii_uppercase())))
+}
+
+fn trim_with(
+    arguments: &[Value],
+    trim: fn(&str, &dyn Fn(char) -> bool) -> String,
+) -> Result<Value> {
+    let Some(text) = value::to_text(&arguments[0]) else {
+        return Ok(Value::Null);
+    };
+    let characters = match arguments.get(1) {
+        Some(characters) => match value::to_text(characters) {
+            Some(characters) => characters,
+            None => return Ok(Value::Null),
+        },
+        None => " ".to_string(),
+    };
+    Ok(Value::Text(trim(&text, &|c| characters.contains(c))))
+}
+
+fn ltrim(arguments: &[Value]) -> Result<Value> {
+    trim_with(arguments, |text, trimmed| {
+        text.trim_start_matches(trimmed).to_string()
+    })
+}
+
+fn rtrim(arguments: &[Value]) -> Result<Value> {
+    trim_with(arguments, |text, trimmed| {
+        text.trim_end_matches(trimmed).to_string()
+    })
+}
+
+fn trim(arguments: &[Value]) -> Result<Value> {
+    trim_with(arguments, |text, trimmed| {
+        text.trim_matches(trimmed).to_string()
+    })
+}
+
+// The scalar max and min are NULL as soon as one of their arguments is.
+fn max(arguments: &[Value]) -> Result<Value> {
+    extreme(arguments, std::cmp::Ordering::Greater)
+}
+
+fn min(arguments: &[Value]) -> Result<Value> {
+    extreme(arguments, std::cmp::Ordering::Less)
+}
+
+fn extreme(arguments: &[Value], wanted: std::cmp::Ordering) -> Result<Value> {
+    let mut result = &arguments[0];
+    for argument in arguments {
+        if matches!(argument, Value::Null) {
+            return Ok(Value::Null);
+        }
+        if value::compare(argument, result, Collation::Binary) == wanted {
+            result = argument;
+        }
+    }
+    Ok(result.clone())
+}
+
+fn nullif(arguments: &[Value]) -> Result<Value> {
+    let equal =
+        value::compare_operands(&arguments[0], None, &arguments[1], None, Collation::Binary)
+            == Some(std::cmp::Ordering::Equal);
+    Ok(if equal {
+        Value::Null
+    } else {
+        arguments[0].clone()
+    })
+}
+
+fn octet_length(arguments: &[Value]) -> Result<Value> {
+    Ok(match &arguments[0] {
+        Value::Null => Value::Null,
+        Value::Blob(blob) => Value::Integer(blob.len() as i64),
+        value => Value::Integer(value::to_text(value).unwrap().len() as i64),
+    })
+}
+
+fn quote(arguments: &[Value]) -> Result<Value> {
+    Ok(Value::Text(match &arguments[0] {
+        Value::Null => "NULL".to_string(),
+        Value::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        Value::Blob(blob) => format!(
+            "X'{}'",
+            blob.iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<String>()
+        ),
+        value => value::to_text(value).unwrap(),
+    }))
+}
+
+fn replace(arguments: &[Value]) -> Result<Value> {
+    Ok(match text_arguments(arguments) {
+        Some([text, from, _]) if from.is_empty() => Value::Text(text),
+        Some([text, from, to]) => Value::Text(text.replace(&from, &to)),
+        None => Value::Null,
+    })
+}
+
+/*
+* Rounds half away from zero, to between 0 and 30 digits after the point. Like SQLite it rounds the
+* value's decimal digits rather than the value times a power of ten, so round(2.675, 2) is 2.67 as
+* printf has it, and adds a half and truncates for 0 digits, so round(0.49999999999999994) is 1.0.
+*/
+fn round(arguments: &[Value]) -> Result<Value> {
+    let Some(value) = value::to_real(&arguments[0]) else {
+        return Ok(Value::Null);
+    };
+    let digits = match arguments.get(1).map(value::to_integer) {
+        Some(None) => return Ok(Value::Null),
+        Some(Some(digits)) => digits.clamp(0, 30),
+        None => 0,
+    };
+    if value.abs() >= 4503599627370496.0 {
+        // Too large to have anything after the point.
+        return Ok(Value::Float(value));
+    }
+    if digits == 0 {
+        return Ok(Value::Float((value + 0.5f64.copysign(value)) as i64 as f64));
+    }
+
+    // The 17 significant digits printf works from and the power of ten of the first, which `{:e}`
+    // always writes.
+    let text = format!("{:.16e}", value.abs());
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let exponent: i64 = exponent.parse().unwrap();
+    let decimals: Vec<u64> = mantissa
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|digit| u64::from(digit - b'0'))
+        .collect();
+    let kept = exponent + 1 + digits;
+    if kept >= decimals.len() as i64 {
+        return Ok(Value::Float(value));
+    }
+    if kept < 0 {
+        return Ok(Value::Float(0f64.copysign(value)));
+    }
+    let kept = kept as usize;
+    let rounded = decimals[..kept]
+        .iter()
+        .fold(0, |number, digit| number * 10 + digit)
+        + u64::from(decimals[kept] >= 5);
+    let rounded: f64 = format!("{}e{}", rounded, exponent + 1 - kept as i64)
+        .parse()
+        .unwrap();
+    Ok(Value::Float(rounded.copysign(value)))
+}
+
+fn sign(arguments: &[Value]) -> Result<Value> {
+    Ok(match value::to_numeric(&arguments[0]) {
+        Value::Integer(value) => Value::Integer(value.signum()),
+        Value::Float(value) => Value::Integer(match value.partial_cmp(&0.0) {
+            Some(std::cmp::Ordering::Less) => -1,
+            Some(std::cmp::Ordering::Greater) => 1,
+            _ => 0,
+        }),
+        _ => Value::Null,
+    })
+}
+
+/*
+* substr(x, start, length) with SQLite's take on the corner cases: characters count from 1, a
+* negative start counts from the end, a negative length takes the characters before the start and
+* a start of 0 is one before the first character. Blobs are cut by bytes rather than characters.
+*/
+fn substr(arguments: &[Value]) -> Result<Value> {
+    let Some(start) = value::to_integer(&arguments[1]) else {
+        return Ok(Value::Null);
+    };
+    let length = match arguments.get(2).map(value::to_integer) {
+        Some(None) => return Ok(Value::Null),
+        length => length.flatten(),
+    };
+    let text = match &arguments[0] {
+        Value::Null => return Ok(Value::Null),
+        Value::Blob(_) => String::new(),
+        // Anything but NULL has a text.
+        value => value::to_text(value).unwrap_or_default(),
+    };
+
+    // In i128 so that none of this overflows whatever the i64 start and length are.
+    let size = match &arguments[0] {
+        Value::Blob(blob) => blob.len(),
+        _ => text.chars().count(),
+    } as i128;
+    let mut start = i128::from(start);
+    let (mut length, negative_length) = match length {
+        Some(length) => (i128::from(length).abs(), length < 0),
+        // SQLite takes its length limit, so nothing is left of a start that far before the text.
+        None => (1_000_000_000, false),
+    };
+    if start < 0 {
+        start += size;
+        if start < 0 {
+            length = (length + start).max(0);
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if length > 0 {
+        length -= 1;
+    }
+    if negative_length {
+        start -= length;
+        if start < 0 {
+            length += start;
+            start = 0;
+        }
+    }
+    let (start, length) = (start.min(size) as usize, length.clamp(0, size) as usize);
+
+    Ok(match &arguments[0] {
+        Value::Blob(blob) => Value::Blob(blob.iter().skip(start).take(length).copied().collect()),
+        _ => Value::Text(text.chars().skip(start).take(length).collect()),
+    })
+}
+
+fn type_of(arguments: &[Value]) -> Result<Value> {
+    Ok(Value::Text(value::type_name(&arguments[0]).to_string()))
+}
+
+fn unicode(arguments: &[Value]) -> Result<Value> {
+    Ok(match value::to_text(&arguments[0]) {
+        Some(text) => text
+            .chars()
+            .next()
+            .map_or(Value::Null, |c| Value::Integer(c as i64)),
+        None => Value::Null,
+    })
+}
+
+fn zeroblob(arguments: &[Value]) -> Result<Value> {
+    let size = value::to_integer(&arguments[0]).unwrap_or(0).max(0) as usize;
+    Ok(Value::Blob(vec![0; size]))
+}
+
+fn current_date(_: &[Value]) -> Result<Value> {
+    Ok(Value::Text(current_date_and_time().0))
+}
+
+fn current_time(_: &[Value]) -> Result<Value> {
+    Ok(Value::Text(current_date_and_time().1))
+}
+
+fn current_timestamp(_: &[Value]) -> Result<Value> {
+    let (date, time) = current_date_and_time();
+    Ok(Value::Text(format!("{} {}", date, time)))
+}
+
+/*
+* CURRENT_DATE, CURRENT_TIME and CURRENT_TIMESTAMP, in UTC like SQLite. Returns the date as
+* `YYYY-MM-DD` and the time as `HH:MM:SS`.
+*/
+pub fn current_date_and_time() -> (String, String) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let (days, seconds) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // Days since 1970-01-01 to a date in the proleptic Gregorian calendar, from Howard Hinnant's
+    // `civil_from_days`.
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::errors::Result;
+    use crate::page::file_structures::Value;
+    use crate::sql::functions::{find, find_aggregate, Accumulator};
+    use crate::sql::value::Collation;
+
+    fn call(name: &str, arguments: &[Value]) -> Value {
+        find(name, arguments.len())
+            .unwrap()
+            .call(arguments)
+            .unwrap()
+    }
+
+    fn text(text: &str) -> Value {
+        Value::Text(text.to_string())
+    }
+
+    #[test]
+    fn scalar_functions_test() {
+        let integer = Value::Integer;
+        for (name, arguments, result) in [
+            ("abs", vec![integer(-3)], integer(3)),
+            ("abs", vec![text("-2.5")], Value::Float(2.5)),
+            (
+                "coalesce",
+                vec![Value::Null, integer(2), integer(3)],
+                integer(2),
+            ),
+            (
+                "concat_ws",
+                vec![text("-"), text("a"), Value::Null, integer(1)],
+                text("a-1"),
+            ),
+            ("hex", vec![text("a\n")], text("610A")),
+            ("instr", vec![text("héllo"), text("llo")], integer(3)),
+            ("length", vec![text("héllo")], integer(5)),
+            ("octet_length", vec![text("héllo")], integer(6)),
+            ("like", vec![text("A_C"), text("abc")], integer(1)),
+            ("LOWER", vec![text("ÀBC")], text("Àbc")),
+            (
+                "max",
+                vec![integer(2), Value::Float(2.5), text("1")],
+                text("1"),
+            ),
+            ("min", vec![integer(2), Value::Null], Value::Null),
+            ("nullif", vec![integer(1), Value::Float(1.0)], Value::Null),
+            ("quote", vec![text("it's")], text("'it''s'")),
+            ("quote", vec![Value::Blob(vec![1, 255])], text("X'01FF'")),
+            (
+                "replace",
+                vec![text("aXbX"), text("X"), text("--")],
+                text("a--b--"),
+            ),
+            ("round", vec![Value::Float(2.5)], Value::Float(3.0)),
+            (
+                "round",
+                vec![Value::Float(-1.2345), integer(2)],
+                Value::Float(-1.23),
+            ),
+            ("sign", vec![Value::Float(-0.5)], integer(-1)),
+            ("trim", vec![text("xxhixx"), text("x")], text("hi")),
+            ("typeof", vec![Value::Float(1.0)], text("real")),
+            ("unicode", vec![text("é")], integer(233)),
+        ] {
+            assert_eq!(call(name, &arguments), result, "{}({:?})", name, arguments);
+        }
+
+        // Results sqlite3 has, including where rounding value * 10^digits would differ.
+        for (value, digits, result) in [
+            (1.005, Some(2), 1.0),
+            (2.675, Some(2), 2.67),
+            (0.25, Some(1), 0.3),
+            (-0.25, Some(1), -0.3),
+            (0.125, Some(2), 0.13),
+            (123456.785, Some(2), 123456.79),
+            (0.004, Some(2), 0.0),
+            (-0.005, Some(2), -0.01),
+            (0.49999999999999994, None, 1.0),
+            (-0.49999999999999994, None, -1.0),
+            (1.5, Some(-3), 2.0),
+            (1.5, Some(i64::MAX), 1.5),
+        ] {
+            let mut arguments = vec![Value::Float(value)];
+            arguments.extend(digits.map(integer));
+            assert_eq!(
+                call("round", &arguments),
+                Value::Float(result),
+                "{} {:?}",
+                value,
+                digits
+            );
+        }
+
+        for (start, length, result) in [
+            (2, Some(3), "bcd"),
+            (0, Some(2), "a"),
+            (-2, None, "ef"),
+            (-10, Some(7), "abc"),
+            (4, Some(-2), "bc"),
+            (7, Some(1), ""),
+            (i64::MIN, Some(2), ""),
+            (i64::MIN, None, ""),
+            (-1_000_000_000, None, "abcdef"),
+            (-1_000_000_006, None, ""),
+            (i64::MIN, Some(i64::MAX), "abcde"),
+            (i64::MAX, Some(2), ""),
+            (i64::MAX, Some(i64::MIN), "abcdef"),
+            (2, Some(i64::MIN), "a"),
+            (0, Some(i64::MIN), ""),
+            (-1, Some(i64::MAX), "f"),
+        ] {
+            let mut arguments = vec![text("abcdef"), integer(start)];
+            arguments.extend(length.map(integer));
+            assert_eq!(
+                call("substr", &arguments),
+                text(result),
+                "{} {:?}",
+                start,
+                length
+            );
+        }
+
+        assert_eq!(
+            find("nope", 1).err().unwrap().to_string(),
+            "no such function: nope"
+        );
+        assert_eq!(
+            find("substr", 1).err().unwrap().to_string(),
+            "wrong number of arguments to function substr()"
+        );
+        assert!(find("abs", 1).unwrap().call(&[integer(i64::MIN)]).is_err());
+    }
+
+    // The value of an aggregate over the rows, each a list of arguments.
+    fn aggregate(name: &str, rows: &[Vec<Value>]) -> Result<Value> {
+        let function = find_aggregate(name, rows[0].len()).unwrap().unwrap();
+        let mut accumulator = Accumulator::default();
+        for arguments in rows {
+            function.step(&mut accumulator, arguments, Collation::Binary);
+        }
+        function.value(&accumulator)
+    }
+
+    #[test]
+    fn aggregate_functions_test() {
+        let integer = Value::Integer;
+        let column = |values: &[Value]| -> Vec<Vec<Value>> {
+            values.iter().map(|value| vec![value.clone()]).collect()
+        };
+        let values = column(&[integer(3), Value::Null, text("4"), Value::Float(0.5)]);
+        for (name, result) in [
+            ("count", integer(3)),
+            ("sum", Value::Float(7.5)),
+            ("total", Value::Float(7.5)),
+            ("avg", Value::Float(2.5)),
+            ("min", Value::Float(0.5)),
+            ("max", text("4")),
+            ("group_concat", text("3,4,0.5")),
+        ] {
+            assert_eq!(aggregate(name, &values).unwrap(), result, "{}", name);
+        }
+        assert_eq!(
+            aggregate("sum", &column(&[integer(1), text("2")])).unwrap(),
+            integer(3)
+        );
+        assert_eq!(
+            aggregate("sum", &column(&[Value::Null])).unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            aggregate("total", &column(&[Value::Null])).unwrap(),
+            Value::Float(0.0)
+        );
+        assert_eq!(
+            aggregate(
+                "string_agg",
+                &[vec![text("a"), text("; ")], vec![text("b"), text("; ")]]
+            )
+            .unwrap(),
+            text("a; b")
+        );
+        // sum() fails once the integers overflow, total() goes on with reals.
+        let huge = column(&[integer(i64::MAX), integer(1)]);
+        assert!(aggregate("sum", &huge).is_err());
+        assert_eq!(
+            aggregate("total", &huge).unwrap(),
+            Value::Float(i64::MAX as f64 + 1.0)
+        );
+
+        // max and min with more than one argument are the scalar functions.
+        assert!(find_aggregate("max", 2).is_none());
+        assert!(find_aggregate("abs", 1).is_none());
+        assert_eq!(
+            find_aggregate("avg", 2).unwrap().unwrap_err().to_string(),
+            "wrong number of arguments to function avg()"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use crate::page::errors::{DBError, Result};
+
+use super::ast::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // Bare identifiers and keywords, which one it is depends on where it shows up.
+    Identifier(String),
+    // "Quoted", [bracketed] or `backticked` identifiers, quotes already stripped. These are never
+    // keywords.
+    QuotedIdentifier(String),
+    // 'String literal', quotes stripped and doubled quotes undone.
+    String(String),
+    // Numbers as written, `12`, `1.5e3`, `.5` or `0x1F`.
+    Number(String),
+    // X'0A1B'
+    Blob(Vec<u8>),
+    // `?`, `?1`, `:name`, `@name` or `$name`.
+    Variable(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    Dot,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    // `||`
+    Concat,
+    // `=` and `==`
+    Eq,
+    // `!=` and `<>`
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    ShiftLeft,
+    ShiftRight,
+    Ampersand,
+    Pipe,
+    Tilde,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    // Whether this is the given keyword, keywords are case insensitive.
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.kind, TokenKind::Identifier(identifier) if identifier.eq_ignore_ascii_case(keyword))
+    }
+}
+
+fn syntax_error(start: usize, end: usize, message: String) -> DBError {
+    DBError::Syntax(Span::new(start, end), message)
+}
+
+// Bytes that may appear in a bare identifier. Anything outside of ASCII is fair game, which keeps
+// multi byte UTF-8 characters in one piece.
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || byte >= 0x80
+}
+
+/*
+* Splits SQL text into tokens. Whitespace and comments, both `-- to the end of the line` and
+* `/* block */`, are dropped. A block comment without its end runs to the end of the text, the same
+* as in SQLite.
+*/
+pub fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let next = bytes.get(i + 1).copied();
+        let kind = match bytes[i] {
+            byte if byte.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'-' if next == Some(b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                i = match sql[i + 2..].find("*/") {
+                    Some(end) => i + 2 + end + 2,
+                    None => bytes.len(),
+                };
+                continue;
+            }
+            quote @ (b'\'' | b'"' | b'`' | b'[') => {
+                let close = if quote == b'[' { b']' } else { quote };
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    let Some(end) = bytes[i..].iter().position(|&byte| byte == close) else {
+                        return Err(syntax_error(
+                            start,
+                            bytes.len(),
+                            format!("unrecognized token: \"{}\"", &sql[start..]),
+                        ));
+                    };
+                    value.push_str(&sql[i..i + end]);
+                    i += end + 1;
+                    // Doubled quote characters stand for the quote itself.
+                    if close != b']' && bytes.get(i) == Some(&close) {
+                        value.push(close as char);
+                        i += 1;
+                    } else {
+                        break;
+                    }
+                }
+                match quote {
+                    b'\'' => TokenKind::String(value),
+                    _ => TokenKind::QuotedIdentifier(value),
+                }
+            }
+            b'x' | b'X' if next == Some(b'\'') => {
+                i += 2;
+                let end = bytes[i..].iter().position(|&byte| byte == b'\'');
+                let hex = &sql[i..end.map_or(bytes.len(), |end| i + end)];
+                i = end.map_or(bytes.len(), |end| i + end + 1);
+                if end.is_none()
+                    || !hex.len().is_multiple_of(2)
+                    || !hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+                {
+                    return Err(syntax_error(
+                        start,
+                        i,
+                        format!("unrecognized token: \"{}\"", &sql[start..i]),
+                    ));
+                }
+                let blob = (0..hex.len())
+                    .step_by(2)
+                    .map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap())
+                    .collect();
+                TokenKind::Blob(blob)
+            }
+            byte if byte.is_ascii_digit()
+                || byte == b'.' && next.is_some_and(|next| next.is_ascii_digit()) =>
+            {
+                i = number_end(bytes, i);
+                // `12abc` is not a number followed by an identifier.
+                if i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                        i += 1;
+                    }
+                    return Err(syntax_error(
+                        start,
+                        i,
+                        format!("unrecognized token: \"{}\"", &sql[start..i]),
+                    ));
+                }
+                TokenKind::Number(sql[start..i].to_string())
+            }
+            // `$` only goes inside identifiers, at the start it makes a variable.
+            byte if byte != b'$' && is_identifier_byte(byte) => {
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                TokenKind::Identifier(sql[start..i].to_string())
+            }
+            b'?' => {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                TokenKind::Variable(sql[start..i].to_string())
+            }
+            b':' | b'@' | b'$' => {
+                i += 1;
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                if i == start + 1 {
+                    return Err(syntax_error(
+                        start,
+                        i,
+                        format!("unrecognized token: \"{}\"", &sql[start..i]),
+                    ));
+                }
+                TokenKind::Variable(sql[start..i].to_string())
+            }
+            byte => {
+                let (kind, len) = match (byte, next) {
+                    (b'|', Some(b'|')) => (TokenKind::Concat, 2),
+                    (b'=', Some(b'=')) => (TokenKind::Eq, 2),
+                    (b'!', Some(b'=')) => (TokenKind::NotEq, 2),
+                    (b'<', Some(b'>')) => (TokenKind::NotEq, 2),
+                    (b'<', Some(b'=')) => (TokenKind::LtEq, 2),
+                    (b'>', Some(b'=')) => (TokenKind::GtEq, 2),
+                    (b'<', Some(b'<')) => (TokenKind::ShiftLeft, 2),
+                    (b'>', Some(b'>')) => (TokenKind::ShiftRight, 2),
+                    (b'(', _) => (TokenKind::LeftParen, 1),
+                    (b')', _) => (TokenKind::RightParen, 1),
+                    (b',', _) => (TokenKind::Comma, 1),
+                    (b';', _) => (TokenKind::Semicolon, 1),
+                    (b'.', _) => (TokenKind::Dot, 1),
+                    (b'+', _) => (TokenKind::Plus, 1),
+                    (b'-', _) => (TokenKind::Minus, 1),
+                    (b'*', _) => (TokenKind::Star, 1),
+                    (b'/', _) => (TokenKind::Slash, 1),
+                    (b'%', _) => (TokenKind::Percent, 1),
+                    (b'=', _) => (TokenKind::Eq, 1),
+                    (b'<', _) => (TokenKind::Lt, 1),
+                    (b'>', _) => (TokenKind::Gt, 1),
+                    (b'&', _) => (TokenKind::Ampersand, 1),
+                    (b'|', _) => (TokenKind::Pipe, 1),
+                    (b'~', _) => (TokenKind::Tilde, 1),
+                    _ => {
+                        return Err(syntax_error(
+                            start,
+                            start + 1,
+                            format!("unrecognized token: \"{}\"", byte as char),
+                        ))
+                    }
+                };
+                i += len;
+                kind
+            }
+        };
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, i),
+        });
+    }
+
+    Ok(tokens)
+}
+
+// End of the number starting at `start`: hex integers, or digits with an optional fraction and
+// exponent.
+fn number_end(bytes: &[u8], start: usize) -> usize {
+    let digits = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+
+    if bytes[start] == b'0' && matches!(bytes.get(start + 1), Some(b'x' | b'X')) {
+        let mut i = start + 2;
+        while i < bytes.len() && bytes[i].is_ascii_hexdigit() {
+            i += 1;
+        }
+        return i;
+    }
+
+    let mut i = digits(start);
+    if bytes.get(i) == Some(&b'.') {
+        i = digits(i + 1);
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        let exponent = match bytes.get(i + 1) {
+            Some(b'+' | b'-') => i + 2,
+            _ => i + 1,
+        };
+        if bytes.get(exponent).is_some_and(u8::is_ascii_digit) {
+            i = digits(exponent);
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::errors::DBError;
+    use crate::sql::ast::Span;
+    use crate::sql::lexer::{tokenize, TokenKind};
+
+    #[test]
+    fn tokenize_test() {
+        let tokens = tokenize(
+            "SELECT \"a\"\"b\", [c d], `e`, 'it''s', x'0aFF', 12, 1.5e-3, .5, 0x1F -- comment\n\
+             FROM t /* block */ WHERE a<>b AND c||d <= ?1 OR :name >= @x AND $y != ? << >> ==",
+        )
+        .unwrap();
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        let identifier = |name: &str| TokenKind::Identifier(name.to_string());
+        let quoted = |name: &str| TokenKind::QuotedIdentifier(name.to_string());
+        let number = |number: &str| TokenKind::Number(number.to_string());
+        let variable = |name: &str| TokenKind::Variable(name.to_string());
+        assert_eq!(
+            kinds,
+            vec![
+                identifier("SELECT"),
+                quoted("a\"b"),
+                TokenKind::Comma,
+                quoted("c d"),
+                TokenKind::Comma,
+                quoted("e"),
+                TokenKind::Comma,
+                TokenKind::String("it's".to_string()),
+                TokenKind::Comma,
+                TokenKind::Blob(vec![0x0a, 0xff]),
+                TokenKind::Comma,
+                number("12"),
+                TokenKind::Comma,
+                number("1.5e-3"),
+                TokenKind::Comma,
+                number(".5"),
+                TokenKind::Comma,
+                number("0x1F"),
+                identifier("FROM"),
+                identifier("t"),
+                identifier("WHERE"),
+                identifier("a"),
+                TokenKind::NotEq,
+                identifier("b"),
+                identifier("AND"),
+                identifier("c"),
+                TokenKind::Concat,
+                identifier("d"),
+                TokenKind::LtEq,
+                variable("?1"),
+                identifier("OR"),
+                variable(":name"),
+                TokenKind::GtEq,
+                variable("@x"),
+                identifier("AND"),
+                variable("$y"),
+                TokenKind::NotEq,
+                variable("?"),
+                TokenKind::ShiftLeft,
+                TokenKind::ShiftRight,
+                TokenKind::Eq,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_spans_test() {
+        let sql = "select naïve, 'ü' from t";
+        let tokens = tokenize(sql).unwrap();
+        let texts: Vec<&str> = tokens
+            .iter()
+            .map(|token| &sql[token.span.start..token.span.end])
+            .collect();
+        assert_eq!(texts, vec!["select", "naïve", ",", "'ü'", "from", "t"]);
+    }
+
+    #[test]
+    fn tokenize_errors_test() {
+        for (sql, span) in [
+            ("SELECT 'abc", Span::new(7, 11)),
+            ("SELECT 12ab", Span::new(7, 11)),
+            ("SELECT x'abc'", Span::new(7, 13)),
+            ("SELECT #", Span::new(7, 8)),
+        ] {
+            match tokenize(sql) {
+                Err(DBError::Syntax(error_span, _)) => assert_eq!(error_span, span, "{}", sql),
+                result => panic!("Expected a syntax error for {}, got: {:?}", sql, result),
+            }
+        }
+        // An unterminated block comment just runs to the end.
+        assert_eq!(tokenize("SELECT 1 /* comment").unwrap().len(), 2);
+    }
+}
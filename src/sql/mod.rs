@@ -0,0 +1,27 @@
+/*
+* The SQL front end: a lexer turning SQL text into tokens and a recursive descent parser turning
+* those into the statements in `ast`.
+*
+* The parser covers SQLite's dialect for SELECT, INSERT, UPDATE, DELETE, CREATE and DROP of tables,
+* indexes, views and triggers, and the transaction statements. Every expression and table name
+* keeps the span of SQL text it came from, so errors further down can point at it. Things SQLite
+* has that are not parsed (yet): upserts, ALTER TABLE, ATTACH, PRAGMA and virtual tables.
+*
+* `query` runs SELECTs over the tables of a database: `compiler` turns them into programs for the
+* bytecode virtual machine in `vm`, with SQLite's semantics for values in `value`, its built-in
+* functions in `functions` and its window functions in `window`. `planner` picks the index, if
+* any, a query gets to its rows through.
+*/
+pub mod ast;
+pub mod compiler;
+pub mod functions;
+pub mod lexer;
+pub mod parser;
+pub mod planner;
+pub mod query;
+pub mod value;
+pub mod vm;
+pub mod window;
+
+pub use parser::{parse, parse_expr, parse_statement};
+pub use query::Rows;
@@ -0,0 +1,2509 @@
+use crate::page::errors::{DBError, Result};
+
+use super::ast::*;
+use super::lexer::{tokenize, Token, TokenKind};
+
+// Keywords that can not be used as bare names. SQLite lets most keywords double as names, these are
+// the ones that would make it ambiguous where a clause starts, e.g. whether the `FROM` in
+// `SELECT a FROM t` is the alias of `a`.
+const RESERVED: [&str; 70] = [
+    "ALL",
+    "ALTER",
+    "AND",
+    "AS",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "CHECK",
+    "COLLATE",
+    "COMMIT",
+    "CONSTRAINT",
+    "CREATE",
+    "CROSS",
+    "CURRENT_DATE",
+    "CURRENT_TIME",
+    "CURRENT_TIMESTAMP",
+    "DEFAULT",
+    "DELETE",
+    "DISTINCT",
+    "DROP",
+    "ELSE",
+    "END",
+    "ESCAPE",
+    "EXCEPT",
+    "EXISTS",
+    "FOREIGN",
+    "FROM",
+    "FULL",
+    "GLOB",
+    "GROUP",
+    "HAVING",
+    "IN",
+    "INDEX",
+    "INDEXED",
+    "INNER",
+    "INSERT",
+    "INTERSECT",
+    "INTO",
+    "IS",
+    "ISNULL",
+    "JOIN",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "MATCH",
+    "NATURAL",
+    "NOT",
+    "NOTNULL",
+    "NULL",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "PRIMARY",
+    "REFERENCES",
+    "REGEXP",
+    "RETURNING",
+    "RIGHT",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "THEN",
+    "TO",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "USING",
+    "VALUES",
+    "WHERE",
+];
+
+// Keywords that end the type name of a column definition and start its constraints.
+const COLUMN_CONSTRAINTS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+// Keywords that start a table constraint rather than a column definition.
+const TABLE_CONSTRAINTS: [&str; 5] = ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+fn is_reserved(identifier: &str) -> bool {
+    RESERVED
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(identifier))
+}
+
+/*
+* Binding power of the binary and postfix operators, from loosest to tightest. NOT as a prefix
+* operator sits between AND and the comparisons, so `NOT a = b` is `NOT (a = b)`.
+*/
+const OR: u8 = 1;
+const AND: u8 = 2;
+const NOT: u8 = 3;
+// =, !=, IS, IN, LIKE, BETWEEN, ISNULL and friends.
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const BITWISE: u8 = 6;
+const ADDITIVE: u8 = 7;
+const MULTIPLICATIVE: u8 = 8;
+const CONCAT: u8 = 9;
+// COLLATE, and the operand of the unary operators.
+const POSTFIX: u8 = 10;
+
+// Parses SQL text holding any number of statements separated by semicolons.
+pub fn parse(sql: &str) -> Result<Vec<Statement>> {
+    let mut parser = Parser::new(sql)?;
+    let mut statements = Vec::new();
+    loop {
+        while parser.eat(&TokenKind::Semicolon) {}
+        if parser.at_end() {
+            return Ok(statements);
+        }
+        statements.push(parser.statement()?);
+        if !parser.at_end() {
+            parser.expect(&TokenKind::Semicolon)?;
+        }
+    }
+}
+
+// Parses SQL text holding exactly one statement, a trailing semicolon is fine.
+pub fn parse_statement(sql: &str) -> Result<Statement> {
+    let mut parser = Parser::new(sql)?;
+    let statement = parser.statement()?;
+    parser.eat(&TokenKind::Semicolon);
+    if !parser.at_end() {
+        return Err(parser.error());
+    }
+    Ok(statement)
+}
+
+// Parses a single expression, like the ones in the `sql` of a partial index.
+pub fn parse_expr(sql: &str) -> Result<Expr> {
+    let mut parser = Parser::new(sql)?;
+    let expr = parser.expr()?;
+    if !parser.at_end() {
+        return Err(parser.error());
+    }
+    Ok(expr)
+}
+
+/*
+* Recursive descent parser over the tokens of a piece of SQL, following the grammar on
+* https://www.sqlite.org/lang.html. Every `fn` here parses one rule of it starting at the current
+* token and leaves the parser right after it.
+*/
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(sql: &'a str) -> Result<Parser<'a>> {
+        Ok(Parser {
+            sql,
+            tokens: tokenize(sql)?,
+            position: 0,
+        })
+    }
+
+    /*
+     * Token helpers.
+     */
+    fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<&TokenKind> {
+        self.tokens
+            .get(self.position + ahead)
+            .map(|token| &token.kind)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        self.peek_keyword_at(0, keyword)
+    }
+
+    fn peek_keyword_at(&self, ahead: usize, keyword: &str) -> bool {
+        self.tokens
+            .get(self.position + ahead)
+            .is_some_and(|token| token.is_keyword(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    // Eats the keywords if they all come next, in that order.
+    fn eat_keywords(&mut self, keywords: &[&str]) -> bool {
+        if keywords
+            .iter()
+            .enumerate()
+            .all(|(ahead, keyword)| self.peek_keyword_at(ahead, keyword))
+        {
+            self.position += keywords.len();
+            return true;
+        }
+        false
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.eat_keyword(keyword) {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek() == Some(kind) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<()> {
+        if !self.eat(kind) {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    // Where the current token starts, the end of the text once there are no more.
+    fn start(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.sql.len(), |token| token.span.start)
+    }
+
+    // Span from `start` to the end of the last token eaten.
+    fn span_from(&self, start: usize) -> Span {
+        let end = match self.position {
+            0 => start,
+            position => self.tokens[position - 1].span.end,
+        };
+        Span::new(start, end.max(start))
+    }
+
+    // The error for an unexpected token, worded like SQLite's.
+    fn error(&self) -> DBError {
+        match self.tokens.get(self.position) {
+            Some(token) => DBError::Syntax(
+                token.span,
+                format!(
+                    "near \"{}\": syntax error",
+                    &self.sql[token.span.start..token.span.end]
+                ),
+            ),
+            None => DBError::Syntax(
+                Span::new(self.sql.len(), self.sql.len()),
+                "incomplete input".to_string(),
+            ),
+        }
+    }
+
+    // A name: a bare identifier that is not reserved, a quoted identifier, or a string, which
+    // SQLite takes for a name where only a name makes sense.
+    fn name(&mut self) -> Result<String> {
+        let name = match self.peek() {
+            Some(TokenKind::Identifier(name)) if !is_reserved(name) => name.clone(),
+            Some(TokenKind::QuotedIdentifier(name)) | Some(TokenKind::String(name)) => name.clone(),
+            _ => return Err(self.error()),
+        };
+        self.position += 1;
+        Ok(name)
+    }
+
+    // `name` or `schema.name`
+    fn qualified_name(&mut self) -> Result<QualifiedName> {
+        let start = self.start();
+        let first = self.name()?;
+        let (schema, name) = if self.eat(&TokenKind::Dot) {
+            (Some(first), self.name()?)
+        } else {
+            (None, first)
+        };
+        Ok(QualifiedName {
+            schema,
+            name,
+            span: self.span_from(start),
+        })
+    }
+
+    // `(name, ...)`
+    fn name_list(&mut self) -> Result<Vec<String>> {
+        self.expect(&TokenKind::LeftParen)?;
+        let mut names = vec![self.name()?];
+        while self.eat(&TokenKind::Comma) {
+            names.push(self.name()?);
+        }
+        self.expect(&TokenKind::RightParen)?;
+        Ok(names)
+    }
+
+    // `[AS] alias`, where leaving out AS only works for names that can't be mistaken for the next
+    // clause.
+    fn alias(&mut self) -> Result<Option<String>> {
+        if self.eat_keyword("AS") {
+            return self.name().map(Some);
+        }
+        // WINDOW is not reserved either, `WINDOW name AS` starts the WINDOW clause.
+        if self.peek_keyword("WINDOW") && self.peek_keyword_at(2, "AS") {
+            return Ok(None);
+        }
+        match self.peek() {
+            Some(TokenKind::Identifier(name)) if !is_reserved(name) => self.name().map(Some),
+            Some(TokenKind::QuotedIdentifier(_)) | Some(TokenKind::String(_)) => {
+                self.name().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /*
+     * Statements.
+     */
+    fn statement(&mut self) -> Result<Statement> {
+        if self.peek_keyword("WITH") {
+            let start = self.start();
+            let with = self.with()?;
+            return match self.peek() {
+                _ if self.peek_keyword("INSERT") || self.peek_keyword("REPLACE") => {
+                    self.insert(Some(with)).map(Statement::Insert)
+                }
+                _ if self.peek_keyword("UPDATE") => self.update(Some(with)).map(Statement::Update),
+                _ if self.peek_keyword("DELETE") => self.delete(Some(with)).map(Statement::Delete),
+                _ => self
+                    .select_after_with(Some(with), start)
+                    .map(Statement::Select),
+            };
+        }
+
+        let Some(TokenKind::Identifier(keyword)) = self.peek() else {
+            return Err(self.error());
+        };
+        match keyword.to_ascii_uppercase().as_str() {
+            "EXPLAIN" => {
+                self.position += 1;
+                let query_plan = self.eat_keywords(&["QUERY", "PLAN"]);
+                if self.peek_keyword("EXPLAIN") {
+                    return Err(self.error());
+                }
+                let statement = Box::new(self.statement()?);
+                Ok(Statement::Explain {
+                    query_plan,
+                    statement,
+                })
+            }
+            "SELECT" | "VALUES" => self.select().map(Statement::Select),
+            "INSERT" | "REPLACE" => self.insert(None).map(Statement::Insert),
+            "UPDATE" => self.update(None).map(Statement::Update),
+            "DELETE" => self.delete(None).map(Statement::Delete),
+            "CREATE" => self.create(),
+            "DROP" => self.drop().map(Statement::Drop),
+            "BEGIN" => {
+                self.position += 1;
+                let kind = if self.eat_keyword("DEFERRED") {
+                    Some(TransactionKind::Deferred)
+                } else if self.eat_keyword("IMMEDIATE") {
+                    Some(TransactionKind::Immediate)
+                } else if self.eat_keyword("EXCLUSIVE") {
+                    Some(TransactionKind::Exclusive)
+                } else {
+                    None
+                };
+                self.eat_keyword("TRANSACTION");
+                Ok(Statement::Begin(kind))
+            }
+            "COMMIT" | "END" => {
+                self.position += 1;
+                self.eat_keyword("TRANSACTION");
+                Ok(Statement::Commit)
+            }
+            "ROLLBACK" => {
+                self.position += 1;
+                self.eat_keyword("TRANSACTION");
+                if !self.eat_keyword("TO") {
+                    return Ok(Statement::Rollback(None));
+                }
+                self.eat_keyword("SAVEPOINT");
+                Ok(Statement::Rollback(Some(self.name()?)))
+            }
+            "SAVEPOINT" => {
+                self.position += 1;
+                Ok(Statement::Savepoint(self.name()?))
+            }
+            "RELEASE" => {
+                self.position += 1;
+                self.eat_keyword("SAVEPOINT");
+                Ok(Statement::Release(self.name()?))
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    // WITH [RECURSIVE] name [(columns)] AS [[NOT] MATERIALIZED] (select), ...
+    fn with(&mut self) -> Result<With> {
+        self.expect_keyword("WITH")?;
+        let recursive = self.eat_keyword("RECURSIVE");
+        let mut tables = Vec::new();
+        loop {
+            let name = self.name()?;
+            let columns = if self.peek() == Some(&TokenKind::LeftParen) {
+                self.name_list()?
+            } else {
+                Vec::new()
+            };
+            self.expect_keyword("AS")?;
+            if !self.eat_keywords(&["NOT", "MATERIALIZED"]) {
+                self.eat_keyword("MATERIALIZED");
+            }
+            self.expect(&TokenKind::LeftParen)?;
+            let select = Box::new(self.select()?);
+            self.expect(&TokenKind::RightParen)?;
+            tables.push(CommonTableExpression {
+                name,
+                columns,
+                select,
+            });
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        Ok(With { recursive, tables })
+    }
+
+    fn select(&mut self) -> Result<Select> {
+        let start = self.start();
+        let with = if self.peek_keyword("WITH") {
+            Some(self.with()?)
+        } else {
+            None
+        };
+        self.select_after_with(with, start)
+    }
+
+    fn select_after_with(&mut self, with: Option<With>, start: usize) -> Result<Select> {
+        let body = self.select_core()?;
+        let mut compound = Vec::new();
+        loop {
+            let operator = if self.eat_keywords(&["UNION", "ALL"]) {
+                CompoundOperator::UnionAll
+            } else if self.eat_keyword("UNION") {
+                CompoundOperator::Union
+            } else if self.eat_keyword("INTERSECT") {
+                CompoundOperator::Intersect
+            } else if self.eat_keyword("EXCEPT") {
+                CompoundOperator::Except
+            } else {
+                break;
+            };
+            compound.push((operator, self.select_core()?));
+        }
+
+        let mut order_by = Vec::new();
+        if self.eat_keywords(&["ORDER", "BY"]) {
+            order_by = self.ordering_terms()?;
+        }
+
+        let mut limit = None;
+        if self.eat_keyword("LIMIT") {
+            let first = self.expr()?;
+            limit = Some(if self.eat_keyword("OFFSET") {
+                Limit {
+                    limit: first,
+                    offset: Some(self.expr()?),
+                }
+            } else if self.eat(&TokenKind::Comma) {
+                // `LIMIT offset, limit`, the other way around from what it looks like.
+                Limit {
+                    limit: self.expr()?,
+                    offset: Some(first),
+                }
+            } else {
+                Limit {
+                    limit: first,
+                    offset: None,
+                }
+            });
+        }
+
+        Ok(Select {
+            with,
+            body,
+            compound,
+            order_by,
+            limit,
+            span: self.span_from(start),
+        })
+    }
+
+    fn select_core(&mut self) -> Result<SelectCore> {
+        if self.eat_keyword("VALUES") {
+            return self.value_rows().map(SelectCore::Values);
+        }
+
+        self.expect_keyword("SELECT")?;
+        let distinct = if self.eat_keyword("DISTINCT") {
+            true
+        } else {
+            self.eat_keyword("ALL");
+            false
+        };
+        let columns = self.result_columns()?;
+
+        let from = if self.eat_keyword("FROM") {
+            Some(self.join_clause()?)
+        } else {
+            None
+        };
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let mut group_by = Vec::new();
+        if self.eat_keywords(&["GROUP", "BY"]) {
+            group_by = self.expr_list()?;
+        }
+        let having = if self.eat_keyword("HAVING") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let mut windows = Vec::new();
+        if self.eat_keyword("WINDOW") {
+            loop {
+                let name = self.name()?;
+                self.expect_keyword("AS")?;
+                windows.push((name, self.window()?));
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+
+        Ok(SelectCore::Select {
+            distinct,
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            windows,
+        })
+    }
+
+    // `([base] [PARTITION BY exprs] [ORDER BY terms] [frame])`
+    fn window(&mut self) -> Result<Window> {
+        self.expect(&TokenKind::LeftParen)?;
+        let base = match self.peek() {
+            Some(TokenKind::Identifier(name))
+                if !is_reserved(name)
+                    && !["PARTITION", "ROWS", "RANGE", "GROUPS"]
+                        .iter()
+                        .any(|keyword| keyword.eq_ignore_ascii_case(name)) =>
+            {
+                Some(self.name()?)
+            }
+            _ => None,
+        };
+        let mut partition_by = Vec::new();
+        if self.eat_keywords(&["PARTITION", "BY"]) {
+            partition_by = self.expr_list()?;
+        }
+        let mut order_by = Vec::new();
+        if self.eat_keywords(&["ORDER", "BY"]) {
+            order_by = self.ordering_terms()?;
+        }
+        let frame = self.frame()?;
+        self.expect(&TokenKind::RightParen)?;
+        Ok(Window {
+            base,
+            bare: false,
+            partition_by,
+            order_by,
+            frame,
+        })
+    }
+
+    // `ROWS|RANGE|GROUPS [BETWEEN] start [AND end] [EXCLUDE ...]`, the end being CURRENT ROW
+    // without BETWEEN.
+    fn frame(&mut self) -> Result<Option<Frame>> {
+        let units = if self.eat_keyword("ROWS") {
+            FrameUnits::Rows
+        } else if self.eat_keyword("RANGE") {
+            FrameUnits::Range
+        } else if self.eat_keyword("GROUPS") {
+            FrameUnits::Groups
+        } else {
+            return Ok(None);
+        };
+        let (start, end) = if self.eat_keyword("BETWEEN") {
+            let start = self.frame_bound(true)?;
+            self.expect_keyword("AND")?;
+            (start, self.frame_bound(false)?)
+        } else {
+            (self.frame_bound(true)?, FrameBound::CurrentRow)
+        };
+        let exclude = if !self.eat_keyword("EXCLUDE") || self.eat_keywords(&["NO", "OTHERS"]) {
+            FrameExclude::NoOthers
+        } else if self.eat_keywords(&["CURRENT", "ROW"]) {
+            FrameExclude::CurrentRow
+        } else if self.eat_keyword("GROUP") {
+            FrameExclude::Group
+        } else {
+            self.expect_keyword("TIES")?;
+            FrameExclude::Ties
+        };
+        Ok(Some(Frame {
+            units,
+            start,
+            end,
+            exclude,
+        }))
+    }
+
+    // UNBOUNDED PRECEDING only starts a frame and UNBOUNDED FOLLOWING only ends one.
+    fn frame_bound(&mut self, start: bool) -> Result<FrameBound> {
+        if self.eat_keywords(&["CURRENT", "ROW"]) {
+            return Ok(FrameBound::CurrentRow);
+        }
+        if self.peek_keyword("UNBOUNDED") {
+            self.position += 1;
+            return match start {
+                true => self
+                    .expect_keyword("PRECEDING")
+                    .map(|_| FrameBound::UnboundedPreceding),
+                false => self
+                    .expect_keyword("FOLLOWING")
+                    .map(|_| FrameBound::UnboundedFollowing),
+            };
+        }
+        let offset = self.expr()?;
+        if self.eat_keyword("PRECEDING") {
+            Ok(FrameBound::Preceding(offset))
+        } else {
+            self.expect_keyword("FOLLOWING")?;
+            Ok(FrameBound::Following(offset))
+        }
+    }
+
+    // `(expr, ...), ...` after VALUES.
+    fn value_rows(&mut self) -> Result<Vec<Vec<Expr>>> {
+        let mut rows = Vec::new();
+        loop {
+            self.expect(&TokenKind::LeftParen)?;
+            rows.push(self.expr_list()?);
+            self.expect(&TokenKind::RightParen)?;
+            if !self.eat(&TokenKind::Comma) {
+                return Ok(rows);
+            }
+        }
+    }
+
+    fn result_columns(&mut self) -> Result<Vec<ResultColumn>> {
+        let mut columns = Vec::new();
+        loop {
+            if self.eat(&TokenKind::Star) {
+                columns.push(ResultColumn::All);
+            } else if self.peek_at(1) == Some(&TokenKind::Dot)
+                && self.peek_at(2) == Some(&TokenKind::Star)
+            {
+                let table = self.name()?;
+                self.position += 2;
+                columns.push(ResultColumn::AllFrom(table));
+            } else {
+                let expr = self.expr()?;
+                let alias = self.alias()?;
+                columns.push(ResultColumn::Expr { expr, alias });
+            }
+            if !self.eat(&TokenKind::Comma) {
+                return Ok(columns);
+            }
+        }
+    }
+
+    fn join_clause(&mut self) -> Result<FromClause> {
+        let first = self.table_or_subquery()?;
+        let mut joins = Vec::new();
+        loop {
+            let (natural, kind) = if self.eat(&TokenKind::Comma) {
+                (false, JoinKind::Comma)
+            } else {
+                let start = self.position;
+                let natural = self.eat_keyword("NATURAL");
+                let kind = if self.eat_keyword("LEFT") {
+                    self.eat_keyword("OUTER");
+                    JoinKind::Left
+                } else if self.eat_keyword("RIGHT") {
+                    self.eat_keyword("OUTER");
+                    JoinKind::Right
+                } else if self.eat_keyword("FULL") {
+                    self.eat_keyword("OUTER");
+                    JoinKind::Full
+                } else if self.eat_keyword("CROSS") {
+                    JoinKind::Cross
+                } else {
+                    self.eat_keyword("INNER");
+                    JoinKind::Inner
+                };
+                if !self.eat_keyword("JOIN") {
+                    // No join at all, unless there were join keywords without the JOIN.
+                    if self.position > start {
+                        return Err(self.error());
+                    }
+                    break;
+                }
+                (natural, kind)
+            };
+
+            let table = self.table_or_subquery()?;
+            let constraint = if self.eat_keyword("ON") {
+                Some(JoinConstraint::On(self.expr()?))
+            } else if self.eat_keyword("USING") {
+                Some(JoinConstraint::Using(self.name_list()?))
+            } else {
+                None
+            };
+            joins.push(Join {
+                natural,
+                kind,
+                table,
+                constraint,
+            });
+        }
+        Ok(FromClause { first, joins })
+    }
+
+    fn table_or_subquery(&mut self) -> Result<TableOrSubquery> {
+        if self.eat(&TokenKind::LeftParen) {
+            let select = Box::new(self.select()?);
+            self.expect(&TokenKind::RightParen)?;
+            let alias = self.alias()?;
+            return Ok(TableOrSubquery::Subquery { select, alias });
+        }
+
+        let name = self.qualified_name()?;
+        let alias = self.alias()?;
+        let indexed = if self.eat_keywords(&["INDEXED", "BY"]) {
+            Some(Indexed::By(self.name()?))
+        } else if self.eat_keywords(&["NOT", "INDEXED"]) {
+            Some(Indexed::Not)
+        } else {
+            None
+        };
+        Ok(TableOrSubquery::Table {
+            name,
+            alias,
+            indexed,
+        })
+    }
+
+    fn ordering_terms(&mut self) -> Result<Vec<OrderingTerm>> {
+        let mut terms = Vec::new();
+        loop {
+            let expr = self.expr()?;
+            let order = self.order();
+            let nulls = if self.eat_keywords(&["NULLS", "FIRST"]) {
+                Some(NullsOrder::First)
+            } else if self.eat_keywords(&["NULLS", "LAST"]) {
+                Some(NullsOrder::Last)
+            } else {
+                None
+            };
+            terms.push(OrderingTerm { expr, order, nulls });
+            if !self.eat(&TokenKind::Comma) {
+                return Ok(terms);
+            }
+        }
+    }
+
+    fn order(&mut self) -> Option<Order> {
+        if self.eat_keyword("ASC") {
+            Some(Order::Asc)
+        } else if self.eat_keyword("DESC") {
+            Some(Order::Desc)
+        } else {
+            None
+        }
+    }
+
+    // [RETURNING result-column, ...]
+    fn returning(&mut self) -> Result<Vec<ResultColumn>> {
+        if !self.eat_keyword("RETURNING") {
+            return Ok(Vec::new());
+        }
+        self.result_columns()
+    }
+
+    // [OR ROLLBACK | ABORT | FAIL | IGNORE | REPLACE]
+    fn or_conflict(&mut self) -> Result<Option<ConflictResolution>> {
+        if !self.eat_keyword("OR") {
+            return Ok(None);
+        }
+        self.conflict_resolution().map(Some)
+    }
+
+    fn conflict_resolution(&mut self) -> Result<ConflictResolution> {
+        let resolution = [
+            ("ROLLBACK", ConflictResolution::Rollback),
+            ("ABORT", ConflictResolution::Abort),
+            ("FAIL", ConflictResolution::Fail),
+            ("IGNORE", ConflictResolution::Ignore),
+            ("REPLACE", ConflictResolution::Replace),
+        ]
+        .into_iter()
+        .find(|(keyword, _)| self.peek_keyword(keyword));
+        match resolution {
+            Some((_, resolution)) => {
+                self.position += 1;
+                Ok(resolution)
+            }
+            None => Err(self.error()),
+        }
+    }
+
+    fn insert(&mut self, with: Option<With>) -> Result<Insert> {
+        let or_conflict = if self.eat_keyword("REPLACE") {
+            Some(ConflictResolution::Replace)
+        } else {
+            self.expect_keyword("INSERT")?;
+            self.or_conflict()?
+        };
+        self.expect_keyword("INTO")?;
+        let table = self.qualified_name()?;
+        let alias = if self.eat_keyword("AS") {
+            Some(self.name()?)
+        } else {
+            None
+        };
+        let columns = if self.peek() == Some(&TokenKind::LeftParen) {
+            self.name_list()?
+        } else {
+            Vec::new()
+        };
+
+        let source = if self.eat_keywords(&["DEFAULT", "VALUES"]) {
+            InsertSource::DefaultValues
+        } else if self.eat_keyword("VALUES") {
+            InsertSource::Values(self.value_rows()?)
+        } else {
+            InsertSource::Select(Box::new(self.select()?))
+        };
+
+        Ok(Insert {
+            with,
+            or_conflict,
+            table,
+            alias,
+            columns,
+            source,
+            returning: self.returning()?,
+        })
+    }
+
+    fn update(&mut self, with: Option<With>) -> Result<Update> {
+        self.expect_keyword("UPDATE")?;
+        let or_conflict = self.or_conflict()?;
+        let table = self.qualified_name()?;
+        let alias = if self.eat_keyword("AS") {
+            Some(self.name()?)
+        } else {
+            None
+        };
+
+        self.expect_keyword("SET")?;
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.name()?;
+            self.expect(&TokenKind::Eq)?;
+            assignments.push((column, self.expr()?));
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        Ok(Update {
+            with,
+            or_conflict,
+            table,
+            alias,
+            assignments,
+            where_clause,
+            returning: self.returning()?,
+        })
+    }
+
+    fn delete(&mut self, with: Option<With>) -> Result<Delete> {
+        self.expect_keyword("DELETE")?;
+        self.expect_keyword("FROM")?;
+        let table = self.qualified_name()?;
+        let alias = if self.eat_keyword("AS") {
+            Some(self.name()?)
+        } else {
+            None
+        };
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        Ok(Delete {
+            with,
+            table,
+            alias,
+            where_clause,
+            returning: self.returning()?,
+        })
+    }
+
+    fn if_not_exists(&mut self) -> bool {
+        self.eat_keywords(&["IF", "NOT", "EXISTS"])
+    }
+
+    fn create(&mut self) -> Result<Statement> {
+        self.expect_keyword("CREATE")?;
+        let temporary = self.eat_keyword("TEMP") || self.eat_keyword("TEMPORARY");
+
+        if self.eat_keyword("TABLE") {
+            return self.create_table(temporary).map(Statement::CreateTable);
+        }
+        if self.eat_keyword("VIEW") {
+            let if_not_exists = self.if_not_exists();
+            let name = self.qualified_name()?;
+            let columns = if self.peek() == Some(&TokenKind::LeftParen) {
+                self.name_list()?
+            } else {
+                Vec::new()
+            };
+            self.expect_keyword("AS")?;
+            return Ok(Statement::CreateView(CreateView {
+                temporary,
+                if_not_exists,
+                name,
+                columns,
+                select: Box::new(self.select()?),
+            }));
+        }
+        if self.eat_keyword("TRIGGER") {
+            return self.create_trigger(temporary).map(Statement::CreateTrigger);
+        }
+        if temporary {
+            return Err(self.error());
+        }
+
+        let unique = self.eat_keyword("UNIQUE");
+        self.expect_keyword("INDEX")?;
+        let if_not_exists = self.if_not_exists();
+        let name = self.qualified_name()?;
+        self.expect_keyword("ON")?;
+        let table = self.name()?;
+        let columns = self.indexed_columns()?;
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        Ok(Statement::CreateIndex(CreateIndex {
+            unique,
+            if_not_exists,
+            name,
+            table,
+            columns,
+            where_clause,
+        }))
+    }
+
+    fn create_table(&mut self, temporary: bool) -> Result<CreateTable> {
+        let if_not_exists = self.if_not_exists();
+        let name = self.qualified_name()?;
+        if self.eat_keyword("AS") {
+            return Ok(CreateTable {
+                temporary,
+                if_not_exists,
+                name,
+                definition: TableDefinition::AsSelect(Box::new(self.select()?)),
+            });
+        }
+
+        self.expect(&TokenKind::LeftParen)?;
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        loop {
+            // Once the table constraints start there are no more columns, and the commas between
+            // the constraints are optional.
+            if TABLE_CONSTRAINTS
+                .iter()
+                .any(|keyword| self.peek_keyword(keyword))
+            {
+                loop {
+                    constraints.push(self.table_constraint()?);
+                    self.eat(&TokenKind::Comma);
+                    if self.peek() == Some(&TokenKind::RightParen) {
+                        break;
+                    }
+                }
+                break;
+            }
+            columns.push(self.column_definition()?);
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightParen)?;
+
+        let mut without_rowid = false;
+        let mut strict = false;
+        loop {
+            if self.eat_keywords(&["WITHOUT", "ROWID"]) {
+                without_rowid = true;
+            } else if self.eat_keyword("STRICT") {
+                strict = true;
+            } else {
+                break;
+            }
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+
+        Ok(CreateTable {
+            temporary,
+            if_not_exists,
+            name,
+            definition: TableDefinition::Columns {
+                columns,
+                constraints,
+                without_rowid,
+                strict,
+            },
+        })
+    }
+
+    fn column_definition(&mut self) -> Result<ColumnDefinition> {
+        let name = self.name()?;
+        let type_name = self.type_name()?;
+        let mut constraints = Vec::new();
+        while let Some(constraint) = self.column_constraint()? {
+            constraints.push(constraint);
+        }
+        Ok(ColumnDefinition {
+            name,
+            type_name,
+            constraints,
+        })
+    }
+
+    // The words of a type name up to the first constraint, with `(n)` or `(n, m)` after them.
+    fn type_name(&mut self) -> Result<Option<TypeName>> {
+        let mut words: Vec<String> = Vec::new();
+        loop {
+            match self.peek() {
+                Some(TokenKind::Identifier(word))
+                    if !COLUMN_CONSTRAINTS
+                        .iter()
+                        .any(|keyword| keyword.eq_ignore_ascii_case(word)) =>
+                {
+                    words.push(word.clone())
+                }
+                Some(TokenKind::QuotedIdentifier(word)) => words.push(word.clone()),
+                _ => break,
+            }
+            self.position += 1;
+        }
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        let mut arguments = Vec::new();
+        if self.eat(&TokenKind::LeftParen) {
+            loop {
+                arguments.push(self.signed_number()?);
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::RightParen)?;
+        }
+        Ok(Some(TypeName {
+            name: words.join(" "),
+            arguments,
+        }))
+    }
+
+    fn signed_number(&mut self) -> Result<String> {
+        let sign = if self.eat(&TokenKind::Minus) {
+            "-"
+        } else {
+            self.eat(&TokenKind::Plus);
+            ""
+        };
+        match self.peek() {
+            Some(TokenKind::Number(number)) => {
+                let number = format!("{}{}", sign, number);
+                self.position += 1;
+                Ok(number)
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn constraint_name(&mut self) -> Result<Option<String>> {
+        if self.eat_keyword("CONSTRAINT") {
+            return self.name().map(Some);
+        }
+        Ok(None)
+    }
+
+    // [ON CONFLICT resolution]
+    fn on_conflict(&mut self) -> Result<Option<ConflictResolution>> {
+        if self.eat_keywords(&["ON", "CONFLICT"]) {
+            return self.conflict_resolution().map(Some);
+        }
+        Ok(None)
+    }
+
+    fn column_constraint(&mut self) -> Result<Option<ColumnConstraint>> {
+        let name = self.constraint_name()?;
+        let kind = if self.eat_keywords(&["PRIMARY", "KEY"]) {
+            let order = self.order();
+            let on_conflict = self.on_conflict()?;
+            ColumnConstraintKind::PrimaryKey {
+                order,
+                on_conflict,
+                autoincrement: self.eat_keyword("AUTOINCREMENT"),
+            }
+        } else if self.eat_keywords(&["NOT", "NULL"]) {
+            ColumnConstraintKind::NotNull(self.on_conflict()?)
+        } else if self.eat_keyword("NULL") {
+            ColumnConstraintKind::Null
+        } else if self.eat_keyword("UNIQUE") {
+            ColumnConstraintKind::Unique(self.on_conflict()?)
+        } else if self.eat_keyword("CHECK") {
+            ColumnConstraintKind::Check(self.parenthesized_expr()?)
+        } else if self.eat_keyword("DEFAULT") {
+            ColumnConstraintKind::Default(self.default_value()?)
+        } else if self.eat_keyword("COLLATE") {
+            ColumnConstraintKind::Collate(self.name()?)
+        } else if self.peek_keyword("REFERENCES") {
+            ColumnConstraintKind::References(self.foreign_key()?)
+        } else if self.eat_keywords(&["GENERATED", "ALWAYS", "AS"]) || self.eat_keyword("AS") {
+            let expr = self.parenthesized_expr()?;
+            let stored = self.eat_keyword("STORED");
+            if !stored {
+                self.eat_keyword("VIRTUAL");
+            }
+            ColumnConstraintKind::Generated { expr, stored }
+        } else if name.is_some() {
+            return Err(self.error());
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(ColumnConstraint { name, kind }))
+    }
+
+    // A DEFAULT is a literal, a signed number or an expression in parentheses.
+    fn default_value(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(TokenKind::LeftParen) => self.parenthesized_expr(),
+            Some(TokenKind::Minus) | Some(TokenKind::Plus) => self.expr_with(POSTFIX),
+            _ => self.primary(),
+        }
+    }
+
+    fn table_constraint(&mut self) -> Result<TableConstraint> {
+        let name = self.constraint_name()?;
+        let kind = if self.eat_keywords(&["PRIMARY", "KEY"]) {
+            TableConstraintKind::PrimaryKey {
+                columns: self.indexed_columns()?,
+                on_conflict: self.on_conflict()?,
+            }
+        } else if self.eat_keyword("UNIQUE") {
+            TableConstraintKind::Unique {
+                columns: self.indexed_columns()?,
+                on_conflict: self.on_conflict()?,
+            }
+        } else if self.eat_keyword("CHECK") {
+            TableConstraintKind::Check(self.parenthesized_expr()?)
+        } else if self.eat_keywords(&["FOREIGN", "KEY"]) {
+            TableConstraintKind::ForeignKey {
+                columns: self.name_list()?,
+                foreign_key: self.foreign_key()?,
+            }
+        } else {
+            return Err(self.error());
+        };
+        Ok(TableConstraint { name, kind })
+    }
+
+    // REFERENCES table [(columns)] [ON DELETE|UPDATE action] [MATCH name]
+    //     [[NOT] DEFERRABLE [INITIALLY DEFERRED|IMMEDIATE]]
+    fn foreign_key(&mut self) -> Result<ForeignKey> {
+        self.expect_keyword("REFERENCES")?;
+        let table = self.name()?;
+        let columns = if self.peek() == Some(&TokenKind::LeftParen) {
+            self.name_list()?
+        } else {
+            Vec::new()
+        };
+
+        let mut foreign_key = ForeignKey {
+            table,
+            columns,
+            on_delete: None,
+            on_update: None,
+            deferred: false,
+        };
+        loop {
+            if self.eat_keyword("ON") {
+                let on_delete = self.eat_keyword("DELETE");
+                if !on_delete {
+                    self.expect_keyword("UPDATE")?;
+                }
+                let action = self.foreign_key_action()?;
+                if on_delete {
+                    foreign_key.on_delete = Some(action);
+                } else {
+                    foreign_key.on_update = Some(action);
+                }
+            } else if self.eat_keyword("MATCH") {
+                self.name()?;
+            } else if self.peek_keyword("DEFERRABLE")
+                || self.peek_keyword("NOT") && self.peek_keyword_at(1, "DEFERRABLE")
+            {
+                let not = self.eat_keyword("NOT");
+                self.expect_keyword("DEFERRABLE")?;
+                let initially_deferred = self.eat_keywords(&["INITIALLY", "DEFERRED"]);
+                if !initially_deferred {
+                    self.eat_keywords(&["INITIALLY", "IMMEDIATE"]);
+                }
+                foreign_key.deferred = !not && initially_deferred;
+            } else {
+                return Ok(foreign_key);
+            }
+        }
+    }
+
+    fn foreign_key_action(&mut self) -> Result<ForeignKeyAction> {
+        if self.eat_keywords(&["SET", "NULL"]) {
+            Ok(ForeignKeyAction::SetNull)
+        } else if self.eat_keywords(&["SET", "DEFAULT"]) {
+            Ok(ForeignKeyAction::SetDefault)
+        } else if self.eat_keyword("CASCADE") {
+            Ok(ForeignKeyAction::Cascade)
+        } else if self.eat_keyword("RESTRICT") {
+            Ok(ForeignKeyAction::Restrict)
+        } else if self.eat_keywords(&["NO", "ACTION"]) {
+            Ok(ForeignKeyAction::NoAction)
+        } else {
+            Err(self.error())
+        }
+    }
+
+    // `(column [COLLATE name] [ASC|DESC], ...)`
+    fn indexed_columns(&mut self) -> Result<Vec<IndexedColumn>> {
+        self.expect(&TokenKind::LeftParen)?;
+        let mut columns = Vec::new();
+        loop {
+            let expr = self.expr()?;
+            columns.push(IndexedColumn {
+                expr,
+                order: self.order(),
+            });
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightParen)?;
+        Ok(columns)
+    }
+
+    fn create_trigger(&mut self, temporary: bool) -> Result<CreateTrigger> {
+        let if_not_exists = self.if_not_exists();
+        let name = self.qualified_name()?;
+        let time = if self.eat_keyword("BEFORE") {
+            Some(TriggerTime::Before)
+        } else if self.eat_keyword("AFTER") {
+            Some(TriggerTime::After)
+        } else if self.eat_keywords(&["INSTEAD", "OF"]) {
+            Some(TriggerTime::InsteadOf)
+        } else {
+            None
+        };
+
+        let event = if self.eat_keyword("DELETE") {
+            TriggerEvent::Delete
+        } else if self.eat_keyword("INSERT") {
+            TriggerEvent::Insert
+        } else {
+            self.expect_keyword("UPDATE")?;
+            let mut columns = Vec::new();
+            if self.eat_keyword("OF") {
+                loop {
+                    columns.push(self.name()?);
+                    if !self.eat(&TokenKind::Comma) {
+                        break;
+                    }
+                }
+            }
+            TriggerEvent::Update(columns)
+        };
+        self.expect_keyword("ON")?;
+        let table = self.name()?;
+        let for_each_row = self.eat_keywords(&["FOR", "EACH", "ROW"]);
+        let when = if self.eat_keyword("WHEN") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        self.expect_keyword("BEGIN")?;
+        let mut body = Vec::new();
+        while !self.eat_keyword("END") {
+            let statement = match self.statement()? {
+                statement @ (Statement::Select(_)
+                | Statement::Insert(_)
+                | Statement::Update(_)
+                | Statement::Delete(_)) => statement,
+                _ => return Err(self.error()),
+            };
+            body.push(statement);
+            self.expect(&TokenKind::Semicolon)?;
+        }
+
+        Ok(CreateTrigger {
+            temporary,
+            if_not_exists,
+            name,
+            time,
+            event,
+            table,
+            for_each_row,
+            when,
+            body,
+        })
+    }
+
+    fn drop(&mut self) -> Result<DropObject> {
+        self.expect_keyword("DROP")?;
+        let kind = [
+            ("TABLE", ObjectKind::Table),
+            ("INDEX", ObjectKind::Index),
+            ("VIEW", ObjectKind::View),
+            ("TRIGGER", ObjectKind::Trigger),
+        ]
+        .into_iter()
+        .find(|(keyword, _)| self.peek_keyword(keyword))
+        .map(|(_, kind)| kind)
+        .ok_or_else(|| self.error())?;
+        self.position += 1;
+        let if_exists = self.eat_keywords(&["IF", "EXISTS"]);
+        Ok(DropObject {
+            kind,
+            if_exists,
+            name: self.qualified_name()?,
+        })
+    }
+
+    /*
+     * Expressions.
+     */
+    fn expr(&mut self) -> Result<Expr> {
+        self.expr_with(OR)
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>> {
+        let mut exprs = vec![self.expr()?];
+        while self.eat(&TokenKind::Comma) {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn parenthesized_expr(&mut self) -> Result<Expr> {
+        self.expect(&TokenKind::LeftParen)?;
+        let expr = self.expr()?;
+        self.expect(&TokenKind::RightParen)?;
+        Ok(expr)
+    }
+
+    // Parses an expression made of operators that bind at least as tight as `min_precedence`.
+    fn expr_with(&mut self, min_precedence: u8) -> Result<Expr> {
+        let start = self.start();
+        let mut left = if self.eat_keyword("NOT") {
+            let expr = self.expr_with(NOT)?;
+            self.unary(UnaryOperator::Not, expr, start)
+        } else if let Some(operator) = match self.peek() {
+            Some(TokenKind::Minus) => Some(UnaryOperator::Negate),
+            Some(TokenKind::Plus) => Some(UnaryOperator::Plus),
+            Some(TokenKind::Tilde) => Some(UnaryOperator::BitNot),
+            _ => None,
+        } {
+            self.position += 1;
+            // -9223372036854775808 is an integer even though 9223372036854775808 is not.
+            if operator == UnaryOperator::Negate
+                && self.peek() == Some(&TokenKind::Number("9223372036854775808".to_string()))
+            {
+                self.position += 1;
+                Expr {
+                    kind: ExprKind::Literal(Literal::Integer(i64::MIN)),
+                    span: self.span_from(start),
+                }
+            } else {
+                let expr = self.expr_with(POSTFIX)?;
+                self.unary(operator, expr, start)
+            }
+        } else {
+            self.primary()?
+        };
+
+        while let Some(precedence) = self.infix_precedence() {
+            if precedence < min_precedence {
+                break;
+            }
+            left = self.infix(left, precedence)?;
+        }
+        Ok(left)
+    }
+
+    fn unary(&self, operator: UnaryOperator, expr: Expr, start: usize) -> Expr {
+        Expr {
+            kind: ExprKind::Unary {
+                operator,
+                expr: Box::new(expr),
+            },
+            span: self.span_from(start),
+        }
+    }
+
+    // Precedence of the operator at the current token, None when there is no operator.
+    fn infix_precedence(&self) -> Option<u8> {
+        let precedence = match self.peek()? {
+            TokenKind::Eq | TokenKind::NotEq => EQUALITY,
+            TokenKind::Lt | TokenKind::LtEq | TokenKind::Gt | TokenKind::GtEq => COMPARISON,
+            TokenKind::Ampersand
+            | TokenKind::Pipe
+            | TokenKind::ShiftLeft
+            | TokenKind::ShiftRight => BITWISE,
+            TokenKind::Plus | TokenKind::Minus => ADDITIVE,
+            TokenKind::Star | TokenKind::Slash | TokenKind::Percent => MULTIPLICATIVE,
+            TokenKind::Concat => CONCAT,
+            TokenKind::Identifier(keyword) => match keyword.to_ascii_uppercase().as_str() {
+                "OR" => OR,
+                "AND" => AND,
+                "IS" | "IN" | "LIKE" | "GLOB" | "REGEXP" | "MATCH" | "BETWEEN" | "ISNULL"
+                | "NOTNULL" => EQUALITY,
+                "NOT"
+                    if ["IN", "LIKE", "GLOB", "REGEXP", "MATCH", "BETWEEN", "NULL"]
+                        .iter()
+                        .any(|keyword| self.peek_keyword_at(1, keyword)) =>
+                {
+                    EQUALITY
+                }
+                "COLLATE" => POSTFIX,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(precedence)
+    }
+
+    fn infix(&mut self, left: Expr, precedence: u8) -> Result<Expr> {
+        let start = left.span.start;
+        let operator = match self.peek() {
+            Some(TokenKind::Eq) => Some(BinaryOperator::Eq),
+            Some(TokenKind::NotEq) => Some(BinaryOperator::NotEq),
+            Some(TokenKind::Lt) => Some(BinaryOperator::Lt),
+            Some(TokenKind::LtEq) => Some(BinaryOperator::LtEq),
+            Some(TokenKind::Gt) => Some(BinaryOperator::Gt),
+            Some(TokenKind::GtEq) => Some(BinaryOperator::GtEq),
+            Some(TokenKind::Ampersand) => Some(BinaryOperator::BitAnd),
+            Some(TokenKind::Pipe) => Some(BinaryOperator::BitOr),
+            Some(TokenKind::ShiftLeft) => Some(BinaryOperator::ShiftLeft),
+            Some(TokenKind::ShiftRight) => Some(BinaryOperator::ShiftRight),
+            Some(TokenKind::Plus) => Some(BinaryOperator::Add),
+            Some(TokenKind::Minus) => Some(BinaryOperator::Subtract),
+            Some(TokenKind::Star) => Some(BinaryOperator::Multiply),
+            Some(TokenKind::Slash) => Some(BinaryOperator::Divide),
+            Some(TokenKind::Percent) => Some(BinaryOperator::Remainder),
+            Some(TokenKind::Concat) => Some(BinaryOperator::Concat),
+            _ if self.peek_keyword("OR") => Some(BinaryOperator::Or),
+            _ if self.peek_keyword("AND") => Some(BinaryOperator::And),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            self.position += 1;
+            let right = self.expr_with(precedence + 1)?;
+            return Ok(Expr {
+                kind: ExprKind::Binary {
+                    operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+                span: self.span_from(start),
+            });
+        }
+
+        let left = Box::new(left);
+        let kind = if self.eat_keyword("IS") {
+            // IS [NOT] [DISTINCT FROM], where IS DISTINCT FROM is IS NOT.
+            let mut negated = self.eat_keyword("NOT");
+            if self.eat_keywords(&["DISTINCT", "FROM"]) {
+                negated = !negated;
+            }
+            let operator = if negated {
+                BinaryOperator::IsNot
+            } else {
+                BinaryOperator::Is
+            };
+            ExprKind::Binary {
+                operator,
+                left,
+                right: Box::new(self.expr_with(COMPARISON)?),
+            }
+        } else if self.eat_keyword("ISNULL") {
+            ExprKind::IsNull {
+                negated: false,
+                expr: left,
+            }
+        } else if self.eat_keyword("NOTNULL") || self.eat_keywords(&["NOT", "NULL"]) {
+            ExprKind::IsNull {
+                negated: true,
+                expr: left,
+            }
+        } else if self.eat_keyword("COLLATE") {
+            ExprKind::Collate {
+                expr: left,
+                collation: self.name()?,
+            }
+        } else {
+            let negated = self.eat_keyword("NOT");
+            if self.eat_keyword("IN") {
+                self.in_expr(left, negated)?
+            } else if self.eat_keyword("BETWEEN") {
+                let low = Box::new(self.expr_with(COMPARISON)?);
+                self.expect_keyword("AND")?;
+                let high = Box::new(self.expr_with(COMPARISON)?);
+                ExprKind::Between {
+                    negated,
+                    expr: left,
+                    low,
+                    high,
+                }
+            } else {
+                let operator = [
+                    ("LIKE", LikeOperator::Like),
+                    ("GLOB", LikeOperator::Glob),
+                    ("REGEXP", LikeOperator::Regexp),
+                    ("MATCH", LikeOperator::Match),
+                ]
+                .into_iter()
+                .find(|(keyword, _)| self.peek_keyword(keyword))
+                .map(|(_, operator)| operator)
+                .ok_or_else(|| self.error())?;
+                self.position += 1;
+                let pattern = Box::new(self.expr_with(COMPARISON)?);
+                let escape = if self.eat_keyword("ESCAPE") {
+                    Some(Box::new(self.expr_with(COMPARISON)?))
+                } else {
+                    None
+                };
+                ExprKind::Like {
+                    operator,
+                    negated,
+                    expr: left,
+                    pattern,
+                    escape,
+                }
+            }
+        };
+        Ok(Expr {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    // What follows `x [NOT] IN`: a list, a subquery or a table.
+    fn in_expr(&mut self, expr: Box<Expr>, negated: bool) -> Result<ExprKind> {
+        if !self.eat(&TokenKind::LeftParen) {
+            return Ok(ExprKind::InTable {
+                negated,
+                expr,
+                table: self.qualified_name()?,
+            });
+        }
+
+        let kind = if self.starts_select() {
+            ExprKind::InSelect {
+                negated,
+                expr,
+                select: Box::new(self.select()?),
+            }
+        } else if self.peek() == Some(&TokenKind::RightParen) {
+            ExprKind::InList {
+                negated,
+                expr,
+                list: Vec::new(),
+            }
+        } else {
+            ExprKind::InList {
+                negated,
+                expr,
+                list: self.expr_list()?,
+            }
+        };
+        self.expect(&TokenKind::RightParen)?;
+        Ok(kind)
+    }
+
+    fn starts_select(&self) -> bool {
+        self.peek_keyword("SELECT") || self.peek_keyword("WITH") || self.peek_keyword("VALUES")
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let start = self.start();
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.error());
+        };
+        let next_is_paren = self.peek_at(1) == Some(&TokenKind::LeftParen);
+
+        let kind = match token {
+            TokenKind::Number(number) => {
+                let literal = self.number(&number)?;
+                self.position += 1;
+                ExprKind::Literal(literal)
+            }
+            TokenKind::String(string) => {
+                self.position += 1;
+                ExprKind::Literal(Literal::String(string))
+            }
+            TokenKind::Blob(blob) => {
+                self.position += 1;
+                ExprKind::Literal(Literal::Blob(blob))
+            }
+            TokenKind::Variable(name) => {
+                self.position += 1;
+                ExprKind::Variable(name)
+            }
+            TokenKind::LeftParen => {
+                self.position += 1;
+                if self.starts_select() {
+                    let select = self.select()?;
+                    self.expect(&TokenKind::RightParen)?;
+                    ExprKind::Subquery(Box::new(select))
+                } else {
+                    let mut exprs = self.expr_list()?;
+                    self.expect(&TokenKind::RightParen)?;
+                    if exprs.len() > 1 {
+                        ExprKind::Row(exprs)
+                    } else {
+                        // The parentheses are part of the span.
+                        exprs.pop().unwrap().kind
+                    }
+                }
+            }
+            _ if self.eat_keyword("NULL") => ExprKind::Literal(Literal::Null),
+            _ if self.eat_keyword("TRUE") => ExprKind::Literal(Literal::Integer(1)),
+            _ if self.eat_keyword("FALSE") => ExprKind::Literal(Literal::Integer(0)),
+            _ if self.eat_keyword("CURRENT_TIME") => ExprKind::Literal(Literal::CurrentTime),
+            _ if self.eat_keyword("CURRENT_DATE") => ExprKind::Literal(Literal::CurrentDate),
+            _ if self.eat_keyword("CURRENT_TIMESTAMP") => {
+                ExprKind::Literal(Literal::CurrentTimestamp)
+            }
+            _ if self.eat_keyword("CASE") => self.case()?,
+            _ if next_is_paren && self.eat_keyword("CAST") => {
+                self.expect(&TokenKind::LeftParen)?;
+                let expr = Box::new(self.expr()?);
+                self.expect_keyword("AS")?;
+                let type_name = self.type_name()?.ok_or_else(|| self.error())?;
+                self.expect(&TokenKind::RightParen)?;
+                ExprKind::Cast { expr, type_name }
+            }
+            _ if next_is_paren && self.eat_keyword("EXISTS") => {
+                self.expect(&TokenKind::LeftParen)?;
+                let select = self.select()?;
+                self.expect(&TokenKind::RightParen)?;
+                ExprKind::Exists(Box::new(select))
+            }
+            _ if next_is_paren && self.eat_keyword("RAISE") => self.raise()?,
+            TokenKind::Identifier(name) if next_is_paren => {
+                self.position += 1;
+                self.function(name)?
+            }
+            TokenKind::Identifier(_) | TokenKind::QuotedIdentifier(_) => {
+                // column, table.column or schema.table.column
+                let mut names = vec![self.name()?];
+                while names.len() < 3 && self.peek() == Some(&TokenKind::Dot) {
+                    self.position += 1;
+                    names.push(self.name()?);
+                }
+                let name = names.pop().unwrap();
+                ExprKind::Column {
+                    table: names.pop(),
+                    name,
+                }
+            }
+            _ => return Err(self.error()),
+        };
+        Ok(Expr {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    // Integers too large for an i64 turn into reals, the same as in SQLite, except for hex numbers.
+    fn number(&self, number: &str) -> Result<Literal> {
+        if let Some(hex) = number
+            .strip_prefix("0x")
+            .or_else(|| number.strip_prefix("0X"))
+        {
+            return match u64::from_str_radix(hex, 16) {
+                Ok(value) => Ok(Literal::Integer(value as i64)),
+                Err(_) => Err(DBError::Syntax(
+                    self.tokens[self.position].span,
+                    format!("hex literal too big: {}", number),
+                )),
+            };
+        }
+        if let Ok(value) = number.parse::<i64>() {
+            return Ok(Literal::Integer(value));
+        }
+        number.parse::<f64>().map(Literal::Real).map_err(|_| {
+            DBError::Syntax(
+                self.tokens[self.position].span,
+                format!("malformed number: {}", number),
+            )
+        })
+    }
+
+    // CASE [operand] WHEN expr THEN expr ... [ELSE expr] END, after the CASE.
+    fn case(&mut self) -> Result<ExprKind> {
+        let operand = if self.peek_keyword("WHEN") {
+            None
+        } else {
+            Some(Box::new(self.expr()?))
+        };
+        let mut when_then = Vec::new();
+        while self.eat_keyword("WHEN") {
+            let when = self.expr()?;
+            self.expect_keyword("THEN")?;
+            when_then.push((when, self.expr()?));
+        }
+        if when_then.is_empty() {
+            return Err(self.error());
+        }
+        let else_expr = if self.eat_keyword("ELSE") {
+            Some(Box::new(self.expr()?))
+        } else {
+            None
+        };
+        self.expect_keyword("END")?;
+        Ok(ExprKind::Case {
+            operand,
+            when_then,
+            else_expr,
+        })
+    }
+
+    // RAISE(IGNORE) or RAISE(ROLLBACK|ABORT|FAIL, message), after the RAISE.
+    fn raise(&mut self) -> Result<ExprKind> {
+        self.expect(&TokenKind::LeftParen)?;
+        let kind = if self.eat_keyword("IGNORE") {
+            RaiseKind::Ignore
+        } else if self.eat_keyword("ROLLBACK") {
+            RaiseKind::Rollback
+        } else if self.eat_keyword("ABORT") {
+            RaiseKind::Abort
+        } else if self.eat_keyword("FAIL") {
+            RaiseKind::Fail
+        } else {
+            return Err(self.error());
+        };
+        let mut message = None;
+        if kind != RaiseKind::Ignore {
+            self.expect(&TokenKind::Comma)?;
+            match self.peek() {
+                Some(TokenKind::String(text)) => message = Some(text.clone()),
+                _ => return Err(self.error()),
+            }
+            self.position += 1;
+        }
+        self.expect(&TokenKind::RightParen)?;
+        Ok(ExprKind::Raise { kind, message })
+    }
+
+    // name([DISTINCT] arguments) or name(*), after the name, then the FILTER clause of an aggregate
+    // and the OVER clause of a window function.
+    fn function(&mut self, name: String) -> Result<ExprKind> {
+        self.expect(&TokenKind::LeftParen)?;
+        let mut distinct = false;
+        let mut star = false;
+        let mut arguments = Vec::new();
+        if self.eat(&TokenKind::Star) {
+            star = true;
+        } else if self.peek() != Some(&TokenKind::RightParen) {
+            distinct = self.eat_keyword("DISTINCT");
+            if !distinct {
+                self.eat_keyword("ALL");
+            }
+            arguments = self.expr_list()?;
+        }
+        self.expect(&TokenKind::RightParen)?;
+        // FILTER is not reserved, without the parenthesis it is an alias.
+        let mut filter = None;
+        if self.peek_keyword("FILTER") && self.peek_at(1) == Some(&TokenKind::LeftParen) {
+            self.position += 2;
+            self.expect_keyword("WHERE")?;
+            filter = Some(Box::new(self.expr()?));
+            self.expect(&TokenKind::RightParen)?;
+        }
+        // Neither is OVER, which is followed by the window or its name.
+        let mut over = None;
+        if self.peek_keyword("OVER") {
+            match self.peek_at(1) {
+                Some(TokenKind::LeftParen) => {
+                    self.position += 1;
+                    over = Some(Box::new(self.window()?));
+                }
+                Some(TokenKind::Identifier(name)) if !is_reserved(name) => {
+                    self.position += 1;
+                    over = Some(Box::new(Window {
+                        base: Some(self.name()?),
+                        bare: true,
+                        partition_by: Vec::new(),
+                        order_by: Vec::new(),
+                        frame: None,
+                    }));
+                }
+                _ => {}
+            }
+        }
+        Ok(ExprKind::Function {
+            name,
+            arguments,
+            distinct,
+            star,
+            filter,
+            over,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::errors::DBError;
+    use crate::sql::ast::*;
+    use crate::sql::{parse, parse_expr, parse_statement};
+
+    // Writes an expression back out with every operator in parentheses, to see how it was grouped.
+    fn show(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Literal(Literal::Integer(value)) => value.to_string(),
+            ExprKind::Literal(Literal::Real(value)) => format!("{:?}", value),
+            ExprKind::Literal(Literal::String(value)) => format!("'{}'", value),
+            ExprKind::Literal(Literal::Null) => "NULL".to_string(),
+            ExprKind::Variable(name) => name.clone(),
+            ExprKind::Column { table, name } => match table {
+                Some(table) => format!("{}.{}", table, name),
+                None => name.clone(),
+            },
+            ExprKind::Unary { operator, expr } => format!("({:?} {})", operator, show(expr)),
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+            } => format!("({} {:?} {})", show(left), operator, show(right)),
+            ExprKind::Function {
+                name,
+                arguments,
+                distinct,
+                star,
+                filter,
+                ..
+            } => {
+                let arguments: Vec<String> = arguments.iter().map(show).collect();
+                let distinct = if *distinct { "DISTINCT " } else { "" };
+                let star = if *star { "*" } else { "" };
+                let filter = filter.as_ref().map_or(String::new(), |filter| {
+                    format!(" FILTER ({})", show(filter))
+                });
+                format!(
+                    "{}({}{}{}){}",
+                    name,
+                    distinct,
+                    star,
+                    arguments.join(", "),
+                    filter
+                )
+            }
+            ExprKind::Collate { expr, collation } => {
+                format!("({} COLLATE {})", show(expr), collation)
+            }
+            ExprKind::Like {
+                operator,
+                negated,
+                expr,
+                pattern,
+                escape,
+            } => format!(
+                "({} {}{:?} {}{})",
+                show(expr),
+                if *negated { "NOT " } else { "" },
+                operator,
+                show(pattern),
+                escape
+                    .as_ref()
+                    .map_or(String::new(), |escape| format!(" ESCAPE {}", show(escape)))
+            ),
+            ExprKind::Between {
+                negated,
+                expr,
+                low,
+                high,
+            } => format!(
+                "({} {}BETWEEN {} AND {})",
+                show(expr),
+                if *negated { "NOT " } else { "" },
+                show(low),
+                show(high)
+            ),
+            ExprKind::InList {
+                negated,
+                expr,
+                list,
+            } => format!(
+                "({} {}IN ({}))",
+                show(expr),
+                if *negated { "NOT " } else { "" },
+                list.iter().map(show).collect::<Vec<String>>().join(", ")
+            ),
+            ExprKind::IsNull { negated, expr } => format!(
+                "({} {})",
+                show(expr),
+                if *negated { "NOTNULL" } else { "ISNULL" }
+            ),
+            ExprKind::Cast { expr, type_name } => format!("CAST({} AS {})", show(expr), type_name),
+            ExprKind::Case {
+                operand,
+                when_then,
+                else_expr,
+            } => {
+                let mut case = "CASE".to_string();
+                if let Some(operand) = operand {
+                    case += &format!(" {}", show(operand));
+                }
+                for (when, then) in when_then {
+                    case += &format!(" WHEN {} THEN {}", show(when), show(then));
+                }
+                if let Some(else_expr) = else_expr {
+                    case += &format!(" ELSE {}", show(else_expr));
+                }
+                case + " END"
+            }
+            kind => format!("{:?}", kind),
+        }
+    }
+
+    fn parse_select(sql: &str) -> Select {
+        match parse_statement(sql).unwrap() {
+            Statement::Select(select) => select,
+            statement => panic!("Expected a SELECT, got: {:?}", statement),
+        }
+    }
+
+    fn table_name(table: &TableOrSubquery) -> &str {
+        match table {
+            TableOrSubquery::Table { name, .. } => &name.name,
+            TableOrSubquery::Subquery { .. } => "(subquery)",
+        }
+    }
+
+    #[test]
+    fn expression_precedence_test() {
+        for (sql, expected) in [
+            ("1 + 2 * 3 - 4", "((1 Add (2 Multiply 3)) Subtract 4)"),
+            ("a OR b AND NOT c = d", "(a Or (b And (Not (c Eq d))))"),
+            (
+                "-a.b || 'x' COLLATE nocase",
+                "((Negate a.b) Concat ('x' COLLATE nocase))",
+            ),
+            ("a < b = c > d", "((a Lt b) Eq (c Gt d))"),
+            ("x BETWEEN 1 AND 2 AND y", "((x BETWEEN 1 AND 2) And y)"),
+            ("x NOT LIKE 'a%' ESCAPE '!'", "(x NOT Like 'a%' ESCAPE '!')"),
+            ("x IS NOT DISTINCT FROM y", "(x Is y)"),
+            (
+                "x IS NOT NULL OR y NOT NULL",
+                "((x IsNot NULL) Or (y NOTNULL))",
+            ),
+            (
+                "x NOT IN (1, 2) AND y IN ()",
+                "((x NOT IN (1, 2)) And (y IN ()))",
+            ),
+            (
+                "count(*) + count(DISTINCT a)",
+                "(count(*) Add count(DISTINCT a))",
+            ),
+            ("sum(a) FILTER (WHERE a > 0)", "sum(a) FILTER ((a Gt 0))"),
+            ("-9223372036854775808", "-9223372036854775808"),
+            (
+                "9223372036854775808 + 0x10",
+                "(9.223372036854776e18 Add 16)",
+            ),
+            (
+                "CAST(a AS VARCHAR(10)) >= ?1 & :b",
+                "(CAST(a AS VARCHAR(10)) GtEq (?1 BitAnd :b))",
+            ),
+            (
+                "CASE WHEN a THEN 1 ELSE 2 END * CASE b WHEN 1 THEN 'x' END",
+                "(CASE WHEN a THEN 1 ELSE 2 END Multiply CASE b WHEN 1 THEN 'x' END)",
+            ),
+            ("(1 + 2) * 3", "((1 Add 2) Multiply 3)"),
+            ("~1 << 2 % 3", "((BitNot 1) ShiftLeft (2 Remainder 3))"),
+        ] {
+            assert_eq!(show(&parse_expr(sql).unwrap()), expected, "{}", sql);
+        }
+
+        // Spans cover the whole expression, the parentheses included.
+        let sql = "a + (b * c)";
+        let expr = parse_expr(sql).unwrap();
+        assert_eq!(expr.span, Span::new(0, sql.len()));
+        let ExprKind::Binary { right, .. } = expr.kind else {
+            panic!("Expected a binary expression");
+        };
+        assert_eq!(&sql[right.span.start..right.span.end], "(b * c)");
+    }
+
+    #[test]
+    fn select_test() {
+        let select = parse_select(
+            "WITH recent AS (SELECT * FROM sightings WHERE year > 2000) \
+             SELECT DISTINCT w.name, count(*) AS sightings, max(s.year) last \
+             FROM worms AS w LEFT OUTER JOIN recent s ON s.worm_id = w.id, places p \
+             NATURAL JOIN regions USING (region_id) \
+             WHERE w.length > (SELECT avg(length) FROM worms) AND EXISTS (SELECT 1 FROM t) \
+             GROUP BY w.name HAVING count(*) > 1 \
+             UNION ALL VALUES (1, 2, 3) \
+             ORDER BY 2 DESC NULLS LAST, name LIMIT 10 OFFSET 5",
+        );
+        assert_eq!(select.with.as_ref().unwrap().tables[0].name, "recent");
+        let SelectCore::Select {
+            distinct,
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            ..
+        } = &select.body
+        else {
+            panic!("Expected a SELECT core");
+        };
+        assert!(distinct);
+        let aliases: Vec<Option<&str>> = columns
+            .iter()
+            .map(|column| match column {
+                ResultColumn::Expr { alias, .. } => alias.as_deref(),
+                _ => panic!("Expected an expression column"),
+            })
+            .collect();
+        assert_eq!(aliases, vec![None, Some("sightings"), Some("last")]);
+
+        let from = from.as_ref().unwrap();
+        assert!(matches!(
+            &from.first,
+            TableOrSubquery::Table { name, alias: Some(alias), .. } if name.name == "worms" && alias == "w"
+        ));
+        let joins: Vec<(bool, JoinKind, &str)> = from
+            .joins
+            .iter()
+            .map(|join| (join.natural, join.kind, table_name(&join.table)))
+            .collect();
+        assert_eq!(
+            joins,
+            vec![
+                (false, JoinKind::Left, "recent"),
+                (false, JoinKind::Comma, "places"),
+                (true, JoinKind::Inner, "regions"),
+            ]
+        );
+        assert_eq!(
+            from.joins[2].constraint,
+            Some(JoinConstraint::Using(vec!["region_id".to_string()]))
+        );
+        assert!(matches!(
+            &where_clause.as_ref().unwrap().kind,
+            ExprKind::Binary { operator: BinaryOperator::And, left, right }
+                if matches!(&left.kind, ExprKind::Binary { right, .. } if matches!(right.kind, ExprKind::Subquery(_)))
+                    && matches!(right.kind, ExprKind::Exists(_))
+        ));
+        assert_eq!(group_by.len(), 1);
+        assert_eq!(show(having.as_ref().unwrap()), "(count(*) Gt 1)");
+
+        assert_eq!(select.compound.len(), 1);
+        assert!(matches!(
+            &select.compound[0],
+            (CompoundOperator::UnionAll, SelectCore::Values(rows)) if rows[0].len() == 3
+        ));
+        assert_eq!(select.order_by.len(), 2);
+        assert_eq!(select.order_by[0].order, Some(Order::Desc));
+        assert_eq!(select.order_by[0].nulls, Some(NullsOrder::Last));
+        let limit = select.limit.as_ref().unwrap();
+        assert_eq!(show(&limit.limit), "10");
+        assert_eq!(show(limit.offset.as_ref().unwrap()), "5");
+
+        // `LIMIT offset, count` and `table.*`.
+        let select = parse_select("SELECT w.*, * FROM worms w INDEXED BY worms_name LIMIT 5, 10");
+        let SelectCore::Select { columns, from, .. } = &select.body else {
+            panic!("Expected a SELECT core");
+        };
+        assert_eq!(
+            columns,
+            &vec![ResultColumn::AllFrom("w".to_string()), ResultColumn::All]
+        );
+        assert!(matches!(
+            &from.as_ref().unwrap().first,
+            TableOrSubquery::Table { indexed: Some(Indexed::By(index)), .. } if index == "worms_name"
+        ));
+        let limit = select.limit.unwrap();
+        assert_eq!(show(&limit.limit), "10");
+        assert_eq!(show(&limit.offset.unwrap()), "5");
+    }
+
+    #[test]
+    fn window_test() {
+        let select = parse_select(
+            "SELECT rank() OVER (PARTITION BY a, b ORDER BY c DESC), \
+                 sum(c) FILTER (WHERE c > 0) OVER (w ROWS BETWEEN 2 PRECEDING AND UNBOUNDED FOLLOWING \
+                 EXCLUDE TIES), \
+                 count(*) OVER w, max(c) over \
+             FROM t window WINDOW w AS (ORDER BY c GROUPS CURRENT ROW)",
+        );
+        let SelectCore::Select {
+            columns,
+            from,
+            windows,
+            ..
+        } = &select.body
+        else {
+            panic!("Expected a SELECT core");
+        };
+        let overs: Vec<Option<&Window>> = columns
+            .iter()
+            .map(|column| match column {
+                ResultColumn::Expr {
+                    expr:
+                        Expr {
+                            kind: ExprKind::Function { over, .. },
+                            ..
+                        },
+                    ..
+                } => over.as_deref(),
+                _ => panic!("Expected a function"),
+            })
+            .collect();
+        let rank = overs[0].unwrap();
+        assert_eq!(rank.partition_by.len(), 2);
+        assert_eq!(rank.order_by[0].order, Some(Order::Desc));
+        assert_eq!(rank.frame, None);
+        let sum = overs[1].unwrap();
+        assert_eq!(sum.base.as_deref(), Some("w"));
+        assert!(!sum.bare);
+        let frame = sum.frame.as_ref().unwrap();
+        assert_eq!(frame.units, FrameUnits::Rows);
+        assert!(matches!(&frame.start, FrameBound::Preceding(offset) if show(offset) == "2"));
+        assert_eq!(frame.end, FrameBound::UnboundedFollowing);
+        assert_eq!(frame.exclude, FrameExclude::Ties);
+        assert_eq!(overs[2].unwrap().base.as_deref(), Some("w"));
+        assert!(overs[2].unwrap().bare);
+        // OVER and WINDOW are not reserved, they can be aliases.
+        assert!(overs[3].is_none());
+        assert!(matches!(
+            &from.as_ref().unwrap().first,
+            TableOrSubquery::Table { alias: Some(alias), .. } if alias == "window"
+        ));
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].0, "w");
+        let frame = windows[0].1.frame.as_ref().unwrap();
+        assert_eq!(
+            (frame.units, &frame.start, &frame.end),
+            (
+                FrameUnits::Groups,
+                &FrameBound::CurrentRow,
+                &FrameBound::CurrentRow
+            )
+        );
+    }
+
+    #[test]
+    fn insert_update_delete_test() {
+        let Statement::Insert(insert) = parse_statement(
+            "INSERT OR IGNORE INTO main.worms (name, length) VALUES ('a', 1.5), ('b', NULL) RETURNING id",
+        )
+        .unwrap() else {
+            panic!("Expected an INSERT");
+        };
+        assert_eq!(insert.or_conflict, Some(ConflictResolution::Ignore));
+        assert_eq!(insert.table.schema.as_deref(), Some("main"));
+        assert_eq!(insert.table.name, "worms");
+        assert_eq!(insert.columns, vec!["name", "length"]);
+        assert!(matches!(&insert.source, InsertSource::Values(rows) if rows.len() == 2));
+        assert_eq!(insert.returning.len(), 1);
+
+        let Statement::Insert(insert) =
+            parse_statement("REPLACE INTO worms SELECT * FROM old_worms").unwrap()
+        else {
+            panic!("Expected an INSERT");
+        };
+        assert_eq!(insert.or_conflict, Some(ConflictResolution::Replace));
+        assert!(matches!(insert.source, InsertSource::Select(_)));
+
+        let Statement::Insert(insert) =
+            parse_statement("INSERT INTO worms DEFAULT VALUES").unwrap()
+        else {
+            panic!("Expected an INSERT");
+        };
+        assert_eq!(insert.source, InsertSource::DefaultValues);
+
+        let Statement::Update(update) = parse_statement(
+            "UPDATE OR ROLLBACK worms SET length = length * 2, name = upper(name) WHERE id IN (SELECT id FROM t)",
+        )
+        .unwrap() else {
+            panic!("Expected an UPDATE");
+        };
+        assert_eq!(update.or_conflict, Some(ConflictResolution::Rollback));
+        let assignments: Vec<(&str, String)> = update
+            .assignments
+            .iter()
+            .map(|(column, expr)| (column.as_str(), show(expr)))
+            .collect();
+        assert_eq!(
+            assignments,
+            vec![
+                ("length", "(length Multiply 2)".to_string()),
+                ("name", "upper(name)".to_string())
+            ]
+        );
+        assert!(matches!(
+            update.where_clause.unwrap().kind,
+            ExprKind::InSelect { negated: false, .. }
+        ));
+
+        let Statement::Delete(delete) =
+            parse_statement("DELETE FROM worms WHERE id = 5 RETURNING *").unwrap()
+        else {
+            panic!("Expected a DELETE");
+        };
+        assert_eq!(show(&delete.where_clause.unwrap()), "(id Eq 5)");
+        assert_eq!(delete.returning, vec![ResultColumn::All]);
+    }
+
+    #[test]
+    fn create_table_test() {
+        let Statement::CreateTable(create_table) = parse_statement(
+            "CREATE TEMP TABLE IF NOT EXISTS sightings (\
+                 id INTEGER PRIMARY KEY DESC ON CONFLICT REPLACE AUTOINCREMENT,\
+                 worm_id INT NOT NULL REFERENCES worms(id) ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED,\
+                 place TEXT COLLATE NOCASE DEFAULT 'desert' CHECK (place <> ''),\
+                 seen_at DATETIME DEFAULT CURRENT_TIMESTAMP,\
+                 depth DECIMAL(10, -2) DEFAULT -1,\
+                 depth_km REAL GENERATED ALWAYS AS (depth / 1000) STORED,\
+                 CONSTRAINT one_per_place UNIQUE (worm_id, place COLLATE NOCASE DESC)\
+                 FOREIGN KEY (place) REFERENCES places (name) ON UPDATE SET NULL\
+             ) WITHOUT ROWID, STRICT",
+        )
+        .unwrap() else {
+            panic!("Expected a CREATE TABLE");
+        };
+        assert!(create_table.temporary);
+        assert!(create_table.if_not_exists);
+        assert_eq!(create_table.name.name, "sightings");
+        let TableDefinition::Columns {
+            columns,
+            constraints,
+            without_rowid,
+            strict,
+        } = create_table.definition
+        else {
+            panic!("Expected column definitions");
+        };
+        assert!(without_rowid && strict);
+
+        let types: Vec<String> = columns
+            .iter()
+            .map(|column| column.type_name.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                "INTEGER",
+                "INT",
+                "TEXT",
+                "DATETIME",
+                "DECIMAL(10, -2)",
+                "REAL"
+            ]
+        );
+        assert_eq!(
+            columns[0].constraints[0].kind,
+            ColumnConstraintKind::PrimaryKey {
+                order: Some(Order::Desc),
+                on_conflict: Some(ConflictResolution::Replace),
+                autoincrement: true,
+            }
+        );
+        assert_eq!(
+            columns[1].constraints[1].kind,
+            ColumnConstraintKind::References(ForeignKey {
+                table: "worms".to_string(),
+                columns: vec!["id".to_string()],
+                on_delete: Some(ForeignKeyAction::Cascade),
+                on_update: None,
+                deferred: true,
+            })
+        );
+        let place_constraints: Vec<&ColumnConstraintKind> = columns[2]
+            .constraints
+            .iter()
+            .map(|constraint| &constraint.kind)
+            .collect();
+        assert!(matches!(
+            place_constraints.as_slice(),
+            [
+                ColumnConstraintKind::Collate(_),
+                ColumnConstraintKind::Default(_),
+                ColumnConstraintKind::Check(_)
+            ]
+        ));
+        assert!(matches!(
+            &columns[3].constraints[0].kind,
+            ColumnConstraintKind::Default(Expr {
+                kind: ExprKind::Literal(Literal::CurrentTimestamp),
+                ..
+            })
+        ));
+        assert!(matches!(
+            &columns[5].constraints[0].kind,
+            ColumnConstraintKind::Generated { stored: true, .. }
+        ));
+
+        // The comma between table constraints is optional.
+        assert_eq!(constraints.len(), 2);
+        assert_eq!(constraints[0].name.as_deref(), Some("one_per_place"));
+        let TableConstraintKind::Unique { columns, .. } = &constraints[0].kind else {
+            panic!("Expected a UNIQUE constraint");
+        };
+        let names: Vec<Option<&str>> = columns.iter().map(IndexedColumn::column_name).collect();
+        assert_eq!(names, vec![Some("worm_id"), Some("place")]);
+        assert_eq!(columns[1].order, Some(Order::Desc));
+        assert!(matches!(
+            &constraints[1].kind,
+            TableConstraintKind::ForeignKey { columns, foreign_key }
+                if columns == &vec!["place".to_string()] && foreign_key.on_update == Some(ForeignKeyAction::SetNull)
+        ));
+    }
+
+    #[test]
+    fn other_statements_test() {
+        let statements = parse(
+            "CREATE UNIQUE INDEX IF NOT EXISTS worms_name ON worms (lower(name), length DESC) WHERE length > 0;\
+             CREATE VIEW long_worms (name) AS SELECT name FROM worms WHERE length > 100;\
+             CREATE TRIGGER IF NOT EXISTS log_update AFTER UPDATE OF length, name ON worms FOR EACH ROW \
+                 WHEN new.length < 0 BEGIN \
+                 SELECT RAISE(ABORT, 'negative length'); \
+                 INSERT INTO log VALUES (old.id, new.length); \
+             END;\
+             DROP TABLE IF EXISTS main.worms;\
+             DROP INDEX worms_name;;\
+             BEGIN IMMEDIATE TRANSACTION; SAVEPOINT a; RELEASE SAVEPOINT a; ROLLBACK TO a; ROLLBACK; END",
+        )
+        .unwrap();
+        assert_eq!(statements.len(), 11);
+
+        let Statement::CreateIndex(index) = &statements[0] else {
+            panic!("Expected a CREATE INDEX");
+        };
+        assert!(index.unique && index.if_not_exists);
+        assert_eq!(index.table, "worms");
+        let names: Vec<Option<&str>> = index
+            .columns
+            .iter()
+            .map(IndexedColumn::column_name)
+            .collect();
+        assert_eq!(names, vec![None, Some("length")]);
+        assert_eq!(show(index.where_clause.as_ref().unwrap()), "(length Gt 0)");
+
+        let Statement::CreateView(view) = &statements[1] else {
+            panic!("Expected a CREATE VIEW");
+        };
+        assert_eq!(view.columns, vec!["name"]);
+
+        let Statement::CreateTrigger(trigger) = &statements[2] else {
+            panic!("Expected a CREATE TRIGGER");
+        };
+        assert_eq!(trigger.time, Some(TriggerTime::After));
+        assert_eq!(
+            trigger.event,
+            TriggerEvent::Update(vec!["length".to_string(), "name".to_string()])
+        );
+        assert!(trigger.for_each_row);
+        assert_eq!(trigger.body.len(), 2);
+
+        assert_eq!(
+            statements[3..5]
+                .iter()
+                .map(|statement| match statement {
+                    Statement::Drop(drop) => (drop.kind, drop.if_exists, drop.name.name.as_str()),
+                    statement => panic!("Expected a DROP, got: {:?}", statement),
+                })
+                .collect::<Vec<_>>(),
+            vec![
+                (ObjectKind::Table, true, "worms"),
+                (ObjectKind::Index, false, "worms_name")
+            ]
+        );
+        assert_eq!(
+            statements[5..],
+            [
+                Statement::Begin(Some(TransactionKind::Immediate)),
+                Statement::Savepoint("a".to_string()),
+                Statement::Release("a".to_string()),
+                Statement::Rollback(Some("a".to_string())),
+                Statement::Rollback(None),
+                Statement::Commit,
+            ]
+        );
+
+        let Statement::Explain {
+            query_plan: true,
+            statement,
+        } = parse_statement("EXPLAIN QUERY PLAN SELECT 1").unwrap()
+        else {
+            panic!("Expected an EXPLAIN QUERY PLAN");
+        };
+        assert!(matches!(*statement, Statement::Select(_)));
+        assert!(matches!(
+            parse_statement("EXPLAIN DROP TABLE worms").unwrap(),
+            Statement::Explain {
+                query_plan: false,
+                ..
+            }
+        ));
+        assert!(parse_statement("EXPLAIN EXPLAIN SELECT 1").is_err());
+    }
+
+    #[test]
+    fn syntax_error_test() {
+        for (sql, message, at) in [
+            ("SELECT * FROM", "incomplete input", "SELECT * FROM".len()),
+            ("SELECT a FROM t WHERE", "incomplete input", 21),
+            ("SELECT a, FROM t", "near \"FROM\": syntax error", 10),
+            ("SELECT a FROM t LEFT t2", "near \"t2\": syntax error", 21),
+            ("INSERT INTO t VALUES (1", "incomplete input", 23),
+            ("CREATE TABLE t (a INT,)", "near \")\": syntax error", 22),
+            ("SELECT 1 SELECT 2", "near \"SELECT\": syntax error", 9),
+            (
+                "SELECT sum(a) OVER (ROWS UNBOUNDED FOLLOWING) FROM t",
+                "near \"FOLLOWING\": syntax error",
+                35,
+            ),
+            (
+                "SELECT 0xFFFFFFFFFFFFFFFFF",
+                "hex literal too big: 0xFFFFFFFFFFFFFFFFF",
+                7,
+            ),
+        ] {
+            match parse_statement(sql) {
+                Err(DBError::Syntax(span, error)) => {
+                    assert_eq!(error, message, "{}", sql);
+                    assert_eq!(span.start, at, "{}", sql);
+                }
+                result => panic!("Expected a syntax error for {}, got: {:?}", sql, result),
+            }
+        }
+    }
+}
@@ -0,0 +1,1072 @@
+use crate::page::errors::{DBError, Result};
+use crate::page::{ColumnType, Index, Table};
+
+use super::ast::{BinaryOperator, Expr, Indexed};
+use super::value::Collation;
+use super::vm::SortOrder;
+
+/*
+* Picks how a query gets to the rows of its table: a full scan, a lookup or a range of rowids, or a
+* search or scan of one of the table's indexes.
+*
+* The compiler breaks WHERE down into the terms ANDed together and tells the planner which of them
+* compare a column of the table with a value that does not depend on the row. Equality on the
+* leading columns of an index and a range on the column after them narrow a search of the index,
+* the same on the rowid a search of the table. The terms a search takes care of are left out of the
+* filter the rows still go through.
+*
+* Every way of getting to the rows gets a cost, in the spirit of SQLite's query planner
+* (https://www.sqlite.org/queryplanner.html), and the cheapest one wins. There are no statistics
+* to go by, so like SQLite without ANALYZE the planner takes every table to have about a million
+* rows and an equality on an index to narrow that down to about ten. Rows that come out of a path
+* in the order ORDER BY wants spare sorting them, and an index holding every column the query needs
+* spares looking up the rows in the table.
+*
+* A join is loops nested inside each other, and the planner also picks the order they go in, the
+* one costing the least with the plan of every loop counted once for each row outside it.
+*/
+
+// How many rows a table is taken to have.
+const TABLE_ROWS: f64 = 1048576.0;
+// How many rows equality on the leading columns of an index is taken to leave.
+const EQUALITY_ROWS: f64 = 10.0;
+// The cost of reading a row of a table, and of an index entry.
+const ROW_COST: f64 = 3.0;
+const ENTRY_COST: f64 = 1.5;
+// How many of the cheapest orders the search for the order of the tables of a join keeps going
+// with at every step, like the N in SQLite's "N nearest neighbors" search.
+const JOIN_PATHS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnRef {
+    Column(usize),
+    Rowid,
+}
+
+// `column operator value`, with the column of the table on the left.
+#[derive(Debug, Clone, Copy)]
+pub struct Constraint<'e> {
+    // The rowid for the rowid and its aliases.
+    pub column: ColumnRef,
+    // =, <, <=, > or >=.
+    pub operator: BinaryOperator,
+    pub value: &'e Expr,
+    pub value_affinity: Option<ColumnType>,
+    pub collation: Collation,
+}
+
+// One of the terms ANDed together in WHERE, and the constraints it puts on columns of the table.
+// BETWEEN puts two.
+#[derive(Debug)]
+pub struct Term<'e> {
+    pub expr: &'e Expr,
+    pub constraints: Vec<Constraint<'e>>,
+}
+
+// An ORDER BY term, with the column of the table it sorts by if it is a plain column reference.
+#[derive(Debug, Clone, Copy)]
+pub struct OrderTerm {
+    pub column: Option<ColumnRef>,
+    pub order: SortOrder,
+}
+
+// A value the rows are looked up by, and the affinity it takes on for the lookup.
+#[derive(Debug, Clone, Copy)]
+pub struct Key<'e> {
+    pub value: &'e Expr,
+    pub value_affinity: Option<ColumnType>,
+    // BLOB for none.
+    pub affinity: ColumnType,
+}
+
+// One end of a range.
+#[derive(Debug, Clone, Copy)]
+pub struct Bound<'e> {
+    pub key: Key<'e>,
+    pub inclusive: bool,
+}
+
+#[derive(Debug)]
+pub enum Access<'e, 'a> {
+    Scan,
+    RowidEq(Key<'e>),
+    RowidRange {
+        lower: Option<Bound<'e>>,
+        upper: Option<Bound<'e>>,
+    },
+    /*
+     * The entries of an index with the leading columns equal to `equal`, and the column after them
+     * in the range from `lower` to `upper`. With none of these it is a scan of the whole index. A
+     * covering index has every column the query needs and the rows are not looked up in the table.
+     */
+    Index {
+        index: &'a Index,
+        equal: Vec<Key<'e>>,
+        lower: Option<Bound<'e>>,
+        upper: Option<Bound<'e>>,
+        covering: bool,
+    },
+}
+
+#[derive(Debug)]
+pub struct Plan<'e, 'a> {
+    pub access: Access<'e, 'a>,
+    // Whether the rows come out in the order of ORDER BY, so they do not need sorting.
+    pub ordered: bool,
+    // Walks the table or index backwards, for an ORDER BY ... DESC.
+    pub reverse: bool,
+    // The terms of WHERE the rows still have to be checked against.
+    pub filter: Vec<&'e Expr>,
+    // How many rows the plan is taken to get to, and what getting to them costs, sorting aside.
+    pub rows: f64,
+    pub cost: f64,
+}
+
+impl Plan<'_, '_> {
+    // Whether the plan gets to one row at most, by the rowid or every column of a unique index.
+    pub fn single_row(&self) -> bool {
+        match &self.access {
+            Access::RowidEq(_) => true,
+            Access::Index { index, equal, .. } => {
+                index.unique && index.where_clause.is_none() && equal.len() == index.columns.len()
+            }
+            _ => false,
+        }
+    }
+
+    /*
+     * What EXPLAIN QUERY PLAN says about the plan, like "SEARCH t USING INDEX i (a=? AND b>?)".
+     * Like SQLite's, it does not tell >= from > or <= from <.
+     */
+    pub fn describe(&self, name: &str, table: &Table) -> String {
+        let range = |column: &str, lower: &Option<Bound>, upper: &Option<Bound>| {
+            let mut parts = Vec::new();
+            if lower.is_some() {
+                parts.push(format!("{}>?", column));
+            }
+            if upper.is_some() {
+                parts.push(format!("{}<?", column));
+            }
+            parts
+        };
+        match &self.access {
+            Access::Scan => format!("SCAN {}", name),
+            Access::RowidEq(_) => format!("SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", name),
+            Access::RowidRange { lower, upper } => format!(
+                "SEARCH {} USING INTEGER PRIMARY KEY ({})",
+                name,
+                range("rowid", lower, upper).join(" AND ")
+            ),
+            Access::Index {
+                index,
+                equal,
+                lower,
+                upper,
+                covering,
+            } => {
+                let column_name = |position: usize| match index.columns[position].column {
+                    Some(column) => table.columns[column].name.clone(),
+                    None => "<expr>".to_string(),
+                };
+                let mut parts: Vec<String> = (0..equal.len())
+                    .map(|position| format!("{}=?", column_name(position)))
+                    .collect();
+                if lower.is_some() || upper.is_some() {
+                    parts.extend(range(&column_name(equal.len()), lower, upper));
+                }
+                let covering = if *covering { "COVERING " } else { "" };
+                match parts.is_empty() {
+                    true => format!("SCAN {} USING {}INDEX {}", name, covering, index.name),
+                    false => format!(
+                        "SEARCH {} USING {}INDEX {} ({})",
+                        name,
+                        covering,
+                        index.name,
+                        parts.join(" AND ")
+                    ),
+                }
+            }
+        }
+    }
+}
+
+// A way of getting to the rows and what it costs, before it is picked.
+struct Candidate<'e, 'a> {
+    access: Access<'e, 'a>,
+    rows: f64,
+    cost: f64,
+    // Columns every row has the same value of, by the collation they are equal under.
+    equal_columns: Vec<(ColumnRef, Collation)>,
+    // The order the rows come out in: the columns, whether each is descending and its collation.
+    order: Vec<(ColumnRef, bool, Collation)>,
+    // The constraints used, by term and position in the term.
+    used: Vec<(usize, usize)>,
+}
+
+/*
+* Plans the query of a table. `columns` are the columns the query reads, `indexes` those of the
+* table. `indexed` is the INDEXED BY or NOT INDEXED of the FROM clause. `usable_partial` names the
+* partial indexes that have entries for every row the query wants, the others are left alone.
+*/
+pub fn plan<'e, 'a>(
+    table: &'a Table,
+    indexes: &[&'a Index],
+    indexed: Option<&Indexed>,
+    terms: &[Term<'e>],
+    order_by: &[OrderTerm],
+    columns: &[ColumnRef],
+    usable_partial: &[&str],
+) -> Result<Plan<'e, 'a>> {
+    let mut candidates = vec![Candidate {
+        access: Access::Scan,
+        rows: TABLE_ROWS,
+        cost: TABLE_ROWS * ROW_COST,
+        equal_columns: Vec::new(),
+        order: vec![(ColumnRef::Rowid, false, Collation::Binary)],
+        used: Vec::new(),
+    }];
+    candidates.extend(rowid_candidate(terms));
+
+    let indexes: Vec<&Index> = match indexed {
+        None => indexes.to_vec(),
+        Some(Indexed::Not) => Vec::new(),
+        Some(Indexed::By(name)) => {
+            let index = indexes
+                .iter()
+                .find(|index| index.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| DBError::Sql(format!("no such index: {}", name)))?;
+            // The index is all there is, the table is not scanned on its own.
+            candidates.clear();
+            vec![*index]
+        }
+    };
+    for index in indexes {
+        // The rows a partial index has no entries for might still be wanted.
+        if index.where_clause.is_some() && !usable_partial.contains(&index.name.as_str()) {
+            continue;
+        }
+        if let Some(candidate) = index_candidate(table, index, terms, columns) {
+            candidates.push(candidate);
+        }
+    }
+    if candidates.is_empty() {
+        return Err(DBError::Sql("no query solution".to_string()));
+    }
+
+    // The cheapest candidate with sorting its rows counted in, the earliest one of those that cost
+    // the same.
+    let mut best: Option<(f64, Candidate, Option<bool>)> = None;
+    for candidate in candidates {
+        let reverse = match order_by.is_empty() {
+            true => Some(false),
+            false => ordered_by(&candidate, order_by),
+        };
+        let mut cost = candidate.cost;
+        if reverse.is_none() {
+            cost += candidate.rows * candidate.rows.log2().max(1.0);
+        }
+        if best
+            .as_ref()
+            .is_none_or(|(best_cost, _, _)| cost < *best_cost)
+        {
+            best = Some((cost, candidate, reverse));
+        }
+    }
+    let (_, candidate, reverse) = best.unwrap();
+
+    let filter = terms
+        .iter()
+        .enumerate()
+        .filter(|(term_index, term)| {
+            let taken_care_of = !term.constraints.is_empty()
+                && (0..term.constraints.len())
+                    .all(|position| candidate.used.contains(&(*term_index, position)));
+            !taken_care_of
+        })
+        .map(|(_, term)| term.expr)
+        .collect();
+    Ok(Plan {
+        access: candidate.access,
+        ordered: reverse.is_some(),
+        reverse: reverse.unwrap_or(false),
+        filter,
+        rows: candidate.rows,
+        cost: candidate.cost,
+    })
+}
+
+/*
+* Picks the order the loops of a join go through its `count` tables in. Every order is given the
+* cost of going through the loops, each costing what `plan` says the loop over a table costs with
+* the given tables outside it for every row of those, and the cheapest wins. `plan` also says how
+* many rows the loop gets to. Tables `fixed` says so of keep their place, with the tables before
+* them in FROM outside them and those after inside. Orders are built up a table at a time keeping
+* the JOIN_PATHS cheapest ones so far, so a query with many tables does not try every order there
+* is.
+*/
+pub fn join_order(
+    count: usize,
+    fixed: impl Fn(usize) -> bool,
+    mut plan: impl FnMut(usize, &[usize]) -> Result<(f64, f64)>,
+) -> Result<Vec<usize>> {
+    if count <= 1 {
+        return Ok((0..count).collect());
+    }
+    let mut paths: Vec<(Vec<usize>, f64, f64)> = vec![(Vec::new(), 0.0, 1.0)];
+    let mut start = 0;
+    while start < count {
+        let end = match fixed(start) {
+            true => start + 1,
+            false => (start + 1..count)
+                .find(|source| fixed(*source))
+                .unwrap_or(count),
+        };
+        for _ in start..end {
+            let mut extended = Vec::new();
+            for (path, cost, rows) in paths.iter() {
+                for source in (start..end).filter(|source| !path.contains(source)) {
+                    let (loop_cost, loop_rows) = plan(source, path)?;
+                    let mut path = path.clone();
+                    path.push(source);
+                    extended.push((path, cost + rows * loop_cost, rows * loop_rows));
+                }
+            }
+            // Orders costing the same stay in the order of FROM.
+            extended.sort_by(|a, b| a.1.total_cmp(&b.1));
+            paths.clear();
+            for (path, cost, rows) in extended {
+                // An order of the same tables as a cheaper one is of no more use.
+                let mut tables = path.clone();
+                tables.sort_unstable();
+                let seen = paths.iter().any(|(other, _, _)| {
+                    let mut other = other.clone();
+                    other.sort_unstable();
+                    other == tables
+                });
+                if !seen && paths.len() < JOIN_PATHS {
+                    paths.push((path, cost, rows));
+                }
+            }
+        }
+        start = end;
+    }
+    Ok(paths.swap_remove(0).0)
+}
+
+// Equality or a range on the rowid.
+fn rowid_candidate<'e, 'a>(terms: &[Term<'e>]) -> Option<Candidate<'e, 'a>> {
+    let constraints = constraints_on(terms, ColumnRef::Rowid, Collation::Binary);
+    let seek = (TABLE_ROWS).log2();
+    let key = |constraint: &Constraint<'e>| Key {
+        value: constraint.value,
+        value_affinity: constraint.value_affinity,
+        // Seek does the rest.
+        affinity: ColumnType::Blob,
+    };
+    if let Some((used, constraint)) = constraints
+        .iter()
+        .find(|(_, constraint)| constraint.operator == BinaryOperator::Eq)
+    {
+        return Some(Candidate {
+            access: Access::RowidEq(key(constraint)),
+            rows: 1.0,
+            cost: seek,
+            equal_columns: vec![(ColumnRef::Rowid, Collation::Binary)],
+            order: Vec::new(),
+            used: vec![*used],
+        });
+    }
+
+    let (lower, upper, used) = range(&constraints, key);
+    if lower.is_none() && upper.is_none() {
+        return None;
+    }
+    let rows = range_rows(TABLE_ROWS, &lower, &upper);
+    Some(Candidate {
+        access: Access::RowidRange { lower, upper },
+        rows,
+        cost: seek + rows * ROW_COST,
+        equal_columns: Vec::new(),
+        order: vec![(ColumnRef::Rowid, false, Collation::Binary)],
+        used,
+    })
+}
+
+/*
+* A search of an index: equality on as many of its leading columns as there are terms for and a
+* range on the next one. Without either it is a scan of the whole index, which is only worth it for
+* the order of the entries or when the index covers the query.
+*/
+fn index_candidate<'e, 'a>(
+    table: &Table,
+    index: &'a Index,
+    terms: &[Term<'e>],
+    columns: &[ColumnRef],
+) -> Option<Candidate<'e, 'a>> {
+    let key_columns: Vec<(usize, Collation)> = index
+        .columns
+        .iter()
+        .map_while(|column| {
+            let collation = match &column.collation {
+                Some(name) => Collation::from_name(name)?,
+                None => Collation::Binary,
+            };
+            Some((column.column?, collation))
+        })
+        .collect();
+
+    let mut equal = Vec::new();
+    let mut equal_columns = Vec::new();
+    let mut used = Vec::new();
+    let mut lower = None;
+    let mut upper = None;
+    for (column, collation) in key_columns.iter().copied() {
+        let column_type = table.columns[column].column_type;
+        let constraints: Vec<((usize, usize), Constraint)> =
+            constraints_on(terms, ColumnRef::Column(column), collation)
+                .into_iter()
+                .filter(|(_, constraint)| {
+                    key_affinity(column_type, constraint.value_affinity).is_some()
+                })
+                .collect();
+        let key = |constraint: &Constraint<'e>| Key {
+            value: constraint.value,
+            value_affinity: constraint.value_affinity,
+            affinity: key_affinity(column_type, constraint.value_affinity).unwrap(),
+        };
+        if let Some((position, constraint)) = constraints
+            .iter()
+            .find(|(_, constraint)| constraint.operator == BinaryOperator::Eq)
+        {
+            equal.push(key(constraint));
+            equal_columns.push((ColumnRef::Column(column), collation));
+            used.push(*position);
+            continue;
+        }
+        let (range_lower, range_upper, range_used) = range(&constraints, key);
+        lower = range_lower;
+        upper = range_upper;
+        used.extend(range_used);
+        break;
+    }
+
+    let covering = columns.iter().all(|column| match column {
+        ColumnRef::Column(column) => index
+            .columns
+            .iter()
+            .any(|index_column| index_column.column == Some(*column)),
+        ColumnRef::Rowid => true,
+    });
+    // Equality on every column of a unique index finds one row at most.
+    let unique = index.unique && equal.len() == index.columns.len();
+    let mut rows = match equal.len() {
+        0 => TABLE_ROWS,
+        _ if unique => 1.0,
+        _ => EQUALITY_ROWS,
+    };
+    rows = range_rows(rows, &lower, &upper);
+    let entry_cost = match covering {
+        true => ENTRY_COST,
+        false => ENTRY_COST + ROW_COST,
+    };
+    let searched = !equal.is_empty() || lower.is_some() || upper.is_some();
+    let cost = match searched {
+        true => TABLE_ROWS.log2() + rows * entry_cost,
+        false => rows * entry_cost,
+    };
+
+    // After the equal columns the entries are in order of the rest of the index, and the rowid.
+    let mut order: Vec<(ColumnRef, bool, Collation)> = index.columns[equal.len()..]
+        .iter()
+        .zip(key_columns[equal.len().min(key_columns.len())..].iter())
+        .map(|(index_column, (column, collation))| {
+            (
+                ColumnRef::Column(*column),
+                index_column.descending,
+                *collation,
+            )
+        })
+        .collect();
+    if order.len() + equal.len() == index.columns.len() {
+        order.push((ColumnRef::Rowid, false, Collation::Binary));
+    }
+    if unique {
+        order.clear();
+        equal_columns.push((ColumnRef::Rowid, Collation::Binary));
+    }
+
+    Some(Candidate {
+        access: Access::Index {
+            index,
+            equal,
+            lower,
+            upper,
+            covering,
+        },
+        rows,
+        cost,
+        equal_columns,
+        order,
+        used,
+    })
+}
+
+// The constraints on a column under the collation, and where they are in the terms.
+fn constraints_on<'e>(
+    terms: &[Term<'e>],
+    column: ColumnRef,
+    collation: Collation,
+) -> Vec<((usize, usize), Constraint<'e>)> {
+    let mut found = Vec::new();
+    for (term_index, term) in terms.iter().enumerate() {
+        for (position, constraint) in term.constraints.iter().enumerate() {
+            // The rowid is an integer, no collation changes how it compares.
+            if constraint.column == column
+                && (column == ColumnRef::Rowid || constraint.collation == collation)
+            {
+                found.push(((term_index, position), *constraint));
+            }
+        }
+    }
+    found
+}
+
+// The first lower and upper bound among the constraints.
+#[allow(clippy::type_complexity)]
+fn range<'e>(
+    constraints: &[((usize, usize), Constraint<'e>)],
+    key: impl Fn(&Constraint<'e>) -> Key<'e>,
+) -> (Option<Bound<'e>>, Option<Bound<'e>>, Vec<(usize, usize)>) {
+    let mut lower = None;
+    let mut upper = None;
+    let mut used = Vec::new();
+    for (position, constraint) in constraints {
+        let bound = Bound {
+            key: key(constraint),
+            inclusive: matches!(
+                constraint.operator,
+                BinaryOperator::GtEq | BinaryOperator::LtEq
+            ),
+        };
+        let side = match constraint.operator {
+            BinaryOperator::Gt | BinaryOperator::GtEq => &mut lower,
+            BinaryOperator::Lt | BinaryOperator::LtEq => &mut upper,
+            _ => continue,
+        };
+        if side.is_none() {
+            *side = Some(bound);
+            used.push(*position);
+        }
+    }
+    (lower, upper, used)
+}
+
+// SQLite's guess without statistics: a bound leaves a quarter of the rows, two a sixty-fourth.
+fn range_rows(rows: f64, lower: &Option<Bound>, upper: &Option<Bound>) -> f64 {
+    let rows = match (lower, upper) {
+        (Some(_), Some(_)) => rows / 64.0,
+        (Some(_), None) | (None, Some(_)) => rows / 4.0,
+        (None, None) => rows,
+    };
+    rows.max(1.0)
+}
+
+/*
+* The affinity a value takes on to be looked up in an index on a column with the given affinity,
+* BLOB for none. It is what comparing the column with the value converts the value to. None when
+* the comparison converts the column instead, an index on the column is of no use then.
+*/
+fn key_affinity(column: ColumnType, value: Option<ColumnType>) -> Option<ColumnType> {
+    let is_numeric = |affinity: ColumnType| {
+        matches!(
+            affinity,
+            ColumnType::Integer | ColumnType::Real | ColumnType::Numeric
+        )
+    };
+    let value_numeric = value.is_some_and(is_numeric);
+    match column {
+        column if is_numeric(column) => match value_numeric {
+            true => Some(ColumnType::Blob),
+            false => Some(ColumnType::Numeric),
+        },
+        ColumnType::Text => match value {
+            _ if value_numeric => None,
+            Some(ColumnType::Text) => Some(ColumnType::Blob),
+            _ => Some(ColumnType::Text),
+        },
+        _ => match value {
+            _ if value_numeric => None,
+            Some(ColumnType::Text) => None,
+            _ => Some(ColumnType::Blob),
+        },
+    }
+}
+
+/*
+* Whether the rows of a candidate come out in the order of ORDER BY: Some(false) when walking it
+* forwards, Some(true) backwards and None when they have to be sorted. Terms on columns that are the
+* same in every row are in order whichever way.
+*/
+fn ordered_by(candidate: &Candidate, order_by: &[OrderTerm]) -> Option<bool> {
+    let mut reverse = None;
+    let mut position = 0;
+    for term in order_by {
+        let column = term.column?;
+        if candidate
+            .equal_columns
+            .iter()
+            .any(|(equal, collation)| *equal == column && *collation == term.order.collation)
+        {
+            continue;
+        }
+        // No more terms matter once the rows are in order of something unique, like the rowid.
+        if candidate
+            .equal_columns
+            .contains(&(ColumnRef::Rowid, Collation::Binary))
+        {
+            break;
+        }
+        let (ordered_column, descending, collation) = *candidate.order.get(position)?;
+        if ordered_column != column {
+            return None;
+        }
+        if column != ColumnRef::Rowid
+            && (term.order.collation != collation
+                || term.order.nulls_first == term.order.descending)
+        {
+            return None;
+        }
+        let backwards = term.order.descending != descending;
+        if reverse.is_some_and(|reverse| reverse != backwards) {
+            return None;
+        }
+        reverse = Some(backwards);
+        if column == ColumnRef::Rowid {
+            break;
+        }
+        position += 1;
+    }
+    Some(reverse.unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::errors::DBError;
+    use crate::page::schema::{parse_create_table, parse_index};
+    use crate::page::{ColumnType, Index, Table};
+    use crate::sql::ast::{BinaryOperator, Expr, Indexed};
+    use crate::sql::parse_expr;
+    use crate::sql::planner::{
+        join_order, plan, Access, ColumnRef, Constraint, OrderTerm, Plan, Term, JOIN_PATHS,
+    };
+    use crate::sql::value::Collation;
+    use crate::sql::vm::SortOrder;
+
+    const A: ColumnRef = ColumnRef::Column(1);
+    const B: ColumnRef = ColumnRef::Column(2);
+    const C: ColumnRef = ColumnRef::Column(3);
+
+    // `t(id INTEGER PRIMARY KEY, a INTEGER, b TEXT, c)` with the indexes t_a_b(a, b), the unique
+    // t_c(c) and the partial t_b(b) WHERE b IS NOT NULL.
+    fn schema() -> (Table, Vec<Index>) {
+        let table = parse_create_table(
+            "CREATE TABLE t(id INTEGER PRIMARY KEY, a INTEGER, b TEXT, c)",
+            2,
+        )
+        .unwrap();
+        let indexes = [
+            ("t_a_b", "CREATE INDEX t_a_b ON t(a, b)"),
+            ("t_b", "CREATE INDEX t_b ON t(b) WHERE b IS NOT NULL"),
+            ("t_c", "CREATE UNIQUE INDEX t_c ON t(c)"),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(position, (name, sql))| {
+            parse_index(
+                name.to_string(),
+                &table,
+                3 + position,
+                Some(sql.to_string()),
+            )
+            .unwrap()
+        })
+        .collect();
+        (table, indexes)
+    }
+
+    // A term of WHERE comparing a column with a value, `value` standing in for the whole term.
+    fn term(value: &Expr, column: ColumnRef, operator: BinaryOperator) -> Term<'_> {
+        Term {
+            expr: value,
+            constraints: vec![Constraint {
+                column,
+                operator,
+                value,
+                value_affinity: None,
+                collation: Collation::Binary,
+            }],
+        }
+    }
+
+    fn order(column: ColumnRef, descending: bool) -> OrderTerm {
+        OrderTerm {
+            column: Some(column),
+            order: SortOrder {
+                descending,
+                nulls_first: !descending,
+                collation: Collation::Binary,
+            },
+        }
+    }
+
+    // The plan for a query reading every column.
+    fn plan_of<'e, 'a>(
+        (table, indexes): &'a (Table, Vec<Index>),
+        terms: &[Term<'e>],
+        order_by: &[OrderTerm],
+    ) -> Plan<'e, 'a> {
+        let indexes: Vec<&Index> = indexes.iter().collect();
+        plan(table, &indexes, None, terms, order_by, &[A, B, C], &[]).unwrap()
+    }
+
+    #[test]
+    fn cost_test() {
+        let schema = schema();
+        let value = parse_expr("?").unwrap();
+        let describe = |plan: &Plan| plan.describe("t", &schema.0);
+
+        let scan = plan_of(&schema, &[], &[]);
+        assert!(matches!(scan.access, Access::Scan));
+        assert_eq!(scan.cost, 1048576.0 * 3.0);
+
+        // A search of an index and looking up the ten rows it is taken to find beats a scan, but
+        // not a lookup of one row by its rowid.
+        let terms = [term(&value, A, BinaryOperator::Eq)];
+        let search = plan_of(&schema, &terms, &[]);
+        assert_eq!(describe(&search), "SEARCH t USING INDEX t_a_b (a=?)");
+        assert_eq!((search.rows, search.cost), (10.0, 20.0 + 10.0 * 4.5));
+        assert!(search.filter.is_empty());
+        let terms = [
+            term(&value, A, BinaryOperator::Eq),
+            term(&value, ColumnRef::Rowid, BinaryOperator::Eq),
+        ];
+        let lookup = plan_of(&schema, &terms, &[]);
+        assert_eq!(
+            describe(&lookup),
+            "SEARCH t USING INTEGER PRIMARY KEY (rowid=?)"
+        );
+        assert!(lookup.single_row());
+        assert_eq!(lookup.filter.len(), 1);
+
+        // Equality on every column of a unique index finds one row.
+        let terms = [
+            term(&value, A, BinaryOperator::Eq),
+            term(&value, C, BinaryOperator::Eq),
+        ];
+        let unique = plan_of(&schema, &terms, &[]);
+        assert_eq!(describe(&unique), "SEARCH t USING INDEX t_c (c=?)");
+        assert!(unique.single_row());
+        assert_eq!(unique.rows, 1.0);
+
+        // Reading entries of an index that covers the query is cheaper than reading rows.
+        let (table, indexes) = &schema;
+        let indexes: Vec<&Index> = indexes.iter().collect();
+        let covering = plan(table, &indexes, None, &[], &[], &[A], &[]).unwrap();
+        assert_eq!(describe(&covering), "SCAN t USING COVERING INDEX t_a_b");
+        assert_eq!(covering.cost, 1048576.0 * 1.5);
+
+        // A partial index is only a candidate once the query is known to want none of the rows it
+        // leaves out.
+        let terms = [term(&value, B, BinaryOperator::Eq)];
+        assert_eq!(describe(&plan_of(&schema, &terms, &[])), "SCAN t");
+        let partial = plan(table, &indexes, None, &terms, &[], &[A, B, C], &["t_b"]).unwrap();
+        assert_eq!(describe(&partial), "SEARCH t USING INDEX t_b (b=?)");
+        assert!(!partial.single_row());
+    }
+
+    // How many leading columns of the index a plan has equal, and whether its lower and upper
+    // bounds are inclusive.
+    fn bounds(plan: &Plan) -> (usize, Option<bool>, Option<bool>) {
+        let (equal, lower, upper) = match &plan.access {
+            Access::Index {
+                equal,
+                lower,
+                upper,
+                ..
+            } => (equal.len(), lower, upper),
+            Access::RowidRange { lower, upper } => (0, lower, upper),
+            access => panic!("Expected a range, got: {:?}", access),
+        };
+        (
+            equal,
+            lower.map(|bound| bound.inclusive),
+            upper.map(|bound| bound.inclusive),
+        )
+    }
+
+    #[test]
+    fn range_test() {
+        let schema = schema();
+        let value = parse_expr("?").unwrap();
+        let describe = |plan: &Plan| plan.describe("t", &schema.0);
+
+        let terms = [
+            term(&value, A, BinaryOperator::Gt),
+            term(&value, A, BinaryOperator::LtEq),
+        ];
+        let range = plan_of(&schema, &terms, &[]);
+        assert_eq!(describe(&range), "SEARCH t USING INDEX t_a_b (a>? AND a<?)");
+        assert_eq!(bounds(&range), (0, Some(false), Some(true)));
+        assert_eq!(range.rows, 1048576.0 / 64.0);
+        assert!(range.filter.is_empty());
+
+        // The range is on the column after the equal ones.
+        let terms = [
+            term(&value, B, BinaryOperator::GtEq),
+            term(&value, A, BinaryOperator::Eq),
+        ];
+        let range = plan_of(&schema, &terms, &[]);
+        assert_eq!(describe(&range), "SEARCH t USING INDEX t_a_b (a=? AND b>?)");
+        assert_eq!(bounds(&range), (1, Some(true), None));
+        assert_eq!(range.rows, 10.0 / 4.0);
+
+        // Only the first of two lower bounds narrows the search, the other is left to the filter.
+        let terms = [
+            term(&value, A, BinaryOperator::Gt),
+            term(&value, A, BinaryOperator::GtEq),
+        ];
+        let range = plan_of(&schema, &terms, &[]);
+        assert_eq!(bounds(&range), (0, Some(false), None));
+        assert_eq!(range.filter.len(), 1);
+
+        let terms = [term(&value, ColumnRef::Rowid, BinaryOperator::Lt)];
+        let range = plan_of(&schema, &terms, &[]);
+        assert_eq!(
+            describe(&range),
+            "SEARCH t USING INTEGER PRIMARY KEY (rowid<?)"
+        );
+        assert_eq!(bounds(&range), (0, None, Some(false)));
+        assert_eq!(range.rows, 1048576.0 / 4.0);
+
+        // A range on anything but the column after the equal ones is of no use to the index.
+        let terms = [term(&value, B, BinaryOperator::Lt)];
+        assert_eq!(describe(&plan_of(&schema, &terms, &[])), "SCAN t");
+    }
+
+    #[test]
+    fn order_by_test() {
+        let schema = schema();
+        let value = parse_expr("?").unwrap();
+        let ordered = |terms: &[Term], order_by: &[OrderTerm]| {
+            let plan = plan_of(&schema, terms, order_by);
+            (plan.describe("t", &schema.0), plan.ordered, plan.reverse)
+        };
+        let scan = || "SCAN t".to_string();
+        let search = || "SEARCH t USING INDEX t_a_b (a=?)".to_string();
+        let a_equal = [term(&value, A, BinaryOperator::Eq)];
+
+        // A table is in order of the rowid, either way.
+        let rowid = ColumnRef::Rowid;
+        assert_eq!(ordered(&[], &[order(rowid, false)]), (scan(), true, false));
+        assert_eq!(ordered(&[], &[order(rowid, true)]), (scan(), true, true));
+        assert_eq!(ordered(&[], &[order(B, false)]), (scan(), false, false));
+
+        // Entries with the same a are in order of b and then the rowid.
+        assert_eq!(
+            ordered(&a_equal, &[order(B, false)]),
+            (search(), true, false)
+        );
+        assert_eq!(
+            ordered(&a_equal, &[order(A, false), order(B, true)]),
+            (search(), true, true)
+        );
+        assert_eq!(
+            ordered(&a_equal, &[order(B, true), order(rowid, true)]),
+            (search(), true, true)
+        );
+        assert_eq!(
+            ordered(&a_equal, &[order(B, false), order(rowid, true)]),
+            (search(), false, false)
+        );
+        let mut nocase = order(B, false);
+        nocase.order.collation = Collation::NoCase;
+        assert_eq!(ordered(&a_equal, &[nocase]), (search(), false, false));
+
+        // Ordering a scan of an index costs less than sorting a scan of the table.
+        assert_eq!(
+            ordered(&[], &[order(A, true), order(B, true)]),
+            ("SCAN t USING INDEX t_a_b".to_string(), true, true)
+        );
+
+        // One row is in any order.
+        let c_equal = [term(&value, C, BinaryOperator::Eq)];
+        assert_eq!(
+            ordered(&c_equal, &[order(B, false), order(A, true)]),
+            ("SEARCH t USING INDEX t_c (c=?)".to_string(), true, false)
+        );
+    }
+
+    #[test]
+    fn indexed_test() {
+        let (table, indexes) = &schema();
+        let indexes: Vec<&Index> = indexes.iter().collect();
+        let value = parse_expr("?").unwrap();
+        let terms = [term(&value, A, BinaryOperator::Eq)];
+        let indexed_plan = |indexed: Indexed, terms: &[Term]| {
+            plan(table, &indexes, Some(&indexed), terms, &[], &[A, B, C], &[])
+                .map(|plan| plan.describe("t", table))
+        };
+        let by = |name: &str| Indexed::By(name.to_string());
+
+        // NOT INDEXED leaves the table to be scanned even when an index would do.
+        assert_eq!(indexed_plan(Indexed::Not, &terms).unwrap(), "SCAN t");
+
+        // INDEXED BY takes the index, for a scan of all of it if it has to.
+        assert_eq!(
+            indexed_plan(by("T_A_B"), &terms).unwrap(),
+            "SEARCH t USING INDEX t_a_b (a=?)"
+        );
+        assert_eq!(
+            indexed_plan(by("t_c"), &terms).unwrap(),
+            "SCAN t USING INDEX t_c"
+        );
+
+        // Without the index there is no way to the rows.
+        for (name, expected) in [("t_b", "no query solution"), ("t_d", "no such index: t_d")] {
+            match indexed_plan(by(name), &terms) {
+                Err(DBError::Sql(message)) => assert_eq!(message, expected),
+                result => panic!("Expected {:?}, got: {:?}", expected, result),
+            }
+        }
+    }
+
+    #[test]
+    fn collation_and_affinity_test() {
+        let table = parse_create_table(
+            "CREATE TABLE t(id INTEGER PRIMARY KEY, a INTEGER, b TEXT, c)",
+            2,
+        )
+        .unwrap();
+        let indexes: Vec<Index> = ["a", "b COLLATE NOCASE", "c"]
+            .iter()
+            .enumerate()
+            .map(|(position, column)| {
+                let name = format!("t_{}", position);
+                let sql = format!("CREATE INDEX {} ON t({})", name, column);
+                parse_index(name, &table, 3 + position, Some(sql)).unwrap()
+            })
+            .collect();
+        let indexes: Vec<&Index> = indexes.iter().collect();
+        let value = parse_expr("?").unwrap();
+        // The plan for one equality, and the affinity its key takes on.
+        let search = |column: ColumnRef, value_affinity, collation| {
+            let mut term = term(&value, column, BinaryOperator::Eq);
+            term.constraints[0].value_affinity = value_affinity;
+            term.constraints[0].collation = collation;
+            let plan = plan(&table, &indexes, None, &[term], &[], &[A, B, C], &[]).unwrap();
+            let affinity = match &plan.access {
+                Access::Index { equal, .. } => equal.first().map(|key| key.affinity),
+                _ => None,
+            };
+            (plan.describe("t", &table), affinity)
+        };
+        let scan = || ("SCAN t".to_string(), None);
+        let searched = |index: &str, column: &str, affinity| {
+            (
+                format!("SEARCH t USING INDEX {} ({}=?)", index, column),
+                Some(affinity),
+            )
+        };
+
+        // An index by NOCASE only helps a comparison by NOCASE.
+        assert_eq!(search(B, None, Collation::Binary), scan());
+        assert_eq!(
+            search(B, None, Collation::NoCase),
+            searched("t_1", "b", ColumnType::Text)
+        );
+
+        // A value without affinity is compared as a number with an INTEGER column, so it is looked
+        // up as one. One that is a number already stays as it is.
+        assert_eq!(
+            search(A, None, Collation::Binary),
+            searched("t_0", "a", ColumnType::Numeric)
+        );
+        assert_eq!(
+            search(A, Some(ColumnType::Text), Collation::Binary),
+            searched("t_0", "a", ColumnType::Numeric)
+        );
+        assert_eq!(
+            search(A, Some(ColumnType::Real), Collation::Binary),
+            searched("t_0", "a", ColumnType::Blob)
+        );
+
+        // Comparing a column with a number turns the column into a number, which the order of an
+        // index on the column as it is says nothing about.
+        assert_eq!(
+            search(B, Some(ColumnType::Integer), Collation::NoCase),
+            scan()
+        );
+        assert_eq!(search(C, Some(ColumnType::Text), Collation::Binary), scan());
+        assert_eq!(
+            search(C, None, Collation::Binary),
+            searched("t_2", "c", ColumnType::Blob)
+        );
+    }
+
+    #[test]
+    fn join_order_test() {
+        // Table 0 has ten rows, every other one a thousand, of which the loop inside the table before
+        // it finds one for each row.
+        let chain = |source: usize, outer: &[usize]| match source {
+            0 => Ok((10.0, 10.0)),
+            _ if outer.contains(&(source - 1)) => Ok((1.0, 1.0)),
+            _ => Ok((1000.0, 1000.0)),
+        };
+        assert_eq!(join_order(3, |_| false, chain).unwrap(), [0, 1, 2]);
+
+        // The same, with FROM naming them the other way around.
+        let reversed = |source: usize, outer: &[usize]| {
+            let outer: Vec<usize> = outer.iter().map(|outer| 2 - outer).collect();
+            chain(2 - source, &outer)
+        };
+        assert_eq!(join_order(3, |_| false, reversed).unwrap(), [2, 1, 0]);
+
+        // A fixed table stays where it is, the ones on either side of it are ordered on their own.
+        assert_eq!(
+            join_order(3, |source| source == 1, reversed).unwrap(),
+            [0, 1, 2]
+        );
+        let swapped = |source: usize, outer: &[usize]| {
+            let outer: Vec<usize> = outer.iter().map(|outer| outer ^ 1).collect();
+            chain(source ^ 1, &outer)
+        };
+        assert_eq!(
+            join_order(4, |source| source == 2, swapped).unwrap(),
+            [1, 0, 2, 3]
+        );
+
+        // Tables costing the same stay in the order of FROM.
+        let flat = |_: usize, _: &[usize]| Ok((5.0, 5.0));
+        assert_eq!(join_order(4, |_| false, flat).unwrap(), [0, 1, 2, 3]);
+
+        // Many tables are ordered without trying every order.
+        let mut calls = 0;
+        let order = join_order(
+            12,
+            |_| false,
+            |source, outer| {
+                calls += 1;
+                chain(source, outer)
+            },
+        )
+        .unwrap();
+        assert_eq!(order, (0..12).collect::<Vec<usize>>());
+        assert!(calls <= 12 * 12 * JOIN_PATHS, "{} calls", calls);
+
+        let failing = |_: usize, _: &[usize]| Err(DBError::Sql("no query solution".to_string()));
+        assert!(matches!(
+            join_order(2, |_| false, failing),
+            Err(DBError::Sql(message)) if message == "no query solution"
+        ));
+    }
+}
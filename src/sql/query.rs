@@ -0,0 +1,610 @@
+use crate::page::errors::Result;
+use crate::page::file_structures::Value;
+use crate::page::pager::Pager;
+
+use super::vm::{Program, Vm};
+
+/*
+* Runs SELECT statements: `compiler` turns them into a program and the `vm` runs it, a result row
+* at a time. With EXPLAIN the rows are the instructions of the program instead, with EXPLAIN QUERY
+* PLAN the steps of its plan.
+*/
+
+// The rows of a query, see `Database::query`. Stops after the first error. The query is a read of
+// the pager, which ends when the rows are dropped.
+pub struct Rows<'a> {
+    vm: Vm<'a>,
+    done: bool,
+}
+
+impl<'a> Rows<'a> {
+    // Runs `program`, the pager has to be in a read for it.
+    pub fn new(program: Program, pager: &'a mut Pager) -> Rows<'a> {
+        Rows {
+            vm: Vm::new(program, pager),
+            done: false,
+        }
+    }
+}
+
+impl Rows<'_> {
+    // Names of the result columns: their alias, the name of the column for plain column
+    // references and the SQL text of the expression otherwise.
+    pub fn columns(&self) -> &[String] {
+        &self.vm.program().columns
+    }
+
+    // The program the query runs, which prints like EXPLAIN lists it.
+    pub fn program(&self) -> &Program {
+        self.vm.program()
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let row = self.vm.step();
+        if !matches!(row, Ok(Some(_))) {
+            self.done = true;
+        }
+        row.transpose()
+    }
+}
+
+impl Drop for Rows<'_> {
+    fn drop(&mut self) {
+        let _ = self.vm.pager().end_transaction();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::errors::DBError;
+    use crate::page::file_structures::Value;
+    use crate::page::test_utils::temp_copy;
+    use crate::page::Database;
+
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+    const MULTI_PAGE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/multi_page.db");
+
+    // `items(id INTEGER PRIMARY KEY, name TEXT, data BLOB, score REAL)` with a few rows in it.
+    fn items_database() -> Database {
+        let mut database = Database::open(temp_copy(EMPTY_TABLE_DB)).unwrap();
+        let text = |text: &str| Value::Text(text.to_string());
+        for (name, data, score) in [
+            (text("apple"), Value::Blob(vec![1]), Value::Float(1.5)),
+            (text("Banana"), Value::Null, Value::Float(3.0)),
+            (text("cherry"), Value::Blob(vec![2, 3]), Value::Null),
+            (Value::Null, Value::Null, Value::Float(-2.0)),
+            (text("apple pie"), Value::Blob(vec![]), Value::Float(10.0)),
+            (text("date"), Value::Null, Value::Float(3.0)),
+            (text("Elderberry"), Value::Null, Value::Float(7.25)),
+        ] {
+            database
+                .insert("items", vec![Value::Null, name, data, score])
+                .unwrap();
+        }
+        database
+    }
+
+    // The rows of a query, each value written out like the sqlite3 shell does.
+    fn query(database: &mut Database, sql: &str) -> Vec<String> {
+        database
+            .query(sql)
+            .unwrap()
+            .map(|row| {
+                let row: Vec<String> = row
+                    .unwrap()
+                    .iter()
+                    .map(|value| match value {
+                        Value::Null => String::new(),
+                        Value::Blob(blob) => format!("{:?}", blob),
+                        value => crate::sql::value::to_text(value).unwrap(),
+                    })
+                    .collect();
+                row.join("|")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn select_test() {
+        let mut database = items_database();
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT id, name FROM items WHERE score > 2 AND name IS NOT NULL"
+            ),
+            vec!["2|Banana", "5|apple pie", "6|date", "7|Elderberry"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT id * 2 + 1, upper(name) || '!', \
+                     CASE WHEN score IS NULL THEN 'none' WHEN score < 0 THEN 'neg' ELSE 'pos' END AS sign, \
+                     length(data) \
+                 FROM items WHERE id IN (1, 3, 4) ORDER BY 1 DESC"
+            ),
+            vec!["9||neg|", "7|CHERRY!|none|2", "3|APPLE!|pos|1"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT name, score FROM items \
+                 WHERE name LIKE 'a%' OR name GLOB '[A-Z]*' OR score BETWEEN -2 AND -1"
+            ),
+            vec![
+                "apple|1.5",
+                "Banana|3.0",
+                "|-2.0",
+                "apple pie|10.0",
+                "Elderberry|7.25"
+            ]
+        );
+        // The REAL column turns the text into a number before comparing.
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT typeof(score), score FROM items WHERE score = '3'"
+            ),
+            vec!["real|3.0", "real|3.0"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT 1 + 1, 'a' LIKE 'A', typeof(1.0), 7 / 2, '3' = 3, \
+                     (SELECT name FROM items WHERE score IS NULL)"
+            ),
+            vec!["2|1|real|3|0|cherry"]
+        );
+        // A double quoted name that is not a column is a string.
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT \"nope\", _rowid_ FROM items WHERE id = 4"
+            ),
+            vec!["nope|4"]
+        );
+
+        let rows = database
+            .query("SELECT id AS key, name, score * 2, * FROM items")
+            .unwrap();
+        assert_eq!(
+            rows.columns(),
+            ["key", "name", "score * 2", "id", "name", "data", "score"]
+        );
+    }
+
+    #[test]
+    fn order_distinct_limit_test() {
+        let mut database = items_database();
+        assert_eq!(
+            query(&mut database, "SELECT name FROM items ORDER BY name"),
+            vec![
+                "",
+                "Banana",
+                "Elderberry",
+                "apple",
+                "apple pie",
+                "cherry",
+                "date"
+            ]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT name FROM items ORDER BY name COLLATE NOCASE DESC"
+            ),
+            vec![
+                "Elderberry",
+                "date",
+                "cherry",
+                "Banana",
+                "apple pie",
+                "apple",
+                ""
+            ]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT DISTINCT score FROM items ORDER BY score DESC NULLS FIRST LIMIT 3 OFFSET 1"
+            ),
+            vec!["10.0", "7.25", "3.0"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT DISTINCT score AS s FROM items WHERE s > 0 LIMIT -1 OFFSET 2"
+            ),
+            vec!["10.0", "7.25"]
+        );
+        assert_eq!(
+            query(&mut database, "SELECT id FROM items LIMIT 2, 3"),
+            vec!["3", "4", "5"]
+        );
+    }
+
+    #[test]
+    fn subquery_test() {
+        let mut database = items_database();
+        // Correlated, the inner query sees the row of the outer one.
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT a.id FROM items a \
+                 WHERE EXISTS (SELECT 1 FROM items b WHERE b.score = a.score AND b.id <> a.id)"
+            ),
+            vec!["2", "6"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT name FROM items WHERE id IN (SELECT id + 1 FROM items WHERE score < 2) \
+                 ORDER BY sign(score), name"
+            ),
+            vec!["Banana", "apple pie"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT id, (SELECT name FROM items i WHERE i.id = items.id + 1) FROM items \
+                 WHERE id > 5"
+            ),
+            vec!["6|Elderberry", "7|"]
+        );
+    }
+
+    #[test]
+    fn three_valued_logic_test() {
+        let mut database = items_database();
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT id, score BETWEEN 1 AND 3, score NOT BETWEEN 1 AND 3, name IN ('apple', NULL), \
+                     name NOT IN ('date'), score IN (SELECT score FROM items WHERE id > 5) \
+                 FROM items"
+            ),
+            vec![
+                "1|1|0|1|1|0",
+                "2|1|0||1|1",
+                "3||||1|",
+                "4|0|1|||0",
+                "5|0|1||1|0",
+                "6|1|0||0|1",
+                "7|0|1||1|1"
+            ]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT id FROM items WHERE NOT (score BETWEEN 2 AND 8) OR name IS NULL"
+            ),
+            vec!["1", "4", "5"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT CASE name WHEN 'apple' THEN 1 WHEN NULL THEN 2 ELSE 3 END, name IS 'date', \
+                     score IS NOT NULL \
+                 FROM items WHERE id IN (1, 3, 6)"
+            ),
+            vec!["1|0|1", "3|0|0", "3|1|1"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT NOT NULL, NULL AND 0, NULL OR 1, 1 < NULL, -id, ~id FROM items WHERE id = 1"
+            ),
+            vec!["|0|1||-1|-2"]
+        );
+        // Looking a row up by its rowid converts the key like comparing with it would.
+        assert_eq!(
+            query(&mut database, "SELECT name FROM items WHERE id = '3'"),
+            vec!["cherry"]
+        );
+        assert!(query(&mut database, "SELECT name FROM items WHERE rowid = 3.5").is_empty());
+        assert!(query(
+            &mut database,
+            "SELECT name FROM items WHERE id = 2 AND score > 100"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn explain_test() {
+        let mut database = items_database();
+        let opcodes = |database: &mut Database, sql: &str| -> Vec<String> {
+            let rows = database.query(sql).unwrap();
+            assert_eq!(
+                rows.columns(),
+                ["addr", "opcode", "p1", "p2", "p3", "p4", "comment"]
+            );
+            rows.map(|row| match &row.unwrap()[1] {
+                Value::Text(opcode) => opcode.clone(),
+                value => panic!("Expected an opcode, got: {:?}", value),
+            })
+            .collect()
+        };
+        assert_eq!(
+            opcodes(
+                &mut database,
+                "EXPLAIN SELECT name FROM items WHERE score > 2"
+            ),
+            [
+                "OpenRead",
+                "Rewind",
+                "Column",
+                "RealAffinity",
+                "Integer",
+                "Le",
+                "Column",
+                "ResultRow",
+                "Next",
+                "Halt"
+            ]
+        );
+        // The rowid is looked up rather than scanned for.
+        let lookup = opcodes(&mut database, "EXPLAIN SELECT name FROM items WHERE id = 4");
+        assert!(lookup.contains(&"SeekRowid".to_string()));
+        assert!(!lookup.contains(&"Next".to_string()));
+        // Subqueries that are not correlated only run once.
+        assert!(opcodes(
+            &mut database,
+            "EXPLAIN SELECT (SELECT name FROM items WHERE id = 1) FROM items"
+        )
+        .contains(&"Once".to_string()));
+        assert!(!opcodes(
+            &mut database,
+            "EXPLAIN SELECT (SELECT name FROM items i WHERE i.id = items.id) FROM items"
+        )
+        .contains(&"Once".to_string()));
+
+        let program = database
+            .query("SELECT DISTINCT name FROM items ORDER BY 1 LIMIT 2")
+            .unwrap()
+            .program()
+            .to_string();
+        assert!(program.starts_with("addr  opcode         p1    p2    p3    p4"));
+        assert!(program.contains("SorterOpen     2     1     0     +BINARY"));
+        assert!(program.contains("ResultRow"));
+    }
+
+    #[test]
+    fn multi_page_query_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT rowid, name, length FROM worms WHERE id >= 298 OR id = 150"
+            ),
+            vec![
+                "150|worm-0150|225.0",
+                "298|worm-0298|447.0",
+                "299|worm-0299|448.5",
+                "300|worm-0300|450.0"
+            ]
+        );
+        // LIMIT stops reading the table early.
+        let rows = database.query("SELECT id FROM worms LIMIT 3").unwrap();
+        assert_eq!(rows.count(), 3);
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT id FROM worms ORDER BY length DESC, id LIMIT 2"
+            ),
+            vec!["300", "299"]
+        );
+    }
+
+    // The details of the steps of EXPLAIN QUERY PLAN.
+    fn query_plan(database: &mut Database, sql: &str) -> Vec<String> {
+        database
+            .query(&format!("EXPLAIN QUERY PLAN {}", sql))
+            .unwrap()
+            .map(|row| match &row.unwrap()[3] {
+                Value::Text(detail) => detail.clone(),
+                value => panic!("Expected a detail, got: {:?}", value),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn explain_query_plan_test() {
+        let mut database = Database::open(MULTI_PAGE_DB.to_string()).unwrap();
+        let rows = database
+            .query(
+                "EXPLAIN QUERY PLAN SELECT DISTINCT length, \
+                     (SELECT name FROM worms w WHERE w.id = worms.id + 1) \
+                 FROM worms WHERE name > 'worm-0298' AND length > (SELECT length FROM worms WHERE id = 2) \
+                 ORDER BY 1",
+            )
+            .unwrap();
+        assert_eq!(rows.columns(), ["id", "parent", "notused", "detail"]);
+        assert_eq!(
+            rows.program().query_plan(),
+            "QUERY PLAN\n\
+             |--SEARCH worms USING INDEX worms_name (name>?)\n\
+             |--SCALAR SUBQUERY 1\n\
+             |  `--SEARCH worms USING INTEGER PRIMARY KEY (rowid=?)\n\
+             |--CORRELATED SCALAR SUBQUERY 2\n\
+             |  `--SEARCH w USING INTEGER PRIMARY KEY (rowid=?)\n\
+             |--USE TEMP B-TREE FOR DISTINCT\n\
+             `--USE TEMP B-TREE FOR ORDER BY\n"
+        );
+        let rows: Vec<Vec<Value>> = rows.map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 7);
+        assert_eq!(rows[2][..2], [Value::Integer(3), Value::Integer(2)]);
+
+        assert_eq!(
+            query_plan(&mut database, "SELECT name FROM worms ORDER BY name DESC"),
+            ["SCAN worms USING COVERING INDEX worms_name"]
+        );
+        assert_eq!(
+            query_plan(&mut database, "SELECT 1 WHERE 1 IN (SELECT 1)"),
+            ["SCAN CONSTANT ROW", "LIST SUBQUERY 1", "SCAN CONSTANT ROW"]
+        );
+        assert_eq!(
+            query(
+                &mut database,
+                "SELECT id, length FROM worms WHERE name BETWEEN 'worm-0010' AND 'worm-0012'"
+            ),
+            ["10|15.0", "11|16.5", "12|18.0"]
+        );
+    }
+
+    #[test]
+    fn query_errors_test() {
+        let mut database = items_database();
+        for (sql, message) in [
+            ("SELECT * FROM nope", "no such table: nope"),
+            ("SELECT nope FROM items WHERE 0", "no such column: nope"),
+            (
+                "SELECT items.id FROM items AS i",
+                "no such column: items.id",
+            ),
+            (
+                "SELECT * FROM items WHERE nope(id)",
+                "no such function: nope",
+            ),
+            (
+                "SELECT substr(name) FROM items",
+                "wrong number of arguments to function substr()",
+            ),
+            (
+                "SELECT id FROM items ORDER BY 2",
+                "1st ORDER BY term out of range - should be between 1 and 1",
+            ),
+            (
+                "SELECT id FROM items WHERE count(*) > 1",
+                "misuse of aggregate: count()",
+            ),
+            (
+                "SELECT max(count(*)) FROM items",
+                "misuse of aggregate function count()",
+            ),
+            (
+                "SELECT count(*) FROM items GROUP BY count(*)",
+                "aggregate functions are not allowed in the GROUP BY clause",
+            ),
+            (
+                "SELECT count(*) FROM items GROUP BY 2",
+                "1st GROUP BY term out of range - should be between 1 and 1",
+            ),
+            (
+                "SELECT id FROM items HAVING id > 1",
+                "HAVING clause on a non-aggregate query",
+            ),
+            (
+                "SELECT abs(id) FILTER (WHERE 1) FROM items",
+                "FILTER may not be used with non-aggregate abs()",
+            ),
+            (
+                "SELECT sum(*) FROM items",
+                "wrong number of arguments to function sum()",
+            ),
+            (
+                "SELECT id FROM items WHERE id IN (SELECT id, name FROM items)",
+                "sub-select returns 2 columns - expected 1",
+            ),
+            (
+                "SELECT name COLLATE klingon FROM items",
+                "no such collation sequence: klingon",
+            ),
+            ("SELECT * FROM items LIMIT 'a'", "datatype mismatch"),
+            (
+                "SELECT id FROM items a, items b",
+                "ambiguous column name: id",
+            ),
+            (
+                "SELECT * FROM items a JOIN items b USING (nope)",
+                "cannot join using column nope - column not present in both tables",
+            ),
+            (
+                "SELECT * FROM items a NATURAL JOIN items b ON a.id = b.id",
+                "a NATURAL join may not have an ON or USING clause",
+            ),
+            (
+                "SELECT * FROM items a LEFT JOIN items b ON a.id = c.id JOIN items c",
+                "ON clause references tables to its right",
+            ),
+            ("SELECT * FROM items INDEXED BY nope", "no such index: nope"),
+            (
+                "SELECT id FROM items WHERE sum(id) OVER () > 1",
+                "misuse of window function sum()",
+            ),
+            (
+                "SELECT row_number() FROM items",
+                "misuse of window function row_number()",
+            ),
+            (
+                "SELECT abs(id) OVER () FROM items",
+                "abs() may not be used as a window function",
+            ),
+            (
+                "SELECT count(DISTINCT id) OVER () FROM items",
+                "DISTINCT is not supported for window functions",
+            ),
+            (
+                "SELECT rank() FILTER (WHERE 1) OVER () FROM items",
+                "FILTER clause may only be used with aggregate window functions",
+            ),
+            ("SELECT sum(id) OVER w FROM items", "no such window: w"),
+            (
+                "SELECT sum(id) OVER (w PARTITION BY name) FROM items WINDOW w AS (PARTITION BY id)",
+                "cannot override PARTITION clause of window: w",
+            ),
+            (
+                "SELECT sum(id) OVER (w ORDER BY name) FROM items WINDOW w AS (ORDER BY id)",
+                "cannot override ORDER BY clause of window: w",
+            ),
+            (
+                "SELECT sum(id) OVER (w) FROM items WINDOW w AS (ROWS 1 PRECEDING)",
+                "cannot override frame specification of window: w",
+            ),
+            (
+                "SELECT sum(id) OVER v FROM items WINDOW w AS (ROWS 1 PRECEDING), v AS (w)",
+                "cannot override frame specification of window: w",
+            ),
+            (
+                "SELECT sum(id) OVER (ROWS BETWEEN CURRENT ROW AND 1 PRECEDING) FROM items",
+                "unsupported frame specification",
+            ),
+            (
+                "SELECT sum(id) OVER (RANGE 1 PRECEDING) FROM items",
+                "RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression",
+            ),
+            ("DELETE FROM items", "only SELECT statements can be queried"),
+        ] {
+            match database.query(sql) {
+                Err(DBError::Sql(error)) => assert_eq!(error, message, "{}", sql),
+                Err(err) => panic!("Expected an SQL error for {}, got: {:?}", sql, err),
+                Ok(_) => panic!("Expected an SQL error for {}", sql),
+            }
+        }
+        assert!(matches!(
+            database.query("SELECT FROM items"),
+            Err(DBError::Syntax(..))
+        ));
+
+        // Errors while running come out of the iterator, and end it.
+        let mut rows = database
+            .query("SELECT abs(-9223372036854775807 - id) FROM items")
+            .unwrap();
+        assert!(matches!(rows.next(), Some(Err(DBError::Sql(_)))));
+        assert!(rows.next().is_none());
+        drop(rows);
+        let mut rows = database
+            .query("SELECT sum(9223372036854775807) FROM items")
+            .unwrap();
+        assert!(
+            matches!(rows.next(), Some(Err(DBError::Sql(error))) if error == "integer overflow")
+        );
+    }
+}
@@ -0,0 +1,835 @@
+use std::cmp::Ordering;
+
+use crate::page::file_structures::Value;
+use crate::page::ColumnType;
+
+use super::ast::BinaryOperator;
+
+/*
+* How SQL values behave: how they compare and sort, how they turn into one another and how they
+* do arithmetic. All of it follows SQLite down to the odd corners, see
+* https://www.sqlite.org/datatype3.html.
+*
+* Affinities are ColumnTypes, where Blob, like NULL, means no affinity at all.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collation {
+    Binary,
+    // Folds the 26 ASCII letters only, like SQLite.
+    NoCase,
+    // Ignores trailing spaces.
+    RTrim,
+}
+
+impl Collation {
+    pub fn from_name(name: &str) -> Option<Collation> {
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Some(Collation::Binary),
+            "NOCASE" => Some(Collation::NoCase),
+            "RTRIM" => Some(Collation::RTrim),
+            _ => None,
+        }
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => a
+                .bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(b.bytes().map(|byte| byte.to_ascii_lowercase())),
+            Collation::RTrim => a.trim_end_matches(' ').cmp(b.trim_end_matches(' ')),
+        }
+    }
+}
+
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Integer(_) => "integer",
+        Value::Float(_) => "real",
+        Value::Text(_) => "text",
+        Value::Blob(_) => "blob",
+    }
+}
+
+/*
+* The order of ORDER BY, DISTINCT and the comparison operators once both sides are not NULL.
+*
+* Values of different storage classes sort NULLs first, then numbers, then text and then blobs.
+* Integers and reals compare by their numeric value, text by the collation and blobs byte by byte.
+*/
+pub fn compare(a: &Value, b: &Value, collation: Collation) -> Ordering {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::Integer(a), Value::Float(b)) => compare_integer_to_real(*a, *b),
+        (Value::Float(a), Value::Integer(b)) => compare_integer_to_real(*b, *a).reverse(),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Value::Text(a), Value::Text(b)) => collation.compare(a, b),
+        (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+        _ => storage_class_rank(a).cmp(&storage_class_rank(b)),
+    }
+}
+
+fn storage_class_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Integer(_) | Value::Float(_) => 1,
+        Value::Text(_) => 2,
+        Value::Blob(_) => 3,
+    }
+}
+
+// Not every i64 has an exact f64, so going through either one alone gets large values wrong.
+fn compare_integer_to_real(integer: i64, real: f64) -> Ordering {
+    if real.is_nan() || real >= 9223372036854775808.0 {
+        return Ordering::Less;
+    }
+    if real < -9223372036854775808.0 {
+        return Ordering::Greater;
+    }
+    match integer.cmp(&(real as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&real.fract()).unwrap_or(Ordering::Equal),
+        ordering => ordering,
+    }
+}
+
+/*
+* Compares two operands of `=`, `<` and friends, None when either of them is NULL.
+*
+* Before comparing, SQLite converts the operands according to their affinities: if one side is
+* numeric, text on the other side that looks like a number becomes one. If one side is text and the
+* other has no affinity, the other side becomes text.
+*/
+pub fn compare_operands(
+    left: &Value,
+    left_affinity: Option<ColumnType>,
+    right: &Value,
+    right_affinity: Option<ColumnType>,
+    collation: Collation,
+) -> Option<Ordering> {
+    if matches!(left, Value::Null) || matches!(right, Value::Null) {
+        return None;
+    }
+    let is_numeric = |affinity: Option<ColumnType>| {
+        matches!(
+            affinity,
+            Some(ColumnType::Integer | ColumnType::Real | ColumnType::Numeric)
+        )
+    };
+    let is_none = |affinity: Option<ColumnType>| {
+        matches!(affinity, None | Some(ColumnType::Blob | ColumnType::NULL))
+    };
+
+    let (left, right) = if is_numeric(left_affinity) && !is_numeric(right_affinity) {
+        (
+            left.clone(),
+            apply_affinity(right.clone(), ColumnType::Numeric),
+        )
+    } else if is_numeric(right_affinity) && !is_numeric(left_affinity) {
+        (
+            apply_affinity(left.clone(), ColumnType::Numeric),
+            right.clone(),
+        )
+    } else if left_affinity == Some(ColumnType::Text) && is_none(right_affinity) {
+        (
+            left.clone(),
+            apply_affinity(right.clone(), ColumnType::Text),
+        )
+    } else if right_affinity == Some(ColumnType::Text) && is_none(left_affinity) {
+        (
+            apply_affinity(left.clone(), ColumnType::Text),
+            right.clone(),
+        )
+    } else {
+        (left.clone(), right.clone())
+    };
+    Some(compare(&left, &right, collation))
+}
+
+// Whether a value counts as true in WHERE, CASE and the logical operators, None for NULL.
+pub fn is_true(value: &Value) -> Option<bool> {
+    match value {
+        Value::Null => None,
+        Value::Integer(value) => Some(*value != 0),
+        Value::Float(value) => Some(*value != 0.0),
+        Value::Text(_) | Value::Blob(_) => match to_numeric(value) {
+            Value::Integer(value) => Some(value != 0),
+            Value::Float(value) => Some(value != 0.0),
+            _ => Some(false),
+        },
+    }
+}
+
+pub fn from_bool(value: bool) -> Value {
+    Value::Integer(value as i64)
+}
+
+// The text of a value, None for NULL. Blobs are taken for UTF-8.
+pub fn to_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Float(value) => Some(real_to_text(*value)),
+        Value::Text(value) => Some(value.clone()),
+        Value::Blob(value) => Some(String::from_utf8_lossy(value).to_string()),
+    }
+}
+
+/*
+* Formats a real the way SQLite does, which is printf's `%!.15g`: 15 significant digits, exponent
+* notation for very large and very small values, and always either a `.` or an exponent so that it
+* reads back as a real.
+*/
+pub fn real_to_text(value: f64) -> String {
+    if value.is_infinite() {
+        return if value > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    let formatted = format!("{:.14e}", value);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let digits = digits.trim_end_matches('0');
+    let digits = if digits.is_empty() { "0" } else { digits };
+
+    if !(-4..15).contains(&exponent) {
+        let fraction = if digits.len() > 1 { &digits[1..] } else { "0" };
+        return format!(
+            "{}{}.{}e{}{:02}",
+            sign,
+            &digits[..1],
+            fraction,
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        );
+    }
+
+    let (integer, fraction) = if exponent < 0 {
+        (
+            "0".to_string(),
+            "0".repeat((-exponent - 1) as usize) + digits,
+        )
+    } else {
+        let point = exponent as usize + 1;
+        if digits.len() > point {
+            (digits[..point].to_string(), digits[point..].to_string())
+        } else {
+            (
+                digits.to_string() + &"0".repeat(point - digits.len()),
+                String::new(),
+            )
+        }
+    };
+    let fraction = if fraction.is_empty() { "0" } else { &fraction };
+    format!("{}{}.{}", sign, integer, fraction)
+}
+
+/*
+* Reads the number at the start of some text, the way SQLite does when text ends up in arithmetic.
+* Leading and trailing spaces are fine and anything after the number is ignored, text without a
+* number at its start is 0. Also returns whether the text was nothing but the number.
+*/
+pub fn parse_number(text: &str) -> (Value, bool) {
+    let bytes = text.as_bytes();
+    let is_space = |byte: u8| matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c');
+    let digits_from = |mut position: usize| {
+        while position < bytes.len() && bytes[position].is_ascii_digit() {
+            position += 1;
+        }
+        position
+    };
+
+    let mut position = 0;
+    while position < bytes.len() && is_space(bytes[position]) {
+        position += 1;
+    }
+    let start = position;
+    if position < bytes.len() && matches!(bytes[position], b'+' | b'-') {
+        position += 1;
+    }
+    let integer_start = position;
+    position = digits_from(position);
+    let mut has_digits = position > integer_start;
+    let mut is_real = false;
+    if position < bytes.len() && bytes[position] == b'.' {
+        let fraction_end = digits_from(position + 1);
+        has_digits |= fraction_end > position + 1;
+        is_real = true;
+        position = fraction_end;
+    }
+    if !has_digits {
+        return (Value::Integer(0), false);
+    }
+    if position < bytes.len() && matches!(bytes[position], b'e' | b'E') {
+        let mut exponent = position + 1;
+        if exponent < bytes.len() && matches!(bytes[exponent], b'+' | b'-') {
+            exponent += 1;
+        }
+        let exponent_end = digits_from(exponent);
+        if exponent_end > exponent {
+            is_real = true;
+            position = exponent_end;
+        }
+    }
+    let number = &text[start..position];
+    let mut end = position;
+    while end < bytes.len() && is_space(bytes[end]) {
+        end += 1;
+    }
+    let complete = end == bytes.len();
+
+    let value = match number.parse::<i64>() {
+        Ok(value) if !is_real => Value::Integer(value),
+        _ => Value::Float(number.parse().unwrap_or(0.0)),
+    };
+    (value, complete)
+}
+
+// A value as a number for arithmetic, NULL stays NULL.
+pub fn to_numeric(value: &Value) -> Value {
+    match value {
+        Value::Text(text) => parse_number(text).0,
+        Value::Blob(blob) => parse_number(&String::from_utf8_lossy(blob)).0,
+        value => value.clone(),
+    }
+}
+
+// A value as an integer, the way `CAST(x AS INTEGER)` does it. None for NULL.
+pub fn to_integer(value: &Value) -> Option<i64> {
+    match to_numeric(value) {
+        Value::Integer(value) => Some(value),
+        // Saturates, like SQLite.
+        Value::Float(value) => Some(value as i64),
+        _ => None,
+    }
+}
+
+// A value as a real, the way `CAST(x AS REAL)` does it. None for NULL.
+pub fn to_real(value: &Value) -> Option<f64> {
+    match to_numeric(value) {
+        Value::Integer(value) => Some(value as f64),
+        Value::Float(value) => Some(value),
+        _ => None,
+    }
+}
+
+/*
+* Converts a value the way storing it in a column with the given affinity would. Text only becomes
+* a number when all of it is one, and reals that are whole numbers become integers under NUMERIC and
+* INTEGER affinity.
+*/
+pub fn apply_affinity(value: Value, affinity: ColumnType) -> Value {
+    match (affinity, value) {
+        (ColumnType::Text, value @ (Value::Integer(_) | Value::Float(_))) => {
+            Value::Text(to_text(&value).unwrap())
+        }
+        (ColumnType::Integer | ColumnType::Numeric | ColumnType::Real, Value::Text(text)) => {
+            match parse_number(&text) {
+                (number, true) => apply_affinity(number, affinity),
+                _ => Value::Text(text),
+            }
+        }
+        (ColumnType::Integer | ColumnType::Numeric, Value::Float(real)) => real_to_integer(real),
+        (ColumnType::Real, Value::Integer(integer)) => Value::Float(integer as f64),
+        (_, value) => value,
+    }
+}
+
+// The integer a real is equal to if there is one, otherwise the real.
+fn real_to_integer(real: f64) -> Value {
+    if real.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&real) {
+        Value::Integer(real as i64)
+    } else {
+        Value::Float(real)
+    }
+}
+
+// `CAST(value AS type)`, where the affinity of the type name decides what the value turns into.
+pub fn cast(value: &Value, affinity: ColumnType) -> Value {
+    if matches!(value, Value::Null) {
+        return Value::Null;
+    }
+    match affinity {
+        ColumnType::Text => Value::Text(to_text(value).unwrap()),
+        ColumnType::Integer => Value::Integer(to_integer(value).unwrap()),
+        ColumnType::Real => Value::Float(to_real(value).unwrap()),
+        ColumnType::Numeric => match to_numeric(value) {
+            Value::Float(real) => real_to_integer(real),
+            value => value,
+        },
+        ColumnType::Blob | ColumnType::NULL => match value {
+            Value::Text(text) => Value::Blob(text.clone().into_bytes()),
+            Value::Blob(_) => value.clone(),
+            value => Value::Blob(to_text(value).unwrap().into_bytes()),
+        },
+    }
+}
+
+// `+`, `-`, `*`, `/` and `%`. Integer arithmetic that overflows is done over in reals instead,
+// and dividing by zero is NULL.
+pub fn arithmetic(operator: BinaryOperator, left: &Value, right: &Value) -> Value {
+    if matches!(left, Value::Null) || matches!(right, Value::Null) {
+        return Value::Null;
+    }
+    let (left, right) = (to_numeric(left), to_numeric(right));
+    if let (Value::Integer(left), Value::Integer(right)) = (&left, &right) {
+        let result = match operator {
+            BinaryOperator::Add => left.checked_add(*right),
+            BinaryOperator::Subtract => left.checked_sub(*right),
+            BinaryOperator::Multiply => left.checked_mul(*right),
+            BinaryOperator::Divide if *right == 0 => return Value::Null,
+            BinaryOperator::Divide => left.checked_div(*right),
+            BinaryOperator::Remainder if *right == 0 => return Value::Null,
+            BinaryOperator::Remainder => Some(left.checked_rem(*right).unwrap_or(0)),
+            _ => unreachable!("Not an arithmetic operator: {:?}", operator),
+        };
+        if let Some(result) = result {
+            return Value::Integer(result);
+        }
+    }
+
+    let (Some(left_real), Some(right_real)) = (to_real(&left), to_real(&right)) else {
+        return Value::Null;
+    };
+    let result = match operator {
+        BinaryOperator::Add => left_real + right_real,
+        BinaryOperator::Subtract => left_real - right_real,
+        BinaryOperator::Multiply => left_real * right_real,
+        BinaryOperator::Divide if right_real == 0.0 => return Value::Null,
+        BinaryOperator::Divide => left_real / right_real,
+        // Reals are turned into integers first, yet the result is a real.
+        BinaryOperator::Remainder => {
+            let (left, right) = (to_integer(&left).unwrap(), to_integer(&right).unwrap());
+            match right {
+                0 => return Value::Null,
+                -1 => 0.0,
+                right => (left % right) as f64,
+            }
+        }
+        _ => unreachable!("Not an arithmetic operator: {:?}", operator),
+    };
+    if result.is_nan() {
+        Value::Null
+    } else {
+        Value::Float(result)
+    }
+}
+
+// `&`, `|`, `<<` and `>>` over the values as integers.
+pub fn bitwise(operator: BinaryOperator, left: &Value, right: &Value) -> Value {
+    let (Some(left), Some(right)) = (to_integer(left), to_integer(right)) else {
+        return Value::Null;
+    };
+    Value::Integer(match operator {
+        BinaryOperator::BitAnd => left & right,
+        BinaryOperator::BitOr => left | right,
+        BinaryOperator::ShiftLeft => shift_left(left, right),
+        BinaryOperator::ShiftRight => shift_left(left, right.saturating_neg()),
+        _ => unreachable!("Not a bitwise operator: {:?}", operator),
+    })
+}
+
+// Shifts left by a negative amount shift right, and shifting by 64 or more leaves nothing but the
+// sign.
+fn shift_left(value: i64, amount: i64) -> i64 {
+    match amount {
+        64.. => 0,
+        0..=63 => ((value as u64) << amount) as i64,
+        ..=-64 => {
+            if value < 0 {
+                -1
+            } else {
+                0
+            }
+        }
+        _ => value >> -amount,
+    }
+}
+
+pub fn negate(value: &Value) -> Value {
+    match to_numeric(value) {
+        Value::Integer(value) => match value.checked_neg() {
+            Some(value) => Value::Integer(value),
+            None => Value::Float(-(value as f64)),
+        },
+        Value::Float(value) => Value::Float(-value),
+        value => value,
+    }
+}
+
+pub fn concat(left: &Value, right: &Value) -> Value {
+    match (to_text(left), to_text(right)) {
+        (Some(left), Some(right)) => Value::Text(left + &right),
+        _ => Value::Null,
+    }
+}
+
+/*
+* LIKE: `%` matches any run of characters and `_` any one character, the escape character makes the
+* one after it match itself. Case is ignored for the ASCII letters only.
+*/
+pub fn like(pattern: &str, text: &str, escape: Option<char>) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    like_chars(&pattern, &text, escape)
+}
+
+fn like_chars(pattern: &[char], text: &[char], escape: Option<char>) -> bool {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            c if Some(c) == escape => {
+                let Some(literal) = pattern.get(p + 1) else {
+                    return false;
+                };
+                if t >= text.len() || !literal.eq_ignore_ascii_case(&text[t]) {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            '%' => {
+                while p < pattern.len() && pattern[p] == '%' {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                return (t..=text.len()).any(|t| like_chars(&pattern[p..], &text[t..], escape));
+            }
+            '_' => {
+                if t >= text.len() {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            c => {
+                if t >= text.len() || !c.eq_ignore_ascii_case(&text[t]) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/*
+* GLOB: `*` matches any run of characters, `?` any one character and `[...]` one of a set of
+* characters, with ranges like `a-z` and `^` in front for the characters not in it. Case matters.
+*/
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_chars(&pattern, &text)
+}
+
+fn glob_chars(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            '*' => {
+                while p < pattern.len() && pattern[p] == '*' {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                return (t..=text.len()).any(|t| glob_chars(&pattern[p..], &text[t..]));
+            }
+            '?' => {
+                if t >= text.len() {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            '[' => {
+                let Some(&c) = text.get(t) else {
+                    return false;
+                };
+                let Some((matched, end)) = glob_set(&pattern[p + 1..], c) else {
+                    return false;
+                };
+                if !matched {
+                    return false;
+                }
+                p += end + 1;
+                t += 1;
+            }
+            c => {
+                if text.get(t) != Some(&c) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+// Matches `c` against the set after a `[`, returning whether it matched and the length of the set
+// up to and including the `]`. None when the set is never closed.
+fn glob_set(set: &[char], c: char) -> Option<(bool, usize)> {
+    let mut position = 0;
+    let invert = set.first() == Some(&'^');
+    if invert {
+        position += 1;
+    }
+    let mut matched = false;
+    // A `]` right at the start is a member of the set and not its end.
+    if set.get(position) == Some(&']') {
+        matched |= c == ']';
+        position += 1;
+    }
+    while position < set.len() && set[position] != ']' {
+        if set.get(position + 1) == Some(&'-') && set.get(position + 2).is_some_and(|c| *c != ']') {
+            matched |= (set[position]..=set[position + 2]).contains(&c);
+            position += 3;
+        } else {
+            matched |= set[position] == c;
+            position += 1;
+        }
+    }
+    if position == set.len() {
+        return None;
+    }
+    Some((matched != invert, position + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::page::file_structures::Value;
+    use crate::page::ColumnType;
+    use crate::sql::ast::BinaryOperator;
+    use crate::sql::value::*;
+
+    #[test]
+    fn compare_test() {
+        let values = [
+            Value::Null,
+            Value::Integer(-5),
+            Value::Float(-4.5),
+            Value::Integer(i64::MAX),
+            Value::Float(1e19),
+            Value::Text("B".to_string()),
+            Value::Text("a".to_string()),
+            Value::Blob(vec![0]),
+        ];
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(
+                    compare(a, b, Collation::Binary),
+                    i.cmp(&j),
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+        assert_eq!(
+            compare(&Value::Integer(3), &Value::Float(3.0), Collation::Binary),
+            Ordering::Equal
+        );
+        let (a, b) = (Value::Text("a".to_string()), Value::Text("B ".to_string()));
+        assert_eq!(compare(&a, &b, Collation::NoCase), Ordering::Less);
+        assert_eq!(Collation::RTrim.compare("abc  ", "abc"), Ordering::Equal);
+
+        // A column with INTEGER affinity against text that looks like a number.
+        let text = Value::Text("10".to_string());
+        assert_eq!(
+            compare_operands(
+                &Value::Integer(9),
+                Some(ColumnType::Integer),
+                &text,
+                None,
+                Collation::Binary
+            ),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare_operands(&Value::Integer(9), None, &text, None, Collation::Binary),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare_operands(
+                &Value::Text("9".to_string()),
+                Some(ColumnType::Text),
+                &Value::Integer(10),
+                None,
+                Collation::Binary
+            ),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            compare_operands(&Value::Null, None, &text, None, Collation::Binary),
+            None
+        );
+    }
+
+    #[test]
+    fn conversion_test() {
+        for (value, text) in [
+            (1.0, "1.0"),
+            (0.1, "0.1"),
+            (-2.5, "-2.5"),
+            (1e15, "1.0e+15"),
+            (123456789012345.0, "123456789012345.0"),
+            (1.5e-7, "1.5e-07"),
+            (0.0001, "0.0001"),
+            (1.0 / 3.0, "0.333333333333333"),
+        ] {
+            assert_eq!(real_to_text(value), text);
+        }
+
+        for (text, number, complete) in [
+            ("42", Value::Integer(42), true),
+            ("  -3.5e2 ", Value::Float(-350.0), true),
+            ("12abc", Value::Integer(12), false),
+            (".5", Value::Float(0.5), true),
+            ("abc", Value::Integer(0), false),
+            ("1e", Value::Integer(1), false),
+            (
+                "9223372036854775808",
+                Value::Float(9223372036854775808.0),
+                true,
+            ),
+        ] {
+            assert_eq!(parse_number(text), (number, complete), "{}", text);
+        }
+
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_eq!(
+            apply_affinity(text("3.0"), ColumnType::Numeric),
+            Value::Integer(3)
+        );
+        assert_eq!(
+            apply_affinity(text("3.0x"), ColumnType::Integer),
+            text("3.0x")
+        );
+        assert_eq!(
+            apply_affinity(Value::Integer(3), ColumnType::Text),
+            text("3")
+        );
+        assert_eq!(
+            cast(&text("12.9abc"), ColumnType::Integer),
+            Value::Integer(12)
+        );
+        assert_eq!(
+            cast(&Value::Float(2.0), ColumnType::Numeric),
+            Value::Integer(2)
+        );
+        assert_eq!(
+            cast(&Value::Integer(7), ColumnType::Blob),
+            Value::Blob(b"7".to_vec())
+        );
+        assert_eq!(is_true(&text("0.0")), Some(false));
+        assert_eq!(is_true(&text("1x")), Some(true));
+    }
+
+    #[test]
+    fn arithmetic_test() {
+        use BinaryOperator::*;
+        for (operator, left, right, result) in [
+            (Add, Value::Integer(1), Value::Integer(2), Value::Integer(3)),
+            (
+                Add,
+                Value::Integer(i64::MAX),
+                Value::Integer(1),
+                Value::Float(9223372036854775808.0),
+            ),
+            (
+                Subtract,
+                Value::Text("5".to_string()),
+                Value::Float(0.5),
+                Value::Float(4.5),
+            ),
+            (
+                Divide,
+                Value::Integer(7),
+                Value::Integer(2),
+                Value::Integer(3),
+            ),
+            (Divide, Value::Integer(7), Value::Integer(0), Value::Null),
+            (
+                Divide,
+                Value::Float(7.0),
+                Value::Integer(2),
+                Value::Float(3.5),
+            ),
+            (
+                Remainder,
+                Value::Integer(-7),
+                Value::Integer(3),
+                Value::Integer(-1),
+            ),
+            (
+                Remainder,
+                Value::Float(5.5),
+                Value::Integer(2),
+                Value::Float(1.0),
+            ),
+            (Multiply, Value::Null, Value::Integer(2), Value::Null),
+        ] {
+            assert_eq!(
+                arithmetic(operator, &left, &right),
+                result,
+                "{:?} {:?} {:?}",
+                left,
+                operator,
+                right
+            );
+        }
+        assert_eq!(
+            bitwise(ShiftLeft, &Value::Integer(1), &Value::Integer(62)),
+            Value::Integer(1 << 62)
+        );
+        assert_eq!(
+            bitwise(ShiftRight, &Value::Integer(-8), &Value::Integer(100)),
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            bitwise(ShiftLeft, &Value::Integer(8), &Value::Integer(-2)),
+            Value::Integer(2)
+        );
+        assert_eq!(
+            negate(&Value::Integer(i64::MIN)),
+            Value::Float(9223372036854775808.0)
+        );
+        assert_eq!(
+            concat(&Value::Integer(1), &Value::Float(2.0)),
+            Value::Text("12.0".to_string())
+        );
+    }
+
+    #[test]
+    fn pattern_test() {
+        assert!(like("a%C", "ABBc", None));
+        assert!(like("_b%", "abc", None));
+        assert!(!like("_b%", "b", None));
+        assert!(like("100!%", "100%", Some('!')));
+        assert!(!like("100!%", "1000", Some('!')));
+        assert!(like("%", "", None));
+
+        assert!(glob("a*c", "abbbc"));
+        assert!(!glob("a*c", "ABC"));
+        assert!(glob("?[a-c]*", "xb"));
+        assert!(glob("[^0-9]x", "ax"));
+        assert!(!glob("[^0-9]x", "5x"));
+        assert!(glob("[]]", "]"));
+        assert!(!glob("[ab", "a"));
+    }
+}
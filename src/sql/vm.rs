@@ -0,0 +1,2482 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+use crate::page::cursor::{BTreeCursor, IndexCursor};
+use crate::page::errors::{DBError, Result};
+use crate::page::file_structures::Value;
+use crate::page::pager::Pager;
+use crate::page::ColumnType;
+
+use super::ast::BinaryOperator;
+use super::functions::{Accumulator, AggregateFunction, ScalarFunction};
+use super::value::{self, Collation};
+use super::window::WindowCall;
+
+/*
+* A register based virtual machine running compiled queries, modelled on SQLite's VDBE
+* (https://www.sqlite.org/opcode.html).
+*
+* A program is a list of instructions working on numbered registers, each holding one value, and
+* numbered cursors: B-tree cursors reading tables and indexes, sorters collecting rows for ORDER BY
+* and window functions, ephemeral sets remembering rows for DISTINCT and the groups of GROUP BY.
+* Aggregates add up the rows of a group in numbered accumulators. The program runs until it hands out a result row with
+* ResultRow, it carries on from there when asked for the next one, until it halts.
+*
+* Instructions are named after their SQLite counterparts where there is one, and EXPLAIN lists them
+* with the same columns, so a program reads like the one SQLite runs for the same query.
+*/
+
+pub type Register = usize;
+pub type Address = usize;
+pub type CursorId = usize;
+
+// How a sorter orders rows by one of its keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortOrder {
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub collation: Collation,
+}
+
+#[derive(Debug)]
+pub enum Instruction {
+    Goto {
+        target: Address,
+    },
+    // Jumps to a subroutine, with the address to return to in the register.
+    Gosub {
+        register: Register,
+        target: Address,
+    },
+    Return {
+        register: Register,
+    },
+    Halt,
+    // Does nothing, stands in for an instruction the compiler decided against.
+    Noop,
+    // Opens a cursor on the table B-tree at `root_page`.
+    OpenRead {
+        cursor: CursorId,
+        root_page: usize,
+        table: String,
+    },
+    // Opens a cursor on the index B-tree at `root_page`, whose entries are ordered by `key`.
+    OpenIndex {
+        cursor: CursorId,
+        root_page: usize,
+        index: String,
+        key: Vec<SortOrder>,
+    },
+    // Moves a table or index cursor to the first entry, jumps if there is none.
+    Rewind {
+        cursor: CursorId,
+        if_empty: Address,
+    },
+    Last {
+        cursor: CursorId,
+        if_empty: Address,
+    },
+    // Moves to the next entry, jumps back into the loop if there is one.
+    Next {
+        cursor: CursorId,
+        target: Address,
+    },
+    Prev {
+        cursor: CursorId,
+        target: Address,
+    },
+    /*
+     * Moves to the first entry greater than (Gt) or at least (GtEq) the key, or to the last one less
+     * than (Lt) or at most (LtEq) it, jumps if there is no such entry. Index cursors compare the key
+     * with as many columns of the entries as it has. Table cursors take the rowid in `first`, with
+     * the numeric affinity applied, and `count` is 1.
+     */
+    Seek {
+        operator: BinaryOperator,
+        cursor: CursorId,
+        first: Register,
+        count: usize,
+        if_not_found: Address,
+    },
+    // Compares the entry an index cursor is at with the key the way Seek does, and jumps if the
+    // comparison is true.
+    IdxCompare {
+        operator: BinaryOperator,
+        cursor: CursorId,
+        first: Register,
+        count: usize,
+        target: Address,
+    },
+    // Moves to the row with the rowid in the register, jumps if there is no such row.
+    SeekRowid {
+        cursor: CursorId,
+        rowid: Register,
+        if_not_found: Address,
+    },
+    // A column of the row a table, index or sorter cursor is at. Records shorter than the table
+    // have NULL for the columns they are missing.
+    Column {
+        cursor: CursorId,
+        column: usize,
+        target: Register,
+    },
+    Rowid {
+        cursor: CursorId,
+        target: Register,
+    },
+    // Makes a table or index cursor read NULL for every column and the rowid until it moves, for
+    // the row of NULLs an outer join pairs unmatched rows with. The cursor need not be open.
+    NullRow {
+        cursor: CursorId,
+    },
+    // The rowid of the row the entry of an index cursor is for.
+    IdxRowid {
+        cursor: CursorId,
+        target: Register,
+    },
+    // Applies an affinity to each register from `first` on, BLOB leaving the value alone.
+    Affinity {
+        first: Register,
+        affinities: Vec<ColumnType>,
+    },
+    // Turns an integer into a real, for REAL columns SQLite stored as integers to save space.
+    RealAffinity {
+        register: Register,
+    },
+    Integer {
+        value: i64,
+        target: Register,
+    },
+    Real {
+        value: f64,
+        target: Register,
+    },
+    String {
+        value: String,
+        target: Register,
+    },
+    Blob {
+        value: Vec<u8>,
+        target: Register,
+    },
+    Null {
+        target: Register,
+    },
+    Copy {
+        source: Register,
+        target: Register,
+    },
+    // Arithmetic, bitwise operators and ||.
+    Arithmetic {
+        operator: BinaryOperator,
+        left: Register,
+        right: Register,
+        target: Register,
+    },
+    And {
+        left: Register,
+        right: Register,
+        target: Register,
+    },
+    Or {
+        left: Register,
+        right: Register,
+        target: Register,
+    },
+    Not {
+        source: Register,
+        target: Register,
+    },
+    BitNot {
+        source: Register,
+        target: Register,
+    },
+    Negate {
+        source: Register,
+        target: Register,
+    },
+    /*
+     * Compares two registers with the affinities of the operands and jumps if the comparison is
+     * true. Comparing with NULL is neither true nor false, it jumps if `jump_if_null` is set. With
+     * `null_eq`, for IS and IS NOT, NULL equals NULL and nothing else.
+     */
+    Compare {
+        operator: BinaryOperator,
+        left: Register,
+        right: Register,
+        target: Address,
+        jump_if_null: bool,
+        null_eq: bool,
+        affinities: (Option<ColumnType>, Option<ColumnType>),
+        collation: Collation,
+    },
+    // Makes `target` NULL if either `left` or `right` is, for the value of a comparison.
+    ZeroOrNull {
+        left: Register,
+        right: Register,
+        target: Register,
+    },
+    If {
+        register: Register,
+        target: Address,
+        jump_if_null: bool,
+    },
+    IfNot {
+        register: Register,
+        target: Address,
+        jump_if_null: bool,
+    },
+    IsNull {
+        register: Register,
+        target: Address,
+    },
+    NotNull {
+        register: Register,
+        target: Address,
+    },
+    // Falls through the first time it runs and jumps every time after.
+    Once {
+        target: Address,
+    },
+    Function {
+        function: &'static ScalarFunction,
+        first_argument: Register,
+        argument_count: usize,
+        target: Register,
+    },
+    Cast {
+        register: Register,
+        affinity: ColumnType,
+    },
+    // Fails with "datatype mismatch" unless the register holds an integer, for LIMIT and OFFSET.
+    MustBeInt {
+        register: Register,
+    },
+    // Jumps if the register is positive, after taking `decrement` off it.
+    IfPos {
+        register: Register,
+        target: Address,
+        decrement: i64,
+    },
+    // Takes one off the register and jumps if that makes it zero.
+    DecrJumpZero {
+        register: Register,
+        target: Address,
+    },
+    // Opens a sorter, sorting rows by their first `orders.len()` values.
+    SorterOpen {
+        cursor: CursorId,
+        orders: Vec<SortOrder>,
+    },
+    SorterInsert {
+        cursor: CursorId,
+        first: Register,
+        count: usize,
+    },
+    // Sorts the rows and moves to the first one, jumps if there is none.
+    SorterSort {
+        cursor: CursorId,
+        if_empty: Address,
+    },
+    SorterNext {
+        cursor: CursorId,
+        target: Address,
+    },
+    // Sorts the rows of a sorter by the columns of `keys`, leaving rows whose keys are equal in the
+    // order they are in, for the window functions of a window.
+    WindowSort {
+        cursor: CursorId,
+        keys: Vec<(usize, SortOrder)>,
+    },
+    // Adds a column to every row of a sorter with the value the window function has for it.
+    WindowCall {
+        cursor: CursorId,
+        call: Box<WindowCall>,
+    },
+    // Opens an empty set of rows, in which rows whose values are equal under `collations` are the
+    // same.
+    OpenEphemeral {
+        cursor: CursorId,
+        collations: Vec<Collation>,
+    },
+    // Jumps if the row in `count` registers from `first` is in the set.
+    Found {
+        cursor: CursorId,
+        first: Register,
+        count: usize,
+        target: Address,
+    },
+    IdxInsert {
+        cursor: CursorId,
+        first: Register,
+        count: usize,
+    },
+    // Starts the accumulators from `first` on over, for the next group.
+    AggReset {
+        first: usize,
+        count: usize,
+    },
+    /*
+     * Takes the arguments of a row into an accumulator, leaving out values it has seen already with
+     * `distinct`. With `hit`, sets the register to whether min() or max() has its value from the row
+     * now.
+     */
+    AggStep {
+        function: &'static AggregateFunction,
+        first_argument: Register,
+        argument_count: usize,
+        accumulator: usize,
+        distinct: bool,
+        collation: Collation,
+        hit: Option<Register>,
+    },
+    // The value of an aggregate for the rows its accumulator took in.
+    AggFinal {
+        function: &'static AggregateFunction,
+        accumulator: usize,
+        target: Register,
+    },
+    // Opens an empty set of groups, whose keys are equal under the collations of `orders` and sort
+    // by them, and whose rows go into the `count` accumulators from `first`.
+    GroupOpen {
+        cursor: CursorId,
+        orders: Vec<SortOrder>,
+        first: usize,
+        count: usize,
+    },
+    // Moves the accumulators to the group of the key in `count` registers from `first`, starting the
+    // group if there is none yet.
+    GroupFind {
+        cursor: CursorId,
+        first: Register,
+        count: usize,
+    },
+    // Orders the groups by their keys and moves to the first one, its key to be read with Column and
+    // its accumulators in place. Jumps if there are none.
+    GroupSort {
+        cursor: CursorId,
+        if_empty: Address,
+    },
+    GroupNext {
+        cursor: CursorId,
+        target: Address,
+    },
+    // Hands out the `count` registers from `first` as a result row.
+    ResultRow {
+        first: Register,
+        count: usize,
+    },
+}
+
+// An instruction the way EXPLAIN lists it.
+struct Listing {
+    opcode: &'static str,
+    p1: i64,
+    p2: i64,
+    p3: i64,
+    p4: Option<String>,
+    comment: String,
+}
+
+fn comparison_name(operator: BinaryOperator) -> (&'static str, &'static str) {
+    match operator {
+        BinaryOperator::Eq => ("Eq", "=="),
+        BinaryOperator::NotEq => ("Ne", "!="),
+        BinaryOperator::Lt => ("Lt", "<"),
+        BinaryOperator::LtEq => ("Le", "<="),
+        BinaryOperator::Gt => ("Gt", ">"),
+        _ => ("Ge", ">="),
+    }
+}
+
+fn arithmetic_name(operator: BinaryOperator) -> (&'static str, &'static str) {
+    match operator {
+        BinaryOperator::Add => ("Add", "+"),
+        BinaryOperator::Subtract => ("Subtract", "-"),
+        BinaryOperator::Multiply => ("Multiply", "*"),
+        BinaryOperator::Divide => ("Divide", "/"),
+        BinaryOperator::Remainder => ("Remainder", "%"),
+        BinaryOperator::BitAnd => ("BitAnd", "&"),
+        BinaryOperator::BitOr => ("BitOr", "|"),
+        BinaryOperator::ShiftLeft => ("ShiftLeft", "<<"),
+        BinaryOperator::ShiftRight => ("ShiftRight", ">>"),
+        _ => ("Concat", "||"),
+    }
+}
+
+fn collation_name(collation: Collation) -> &'static str {
+    match collation {
+        Collation::Binary => "BINARY",
+        Collation::NoCase => "NOCASE",
+        Collation::RTrim => "RTRIM",
+    }
+}
+
+fn sort_orders(orders: &[SortOrder]) -> String {
+    orders
+        .iter()
+        .map(|order| {
+            let direction = if order.descending { "-" } else { "+" };
+            format!("{}{}", direction, collation_name(order.collation))
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn registers(first: Register, count: usize) -> String {
+    match count {
+        0 => "r[]".to_string(),
+        1 => format!("r[{}]", first),
+        _ => format!("r[{}..{}]", first, first + count - 1),
+    }
+}
+
+impl Instruction {
+    // Where the instruction may jump to.
+    pub fn target_mut(&mut self) -> Option<&mut Address> {
+        match self {
+            Self::Goto { target }
+            | Self::Gosub { target, .. }
+            | Self::Next { target, .. }
+            | Self::Prev { target, .. }
+            | Self::IdxCompare { target, .. }
+            | Self::Compare { target, .. }
+            | Self::If { target, .. }
+            | Self::IfNot { target, .. }
+            | Self::IsNull { target, .. }
+            | Self::NotNull { target, .. }
+            | Self::Once { target }
+            | Self::IfPos { target, .. }
+            | Self::DecrJumpZero { target, .. }
+            | Self::SorterNext { target, .. }
+            | Self::GroupNext { target, .. }
+            | Self::Found { target, .. } => Some(target),
+            Self::Rewind { if_empty, .. }
+            | Self::Last { if_empty, .. }
+            | Self::SorterSort { if_empty, .. }
+            | Self::GroupSort { if_empty, .. } => Some(if_empty),
+            Self::SeekRowid { if_not_found, .. } | Self::Seek { if_not_found, .. } => {
+                Some(if_not_found)
+            }
+            _ => None,
+        }
+    }
+
+    fn listing(&self) -> Listing {
+        let listing =
+            |opcode, [p1, p2, p3]: [usize; 3], p4: Option<String>, comment: String| Listing {
+                opcode,
+                p1: p1 as i64,
+                p2: p2 as i64,
+                p3: p3 as i64,
+                p4,
+                comment,
+            };
+        match self {
+            Self::Goto { target } => listing("Goto", [0, *target, 0], None, String::new()),
+            Self::Gosub { register, target } => {
+                listing("Gosub", [*register, *target, 0], None, String::new())
+            }
+            Self::Return { register } => listing("Return", [*register, 0, 0], None, String::new()),
+            Self::Halt => listing("Halt", [0; 3], None, String::new()),
+            Self::Noop => listing("Noop", [0; 3], None, String::new()),
+            Self::OpenRead {
+                cursor,
+                root_page,
+                table,
+            } => listing(
+                "OpenRead",
+                [*cursor, *root_page, 0],
+                None,
+                format!("root={}; {}", root_page, table),
+            ),
+            Self::OpenIndex {
+                cursor,
+                root_page,
+                index,
+                key,
+            } => listing(
+                "OpenRead",
+                [*cursor, *root_page, 0],
+                Some(format!("k({},{})", key.len(), sort_orders(key))),
+                format!("root={}; {}", root_page, index),
+            ),
+            Self::Rewind { cursor, if_empty } => {
+                listing("Rewind", [*cursor, *if_empty, 0], None, String::new())
+            }
+            Self::Last { cursor, if_empty } => {
+                listing("Last", [*cursor, *if_empty, 0], None, String::new())
+            }
+            Self::Next { cursor, target } => {
+                listing("Next", [*cursor, *target, 0], None, String::new())
+            }
+            Self::Prev { cursor, target } => {
+                listing("Prev", [*cursor, *target, 0], None, String::new())
+            }
+            Self::Seek {
+                operator,
+                cursor,
+                first,
+                count,
+                if_not_found,
+            } => listing(
+                match operator {
+                    BinaryOperator::Gt => "SeekGT",
+                    BinaryOperator::GtEq => "SeekGE",
+                    BinaryOperator::Lt => "SeekLT",
+                    _ => "SeekLE",
+                },
+                [*cursor, *if_not_found, *first],
+                Some(count.to_string()),
+                format!("key={}", registers(*first, *count)),
+            ),
+            Self::IdxCompare {
+                operator,
+                cursor,
+                first,
+                count,
+                target,
+            } => listing(
+                match operator {
+                    BinaryOperator::Gt => "IdxGT",
+                    BinaryOperator::GtEq => "IdxGE",
+                    BinaryOperator::Lt => "IdxLT",
+                    _ => "IdxLE",
+                },
+                [*cursor, *target, *first],
+                Some(count.to_string()),
+                format!("key={}", registers(*first, *count)),
+            ),
+            Self::SeekRowid {
+                cursor,
+                rowid,
+                if_not_found,
+            } => listing(
+                "SeekRowid",
+                [*cursor, *if_not_found, *rowid],
+                None,
+                format!("intkey=r[{}]", rowid),
+            ),
+            Self::Column {
+                cursor,
+                column,
+                target,
+            } => listing(
+                "Column",
+                [*cursor, *column, *target],
+                None,
+                format!("r[{}]=cursor {} column {}", target, cursor, column),
+            ),
+            Self::Rowid { cursor, target } => listing(
+                "Rowid",
+                [*cursor, *target, 0],
+                None,
+                format!("r[{}]=rowid", target),
+            ),
+            Self::IdxRowid { cursor, target } => listing(
+                "IdxRowid",
+                [*cursor, *target, 0],
+                None,
+                format!("r[{}]=rowid", target),
+            ),
+            Self::NullRow { cursor } => listing("NullRow", [*cursor, 0, 0], None, String::new()),
+            Self::Affinity { first, affinities } => listing(
+                "Affinity",
+                [*first, affinities.len(), 0],
+                // SQLite's letters for the affinities.
+                Some(
+                    affinities
+                        .iter()
+                        .map(|affinity| match affinity {
+                            ColumnType::Text => 'B',
+                            ColumnType::Numeric => 'C',
+                            ColumnType::Integer => 'D',
+                            ColumnType::Real => 'E',
+                            _ => 'A',
+                        })
+                        .collect(),
+                ),
+                format!("affinity({})", registers(*first, affinities.len())),
+            ),
+            Self::RealAffinity { register } => {
+                listing("RealAffinity", [*register, 0, 0], None, String::new())
+            }
+            Self::Integer { value, target } => Listing {
+                opcode: "Integer",
+                p1: *value,
+                p2: *target as i64,
+                p3: 0,
+                p4: None,
+                comment: format!("r[{}]={}", target, value),
+            },
+            Self::Real { value, target } => listing(
+                "Real",
+                [0, *target, 0],
+                Some(value::real_to_text(*value)),
+                format!("r[{}]={}", target, value::real_to_text(*value)),
+            ),
+            Self::String { value, target } => listing(
+                "String8",
+                [0, *target, 0],
+                Some(value.clone()),
+                format!("r[{}]='{}'", target, value),
+            ),
+            Self::Blob { value, target } => listing(
+                "Blob",
+                [value.len(), *target, 0],
+                Some(value.iter().map(|byte| format!("{:02X}", byte)).collect()),
+                format!("r[{}]=blob", target),
+            ),
+            Self::Null { target } => {
+                listing("Null", [0, *target, 0], None, format!("r[{}]=NULL", target))
+            }
+            Self::Copy { source, target } => listing(
+                "Copy",
+                [*source, *target, 0],
+                None,
+                format!("r[{}]=r[{}]", target, source),
+            ),
+            Self::Arithmetic {
+                operator,
+                left,
+                right,
+                target,
+            } => {
+                let (opcode, symbol) = arithmetic_name(*operator);
+                listing(
+                    opcode,
+                    [*left, *right, *target],
+                    None,
+                    format!("r[{}]=r[{}]{}r[{}]", target, left, symbol, right),
+                )
+            }
+            Self::And {
+                left,
+                right,
+                target,
+            } => listing(
+                "And",
+                [*left, *right, *target],
+                None,
+                format!("r[{}]=(r[{}] && r[{}])", target, left, right),
+            ),
+            Self::Or {
+                left,
+                right,
+                target,
+            } => listing(
+                "Or",
+                [*left, *right, *target],
+                None,
+                format!("r[{}]=(r[{}] || r[{}])", target, left, right),
+            ),
+            Self::Not { source, target } => listing(
+                "Not",
+                [*source, *target, 0],
+                None,
+                format!("r[{}]=!r[{}]", target, source),
+            ),
+            Self::BitNot { source, target } => listing(
+                "BitNot",
+                [*source, *target, 0],
+                None,
+                format!("r[{}]=~r[{}]", target, source),
+            ),
+            Self::Negate { source, target } => listing(
+                "Negate",
+                [*source, *target, 0],
+                None,
+                format!("r[{}]=-r[{}]", target, source),
+            ),
+            Self::Compare {
+                operator,
+                left,
+                right,
+                target,
+                jump_if_null,
+                null_eq,
+                collation,
+                ..
+            } => {
+                let (opcode, symbol) = comparison_name(*operator);
+                let null = match (null_eq, jump_if_null) {
+                    (true, _) => " (NULL is a value)",
+                    (false, true) => " or NULL",
+                    (false, false) => "",
+                };
+                listing(
+                    opcode,
+                    [*left, *target, *right],
+                    Some(collation_name(*collation).to_string()),
+                    format!(
+                        "if r[{}]{}r[{}]{} goto {}",
+                        left, symbol, right, null, target
+                    ),
+                )
+            }
+            Self::ZeroOrNull {
+                left,
+                right,
+                target,
+            } => listing(
+                "ZeroOrNull",
+                [*left, *target, *right],
+                None,
+                format!("r[{}]=NULL if r[{}] or r[{}] is", target, left, right),
+            ),
+            Self::If {
+                register,
+                target,
+                jump_if_null,
+            } => listing(
+                "If",
+                [*register, *target, *jump_if_null as usize],
+                None,
+                String::new(),
+            ),
+            Self::IfNot {
+                register,
+                target,
+                jump_if_null,
+            } => listing(
+                "IfNot",
+                [*register, *target, *jump_if_null as usize],
+                None,
+                String::new(),
+            ),
+            Self::IsNull { register, target } => listing(
+                "IsNull",
+                [*register, *target, 0],
+                None,
+                format!("if r[{}]==NULL goto {}", register, target),
+            ),
+            Self::NotNull { register, target } => listing(
+                "NotNull",
+                [*register, *target, 0],
+                None,
+                format!("if r[{}]!=NULL goto {}", register, target),
+            ),
+            Self::Once { target } => listing("Once", [0, *target, 0], None, String::new()),
+            Self::Function {
+                function,
+                first_argument,
+                argument_count,
+                target,
+            } => listing(
+                "Function",
+                [0, *first_argument, *target],
+                Some(format!("{}({})", function.name, argument_count)),
+                format!(
+                    "r[{}]=func({})",
+                    target,
+                    registers(*first_argument, *argument_count)
+                ),
+            ),
+            Self::Cast { register, affinity } => listing(
+                "Cast",
+                [*register, 0, 0],
+                Some(format!("{:?}", affinity).to_uppercase()),
+                String::new(),
+            ),
+            Self::MustBeInt { register } => {
+                listing("MustBeInt", [*register, 0, 0], None, String::new())
+            }
+            Self::IfPos {
+                register,
+                target,
+                decrement,
+            } => listing(
+                "IfPos",
+                [*register, *target, *decrement as usize],
+                None,
+                format!(
+                    "if r[{}]>0 then r[{}]-={}, goto {}",
+                    register, register, decrement, target
+                ),
+            ),
+            Self::DecrJumpZero { register, target } => listing(
+                "DecrJumpZero",
+                [*register, *target, 0],
+                None,
+                format!("if (--r[{}])==0 goto {}", register, target),
+            ),
+            Self::SorterOpen { cursor, orders } => listing(
+                "SorterOpen",
+                [*cursor, orders.len(), 0],
+                Some(sort_orders(orders)),
+                String::new(),
+            ),
+            Self::SorterInsert {
+                cursor,
+                first,
+                count,
+            } => listing(
+                "SorterInsert",
+                [*cursor, *first, *count],
+                None,
+                format!("key={}", registers(*first, *count)),
+            ),
+            Self::SorterSort { cursor, if_empty } => {
+                listing("SorterSort", [*cursor, *if_empty, 0], None, String::new())
+            }
+            Self::SorterNext { cursor, target } => {
+                listing("SorterNext", [*cursor, *target, 0], None, String::new())
+            }
+            Self::WindowSort { cursor, keys } => listing(
+                "WindowSort",
+                [*cursor, keys.len(), 0],
+                Some(sort_orders(
+                    &keys.iter().map(|(_, order)| *order).collect::<Vec<_>>(),
+                )),
+                format!(
+                    "key=col[{}]",
+                    keys.iter()
+                        .map(|(column, _)| column.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+            ),
+            Self::WindowCall { cursor, call } => listing(
+                "WindowCall",
+                [*cursor, call.arguments.start, call.arguments.len()],
+                Some(format!(
+                    "{}({})",
+                    call.function.name(),
+                    call.arguments.len()
+                )),
+                String::new(),
+            ),
+            Self::OpenEphemeral { cursor, collations } => listing(
+                "OpenEphemeral",
+                [*cursor, collations.len(), 0],
+                Some(
+                    collations
+                        .iter()
+                        .map(|collation| collation_name(*collation))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ),
+                String::new(),
+            ),
+            Self::Found {
+                cursor,
+                first,
+                count,
+                target,
+            } => listing(
+                "Found",
+                [*cursor, *target, *first],
+                Some(count.to_string()),
+                format!("key={}", registers(*first, *count)),
+            ),
+            Self::IdxInsert {
+                cursor,
+                first,
+                count,
+            } => listing(
+                "IdxInsert",
+                [*cursor, *first, *count],
+                None,
+                format!("key={}", registers(*first, *count)),
+            ),
+            Self::AggReset { first, count } => listing(
+                "AggReset",
+                [*first, *count, 0],
+                None,
+                format!("accum[{}..{}]", first, first + count),
+            ),
+            Self::AggStep {
+                function,
+                first_argument,
+                argument_count,
+                accumulator,
+                distinct,
+                ..
+            } => listing(
+                "AggStep",
+                [*distinct as usize, *first_argument, *accumulator],
+                Some(format!("{}({})", function.name, argument_count)),
+                format!(
+                    "accum[{}] step({})",
+                    accumulator,
+                    registers(*first_argument, *argument_count)
+                ),
+            ),
+            Self::AggFinal {
+                function,
+                accumulator,
+                target,
+            } => listing(
+                "AggFinal",
+                [*accumulator, *target, 0],
+                Some(function.name.to_string()),
+                format!("r[{}]=accum[{}]", target, accumulator),
+            ),
+            Self::GroupOpen {
+                cursor,
+                orders,
+                first,
+                count,
+            } => listing(
+                "GroupOpen",
+                [*cursor, *first, *count],
+                Some(sort_orders(orders)),
+                String::new(),
+            ),
+            Self::GroupFind {
+                cursor,
+                first,
+                count,
+            } => listing(
+                "GroupFind",
+                [*cursor, *first, *count],
+                None,
+                format!("key={}", registers(*first, *count)),
+            ),
+            Self::GroupSort { cursor, if_empty } => {
+                listing("GroupSort", [*cursor, *if_empty, 0], None, String::new())
+            }
+            Self::GroupNext { cursor, target } => {
+                listing("GroupNext", [*cursor, *target, 0], None, String::new())
+            }
+            Self::ResultRow { first, count } => listing(
+                "ResultRow",
+                [*first, *count, 0],
+                None,
+                format!("output={}", registers(*first, *count)),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Explain {
+    // EXPLAIN lists the instructions of the program.
+    Program,
+    // EXPLAIN QUERY PLAN lists the steps of its plan.
+    QueryPlan,
+}
+
+// A line of EXPLAIN QUERY PLAN. The steps of a part of the query, like a subquery, have the line
+// introducing that part as their parent, the others have 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+    pub id: usize,
+    pub parent: usize,
+    pub detail: String,
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    // Names of the result columns.
+    pub columns: Vec<String>,
+    pub register_count: usize,
+    pub cursor_count: usize,
+    pub accumulator_count: usize,
+    // Running the program lists its instructions or its plan instead of running them.
+    pub explain: Option<Explain>,
+    // How the program goes about the query, the way EXPLAIN QUERY PLAN describes it.
+    pub plan: Vec<PlanStep>,
+}
+
+// The columns EXPLAIN lists a program with.
+pub const EXPLAIN_COLUMNS: [&str; 7] = ["addr", "opcode", "p1", "p2", "p3", "p4", "comment"];
+
+// The columns of EXPLAIN QUERY PLAN.
+pub const QUERY_PLAN_COLUMNS: [&str; 4] = ["id", "parent", "notused", "detail"];
+
+impl Program {
+    // The plan drawn as a tree, like the sqlite3 shell shows EXPLAIN QUERY PLAN.
+    pub fn query_plan(&self) -> String {
+        let mut tree = "QUERY PLAN\n".to_string();
+        self.draw_plan(0, "", &mut tree);
+        tree
+    }
+
+    fn draw_plan(&self, parent: usize, prefix: &str, tree: &mut String) {
+        let children: Vec<&PlanStep> = self
+            .plan
+            .iter()
+            .filter(|step| step.parent == parent)
+            .collect();
+        for (index, step) in children.iter().enumerate() {
+            let last = index + 1 == children.len();
+            let branch = if last { "`--" } else { "|--" };
+            tree.push_str(&format!("{}{}{}\n", prefix, branch, step.detail));
+            let indent = if last { "   " } else { "|  " };
+            self.draw_plan(step.id, &format!("{}{}", prefix, indent), tree);
+        }
+    }
+
+    fn plan_row(step: &PlanStep) -> Vec<Value> {
+        vec![
+            Value::Integer(step.id as i64),
+            Value::Integer(step.parent as i64),
+            Value::Integer(0),
+            Value::Text(step.detail.clone()),
+        ]
+    }
+
+    // The row EXPLAIN lists an instruction as.
+    fn explain_row(&self, address: Address) -> Vec<Value> {
+        let listing = self.instructions[address].listing();
+        vec![
+            Value::Integer(address as i64),
+            Value::Text(listing.opcode.to_string()),
+            Value::Integer(listing.p1),
+            Value::Integer(listing.p2),
+            Value::Integer(listing.p3),
+            listing.p4.map_or(Value::Null, Value::Text),
+            Value::Text(listing.comment),
+        ]
+    }
+}
+
+// Lists the program like the sqlite3 shell lists EXPLAIN.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "addr  opcode         p1    p2    p3    p4             comment"
+        )?;
+        writeln!(
+            f,
+            "----  -------------  ----  ----  ----  -------------  -------------"
+        )?;
+        for (address, instruction) in self.instructions.iter().enumerate() {
+            let listing = instruction.listing();
+            let line = format!(
+                "{:<4}  {:<13}  {:<4}  {:<4}  {:<4}  {:<13}  {}",
+                address,
+                listing.opcode,
+                listing.p1,
+                listing.p2,
+                listing.p3,
+                listing.p4.unwrap_or_default(),
+                listing.comment
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+// A row of values, ordered value by value the way ORDER BY orders them.
+#[derive(Debug)]
+struct Record(Vec<Value>);
+
+impl Ord for Record {
+    fn cmp(&self, other: &Record) -> Ordering {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| value::compare(a, b, Collation::Binary))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or_else(|| self.0.len().cmp(&other.0.len()))
+    }
+}
+
+impl PartialOrd for Record {
+    fn partial_cmp(&self, other: &Record) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Record) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Record {}
+
+// Consistent with equality: integers hash as the real they equal, if any real equals them.
+impl Hash for Record {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for value in self.0.iter() {
+            match value {
+                Value::Null => 0.hash(state),
+                Value::Integer(integer) => (1, (*integer as f64).to_bits()).hash(state),
+                // 0.0 == -0.0
+                Value::Float(real) => (1, (real + 0.0).to_bits()).hash(state),
+                Value::Text(text) => (2, text).hash(state),
+                Value::Blob(blob) => (3, blob).hash(state),
+            }
+        }
+    }
+}
+
+struct Sorter {
+    orders: Vec<SortOrder>,
+    rows: Vec<Vec<Value>>,
+    position: usize,
+}
+
+// An aggregate of the group at hand, and the arguments its DISTINCT has seen in the group.
+#[derive(Default)]
+struct Aggregation {
+    accumulator: Accumulator,
+    seen: BTreeSet<Record>,
+}
+
+// How many groups GROUP BY keeps in a hash table before they move to an ordered map.
+const GROUP_LIMIT: usize = 512;
+
+struct Group {
+    // The values of the key in the first row of the group.
+    key: Vec<Value>,
+    aggregations: Vec<Aggregation>,
+}
+
+/*
+* The groups of GROUP BY, found by their key. They are in a hash table while there are few of them,
+* and in an ordered map once there are more than GROUP_LIMIT, which for ascending keys has them in
+* order already so that sorting them at the end has little to do. Either way they all stay in
+* memory, unlike SQLite nothing goes to a temp file however many groups there are. The group the
+* rows are going into is out of the table, its accumulators are in the Vm.
+*/
+struct Groups {
+    orders: Vec<SortOrder>,
+    collations: Vec<Collation>,
+    accumulators: Range<usize>,
+    hashed: HashMap<Record, Group>,
+    ordered: Option<BTreeMap<Record, Group>>,
+    current: Option<(Record, Vec<Value>)>,
+    // The groups in order once they are sorted, and the one the cursor is on.
+    sorted: Vec<Group>,
+    position: usize,
+}
+
+impl Groups {
+    fn find(&mut self, key: &[Value], aggregations: &mut [Aggregation]) {
+        let record = folded_record(key, &self.collations);
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|(current, _)| *current == record)
+        {
+            return;
+        }
+        self.put_back(aggregations);
+        let group = match &mut self.ordered {
+            Some(ordered) => ordered.remove(&record),
+            None => self.hashed.remove(&record),
+        };
+        let accumulators = &mut aggregations[self.accumulators.clone()];
+        match group {
+            Some(group) => {
+                for (slot, aggregation) in accumulators.iter_mut().zip(group.aggregations) {
+                    *slot = aggregation;
+                }
+                self.current = Some((record, group.key));
+            }
+            None => {
+                accumulators.fill_with(Aggregation::default);
+                self.current = Some((record, key.to_vec()));
+            }
+        }
+    }
+
+    // Puts the group the rows were going into back in the table, with its accumulators.
+    fn put_back(&mut self, aggregations: &mut [Aggregation]) {
+        let Some((record, key)) = self.current.take() else {
+            return;
+        };
+        let group = Group {
+            key,
+            aggregations: aggregations[self.accumulators.clone()]
+                .iter_mut()
+                .map(std::mem::take)
+                .collect(),
+        };
+        match &mut self.ordered {
+            Some(ordered) => {
+                ordered.insert(record, group);
+            }
+            None => {
+                self.hashed.insert(record, group);
+                if self.hashed.len() > GROUP_LIMIT {
+                    self.ordered = Some(self.hashed.drain().collect());
+                }
+            }
+        }
+    }
+
+    fn sort(&mut self, aggregations: &mut [Aggregation]) {
+        self.put_back(aggregations);
+        let mut groups: Vec<(Record, Group)> = match self.ordered.take() {
+            Some(ordered) => ordered.into_iter().collect(),
+            None => self.hashed.drain().collect(),
+        };
+        groups.sort_by(|a, b| compare_sort_keys(&self.orders, &a.0 .0, &b.0 .0));
+        self.sorted = groups.into_iter().map(|(_, group)| group).collect();
+        self.position = 0;
+    }
+
+    // Moves the accumulators of the group the cursor is on into place, returns whether there is one.
+    fn load(&mut self, aggregations: &mut [Aggregation]) -> bool {
+        match self.sorted.get_mut(self.position) {
+            Some(group) => {
+                let accumulators = &mut aggregations[self.accumulators.clone()];
+                for (slot, aggregation) in accumulators.iter_mut().zip(group.aggregations.drain(..))
+                {
+                    *slot = aggregation;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+enum Cursor {
+    Table(BTreeCursor),
+    // An index cursor and how the entries of the index are ordered.
+    Index(IndexCursor, Vec<SortOrder>),
+    Sorter(Sorter),
+    // A set of rows and the collations its values are equal under.
+    Ephemeral(BTreeSet<Record>, Vec<Collation>),
+    Groups(Groups),
+}
+
+pub fn compare_sort_keys(orders: &[SortOrder], a: &[Value], b: &[Value]) -> Ordering {
+    orders
+        .iter()
+        .zip(a.iter().zip(b.iter()))
+        .map(|(order, (a, b))| compare_sort_key(order, a, b))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+fn compare_sort_key(order: &SortOrder, a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) if order.nulls_first => Ordering::Less,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) if order.nulls_first => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        (a, b) if order.descending => value::compare(a, b, order.collation).reverse(),
+        (a, b) => value::compare(a, b, order.collation),
+    }
+}
+
+fn corrupt_program(address: Address, what: &str) -> DBError {
+    DBError::Sql(format!("instruction {}: {}", address, what))
+}
+
+pub struct Vm<'a> {
+    program: Program,
+    pager: &'a mut Pager,
+    pc: Address,
+    registers: Vec<Value>,
+    cursors: Vec<Option<Cursor>>,
+    // Which cursors are on a row of NULLs, by NullRow.
+    null_rows: Vec<bool>,
+    // Which Once instructions have run, by address.
+    once: Vec<bool>,
+    aggregations: Vec<Aggregation>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: Program, pager: &'a mut Pager) -> Vm<'a> {
+        Vm {
+            registers: vec![Value::Null; program.register_count],
+            cursors: (0..program.cursor_count).map(|_| None).collect(),
+            null_rows: vec![false; program.cursor_count],
+            once: vec![false; program.instructions.len()],
+            aggregations: (0..program.accumulator_count)
+                .map(|_| Aggregation::default())
+                .collect(),
+            program,
+            pager,
+            pc: 0,
+        }
+    }
+
+    pub fn pager(&mut self) -> &mut Pager {
+        self.pager
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    // Runs the program up to the next result row, None once it halts.
+    pub fn step(&mut self) -> Result<Option<Vec<Value>>> {
+        match self.program.explain {
+            Some(Explain::Program) => {
+                if self.pc >= self.program.instructions.len() {
+                    return Ok(None);
+                }
+                self.pc += 1;
+                return Ok(Some(self.program.explain_row(self.pc - 1)));
+            }
+            Some(Explain::QueryPlan) => {
+                let step = self.program.plan.get(self.pc);
+                self.pc += 1;
+                return Ok(step.map(Program::plan_row));
+            }
+            None => {}
+        }
+
+        let registers = &mut self.registers;
+        let cursors = &mut self.cursors;
+        let null_rows = &mut self.null_rows;
+        let aggregations = &mut self.aggregations;
+        while let Some(instruction) = self.program.instructions.get(self.pc) {
+            let address = self.pc;
+            self.pc += 1;
+            let mut jump = |target: Address, condition: bool| {
+                if condition {
+                    self.pc = target;
+                }
+            };
+            match instruction {
+                Instruction::Goto { target } => jump(*target, true),
+                Instruction::Gosub { register, target } => {
+                    registers[*register] = Value::Integer(address as i64 + 1);
+                    jump(*target, true);
+                }
+                Instruction::Return { register } => match registers[*register] {
+                    Value::Integer(address) => jump(address as Address, true),
+                    _ => return Err(corrupt_program(address, "no address to return to")),
+                },
+                Instruction::Halt => break,
+                Instruction::Noop => {}
+                Instruction::OpenRead {
+                    cursor, root_page, ..
+                } => cursors[*cursor] = Some(Cursor::Table(BTreeCursor::new(*root_page))),
+                Instruction::OpenIndex {
+                    cursor,
+                    root_page,
+                    key,
+                    ..
+                } => {
+                    cursors[*cursor] =
+                        Some(Cursor::Index(IndexCursor::new(*root_page), key.clone()))
+                }
+                Instruction::Rewind { cursor, if_empty } => {
+                    null_rows[*cursor] = false;
+                    let more = move_cursor(cursors, *cursor, address, self.pager, Move::First)?;
+                    jump(*if_empty, !more);
+                }
+                Instruction::Last { cursor, if_empty } => {
+                    null_rows[*cursor] = false;
+                    let more = move_cursor(cursors, *cursor, address, self.pager, Move::Last)?;
+                    jump(*if_empty, !more);
+                }
+                Instruction::Next { cursor, target } => {
+                    null_rows[*cursor] = false;
+                    let more = move_cursor(cursors, *cursor, address, self.pager, Move::Next)?;
+                    jump(*target, more);
+                }
+                Instruction::Prev { cursor, target } => {
+                    null_rows[*cursor] = false;
+                    let more = move_cursor(cursors, *cursor, address, self.pager, Move::Prev)?;
+                    jump(*target, more);
+                }
+                Instruction::Seek {
+                    operator,
+                    cursor,
+                    first,
+                    count,
+                    if_not_found,
+                } => {
+                    null_rows[*cursor] = false;
+                    let key = &registers[*first..*first + *count];
+                    let found = match cursors.get_mut(*cursor) {
+                        Some(Some(Cursor::Table(cursor))) => {
+                            seek_rowid(cursor, self.pager, *operator, &key[0])?
+                        }
+                        Some(Some(Cursor::Index(cursor, orders))) => {
+                            let compare = |record: &[Value]| compare_sort_keys(orders, record, key);
+                            match operator {
+                                BinaryOperator::GtEq => {
+                                    cursor.seek(self.pager, |record| compare(record).is_lt())?
+                                }
+                                BinaryOperator::Gt => {
+                                    cursor.seek(self.pager, |record| compare(record).is_le())?
+                                }
+                                // The entry before the first one past the key.
+                                BinaryOperator::Lt => {
+                                    cursor.seek(self.pager, |record| compare(record).is_lt())?;
+                                    cursor.prev(self.pager)?
+                                }
+                                _ => {
+                                    cursor.seek(self.pager, |record| compare(record).is_le())?;
+                                    cursor.prev(self.pager)?
+                                }
+                            }
+                        }
+                        _ => return Err(corrupt_program(address, "not a table or index cursor")),
+                    };
+                    jump(*if_not_found, !found);
+                }
+                Instruction::IdxCompare {
+                    operator,
+                    cursor,
+                    first,
+                    count,
+                    target,
+                } => {
+                    let (cursor, orders) = index_cursor(cursors, *cursor, address)?;
+                    let record = cursor.record().ok_or_else(|| {
+                        corrupt_program(address, "index cursor is not on an entry")
+                    })?;
+                    let ordering =
+                        compare_sort_keys(orders, record, &registers[*first..*first + *count]);
+                    jump(
+                        *target,
+                        match operator {
+                            BinaryOperator::Gt => ordering.is_gt(),
+                            BinaryOperator::GtEq => ordering.is_ge(),
+                            BinaryOperator::Lt => ordering.is_lt(),
+                            _ => ordering.is_le(),
+                        },
+                    );
+                }
+                Instruction::SeekRowid {
+                    cursor,
+                    rowid,
+                    if_not_found,
+                } => {
+                    null_rows[*cursor] = false;
+                    let found =
+                        match value::apply_affinity(registers[*rowid].clone(), ColumnType::Numeric)
+                        {
+                            Value::Integer(rowid) => table_cursor(cursors, *cursor, address)?
+                                .seek(self.pager, rowid as u64)?,
+                            _ => false,
+                        };
+                    jump(*if_not_found, !found);
+                }
+                Instruction::Column { cursor, target, .. } if null_rows[*cursor] => {
+                    registers[*target] = Value::Null
+                }
+                Instruction::Column {
+                    cursor,
+                    column,
+                    target,
+                } => {
+                    registers[*target] = match cursors[*cursor].as_ref() {
+                        Some(Cursor::Table(cursor)) => cursor
+                            .cell()
+                            .and_then(|cell| cell.payload.get(*column).cloned()),
+                        Some(Cursor::Index(cursor, _)) => cursor
+                            .record()
+                            .and_then(|record| record.get(*column).cloned()),
+                        Some(Cursor::Sorter(sorter)) => sorter
+                            .rows
+                            .get(sorter.position)
+                            .and_then(|row| row.get(*column).cloned()),
+                        Some(Cursor::Groups(groups)) => groups
+                            .sorted
+                            .get(groups.position)
+                            .and_then(|group| group.key.get(*column).cloned()),
+                        _ => return Err(corrupt_program(address, "no cursor to read from")),
+                    }
+                    .unwrap_or(Value::Null);
+                }
+                Instruction::Rowid { cursor, target }
+                | Instruction::IdxRowid { cursor, target }
+                    if null_rows[*cursor] =>
+                {
+                    registers[*target] = Value::Null
+                }
+                Instruction::Rowid { cursor, target } => {
+                    registers[*target] = table_cursor(cursors, *cursor, address)?
+                        .rowid()
+                        .map_or(Value::Null, |rowid| Value::Integer(rowid as i64));
+                }
+                Instruction::IdxRowid { cursor, target } => {
+                    registers[*target] = index_cursor(cursors, *cursor, address)?
+                        .0
+                        .rowid()
+                        .map_or(Value::Null, Value::Integer);
+                }
+                Instruction::NullRow { cursor } => null_rows[*cursor] = true,
+                Instruction::Affinity { first, affinities } => {
+                    for (register, affinity) in registers[*first..].iter_mut().zip(affinities) {
+                        *register = value::apply_affinity(register.clone(), *affinity);
+                    }
+                }
+                Instruction::RealAffinity { register } => {
+                    if let Value::Integer(integer) = registers[*register] {
+                        registers[*register] = Value::Float(integer as f64);
+                    }
+                }
+                Instruction::Integer { value, target } => {
+                    registers[*target] = Value::Integer(*value)
+                }
+                Instruction::Real { value, target } => registers[*target] = Value::Float(*value),
+                Instruction::String { value, target } => {
+                    registers[*target] = Value::Text(value.clone())
+                }
+                Instruction::Blob { value, target } => {
+                    registers[*target] = Value::Blob(value.clone())
+                }
+                Instruction::Null { target } => registers[*target] = Value::Null,
+                Instruction::Copy { source, target } => {
+                    registers[*target] = registers[*source].clone()
+                }
+                Instruction::Arithmetic {
+                    operator,
+                    left,
+                    right,
+                    target,
+                } => {
+                    let (left, right) = (&registers[*left], &registers[*right]);
+                    registers[*target] = match operator {
+                        BinaryOperator::Concat => value::concat(left, right),
+                        BinaryOperator::BitAnd
+                        | BinaryOperator::BitOr
+                        | BinaryOperator::ShiftLeft
+                        | BinaryOperator::ShiftRight => value::bitwise(*operator, left, right),
+                        _ => value::arithmetic(*operator, left, right),
+                    };
+                }
+                Instruction::And {
+                    left,
+                    right,
+                    target,
+                } => {
+                    let result = match (
+                        value::is_true(&registers[*left]),
+                        value::is_true(&registers[*right]),
+                    ) {
+                        (Some(false), _) | (_, Some(false)) => Some(false),
+                        (Some(true), Some(true)) => Some(true),
+                        _ => None,
+                    };
+                    registers[*target] = result.map_or(Value::Null, value::from_bool);
+                }
+                Instruction::Or {
+                    left,
+                    right,
+                    target,
+                } => {
+                    let result = match (
+                        value::is_true(&registers[*left]),
+                        value::is_true(&registers[*right]),
+                    ) {
+                        (Some(true), _) | (_, Some(true)) => Some(true),
+                        (Some(false), Some(false)) => Some(false),
+                        _ => None,
+                    };
+                    registers[*target] = result.map_or(Value::Null, value::from_bool);
+                }
+                Instruction::Not { source, target } => {
+                    registers[*target] = value::is_true(&registers[*source])
+                        .map_or(Value::Null, |value| value::from_bool(!value));
+                }
+                Instruction::BitNot { source, target } => {
+                    registers[*target] = value::to_integer(&registers[*source])
+                        .map_or(Value::Null, |value| Value::Integer(!value));
+                }
+                Instruction::Negate { source, target } => {
+                    registers[*target] = value::negate(&registers[*source])
+                }
+                Instruction::Compare {
+                    operator,
+                    left,
+                    right,
+                    target,
+                    jump_if_null,
+                    null_eq,
+                    affinities,
+                    collation,
+                } => {
+                    let (left, right) = (&registers[*left], &registers[*right]);
+                    let taken = match (left, right) {
+                        (Value::Null, Value::Null) if *null_eq => *operator == BinaryOperator::Eq,
+                        (Value::Null, _) | (_, Value::Null) if *null_eq => {
+                            *operator == BinaryOperator::NotEq
+                        }
+                        _ => match value::compare_operands(
+                            left,
+                            affinities.0,
+                            right,
+                            affinities.1,
+                            *collation,
+                        ) {
+                            None => *jump_if_null,
+                            Some(ordering) => match operator {
+                                BinaryOperator::Eq => ordering == Ordering::Equal,
+                                BinaryOperator::NotEq => ordering != Ordering::Equal,
+                                BinaryOperator::Lt => ordering == Ordering::Less,
+                                BinaryOperator::LtEq => ordering != Ordering::Greater,
+                                BinaryOperator::Gt => ordering == Ordering::Greater,
+                                _ => ordering != Ordering::Less,
+                            },
+                        },
+                    };
+                    jump(*target, taken);
+                }
+                Instruction::ZeroOrNull {
+                    left,
+                    right,
+                    target,
+                } => {
+                    if registers[*left] == Value::Null || registers[*right] == Value::Null {
+                        registers[*target] = Value::Null;
+                    }
+                }
+                Instruction::If {
+                    register,
+                    target,
+                    jump_if_null,
+                } => jump(
+                    *target,
+                    value::is_true(&registers[*register]).unwrap_or(*jump_if_null),
+                ),
+                Instruction::IfNot {
+                    register,
+                    target,
+                    jump_if_null,
+                } => jump(
+                    *target,
+                    value::is_true(&registers[*register]).map_or(*jump_if_null, |value| !value),
+                ),
+                Instruction::IsNull { register, target } => {
+                    jump(*target, registers[*register] == Value::Null)
+                }
+                Instruction::NotNull { register, target } => {
+                    jump(*target, registers[*register] != Value::Null)
+                }
+                Instruction::Once { target } => {
+                    jump(*target, self.once[address]);
+                    self.once[address] = true;
+                }
+                Instruction::Function {
+                    function,
+                    first_argument,
+                    argument_count,
+                    target,
+                } => {
+                    registers[*target] = function
+                        .call(&registers[*first_argument..*first_argument + *argument_count])?;
+                }
+                Instruction::Cast { register, affinity } => {
+                    registers[*register] = value::cast(&registers[*register], *affinity)
+                }
+                Instruction::MustBeInt { register } => {
+                    match value::apply_affinity(registers[*register].clone(), ColumnType::Numeric) {
+                        Value::Integer(integer) => registers[*register] = Value::Integer(integer),
+                        _ => return Err(DBError::Sql("datatype mismatch".to_string())),
+                    }
+                }
+                Instruction::IfPos {
+                    register,
+                    target,
+                    decrement,
+                } => {
+                    if let Value::Integer(integer) = &mut registers[*register] {
+                        if *integer > 0 {
+                            *integer -= decrement;
+                            jump(*target, true);
+                        }
+                    }
+                }
+                Instruction::DecrJumpZero { register, target } => {
+                    if let Value::Integer(integer) = &mut registers[*register] {
+                        *integer = integer.saturating_sub(1);
+                        jump(*target, *integer == 0);
+                    }
+                }
+                Instruction::SorterOpen { cursor, orders } => {
+                    cursors[*cursor] = Some(Cursor::Sorter(Sorter {
+                        orders: orders.clone(),
+                        rows: Vec::new(),
+                        position: 0,
+                    }))
+                }
+                Instruction::SorterInsert {
+                    cursor,
+                    first,
+                    count,
+                } => sorter(cursors, *cursor, address)?
+                    .rows
+                    .push(registers[*first..*first + *count].to_vec()),
+                Instruction::SorterSort { cursor, if_empty } => {
+                    let sorter = sorter(cursors, *cursor, address)?;
+                    // Stable, rows with equal keys stay in the order they were inserted.
+                    let orders = &sorter.orders;
+                    sorter.rows.sort_by(|a, b| compare_sort_keys(orders, a, b));
+                    sorter.position = 0;
+                    jump(*if_empty, sorter.rows.is_empty());
+                }
+                Instruction::SorterNext { cursor, target } => {
+                    let sorter = sorter(cursors, *cursor, address)?;
+                    sorter.position += 1;
+                    jump(*target, sorter.position < sorter.rows.len());
+                }
+                Instruction::WindowSort { cursor, keys } => {
+                    sorter(cursors, *cursor, address)?.rows.sort_by(|a, b| {
+                        keys.iter()
+                            .map(|(column, order)| {
+                                compare_sort_key(order, &a[*column], &b[*column])
+                            })
+                            .find(|ordering| *ordering != Ordering::Equal)
+                            .unwrap_or(Ordering::Equal)
+                    })
+                }
+                Instruction::WindowCall { cursor, call } => {
+                    call.compute(&mut sorter(cursors, *cursor, address)?.rows, registers)?
+                }
+                Instruction::OpenEphemeral { cursor, collations } => {
+                    cursors[*cursor] = Some(Cursor::Ephemeral(BTreeSet::new(), collations.clone()))
+                }
+                Instruction::Found {
+                    cursor,
+                    first,
+                    count,
+                    target,
+                } => {
+                    let (set, collations) = ephemeral(cursors, *cursor, address)?;
+                    let record = folded_record(&registers[*first..*first + *count], collations);
+                    jump(*target, set.contains(&record));
+                }
+                Instruction::IdxInsert {
+                    cursor,
+                    first,
+                    count,
+                } => {
+                    let (set, collations) = ephemeral(cursors, *cursor, address)?;
+                    let record = folded_record(&registers[*first..*first + *count], collations);
+                    set.insert(record);
+                }
+                Instruction::AggReset { first, count } => {
+                    for aggregation in aggregations[*first..*first + *count].iter_mut() {
+                        *aggregation = Aggregation::default();
+                    }
+                }
+                Instruction::AggStep {
+                    function,
+                    first_argument,
+                    argument_count,
+                    accumulator,
+                    distinct,
+                    collation,
+                    hit,
+                } => {
+                    let arguments = &registers[*first_argument..*first_argument + *argument_count];
+                    let aggregation = &mut aggregations[*accumulator];
+                    let seen = *distinct
+                        && !aggregation
+                            .seen
+                            .insert(folded_record(arguments, &[*collation]));
+                    let taken =
+                        !seen && function.step(&mut aggregation.accumulator, arguments, *collation);
+                    if let Some(hit) = hit {
+                        registers[*hit] = value::from_bool(taken);
+                    }
+                }
+                Instruction::AggFinal {
+                    function,
+                    accumulator,
+                    target,
+                } => {
+                    registers[*target] = function.value(&aggregations[*accumulator].accumulator)?
+                }
+                Instruction::GroupOpen {
+                    cursor,
+                    orders,
+                    first,
+                    count,
+                } => {
+                    cursors[*cursor] = Some(Cursor::Groups(Groups {
+                        orders: orders.clone(),
+                        collations: orders.iter().map(|order| order.collation).collect(),
+                        accumulators: *first..*first + *count,
+                        hashed: HashMap::new(),
+                        ordered: None,
+                        current: None,
+                        sorted: Vec::new(),
+                        position: 0,
+                    }))
+                }
+                Instruction::GroupFind {
+                    cursor,
+                    first,
+                    count,
+                } => groups(cursors, *cursor, address)?
+                    .find(&registers[*first..*first + *count], aggregations),
+                Instruction::GroupSort { cursor, if_empty } => {
+                    let groups = groups(cursors, *cursor, address)?;
+                    groups.sort(aggregations);
+                    jump(*if_empty, !groups.load(aggregations));
+                }
+                Instruction::GroupNext { cursor, target } => {
+                    let groups = groups(cursors, *cursor, address)?;
+                    groups.position += 1;
+                    jump(*target, groups.load(aggregations));
+                }
+                Instruction::ResultRow { first, count } => {
+                    return Ok(Some(registers[*first..*first + *count].to_vec()))
+                }
+            }
+        }
+        self.pc = self.program.instructions.len();
+        Ok(None)
+    }
+}
+
+fn table_cursor(
+    cursors: &mut [Option<Cursor>],
+    cursor: CursorId,
+    address: Address,
+) -> Result<&mut BTreeCursor> {
+    match cursors.get_mut(cursor) {
+        Some(Some(Cursor::Table(cursor))) => Ok(cursor),
+        _ => Err(corrupt_program(address, "not a table cursor")),
+    }
+}
+
+fn index_cursor(
+    cursors: &mut [Option<Cursor>],
+    cursor: CursorId,
+    address: Address,
+) -> Result<(&mut IndexCursor, &[SortOrder])> {
+    match cursors.get_mut(cursor) {
+        Some(Some(Cursor::Index(cursor, orders))) => Ok((cursor, orders)),
+        _ => Err(corrupt_program(address, "not an index cursor")),
+    }
+}
+
+enum Move {
+    First,
+    Last,
+    Next,
+    Prev,
+}
+
+// Moves a table or index cursor, returns whether it is on an entry.
+fn move_cursor(
+    cursors: &mut [Option<Cursor>],
+    cursor: CursorId,
+    address: Address,
+    pager: &mut Pager,
+    movement: Move,
+) -> Result<bool> {
+    match cursors.get_mut(cursor) {
+        Some(Some(Cursor::Table(cursor))) => match movement {
+            Move::First => cursor.first(pager),
+            Move::Last => cursor.last(pager),
+            Move::Next => cursor.next(pager),
+            Move::Prev => cursor.prev(pager),
+        },
+        Some(Some(Cursor::Index(cursor, _))) => match movement {
+            Move::First => cursor.first(pager),
+            Move::Last => cursor.last(pager),
+            Move::Next => cursor.next(pager),
+            Move::Prev => cursor.prev(pager),
+        },
+        _ => Err(corrupt_program(address, "not a table or index cursor")),
+    }
+}
+
+/*
+* Seek on a table cursor. Rowids are integers, so the key is turned into the smallest rowid that can
+* be greater than (or at least) it, or the largest one that can be less than (or at most) it. Text
+* and blobs are larger than every number.
+*/
+fn seek_rowid(
+    cursor: &mut BTreeCursor,
+    pager: &mut Pager,
+    operator: BinaryOperator,
+    key: &Value,
+) -> Result<bool> {
+    let upward = matches!(operator, BinaryOperator::Gt | BinaryOperator::GtEq);
+    let bound = match value::apply_affinity(key.clone(), ColumnType::Numeric) {
+        Value::Integer(key) => match operator {
+            BinaryOperator::Gt => key.checked_add(1),
+            BinaryOperator::Lt => key.checked_sub(1),
+            _ => Some(key),
+        },
+        // Whole reals in range became integers, this one is between two of them or out of range.
+        Value::Float(key) if !key.is_nan() => {
+            let rounded = if upward { key.ceil() } else { key.floor() };
+            if rounded >= 9223372036854775808.0 {
+                (!upward).then_some(i64::MAX)
+            } else if rounded < -9223372036854775808.0 {
+                upward.then_some(i64::MIN)
+            } else {
+                Some(rounded as i64)
+            }
+        }
+        Value::Text(_) | Value::Blob(_) => (!upward).then_some(i64::MAX),
+        _ => None,
+    };
+    let Some(bound) = bound else {
+        return Ok(false);
+    };
+    let exact = cursor.seek(pager, bound as u64)?;
+    if upward || exact {
+        return Ok(cursor.is_valid());
+    }
+    // On the first row past the bound, or past the end of the table.
+    cursor.prev(pager)
+}
+
+fn sorter(
+    cursors: &mut [Option<Cursor>],
+    cursor: CursorId,
+    address: Address,
+) -> Result<&mut Sorter> {
+    match cursors.get_mut(cursor) {
+        Some(Some(Cursor::Sorter(sorter))) => Ok(sorter),
+        _ => Err(corrupt_program(address, "not a sorter")),
+    }
+}
+
+fn groups(
+    cursors: &mut [Option<Cursor>],
+    cursor: CursorId,
+    address: Address,
+) -> Result<&mut Groups> {
+    match cursors.get_mut(cursor) {
+        Some(Some(Cursor::Groups(groups))) => Ok(groups),
+        _ => Err(corrupt_program(address, "not a set of groups")),
+    }
+}
+
+fn ephemeral(
+    cursors: &mut [Option<Cursor>],
+    cursor: CursorId,
+    address: Address,
+) -> Result<(&mut BTreeSet<Record>, &[Collation])> {
+    match cursors.get_mut(cursor) {
+        Some(Some(Cursor::Ephemeral(set, collations))) => Ok((set, collations)),
+        _ => Err(corrupt_program(address, "not an ephemeral set")),
+    }
+}
+
+// A row for an ephemeral set, its text folded so that values equal under their collation are equal
+// byte for byte.
+fn folded_record(values: &[Value], collations: &[Collation]) -> Record {
+    let collations = collations
+        .iter()
+        .chain(std::iter::repeat(&Collation::Binary));
+    Record(
+        values
+            .iter()
+            .zip(collations)
+            .map(|(value, collation)| match (value, collation) {
+                (Value::Text(text), Collation::NoCase) => Value::Text(text.to_ascii_lowercase()),
+                (Value::Text(text), Collation::RTrim) => {
+                    Value::Text(text.trim_end_matches(' ').to_string())
+                }
+                (value, _) => value.clone(),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::page::errors::{DBError, Result};
+    use crate::page::file_structures::Value;
+    use crate::page::{ColumnType, Database};
+    use crate::sql::ast::BinaryOperator;
+    use crate::sql::functions::find_aggregate;
+    use crate::sql::value::Collation;
+    use crate::sql::vm::{Aggregation, Groups, Instruction, Program, SortOrder, Vm, GROUP_LIMIT};
+
+    const EMPTY_TABLE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty_table.db");
+    // `worms(id INTEGER PRIMARY KEY, name TEXT NOT NULL, length REAL)` with rowids 1 to 300 on
+    // several pages.
+    const MULTI_PAGE_DB: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/multi_page.db");
+    // The index worms_name(name) of the worms, named worm-0001 to worm-0300.
+    const WORMS_NAME_ROOT_PAGE: usize = 3;
+
+    // Runs a hand-built program with 8 registers and 2 cursors to the end, returning its rows.
+    fn run(file_path: &str, instructions: Vec<Instruction>) -> Result<Vec<Vec<Value>>> {
+        let mut database = Database::open_read_only(file_path.to_string()).unwrap();
+        let program = Program {
+            instructions,
+            columns: Vec::new(),
+            register_count: 8,
+            cursor_count: 2,
+            accumulator_count: 0,
+            explain: None,
+            plan: Vec::new(),
+        };
+        database.pager.begin_read().unwrap();
+        let mut vm = Vm::new(program, &mut database.pager);
+        let mut rows = Vec::new();
+        let result = loop {
+            match vm.step() {
+                Ok(Some(row)) => rows.push(row),
+                Ok(None) => break Ok(rows),
+                Err(error) => break Err(error),
+            }
+        };
+        vm.pager().end_transaction().unwrap();
+        result
+    }
+
+    // Whether Compare jumps for `left operator right`.
+    fn compare(
+        operator: BinaryOperator,
+        left: Value,
+        right: Value,
+        jump_if_null: bool,
+        null_eq: bool,
+        affinities: (Option<ColumnType>, Option<ColumnType>),
+    ) -> bool {
+        let value = |value: Value, target| match value {
+            Value::Null => Instruction::Null { target },
+            Value::Integer(value) => Instruction::Integer { value, target },
+            Value::Float(value) => Instruction::Real { value, target },
+            Value::Text(value) => Instruction::String { value, target },
+            Value::Blob(value) => Instruction::Blob { value, target },
+        };
+        let rows = run(
+            EMPTY_TABLE_DB,
+            vec![
+                value(left, 0),
+                value(right, 1),
+                Instruction::Compare {
+                    operator,
+                    left: 0,
+                    right: 1,
+                    target: 6,
+                    jump_if_null,
+                    null_eq,
+                    affinities,
+                    collation: Collation::Binary,
+                },
+                Instruction::Integer {
+                    value: 0,
+                    target: 2,
+                },
+                Instruction::ResultRow { first: 2, count: 1 },
+                Instruction::Halt,
+                Instruction::Integer {
+                    value: 1,
+                    target: 2,
+                },
+                Instruction::ResultRow { first: 2, count: 1 },
+            ],
+        )
+        .unwrap();
+        rows == vec![vec![Value::Integer(1)]]
+    }
+
+    #[test]
+    fn affinity_test() {
+        let rows = run(
+            EMPTY_TABLE_DB,
+            vec![
+                Instruction::String {
+                    value: "42".to_string(),
+                    target: 0,
+                },
+                Instruction::String {
+                    value: "4.0".to_string(),
+                    target: 1,
+                },
+                Instruction::Integer {
+                    value: 7,
+                    target: 2,
+                },
+                Instruction::Real {
+                    value: 2.5,
+                    target: 3,
+                },
+                Instruction::String {
+                    value: "abc".to_string(),
+                    target: 4,
+                },
+                Instruction::Integer {
+                    value: 3,
+                    target: 5,
+                },
+                Instruction::Affinity {
+                    first: 0,
+                    affinities: vec![
+                        ColumnType::Integer,
+                        ColumnType::Numeric,
+                        ColumnType::Text,
+                        ColumnType::Integer,
+                        ColumnType::Real,
+                    ],
+                },
+                Instruction::RealAffinity { register: 5 },
+                Instruction::ResultRow { first: 0, count: 6 },
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![vec![
+                Value::Integer(42),
+                Value::Integer(4),
+                Value::Text("7".to_string()),
+                // Only lossless conversions, 2.5 stays a real and "abc" text.
+                Value::Float(2.5),
+                Value::Text("abc".to_string()),
+                Value::Float(3.0),
+            ]]
+        );
+    }
+
+    #[test]
+    fn null_comparison_test() {
+        let none = (None, None);
+        let null = || Value::Null;
+        let one = || Value::Integer(1);
+
+        // A comparison with NULL is neither true nor false, jump_if_null decides.
+        for operator in [
+            BinaryOperator::Eq,
+            BinaryOperator::NotEq,
+            BinaryOperator::Lt,
+        ] {
+            assert!(!compare(operator, null(), one(), false, false, none));
+            assert!(compare(operator, one(), null(), true, false, none));
+            assert!(compare(operator, null(), null(), true, false, none));
+        }
+
+        // IS and IS NOT, NULL equals NULL and nothing else.
+        assert!(compare(
+            BinaryOperator::Eq,
+            null(),
+            null(),
+            false,
+            true,
+            none
+        ));
+        assert!(!compare(
+            BinaryOperator::Eq,
+            null(),
+            one(),
+            true,
+            true,
+            none
+        ));
+        assert!(compare(
+            BinaryOperator::NotEq,
+            one(),
+            null(),
+            false,
+            true,
+            none
+        ));
+        assert!(!compare(
+            BinaryOperator::NotEq,
+            null(),
+            null(),
+            true,
+            true,
+            none
+        ));
+        assert!(compare(BinaryOperator::Eq, one(), one(), false, true, none));
+
+        // Numbers sort before text unless the affinity of a column operand turns the text into one.
+        let text = || Value::Text("1".to_string());
+        assert!(compare(
+            BinaryOperator::Lt,
+            one(),
+            text(),
+            false,
+            false,
+            none
+        ));
+        assert!(!compare(
+            BinaryOperator::Eq,
+            one(),
+            text(),
+            false,
+            false,
+            none
+        ));
+        let integer = (Some(ColumnType::Integer), None);
+        assert!(compare(
+            BinaryOperator::Eq,
+            one(),
+            text(),
+            false,
+            false,
+            integer
+        ));
+    }
+
+    #[test]
+    fn cursor_eof_test() {
+        // Rewind jumps straight past the loop on an empty table.
+        let rows = run(
+            EMPTY_TABLE_DB,
+            vec![
+                Instruction::OpenRead {
+                    cursor: 0,
+                    root_page: 2,
+                    table: "items".to_string(),
+                },
+                Instruction::Rewind {
+                    cursor: 0,
+                    if_empty: 5,
+                },
+                Instruction::Rowid {
+                    cursor: 0,
+                    target: 0,
+                },
+                Instruction::ResultRow { first: 0, count: 1 },
+                Instruction::Next {
+                    cursor: 0,
+                    target: 2,
+                },
+                Instruction::Halt,
+            ],
+        )
+        .unwrap();
+        assert!(rows.is_empty());
+
+        // Next falls through after the last row, a cursor past it and a NullRow cursor read NULL.
+        let rows = run(
+            MULTI_PAGE_DB,
+            vec![
+                Instruction::OpenRead {
+                    cursor: 0,
+                    root_page: 2,
+                    table: "worms".to_string(),
+                },
+                Instruction::Rewind {
+                    cursor: 0,
+                    if_empty: 8,
+                },
+                Instruction::Rowid {
+                    cursor: 0,
+                    target: 0,
+                },
+                Instruction::ResultRow { first: 0, count: 1 },
+                Instruction::Next {
+                    cursor: 0,
+                    target: 2,
+                },
+                Instruction::Rowid {
+                    cursor: 0,
+                    target: 0,
+                },
+                Instruction::Column {
+                    cursor: 0,
+                    column: 1,
+                    target: 1,
+                },
+                Instruction::ResultRow { first: 0, count: 2 },
+                Instruction::Last {
+                    cursor: 0,
+                    if_empty: 12,
+                },
+                Instruction::NullRow { cursor: 0 },
+                Instruction::Column {
+                    cursor: 0,
+                    column: 1,
+                    target: 1,
+                },
+                Instruction::ResultRow { first: 1, count: 1 },
+                Instruction::Halt,
+            ],
+        )
+        .unwrap();
+        let (last, rows) = rows.split_last().unwrap();
+        let (end, rows) = rows.split_last().unwrap();
+        let rowids: Vec<Value> = (1..=300).map(Value::Integer).collect();
+        assert_eq!(rows.concat(), rowids);
+        assert_eq!(end, &vec![Value::Null, Value::Null]);
+        assert_eq!(last, &vec![Value::Null]);
+    }
+
+    #[test]
+    fn index_seek_test() {
+        let text = |value: &str, target| Instruction::String {
+            value: value.to_string(),
+            target,
+        };
+        let order = SortOrder {
+            descending: false,
+            nulls_first: true,
+            collation: Collation::Binary,
+        };
+        // The rowids of the entries from the first at or after r0 to the last at or before r1.
+        let range = |operator, low: &str, high: &str| {
+            run(
+                MULTI_PAGE_DB,
+                vec![
+                    Instruction::OpenIndex {
+                        cursor: 0,
+                        root_page: WORMS_NAME_ROOT_PAGE,
+                        index: "worms_name".to_string(),
+                        key: vec![order],
+                    },
+                    text(low, 0),
+                    text(high, 1),
+                    Instruction::Seek {
+                        operator,
+                        cursor: 0,
+                        first: 0,
+                        count: 1,
+                        if_not_found: 8,
+                    },
+                    Instruction::IdxCompare {
+                        operator: BinaryOperator::Gt,
+                        cursor: 0,
+                        first: 1,
+                        count: 1,
+                        target: 8,
+                    },
+                    Instruction::IdxRowid {
+                        cursor: 0,
+                        target: 2,
+                    },
+                    Instruction::ResultRow { first: 2, count: 1 },
+                    Instruction::Next {
+                        cursor: 0,
+                        target: 4,
+                    },
+                    Instruction::Halt,
+                ],
+            )
+            .unwrap()
+            .concat()
+        };
+        let rowids =
+            |rowids: &[i64]| -> Vec<Value> { rowids.iter().copied().map(Value::Integer).collect() };
+
+        assert_eq!(
+            range(BinaryOperator::GtEq, "worm-0010", "worm-0012"),
+            rowids(&[10, 11, 12])
+        );
+        assert_eq!(
+            range(BinaryOperator::Gt, "worm-0010", "worm-0012"),
+            rowids(&[11, 12])
+        );
+        // Between two keys, and past the last one.
+        assert_eq!(
+            range(BinaryOperator::GtEq, "worm-0010a", "worm-0012"),
+            rowids(&[11, 12])
+        );
+        assert_eq!(
+            range(BinaryOperator::GtEq, "worm-0299", "x"),
+            rowids(&[299, 300])
+        );
+        assert!(range(BinaryOperator::GtEq, "x", "y").is_empty());
+    }
+
+    #[test]
+    fn program_errors_test() {
+        let expect_error = |instructions, expected: &str| match run(EMPTY_TABLE_DB, instructions) {
+            Err(DBError::Sql(message)) => assert_eq!(message, expected),
+            result => panic!("Expected {:?}, got: {:?}", expected, result),
+        };
+        expect_error(
+            vec![
+                Instruction::Noop,
+                Instruction::Rewind {
+                    cursor: 1,
+                    if_empty: 2,
+                },
+            ],
+            "instruction 1: not a table or index cursor",
+        );
+        expect_error(
+            vec![Instruction::Column {
+                cursor: 0,
+                column: 0,
+                target: 0,
+            }],
+            "instruction 0: no cursor to read from",
+        );
+        expect_error(
+            vec![Instruction::Return { register: 0 }],
+            "instruction 0: no address to return to",
+        );
+        expect_error(
+            vec![
+                Instruction::String {
+                    value: "abc".to_string(),
+                    target: 0,
+                },
+                Instruction::MustBeInt { register: 0 },
+            ],
+            "datatype mismatch",
+        );
+
+        // A number in text passes MustBeInt as the integer.
+        let rows = run(
+            EMPTY_TABLE_DB,
+            vec![
+                Instruction::String {
+                    value: " 12 ".to_string(),
+                    target: 0,
+                },
+                Instruction::MustBeInt { register: 0 },
+                Instruction::ResultRow { first: 0, count: 1 },
+            ],
+        )
+        .unwrap();
+        assert_eq!(rows, vec![vec![Value::Integer(12)]]);
+    }
+
+    #[test]
+    fn groups_past_limit_test() {
+        let order = SortOrder {
+            descending: true,
+            nulls_first: false,
+            collation: Collation::NoCase,
+        };
+        let mut groups = Groups {
+            orders: vec![order],
+            collations: vec![Collation::NoCase],
+            accumulators: 1..2,
+            hashed: HashMap::new(),
+            ordered: None,
+            current: None,
+            sorted: Vec::new(),
+            position: 0,
+        };
+        let count = find_aggregate("count", 0).unwrap().unwrap();
+        let mut aggregations: Vec<Aggregation> = (0..2).map(|_| Aggregation::default()).collect();
+
+        // Every key twice, in another case the second time, and the rows all over the place.
+        let group_count = 3 * GROUP_LIMIT;
+        for row in 0..2 * group_count {
+            let row = row * 7919 % (2 * group_count);
+            let key = match row < group_count {
+                true => format!("key-{:04}", row),
+                false => format!("KEY-{:04}", row - group_count),
+            };
+            groups.find(&[Value::Text(key)], &mut aggregations);
+            count.step(&mut aggregations[1].accumulator, &[], Collation::Binary);
+        }
+        assert!(groups.ordered.is_some());
+
+        groups.sort(&mut aggregations);
+        let mut keys = Vec::new();
+        while groups.load(&mut aggregations) {
+            let key = groups.sorted[groups.position].key[0].clone();
+            let Value::Text(key) = key else {
+                panic!("Expected a text key, got: {:?}", key);
+            };
+            assert_eq!(
+                count.value(&aggregations[1].accumulator).unwrap(),
+                Value::Integer(2)
+            );
+            keys.push(key.to_lowercase());
+            groups.position += 1;
+        }
+        let expected: Vec<String> = (0..group_count)
+            .rev()
+            .map(|group| format!("key-{:04}", group))
+            .collect();
+        assert_eq!(keys, expected);
+    }
+}
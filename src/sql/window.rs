@@ -0,0 +1,976 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::page::errors::{DBError, Result};
+use crate::page::file_structures::Value;
+use crate::page::ColumnType;
+
+use super::ast::{
+    BinaryOperator, Expr, ExprKind, Frame, FrameBound, FrameExclude, FrameUnits, Literal,
+};
+use super::functions::{Accumulator, AggregateFunction};
+use super::value::{self, Collation};
+use super::vm::{Register, SortOrder};
+
+/*
+* SQLite's built-in window functions, https://www.sqlite.org/windowfunctions.html, and the frames
+* they and the aggregates used as window functions work out their values over.
+*
+* A window function has a value for every row, from the rows of the row's partition: the rows whose
+* PARTITION BY terms are equal to its own, sorted by the ORDER BY of the window. Rows ORDER BY puts
+* in the same place are peers. The ranking functions, ntile(), lag() and lead() look at the whole
+* partition, the others only at the frame of the row, the rows ROWS, RANGE or GROUPS picks around it
+* less the ones EXCLUDE leaves out.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Builtin {
+    RowNumber,
+    Rank,
+    DenseRank,
+    PercentRank,
+    CumeDist,
+    Ntile,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    NthValue,
+}
+
+#[derive(Debug)]
+pub struct WindowFunction {
+    pub name: &'static str,
+    min_arguments: usize,
+    max_arguments: usize,
+    builtin: Builtin,
+}
+
+const fn window(
+    name: &'static str,
+    min_arguments: usize,
+    max_arguments: usize,
+    builtin: Builtin,
+) -> WindowFunction {
+    WindowFunction {
+        name,
+        min_arguments,
+        max_arguments,
+        builtin,
+    }
+}
+
+const WINDOW_FUNCTIONS: &[WindowFunction] = &[
+    window("row_number", 0, 0, Builtin::RowNumber),
+    window("rank", 0, 0, Builtin::Rank),
+    window("dense_rank", 0, 0, Builtin::DenseRank),
+    window("percent_rank", 0, 0, Builtin::PercentRank),
+    window("cume_dist", 0, 0, Builtin::CumeDist),
+    window("ntile", 1, 1, Builtin::Ntile),
+    window("lag", 1, 3, Builtin::Lag),
+    window("lead", 1, 3, Builtin::Lead),
+    window("first_value", 1, 1, Builtin::FirstValue),
+    window("last_value", 1, 1, Builtin::LastValue),
+    window("nth_value", 2, 2, Builtin::NthValue),
+];
+
+impl WindowFunction {
+    /*
+     * The frame SQLite gives the functions that look at the whole partition, whatever their window
+     * says. They do not use it, but only share a window with calls that have the same frame.
+     */
+    pub fn frame(&self) -> Option<Frame> {
+        let (units, start, end) = match self.builtin {
+            Builtin::RowNumber | Builtin::Lag => (
+                FrameUnits::Rows,
+                FrameBound::UnboundedPreceding,
+                FrameBound::CurrentRow,
+            ),
+            Builtin::Rank | Builtin::DenseRank => (
+                FrameUnits::Range,
+                FrameBound::UnboundedPreceding,
+                FrameBound::CurrentRow,
+            ),
+            Builtin::PercentRank => (
+                FrameUnits::Groups,
+                FrameBound::CurrentRow,
+                FrameBound::UnboundedFollowing,
+            ),
+            Builtin::CumeDist => (
+                FrameUnits::Groups,
+                FrameBound::Following(Expr {
+                    kind: ExprKind::Literal(Literal::Integer(1)),
+                    span: Default::default(),
+                }),
+                FrameBound::UnboundedFollowing,
+            ),
+            Builtin::Ntile => (
+                FrameUnits::Rows,
+                FrameBound::CurrentRow,
+                FrameBound::UnboundedFollowing,
+            ),
+            Builtin::Lead => (
+                FrameUnits::Rows,
+                FrameBound::UnboundedPreceding,
+                FrameBound::UnboundedFollowing,
+            ),
+            Builtin::FirstValue | Builtin::LastValue | Builtin::NthValue => return None,
+        };
+        Some(Frame {
+            units,
+            start,
+            end,
+            exclude: FrameExclude::NoOthers,
+        })
+    }
+}
+
+// Looks up a built-in window function by name, None when there is none by that name.
+pub fn find_window(name: &str, argument_count: usize) -> Option<Result<&'static WindowFunction>> {
+    let function = WINDOW_FUNCTIONS
+        .iter()
+        .find(|function| function.name.eq_ignore_ascii_case(name))?;
+    match (function.min_arguments..=function.max_arguments).contains(&argument_count) {
+        true => Some(Ok(function)),
+        false => Some(Err(DBError::Sql(format!(
+            "wrong number of arguments to function {}()",
+            name
+        )))),
+    }
+}
+
+// What a window function call calls: a built-in window function or an aggregate.
+#[derive(Debug, Clone, Copy)]
+pub enum Function {
+    Window(&'static WindowFunction),
+    Aggregate(&'static AggregateFunction),
+}
+
+impl Function {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Window(function) => function.name,
+            Function::Aggregate(function) => function.name,
+        }
+    }
+}
+
+// An end of a frame, with the register its offset is in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameEdge {
+    UnboundedPreceding,
+    Preceding(Register),
+    CurrentRow,
+    Following(Register),
+    UnboundedFollowing,
+}
+
+impl FrameEdge {
+    fn offset(&self) -> Option<Register> {
+        match self {
+            FrameEdge::Preceding(register) | FrameEdge::Following(register) => Some(*register),
+            _ => None,
+        }
+    }
+}
+
+/*
+* A call of a window function over the rows of a sorter, sorted by its partition and ORDER BY
+* columns already. The columns it reads are those of its arguments, the value of its FILTER, the
+* terms of PARTITION BY with the collations they compare by and those of ORDER BY with the way
+* they sort.
+*/
+#[derive(Debug)]
+pub struct WindowCall {
+    pub function: Function,
+    pub arguments: Range<usize>,
+    pub filter: Option<usize>,
+    pub partition: Vec<(usize, Collation)>,
+    pub order: Vec<(usize, SortOrder)>,
+    pub units: FrameUnits,
+    pub start: FrameEdge,
+    pub end: FrameEdge,
+    pub exclude: FrameExclude,
+    // The collation of the first argument, which min() and max() compare text by.
+    pub collation: Collation,
+}
+
+impl WindowCall {
+    /*
+     * Adds the value of the function for each row to the end of the row. The offsets of the frame
+     * are checked once there are rows, like SQLite does: ROWS and GROUPS count rows and groups of
+     * peers, RANGE goes by the difference of the ORDER BY values.
+     */
+    pub fn compute(&self, rows: &mut [Vec<Value>], registers: &[Value]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let start = self.frame_offset(&self.start, registers, "starting")?;
+        let end = self.frame_offset(&self.end, registers, "ending")?;
+        let mut first = 0;
+        while first < rows.len() {
+            let count = rows[first..]
+                .iter()
+                .position(|row| !self.same_partition(&rows[first], row))
+                .unwrap_or(rows.len() - first);
+            let partition = Partition::new(self, &rows[first..first + count]);
+            let values = partition.values(start.as_ref(), end.as_ref())?;
+            for (row, value) in rows[first..first + count].iter_mut().zip(values) {
+                row.push(value);
+            }
+            first += count;
+        }
+        Ok(())
+    }
+
+    fn frame_offset(
+        &self,
+        edge: &FrameEdge,
+        registers: &[Value],
+        which: &str,
+    ) -> Result<Option<Value>> {
+        let Some(register) = edge.offset() else {
+            return Ok(None);
+        };
+        let offset = value::apply_affinity(registers[register].clone(), ColumnType::Numeric);
+        match (self.units, &offset) {
+            (_, Value::Integer(integer)) if *integer >= 0 => Ok(Some(offset)),
+            (FrameUnits::Range, Value::Float(real)) if *real >= 0.0 => Ok(Some(offset)),
+            (FrameUnits::Range, _) => Err(DBError::Sql(format!(
+                "frame {} offset must be a non-negative number",
+                which
+            ))),
+            _ => Err(DBError::Sql(format!(
+                "frame {} offset must be a non-negative integer",
+                which
+            ))),
+        }
+    }
+
+    fn same_partition(&self, a: &[Value], b: &[Value]) -> bool {
+        self.partition.iter().all(|(column, collation)| {
+            value::compare(&a[*column], &b[*column], *collation) == Ordering::Equal
+        })
+    }
+
+    // Whether two rows are peers: ORDER BY puts them in the same place.
+    fn peers(&self, a: &[Value], b: &[Value]) -> bool {
+        self.order.iter().all(|(column, order)| {
+            value::compare(&a[*column], &b[*column], order.collation) == Ordering::Equal
+        })
+    }
+
+    // Whether FILTER lets a row into an aggregate.
+    fn filtered(&self, row: &[Value]) -> bool {
+        self.filter
+            .is_none_or(|column| value::is_true(&row[column]) == Some(true))
+    }
+}
+
+// The rows of a partition, with the groups of peers they are in.
+struct Partition<'p> {
+    call: &'p WindowCall,
+    rows: &'p [Vec<Value>],
+    // The group of each row, and where each group starts, followed by the number of rows.
+    groups: Vec<usize>,
+    group_starts: Vec<usize>,
+}
+
+impl<'p> Partition<'p> {
+    fn new(call: &'p WindowCall, rows: &'p [Vec<Value>]) -> Partition<'p> {
+        let mut groups = Vec::with_capacity(rows.len());
+        let mut group_starts = Vec::new();
+        for (index, row) in rows.iter().enumerate() {
+            if index == 0 || !call.peers(&rows[index - 1], row) {
+                group_starts.push(index);
+            }
+            groups.push(group_starts.len() - 1);
+        }
+        group_starts.push(rows.len());
+        Partition {
+            call,
+            rows,
+            groups,
+            group_starts,
+        }
+    }
+
+    fn values(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Vec<Value>> {
+        let count = self.rows.len();
+        let argument =
+            |row: usize, index: usize| self.rows[row][self.call.arguments.start + index].clone();
+        let mut values = Vec::with_capacity(count);
+        let function = match self.call.function {
+            Function::Window(function) => function,
+            Function::Aggregate(function) => return self.aggregate(function, start, end),
+        };
+        let buckets = match function.builtin {
+            Builtin::Ntile => match value::to_integer(&argument(0, 0)) {
+                Some(buckets) if buckets > 0 => buckets as usize,
+                _ => {
+                    return Err(DBError::Sql(
+                        "argument of ntile must be a positive integer".to_string(),
+                    ))
+                }
+            },
+            _ => 0,
+        };
+        for row in 0..count {
+            let group = self.groups[row];
+            values.push(match function.builtin {
+                Builtin::RowNumber => Value::Integer(row as i64 + 1),
+                Builtin::Rank => Value::Integer(self.group_starts[group] as i64 + 1),
+                Builtin::DenseRank => Value::Integer(group as i64 + 1),
+                Builtin::PercentRank => Value::Float(match count {
+                    1 => 0.0,
+                    _ => self.group_starts[group] as f64 / (count - 1) as f64,
+                }),
+                Builtin::CumeDist => {
+                    Value::Float(self.group_starts[group + 1] as f64 / count as f64)
+                }
+                // The first `count % buckets` buckets get a row more than the others.
+                Builtin::Ntile => {
+                    let size = count / buckets;
+                    let large = count % buckets;
+                    let bucket = match row < large * (size + 1) {
+                        true => row / (size + 1),
+                        false => large + (row - large * (size + 1)) / size,
+                    };
+                    Value::Integer(bucket as i64 + 1)
+                }
+                Builtin::Lag | Builtin::Lead => {
+                    let offset = match function.builtin {
+                        Builtin::Lag => 1,
+                        _ => -1,
+                    };
+                    let offset = match self.call.arguments.len() {
+                        1 => Some(offset),
+                        _ => value::to_integer(&argument(row, 1))
+                            .map(|steps| steps.saturating_mul(offset)),
+                    };
+                    match offset {
+                        None => Value::Null,
+                        Some(offset) => match (row as i64).checked_sub(offset) {
+                            Some(other) if (0..count as i64).contains(&other) => {
+                                argument(other as usize, 0)
+                            }
+                            _ if self.call.arguments.len() == 3 => argument(row, 2),
+                            _ => Value::Null,
+                        },
+                    }
+                }
+                Builtin::FirstValue | Builtin::LastValue | Builtin::NthValue => {
+                    let mut frame = self.frame(row, start, end);
+                    let found = match function.builtin {
+                        Builtin::FirstValue => frame.next(),
+                        Builtin::LastValue => frame.last(),
+                        _ => match value::apply_affinity(argument(row, 1), ColumnType::Numeric) {
+                            Value::Integer(nth) if nth > 0 => {
+                                frame.nth(usize::try_from(nth - 1).unwrap_or(usize::MAX))
+                            }
+                            _ => {
+                                return Err(DBError::Sql(
+                                    "second argument to nth_value must be a positive integer"
+                                        .to_string(),
+                                ))
+                            }
+                        },
+                    };
+                    found.map_or(Value::Null, |found| argument(found, 0))
+                }
+            });
+        }
+        Ok(values)
+    }
+
+    /*
+     * The values of an aggregate. While frames start at the partition and leave nothing out, each
+     * frame is the one before with the rows it ends after added, and the accumulator carries on.
+     * Otherwise it starts over for every row.
+     */
+    fn aggregate(
+        &self,
+        function: &AggregateFunction,
+        start: Option<&Value>,
+        end: Option<&Value>,
+    ) -> Result<Vec<Value>> {
+        let call = self.call;
+        let step = |accumulator: &mut Accumulator, row: usize| {
+            let row = &self.rows[row];
+            if call.filtered(row) {
+                function.step(accumulator, &row[call.arguments.clone()], call.collation);
+            }
+        };
+        let mut values = Vec::with_capacity(self.rows.len());
+        let growing =
+            call.start == FrameEdge::UnboundedPreceding && call.exclude == FrameExclude::NoOthers;
+        let mut accumulator = Accumulator::default();
+        let mut stepped = 0;
+        for row in 0..self.rows.len() {
+            if growing {
+                let (_, frame_end) = self.bounds(row, start, end);
+                while stepped < frame_end {
+                    step(&mut accumulator, stepped);
+                    stepped += 1;
+                }
+                values.push(function.value(&accumulator)?);
+            } else {
+                let mut accumulator = Accumulator::default();
+                for framed in self.frame(row, start, end) {
+                    step(&mut accumulator, framed);
+                }
+                values.push(function.value(&accumulator)?);
+            }
+        }
+        Ok(values)
+    }
+
+    // The rows in the frame of a row, in order.
+    fn frame(
+        &self,
+        row: usize,
+        start: Option<&Value>,
+        end: Option<&Value>,
+    ) -> impl Iterator<Item = usize> + '_ {
+        let (frame_start, frame_end) = self.bounds(row, start, end);
+        let group = self.group_starts[self.groups[row]]..self.group_starts[self.groups[row] + 1];
+        let exclude = self.call.exclude;
+        (frame_start..frame_end).filter(move |framed| match exclude {
+            FrameExclude::NoOthers => true,
+            FrameExclude::CurrentRow => *framed != row,
+            FrameExclude::Group => !group.contains(framed),
+            FrameExclude::Ties => *framed == row || !group.contains(framed),
+        })
+    }
+
+    // Where the frame of a row starts, and where it ends, past its last row.
+    fn bounds(&self, row: usize, start: Option<&Value>, end: Option<&Value>) -> (usize, usize) {
+        let frame_start = self.edge(self.call.start, row, start, true);
+        let frame_end = self.edge(self.call.end, row, end, false);
+        (frame_start, frame_end.max(frame_start))
+    }
+
+    // The row an end of the frame of a row is at: the first row for the start, the one past the
+    // last for the end.
+    fn edge(&self, edge: FrameEdge, row: usize, offset: Option<&Value>, start: bool) -> usize {
+        let count = self.rows.len();
+        let group = self.groups[row];
+        let groups = self.group_starts.len() - 1;
+        // ROWS and GROUPS offsets were checked to be non-negative integers.
+        let steps = match offset {
+            Some(Value::Integer(steps)) => usize::try_from(*steps).unwrap_or(usize::MAX),
+            _ => 0,
+        };
+        match (edge, self.call.units) {
+            (FrameEdge::UnboundedPreceding, _) => 0,
+            (FrameEdge::UnboundedFollowing, _) => count,
+            (FrameEdge::CurrentRow, FrameUnits::Rows) => row + !start as usize,
+            (FrameEdge::CurrentRow, _) => self.group_starts[group + !start as usize],
+            (FrameEdge::Preceding(_), FrameUnits::Rows) => {
+                (row + !start as usize).saturating_sub(steps)
+            }
+            (FrameEdge::Following(_), FrameUnits::Rows) => row
+                .saturating_add(steps)
+                .saturating_add(!start as usize)
+                .min(count),
+            (FrameEdge::Preceding(_), FrameUnits::Groups) => {
+                match (group + !start as usize).checked_sub(steps) {
+                    Some(other) => self.group_starts[other],
+                    None => 0,
+                }
+            }
+            (FrameEdge::Following(_), FrameUnits::Groups) => {
+                let other = group.saturating_add(steps).saturating_add(!start as usize);
+                self.group_starts[other.min(groups)]
+            }
+            (_, FrameUnits::Range) => self.range_edge(edge, row, offset, start),
+        }
+    }
+
+    /*
+     * An end of a RANGE frame with an offset, which takes the rows whose ORDER BY value is within
+     * the offset of the row's. The value is a number, text and blobs being within no offset but
+     * their own. A row whose value is NULL has its peers for its frame, which other rows' frames
+     * only take in when they reach the start or end of the partition.
+     */
+    fn range_edge(
+        &self,
+        edge: FrameEdge,
+        row: usize,
+        offset: Option<&Value>,
+        start: bool,
+    ) -> usize {
+        let (column, order) = self.call.order[0];
+        let key = &self.rows[row][column];
+        if *key == Value::Null {
+            return self.group_starts[self.groups[row] + !start as usize];
+        }
+        let forward = matches!(edge, FrameEdge::Following(_)) != order.descending;
+        let operator = match forward {
+            true => BinaryOperator::Add,
+            false => BinaryOperator::Subtract,
+        };
+        let bound = match (key, offset) {
+            (Value::Integer(_) | Value::Float(_), Some(offset)) => {
+                value::arithmetic(operator, key, offset)
+            }
+            _ => key.clone(),
+        };
+        // The NULLs are together at one end of the partition.
+        let nulls = |row: &Vec<Value>| row[column] == Value::Null;
+        let (first, last) = match order.nulls_first {
+            true => (self.rows.partition_point(nulls), self.rows.len()),
+            false => (0, self.rows.partition_point(|row| !nulls(row))),
+        };
+        let before = |row: &Vec<Value>| {
+            let ordering = value::compare(&row[column], &bound, order.collation);
+            match order.descending {
+                false => ordering,
+                true => ordering.reverse(),
+            }
+        };
+        let rows = &self.rows[first..last];
+        first
+            + match start {
+                true => rows.partition_point(|row| before(row) == Ordering::Less),
+                false => rows.partition_point(|row| before(row) != Ordering::Greater),
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::errors::{DBError, Result};
+    use crate::page::file_structures::Value;
+    use crate::sql::ast::{FrameExclude, FrameUnits};
+    use crate::sql::functions::find_aggregate;
+    use crate::sql::value;
+    use crate::sql::value::Collation;
+    use crate::sql::vm::SortOrder;
+    use crate::sql::window::{find_window, FrameEdge, Function, WindowCall};
+
+    // The ids and points of the scores of the windows.db fixture, in order of the points with the
+    // NULL first.
+    const SCORES: [(i64, Option<i64>); 7] = [
+        (5, None),
+        (2, Some(1)),
+        (4, Some(1)),
+        (3, Some(2)),
+        (1, Some(3)),
+        (7, Some(3)),
+        (6, Some(5)),
+    ];
+
+    fn call(function: &str, units: FrameUnits, start: FrameEdge, end: FrameEdge) -> WindowCall {
+        let function = match find_window(function, 1) {
+            Some(function) => Function::Window(function.unwrap()),
+            None => Function::Aggregate(find_aggregate(function, 1).unwrap().unwrap()),
+        };
+        let order = SortOrder {
+            descending: false,
+            nulls_first: true,
+            collation: Collation::Binary,
+        };
+        WindowCall {
+            function,
+            arguments: 1..2,
+            filter: None,
+            partition: vec![(0, Collation::Binary)],
+            order: vec![(1, order)],
+            units,
+            start,
+            end,
+            exclude: FrameExclude::NoOthers,
+            collation: Collation::Binary,
+        }
+    }
+
+    fn values(call: &WindowCall, rows: &[(&str, Value)], registers: &[Value]) -> Vec<Value> {
+        let mut rows: Vec<Vec<Value>> = rows
+            .iter()
+            .map(|(partition, value)| vec![Value::Text(partition.to_string()), value.clone()])
+            .collect();
+        call.compute(&mut rows, registers).unwrap();
+        rows.into_iter().map(|row| row[2].clone()).collect()
+    }
+
+    /*
+     * The values of a call over SCORES ordered by the points, all in one partition. Each row is the
+     * points, the id and `arguments`, which are the arguments of the call after the id. Functions
+     * without arguments get none.
+     */
+    fn over_scores(
+        function: &str,
+        arguments: &[Value],
+        (units, start, end, exclude): (FrameUnits, FrameEdge, FrameEdge, FrameExclude),
+        registers: &[Value],
+    ) -> Result<Vec<String>> {
+        let (function, columns) = match find_window(function, 0) {
+            Some(Ok(function)) => (Function::Window(function), 1..1),
+            _ => {
+                let count = 1 + arguments.len();
+                let function = match find_window(function, count) {
+                    Some(function) => Function::Window(function?),
+                    None => Function::Aggregate(find_aggregate(function, count).unwrap()?),
+                };
+                (function, 1..1 + count)
+            }
+        };
+        let call = WindowCall {
+            function,
+            arguments: columns,
+            filter: None,
+            partition: Vec::new(),
+            order: vec![(
+                0,
+                SortOrder {
+                    descending: false,
+                    nulls_first: true,
+                    collation: Collation::Binary,
+                },
+            )],
+            units,
+            start,
+            end,
+            exclude,
+            collation: Collation::Binary,
+        };
+        let mut rows: Vec<Vec<Value>> = SCORES
+            .iter()
+            .map(|(id, points)| {
+                let mut row = vec![
+                    points.map_or(Value::Null, Value::Integer),
+                    Value::Integer(*id),
+                ];
+                row.extend_from_slice(arguments);
+                row
+            })
+            .collect();
+        call.compute(&mut rows, registers)?;
+        Ok(rows
+            .iter()
+            .map(|row| match row.last().unwrap() {
+                Value::Null => String::new(),
+                value => value::to_text(value).unwrap(),
+            })
+            .collect())
+    }
+
+    #[test]
+    fn frame_exclude_test() {
+        use FrameEdge::{CurrentRow, Following, Preceding, UnboundedFollowing, UnboundedPreceding};
+        let one = [Value::Integer(1), Value::Integer(1)];
+        // The ids in the frame of each row.
+        let ids = |units, start, end, exclude| {
+            over_scores("group_concat", &[], (units, start, end, exclude), &one).unwrap()
+        };
+        let groups = |exclude| ids(FrameUnits::Groups, Preceding(0), Following(1), exclude);
+        // The sum of the ids in frames from the start of the partition, which only carry on from
+        // the frame before while they leave nothing out.
+        let running = |exclude| {
+            over_scores(
+                "sum",
+                &[],
+                (FrameUnits::Groups, UnboundedPreceding, CurrentRow, exclude),
+                &[],
+            )
+            .unwrap()
+        };
+
+        // The groups of the points before and after the row's own, with the row's own group, less
+        // the row, less its group, or less its peers but the row itself.
+        assert_eq!(
+            groups(FrameExclude::NoOthers),
+            [
+                "5,2,4",
+                "5,2,4,3",
+                "5,2,4,3",
+                "2,4,3,1,7",
+                "3,1,7,6",
+                "3,1,7,6",
+                "1,7,6"
+            ]
+        );
+        assert_eq!(
+            groups(FrameExclude::CurrentRow),
+            ["2,4", "5,4,3", "5,2,3", "2,4,1,7", "3,7,6", "3,1,6", "1,7"]
+        );
+        assert_eq!(
+            groups(FrameExclude::Group),
+            ["2,4", "5,3", "5,3", "2,4,1,7", "3,6", "3,6", "1,7"]
+        );
+        assert_eq!(
+            groups(FrameExclude::Ties),
+            [
+                "5,2,4",
+                "5,2,3",
+                "5,4,3",
+                "2,4,3,1,7",
+                "3,1,6",
+                "3,7,6",
+                "1,7,6"
+            ]
+        );
+
+        assert_eq!(
+            running(FrameExclude::NoOthers),
+            ["5", "11", "11", "14", "22", "22", "28"]
+        );
+        assert_eq!(
+            running(FrameExclude::CurrentRow),
+            ["", "9", "7", "11", "21", "15", "22"]
+        );
+        assert_eq!(
+            running(FrameExclude::Group),
+            ["", "5", "5", "11", "14", "14", "22"]
+        );
+        assert_eq!(
+            running(FrameExclude::Ties),
+            ["5", "7", "9", "14", "15", "21", "28"]
+        );
+
+        // EXCLUDE works the same on ROWS and RANGE frames. A NULL is only in range of NULLs.
+        assert_eq!(
+            ids(
+                FrameUnits::Rows,
+                Preceding(0),
+                Following(1),
+                FrameExclude::Group
+            ),
+            ["2", "5", "3", "4,1", "3", "6", "7"]
+        );
+        assert_eq!(
+            ids(
+                FrameUnits::Range,
+                Preceding(0),
+                Following(1),
+                FrameExclude::Ties
+            ),
+            ["5", "2,3", "4,3", "2,4,3,1,7", "3,1", "3,7", "6"]
+        );
+        assert_eq!(
+            ids(
+                FrameUnits::Range,
+                CurrentRow,
+                UnboundedFollowing,
+                FrameExclude::CurrentRow
+            ),
+            [
+                "2,4,3,1,7,6",
+                "4,3,1,7,6",
+                "2,3,1,7,6",
+                "1,7,6",
+                "7,6",
+                "1,6",
+                ""
+            ]
+        );
+    }
+
+    #[test]
+    fn partition_functions_test() {
+        // The frame of the window is RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW, which only
+        // first_value(), last_value() and nth_value() look at.
+        let frame = (
+            FrameUnits::Range,
+            FrameEdge::UnboundedPreceding,
+            FrameEdge::CurrentRow,
+            FrameExclude::NoOthers,
+        );
+        let over = |function, arguments: &[Value]| over_scores(function, arguments, frame, &[]);
+        let integer = Value::Integer;
+
+        // The points are ranked in groups of peers, the NULL first.
+        for (function, expected) in [
+            ("row_number", ["1", "2", "3", "4", "5", "6", "7"]),
+            ("rank", ["1", "2", "2", "4", "5", "5", "7"]),
+            ("dense_rank", ["1", "2", "2", "3", "4", "4", "5"]),
+        ] {
+            assert_eq!(over(function, &[]).unwrap(), expected, "{}", function);
+        }
+        assert_eq!(
+            over("percent_rank", &[]).unwrap(),
+            [
+                "0.0",
+                "0.166666666666667",
+                "0.166666666666667",
+                "0.5",
+                "0.666666666666667",
+                "0.666666666666667",
+                "1.0"
+            ]
+        );
+        assert_eq!(
+            over("cume_dist", &[]).unwrap(),
+            [
+                "0.142857142857143",
+                "0.428571428571429",
+                "0.428571428571429",
+                "0.571428571428571",
+                "0.857142857142857",
+                "0.857142857142857",
+                "1.0"
+            ]
+        );
+        // lag() and lead() look past the frame, to the rows before and after in the partition.
+        assert_eq!(
+            over("lag", &[]).unwrap(),
+            ["", "5", "2", "4", "3", "1", "7"]
+        );
+        assert_eq!(
+            over("lead", &[integer(2), integer(0)]).unwrap(),
+            ["4", "3", "1", "7", "6", "0", "0"]
+        );
+
+        // The frame ends with the last peer of the row.
+        assert_eq!(
+            over("first_value", &[]).unwrap(),
+            ["5", "5", "5", "5", "5", "5", "5"]
+        );
+        assert_eq!(
+            over("last_value", &[]).unwrap(),
+            ["5", "4", "4", "3", "7", "7", "6"]
+        );
+        assert_eq!(
+            over("nth_value", &[integer(3)]).unwrap(),
+            ["", "4", "4", "4", "4", "4", "4"]
+        );
+    }
+
+    #[test]
+    fn window_functions_test() {
+        let rows = [
+            ("a", Value::Null),
+            ("a", Value::Integer(1)),
+            ("a", Value::Integer(1)),
+            ("a", Value::Integer(4)),
+            ("b", Value::Integer(2)),
+            ("b", Value::Float(2.5)),
+        ];
+        let integers = |integers: &[Option<i64>]| -> Vec<Value> {
+            integers
+                .iter()
+                .map(|integer| integer.map_or(Value::Null, Value::Integer))
+                .collect()
+        };
+
+        let first_value = call(
+            "first_value",
+            FrameUnits::Rows,
+            FrameEdge::Preceding(0),
+            FrameEdge::CurrentRow,
+        );
+        assert_eq!(
+            values(&first_value, &rows, &[Value::Integer(1)]),
+            [
+                Value::Null,
+                Value::Null,
+                Value::Integer(1),
+                Value::Integer(1),
+                Value::Integer(2),
+                Value::Integer(2),
+            ]
+        );
+
+        // The NULL is only in its own frame, 1 and 4 are more than 2 apart.
+        let sum = call(
+            "sum",
+            FrameUnits::Range,
+            FrameEdge::Preceding(0),
+            FrameEdge::Following(1),
+        );
+        assert_eq!(
+            values(&sum, &rows, &[Value::Integer(2), Value::Integer(0)]),
+            [
+                Value::Null,
+                Value::Integer(2),
+                Value::Integer(2),
+                Value::Integer(4),
+                Value::Integer(2),
+                Value::Float(4.5),
+            ]
+        );
+
+        let count = call(
+            "count",
+            FrameUnits::Groups,
+            FrameEdge::Following(0),
+            FrameEdge::UnboundedFollowing,
+        );
+        assert_eq!(
+            values(&count, &rows, &[Value::Integer(1)]),
+            integers(&[Some(3), Some(1), Some(1), Some(0), Some(1), Some(0)])
+        );
+
+        let ntile = call(
+            "ntile",
+            FrameUnits::Range,
+            FrameEdge::UnboundedPreceding,
+            FrameEdge::CurrentRow,
+        );
+        let ntile_rows: Vec<(&str, Value)> = (0..7).map(|_| ("a", Value::Integer(3))).collect();
+        assert_eq!(
+            values(&ntile, &ntile_rows, &[]),
+            integers(&[
+                Some(1),
+                Some(1),
+                Some(1),
+                Some(2),
+                Some(2),
+                Some(3),
+                Some(3)
+            ])
+        );
+
+        let mut rows: Vec<Vec<Value>> = vec![vec![Value::Null, Value::Integer(1)]];
+        assert!(matches!(
+            sum.compute(&mut rows, &[Value::Float(-1.0), Value::Integer(0)]),
+            Err(DBError::Sql(error)) if error == "frame starting offset must be a non-negative number"
+        ));
+        assert!(matches!(
+            count.compute(&mut rows, &[Value::Float(1.5)]),
+            Err(DBError::Sql(error)) if error == "frame starting offset must be a non-negative integer"
+        ));
+    }
+
+    #[test]
+    fn window_errors_test() {
+        let rows_frame = (
+            FrameUnits::Rows,
+            FrameEdge::Preceding(0),
+            FrameEdge::CurrentRow,
+            FrameExclude::NoOthers,
+        );
+        let expect_error = |result: Result<Vec<String>>, expected: &str| match result {
+            Err(DBError::Sql(message)) => assert_eq!(message, expected),
+            result => panic!("Expected {:?}, got: {:?}", expected, result),
+        };
+        expect_error(
+            over_scores("sum", &[], rows_frame, &[Value::Integer(-1)]),
+            "frame starting offset must be a non-negative integer",
+        );
+        expect_error(
+            over_scores(
+                "nth_value",
+                &[Value::Integer(0)],
+                rows_frame,
+                &[Value::Integer(1)],
+            ),
+            "second argument to nth_value must be a positive integer",
+        );
+        let ntile = call(
+            "ntile",
+            FrameUnits::Rows,
+            FrameEdge::UnboundedPreceding,
+            FrameEdge::CurrentRow,
+        );
+        let mut rows = vec![vec![Value::Null, Value::Integer(0)]];
+        expect_error(
+            ntile.compute(&mut rows, &[]).map(|_| Vec::new()),
+            "argument of ntile must be a positive integer",
+        );
+
+        // Offsets are only checked when there are rows.
+        let sum = call("sum", rows_frame.0, rows_frame.1, rows_frame.2);
+        assert!(sum.compute(&mut [], &[Value::Integer(-1)]).is_ok());
+    }
+}